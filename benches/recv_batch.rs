@@ -0,0 +1,54 @@
+// Compares one recv per datagram with recvmmsg batches. Each round queues
+// ROUND loopback datagrams and then times only receiving them, so senders
+// don't compete with the receiver for cores. Run with
+// `cargo bench --bench recv_batch`.
+use sequencer::udp::RecvBatch;
+use socket2::SockRef;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 200;
+const ROUND: usize = 2_048;
+const DATAGRAM_LEN: usize = 64;
+
+fn receive(batch_len: usize) -> f64 {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Room for a whole round, capped by net.core.rmem_max
+    SockRef::from(&receiver)
+        .set_recv_buffer_size(16 << 20)
+        .unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    let datagram = [0_u8; DATAGRAM_LEN];
+
+    let mut batch = RecvBatch::new(batch_len);
+    let mut n_datagrams = 0;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        for _ in 0..ROUND {
+            sender.send(&datagram).unwrap();
+        }
+        // Whatever didn't fit in the receive buffer was dropped
+        let start = Instant::now();
+        while let Ok(n) = batch.recv(&receiver) {
+            n_datagrams += n;
+        }
+        elapsed += start.elapsed();
+    }
+    n_datagrams as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let base = receive(1);
+    eprintln!("batch  1: {:.0} pps", base);
+    for batch_len in [8, 32, 64] {
+        let pps = receive(batch_len);
+        eprintln!(
+            "batch {:>2}: {:.0} pps ({:.2}x)",
+            batch_len,
+            pps,
+            pps / base
+        );
+    }
+}
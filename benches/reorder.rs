@@ -0,0 +1,51 @@
+// Sequences 1M blocks from an A/B pair where A loses every 10,000th block and
+// B trails A by 1,000 blocks, so the reorder buffer holds ~1,000 blocks most
+// of the time. Run with `cargo bench --bench reorder > /dev/null` to keep the
+// sequencer's logging out of the terminal.
+use sequencer::{Block, BlockHeader, Sequencer};
+use std::time::{Duration, Instant};
+
+const N_BLOCKS: u64 = 1_000_000;
+const LAG: usize = 1_000;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+fn main() {
+    let a: Vec<u64> = (0..N_BLOCKS).filter(|s| s % 10_000 != 5_000).collect();
+    let b: Vec<u64> = (0..N_BLOCKS).collect();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+
+    let start = Instant::now();
+    let mut n_packets = 0;
+    for i in 0..b.len() + LAG {
+        if let Some(s) = a.get(i) {
+            sequencer.push_from(0, block(*s));
+            n_packets += 1;
+        }
+        if i >= LAG {
+            sequencer.push_from(1, block(b[i - LAG]));
+            n_packets += 1;
+        }
+        if i % 4_096 == 0 {
+            receiver.try_iter().for_each(drop);
+        }
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(sequencer.seqnum(0), N_BLOCKS);
+
+    eprintln!(
+        "{} packets in {:?} ({:.0} pps), max depth {}",
+        n_packets,
+        elapsed,
+        n_packets as f64 / elapsed.as_secs_f64(),
+        sequencer.stats().max_reorder_depth
+    );
+}
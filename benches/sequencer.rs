@@ -0,0 +1,133 @@
+// Criterion benchmarks of the sequencing hot path: in-order throughput from
+// one feed, arbitration between two identical feeds, flushing the reorder
+// buffer once a gap fills and checking timeouts with blocks pending, per push
+// or on a tick. Run with `cargo bench --bench sequencer`.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use crossbeam_channel::Receiver;
+use sequencer::{Block, BlockHeader, Payload, SequencedEvent, Sequencer};
+use std::time::{Duration, Instant};
+
+const N_BLOCKS: u64 = 10_000;
+const DEPTHS: [u64; 4] = [10, 100, 1_000, 10_000];
+// Long enough that nothing times out while measuring
+const TIMEOUT: Duration = Duration::from_secs(3_600);
+
+fn block(seqnum: u64) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    let mut b = Block::new(header, Payload::default());
+    b.received = Some(Instant::now());
+    b
+}
+
+fn blocks(seqnums: impl Iterator<Item = u64>) -> Vec<Block<Payload>> {
+    seqnums.map(block).collect()
+}
+
+type Setup = (
+    Sequencer<Block<Payload>>,
+    Receiver<SequencedEvent<Block<Payload>>>,
+);
+
+// A sequencer holding 1..=depth behind a gap at 0
+fn pending(depth: u64) -> Setup {
+    let (mut sequencer, receiver) = Sequencer::with_buffer_len(TIMEOUT, depth as usize + 1);
+    for s in 1..=depth {
+        sequencer.push(block(s));
+    }
+    (sequencer, receiver)
+}
+
+fn single_feed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_feed");
+    group.throughput(Throughput::Elements(N_BLOCKS));
+    group.bench_function("in_order", |bench| {
+        bench.iter_batched(
+            || (Sequencer::new(TIMEOUT), blocks(0..N_BLOCKS)),
+            |((mut sequencer, receiver), blocks)| {
+                for b in blocks {
+                    sequencer.push(b);
+                }
+                receiver.try_iter().for_each(drop);
+                sequencer
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn two_feeds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("two_feeds");
+    group.throughput(Throughput::Elements(2 * N_BLOCKS));
+    group.bench_function("first_wins", |bench| {
+        bench.iter_batched(
+            || {
+                let a = blocks(0..N_BLOCKS);
+                let b = blocks(0..N_BLOCKS);
+                (Sequencer::new(TIMEOUT), a, b)
+            },
+            |((mut sequencer, receiver), a, b)| {
+                for (a, b) in a.into_iter().zip(b) {
+                    sequencer.push_from(0, a);
+                    sequencer.push_from(1, b);
+                }
+                receiver.try_iter().for_each(drop);
+                sequencer
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn reorder_flush(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reorder_flush");
+    for depth in DEPTHS {
+        group.throughput(Throughput::Elements(depth));
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |bench, &d| {
+            bench.iter_batched(
+                || (pending(d), block(0)),
+                |((mut sequencer, receiver), b)| {
+                    // Fills the gap, flushing everything held behind it
+                    sequencer.push(b);
+                    (sequencer, receiver)
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn timeout_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("timeout_scan");
+    for depth in DEPTHS {
+        let (mut sequencer, _receiver) = pending(depth);
+        group.bench_with_input(BenchmarkId::new("poll", depth), &depth, |bench, _| {
+            bench.iter(|| sequencer.poll_timeouts())
+        });
+        // A copy of a held block, dropped as a duplicate but still checking
+        // for timeouts, so the depth stays the same
+        group.bench_with_input(BenchmarkId::new("push", depth), &depth, |bench, &d| {
+            bench.iter_batched(|| block(d), |b| sequencer.push(b), BatchSize::SmallInput)
+        });
+        // The same with timeouts left to a tick
+        sequencer.set_timeout_tick(Some(Duration::from_millis(1)));
+        group.bench_with_input(
+            BenchmarkId::new("push_ticked", depth),
+            &depth,
+            |bench, &d| {
+                bench.iter_batched(|| block(d), |b| sequencer.push(b), BatchSize::SmallInput)
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_feed, two_feeds, reorder_flush, timeout_scan);
+criterion_main!(benches);
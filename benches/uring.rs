@@ -0,0 +1,82 @@
+// Compares recvmmsg batches with io_uring multishot recvmsg. Each round sends
+// ROUND loopback datagrams and times receiving all of them, the same way as
+// the recv_batch bench. Run with
+// `cargo bench --features io_uring --bench uring`.
+use sequencer::pool::BufferPool;
+use sequencer::udp::{RecvBatch, MAX_BATCH};
+use sequencer::uring::{UringRecv, RECV_OVERHEAD};
+use socket2::SockRef;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 200;
+const ROUND: usize = 2_048;
+const DATAGRAM_LEN: usize = 64;
+const WAIT: Duration = Duration::from_millis(100);
+
+fn sockets() -> (UdpSocket, UdpSocket) {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Room for a whole round, capped by net.core.rmem_max
+    SockRef::from(&receiver)
+        .set_recv_buffer_size(16 << 20)
+        .unwrap();
+    receiver.set_read_timeout(Some(WAIT)).unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    (receiver, sender)
+}
+
+// Times `recv` receiving each round, which returns how many datagrams it got
+// and 0 once it times out
+fn bench(sender: &UdpSocket, mut recv: impl FnMut() -> usize) -> f64 {
+    let datagram = [0_u8; DATAGRAM_LEN];
+    let mut n_datagrams = 0;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        for _ in 0..ROUND {
+            sender.send(&datagram).unwrap();
+        }
+        let start = Instant::now();
+        let mut n = 0;
+        // Whatever didn't fit in the receive buffer was dropped
+        while n < ROUND {
+            match recv() {
+                0 => break,
+                got => n += got,
+            }
+        }
+        elapsed += start.elapsed();
+        n_datagrams += n;
+    }
+    n_datagrams as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let (receiver, sender) = sockets();
+    // Buffers are handed over and replaced like a feed does
+    let pool = BufferPool::new(2 * ROUND, DATAGRAM_LEN);
+    let mut batch = RecvBatch::with_pool(MAX_BATCH, pool);
+    let recvmmsg = bench(&sender, || match batch.recv(&receiver) {
+        Ok(_) => batch.take().count(),
+        Err(_) => 0,
+    });
+    eprintln!("recvmmsg x{}: {:.0} pps", MAX_BATCH, recvmmsg);
+
+    let (receiver, sender) = sockets();
+    let pool = BufferPool::new(2 * ROUND, DATAGRAM_LEN + RECV_OVERHEAD);
+    let mut ring = match UringRecv::new(&receiver, pool, 2 * ROUND as u16) {
+        Ok(ring) => ring,
+        Err(e) => {
+            eprintln!("io_uring unsupported: {}", e);
+            return;
+        }
+    };
+    let mut received = Vec::new();
+    let uring = bench(&sender, || {
+        ring.recv(&mut received, Some(WAIT)).unwrap();
+        let n = received.len();
+        received.clear();
+        n
+    });
+    eprintln!("io_uring: {:.0} pps ({:.2}x)", uring, uring / recvmmsg);
+}
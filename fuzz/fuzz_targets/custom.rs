@@ -0,0 +1,111 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::custom::{self, Endian, Field, Framing, MessageFraming};
+use sequencer::protocol::Protocol;
+
+// A framing as a config file could declare it, and a packet to frame by it
+#[derive(Arbitrary, Debug)]
+struct Input {
+    header_len: u8,
+    seqnum: (u8, u8, bool),
+    count: Option<(u8, u8, bool)>,
+    messages: Option<Messages>,
+    session: Option<(u8, u8)>,
+    first_seqnum: u64,
+    packet: Vec<u8>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Messages {
+    Fixed(u8),
+    LengthPrefixed {
+        width: u8,
+        little: bool,
+        inclusive: bool,
+    },
+}
+
+fn endian(little: bool) -> Endian {
+    if little {
+        Endian::Little
+    } else {
+        Endian::Big
+    }
+}
+
+fn field((offset, width, little): (u8, u8, bool)) -> Field {
+    Field {
+        offset: offset as usize,
+        width: width as usize,
+        endian: endian(little),
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let framing = Framing {
+        header_len: input.header_len as usize,
+        seqnum: field(input.seqnum),
+        count: input.count.map(field),
+        messages: input.messages.map(|m| match m {
+            Messages::Fixed(len) => MessageFraming::Fixed(len as usize),
+            Messages::LengthPrefixed {
+                width,
+                little,
+                inclusive,
+            } => MessageFraming::LengthPrefixed {
+                width: width as usize,
+                endian: endian(little),
+                inclusive,
+            },
+        }),
+        session: input
+            .session
+            .map(|(offset, width)| field((offset, width, false))),
+        first_seqnum: input.first_seqnum,
+    };
+    // Refused when the config file is loaded
+    if framing.validate().is_err() {
+        return;
+    }
+    let buf = &input.packet[..];
+    let packet = match custom::parse(&framing, buf) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(buf.len() < framing.header_len);
+            assert!(Protocol::Custom(Some(framing)).parse(buf).is_none());
+            return;
+        }
+    };
+    assert_eq!(Some(packet.header.seqnum), framing.seqnum.read(buf));
+    let count = framing.count.map(|c| c.read(buf).unwrap() as u16);
+    let messages = match framing.messages {
+        Some(messages) => messages,
+        None => {
+            assert_eq!(packet.payload, &buf[framing.header_len..]);
+            assert_eq!(packet.header.n_messages, count.unwrap_or(1));
+            assert!(!packet.malformed());
+            return;
+        }
+    };
+    assert_eq!(
+        packet.payload.len() + packet.trailing,
+        buf.len() - framing.header_len
+    );
+    if let Some(count) = count {
+        assert_eq!(packet.header.n_messages + packet.missing, count);
+    }
+    // Every message counted is whole and they fill the payload
+    let prefix = match messages {
+        MessageFraming::Fixed(_) => 0,
+        MessageFraming::LengthPrefixed { width, .. } => width,
+    };
+    let mut len = 0;
+    let mut n = 0;
+    for msg in custom::Messages::new(messages, packet.payload, packet.header.n_messages) {
+        len += prefix + msg.unwrap().len();
+        n += 1;
+    }
+    assert_eq!(len, packet.payload.len());
+    assert_eq!(n, packet.header.n_messages);
+});
@@ -0,0 +1,44 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::fast::{self, Messages, Templates};
+use std::sync::OnceLock;
+
+const TEMPLATES: &str = r#"<templates>
+  <template name="Refresh" id="2">
+    <string name="MessageType" id="35"><constant value="X"/></string>
+    <uInt32 name="MsgSeqNum" id="34"><increment/></uInt32>
+    <uInt64 name="SendingTime" id="52"><copy/></uInt64>
+    <byteVector name="Data" presence="optional"><tail/></byteVector>
+    <sequence name="MDEntries">
+      <uInt32 name="MDUpdateAction" id="279"><copy value="0"/></uInt32>
+      <string name="Symbol" id="55" presence="optional"><delta/></string>
+      <decimal name="MDEntryPx" id="270" presence="optional"><delta/></decimal>
+      <decimal name="MDEntrySize" id="271">
+        <exponent><default value="0"/></exponent>
+        <mantissa><delta/></mantissa>
+      </decimal>
+      <group name="Extra" presence="optional">
+        <int32 name="Flags"><default value="1"/></int32>
+      </group>
+    </sequence>
+  </template>
+</templates>"#;
+
+fn templates() -> &'static Templates {
+    static TEMPLATES_: OnceLock<Templates> = OnceLock::new();
+    TEMPLATES_.get_or_init(|| Templates::parse(TEMPLATES).unwrap())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let templates = templates();
+    // Decoding ends, one way or another
+    let decoded = Messages::new(templates, data)
+        .take_while(Result::is_ok)
+        .count();
+    if let Ok(packet) = fast::parse_with(templates, data) {
+        assert_eq!(packet.header.n_messages as usize, decoded);
+    }
+    if fast::parse(data).is_err() {
+        assert!(data.len() < fast::HEADER_LEN);
+    }
+});
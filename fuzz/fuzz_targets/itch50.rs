@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::{itch50, moldudp64};
+use sequencer::{Block, BlockHeader};
+
+fuzz_target!(|data: &[u8]| {
+    // A lone message
+    let _ = itch50::parse(data);
+    // A block of them, its first two bytes the message count
+    if data.len() < 2 {
+        return;
+    }
+    let n_messages = u16::from_be_bytes([data[0], data[1]]);
+    let header = BlockHeader {
+        n_messages,
+        ..Default::default()
+    };
+    let block = Block::new(header, &data[2..]);
+    let n = itch50::messages(&block).count();
+    assert!(n <= n_messages as usize);
+    let framed = moldudp64::Messages::new(&data[2..], n_messages).count();
+    assert_eq!(n, framed);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::lz4::{self, Lz4Reader};
+use std::io::Read;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything decompresses to at most what it says or fails, without panicking
+    let mut out = Vec::new();
+    let _ = lz4::decompress_block(data, &mut out, 1 << 16);
+    assert!(out.len() <= 1 << 16);
+    if let Ok(mut reader) = Lz4Reader::new(data) {
+        let _ = reader.read_to_end(&mut Vec::new());
+    }
+
+    let mut block = Vec::new();
+    lz4::compress_block(data, &mut block);
+    let mut round_trip = Vec::new();
+    lz4::decompress_block(&block, &mut round_trip, data.len()).unwrap();
+    assert_eq!(round_trip, data);
+});
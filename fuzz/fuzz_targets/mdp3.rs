@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::mdp3;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match mdp3::parse(data) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(data.len() < mdp3::HEADER_LEN);
+            return;
+        }
+    };
+    assert_eq!(packet.header.n_messages, 1);
+    // Each message takes at least its size and SBE header, so iteration ends
+    let mut len = 0;
+    for msg in packet.messages() {
+        match msg {
+            Ok(msg) => len += 2 + mdp3::SBE_HEADER_LEN + msg.body.len(),
+            Err(_) => break,
+        }
+    }
+    assert!(len <= packet.payload.len());
+});
@@ -0,0 +1,38 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::pitch::PacketKind;
+use sequencer::protocol::{mitch, pitch, Protocol};
+
+// MITCH packets are framed by pitch::parse(), with market data groups for
+// units, so this is of what MITCH adds: its session messages
+fuzz_target!(|data: &[u8]| {
+    let packet = match pitch::parse(data) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(Protocol::Mitch.parse(data).is_none());
+            return;
+        }
+    };
+    // Groups' multicast feeds have no session messages
+    if packet.kind == PacketKind::Unsequenced {
+        assert!(Protocol::Mitch.parse(data).is_none());
+    } else {
+        let (header, payload) = Protocol::Mitch.parse(data).unwrap();
+        assert_eq!(header.channel, data[3] as u32);
+        assert_eq!(payload, packet.payload);
+    }
+    for msg in packet.messages() {
+        let msg = msg.unwrap();
+        let control = match mitch::parse_control(&msg) {
+            Some(Ok(control)) => control,
+            _ => continue,
+        };
+        // What parses is written back as a message that parses the same
+        let mut buf = Vec::new();
+        pitch::write_header(&mut buf, packet.unit, 0);
+        mitch::write_control(&mut buf, &control);
+        let written = pitch::parse(&buf).unwrap();
+        let msg = written.messages().next().unwrap().unwrap();
+        assert_eq!(mitch::parse_control(&msg), Some(Ok(control)));
+    }
+});
@@ -0,0 +1,37 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::moldudp64::{self, PacketKind, END_OF_SESSION};
+use sequencer::protocol::Protocol;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match moldudp64::parse(data) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(Protocol::MoldUdp64.parse(data).is_none());
+            return;
+        }
+    };
+    let count = u16::from_be_bytes([data[18], data[19]]);
+    match packet.kind {
+        PacketKind::Data => assert_eq!(packet.header.n_messages + packet.missing, count),
+        PacketKind::Heartbeat => assert_eq!(count, 0),
+        PacketKind::EndOfSession => assert_eq!(count, END_OF_SESSION),
+    }
+    assert_eq!(
+        packet.payload.len() + packet.trailing,
+        data.len() - moldudp64::HEADER_LEN
+    );
+    // Every message counted is whole and they fill the payload
+    let mut len = 0;
+    let mut n = 0;
+    for msg in packet.messages() {
+        len += 2 + msg.unwrap().len();
+        n += 1;
+    }
+    assert_eq!(len, packet.payload.len());
+    assert_eq!(n, packet.header.n_messages);
+    assert_eq!(
+        packet.malformed(),
+        packet.missing > 0 || packet.trailing > 0
+    );
+});
@@ -0,0 +1,28 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::opra;
+use sequencer::protocol::Protocol;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match opra::parse(data) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(Protocol::Opra.parse(data).is_none());
+            return;
+        }
+    };
+    assert_eq!(packet.header.n_messages + packet.missing, data[10] as u16);
+    assert_eq!(
+        packet.payload.len() + packet.trailing,
+        data.len() - opra::HEADER_LEN
+    );
+    // Every message counted is whole and they fill the payload
+    let mut len = 0;
+    let mut n = 0;
+    for msg in packet.messages() {
+        len += opra::MESSAGE_HEADER_LEN + msg.unwrap().data.len();
+        n += 1;
+    }
+    assert_eq!(len, packet.payload.len());
+    assert_eq!(n, packet.header.n_messages);
+});
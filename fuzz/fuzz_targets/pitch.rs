@@ -0,0 +1,41 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sequencer::protocol::pitch::{self, PacketKind};
+use sequencer::protocol::Protocol;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match pitch::parse(data) {
+        Ok(packet) => packet,
+        Err(_) => {
+            assert!(Protocol::Pitch.parse(data).is_none());
+            return;
+        }
+    };
+    let (count, unit) = (data[2] as u16, data[3]);
+    assert_eq!(packet.header.n_messages + packet.missing, count);
+    assert_eq!((packet.unit, packet.header.channel), (unit, unit as u32));
+    match packet.kind {
+        PacketKind::Unsequenced => assert_eq!(packet.header.seqnum, 0),
+        PacketKind::Heartbeat => assert_eq!(count, 0),
+        PacketKind::Data => assert!(count > 0),
+    }
+    assert_eq!(
+        packet.payload.len() + packet.trailing,
+        data.len() - pitch::HEADER_LEN
+    );
+    // Every message counted is whole and they fill the payload
+    let mut len = 0;
+    let mut n = 0;
+    for msg in packet.messages() {
+        let msg = msg.unwrap();
+        len += 2 + msg.body.len();
+        n += 1;
+        let _ = pitch::parse_control(&msg);
+    }
+    assert_eq!(len, packet.payload.len());
+    assert_eq!(n, packet.header.n_messages);
+    assert_eq!(
+        packet.malformed(),
+        packet.missing > 0 || packet.trailing > 0
+    );
+});
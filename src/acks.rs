@@ -0,0 +1,316 @@
+// Acknowledged delivery of the published stream to critical consumers. Each
+// logs in over TCP by name and acknowledges a watermark, the seqnum of the
+// first message it hasn't received. The publisher retains every packet past
+// the lowest watermark of the consumers it waits on, in memory up to a limit
+// and in a file past it, and a consumer that missed datagrams or was down
+// asks for them again, so none of them loses anything it hasn't acked.
+// Consumers drop packets they already have by their seqnums.
+//
+// All integers are big endian.
+// Login: 'L', name len: u8, name. Answered with 'W', watermark: u64, the last
+// one acked under that name.
+// Ack: 'A', watermark: u64
+// Resend: 'R', start: u64. Answered with frames of len: u16 followed by a
+// retained MoldUDP64 packet, from the one with `start` to the last
+// published. A frame of len 0 ends them.
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use tracing::{info, warn};
+
+pub const LOGIN: u8 = b'L';
+pub const WATERMARK: u8 = b'W';
+pub const ACK: u8 = b'A';
+pub const RESEND: u8 = b'R';
+
+// Distinguishes the retention files of one process
+static FILES: AtomicU64 = AtomicU64::new(0);
+
+struct Retained {
+    seqnum: u64,
+    count: u16,
+    packet: Vec<u8>,
+}
+
+impl Retained {
+    fn end(&self) -> u64 {
+        self.seqnum + self.count as u64
+    }
+}
+
+// (seqnum, count, offset, len) of packets written out, oldest first
+struct Spilled {
+    file: File,
+    index: VecDeque<(u64, u16, u64, usize)>,
+    end: u64,
+}
+
+struct Retention {
+    // Consumers waited on and their watermarks
+    watermarks: BTreeMap<String, u64>,
+    // Newer than anything spilled
+    memory: VecDeque<Retained>,
+    memory_bytes: usize,
+    memory_limit: usize,
+    spilled: Option<Spilled>,
+}
+
+impl Retention {
+    fn spill(&mut self) -> io::Result<()> {
+        let spilled = match &mut self.spilled {
+            Some(spilled) => spilled,
+            None => return Ok(()),
+        };
+        while self.memory_bytes > self.memory_limit {
+            let r = match self.memory.pop_front() {
+                Some(r) => r,
+                None => break,
+            };
+            spilled.file.seek(SeekFrom::Start(spilled.end))?;
+            spilled.file.write_all(&r.packet)?;
+            let len = r.packet.len();
+            spilled
+                .index
+                .push_back((r.seqnum, r.count, spilled.end, len));
+            spilled.end += len as u64;
+            self.memory_bytes -= len;
+        }
+        Ok(())
+    }
+
+    // Drop what every consumer has
+    fn trim(&mut self) -> io::Result<()> {
+        let low = self.watermarks.values().copied().min().unwrap_or(u64::MAX);
+        if let Some(spilled) = &mut self.spilled {
+            while let Some((seqnum, count, _, _)) = spilled.index.front() {
+                if seqnum + *count as u64 > low {
+                    break;
+                }
+                spilled.index.pop_front();
+            }
+            if spilled.index.is_empty() && spilled.end > 0 {
+                spilled.file.set_len(0)?;
+                spilled.end = 0;
+            }
+        }
+        while self.memory.front().is_some_and(|r| r.end() <= low) {
+            let r = self.memory.pop_front().unwrap();
+            self.memory_bytes -= r.packet.len();
+        }
+        Ok(())
+    }
+
+    fn packets_from(&mut self, start: u64) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        if let Some(spilled) = &mut self.spilled {
+            for (seqnum, count, offset, len) in &spilled.index {
+                if seqnum + *count as u64 <= start {
+                    continue;
+                }
+                let mut packet = vec![0; *len];
+                spilled.file.seek(SeekFrom::Start(*offset))?;
+                spilled.file.read_exact(&mut packet)?;
+                packets.push(packet);
+            }
+        }
+        packets.extend(
+            self.memory
+                .iter()
+                .filter(|r| r.end() > start)
+                .map(|r| r.packet.clone()),
+        );
+        Ok(packets)
+    }
+}
+
+// Packets published but not yet acked by every consumer. Clones share them.
+#[derive(Clone)]
+pub struct Acks(Arc<Mutex<Retention>>);
+
+impl Acks {
+    // Retain packets until each of `consumers` acks them, up to
+    // `memory_limit` bytes of them in memory and the rest in a file in `dir`,
+    // or all of them in memory without one
+    pub fn new(consumers: &[String], memory_limit: usize, dir: Option<&Path>) -> io::Result<Self> {
+        let spilled = match dir {
+            Some(dir) => {
+                let n = FILES.fetch_add(1, Relaxed);
+                let path = dir.join(format!("sequencer-{}-{}.unacked", process::id(), n));
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                // Nothing is left behind if the process dies
+                fs::remove_file(&path)?;
+                Some(Spilled {
+                    file,
+                    index: VecDeque::new(),
+                    end: 0,
+                })
+            }
+            None => None,
+        };
+        let retention = Retention {
+            watermarks: consumers.iter().map(|c| (c.clone(), 0)).collect(),
+            memory: VecDeque::new(),
+            memory_bytes: 0,
+            memory_limit,
+            spilled,
+        };
+        Ok(Self(Arc::new(Mutex::new(retention))))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Retention> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Keep the published `packet` holding `count` messages from `seqnum`
+    pub fn retain(&self, seqnum: u64, count: u16, packet: &[u8]) -> io::Result<()> {
+        let mut retention = self.lock();
+        retention.memory_bytes += packet.len();
+        retention.memory.push_back(Retained {
+            seqnum,
+            count,
+            packet: packet.to_vec(),
+        });
+        retention.spill()
+    }
+
+    // False for consumers that aren't waited on. Watermarks only go up.
+    pub fn ack(&self, consumer: &str, watermark: u64) -> io::Result<bool> {
+        let mut retention = self.lock();
+        match retention.watermarks.get_mut(consumer) {
+            Some(w) => *w = (*w).max(watermark),
+            None => return Ok(false),
+        }
+        retention.trim()?;
+        Ok(true)
+    }
+
+    pub fn watermark(&self, consumer: &str) -> Option<u64> {
+        self.lock().watermarks.get(consumer).copied()
+    }
+
+    // Retained packets with messages at or past `start`, oldest first
+    pub fn packets_from(&self, start: u64) -> io::Result<Vec<Vec<u8>>> {
+        self.lock().packets_from(start)
+    }
+
+    // Packets retained in memory and in the file
+    pub fn retained(&self) -> (usize, usize) {
+        let retention = self.lock();
+        let spilled = retention.spilled.as_ref().map_or(0, |s| s.index.len());
+        (retention.memory.len(), spilled)
+    }
+}
+
+// Takes consumers' logins, acks and resend requests, one thread per
+// connection
+pub struct AckServer {
+    listener: TcpListener,
+    acks: Acks,
+}
+
+impl AckServer {
+    pub fn bind(addr: SocketAddr, acks: Acks) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            acks,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Accept consumers until the listener fails
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+            let acks = self.acks.clone();
+            thread::Builder::new()
+                .name(format!("acks {}", peer))
+                .spawn(move || {
+                    if let Err(e) = serve(stream, &acks) {
+                        warn!(%peer, error = %e, "ack consumer failed");
+                    }
+                })?;
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("acks".to_string())
+            .spawn(move || self.run())
+    }
+}
+
+fn read_u64(stream: &mut TcpStream) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn serve(mut stream: TcpStream, acks: &Acks) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut consumer = None;
+    loop {
+        let mut kind = [0];
+        match stream.read_exact(&mut kind) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        match (kind[0], &consumer) {
+            (LOGIN, _) => {
+                let mut len = [0];
+                stream.read_exact(&mut len)?;
+                let mut name = vec![0; len[0] as usize];
+                stream.read_exact(&mut name)?;
+                let name = String::from_utf8_lossy(&name).into_owned();
+                let watermark = match acks.watermark(&name) {
+                    Some(watermark) => watermark,
+                    None => {
+                        warn!(consumer = name, "unknown ack consumer");
+                        return Ok(());
+                    }
+                };
+                info!(consumer = name, watermark, "ack consumer logged in");
+                let mut reply = vec![WATERMARK];
+                reply.extend_from_slice(&watermark.to_be_bytes());
+                stream.write_all(&reply)?;
+                consumer = Some(name);
+            }
+            (ACK, Some(name)) => {
+                let watermark = read_u64(&mut stream)?;
+                acks.ack(name, watermark)?;
+            }
+            (RESEND, Some(_)) => {
+                let start = read_u64(&mut stream)?;
+                let mut w = io::BufWriter::new(&stream);
+                for packet in acks.packets_from(start)? {
+                    w.write_all(&(packet.len() as u16).to_be_bytes())?;
+                    w.write_all(&packet)?;
+                }
+                w.write_all(&0_u16.to_be_bytes())?;
+                w.flush()?;
+            }
+            // Including acks and resends before a login
+            (kind, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected message {:?}", kind as char),
+                ))
+            }
+        }
+    }
+}
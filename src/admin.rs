@@ -0,0 +1,278 @@
+// Local control socket for operators to intervene without restarting. Each
+// command is a line and gets a line back, "ok" with any output or "error"
+// with why:
+//   stats                 sequencer stats and each channel's next seqnum
+//   lines                 each feed's line quality
+//   reset-channel <id>    deliver what the channel buffered, start it over at
+//                         the seqnum of its next block
+//   set-timeout <ms>      gap timeout
+//   set-feed-timeout <ms> silence after which a feed is down, 0 to not check
+//   pause-feed <feed>     discard a feed's blocks, by index or letter (A is 0)
+//   resume-feed <feed>
+//   remove-feed <feed>    stop reading a feed for good
+//   rotate-journal        move the journal aside before its next record
+//   end-session           end every channel's session, as at the end of the day
+//   gap-report <path>     write every gap so far to a file, JSON if it ends
+//                         in .json and otherwise CSV
+// Commands on sequencing state are applied by the arbiter between blocks, so
+// the sequencer is still only touched on its thread.
+use crate::journal::Rotation;
+use crate::metrics::FeedId;
+use crate::ChannelId;
+use crossbeam_channel::{bounded, Sender};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info};
+
+// How long a connection waits for the arbiter to apply its command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Stats,
+    Lines,
+    ResetChannel(ChannelId),
+    SetTimeout(Duration),
+    SetFeedTimeout(Option<Duration>),
+    PauseFeed(FeedId),
+    ResumeFeed(FeedId),
+    RemoveFeed(FeedId),
+    RotateJournal,
+    EndSession,
+    GapReport(PathBuf),
+    // The gap report at a session's end time, after which the next session's
+    // starts afresh
+    SessionGapReport(PathBuf),
+}
+
+// Feeds are 0, 1, ... or A, B, ...
+fn parse_feed(s: &str) -> Result<FeedId, String> {
+    match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Ok((c.to_ascii_uppercase() - b'A') as FeedId),
+        _ => s.parse().map_err(|_| format!("bad feed {}", s)),
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err("too many arguments".to_string());
+        }
+        let none = |c| match arg {
+            Some(_) => Err(format!("{} takes no argument", command)),
+            None => Ok(c),
+        };
+        let arg = || arg.ok_or_else(|| format!("{} needs an argument", command));
+        match command {
+            "stats" => none(Command::Stats),
+            "lines" => none(Command::Lines),
+            "reset-channel" => {
+                let channel = arg()?;
+                channel
+                    .parse()
+                    .map(Command::ResetChannel)
+                    .map_err(|_| format!("bad channel {}", channel))
+            }
+            "set-timeout" => {
+                let ms = arg()?;
+                ms.parse()
+                    .map(|ms| Command::SetTimeout(Duration::from_millis(ms)))
+                    .map_err(|_| format!("bad timeout {}", ms))
+            }
+            "set-feed-timeout" => {
+                let ms = arg()?;
+                ms.parse()
+                    .map(|ms| Command::SetFeedTimeout((ms > 0).then(|| Duration::from_millis(ms))))
+                    .map_err(|_| format!("bad timeout {}", ms))
+            }
+            "pause-feed" => parse_feed(arg()?).map(Command::PauseFeed),
+            "resume-feed" => parse_feed(arg()?).map(Command::ResumeFeed),
+            "remove-feed" => parse_feed(arg()?).map(Command::RemoveFeed),
+            "rotate-journal" => none(Command::RotateJournal),
+            "end-session" => none(Command::EndSession),
+            "gap-report" => Ok(Command::GapReport(PathBuf::from(arg()?))),
+            _ => Err(format!("unknown command {}", command)),
+        }
+    }
+}
+
+// A command for the arbiter and where its output goes
+pub struct Request {
+    pub command: Command,
+    pub reply: Sender<Result<String, String>>,
+}
+
+// host:port, or else the path of a Unix socket
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AdminAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for AdminAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(addr) => Ok(AdminAddr::Tcp(addr)),
+            Err(_) => Ok(AdminAddr::Unix(PathBuf::from(s))),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+pub struct AdminServer {
+    listener: Listener,
+    control: Sender<Request>,
+    rotation: Option<Rotation>,
+}
+
+impl AdminServer {
+    // Commands on sequencing state are sent to `control`, which
+    // Arbiter::control returns. A Unix socket left behind at the path is
+    // replaced.
+    pub fn bind(addr: &AdminAddr, control: Sender<Request>) -> io::Result<Self> {
+        let listener = match addr {
+            AdminAddr::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            AdminAddr::Unix(path) => {
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+            #[cfg(not(unix))]
+            AdminAddr::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix sockets are not supported here",
+                ))
+            }
+        };
+        Ok(Self {
+            listener,
+            control,
+            rotation: None,
+        })
+    }
+
+    // Journal rotate-journal rotates
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = Some(rotation);
+    }
+
+    // TCP address, if listening on one
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(l) => l.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    // Serve connections, each on a thread of its own, until the process exits
+    pub fn run(self) {
+        loop {
+            let conn = match &self.listener {
+                Listener::Tcp(l) => l.accept().and_then(|(s, _)| split(s.try_clone(), s)),
+                #[cfg(unix)]
+                Listener::Unix(l) => l.accept().and_then(|(s, _)| split(s.try_clone(), s)),
+            };
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    debug!(error = %e, "admin accept failed");
+                    continue;
+                }
+            };
+            let (control, rotation) = (self.control.clone(), self.rotation.clone());
+            let spawned = thread::Builder::new()
+                .name("admin client".to_string())
+                .spawn(move || {
+                    let (reader, writer) = conn;
+                    if let Err(e) = serve(reader, writer, &control, rotation.as_ref()) {
+                        debug!(error = %e, "admin client gone");
+                    }
+                });
+            if let Err(e) = spawned {
+                info!(error = %e, "failed to start admin client thread");
+            }
+        }
+    }
+
+    // run() on a thread of its own
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<()>> {
+        thread::Builder::new()
+            .name("admin".to_string())
+            .spawn(move || self.run())
+    }
+}
+
+type Conn = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+// Either kind of stream as a reader and writer of the same connection
+fn split<S: Read + Write + Send + 'static>(reader: io::Result<S>, writer: S) -> io::Result<Conn> {
+    Ok((Box::new(reader?), Box::new(writer)))
+}
+
+fn serve(
+    reader: Box<dyn Read + Send>,
+    mut writer: Box<dyn Write + Send>,
+    control: &Sender<Request>,
+    rotation: Option<&Rotation>,
+) -> io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse() {
+            Ok(command) => {
+                info!(command = line.trim(), "admin");
+                execute(command, control, rotation)
+            }
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(out) if out.is_empty() => writeln!(writer, "ok")?,
+            Ok(out) => writeln!(writer, "ok {}", out)?,
+            Err(e) => writeln!(writer, "error {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+fn execute(
+    command: Command,
+    control: &Sender<Request>,
+    rotation: Option<&Rotation>,
+) -> Result<String, String> {
+    if command == Command::RotateJournal {
+        let rotation = rotation.ok_or("no journal to rotate")?;
+        rotation.request();
+        return Ok("rotating before the next record".to_string());
+    }
+    let (reply, replied) = bounded(1);
+    control
+        .send(Request { command, reply })
+        .map_err(|_| "sequencer stopped")?;
+    replied
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| "sequencer did not reply".to_string())?
+}
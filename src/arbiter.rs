@@ -0,0 +1,412 @@
+use crate::admin::{Command, Request};
+use crate::gaps;
+use crate::metrics::{FeedId, FeedMetrics, Metrics};
+use crate::shutdown::Shutdown;
+use crate::{Sequenced, Sequencer};
+use crossbeam_channel::{bounded, unbounded, Receiver, Select, Sender};
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const FEED_QUEUE_LEN: usize = 4_096;
+// Admin requests waiting to be applied
+const CONTROL_QUEUE_LEN: usize = 16;
+
+// A feed's queue of single blocks or of batches of them
+enum FeedQueue<T> {
+    Blocks(Receiver<T>),
+    Batches(Receiver<Vec<T>>),
+}
+
+// Owns the sequencer. Each feed thread pushes raw blocks into its own
+// single-producer queue and the arbiter is the only reader, so sequencing
+// state is never shared between threads.
+pub struct Arbiter<T> {
+    sequencer: Sequencer<T>,
+    // By feed id, None once a feed has ended or been removed
+    feeds: Vec<Option<FeedQueue<T>>>,
+    // Id of the next feed added
+    next_feed: Arc<AtomicUsize>,
+    // Feeds added and removed through FeedSets
+    changes: (Sender<FeedChange<T>>, Receiver<FeedChange<T>>),
+    poll_interval: Duration,
+    stats_interval: Option<Duration>,
+    // Spin on the feed queues instead of parking
+    busy_poll: bool,
+    shutdown: Option<Shutdown>,
+    // Admin requests and a sender to hand out for them
+    control: (Sender<Request>, Receiver<Request>),
+}
+
+impl<T: Sequenced + Send + 'static> Arbiter<T> {
+    // run() on a thread of its own, so the sequencer is only ever touched
+    // there. Joining it returns the sequencer.
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<Sequencer<T>>> {
+        thread::Builder::new()
+            .name("arbiter".to_string())
+            .spawn(move || self.run())
+    }
+}
+
+impl<T: Sequenced> Arbiter<T> {
+    pub fn new(sequencer: Sequencer<T>, poll_interval: Duration) -> Self {
+        Self {
+            next_feed: Arc::new(AtomicUsize::new(sequencer.n_feeds())),
+            sequencer,
+            feeds: Vec::new(),
+            changes: unbounded(),
+            poll_interval,
+            stats_interval: None,
+            busy_poll: false,
+            shutdown: None,
+            control: bounded(CONTROL_QUEUE_LEN),
+        }
+    }
+
+    // Print a stats line every `interval` while running
+    pub fn set_stats_interval(&mut self, interval: Option<Duration>) {
+        self.stats_interval = interval;
+    }
+
+    // Spin on the feed queues, polling timeouts whenever they are empty. Cuts
+    // wake up latency at the cost of a core.
+    pub fn set_busy_poll(&mut self, busy_poll: bool) {
+        self.busy_poll = busy_poll;
+    }
+
+    // Stop early once `shutdown` is requested, draining the reorder buffer
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // Queue for commands such as an AdminServer's, which are applied between
+    // blocks
+    pub fn control(&self) -> Sender<Request> {
+        self.control.0.clone()
+    }
+
+    // Handle for adding and removing feeds while running
+    pub fn feed_set(&self) -> FeedSet<T> {
+        FeedSet {
+            next: Arc::clone(&self.next_feed),
+            changes: self.changes.0.clone(),
+            metrics: self.sequencer.metrics(),
+        }
+    }
+
+    // Queue for a new feed thread to push into
+    pub fn add_feed(&mut self) -> Sender<T> {
+        let (sender, receiver) = bounded(FEED_QUEUE_LEN);
+        let id = self.next_feed.fetch_add(1, Relaxed);
+        self.insert(id, FeedQueue::Blocks(receiver));
+        sender
+    }
+
+    // Queue for a new feed thread that receives `batch_len` blocks at a time,
+    // holding about as many blocks as add_feed's
+    pub fn add_batch_feed(&mut self, batch_len: usize) -> Sender<Vec<T>> {
+        let (sender, receiver) = bounded(batch_queue_len(batch_len));
+        let id = self.next_feed.fetch_add(1, Relaxed);
+        self.insert(id, FeedQueue::Batches(receiver));
+        sender
+    }
+
+    fn insert(&mut self, id: FeedId, queue: FeedQueue<T>) {
+        while self.sequencer.n_feeds() <= id {
+            self.sequencer.add_feed();
+        }
+        if self.feeds.len() <= id {
+            self.feeds.resize_with(id + 1, || None);
+        }
+        self.feeds[id] = Some(queue);
+    }
+
+    // A feed added or removed through a FeedSet
+    fn apply_feed_change(&mut self, change: FeedChange<T>) {
+        match change {
+            FeedChange::Add(id, queue) => {
+                info!(feed = id, "feed added");
+                self.insert(id, queue);
+            }
+            FeedChange::Remove(id) => {
+                if let Some(queue) = self.feeds.get_mut(id) {
+                    *queue = None;
+                }
+                self.sequencer.remove_feed(id);
+            }
+        }
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        self.shutdown.as_ref().is_some_and(|s| s.requested())
+    }
+
+    // Sequence until every feed's sender is dropped or removed and no
+    // FeedSet is left to add more, or shutdown is requested. Timeouts are
+    // polled whenever no feed has delivered a block within `poll_interval`,
+    // and every timeout tick if the sequencer has one, and feed liveness at
+    // most every `poll_interval`.
+    pub fn run(mut self) -> Sequencer<T> {
+        let mut last_stats = Instant::now();
+        let mut last_liveness = Instant::now();
+        let mut last_timeouts = Instant::now();
+        let tick = self.sequencer.timeout_tick();
+        loop {
+            while let Ok(change) = self.changes.1.try_recv() {
+                self.apply_feed_change(change);
+            }
+            // Selected over until the set of feeds changes
+            let mut select = Select::new();
+            let mut ids = Vec::new();
+            for (id, feed) in self.feeds.iter().enumerate() {
+                match feed {
+                    Some(FeedQueue::Blocks(r)) => select.recv(r),
+                    Some(FeedQueue::Batches(r)) => select.recv(r),
+                    None => continue,
+                };
+                ids.push(id);
+            }
+            let live = ids.len();
+            if live == 0 {
+                if Arc::strong_count(&self.next_feed) == 1 {
+                    break;
+                }
+                // Idle until a feed is added
+                if self.shutdown_requested() || self.sequencer.consumer_gone() {
+                    self.sequencer.drain();
+                    return self.sequencer;
+                }
+                apply_requests(&self.control.1, &self.changes.0, &mut self.sequencer);
+                if let Ok(change) = self.changes.1.recv_timeout(self.poll_interval) {
+                    self.apply_feed_change(change);
+                }
+                self.sequencer.poll_timeouts();
+                self.sequencer.poll_liveness();
+                continue;
+            }
+            // Feed whose sender was dropped
+            let mut ended = None;
+            loop {
+                if self.shutdown_requested() {
+                    info!(live, "shutting down, draining");
+                    self.sequencer.drain();
+                    return self.sequencer;
+                }
+                if self.sequencer.consumer_gone() {
+                    warn!(live, "consumer gone, stopping");
+                    return self.sequencer;
+                }
+                apply_requests(&self.control.1, &self.changes.0, &mut self.sequencer);
+                if !self.changes.1.is_empty() {
+                    break;
+                }
+                if self.sequencer.is_full() {
+                    // Feed queues fill up and block their producers until
+                    // timeouts drain the reorder buffer
+                    thread::sleep(self.poll_interval);
+                    self.sequencer.poll_timeouts();
+                    continue;
+                }
+                let ready = if self.busy_poll {
+                    select.try_select().map_err(|_| ())
+                } else {
+                    select.select_timeout(self.poll_interval).map_err(|_| ())
+                };
+                match ready {
+                    Ok(op) => {
+                        let id = ids[op.index()];
+                        let received = match &self.feeds[id] {
+                            Some(FeedQueue::Blocks(r)) => {
+                                op.recv(r).map(|b| self.sequencer.push_from(id, b))
+                            }
+                            Some(FeedQueue::Batches(r)) => op
+                                .recv(r)
+                                .map(|blocks| self.sequencer.push_batch_from(id, blocks)),
+                            None => unreachable!(),
+                        };
+                        if received.is_err() {
+                            ended = Some(id);
+                            break;
+                        }
+                    }
+                    Err(()) => {
+                        self.sequencer.poll_timeouts();
+                        last_timeouts = Instant::now();
+                    }
+                }
+                let now = Instant::now();
+                if tick.is_some_and(|tick| now - last_timeouts >= tick) {
+                    self.sequencer.poll_timeouts();
+                    last_timeouts = now;
+                }
+                if now - last_liveness >= self.poll_interval {
+                    self.sequencer.poll_liveness();
+                    last_liveness = now;
+                }
+                if let Some(interval) = self.stats_interval {
+                    if last_stats.elapsed() >= interval {
+                        let stats = self.sequencer.stats();
+                        info!(%stats, "stats");
+                        if stats.feeds.len() > 1 {
+                            info!(lines = %stats.line_report(), "line quality");
+                        }
+                        if let Some(timeout) = stats.recommended_timeout() {
+                            let current = self.sequencer.timeout();
+                            info!(?timeout, ?current, "recommended gap timeout");
+                        }
+                        if let Some(latency) = self.sequencer.latency() {
+                            info!(%latency, "latency");
+                        }
+                        last_stats = Instant::now();
+                    }
+                }
+            }
+            drop(select);
+            if let Some(id) = ended {
+                self.feeds[id] = None;
+            }
+        }
+        self.sequencer
+    }
+}
+
+fn batch_queue_len(batch_len: usize) -> usize {
+    (FEED_QUEUE_LEN / batch_len.max(1)).max(1)
+}
+
+enum FeedChange<T> {
+    Add(FeedId, FeedQueue<T>),
+    Remove(FeedId),
+}
+
+// Adds and removes feeds of an arbiter from any thread, before or while it
+// runs. Ids are never reused.
+pub struct FeedSet<T> {
+    next: Arc<AtomicUsize>,
+    changes: Sender<FeedChange<T>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<T> Clone for FeedSet<T> {
+    fn clone(&self) -> Self {
+        Self {
+            next: Arc::clone(&self.next),
+            changes: self.changes.clone(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+impl<T> FeedSet<T> {
+    // Queue for a new feed thread, read from the arbiter's next poll
+    pub fn add_feed(&self) -> (FeedId, Sender<T>) {
+        let (sender, receiver) = bounded(FEED_QUEUE_LEN);
+        (self.add(FeedQueue::Blocks(receiver)), sender)
+    }
+
+    // add_feed() for a feed receiving `batch_len` blocks at a time
+    pub fn add_batch_feed(&self, batch_len: usize) -> (FeedId, Sender<Vec<T>>) {
+        let (sender, receiver) = bounded(batch_queue_len(batch_len));
+        (self.add(FeedQueue::Batches(receiver)), sender)
+    }
+
+    fn add(&self, queue: FeedQueue<T>) -> FeedId {
+        let id = self.next.fetch_add(1, Relaxed);
+        // The feed sees a hang up if the arbiter has stopped
+        let _ = self.changes.send(FeedChange::Add(id, queue));
+        id
+    }
+
+    // The sequencer's counters of a feed, for what the feed thread itself
+    // sees such as malformed datagrams
+    pub fn metrics(&self, feed: FeedId) -> Arc<FeedMetrics> {
+        self.metrics.feed(feed)
+    }
+
+    // Stop reading a feed, so its sends fail, and fail over if it was the
+    // active one
+    pub fn remove_feed(&self, feed: FeedId) {
+        let _ = self.changes.send(FeedChange::Remove(feed));
+    }
+}
+
+fn apply_requests<T: Sequenced>(
+    control: &Receiver<Request>,
+    changes: &Sender<FeedChange<T>>,
+    sequencer: &mut Sequencer<T>,
+) {
+    for request in control.try_iter() {
+        let reply = apply(sequencer, changes, request.command);
+        // The connection may have given up waiting
+        let _ = request.reply.send(reply);
+    }
+}
+
+fn apply<T: Sequenced>(
+    sequencer: &mut Sequencer<T>,
+    changes: &Sender<FeedChange<T>>,
+    command: Command,
+) -> Result<String, String> {
+    match command {
+        Command::Stats => {
+            let mut out = sequencer.stats().to_string();
+            let mut seqnums: Vec<_> = sequencer.seqnums().collect();
+            seqnums.sort_unstable();
+            for (channel, seqnum) in seqnums {
+                let _ = write!(out, " channel {} seqnum {}", channel, seqnum);
+            }
+            Ok(out)
+        }
+        Command::Lines => Ok(sequencer.stats().line_report().to_string()),
+        Command::ResetChannel(channel) => match sequencer.reset_channel(channel) {
+            true => Ok(String::new()),
+            false => Err(format!("no channel {}", channel)),
+        },
+        Command::SetTimeout(timeout) => {
+            info!(?timeout, "setting timeout");
+            sequencer.set_timeout(timeout);
+            Ok(String::new())
+        }
+        Command::SetFeedTimeout(timeout) => {
+            info!(?timeout, "setting feed timeout");
+            sequencer.set_feed_timeout(timeout);
+            Ok(String::new())
+        }
+        Command::PauseFeed(feed) => match sequencer.pause_feed(feed) {
+            true => Ok(String::new()),
+            false => Err(format!("no feed {}", feed)),
+        },
+        Command::ResumeFeed(feed) => match sequencer.resume_feed(feed) {
+            true => Ok(String::new()),
+            false => Err(format!("no feed {}", feed)),
+        },
+        Command::RemoveFeed(feed) if feed < sequencer.n_feeds() => {
+            let _ = changes.send(FeedChange::Remove(feed));
+            Ok(String::new())
+        }
+        Command::RemoveFeed(feed) => Err(format!("no feed {}", feed)),
+        Command::EndSession => {
+            let ended = sequencer.end_sessions();
+            Ok(format!("ended {} channels", ended))
+        }
+        Command::GapReport(_) if !sequencer.records_gaps() => {
+            Err("gaps aren't being recorded".to_string())
+        }
+        Command::GapReport(path) => {
+            let gaps = sequencer.gaps();
+            gaps::export(&gaps, &path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(format!("{} gaps", gaps.len()))
+        }
+        Command::SessionGapReport(path) => {
+            let reported = apply(sequencer, changes, Command::GapReport(path))?;
+            sequencer.clear_gaps();
+            Ok(reported)
+        }
+        // AdminServer rotates the journal itself
+        Command::RotateJournal => Err("no journal to rotate".to_string()),
+    }
+}
@@ -0,0 +1,208 @@
+use crate::metrics::FeedId;
+use std::str::FromStr;
+
+// Decides which feed's copy of a block is sequenced. Blocks from feeds that
+// are not active are kept as standby copies and only used to fill gaps in
+// the active feed's stream.
+pub trait ArbitrationPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    // Feed whose blocks are sequenced, or None for whichever copy arrives
+    // first
+    fn active(&self) -> Option<FeedId>;
+
+    // Every packet including heartbeats and duplicates. `gap` is true if it
+    // skipped seqnums of the feed's own stream.
+    fn on_packet(&mut self, _feed: FeedId, _gap: bool) {}
+
+    fn on_feed_down(&mut self, _feed: FeedId) {}
+
+    fn on_feed_up(&mut self, _feed: FeedId) {}
+
+    // The feed is gone for good, it is never up again
+    fn on_feed_removed(&mut self, feed: FeedId) {
+        self.on_feed_down(feed);
+    }
+}
+
+// Every feed is active, the first copy of a seqnum wins
+#[derive(Clone, Debug, Default)]
+pub struct FirstWins;
+
+impl ArbitrationPolicy for FirstWins {
+    fn name(&self) -> &'static str {
+        "first-wins"
+    }
+
+    fn active(&self) -> Option<FeedId> {
+        None
+    }
+}
+
+// Sequences the primary feed and fails over to the lowest numbered feed that
+// is up while the primary is down
+#[derive(Clone, Debug, Default)]
+pub struct PreferPrimary {
+    primary: FeedId,
+    down: Vec<bool>,
+}
+
+impl PreferPrimary {
+    pub fn new(primary: FeedId) -> Self {
+        Self {
+            primary,
+            down: Vec::new(),
+        }
+    }
+
+    fn set_down(&mut self, feed: FeedId, down: bool) {
+        if self.down.len() <= feed {
+            self.down.resize(feed + 1, false);
+        }
+        self.down[feed] = down;
+    }
+
+    fn is_down(&self, feed: FeedId) -> bool {
+        self.down.get(feed).copied().unwrap_or(false)
+    }
+}
+
+impl ArbitrationPolicy for PreferPrimary {
+    fn name(&self) -> &'static str {
+        "prefer-primary"
+    }
+
+    fn active(&self) -> Option<FeedId> {
+        if !self.is_down(self.primary) {
+            return Some(self.primary);
+        }
+        // Everything down, keep waiting on the primary
+        Some(
+            (0..self.down.len())
+                .find(|f| !self.down[*f])
+                .unwrap_or(self.primary),
+        )
+    }
+
+    fn on_packet(&mut self, feed: FeedId, _gap: bool) {
+        if self.down.len() <= feed {
+            self.down.resize(feed + 1, false);
+        }
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId) {
+        self.set_down(feed, true);
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) {
+        self.set_down(feed, false);
+    }
+}
+
+// Weight of the latest packet in a feed's loss rate
+const LOSS_ALPHA: f64 = 0.01;
+// How much lower another feed's loss rate must be to switch to it, so two
+// similar feeds don't flap
+const LOSS_MARGIN: f64 = 0.005;
+
+// Sequences the feed with the lowest recent loss rate, measured as the
+// exponentially weighted fraction of packets that skipped seqnums of their
+// own feed's stream
+#[derive(Clone, Debug, Default)]
+pub struct LineQuality {
+    loss: Vec<f64>,
+    down: Vec<bool>,
+    active: Option<FeedId>,
+}
+
+impl LineQuality {
+    fn grow(&mut self, feed: FeedId) {
+        if self.loss.len() <= feed {
+            self.loss.resize(feed + 1, 0.0);
+            self.down.resize(feed + 1, false);
+        }
+    }
+
+    // Loss rate of each feed seen so far
+    pub fn loss(&self) -> &[f64] {
+        &self.loss
+    }
+
+    fn choose(&mut self) {
+        let best = (0..self.loss.len())
+            .filter(|f| !self.down[*f])
+            .min_by(|a, b| self.loss[*a].total_cmp(&self.loss[*b]));
+        let best = match best {
+            Some(best) => best,
+            None => return,
+        };
+        self.active = match self.active {
+            Some(cur) if !self.down[cur] && self.loss[best] + LOSS_MARGIN >= self.loss[cur] => {
+                Some(cur)
+            }
+            _ => Some(best),
+        };
+    }
+}
+
+impl ArbitrationPolicy for LineQuality {
+    fn name(&self) -> &'static str {
+        "line-quality"
+    }
+
+    fn active(&self) -> Option<FeedId> {
+        self.active
+    }
+
+    fn on_packet(&mut self, feed: FeedId, gap: bool) {
+        self.grow(feed);
+        let sample = if gap { 1.0 } else { 0.0 };
+        self.loss[feed] += LOSS_ALPHA * (sample - self.loss[feed]);
+        self.choose();
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId) {
+        self.grow(feed);
+        self.down[feed] = true;
+        self.choose();
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) {
+        self.grow(feed);
+        self.down[feed] = false;
+        self.choose();
+    }
+}
+
+// Built in policies by name
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Arbitration {
+    #[default]
+    FirstWins,
+    // Feed 0 with failover to the others
+    PreferPrimary,
+    LineQuality,
+}
+
+impl Arbitration {
+    pub fn policy(&self) -> Box<dyn ArbitrationPolicy> {
+        match self {
+            Arbitration::FirstWins => Box::new(FirstWins),
+            Arbitration::PreferPrimary => Box::new(PreferPrimary::new(0)),
+            Arbitration::LineQuality => Box::<LineQuality>::default(),
+        }
+    }
+}
+
+impl FromStr for Arbitration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-wins" => Ok(Arbitration::FirstWins),
+            "prefer-primary" => Ok(Arbitration::PreferPrimary),
+            "line-quality" => Ok(Arbitration::LineQuality),
+            _ => Err(format!("unknown arbitration policy {}", s)),
+        }
+    }
+}
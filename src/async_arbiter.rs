@@ -0,0 +1,118 @@
+use crate::arbiter::FEED_QUEUE_LEN;
+use crate::metrics::FeedId;
+use crate::{Sequenced, SequencedEvent, Sequencer};
+use crossbeam_channel::Receiver;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+// Handle a feed task pushes raw blocks through
+#[derive(Clone, Debug)]
+pub struct FeedSender<T> {
+    feed: FeedId,
+    sender: mpsc::Sender<(FeedId, T)>,
+}
+
+impl<T> FeedSender<T> {
+    pub fn feed(&self) -> FeedId {
+        self.feed
+    }
+
+    // Waits while the arbiter is behind. Err if the arbiter has stopped.
+    pub async fn send(&self, b: T) -> Result<(), T> {
+        self.sender
+            .send((self.feed, b))
+            .await
+            .map_err(|mpsc::error::SendError((_, b))| b)
+    }
+}
+
+// Arbiter as a task instead of a thread. Feeds share one queue tagged with
+// their feed id, timeouts are slept until instead of polled and sequenced
+// events are forwarded to an async channel.
+pub struct AsyncArbiter<T> {
+    sequencer: Sequencer<T>,
+    events: Receiver<SequencedEvent<T>>,
+    output: mpsc::UnboundedSender<SequencedEvent<T>>,
+    sender: Option<mpsc::Sender<(FeedId, T)>>,
+    receiver: mpsc::Receiver<(FeedId, T)>,
+    poll_interval: Duration,
+}
+
+impl<T: Sequenced> AsyncArbiter<T> {
+    // `events` is the receiver `sequencer` was created with
+    pub fn new(
+        sequencer: Sequencer<T>,
+        events: Receiver<SequencedEvent<T>>,
+        poll_interval: Duration,
+    ) -> (Self, mpsc::UnboundedReceiver<SequencedEvent<T>>) {
+        let (output, output_receiver) = mpsc::unbounded_channel();
+        let (sender, receiver) = mpsc::channel(FEED_QUEUE_LEN);
+        let arbiter = Self {
+            sequencer,
+            events,
+            output,
+            sender: Some(sender),
+            receiver,
+            poll_interval,
+        };
+        (arbiter, output_receiver)
+    }
+
+    pub fn add_feed(&mut self) -> FeedSender<T> {
+        let feed = self.sequencer.add_feed();
+        FeedSender {
+            feed,
+            sender: self.sender.clone().unwrap(),
+        }
+    }
+
+    fn forward(&self) {
+        for e in self.events.try_iter() {
+            // Nobody listening is not our problem
+            let _ = self.output.send(e);
+        }
+    }
+
+    // Sequence until every FeedSender is dropped. Timeouts are polled when the
+    // earliest buffered block is due, and every timeout tick if the sequencer
+    // has one, and feed liveness every `poll_interval`.
+    pub async fn run(mut self) -> Sequencer<T> {
+        // Only feeds keep the queue open
+        self.sender = None;
+        let mut next_liveness = Instant::now() + self.poll_interval;
+        let tick = self.sequencer.timeout_tick();
+        let mut next_tick = Instant::now() + tick.unwrap_or_default();
+        loop {
+            if self.sequencer.is_full() {
+                tokio::time::sleep(self.poll_interval).await;
+                self.sequencer.poll_timeouts();
+                self.forward();
+                continue;
+            }
+            let wake = match self.sequencer.next_deadline() {
+                Some(deadline) => Instant::from_std(deadline).min(next_liveness),
+                None => next_liveness,
+            };
+            tokio::select! {
+                r = self.receiver.recv() => match r {
+                    Some((feed, b)) => self.sequencer.push_from(feed, b),
+                    None => break,
+                },
+                _ = sleep_until(wake) => self.sequencer.poll_timeouts(),
+            }
+            let now = Instant::now();
+            if let Some(tick) = tick.filter(|_| now >= next_tick) {
+                self.sequencer.poll_timeouts();
+                next_tick = now + tick;
+            }
+            if now >= next_liveness {
+                self.sequencer.poll_liveness();
+                next_liveness = now + self.poll_interval;
+            }
+            self.forward();
+        }
+        self.forward();
+        self.sequencer
+    }
+}
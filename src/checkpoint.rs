@@ -0,0 +1,102 @@
+// Where sequencing had got to, written by the journal so a restart resumes
+// there instead of at the first seqnum. All integers are little endian.
+//
+// magic "SEQCKPT\0", version: u16, journal_offset: u64, n_channels: u32, then
+// per channel: channel: u32, next seqnum: u64, and last crc32c: u32 (of the
+// bytes before it)
+use crate::ChannelId;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: [u8; 8] = *b"SEQCKPT\0";
+pub const VERSION: u16 = 1;
+// magic, version, journal_offset, n_channels
+const HEADER_LEN: usize = 8 + 2 + 8 + 4;
+// channel, next seqnum
+const CHANNEL_LEN: usize = 4 + 8;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checkpoint {
+    // Length of the journal up to the last block checkpointed. Records after
+    // it are not covered and are truncated on resume.
+    pub journal_offset: u64,
+    // Seqnum each channel expects next
+    pub channels: BTreeMap<ChannelId, u64>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Checkpoint {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.channels.len() * CHANNEL_LEN + 4);
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&self.journal_offset.to_le_bytes());
+        buf.extend_from_slice(&(self.channels.len() as u32).to_le_bytes());
+        for (channel, seqnum) in &self.channels {
+            buf.extend_from_slice(&channel.to_le_bytes());
+            buf.extend_from_slice(&seqnum.to_le_bytes());
+        }
+        let crc = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < HEADER_LEN + 4 || buf[0..8] != MAGIC {
+            return Err(invalid("not a checkpoint".to_string()));
+        }
+        let version = u16::from_le_bytes([buf[8], buf[9]]);
+        if version != VERSION {
+            return Err(invalid(format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+        let n_channels = u32::from_le_bytes(buf[18..22].try_into().unwrap()) as usize;
+        let len = HEADER_LEN + n_channels * CHANNEL_LEN;
+        if buf.len() != len + 4 {
+            return Err(invalid(format!("checkpoint of {} bytes", buf.len())));
+        }
+        let crc = u32::from_le_bytes(buf[len..].try_into().unwrap());
+        if crc32c::crc32c(&buf[..len]) != crc {
+            return Err(invalid("checkpoint crc mismatch".to_string()));
+        }
+        let channels = buf[HEADER_LEN..len]
+            .chunks_exact(CHANNEL_LEN)
+            .map(|c| {
+                let channel = u32::from_le_bytes(c[0..4].try_into().unwrap());
+                (channel, u64::from_le_bytes(c[4..12].try_into().unwrap()))
+            })
+            .collect();
+        Ok(Self {
+            journal_offset: u64::from_le_bytes(buf[10..18].try_into().unwrap()),
+            channels,
+        })
+    }
+
+    // None if there is no checkpoint at `path`
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(buf) => Self::decode(&buf).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Written aside and renamed over `path`, so a crash leaves the old
+    // checkpoint or the new one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.encode())?;
+        file.sync_data()?;
+        fs::rename(&tmp, path)
+    }
+}
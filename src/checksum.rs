@@ -0,0 +1,54 @@
+// CRC32C trailers publishers can append to each datagram or TCP packet, over
+// the bytes before them. Feeds check and strip the trailer before parsing, so
+// a flaky NIC or switch corrupting packets is counted instead of their bytes
+// being journaled as what the feed sent. Journal records carry a CRC32C of
+// their own.
+use std::str::FromStr;
+
+// crc32c: u32 BE
+pub const TRAILER_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Checksum {
+    #[default]
+    Off,
+    Crc32c,
+}
+
+impl Checksum {
+    // `packet` without its trailer, or None if the trailer doesn't match
+    pub fn check<'a>(&self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        match self {
+            Checksum::Off => Some(packet),
+            Checksum::Crc32c => {
+                let (data, trailer) =
+                    packet.split_at_checked(packet.len().checked_sub(TRAILER_LEN)?)?;
+                let expected = u32::from_be_bytes(trailer.try_into().ok()?);
+                (crc32c::crc32c(data) == expected).then_some(data)
+            }
+        }
+    }
+
+    // Append the trailer of the packet in `buf`, as a publisher sends it
+    pub fn append(&self, buf: &mut Vec<u8>) {
+        match self {
+            Checksum::Off => {}
+            Checksum::Crc32c => {
+                let crc = crc32c::crc32c(buf);
+                buf.extend_from_slice(&crc.to_be_bytes());
+            }
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Checksum::Off),
+            "crc32c" => Ok(Checksum::Crc32c),
+            _ => Err(format!("unknown checksum {}", s)),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// Where the sequencer gets the time from, so tests can drive timeouts
+// instead of sleeping through them
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Stands still until advanced. Clones share the time, so a test keeps one to
+// advance the one it gave the sequencer.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    // ns since start
+    elapsed: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.fetch_add(by.as_nanos() as u64, Relaxed);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed.load(Relaxed))
+    }
+}
@@ -0,0 +1,364 @@
+// TOML file of settings that would otherwise be flags, such as:
+//
+//   timeout_ms = 100
+//   feed_timeout_ms = 500
+//   log_level = "info"
+//   pin_cores = [0, 2, 3]
+//   journal = "messages.journal"
+//
+//   [[feeds]]
+//   group = "233.54.12.111:26477"
+//   interface = "10.0.0.5"
+//   channel = 0
+//   source = "10.1.1.1"
+//   protocol = "moldudp64"
+//
+//   [[lines]]
+//   line = 1
+//   a = "233.43.202.1:11101"
+//   b = "233.43.202.65:11101"
+//
+//   [[sinks]]
+//   type = "websocket"
+//   addr = "127.0.0.1:9001"
+//   sample_ms = 100
+//
+// A [framing] table frames the feeds of proprietary protocols, see
+// protocol::custom. Lines are numbered feeds such as OPRA's 48, each sent on
+// an A and a B group. Both are sequenced in the line's channel, its number, so
+// they are arbitrated with each other.
+//
+// ConfigWatcher rereads it while running. Timeouts, the log level, added
+// sinks and added or removed feeds are applied on the fly, anything else takes
+// a restart.
+use crate::hugepages::HugePages;
+use crate::journal::{self, Compression, JournalWriter, FSYNC_MS};
+use crate::protocol::custom::Framing;
+use crate::protocol::{opra, Protocol};
+use crate::publisher::UnicastPublisher;
+use crate::shm::{ShmSink, SHM_SLOTS, SHM_SLOT_LEN};
+use crate::sink::Sink;
+use crate::websocket::{WebSocketSink, SAMPLE_MS};
+use crate::ChannelId;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub timeout_ms: Option<u64>,
+    // 0 to not check
+    pub feed_timeout_ms: Option<u64>,
+    // Level or tracing filter directives
+    pub log_level: Option<String>,
+    // Arbiter's core, then each feed's
+    pub pin_cores: Option<Vec<usize>>,
+    pub journal: Option<PathBuf>,
+    // Framing of custom feeds, see protocol::custom
+    pub framing: Option<Framing>,
+    pub feeds: Vec<FeedEntry>,
+    pub lines: Vec<LineEntry>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+// A multicast feed, like --udp with its per feed flags
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeedEntry {
+    pub group: SocketAddrV4,
+    // The default interface if None
+    pub interface: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub channel: ChannelId,
+    // Source specific multicast sender
+    pub source: Option<Ipv4Addr>,
+    // Decoder of the feed's datagrams, --protocol if None. Feeds of a channel
+    // can frame the same messages differently as long as their seqnums and
+    // sessions are the same.
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+}
+
+// A line's A and B feeds
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LineEntry {
+    // 1 to opra::LINES
+    pub line: u8,
+    pub a: SocketAddrV4,
+    // Lines only joined on one side have no B group
+    pub b: Option<SocketAddrV4>,
+    pub interface: Option<Ipv4Addr>,
+    pub source: Option<Ipv4Addr>,
+}
+
+impl LineEntry {
+    pub fn feeds(&self) -> impl Iterator<Item = FeedEntry> + '_ {
+        [Some(self.a), self.b]
+            .into_iter()
+            .flatten()
+            .map(|group| FeedEntry {
+                group,
+                interface: self.interface,
+                channel: self.line as ChannelId,
+                source: self.source,
+                protocol: None,
+            })
+    }
+}
+
+// A sink any number of which can be added while running
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    Relay {
+        addr: SocketAddr,
+    },
+    Websocket {
+        addr: SocketAddr,
+        #[serde(default = "sample_ms")]
+        sample_ms: u64,
+    },
+    Shm {
+        path: PathBuf,
+        #[serde(default = "shm_slots")]
+        slots: usize,
+        #[serde(default = "shm_slot_len")]
+        slot_len: usize,
+        #[serde(default)]
+        huge_pages: HugePages,
+    },
+    // Another journal, with no session id
+    Journal {
+        path: PathBuf,
+        #[serde(default)]
+        compression: Compression,
+        // 0 to leave syncing to the OS
+        #[serde(default = "fsync_ms")]
+        fsync_ms: u64,
+    },
+}
+
+fn sample_ms() -> u64 {
+    SAMPLE_MS
+}
+
+fn shm_slots() -> usize {
+    SHM_SLOTS
+}
+
+fn shm_slot_len() -> usize {
+    SHM_SLOT_LEN
+}
+
+fn fsync_ms() -> u64 {
+    FSYNC_MS
+}
+
+impl SinkConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SinkConfig::Relay { .. } => "relay",
+            SinkConfig::Websocket { .. } => "websocket",
+            SinkConfig::Shm { .. } => "shm",
+            SinkConfig::Journal { .. } => "journal",
+        }
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn Sink + Send>> {
+        Ok(match self {
+            SinkConfig::Relay { addr } => Box::new(UnicastPublisher::bind(*addr)?),
+            SinkConfig::Websocket { addr, sample_ms } => Box::new(WebSocketSink::bind(
+                *addr,
+                Duration::from_millis(*sample_ms),
+            )?),
+            SinkConfig::Shm {
+                path,
+                slots,
+                slot_len,
+                huge_pages,
+            } => Box::new(ShmSink::with_huge_pages(
+                path,
+                *slots,
+                *slot_len,
+                *huge_pages,
+            )?),
+            SinkConfig::Journal {
+                path,
+                compression,
+                fsync_ms,
+            } => {
+                let fsync_interval = (*fsync_ms > 0).then(|| Duration::from_millis(*fsync_ms));
+                Box::new(JournalWriter::create_compressed(
+                    path,
+                    &journal::session(""),
+                    fsync_interval,
+                    *compression,
+                )?)
+            }
+        })
+    }
+}
+
+// What a reload changed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    Timeout(Duration),
+    FeedTimeout(Option<Duration>),
+    LogLevel(String),
+    AddSink(SinkConfig),
+    AddFeed(FeedEntry),
+    RemoveFeed(FeedEntry),
+    // Setting that only takes effect on restart
+    NeedsRestart(&'static str),
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let file: Self =
+            toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(Err(e)) = file.framing.map(|f| f.validate()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        let mut seen = Vec::new();
+        for entry in &file.lines {
+            let line = entry.line;
+            let e = if !(1..=opra::LINES).contains(&line) {
+                format!("line {} is not 1 to {}", line, opra::LINES)
+            } else if seen.contains(&line) {
+                format!("line {} is given twice", line)
+            } else {
+                seen.push(line);
+                continue;
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(file)
+    }
+
+    // The feeds and those of the lines
+    pub fn feed_entries(&self) -> Vec<FeedEntry> {
+        let lines = self.lines.iter().flat_map(LineEntry::feeds);
+        self.feeds.iter().cloned().chain(lines).collect()
+    }
+
+    // Changes from `self` to `new`. Settings removed from the file keep
+    // their value.
+    pub fn changes(&self, new: &ConfigFile) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if let Some(ms) = new.timeout_ms.filter(|ms| self.timeout_ms != Some(*ms)) {
+            changes.push(Change::Timeout(Duration::from_millis(ms)));
+        }
+        if let Some(ms) = new
+            .feed_timeout_ms
+            .filter(|ms| self.feed_timeout_ms != Some(*ms))
+        {
+            let timeout = (ms > 0).then(|| Duration::from_millis(ms));
+            changes.push(Change::FeedTimeout(timeout));
+        }
+        if let Some(level) = new
+            .log_level
+            .as_ref()
+            .filter(|l| self.log_level.as_ref() != Some(l))
+        {
+            changes.push(Change::LogLevel(level.clone()));
+        }
+        if new.pin_cores.is_some() && new.pin_cores != self.pin_cores {
+            changes.push(Change::NeedsRestart("pin_cores"));
+        }
+        if new.journal.is_some() && new.journal != self.journal {
+            changes.push(Change::NeedsRestart("journal"));
+        }
+        if new.framing.is_some() && new.framing != self.framing {
+            changes.push(Change::NeedsRestart("framing"));
+        }
+        let (old_feeds, new_feeds) = (self.feed_entries(), new.feed_entries());
+        for feed in &old_feeds {
+            if !new_feeds.contains(feed) {
+                changes.push(Change::RemoveFeed(feed.clone()));
+            }
+        }
+        for feed in &new_feeds {
+            if !old_feeds.contains(feed) {
+                changes.push(Change::AddFeed(feed.clone()));
+            }
+        }
+        for sink in &new.sinks {
+            if !self.sinks.contains(sink) {
+                changes.push(Change::AddSink(sink.clone()));
+            }
+        }
+        if self.sinks.iter().any(|s| !new.sinks.contains(s)) {
+            changes.push(Change::NeedsRestart("removed sinks"));
+        }
+        changes
+    }
+}
+
+// Rereads a config file for changes
+pub struct ConfigWatcher {
+    path: PathBuf,
+    text: String,
+    current: ConfigFile,
+}
+
+impl ConfigWatcher {
+    // `current` is what the file held when it was loaded
+    pub fn new(path: &Path, current: ConfigFile) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            text: fs::read_to_string(path)?,
+            current,
+        })
+    }
+
+    // Changes since the last poll. A file that can't be read or parsed is
+    // logged and changes nothing.
+    pub fn poll(&mut self) -> Vec<Change> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) if text == self.text => return Vec::new(),
+            Ok(text) => text,
+            Err(e) => {
+                warn!(path = %self.path.display(), error = %e, "failed to read config");
+                return Vec::new();
+            }
+        };
+        let new = match ConfigFile::parse(&text) {
+            Ok(new) => new,
+            Err(e) => {
+                warn!(path = %self.path.display(), error = %e, "bad config, keeping the old one");
+                self.text = text;
+                return Vec::new();
+            }
+        };
+        self.text = text;
+        let changes = self.current.changes(&new);
+        self.current = new;
+        changes
+    }
+
+    // poll() every `interval` on a thread of its own, calling `apply` with
+    // each change, until the process exits
+    pub fn spawn<F: FnMut(Change) + Send + 'static>(
+        mut self,
+        interval: Duration,
+        mut apply: F,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        thread::Builder::new()
+            .name("config".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                self.poll().into_iter().for_each(&mut apply);
+            })
+    }
+}
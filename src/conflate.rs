@@ -0,0 +1,64 @@
+// Conflation for consumers that only want the latest state, such as a
+// monitoring dashboard. While a conflated consumer keeps up it gets every
+// block. While it is behind, a block replaces the one it has queued with the
+// same key, so it catches up on the latest block of each key instead of
+// every block in between. Other consumers, such as the journal, are not
+// affected. Blocks without a key and every other event are always queued.
+use crate::protocol::moldudp64::Messages;
+use crate::{Block, Sequenced};
+use std::str::FromStr;
+
+// What a block is conflated with the queued blocks it shares a key with by
+pub trait ConflationKey<T> {
+    fn key(&self, block: &T) -> Option<u64>;
+}
+
+impl<T, F: Fn(&T) -> Option<u64>> ConflationKey<T> for F {
+    fn key(&self, block: &T) -> Option<u64> {
+        self(block)
+    }
+}
+
+pub type BoxedConflationKey<T> = Box<dyn ConflationKey<T> + Send>;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Conflation {
+    // The latest block of each channel
+    #[default]
+    Channel,
+    // The latest block of each channel and ITCH 5.0 stock locate (symbol),
+    // of blocks whose MoldUDP64 messages are all for one symbol
+    Symbol,
+}
+
+impl FromStr for Conflation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(Conflation::Channel),
+            "symbol" => Ok(Conflation::Symbol),
+            _ => Err(format!("unknown conflation {}", s)),
+        }
+    }
+}
+
+impl<P: AsRef<[u8]>> ConflationKey<Block<P>> for Conflation {
+    fn key(&self, block: &Block<P>) -> Option<u64> {
+        let channel = (block.channel() as u64) << 16;
+        match self {
+            Conflation::Channel => Some(channel),
+            Conflation::Symbol => {
+                let mut locate = None;
+                for msg in Messages::new(block.payload.as_ref(), block.n_messages()) {
+                    let msg = msg.ok()?;
+                    let this = u16::from_be_bytes(msg.get(1..3)?.try_into().ok()?);
+                    if *locate.get_or_insert(this) != this {
+                        return None;
+                    }
+                }
+                Some(channel | locate? as u64)
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Compares two journals block by block, such as a primary's and a standby's,
+// or an arbitrated journal against one captured from a single line. Blocks
+// are matched by channel and seqnum whatever order the journals have them
+// in, so only blocks not yet matched are held while reading. The report has
+// the seqnums only one journal has and the blocks both have that differ in
+// their message count or payload.
+use crate::journal::{JournalReader, Record};
+use crate::ChannelId;
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Difference {
+    // Message counts of a and b
+    Messages(u16, u16),
+    Payload,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    // Records of a and b
+    pub records: (u64, u64),
+    // Blocks both have the same
+    pub same: u64,
+    // Seqnums of the blocks only one has, by channel
+    pub only_a: BTreeMap<ChannelId, Vec<Range<u64>>>,
+    pub only_b: BTreeMap<ChannelId, Vec<Range<u64>>>,
+    // (channel, seqnum) of blocks both have that aren't the same
+    pub different: Vec<(ChannelId, u64, Difference)>,
+}
+
+impl Report {
+    pub fn same(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.different.is_empty()
+    }
+
+    // Match a block with the other journal's, or hold it until that is read.
+    // A second block of a seqnum in one journal is dropped, since the other
+    // can only match it once.
+    fn pair(&mut self, record: &Record, other: &mut Unmatched, own: &mut Unmatched, in_b: bool) {
+        let key = (record.channel, record.seqnum);
+        let held = Held::new(record);
+        let theirs = match other.remove(&key) {
+            Some(theirs) => theirs,
+            None => {
+                if let Entry::Vacant(e) = own.entry(key) {
+                    e.insert(held);
+                }
+                return;
+            }
+        };
+        let (a, b) = if in_b { (theirs, held) } else { (held, theirs) };
+        if a.n_messages != b.n_messages {
+            let difference = Difference::Messages(a.n_messages, b.n_messages);
+            self.different.push((key.0, key.1, difference));
+        } else if a.crc != b.crc {
+            self.different.push((key.0, key.1, Difference::Payload));
+        } else {
+            self.same += 1;
+        }
+    }
+}
+
+// What is kept of a block until the other journal's is read
+#[derive(Clone, Copy)]
+struct Held {
+    n_messages: u16,
+    crc: u32,
+}
+
+impl Held {
+    fn new(record: &Record) -> Self {
+        Self {
+            n_messages: record.n_messages,
+            crc: crc32c::crc32c(&record.payload),
+        }
+    }
+}
+
+// Blocks of one journal not matched yet, by channel and seqnum
+type Unmatched = BTreeMap<(ChannelId, u64), Held>;
+
+// Fails on a record that can't be read, which verify reports on
+pub fn diff<A: Read, B: Read>(a: JournalReader<A>, b: JournalReader<B>) -> io::Result<Report> {
+    let mut report = Report::default();
+    let (mut a, mut b) = (a.fuse(), b.fuse());
+    let (mut held_a, mut held_b) = (Unmatched::new(), Unmatched::new());
+    // Read in step so neither journal gets far ahead of the other
+    loop {
+        let (next_a, next_b) = (a.next().transpose()?, b.next().transpose()?);
+        if next_a.is_none() && next_b.is_none() {
+            break;
+        }
+        if let Some(record) = next_a {
+            report.records.0 += 1;
+            report.pair(&record, &mut held_b, &mut held_a, false);
+        }
+        if let Some(record) = next_b {
+            report.records.1 += 1;
+            report.pair(&record, &mut held_a, &mut held_b, true);
+        }
+    }
+    report.only_a = ranges(held_a);
+    report.only_b = ranges(held_b);
+    report
+        .different
+        .sort_by_key(|&(channel, seqnum, _)| (channel, seqnum));
+    Ok(report)
+}
+
+// Seqnums of the blocks, joined where one ends where the next starts
+fn ranges(held: Unmatched) -> BTreeMap<ChannelId, Vec<Range<u64>>> {
+    let mut ranges: BTreeMap<ChannelId, Vec<Range<u64>>> = BTreeMap::new();
+    for ((channel, seqnum), block) in held {
+        let end = seqnum.saturating_add(block.n_messages.max(1) as u64);
+        let channel = ranges.entry(channel).or_default();
+        match channel.last_mut() {
+            Some(last) if last.end >= seqnum => last.end = last.end.max(end),
+            _ => channel.push(seqnum..end),
+        }
+    }
+    ranges
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "records {} and {} same {} different {}",
+            self.records.0,
+            self.records.1,
+            self.same,
+            self.different.len()
+        )?;
+        for (side, only) in [("a", &self.only_a), ("b", &self.only_b)] {
+            for (channel, ranges) in only {
+                for range in ranges {
+                    writeln!(
+                        f,
+                        "only in {} channel {} seqnums {}..{}",
+                        side, channel, range.start, range.end
+                    )?;
+                }
+            }
+        }
+        for (channel, seqnum, difference) in &self.different {
+            match difference {
+                Difference::Messages(a, b) => writeln!(
+                    f,
+                    "channel {} seqnum {} messages {} and {}",
+                    channel, seqnum, a, b
+                )?,
+                Difference::Payload => {
+                    writeln!(f, "channel {} seqnum {} payloads differ", channel, seqnum)?
+                }
+            }
+        }
+        write!(f, "{}", if self.same() { "same" } else { "DIFFERENT" })
+    }
+}
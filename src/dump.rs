@@ -0,0 +1,135 @@
+// Journal records as text, for seeing what was actually captured. A line with
+// the session, a line per record, then with a decoder a line per message:
+//
+//   1700000000.000000123 channel 0 seqnum 10 messages 2 bytes 42
+//     10 AddOrder(AddOrder { .. })
+//     11 OrderDelete(OrderDelete { .. })
+use crate::journal::{JournalReader, Record};
+use crate::protocol::{itch50, mdp3, opra};
+use crate::Block;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+// Raw payloads print at most this many bytes
+const RAW_LEN: usize = 32;
+
+// How records' payloads are printed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Decode {
+    // In hex
+    #[default]
+    Raw,
+    Itch50,
+    Mdp3,
+    Opra,
+}
+
+impl FromStr for Decode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Decode::Raw),
+            "itch50" => Ok(Decode::Itch50),
+            "mdp3" => Ok(Decode::Mdp3),
+            "opra" => Ok(Decode::Opra),
+            _ => Err(format!("unknown decoder {}", s)),
+        }
+    }
+}
+
+// Print every record `reader` has left. Returns how many there were.
+pub fn dump<R: Read, W: Write>(
+    reader: JournalReader<R>,
+    decode: Decode,
+    out: &mut W,
+) -> io::Result<u64> {
+    writeln!(out, "session {}", String::from_utf8_lossy(reader.session()))?;
+    let mut n = 0;
+    for record in reader {
+        write_record(&record?, decode, out)?;
+        n += 1;
+    }
+    Ok(n)
+}
+
+pub fn write_record<W: Write>(record: &Record, decode: Decode, out: &mut W) -> io::Result<()> {
+    let ts = record.ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(
+        out,
+        "{}.{:09} channel {} seqnum {} messages {} bytes {}",
+        ts.as_secs(),
+        ts.subsec_nanos(),
+        record.channel,
+        record.seqnum,
+        record.n_messages,
+        record.payload.len()
+    )?;
+    match decode {
+        Decode::Raw => {
+            if record.payload.is_empty() {
+                return Ok(());
+            }
+            write!(out, "  ")?;
+            for b in record.payload.iter().take(RAW_LEN) {
+                write!(out, "{:02x}", b)?;
+            }
+            let more = if record.payload.len() > RAW_LEN {
+                ".."
+            } else {
+                ""
+            };
+            writeln!(out, "{}", more)
+        }
+        Decode::Itch50 => {
+            let block = Block::new(record.header(), record.payload.as_slice());
+            for (i, msg) in itch50::messages(&block).enumerate() {
+                let seqnum = record.seqnum + i as u64;
+                match msg {
+                    Ok(msg) => writeln!(out, "  {} {:?}", seqnum, msg)?,
+                    Err(e) => writeln!(out, "  {} {}", seqnum, e)?,
+                }
+            }
+            Ok(())
+        }
+        // Seqnums count packets, messages are numbered within it
+        Decode::Mdp3 => {
+            for (i, msg) in mdp3::Messages::new(&record.payload).enumerate() {
+                match msg {
+                    Ok(msg) => writeln!(
+                        out,
+                        "  {}.{} template {} schema {} version {} bytes {}",
+                        record.seqnum,
+                        i,
+                        msg.template_id,
+                        msg.schema_id,
+                        msg.version,
+                        msg.body.len()
+                    )?,
+                    Err(e) => writeln!(out, "  {}.{} {}", record.seqnum, i, e)?,
+                }
+            }
+            Ok(())
+        }
+        Decode::Opra => {
+            let msgs = opra::Messages::new(&record.payload, record.n_messages);
+            for (i, msg) in msgs.enumerate() {
+                let seqnum = record.seqnum + i as u64;
+                match msg {
+                    Ok(msg) => writeln!(
+                        out,
+                        "  {} participant {} category {} type {} bytes {}",
+                        seqnum,
+                        msg.participant as char,
+                        msg.category as char,
+                        msg.kind as char,
+                        msg.data.len()
+                    )?,
+                    Err(e) => writeln!(out, "  {} {}", seqnum, e)?,
+                }
+            }
+            Ok(())
+        }
+    }
+}
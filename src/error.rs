@@ -0,0 +1,138 @@
+// What stops the sequencer or a thread around it, and what each component
+// does about its own failures
+use crate::metrics::FeedId;
+use crate::shutdown::Shutdown;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tracing::{error, warn};
+
+// Pause before the first retry, doubled on each failure after
+const BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum SequencerError {
+    // Bad flags or config file
+    Config(String),
+    // Setting something up or tearing it down failed
+    Io {
+        what: &'static str,
+        source: io::Error,
+    },
+    Feed {
+        feed: FeedId,
+        source: io::Error,
+    },
+    Sink {
+        sink: &'static str,
+        source: io::Error,
+    },
+    // A thread panicked rather than returning an error
+    Panicked(String),
+}
+
+impl SequencerError {
+    // For map_err, naming what failed
+    pub fn io(what: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| SequencerError::Io { what, source }
+    }
+
+    pub fn feed(feed: FeedId) -> impl FnOnce(io::Error) -> Self {
+        move |source| SequencerError::Feed { feed, source }
+    }
+
+    pub fn sink(sink: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| SequencerError::Sink { sink, source }
+    }
+}
+
+impl fmt::Display for SequencerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SequencerError::Config(e) => write!(f, "config: {}", e),
+            SequencerError::Io { what, source } => write!(f, "{}: {}", what, source),
+            SequencerError::Feed { feed, source } => write!(f, "feed {}: {}", feed, source),
+            SequencerError::Sink { sink, source } => write!(f, "sink {}: {}", sink, source),
+            SequencerError::Panicked(thread) => write!(f, "{} thread panicked", thread),
+        }
+    }
+}
+
+impl Error for SequencerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SequencerError::Io { source, .. }
+            | SequencerError::Feed { source, .. }
+            | SequencerError::Sink { source, .. } => Some(source),
+            SequencerError::Config(_) | SequencerError::Panicked(_) => None,
+        }
+    }
+}
+
+// Join a thread, turning a panic into an error
+pub fn join<T>(thread: thread::JoinHandle<T>) -> Result<T, SequencerError> {
+    let name = thread.thread().name().unwrap_or("unnamed").to_string();
+    thread.join().map_err(|_| SequencerError::Panicked(name))
+}
+
+// What a feed or sink does when it fails
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    // Start it over after a backoff, until shutdown
+    Retry,
+    // Carry on without it, so a failed feed leaves the others to be
+    // sequenced and a failed sink leaves the others to be fed
+    Degrade,
+    // Stop sequencing, draining what is buffered
+    Shutdown,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "retry" => Ok(FailurePolicy::Retry),
+            "degrade" => Ok(FailurePolicy::Degrade),
+            "shutdown" => Ok(FailurePolicy::Shutdown),
+            _ => Err(format!("unknown failure policy {}", s)),
+        }
+    }
+}
+
+// Run `f` under `policy` until it succeeds. Failures are logged, and the
+// error is returned only if shutting down because of it. A component given up
+// on, or failing again once shutdown is requested, returns T::default().
+pub fn supervise<T: Default, F: FnMut() -> Result<T, SequencerError>>(
+    policy: FailurePolicy,
+    shutdown: &Shutdown,
+    mut f: F,
+) -> Result<T, SequencerError> {
+    let mut backoff = BACKOFF;
+    loop {
+        let e = match f() {
+            Ok(t) => return Ok(t),
+            Err(e) => e,
+        };
+        match policy {
+            FailurePolicy::Retry if !shutdown.requested() => {
+                warn!(error = %e, ?backoff, "failed, retrying");
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            FailurePolicy::Retry | FailurePolicy::Degrade => {
+                error!(error = %e, "failed, carrying on without it");
+                return Ok(T::default());
+            }
+            FailurePolicy::Shutdown => {
+                error!(error = %e, "failed, shutting down");
+                shutdown.request();
+                return Err(e);
+            }
+        }
+    }
+}
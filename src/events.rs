@@ -0,0 +1,78 @@
+// The sequenced stream as an Iterator, or a Stream with tokio, for consumers
+// that would rather not deal in channels
+use crate::SequencedEvent;
+use crossbeam_channel::{Receiver, TryIter};
+
+// Iterates a sequencer's events as they come, waiting for each. Ends once the
+// sequencer is dropped and everything it sequenced has been taken.
+pub struct Events<T> {
+    receiver: Receiver<SequencedEvent<T>>,
+}
+
+impl<T> Events<T> {
+    // `receiver` is the one the sequencer was created with
+    pub fn new(receiver: Receiver<SequencedEvent<T>>) -> Self {
+        Self { receiver }
+    }
+
+    // Events sequenced so far, without waiting. For pushing and consuming on
+    // the same thread.
+    pub fn pending(&self) -> TryIter<'_, SequencedEvent<T>> {
+        self.receiver.try_iter()
+    }
+
+    // Just the blocks, leaving out gaps and the rest
+    pub fn blocks(self) -> impl Iterator<Item = T> {
+        self.filter_map(SequencedEvent::into_block)
+    }
+
+    pub fn into_receiver(self) -> Receiver<SequencedEvent<T>> {
+        self.receiver
+    }
+}
+
+impl<T> From<Receiver<SequencedEvent<T>>> for Events<T> {
+    fn from(receiver: Receiver<SequencedEvent<T>>) -> Self {
+        Self::new(receiver)
+    }
+}
+
+impl<T> Iterator for Events<T> {
+    type Item = SequencedEvent<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+// An AsyncArbiter's output as a Stream
+#[cfg(feature = "tokio")]
+pub struct EventStream<T> {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<SequencedEvent<T>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T> EventStream<T> {
+    pub fn new(receiver: tokio::sync::mpsc::UnboundedReceiver<SequencedEvent<T>>) -> Self {
+        Self { receiver }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> From<tokio::sync::mpsc::UnboundedReceiver<SequencedEvent<T>>> for EventStream<T> {
+    fn from(receiver: tokio::sync::mpsc::UnboundedReceiver<SequencedEvent<T>>) -> Self {
+        Self::new(receiver)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> futures_core::Stream for EventStream<T> {
+    type Item = SequencedEvent<T>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
@@ -0,0 +1,460 @@
+// Broadcasts the sequenced stream to consumers that each read from their own
+// queue at their own pace, such as a journal, a metrics sampler and a live
+// strategy. A consumer whose queue is full holds up the others under
+// OutputPolicy::Block and loses its oldest blocks under DropOldest. Filters
+// run before events are queued, so consumers don't pay for what they skip.
+// Consumers that report what they consumed can also be held to a most blocks
+// behind, past which they are disconnected or sent gaps in place of blocks.
+// Consumers that only want the latest state can be conflated instead.
+use crate::conflate::BoxedConflationKey;
+use crate::metrics::Metrics;
+use crate::output::{Output, OutputLimit};
+use crate::protocol::moldudp64::Messages;
+use crate::{Block, ChannelId, GapReason, Sequenced, SequencedEvent};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// How often backlogs are retried while the stream is idle
+const FLUSH_INTERVAL: Duration = Duration::from_millis(1);
+
+// Which events a consumer gets
+pub trait Filter<T> {
+    fn matches(&self, event: &SequencedEvent<T>) -> bool;
+}
+
+impl<T, F: Fn(&SequencedEvent<T>) -> bool> Filter<T> for F {
+    fn matches(&self, event: &SequencedEvent<T>) -> bool {
+        self(event)
+    }
+}
+
+type BoxedFilter<T> = Box<dyn Filter<T> + Send>;
+
+// Blocks of `channels` with any ITCH 5.0 message in their MoldUDP64 payload
+// that is one of `message_types` and has a stock locate (symbol) in
+// `stock_locates`. None matches anything. Gaps, resets and resyncs pass if
+// their channel does and feed events always pass, so a consumer still sees
+// what it may have missed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockFilter {
+    pub channels: Option<Vec<ChannelId>>,
+    pub stock_locates: Option<RangeInclusive<u16>>,
+    pub message_types: Option<Vec<u8>>,
+}
+
+impl BlockFilter {
+    fn channel_matches(&self, channel: ChannelId) -> bool {
+        self.channels.as_ref().is_none_or(|c| c.contains(&channel))
+    }
+
+    // Malformed messages don't match
+    fn message_matches(&self, msg: &[u8]) -> bool {
+        let kind_matches = match (&self.message_types, msg.first()) {
+            (None, _) => true,
+            (Some(types), Some(kind)) => types.contains(kind),
+            (Some(_), None) => false,
+        };
+        let locate_matches = match (&self.stock_locates, msg.get(1..3)) {
+            (None, _) => true,
+            (Some(locates), Some(locate)) => {
+                locates.contains(&u16::from_be_bytes([locate[0], locate[1]]))
+            }
+            (Some(_), None) => false,
+        };
+        kind_matches && locate_matches
+    }
+}
+
+impl<P: AsRef<[u8]>> Filter<Block<P>> for BlockFilter {
+    fn matches(&self, event: &SequencedEvent<Block<P>>) -> bool {
+        match event {
+            SequencedEvent::Block(b) => {
+                if !self.channel_matches(b.channel()) {
+                    return false;
+                }
+                if self.message_types.is_none() && self.stock_locates.is_none() {
+                    return true;
+                }
+                Messages::new(b.payload.as_ref(), b.n_messages())
+                    .any(|m| m.is_ok_and(|m| self.message_matches(m)))
+            }
+            SequencedEvent::Gap { channel, .. }
+            | SequencedEvent::SessionReset { channel, .. }
+            | SequencedEvent::Resynced { channel, .. }
+            | SequencedEvent::EndOfSession { channel, .. }
+            | SequencedEvent::SessionsDiverged { channel, .. }
+            | SequencedEvent::SessionsAgreed { channel, .. } => self.channel_matches(*channel),
+            SequencedEvent::FeedDown { .. } | SequencedEvent::FeedUp { .. } => true,
+        }
+    }
+}
+
+// What to do with a consumer too many blocks behind
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SlowConsumerPolicy {
+    // Stop sending it anything, its receiver hangs up once it is drained
+    #[default]
+    Disconnect,
+    // Send it gaps in place of blocks until it catches up
+    GapNotify,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            "gap-notify" => Ok(SlowConsumerPolicy::GapNotify),
+            _ => Err(format!("unknown slow consumer policy {}", s)),
+        }
+    }
+}
+
+// Blocks queued for a consumer against those it consumed
+#[derive(Default)]
+struct Progress {
+    queued: AtomicU64,
+    consumed: AtomicU64,
+    // Seqnum after the last block consumed
+    watermark: AtomicU64,
+    // Blocks sent as gaps because it was behind
+    downgraded: AtomicU64,
+    // Queued blocks replaced by later ones, which it won't consume
+    conflated: AtomicU64,
+    evicted: AtomicBool,
+}
+
+impl Progress {
+    fn behind(&self) -> u64 {
+        let done = self.consumed.load(Relaxed) + self.conflated.load(Relaxed);
+        self.queued.load(Relaxed).saturating_sub(done)
+    }
+}
+
+struct Consumer<T> {
+    name: String,
+    output: Output<T>,
+    filter: Option<BoxedFilter<T>>,
+    progress: Arc<Progress>,
+    // Being sent gaps for blocks
+    downgraded: bool,
+}
+
+pub struct FanOut<T> {
+    consumers: Vec<Consumer<T>>,
+    // Which consumers the event being broadcast goes to
+    wants: Vec<bool>,
+    // Consumers subscribed while running
+    joining: Option<Receiver<Consumer<T>>>,
+    max_lag: Option<(u64, SlowConsumerPolicy)>,
+}
+
+impl<T> Default for FanOut<T> {
+    fn default() -> Self {
+        Self {
+            consumers: Vec::new(),
+            wants: Vec::new(),
+            joining: None,
+            max_lag: None,
+        }
+    }
+}
+
+fn consumer<T: Sequenced>(
+    name: &str,
+    limit: OutputLimit,
+    filter: Option<BoxedFilter<T>>,
+    conflation: Option<BoxedConflationKey<T>>,
+) -> (Consumer<T>, Receiver<SequencedEvent<T>>, ConsumerLag) {
+    let metrics = Arc::<Metrics>::default();
+    let progress = Arc::<Progress>::default();
+    let (mut output, receiver) = Output::new(limit, None, Arc::clone(&metrics));
+    if let Some(key) = conflation {
+        output.set_conflation(key);
+    }
+    let consumer = Consumer {
+        name: name.to_string(),
+        output,
+        filter,
+        progress: Arc::clone(&progress),
+        downgraded: false,
+    };
+    let lag = ConsumerLag {
+        name: name.to_string(),
+        metrics,
+        progress,
+    };
+    (consumer, receiver, lag)
+}
+
+// Subscribes consumers to a running FanOut from any thread. They get the
+// events it receives after they subscribe.
+#[derive(Clone)]
+pub struct Subscriber<T> {
+    joining: Sender<Consumer<T>>,
+}
+
+impl<T: Sequenced> Subscriber<T> {
+    pub fn subscribe(
+        &self,
+        name: &str,
+        limit: OutputLimit,
+    ) -> (Receiver<SequencedEvent<T>>, ConsumerLag) {
+        let (consumer, receiver, lag) = consumer(name, limit, None, None);
+        // The consumer sees a hang up if the fan-out has stopped
+        let _ = self.joining.send(consumer);
+        (receiver, lag)
+    }
+}
+
+impl<T: Sequenced + Clone> FanOut<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a consumer reading from a queue bounded by `limit`
+    pub fn subscribe(
+        &mut self,
+        name: &str,
+        limit: OutputLimit,
+    ) -> (Receiver<SequencedEvent<T>>, ConsumerLag) {
+        self.add(name, limit, None, None)
+    }
+
+    // subscribe() to only the events `filter` matches
+    pub fn subscribe_filtered(
+        &mut self,
+        name: &str,
+        limit: OutputLimit,
+        filter: BoxedFilter<T>,
+    ) -> (Receiver<SequencedEvent<T>>, ConsumerLag) {
+        self.add(name, limit, Some(filter), None)
+    }
+
+    // subscribe() conflated by `key` while its queue is full, which it never
+    // waits on whatever `limit`'s policy
+    pub fn subscribe_conflated(
+        &mut self,
+        name: &str,
+        limit: OutputLimit,
+        key: BoxedConflationKey<T>,
+    ) -> (Receiver<SequencedEvent<T>>, ConsumerLag) {
+        self.add(name, limit, None, Some(key))
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        limit: OutputLimit,
+        filter: Option<BoxedFilter<T>>,
+        conflation: Option<BoxedConflationKey<T>>,
+    ) -> (Receiver<SequencedEvent<T>>, ConsumerLag) {
+        let (consumer, receiver, lag) = consumer(name, limit, filter, conflation);
+        self.consumers.push(consumer);
+        (receiver, lag)
+    }
+
+    // Apply `policy` to consumers more than `max_lag` blocks behind, by
+    // what they reported to ConsumerLag::consumed(). Under OutputPolicy::Block
+    // it needs to be less than their queues' capacity, or a slow consumer
+    // holds up the others before it is that far behind.
+    pub fn set_max_lag(&mut self, max_lag: u64, policy: SlowConsumerPolicy) {
+        self.max_lag = Some((max_lag, policy));
+    }
+
+    // Handle for subscribing consumers once running. Only the last handle
+    // asked for is listened to.
+    pub fn subscriber(&mut self) -> Subscriber<T> {
+        let (joining, receiver) = unbounded();
+        self.joining = Some(receiver);
+        Subscriber { joining }
+    }
+
+    // Broadcast events from `receiver` until it hangs up or every consumer
+    // has and no more can subscribe, then hand consumers what is left of
+    // their backlogs
+    pub fn run(mut self, receiver: Receiver<SequencedEvent<T>>) {
+        while !self.consumers.is_empty() || self.joining.is_some() {
+            match receiver.recv_timeout(FLUSH_INTERVAL) {
+                Ok(event) => {
+                    if let Some(joining) = &self.joining {
+                        self.consumers.extend(joining.try_iter());
+                    }
+                    self.broadcast(event)
+                }
+                Err(RecvTimeoutError::Timeout) => self.flush(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn broadcast(&mut self, event: SequencedEvent<T>) {
+        let mut wants = mem::take(&mut self.wants);
+        wants.clear();
+        wants.extend(
+            self.consumers
+                .iter()
+                .map(|c| c.filter.as_ref().is_none_or(|f| f.matches(&event))),
+        );
+        // The last consumer it goes to gets it without a clone
+        let last = wants.iter().rposition(|&w| w);
+        let mut event = Some(event);
+        let mut i = 0;
+        let max_lag = self.max_lag;
+        self.consumers.retain_mut(|c| {
+            let wanted = wants[i];
+            let e = match Some(i) == last {
+                true => event.take(),
+                false if wanted => event.clone(),
+                false => None,
+            };
+            i += 1;
+            let mut e = match e {
+                Some(e) => e,
+                None => return true,
+            };
+            if let SequencedEvent::Block(b) = &e {
+                let behind = c.progress.behind();
+                match max_lag {
+                    Some((max_lag, SlowConsumerPolicy::Disconnect)) if behind > max_lag => {
+                        warn!(consumer = c.name, behind, "evicting slow consumer");
+                        c.progress.evicted.store(true, Relaxed);
+                        c.output.discard();
+                        return false;
+                    }
+                    Some((max_lag, SlowConsumerPolicy::GapNotify)) if behind > max_lag => {
+                        if !c.downgraded {
+                            warn!(consumer = c.name, behind, "consumer behind, sending gaps");
+                            c.downgraded = true;
+                        }
+                        let (from, n) = (b.seqnum(), b.n_messages() as u64);
+                        c.progress.downgraded.fetch_add(1, Relaxed);
+                        e = SequencedEvent::Gap {
+                            channel: b.channel(),
+                            from,
+                            to: from + n,
+                            reason: GapReason::Lagged,
+                        };
+                    }
+                    _ => {
+                        if c.downgraded {
+                            info!(consumer = c.name, "consumer caught up");
+                            c.downgraded = false;
+                        }
+                        c.progress.queued.fetch_add(1, Relaxed);
+                    }
+                }
+            }
+            let sent = c.output.try_send(e).is_ok();
+            let conflated = c.output.take_conflated();
+            if conflated > 0 {
+                c.progress.conflated.fetch_add(conflated, Relaxed);
+            }
+            if !sent {
+                info!(consumer = c.name, "consumer hung up");
+            }
+            sent
+        });
+        self.wants = wants;
+    }
+
+    fn flush(&mut self) {
+        self.consumers.retain(|c| {
+            let flushed = c.output.try_flush().is_ok();
+            if !flushed {
+                info!(consumer = c.name, "consumer hung up");
+            }
+            flushed
+        });
+    }
+}
+
+impl<T: Sequenced + Clone + Send + 'static> FanOut<T> {
+    // run() on a thread of its own
+    pub fn spawn(self, receiver: Receiver<SequencedEvent<T>>) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new()
+            .name("fan-out".to_string())
+            .spawn(move || self.run(receiver))
+    }
+}
+
+// How far a consumer is behind, readable from any thread
+#[derive(Clone)]
+pub struct ConsumerLag {
+    name: String,
+    metrics: Arc<Metrics>,
+    progress: Arc<Progress>,
+}
+
+impl ConsumerLag {
+    // Called by the consumer with each event it is done with
+    pub fn consumed<T: Sequenced>(&self, event: &SequencedEvent<T>) {
+        if let SequencedEvent::Block(b) = event {
+            self.progress.consumed.fetch_add(1, Relaxed);
+            let end = b.seqnum() + b.n_messages() as u64;
+            self.progress.watermark.store(end, Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> ConsumerStats {
+        ConsumerStats {
+            name: self.name.clone(),
+            depth: self.metrics.output_depth.load(Relaxed),
+            max_depth: self.metrics.max_output_depth.load(Relaxed),
+            lagged: self.metrics.lagged.load(Relaxed),
+            watermark: self.progress.watermark.load(Relaxed),
+            behind: self.progress.behind(),
+            downgraded: self.progress.downgraded.load(Relaxed),
+            conflated: self.progress.conflated.load(Relaxed),
+            evicted: self.progress.evicted.load(Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConsumerStats {
+    pub name: String,
+    // Events queued for it as of the last one sent
+    pub depth: usize,
+    pub max_depth: usize,
+    // Seqnums dropped from its queue because it fell behind
+    pub lagged: u64,
+    // Seqnum after the last block it consumed
+    pub watermark: u64,
+    // Blocks queued that it hasn't consumed
+    pub behind: u64,
+    // Blocks it was sent gaps for under SlowConsumerPolicy::GapNotify
+    pub downgraded: u64,
+    // Queued blocks replaced by later ones of the same key
+    pub conflated: u64,
+    pub evicted: bool,
+}
+
+impl fmt::Display for ConsumerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} depth {} max depth {} lagged {} watermark {} behind {} downgraded {} conflated {}{}",
+            self.name,
+            self.depth,
+            self.max_depth,
+            self.lagged,
+            self.watermark,
+            self.behind,
+            self.downgraded,
+            self.conflated,
+            if self.evicted { " evicted" } else { "" }
+        )
+    }
+}
@@ -0,0 +1,86 @@
+use crate::udp::parse_header;
+use crate::{Block, ChannelId, Payload};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::ops::Range;
+use std::time::Duration;
+use tracing::warn;
+
+// Asked to recover a range of seqnums before the sequencer gives up on a gap.
+// May return fewer blocks than requested or blocks outside the range.
+pub trait GapFiller<T> {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<T>>;
+}
+
+// Requests seqnum ranges from a retransmission server over TCP.
+//
+// Request: channel: u32 BE, start: u64 BE, end (exclusive): u64 BE
+// Response: frames of len: u16 BE followed by a raw datagram
+// (seqnum: u64 BE, n_messages: u16 BE, payload). A frame of len 0 ends the
+// response.
+pub struct TcpGapFiller {
+    addr: SocketAddr,
+    pub retries: u32,
+    pub timeout: Duration,
+}
+
+impl TcpGapFiller {
+    pub fn new(addr: SocketAddr, retries: u32, timeout: Duration) -> Self {
+        Self {
+            addr,
+            retries,
+            timeout,
+        }
+    }
+
+    fn request(&self, channel: ChannelId, range: &Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut req = [0_u8; 20];
+        req[0..4].copy_from_slice(&channel.to_be_bytes());
+        req[4..12].copy_from_slice(&range.start.to_be_bytes());
+        req[12..20].copy_from_slice(&range.end.to_be_bytes());
+        stream.write_all(&req)?;
+
+        let mut res = Vec::new();
+        let mut buf = vec![0_u8; u16::MAX as usize];
+        loop {
+            let mut len = [0_u8; 2];
+            stream.read_exact(&mut len)?;
+            let len = u16::from_be_bytes(len) as usize;
+            if len == 0 {
+                return Ok(res);
+            }
+            stream.read_exact(&mut buf[..len])?;
+            let mut header = parse_header(&buf[..len])
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short frame"))?;
+            header.channel = channel;
+            res.push(Block::new(header, buf[crate::udp::HEADER_LEN..len].into()));
+        }
+    }
+}
+
+impl GapFiller<Block<Payload>> for TcpGapFiller {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        let mut attempt = 0;
+        loop {
+            match self.request(channel, &range) {
+                Ok(blocks) => return Ok(blocks),
+                Err(e) if attempt < self.retries => {
+                    warn!(
+                        channel,
+                        start = range.start,
+                        end = range.end,
+                        attempt,
+                        error = %e,
+                        "gap fill attempt failed"
+                    );
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// A record of every gap the sequencer saw, for compliance and for
+// troubleshooting the network: the seqnums missing, when that was noticed,
+// how the gap was resolved and how long that took. Exported as CSV or JSON
+// at exit, at the session end time, or by an operator over the admin socket.
+use crate::{ChannelId, GapReason};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    // The missing blocks arrived late, or standby copies of them were used
+    Recovered,
+    // The gap filler had them
+    Retransmitted,
+    // Jumped past from a snapshot
+    Resynced,
+    // Skipped, as reported by a Gap event
+    Abandoned(GapReason),
+}
+
+impl Resolution {
+    // Of the ways parts of a gap were resolved, the one the gap is reported
+    // resolved by is the worst
+    fn rank(&self) -> u8 {
+        match self {
+            Resolution::Recovered => 0,
+            Resolution::Retransmitted => 1,
+            Resolution::Resynced => 2,
+            Resolution::Abandoned(_) => 3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resolution::Recovered => "recovered",
+            Resolution::Retransmitted => "retransmitted",
+            Resolution::Resynced => "resynced",
+            Resolution::Abandoned(_) => "abandoned",
+        }
+    }
+}
+
+fn reason(reason: GapReason) -> &'static str {
+    match reason {
+        GapReason::Timeout => "timeout",
+        GapReason::SessionReset => "session_reset",
+        GapReason::Overflow => "overflow",
+        GapReason::Shutdown => "shutdown",
+        GapReason::Lagged => "lagged",
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GapRecord {
+    pub channel: ChannelId,
+    pub seqnums: Range<u64>,
+    pub detected: SystemTime,
+    // Until it was resolved, or so far if it is still open
+    pub duration: Duration,
+    // None while still open
+    pub resolution: Option<Resolution>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    // JSON for a .json file, otherwise CSV
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ReportFormat::Json,
+            _ => ReportFormat::Csv,
+        }
+    }
+}
+
+pub fn write_report<W: Write>(
+    gaps: &[GapRecord],
+    format: ReportFormat,
+    w: &mut W,
+) -> io::Result<()> {
+    if format == ReportFormat::Csv {
+        writeln!(w, "channel,from,to,detected,duration_us,resolution,reason")?;
+    } else {
+        writeln!(w, "[")?;
+    }
+    for (i, gap) in gaps.iter().enumerate() {
+        let detected = gap.detected.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let (resolution, reason) = match gap.resolution {
+            Some(Resolution::Abandoned(r)) => ("abandoned", reason(r)),
+            Some(resolution) => (resolution.name(), ""),
+            None => ("open", ""),
+        };
+        let fields = (gap.channel, gap.seqnums.start, gap.seqnums.end);
+        let duration_us = gap.duration.as_micros();
+        match format {
+            ReportFormat::Csv => writeln!(
+                w,
+                "{},{},{},{}.{:09},{},{},{}",
+                fields.0,
+                fields.1,
+                fields.2,
+                detected.as_secs(),
+                detected.subsec_nanos(),
+                duration_us,
+                resolution,
+                reason
+            )?,
+            ReportFormat::Json => writeln!(
+                w,
+                r#"  {{"channel":{},"from":{},"to":{},"detected":{}.{:09},"duration_us":{},"resolution":"{}","reason":"{}"}}{}"#,
+                fields.0,
+                fields.1,
+                fields.2,
+                detected.as_secs(),
+                detected.subsec_nanos(),
+                duration_us,
+                resolution,
+                reason,
+                if i + 1 < gaps.len() { "," } else { "" }
+            )?,
+        }
+    }
+    if format == ReportFormat::Json {
+        writeln!(w, "]")?;
+    }
+    Ok(())
+}
+
+// Write the report to `path` in the format its extension says. It is written
+// aside and renamed into place, so a reader never sees half of one.
+pub fn export(gaps: &[GapRecord], path: &Path) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut w = BufWriter::new(File::create(&tmp)?);
+    write_report(gaps, ReportFormat::for_path(path), &mut w)?;
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+struct OpenGap {
+    channel: ChannelId,
+    seqnums: Range<u64>,
+    detected: SystemTime,
+    at: Instant,
+    // The worst way parts of it were resolved so far
+    resolution: Option<Resolution>,
+}
+
+impl OpenGap {
+    fn record(&self, now: Instant) -> GapRecord {
+        GapRecord {
+            channel: self.channel,
+            seqnums: self.seqnums.clone(),
+            detected: self.detected,
+            duration: now.saturating_duration_since(self.at),
+            resolution: self.resolution,
+        }
+    }
+}
+
+// A channel's open gaps, lowest seqnums first
+#[derive(Default)]
+pub(crate) struct OpenGaps {
+    gaps: VecDeque<OpenGap>,
+}
+
+impl OpenGaps {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.gaps.is_empty()
+    }
+
+    // Seqnums above those of every open gap went missing at `at`
+    pub(crate) fn open(&mut self, channel: ChannelId, seqnums: Range<u64>, at: Instant) {
+        self.gaps.push_back(OpenGap {
+            channel,
+            seqnums,
+            detected: SystemTime::now(),
+            at,
+            resolution: None,
+        });
+    }
+
+    // The seqnums `got_past` were got past by `how`. Gaps wholly got past are
+    // closed and moved to `closed`.
+    pub(crate) fn resolve(
+        &mut self,
+        got_past: Range<u64>,
+        how: Resolution,
+        now: Instant,
+        closed: &mut Vec<GapRecord>,
+    ) {
+        for gap in &mut self.gaps {
+            if gap.seqnums.start >= got_past.end {
+                break;
+            }
+            if gap.seqnums.end > got_past.start
+                && gap.resolution.is_none_or(|r| r.rank() < how.rank())
+            {
+                gap.resolution = Some(how);
+            }
+        }
+        while self
+            .gaps
+            .front()
+            .is_some_and(|gap| gap.seqnums.end <= got_past.end)
+        {
+            let mut gap = self.gaps.pop_front().unwrap();
+            gap.resolution.get_or_insert(how);
+            closed.push(gap.record(now));
+        }
+    }
+
+    // As of `now`, with how long they have been open
+    pub(crate) fn records(&self, now: Instant) -> impl Iterator<Item = GapRecord> + '_ {
+        self.gaps.iter().map(move |gap| GapRecord {
+            resolution: None,
+            ..gap.record(now)
+        })
+    }
+}
@@ -0,0 +1,245 @@
+// Liveness and readiness of a running sequencer, for systemd or Kubernetes
+// to supervise, judged from its metrics:
+//   live   the arbiter has polled feed liveness within `stall`, so it isn't
+//          wedged
+//   ready  live, no feed is down, the reorder buffers hold fewer than
+//          `max_buffered` blocks and the consumer is fewer than
+//          `max_output_depth` events behind
+// Served over HTTP as GET /live and GET /ready, 200 or 503 with the reasons
+// in the body, or kept as a heartbeat file rewritten while ready and removed
+// while not, so a stale or missing file means unhealthy.
+use crate::metrics::Metrics;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// Longest a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+pub struct HealthConfig {
+    pub max_buffered: usize,
+    pub max_output_depth: usize,
+    pub stall: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_buffered: crate::BUFFER_LEN,
+            max_output_depth: 65_536,
+            stall: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Status {
+    pub live: bool,
+    // Why it isn't ready, empty if it is
+    pub problems: Vec<String>,
+}
+
+impl Status {
+    pub fn ready(&self) -> bool {
+        self.live && self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problems.is_empty() {
+            true => write!(f, "ok"),
+            false => write!(f, "{}", self.problems.join("; ")),
+        }
+    }
+}
+
+// Checks a sequencer's metrics from any thread
+#[derive(Clone)]
+pub struct Health {
+    metrics: Arc<Metrics>,
+    config: HealthConfig,
+    // Polls seen by the last check and when they last changed
+    last: Arc<Mutex<(u64, Instant)>>,
+}
+
+impl Health {
+    pub fn new(metrics: Arc<Metrics>, config: HealthConfig) -> Self {
+        let polls = metrics.polls.load(Relaxed);
+        Self {
+            metrics,
+            config,
+            last: Arc::new(Mutex::new((polls, Instant::now()))),
+        }
+    }
+
+    pub fn check(&self) -> Status {
+        let polls = self.metrics.polls.load(Relaxed);
+        let since = {
+            let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+            if last.0 != polls {
+                *last = (polls, Instant::now());
+            }
+            last.1.elapsed()
+        };
+        let live = since <= self.config.stall;
+        let mut problems = Vec::new();
+        if !live {
+            problems.push(format!("arbiter stalled for {:?}", since));
+        }
+        let down = self.metrics.feeds_down.load(Relaxed);
+        if down > 0 {
+            problems.push(format!("{} feeds down", down));
+        }
+        let buffered = self.metrics.buffered.load(Relaxed);
+        if buffered >= self.config.max_buffered {
+            problems.push(format!("{} blocks buffered", buffered));
+        }
+        let depth = self.metrics.output_depth.load(Relaxed);
+        if depth >= self.config.max_output_depth {
+            problems.push(format!("consumer {} events behind", depth));
+        }
+        Status { live, problems }
+    }
+}
+
+// Answers GET /live and GET /ready (or /) on one thread, a connection at a
+// time since each is a single short request
+pub struct HealthServer {
+    listener: TcpListener,
+    health: Health,
+}
+
+impl HealthServer {
+    pub fn bind(addr: SocketAddr, health: Health) -> io::Result<Self> {
+        Ok(Self::with_listener(TcpListener::bind(addr)?, health))
+    }
+
+    // Answer on a listener already bound, such as one systemd bound
+    pub fn with_listener(listener: TcpListener, health: Health) -> Self {
+        Self { listener, health }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Answer clients until the listener fails
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            if let Err(e) = self.answer(stream) {
+                warn!(error = %e, "health request failed");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("health".to_string())
+            .spawn(move || self.run())
+    }
+
+    fn answer(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let mut words = line.split_whitespace();
+        let (method, path) = (words.next(), words.next());
+        let (code, body) = match (method, path) {
+            (Some("GET"), Some("/live")) => {
+                let status = self.health.check();
+                (if status.live { 200 } else { 503 }, status.to_string())
+            }
+            (Some("GET"), Some("/ready" | "/")) => {
+                let status = self.health.check();
+                (if status.ready() { 200 } else { 503 }, status.to_string())
+            }
+            (Some("GET"), _) => (404, "not found".to_string()),
+            _ => (405, "method not allowed".to_string()),
+        };
+        let reason = match code {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Service Unavailable",
+        };
+        let mut w = &stream;
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+            code,
+            reason,
+            body.len() + 1,
+            body
+        )?;
+        w.flush()
+    }
+}
+
+// Rewrites a file every `interval` while ready and removes it while not
+pub struct HealthFile {
+    path: PathBuf,
+    health: Health,
+    interval: Duration,
+}
+
+impl HealthFile {
+    pub fn new<P: AsRef<Path>>(path: P, health: Health, interval: Duration) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            health,
+            interval,
+        }
+    }
+
+    // Check once, updating the file. Returns whether it is ready.
+    pub fn beat(&self) -> io::Result<bool> {
+        let status = self.health.check();
+        if status.ready() {
+            // Moved into place, so readers never see it half written
+            let tmp = self.path.with_extension("tmp");
+            fs::write(&tmp, format!("{}\n", status))?;
+            fs::rename(&tmp, &self.path)?;
+            return Ok(true);
+        }
+        match fs::remove_file(&self.path) {
+            Ok(()) => info!(path = %self.path.display(), %status, "not ready"),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(false)
+    }
+
+    // Beat until shutdown is requested, then remove the file
+    pub fn run(self, shutdown: Shutdown) -> io::Result<()> {
+        let mut next = Instant::now();
+        while !shutdown.requested() {
+            if Instant::now() >= next {
+                self.beat()?;
+                next += self.interval;
+            }
+            thread::sleep(SHUTDOWN_POLL.min(self.interval));
+        }
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn spawn(self, shutdown: Shutdown) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("health file".to_string())
+            .spawn(move || self.run(shutdown))
+    }
+}
@@ -0,0 +1,216 @@
+// Memory backed by huge pages, so the buffer pool and shm ring take a TLB
+// entry per 2MB or 1GB instead of per 4KB at high packet rates. Explicit huge
+// pages have to be reserved, such as through /proc/sys/vm/nr_hugepages, so
+// without them memory falls back to ordinary pages the kernel is asked to
+// back with transparent huge pages where it can.
+use serde::Deserialize;
+use std::io;
+use std::str::FromStr;
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum HugePages {
+    #[default]
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "2m")]
+    TwoMb,
+    #[serde(rename = "1g")]
+    OneGb,
+}
+
+impl HugePages {
+    // Bytes of a page, which sizes are rounded up to
+    pub fn page_len(&self) -> usize {
+        match self {
+            HugePages::Off => 4 << 10,
+            HugePages::TwoMb => 2 << 20,
+            HugePages::OneGb => 1 << 30,
+        }
+    }
+
+    // Rounded up to whole pages
+    pub fn round_up(&self, len: usize) -> usize {
+        len.next_multiple_of(self.page_len())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn mmap_flags(&self) -> libc::c_int {
+        match self {
+            HugePages::Off => 0,
+            HugePages::TwoMb => libc::MAP_HUGETLB | libc::MAP_HUGE_2MB,
+            HugePages::OneGb => libc::MAP_HUGETLB | libc::MAP_HUGE_1GB,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn mmap_flags(&self) -> libc::c_int {
+        0
+    }
+}
+
+impl FromStr for HugePages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(HugePages::Off),
+            "2m" => Ok(HugePages::TwoMb),
+            "1g" => Ok(HugePages::OneGb),
+            _ => Err(format!("unknown huge page size {}", s)),
+        }
+    }
+}
+
+// Ask for transparent huge pages for a mapping, which the kernel may ignore
+#[cfg(target_os = "linux")]
+pub(crate) fn advise(base: *mut u8, len: usize) -> io::Result<()> {
+    // SAFETY: advice only, for a range the caller mapped
+    match unsafe { libc::madvise(base.cast(), len, libc::MADV_HUGEPAGE) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn advise(_base: *mut u8, _len: usize) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Bytes of a page of the hugetlbfs the file is on, if it is. The file is in
+// huge pages whatever a mapping of it asks for and its length has to be
+// whole pages.
+#[cfg(target_os = "linux")]
+pub(crate) fn hugetlbfs_page_len(file: &std::fs::File) -> io::Result<Option<usize>> {
+    use std::os::fd::AsRawFd;
+
+    const HUGETLBFS_MAGIC: libc::c_long = 0x958458f6;
+    // SAFETY: all zeroes is a valid statfs, which outlives the call
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((stat.f_type as libc::c_long == HUGETLBFS_MAGIC).then_some(stat.f_bsize as usize))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn hugetlbfs_page_len(_file: &std::fs::File) -> io::Result<Option<usize>> {
+    Ok(None)
+}
+
+// Anonymous, zeroed memory mapped once and unmapped on drop
+pub struct Region {
+    base: *mut u8,
+    len: usize,
+    huge: bool,
+}
+
+// SAFETY: the region is plain memory, and its users keep to their own ranges
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    // At least `len` bytes in `pages`, or in ordinary pages if there aren't
+    // enough huge pages reserved
+    pub fn new(len: usize, pages: HugePages) -> io::Result<Self> {
+        // Only linux maps explicit huge pages
+        if pages != HugePages::Off && cfg!(target_os = "linux") {
+            let huge_len = pages.round_up(len);
+            match map(huge_len, pages.mmap_flags()) {
+                Ok(base) => {
+                    return Ok(Self {
+                        base,
+                        len: huge_len,
+                        huge: true,
+                    })
+                }
+                Err(e) => warn!(?pages, error = %e, "no huge pages, falling back"),
+            }
+        }
+        let len = HugePages::Off.round_up(len.max(1));
+        let base = map(len, 0)?;
+        if pages != HugePages::Off {
+            if let Err(e) = advise(base, len) {
+                warn!(error = %e, "no transparent huge pages either");
+            }
+        }
+        Ok(Self {
+            base,
+            len,
+            huge: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Whether it is in explicit huge pages
+    pub fn is_huge(&self) -> bool {
+        self.huge
+    }
+
+    pub(crate) fn base(&self) -> *mut u8 {
+        self.base
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: mapped in new
+        unsafe { unmap(self.base, self.len) };
+    }
+}
+
+#[cfg(unix)]
+fn map(len: usize, flags: libc::c_int) -> io::Result<*mut u8> {
+    // SAFETY: a new mapping, which aliases nothing in this process
+    let p = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+            -1,
+            0,
+        )
+    };
+    match p {
+        libc::MAP_FAILED => Err(io::Error::last_os_error()),
+        p => Ok(p.cast()),
+    }
+}
+
+#[cfg(unix)]
+unsafe fn unmap(base: *mut u8, len: usize) {
+    libc::munmap(base.cast(), len);
+}
+
+// Without mmap the memory comes from the allocator, aligned to a page
+#[cfg(not(unix))]
+fn map(len: usize, _flags: libc::c_int) -> io::Result<*mut u8> {
+    let layout = page_layout(len)?;
+    // SAFETY: the layout isn't empty, since lengths are whole pages
+    let p = unsafe { std::alloc::alloc_zeroed(layout) };
+    match p.is_null() {
+        true => Err(io::ErrorKind::OutOfMemory.into()),
+        false => Ok(p),
+    }
+}
+
+#[cfg(not(unix))]
+unsafe fn unmap(base: *mut u8, len: usize) {
+    if let Ok(layout) = page_layout(len) {
+        std::alloc::dealloc(base, layout);
+    }
+}
+
+#[cfg(not(unix))]
+fn page_layout(len: usize) -> io::Result<std::alloc::Layout> {
+    std::alloc::Layout::from_size_align(len, HugePages::Off.page_len())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
@@ -0,0 +1,285 @@
+// Sequencing of inputs that have no seqnums of their own, such as the orders
+// clients send an exchange. Each datagram an IngressSocket receives is one
+// input and Ingress totally orders them, numbering them from the channel's
+// next seqnum, so the sequencer journals and publishes them like a feed's
+// blocks.
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::{Block, BlockHeader, ChannelId, Payload};
+use crossbeam_channel::{Receiver, Sender};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const MAX_DATAGRAM: usize = 65_536;
+
+// Decides the order a batch of inputs is numbered in. The same batches must
+// always be put in the same order, whatever order they are given in, so ties
+// are broken by gateway and then by the order the gateway received them in.
+pub trait OrderingPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    fn order(&mut self, inputs: &mut Vec<Input>);
+}
+
+// As received, whichever gateway it was on
+#[derive(Clone, Debug, Default)]
+pub struct ByArrival;
+
+impl OrderingPolicy for ByArrival {
+    fn name(&self) -> &'static str {
+        "arrival"
+    }
+
+    fn order(&mut self, inputs: &mut Vec<Input>) {
+        inputs.sort_by_key(|i| (i.arrived, i.gateway, i.index));
+    }
+}
+
+// An input from each gateway in turn, each gateway's as it received them.
+// Each batch starts with the gateway after the last one the previous batch
+// took from, so none is always first.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl OrderingPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn order(&mut self, inputs: &mut Vec<Input>) {
+        let mut gateways: BTreeMap<usize, VecDeque<Input>> = BTreeMap::new();
+        inputs.sort_by_key(|i| (i.gateway, i.index));
+        for input in inputs.drain(..) {
+            gateways.entry(input.gateway).or_default().push_back(input);
+        }
+        while !gateways.is_empty() {
+            let gateway = match gateways.range(self.next..).next() {
+                Some((gateway, _)) => *gateway,
+                None => *gateways.keys().next().unwrap(),
+            };
+            let queue = gateways.get_mut(&gateway).unwrap();
+            inputs.extend(queue.pop_front());
+            if queue.is_empty() {
+                gateways.remove(&gateway);
+            }
+            self.next = gateway + 1;
+        }
+    }
+}
+
+// Higher priorities first, then as received
+#[derive(Clone, Debug, Default)]
+pub struct ByPriority;
+
+impl OrderingPolicy for ByPriority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn order(&mut self, inputs: &mut Vec<Input>) {
+        inputs.sort_by_key(|i| (Reverse(i.priority), i.arrived, i.gateway, i.index));
+    }
+}
+
+// Built in policies by name
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IngressOrder {
+    #[default]
+    Arrival,
+    RoundRobin,
+    Priority,
+}
+
+impl IngressOrder {
+    pub fn policy(&self) -> Box<dyn OrderingPolicy> {
+        match self {
+            IngressOrder::Arrival => Box::new(ByArrival),
+            IngressOrder::RoundRobin => Box::<RoundRobin>::default(),
+            IngressOrder::Priority => Box::new(ByPriority),
+        }
+    }
+}
+
+impl FromStr for IngressOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrival" => Ok(IngressOrder::Arrival),
+            "round-robin" => Ok(IngressOrder::RoundRobin),
+            "priority" => Ok(IngressOrder::Priority),
+            _ => Err(format!("unknown ingress order {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Input {
+    // Socket it was received on
+    pub gateway: usize,
+    // Of the inputs its gateway received, from 0
+    pub index: u64,
+    pub priority: u8,
+    pub arrived: Instant,
+    pub payload: Payload,
+}
+
+// Receives client inputs, a datagram each
+pub struct IngressSocket {
+    socket: UdpSocket,
+    gateway: usize,
+    index: u64,
+    priority: u8,
+    // Offset of the byte of an input that is its priority
+    priority_field: Option<usize>,
+    shutdown: Option<Shutdown>,
+    buf: Vec<u8>,
+}
+
+impl IngressSocket {
+    pub fn bind(addr: SocketAddr, gateway: usize) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+            gateway,
+            index: 0,
+            priority: 0,
+            priority_field: None,
+            shutdown: None,
+            buf: vec![0; MAX_DATAGRAM],
+        })
+    }
+
+    // Of inputs without a priority field or too short for it
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+
+    pub fn set_priority_field(&mut self, offset: usize) {
+        self.priority_field = Some(offset);
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Stop run() once `shutdown` is requested, even if nothing arrives
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) -> io::Result<()> {
+        self.socket.set_read_timeout(Some(SHUTDOWN_POLL))?;
+        self.shutdown = Some(shutdown);
+        Ok(())
+    }
+
+    // Receive until the ingress hangs up or shutdown is requested
+    pub fn run(mut self, sender: Sender<Input>) -> io::Result<()> {
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            let len = match self.socket.recv(&mut self.buf) {
+                Ok(len) => len,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let payload = &self.buf[..len];
+            let priority = self.priority_field.and_then(|at| payload.get(at).copied());
+            let input = Input {
+                gateway: self.gateway,
+                index: self.index,
+                priority: priority.unwrap_or(self.priority),
+                arrived: Instant::now(),
+                payload: payload.into(),
+            };
+            self.index += 1;
+            if sender.send(input).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Orders inputs and numbers them, a block of one message each
+pub struct Ingress {
+    channel: ChannelId,
+    // Of the next input
+    seqnum: u64,
+    policy: Box<dyn OrderingPolicy>,
+    window: Duration,
+}
+
+impl Ingress {
+    pub fn new(channel: ChannelId, seqnum: u64) -> Self {
+        Self {
+            channel,
+            seqnum,
+            policy: Box::new(ByArrival),
+            window: Duration::ZERO,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: Box<dyn OrderingPolicy>) {
+        self.policy = policy;
+    }
+
+    // Inputs received within `window` of the first of them are ordered
+    // together, delaying it up to `window`
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    // Number `inputs` in the policy's order
+    pub fn sequence(&mut self, mut inputs: Vec<Input>) -> Vec<Block<Payload>> {
+        self.policy.order(&mut inputs);
+        inputs
+            .into_iter()
+            .map(|input| {
+                let header = BlockHeader {
+                    channel: self.channel,
+                    seqnum: self.seqnum,
+                    n_messages: 1,
+                    ..Default::default()
+                };
+                self.seqnum += 1;
+                Block::new(header, input.payload)
+            })
+            .collect()
+    }
+
+    // Sequence what arrives on `inputs` until every IngressSocket is gone or
+    // the arbiter hangs up
+    pub fn run(
+        mut self,
+        inputs: Receiver<Input>,
+        sender: Sender<Block<Payload>>,
+    ) -> io::Result<()> {
+        while let Ok(first) = inputs.recv() {
+            let mut batch = vec![first];
+            let until = batch[0].arrived + self.window;
+            while let Ok(input) = inputs.recv_deadline(until) {
+                batch.push(input);
+            }
+            batch.extend(inputs.try_iter());
+            for block in self.sequence(batch) {
+                if sender.send(block).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,791 @@
+// Binary journal of sequenced blocks. All integers are little endian.
+//
+// File header: magic "SEQJRNL\0", version: u16, session: [u8; 10]
+// Record: len: u32 (bytes after this field), crc32c: u32 (of the bytes after
+// this field), channel: u32, seqnum: u64, n_messages: u16, ts: u64 (ns since
+// the unix epoch), payload
+//
+// Compressed journals are the same as an LZ4 frame.
+use crate::checkpoint::Checkpoint;
+use crate::lz4::{self, Lz4Reader, Lz4Writer};
+use crate::pcap::Speed;
+use crate::{Block, BlockHeader, ChannelId, Payload, Session, SESSION_LEN};
+use crossbeam_channel::Sender;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+pub const MAGIC: [u8; 8] = *b"SEQJRNL\0";
+pub const VERSION: u16 = 1;
+pub const FILE_HEADER_LEN: usize = 8 + 2 + SESSION_LEN;
+// crc, channel, seqnum, n_messages, ts
+pub const RECORD_HEADER_LEN: usize = 4 + 4 + 8 + 2 + 8;
+// Largest payload a record is written with, and so read with, so a corrupt
+// length can't make a reader allocate gigabytes
+pub const MAX_PAYLOAD_LEN: usize = 16 << 20;
+// Default milliseconds between fsyncs
+pub const FSYNC_MS: u64 = 1000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub channel: ChannelId,
+    pub seqnum: u64,
+    pub n_messages: u16,
+    pub ts: SystemTime,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            channel: self.channel,
+            seqnum: self.seqnum,
+            n_messages: self.n_messages,
+            ..Default::default()
+        }
+    }
+
+    pub fn into_block(self) -> Block<Payload> {
+        Block::new(self.header(), self.payload.into())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Session ids shorter than 10 bytes are padded with spaces like MoldUDP64's
+pub fn session(id: &str) -> Session {
+    let mut session = [b' '; SESSION_LEN];
+    let len = id.len().min(SESSION_LEN);
+    session[..len].copy_from_slice(&id.as_bytes()[..len]);
+    session
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Err("zstd is not built in, use lz4".to_string()),
+            _ => Err(format!("unknown compression {}", s)),
+        }
+    }
+}
+
+// Where a JournalWriter's records go
+enum Output {
+    Plain(BufWriter<File>),
+    Lz4(Lz4Writer<BufWriter<File>>),
+}
+
+impl Output {
+    fn file(&self) -> &File {
+        match self {
+            Output::Plain(w) => w.get_ref(),
+            Output::Lz4(w) => w.get_ref().get_ref(),
+        }
+    }
+
+    // End a compressed journal's frame, after which it takes no more records
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Lz4(w) => w.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Lz4(w) => w.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.write_all(buf),
+            Output::Lz4(w) => w.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Lz4(w) => w.flush(),
+        }
+    }
+}
+
+// Whether the file `r` reads starts an LZ4 frame
+fn compressed<R: BufRead>(r: &mut R) -> io::Result<bool> {
+    Ok(r.fill_buf()?.starts_with(&lz4::MAGIC.to_le_bytes()))
+}
+
+// Asks a JournalWriter on another thread to rotate before its next record.
+// Clones share the request.
+#[derive(Clone, Debug, Default)]
+pub struct Rotation(Arc<AtomicBool>);
+
+impl Rotation {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Rolls a journal into segments named "<stem>-<session>-<yyyymmdd>-<first
+// seqnum>.<ext>" next to it, by the session, date and seqnum of their first
+// record, then retires segments older than `retention`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Segments {
+    // Roll once the journal is at least this long, before any compression
+    pub max_bytes: Option<u64>,
+    // Roll once the journal has been written this long
+    pub max_age: Option<Duration>,
+    pub retention: Option<Duration>,
+    // Command retired segments are compressed with, given the segment's path
+    // as its last argument and expected to replace it, such as "zstd -q
+    // --rm". Retired segments are deleted if None.
+    pub compress: Option<String>,
+}
+
+impl Segments {
+    pub fn rolls(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+}
+
+pub struct JournalWriter {
+    w: Output,
+    path: PathBuf,
+    session: Session,
+    compression: Compression,
+    fsync_interval: Option<Duration>,
+    last_sync: Instant,
+    record: Vec<u8>,
+    rotation: Option<Rotation>,
+    segments: Option<Segments>,
+    opened: Instant,
+    // Length of the file, including buffered records
+    offset: u64,
+    // Seqnum each channel expects after the last record
+    next: BTreeMap<ChannelId, u64>,
+    checkpoint: Option<CheckpointFile>,
+}
+
+struct CheckpointFile {
+    path: PathBuf,
+    interval: Duration,
+    last: Instant,
+}
+
+impl JournalWriter {
+    // Records are fsynced at most `fsync_interval` after being appended, or
+    // left to the OS if None
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        session: &Session,
+        fsync_interval: Option<Duration>,
+    ) -> io::Result<Self> {
+        Self::create_compressed(path, session, fsync_interval, Compression::None)
+    }
+
+    // Records are compressed as they are written. Syncing ends the block of
+    // records being compressed, so frequent syncs compress less.
+    pub fn create_compressed<P: AsRef<Path>>(
+        path: P,
+        session: &Session,
+        fsync_interval: Option<Duration>,
+        compression: Compression,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            w: Self::open(&path, session, compression)?,
+            path,
+            session: *session,
+            compression,
+            fsync_interval,
+            last_sync: Instant::now(),
+            record: Vec::new(),
+            rotation: None,
+            segments: None,
+            opened: Instant::now(),
+            offset: FILE_HEADER_LEN as u64,
+            next: BTreeMap::new(),
+            checkpoint: None,
+        })
+    }
+
+    // Carry on the journal at `path` from where `checkpoint` left it,
+    // truncating records written after it. Its session is kept.
+    pub fn resume<P: AsRef<Path>>(
+        path: P,
+        checkpoint: &Checkpoint,
+        fsync_interval: Option<Duration>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if compressed(&mut BufReader::new(File::open(&path)?))? {
+            return Err(invalid("compressed journals can't be resumed".to_string()));
+        }
+        let session = *JournalReader::open(&path)?.session();
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        let len = file.metadata()?.len();
+        if len < checkpoint.journal_offset {
+            return Err(invalid(format!(
+                "journal of {} bytes is behind its checkpoint at {}",
+                len, checkpoint.journal_offset
+            )));
+        }
+        file.set_len(checkpoint.journal_offset)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            w: Output::Plain(BufWriter::new(file)),
+            path,
+            session,
+            compression: Compression::None,
+            fsync_interval,
+            last_sync: Instant::now(),
+            record: Vec::new(),
+            rotation: None,
+            segments: None,
+            opened: Instant::now(),
+            offset: checkpoint.journal_offset,
+            next: checkpoint.channels.clone(),
+            checkpoint: None,
+        })
+    }
+
+    fn open(path: &Path, session: &Session, compression: Compression) -> io::Result<Output> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut w = match compression {
+            Compression::None => Output::Plain(file),
+            Compression::Lz4 => Output::Lz4(Lz4Writer::new(file)?),
+        };
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(session)?;
+        Ok(w)
+    }
+
+    // Save a Checkpoint to `path` on each sync, and at least every `interval`
+    pub fn set_checkpoint(&mut self, path: &Path, interval: Duration) {
+        self.checkpoint = Some(CheckpointFile {
+            path: path.to_path_buf(),
+            interval,
+            last: Instant::now(),
+        });
+    }
+
+    // Where the journal next resumes from
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            journal_offset: self.offset,
+            channels: self.next.clone(),
+        }
+    }
+
+    // Following records of `channel` start at `seqnum`
+    pub fn set_next(&mut self, channel: ChannelId, seqnum: u64) {
+        self.next.insert(channel, seqnum);
+    }
+
+    // Forget `channel`, whose session ended. Once no channel is left the
+    // journal is rotated and checkpointed, returning where it went.
+    pub fn end_session(&mut self, channel: ChannelId) -> io::Result<Option<PathBuf>> {
+        self.next.remove(&channel);
+        if !self.next.is_empty() {
+            return Ok(None);
+        }
+        let rotated = self.rotate()?;
+        self.sync()?;
+        Ok(Some(rotated))
+    }
+
+    // Rotate whenever `rotation` is requested
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = Some(rotation);
+    }
+
+    // Roll into segments, and name rotated journals like them
+    pub fn set_segments(&mut self, segments: Segments) {
+        self.segments = Some(segments);
+    }
+
+    // Move the journal aside to its segment name if rolling segments, or else
+    // the first free "<path>.<n>", and start a new one at its path. Returns
+    // where the old one went.
+    pub fn rotate(&mut self) -> io::Result<PathBuf> {
+        self.sync()?;
+        let rotated = match &self.segments {
+            Some(_) => self.segment_path()?,
+            None => (1..)
+                .map(|n| {
+                    let mut name = self.path.clone().into_os_string();
+                    name.push(format!(".{}", n));
+                    PathBuf::from(name)
+                })
+                .find(|p| !p.exists())
+                .unwrap(),
+        };
+        self.w.finish()?;
+        fs::rename(&self.path, &rotated)?;
+        self.w = Self::open(&self.path, &self.session, self.compression)?;
+        self.offset = FILE_HEADER_LEN as u64;
+        self.opened = Instant::now();
+        // The checkpoint saved above is of the old journal
+        self.sync()?;
+        if let Some(segments) = self.segments.clone().filter(|s| s.retention.is_some()) {
+            let path = self.path.clone();
+            thread::Builder::new()
+                .name("journal retention".to_string())
+                .spawn(move || match retire_segments(&path, &segments) {
+                    Ok(retired) => {
+                        for segment in retired {
+                            info!(segment = %segment.display(), "retired journal segment");
+                        }
+                    }
+                    Err(e) => warn!(%e, "retiring journal segments failed"),
+                })?;
+        }
+        Ok(rotated)
+    }
+
+    // Named by the journal's first record, or now if it has none
+    fn segment_path(&self) -> io::Result<PathBuf> {
+        let (ts, seqnum) = match JournalReader::open(&self.path)?.next() {
+            Some(record) => {
+                let record = record?;
+                (record.ts, record.seqnum)
+            }
+            None => (SystemTime::now(), 0),
+        };
+        let session = String::from_utf8_lossy(&self.session);
+        let mut base = segment_stem(&self.path);
+        if !session.trim().is_empty() {
+            base.push_str(&format!("{}-", session.trim()));
+        }
+        base.push_str(&format!("{}-{}", date(ts), seqnum));
+        let named = |suffix: String| {
+            let name = match self.path.extension() {
+                Some(ext) => format!("{}{}.{}", base, suffix, ext.to_string_lossy()),
+                None => format!("{}{}", base, suffix),
+            };
+            self.path.with_file_name(name)
+        };
+        let segment = named(String::new());
+        if !segment.exists() {
+            return Ok(segment);
+        }
+        // Such as when a session's seqnums restart the same day
+        Ok((1..)
+            .map(|n| named(format!("-{}", n)))
+            .find(|p| !p.exists())
+            .unwrap())
+    }
+
+    // Whether the journal is due to roll into a new segment
+    fn roll_due(&self) -> bool {
+        let segments = match &self.segments {
+            Some(segments) if self.offset > FILE_HEADER_LEN as u64 => segments,
+            _ => return false,
+        };
+        segments.max_bytes.is_some_and(|max| self.offset >= max)
+            || segments
+                .max_age
+                .is_some_and(|max| self.opened.elapsed() >= max)
+    }
+
+    pub fn append(
+        &mut self,
+        header: &BlockHeader,
+        ts: SystemTime,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        self.write_record(header, ts, payload)?;
+        self.sync_if_due()
+    }
+
+    pub fn append_block(&mut self, block: &Block<Payload>, ts: SystemTime) -> io::Result<()> {
+        self.append(&block.header, ts, &block.payload)
+    }
+
+    // Append `blocks` all stamped `ts`, syncing at most once after them
+    pub fn append_blocks(&mut self, blocks: &[Block<Payload>], ts: SystemTime) -> io::Result<()> {
+        for block in blocks {
+            self.write_record(&block.header, ts, &block.payload)?;
+        }
+        self.sync_if_due()
+    }
+
+    fn write_record(
+        &mut self,
+        header: &BlockHeader,
+        ts: SystemTime,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            let e = format!("payload of {} bytes is too long to journal", payload.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+        if self.rotation.as_ref().is_some_and(|r| r.take()) {
+            let rotated = self.rotate()?;
+            info!(to = %rotated.display(), "rotated journal");
+        } else if self.roll_due() {
+            let rotated = self.rotate()?;
+            info!(to = %rotated.display(), "rolled journal segment");
+        }
+        let ns = ts.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let r = &mut self.record;
+        r.clear();
+        // Length and crc, filled in once the rest is there
+        r.extend_from_slice(&[0; 8]);
+        r.extend_from_slice(&header.channel.to_le_bytes());
+        r.extend_from_slice(&header.seqnum.to_le_bytes());
+        r.extend_from_slice(&header.n_messages.to_le_bytes());
+        r.extend_from_slice(&ns.to_le_bytes());
+        r.extend_from_slice(payload);
+        let crc = crc32c::crc32c(&r[8..]);
+        let len = (r.len() - 4) as u32;
+        r[..4].copy_from_slice(&len.to_le_bytes());
+        r[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        // One write a record
+        self.w.write_all(r)?;
+        self.offset += r.len() as u64;
+        self.next.insert(
+            header.channel,
+            header.seqnum.saturating_add(header.n_messages as u64),
+        );
+        Ok(())
+    }
+
+    fn sync_if_due(&mut self) -> io::Result<()> {
+        let fsync_due = self
+            .fsync_interval
+            .is_some_and(|interval| self.last_sync.elapsed() >= interval);
+        let checkpoint_due = self
+            .checkpoint
+            .as_ref()
+            .is_some_and(|c| c.last.elapsed() >= c.interval);
+        if fsync_due || checkpoint_due {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // Hand buffered records to the OS so readers of the file see them
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+
+    // Flush buffered records and fsync them, then checkpoint them if
+    // checkpointing
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.w.flush()?;
+        self.w.file().sync_data()?;
+        self.last_sync = Instant::now();
+        if let Some(c) = &self.checkpoint {
+            self.checkpoint().save(&c.path)?;
+        }
+        if let Some(c) = &mut self.checkpoint {
+            c.last = self.last_sync;
+        }
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// "<stem>-", which the names of a journal's segments start with
+fn segment_stem(path: &Path) -> String {
+    let stem = path.file_stem().map(|s| s.to_string_lossy());
+    format!("{}-", stem.unwrap_or_default())
+}
+
+// yyyymmdd in UTC
+fn date(ts: SystemTime) -> String {
+    let days = (ts.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400) as i64;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+// Delete or compress the segments of the journal at `path` last modified
+// longer than `segments.retention` ago. Returns the retired segments.
+pub fn retire_segments(path: &Path, segments: &Segments) -> io::Result<Vec<PathBuf>> {
+    let retention = match segments.retention {
+        Some(retention) => retention,
+        None => return Ok(Vec::new()),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let stem = segment_stem(path);
+    let mut retired = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let segment = entry.path();
+        // Compressed segments have an extension of their own
+        if !file_name(&segment).starts_with(&stem)
+            || segment.extension() != path.extension()
+            || !entry.file_type()?.is_file()
+        {
+            continue;
+        }
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age <= retention {
+            continue;
+        }
+        match &segments.compress {
+            Some(command) => compress(command, &segment)?,
+            None => fs::remove_file(&segment)?,
+        }
+        retired.push(segment);
+    }
+    retired.sort();
+    Ok(retired)
+}
+
+fn compress(command: &str, segment: &Path) -> io::Result<()> {
+    let mut args = command.split_whitespace();
+    let program = args
+        .next()
+        .ok_or_else(|| invalid("empty compress command".to_string()))?;
+    let status = Command::new(program).args(args).arg(segment).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {} compressing {}",
+            command,
+            status,
+            segment.display()
+        )));
+    }
+    Ok(())
+}
+
+impl Drop for JournalWriter {
+    fn drop(&mut self) {
+        let _ = self.w.finish();
+    }
+}
+
+// Iterates the records of a journal
+pub struct JournalReader<R: Read> {
+    r: R,
+    session: Session,
+    // Byte offset of the next record
+    offset: u64,
+}
+
+// A journal's file, decompressed if it is compressed
+pub enum JournalFile {
+    Plain(BufReader<File>),
+    Lz4(Lz4Reader<BufReader<File>>),
+}
+
+impl Read for JournalFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            JournalFile::Plain(r) => r.read(buf),
+            JournalFile::Lz4(r) => r.read(buf),
+        }
+    }
+}
+
+impl JournalReader<JournalFile> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        if compressed(&mut r)? {
+            Self::new(JournalFile::Lz4(Lz4Reader::new(r)?))
+        } else {
+            Self::new(JournalFile::Plain(r))
+        }
+    }
+
+    // Start reading at the record at `offset`, as returned by offset().
+    // Compressed journals are read up to it.
+    pub fn open_at<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Self> {
+        let mut reader = Self::open(path)?;
+        match &mut reader.r {
+            JournalFile::Plain(r) => {
+                r.seek(SeekFrom::Start(offset))?;
+            }
+            JournalFile::Lz4(r) => {
+                let skip = offset.saturating_sub(FILE_HEADER_LEN as u64);
+                if io::copy(&mut r.take(skip), &mut io::sink())? != skip {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+        reader.offset = offset;
+        Ok(reader)
+    }
+}
+
+impl<R: Read> JournalReader<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let mut header = [0_u8; FILE_HEADER_LEN];
+        r.read_exact(&mut header)?;
+        if header[0..8] != MAGIC {
+            return Err(invalid("not a journal".to_string()));
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version != VERSION {
+            return Err(invalid(format!("unsupported journal version {}", version)));
+        }
+        Ok(Self {
+            r,
+            session: header[10..].try_into().unwrap(),
+            offset: FILE_HEADER_LEN as u64,
+        })
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut len = [0_u8; 4];
+        match self.r.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len < RECORD_HEADER_LEN {
+            return Err(invalid(format!("short record at {}", self.offset)));
+        }
+        if len > RECORD_HEADER_LEN + MAX_PAYLOAD_LEN {
+            let e = format!("record of {} bytes at {} is too long", len, self.offset);
+            return Err(invalid(e));
+        }
+        let mut buf = vec![0_u8; len];
+        self.r.read_exact(&mut buf)?;
+        let offset = self.offset;
+        self.offset += 4 + len as u64;
+        let crc = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        if crc32c::crc32c(&buf[4..]) != crc {
+            return Err(invalid(format!("crc mismatch at {}", offset)));
+        }
+
+        let ns = u64::from_le_bytes(buf[18..26].try_into().unwrap());
+        Ok(Some(Record {
+            channel: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            seqnum: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            n_messages: u16::from_le_bytes(buf[16..18].try_into().unwrap()),
+            ts: UNIX_EPOCH + Duration::from_nanos(ns),
+            payload: buf[RECORD_HEADER_LEN..].to_vec(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for JournalReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+// Replays a journal into a feed queue as the blocks it recorded, sleeping to
+// reproduce the times they were journaled. For backtesting against exactly
+// what was sequenced.
+pub struct JournalSource<R: Read> {
+    reader: JournalReader<R>,
+    speed: Speed,
+}
+
+impl JournalSource<JournalFile> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(JournalReader::open(path)?))
+    }
+}
+
+impl<R: Read> JournalSource<R> {
+    pub fn new(reader: JournalReader<R>) -> Self {
+        Self {
+            reader,
+            speed: Speed::Multiplier(1.0),
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    // Next record as (journal timestamp, block), in the journal's session
+    pub fn next_block(&mut self) -> io::Result<Option<(SystemTime, Block<Payload>)>> {
+        let session = *self.reader.session();
+        match self.reader.next().transpose()? {
+            Some(record) => {
+                let ts = record.ts;
+                let mut block = record.into_block();
+                block.header.session = session;
+                Ok(Some((ts, block)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Send every record to `sender`, stopping at the first unreadable one
+    pub fn run(mut self, sender: &Sender<Block<Payload>>) -> io::Result<()> {
+        let mut start: Option<(SystemTime, Instant)> = None;
+        while let Some((ts, block)) = self.next_block()? {
+            if let Speed::Multiplier(multiplier) = self.speed {
+                let (first_ts, started) = *start.get_or_insert((ts, Instant::now()));
+                let offset = ts.duration_since(first_ts).unwrap_or_default();
+                let due = started + offset.div_f64(multiplier);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+            if sender.send(block).is_err() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+// Archives the sequenced stream to a Kafka topic. Records are keyed by
+// channel id (u32 BE), so a channel stays in order within its partition, and
+// carry the block's seqnum and n_messages as headers. Gaps are records with
+// no payload and a "gap_end" header. librdkafka batches records for up to
+// `linger` and retries failed deliveries until its message timeout, after
+// which they are counted as failed.
+use crate::sink::Sink;
+use crate::{Block, ChannelId, GapReason, Payload};
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{DeliveryResult, Header, OwnedHeaders};
+use rdkafka::producer::{BaseRecord, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::ClientContext;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// How long flush() waits for outstanding deliveries
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct KafkaConfig {
+    // host:port,...
+    pub brokers: String,
+    pub topic: String,
+    // How long records wait to be batched
+    pub linger: Duration,
+    // How long a record is retried before it is failed
+    pub message_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct KafkaMetrics {
+    // Records handed to the producer
+    pub sent: AtomicU64,
+    pub delivered: AtomicU64,
+    // Records the brokers never acknowledged
+    pub failed: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KafkaStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub failed: u64,
+    // Sent but not yet delivered or failed, how far the topic is behind
+    pub in_flight: u64,
+}
+
+impl KafkaMetrics {
+    pub fn stats(&self) -> KafkaStats {
+        let (sent, delivered, failed) = (
+            self.sent.load(Relaxed),
+            self.delivered.load(Relaxed),
+            self.failed.load(Relaxed),
+        );
+        KafkaStats {
+            sent,
+            delivered,
+            failed,
+            in_flight: sent.saturating_sub(delivered + failed),
+        }
+    }
+}
+
+impl fmt::Display for KafkaStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sent {} delivered {} failed {} in flight {}",
+            self.sent, self.delivered, self.failed, self.in_flight
+        )
+    }
+}
+
+struct Context {
+    metrics: Arc<KafkaMetrics>,
+}
+
+impl ClientContext for Context {}
+
+impl ProducerContext for Context {
+    type DeliveryOpaque = ();
+
+    // Called on the producer's polling thread
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        match result {
+            Ok(_) => self.metrics.delivered.fetch_add(1, Relaxed),
+            Err((e, _)) => {
+                warn!(error = %e, "kafka delivery failed");
+                self.metrics.failed.fetch_add(1, Relaxed)
+            }
+        };
+    }
+}
+
+pub struct KafkaSink {
+    producer: ThreadedProducer<Context>,
+    topic: String,
+    metrics: Arc<KafkaMetrics>,
+}
+
+fn kafka_error(e: KafkaError) -> io::Error {
+    io::Error::other(e)
+}
+
+impl KafkaSink {
+    // Connects lazily, so brokers being down only shows up as failed
+    // deliveries
+    pub fn create(config: &KafkaConfig) -> io::Result<Self> {
+        let metrics = Arc::<KafkaMetrics>::default();
+        let context = Context {
+            metrics: Arc::clone(&metrics),
+        };
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("linger.ms", config.linger.as_millis().to_string())
+            .set(
+                "message.timeout.ms",
+                config.message_timeout.as_millis().max(1).to_string(),
+            )
+            .create_with_context(context)
+            .map_err(kafka_error)?;
+        Ok(Self {
+            producer,
+            topic: config.topic.clone(),
+            metrics,
+        })
+    }
+
+    pub fn metrics(&self) -> Arc<KafkaMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn send(&self, channel: ChannelId, headers: OwnedHeaders, payload: &[u8]) -> io::Result<()> {
+        let key = channel.to_be_bytes();
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut record = BaseRecord::to(&self.topic)
+            .key(&key[..])
+            .payload(payload)
+            .headers(headers)
+            .timestamp(ts);
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => break,
+                // Wait for deliveries to make room rather than drop anything
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                    record = r;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err((e, _)) => return Err(kafka_error(e)),
+            }
+        }
+        self.metrics.sent.fetch_add(1, Relaxed);
+        Ok(())
+    }
+}
+
+fn header<'a>(key: &'a str, value: &'a [u8]) -> Header<'a, &'a [u8]> {
+    Header {
+        key,
+        value: Some(value),
+    }
+}
+
+impl Sink for KafkaSink {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        let headers = OwnedHeaders::new()
+            .insert(header("seqnum", &block.header.seqnum.to_be_bytes()))
+            .insert(header("n_messages", &block.header.n_messages.to_be_bytes()));
+        self.send(block.header.channel, headers, &block.payload)
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        _reason: GapReason,
+    ) -> io::Result<()> {
+        let headers = OwnedHeaders::new()
+            .insert(header("seqnum", &range.start.to_be_bytes()))
+            .insert(header("gap_end", &range.end.to_be_bytes()));
+        self.send(channel, headers, &[])
+    }
+
+    // Wait for outstanding deliveries
+    fn flush(&mut self) -> io::Result<()> {
+        let res = self.producer.flush(FLUSH_TIMEOUT).map_err(kafka_error);
+        info!(stats = %self.metrics.stats(), "kafka");
+        res
+    }
+}
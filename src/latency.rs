@@ -0,0 +1,234 @@
+use crate::metrics::FeedId;
+use crate::sink::Sink;
+use crate::{Block, ChannelId, GapReason, Payload, Session};
+use hdrhistogram::Histogram;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// Anything slower is recorded as this
+const MAX_LATENCY: Duration = Duration::from_secs(60);
+const PERCENTILES: [f64; 4] = [50.0, 99.0, 99.9, 100.0];
+
+fn histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY.as_nanos() as u64, 3).unwrap()
+}
+
+// Nanosecond histograms of how long blocks take from being received to
+// leaving the sequencer and to being handled by the sink. Blocks without a
+// receive timestamp are not counted.
+pub struct Latency {
+    sequenced: Mutex<Histogram<u64>>,
+    consumed: Mutex<Histogram<u64>>,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self {
+            sequenced: Mutex::new(histogram()),
+            consumed: Mutex::new(histogram()),
+        }
+    }
+}
+
+fn record(h: &Mutex<Histogram<u64>>, received: Instant) {
+    let ns = received.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+    h.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .saturating_record(ns);
+}
+
+fn write_percentiles(f: &mut fmt::Formatter, name: &str, h: &Histogram<u64>) -> fmt::Result {
+    write!(f, "{} n {}", name, h.len())?;
+    for p in PERCENTILES {
+        let d = Duration::from_nanos(h.value_at_percentile(p));
+        match p {
+            100.0 => write!(f, " max {:?}", d)?,
+            _ => write!(f, " p{} {:?}", p, d)?,
+        }
+    }
+    Ok(())
+}
+
+// HdrHistogram's percentile distribution text, which its plotter reads. Values
+// are in microseconds.
+fn write_distribution<W: Write>(w: &mut W, h: &Histogram<u64>) -> io::Result<()> {
+    writeln!(
+        w,
+        "{:>12} {:>14} {:>10} {:>14}",
+        "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+    )?;
+    let mut total = 0;
+    for v in h.iter_quantiles(1) {
+        total += v.count_since_last_iteration();
+        let q = v.quantile_iterated_to();
+        let inverse = if q < 1.0 {
+            1.0 / (1.0 - q)
+        } else {
+            f64::INFINITY
+        };
+        writeln!(
+            w,
+            "{:12.3} {:2.12} {:10} {:14.2}",
+            v.value_iterated_to() as f64 / 1000.0,
+            q,
+            total,
+            inverse
+        )?;
+    }
+    writeln!(
+        w,
+        "#[Mean = {:.3}, StdDeviation = {:.3}]",
+        h.mean() / 1000.0,
+        h.stdev() / 1000.0
+    )?;
+    writeln!(
+        w,
+        "#[Max = {:.3}, Total count = {}]",
+        h.max() as f64 / 1000.0,
+        h.len()
+    )
+}
+
+impl Latency {
+    pub fn record_sequenced(&self, received: Instant) {
+        record(&self.sequenced, received);
+    }
+
+    pub fn record_consumed(&self, received: Instant) {
+        record(&self.consumed, received);
+    }
+
+    pub fn sequenced(&self) -> Histogram<u64> {
+        self.sequenced
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn consumed(&self) -> Histogram<u64> {
+        self.consumed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Write both distributions to `path` for offline analysis
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        writeln!(w, "# Received to sequenced")?;
+        write_distribution(&mut w, &self.sequenced())?;
+        writeln!(w, "# Received to consumed")?;
+        write_distribution(&mut w, &self.consumed())?;
+        w.flush()
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_percentiles(f, "sequenced", &self.sequenced())?;
+        write!(f, " ")?;
+        write_percentiles(f, "consumed", &self.consumed())
+    }
+}
+
+// Records received to consumed latency of every block `inner` handles
+pub struct LatencySink<S> {
+    inner: S,
+    latency: Arc<Latency>,
+}
+
+impl<S> LatencySink<S> {
+    pub fn new(inner: S, latency: Arc<Latency>) -> Self {
+        Self { inner, latency }
+    }
+}
+
+impl<S: Sink> Sink for LatencySink<S> {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.inner.on_block(block)?;
+        if let Some(received) = block.received {
+            self.latency.record_consumed(received);
+        }
+        Ok(())
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        self.inner.on_blocks(blocks)?;
+        for received in blocks.iter().filter_map(|b| b.received) {
+            self.latency.record_consumed(received);
+        }
+        Ok(())
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        self.inner.on_gap(channel, range, reason)
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        self.inner.on_reset(channel, session, seqnum)
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.inner.on_resync(channel, seqnum, snapshot)
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.inner.on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.inner.on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.inner.on_end_of_session(channel, session, seqnum)
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.inner.on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.inner.on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.inner.on_idle()
+    }
+
+    fn published(&self) -> Option<u64> {
+        self.inner.published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.inner.resume_published(seqnum)
+    }
+}
@@ -0,0 +1,310 @@
+use crate::metrics::FeedId;
+use std::cmp;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+pub mod acks;
+pub mod admin;
+pub mod arbiter;
+pub mod arbitration;
+#[cfg(feature = "tokio")]
+pub mod async_arbiter;
+pub mod checkpoint;
+pub mod checksum;
+pub mod clock;
+pub mod config;
+pub mod conflate;
+pub mod diff;
+pub mod dump;
+pub mod error;
+pub mod events;
+pub mod fanout;
+pub mod gapfill;
+pub mod gaps;
+pub mod health;
+pub mod hugepages;
+pub mod ingress;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod latency;
+pub mod lz4;
+pub mod message_level;
+pub mod metrics;
+pub mod mitch;
+pub mod numa;
+mod output;
+pub mod pacing;
+pub mod pcap;
+pub mod peer;
+pub mod pitch;
+pub mod placeholders;
+pub mod pool;
+pub mod protocol;
+pub mod publisher;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod recorder;
+pub mod recovery;
+pub mod reorder;
+pub mod retransmit;
+pub mod sandbox;
+mod sequencer;
+pub mod session;
+pub mod shm;
+pub mod shutdown;
+pub mod sim;
+pub mod sink;
+pub mod soupbintcp;
+mod spool;
+pub mod stale;
+pub mod standby;
+pub mod systemd;
+pub mod tcp;
+pub mod timeout;
+#[cfg(any(feature = "quic", feature = "tls"))]
+pub mod tls;
+pub mod udp;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub mod uring;
+pub mod verify;
+pub mod websocket;
+
+#[cfg(all(feature = "af_xdp", target_os = "linux"))]
+pub mod xdp;
+
+pub use output::{OutputLimit, OutputPolicy};
+pub use pool::Payload;
+pub use sequencer::{BufferLimit, LateJoin, OverflowPolicy, Sequencer, RESET_JUMP, SYNC_WINDOW};
+pub use spool::Spill;
+
+pub const BUFFER_LEN: usize = 10_000;
+
+// Each channel has its own seqnum space
+pub type ChannelId = u32;
+
+pub const SESSION_LEN: usize = 10;
+// MoldUDP64 style session id. Seqnums restart when it changes.
+pub type Session = [u8; SESSION_LEN];
+// Session of feeds whose framing has none
+pub const NO_SESSION: Session = [0; SESSION_LEN];
+
+pub trait Sequenced {
+    fn seqnum(&self) -> u64;
+    fn n_messages(&self) -> u16;
+    fn channel(&self) -> ChannelId;
+    fn session(&self) -> Session;
+    // Bytes held while buffered, for reorder buffer byte limits
+    fn size(&self) -> usize {
+        0
+    }
+    // When the source received it, such as a kernel timestamp. Timeouts count
+    // from here instead of from when the sequencer got to it.
+    fn received(&self) -> Option<Instant> {
+        None
+    }
+    // Marks the end of its channel's session, with no messages and the
+    // seqnum that would have been next
+    fn end_of_session(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct BlockHeader {
+    pub channel: ChannelId,
+    pub session: Session,
+    pub seqnum: u64,
+    pub n_messages: u16,
+    // Such as MoldUDP64's end of session packet
+    pub end_of_session: bool,
+}
+
+impl Ord for BlockHeader {
+    fn cmp(&self, r: &Self) -> cmp::Ordering {
+        self.seqnum.cmp(&r.seqnum)
+    }
+}
+
+impl PartialOrd for BlockHeader {
+    fn partial_cmp(&self, r: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(r))
+    }
+}
+
+impl Sequenced for BlockHeader {
+    fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    fn n_messages(&self) -> u16 {
+        self.n_messages
+    }
+
+    fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
+    fn session(&self) -> Session {
+        self.session
+    }
+
+    fn end_of_session(&self) -> bool {
+        self.end_of_session
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GapReason {
+    // Nothing filled the gap within the sequencer's timeout
+    Timeout,
+    // Blocks still buffered from the previous session were flushed
+    SessionReset,
+    // The reorder buffer hit its limit and flushed its head
+    Overflow,
+    // Still missing when the sequencer was drained to shut down
+    Shutdown,
+    // Dropped from the output queue because the consumer fell behind
+    Lagged,
+}
+
+// What the sequencer emits on its output channel
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SequencedEvent<T> {
+    Block(T),
+    // Seqnums from..to (exclusive) of `channel` were skipped
+    Gap {
+        channel: ChannelId,
+        from: u64,
+        to: u64,
+        reason: GapReason,
+    },
+    // The channel started a new session or its seqnums wrapped. Following
+    // blocks of `channel` start at `seqnum`.
+    SessionReset {
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    },
+    // A gap was recovered from a snapshot of `channel` as of just before
+    // `seqnum` instead of being skipped. Following blocks start at `seqnum`.
+    Resynced {
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: Vec<T>,
+    },
+    // No packets or heartbeats arrived on `feed` for `silent`
+    FeedDown {
+        feed: FeedId,
+        silent: Duration,
+    },
+    // A feed that was down is receiving again
+    FeedUp {
+        feed: FeedId,
+    },
+    // The session of `channel` ended before `seqnum`. Its next block starts
+    // a new one.
+    EndOfSession {
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    },
+    // Feeds report different sessions of `channel`, each the session of its
+    // latest block. The channel's blocks are discarded until they agree.
+    SessionsDiverged {
+        channel: ChannelId,
+        sessions: Vec<(FeedId, Session)>,
+    },
+    // Feeds agree on `session` of `channel` again
+    SessionsAgreed {
+        channel: ChannelId,
+        session: Session,
+    },
+}
+
+impl<T> SequencedEvent<T> {
+    pub fn into_block(self) -> Option<T> {
+        match self {
+            SequencedEvent::Block(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+// A block of messages as received from a feed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block<P> {
+    pub header: BlockHeader,
+    pub payload: P,
+    pub received: Option<Instant>,
+}
+
+impl<P> Block<P> {
+    pub fn new(header: BlockHeader, payload: P) -> Self {
+        Self {
+            header,
+            payload,
+            received: None,
+        }
+    }
+}
+
+impl<P: AsRef<[u8]>> Sequenced for Block<P> {
+    fn seqnum(&self) -> u64 {
+        self.header.seqnum
+    }
+
+    fn n_messages(&self) -> u16 {
+        self.header.n_messages
+    }
+
+    fn channel(&self) -> ChannelId {
+        self.header.channel
+    }
+
+    fn session(&self) -> Session {
+        self.header.session
+    }
+
+    fn size(&self) -> usize {
+        self.payload.as_ref().len()
+    }
+
+    fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    fn end_of_session(&self) -> bool {
+        self.header.end_of_session
+    }
+}
+
+#[derive(Clone, Debug, Eq)]
+pub struct BlockMeta {
+    pub seqnum: u64,
+    pub ts: Instant,
+}
+
+impl PartialEq for BlockMeta {
+    fn eq(&self, r: &Self) -> bool {
+        self.seqnum == r.seqnum
+    }
+}
+
+impl Ord for BlockMeta {
+    fn cmp(&self, r: &Self) -> cmp::Ordering {
+        self.seqnum.cmp(&r.seqnum)
+    }
+}
+
+impl PartialOrd for BlockMeta {
+    fn partial_cmp(&self, r: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(r))
+    }
+}
+
+impl Hash for BlockMeta {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seqnum.hash(state);
+    }
+}
@@ -0,0 +1,517 @@
+// LZ4 frames, as written and read by the lz4 tool, for compressing journals
+// as they are written. Frames are written with independent 64 KiB blocks and
+// a content checksum. Linked blocks, block checksums, concatenated and
+// skippable frames are read too, dictionaries are not.
+use std::io::{self, Read, Write};
+
+pub const MAGIC: u32 = 0x184D_2204;
+// Of skippable frames, whose low 4 bits can be anything
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const BLOCK_LEN: usize = 64 * 1024;
+// Uncompressed bit of a block's size
+const UNCOMPRESSED: u32 = 1 << 31;
+// Matches reach at most this far back, so linked blocks keep as much
+const WINDOW: usize = 64 * 1024;
+
+const MIN_MATCH: usize = 4;
+// A block ends with at least this many literals...
+const LAST_LITERALS: usize = 5;
+// ...and its last match starts at least this far from its end
+const MF_LIMIT: usize = 12;
+const HASH_LOG: u32 = 12;
+
+// FLG bits
+const VERSION: u8 = 0b0100_0000;
+const INDEPENDENT: u8 = 1 << 5;
+const BLOCK_CHECKSUM: u8 = 1 << 4;
+const CONTENT_SIZE: u8 = 1 << 3;
+const CONTENT_CHECKSUM: u8 = 1 << 2;
+const DICT_ID: u8 = 1;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("lz4: {}", msg))
+}
+
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+// Streaming xxHash32 with seed 0, LZ4's checksum
+#[derive(Clone, Debug)]
+struct Xxh32 {
+    v: [u32; 4],
+    buf: [u8; 16],
+    buffered: usize,
+    len: u64,
+}
+
+fn round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME1)
+}
+
+fn lane(b: &[u8]) -> u32 {
+    u32::from_le_bytes(b[..4].try_into().unwrap())
+}
+
+impl Xxh32 {
+    fn new() -> Self {
+        Self {
+            v: [
+                PRIME1.wrapping_add(PRIME2),
+                PRIME2,
+                0,
+                0_u32.wrapping_sub(PRIME1),
+            ],
+            buf: [0; 16],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, v) in self.v.iter_mut().enumerate() {
+            *v = round(*v, lane(&stripe[i * 4..]));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buffered > 0 {
+            let n = data.len().min(16 - self.buffered);
+            self.buf[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered < 16 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buffered = 0;
+        }
+        let mut stripes = data.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn digest(&self) -> u32 {
+        let mut h = if self.len >= 16 {
+            let [v1, v2, v3, v4] = self.v;
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            PRIME5
+        };
+        h = h.wrapping_add(self.len as u32);
+        let mut rest = self.buf[..self.buffered].chunks_exact(4);
+        for word in &mut rest {
+            h = h
+                .wrapping_add(lane(word).wrapping_mul(PRIME3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME4);
+        }
+        for b in rest.remainder() {
+            h = h
+                .wrapping_add((*b as u32).wrapping_mul(PRIME5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME1);
+        }
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME3);
+        h ^ (h >> 16)
+    }
+}
+
+pub fn xxh32(data: &[u8]) -> u32 {
+    let mut h = Xxh32::new();
+    h.update(data);
+    h.digest()
+}
+
+// Second byte of the descriptor's hash
+fn header_checksum(descriptor: &[u8]) -> u8 {
+    (xxh32(descriptor) >> 8) as u8
+}
+
+fn push_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
+    let lit_token = literals.len().min(15) as u8;
+    let match_token = (match_len - MIN_MATCH).min(15) as u8;
+    out.push(lit_token << 4 | match_token);
+    if literals.len() >= 15 {
+        push_len(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    out.extend_from_slice(&(offset as u16).to_le_bytes());
+    if match_len - MIN_MATCH >= 15 {
+        push_len(out, match_len - MIN_MATCH - 15);
+    }
+}
+
+// Greedy single pass with a hash of the last position of each 4 bytes
+pub fn compress_block(input: &[u8], out: &mut Vec<u8>) {
+    let mut anchor = 0;
+    if input.len() > MF_LIMIT {
+        let mut table = vec![usize::MAX; 1 << HASH_LOG];
+        let match_end = input.len() - LAST_LITERALS;
+        let mut i = 0;
+        while i < input.len() - MF_LIMIT {
+            let seq = lane(&input[i..]);
+            let h = (seq.wrapping_mul(PRIME1) >> (32 - HASH_LOG)) as usize;
+            let candidate = std::mem::replace(&mut table[h], i);
+            if candidate == usize::MAX || i - candidate > u16::MAX as usize {
+                i += 1;
+                continue;
+            }
+            if lane(&input[candidate..]) != seq {
+                i += 1;
+                continue;
+            }
+            let mut len = MIN_MATCH;
+            while i + len < match_end && input[candidate + len] == input[i + len] {
+                len += 1;
+            }
+            push_sequence(out, &input[anchor..i], i - candidate, len);
+            i += len;
+            anchor = i;
+        }
+    }
+    let literals = &input[anchor..];
+    out.push((literals.len().min(15) as u8) << 4);
+    if literals.len() >= 15 {
+        push_len(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+}
+
+fn read_len(input: &[u8], pos: &mut usize, mut len: usize) -> io::Result<usize> {
+    loop {
+        let b = *input.get(*pos).ok_or_else(|| invalid("truncated block"))?;
+        *pos += 1;
+        len += b as usize;
+        if b != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+// Append a block's content to `out`, whose last WINDOW bytes matches can
+// refer back to. At most `max` bytes are appended.
+pub fn decompress_block(input: &[u8], out: &mut Vec<u8>, max: usize) -> io::Result<()> {
+    let limit = out.len() + max;
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos).ok_or_else(|| invalid("truncated block"))?;
+        pos += 1;
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals = read_len(input, &mut pos, literals)?;
+        }
+        let literals = input
+            .get(pos..pos + literals)
+            .ok_or_else(|| invalid("truncated literals"))?;
+        if out.len() + literals.len() > limit {
+            return Err(invalid("block too long"));
+        }
+        out.extend_from_slice(literals);
+        pos += literals.len();
+        // The last sequence has no match
+        if pos == input.len() {
+            return Ok(());
+        }
+        let offset = input
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| invalid("truncated offset"))?;
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(invalid("bad match offset"));
+        }
+        let mut len = (token & 15) as usize;
+        if len == 15 {
+            len = read_len(input, &mut pos, len)?;
+        }
+        len += MIN_MATCH;
+        if out.len() + len > limit {
+            return Err(invalid("block too long"));
+        }
+        // Matches can overlap what they copy
+        let start = out.len() - offset;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+// Compresses everything written to it into a frame. Flushing ends the block
+// being written, so readers get everything written before it, and finish()
+// ends the frame.
+pub struct Lz4Writer<W: Write> {
+    w: W,
+    block: Vec<u8>,
+    compressed: Vec<u8>,
+    checksum: Xxh32,
+    finished: bool,
+}
+
+impl<W: Write> Lz4Writer<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        let descriptor = [VERSION | INDEPENDENT | CONTENT_CHECKSUM, 4 << 4];
+        w.write_all(&MAGIC.to_le_bytes())?;
+        w.write_all(&descriptor)?;
+        w.write_all(&[header_checksum(&descriptor)])?;
+        Ok(Self {
+            w,
+            block: Vec::with_capacity(BLOCK_LEN),
+            compressed: Vec::new(),
+            checksum: Xxh32::new(),
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.checksum.update(&self.block);
+        self.compressed.clear();
+        compress_block(&self.block, &mut self.compressed);
+        if self.compressed.len() < self.block.len() {
+            self.w
+                .write_all(&(self.compressed.len() as u32).to_le_bytes())?;
+            self.w.write_all(&self.compressed)?;
+        } else {
+            let len = self.block.len() as u32 | UNCOMPRESSED;
+            self.w.write_all(&len.to_le_bytes())?;
+            self.w.write_all(&self.block)?;
+        }
+        self.block.clear();
+        Ok(())
+    }
+
+    // End the frame. Later writes fail.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        self.w.write_all(&0_u32.to_le_bytes())?;
+        self.w.write_all(&self.checksum.digest().to_le_bytes())?;
+        self.finished = true;
+        self.w.flush()
+    }
+}
+
+impl<W: Write> Write for Lz4Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(invalid("write after the frame was finished"));
+        }
+        let n = buf.len().min(BLOCK_LEN - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == BLOCK_LEN {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.w.flush()
+    }
+}
+
+struct Frame {
+    independent: bool,
+    block_checksum: bool,
+    content_checksum: Option<Xxh32>,
+    block_len: usize,
+}
+
+// Decompresses frames. A stream ending between blocks reads as the end of
+// it, like a journal still being written.
+pub struct Lz4Reader<R: Read> {
+    r: R,
+    frame: Option<Frame>,
+    // Decompressed, from the window linked blocks refer back to
+    buf: Vec<u8>,
+    // Of the next byte of `buf` to read
+    pos: usize,
+    compressed: Vec<u8>,
+}
+
+// Fill `buf` unless the stream ended before any of it
+fn read_or_end<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) if n == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+impl<R: Read> Lz4Reader<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let frame = Self::read_frame(&mut r)?.ok_or_else(|| invalid("no frame"))?;
+        Ok(Self {
+            r,
+            frame: Some(frame),
+            buf: Vec::new(),
+            pos: 0,
+            compressed: Vec::new(),
+        })
+    }
+
+    // The next frame's header, skipping skippable frames
+    fn read_frame(r: &mut R) -> io::Result<Option<Frame>> {
+        let mut magic = [0_u8; 4];
+        loop {
+            if !read_or_end(r, &mut magic)? {
+                return Ok(None);
+            }
+            let magic = u32::from_le_bytes(magic);
+            if magic == MAGIC {
+                break;
+            }
+            if magic & !0xF != SKIPPABLE_MAGIC {
+                return Err(invalid("not a frame"));
+            }
+            let mut len = [0_u8; 4];
+            r.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as u64;
+            if io::copy(&mut r.take(len), &mut io::sink())? != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let mut descriptor = [0_u8; 2 + 8 + 4 + 1];
+        r.read_exact(&mut descriptor[..2])?;
+        let (flg, bd) = (descriptor[0], descriptor[1]);
+        if flg & 0b1100_0000 != VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        if flg & DICT_ID != 0 {
+            return Err(invalid("dictionaries are unsupported"));
+        }
+        let block_len = match (bd >> 4) & 7 {
+            4 => 64 * 1024,
+            5 => 256 * 1024,
+            6 => 1024 * 1024,
+            7 => 4 * 1024 * 1024,
+            _ => return Err(invalid("bad block size")),
+        };
+        let mut len = 2;
+        if flg & CONTENT_SIZE != 0 {
+            len += 8;
+        }
+        r.read_exact(&mut descriptor[2..len + 1])?;
+        if header_checksum(&descriptor[..len]) != descriptor[len] {
+            return Err(invalid("header checksum mismatch"));
+        }
+        Ok(Some(Frame {
+            independent: flg & INDEPENDENT != 0,
+            block_checksum: flg & BLOCK_CHECKSUM != 0,
+            content_checksum: (flg & CONTENT_CHECKSUM != 0).then(Xxh32::new),
+            block_len,
+        }))
+    }
+
+    // Decompress the next block into `buf`, returning false at the end
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            let frame = match &mut self.frame {
+                Some(frame) => frame,
+                None => return Ok(false),
+            };
+            let mut len = [0_u8; 4];
+            if !read_or_end(&mut self.r, &mut len)? {
+                return Ok(false);
+            }
+            let len = u32::from_le_bytes(len);
+            if len == 0 {
+                if let Some(checksum) = &frame.content_checksum {
+                    let mut expected = [0_u8; 4];
+                    self.r.read_exact(&mut expected)?;
+                    if checksum.digest() != u32::from_le_bytes(expected) {
+                        return Err(invalid("content checksum mismatch"));
+                    }
+                }
+                // Frames can follow each other
+                self.frame = Self::read_frame(&mut self.r)?;
+                self.buf.clear();
+                self.pos = 0;
+                continue;
+            }
+
+            let stored = (len & !UNCOMPRESSED) as usize;
+            if stored > frame.block_len {
+                return Err(invalid("block too long"));
+            }
+            self.compressed.resize(stored, 0);
+            self.r.read_exact(&mut self.compressed)?;
+            if frame.block_checksum {
+                let mut expected = [0_u8; 4];
+                self.r.read_exact(&mut expected)?;
+                if xxh32(&self.compressed) != u32::from_le_bytes(expected) {
+                    return Err(invalid("block checksum mismatch"));
+                }
+            }
+            if frame.independent {
+                self.buf.clear();
+            } else if self.buf.len() > WINDOW {
+                self.buf.drain(..self.buf.len() - WINDOW);
+            }
+            let start = self.buf.len();
+            if len & UNCOMPRESSED != 0 {
+                self.buf.extend_from_slice(&self.compressed);
+            } else {
+                decompress_block(&self.compressed, &mut self.buf, frame.block_len)?;
+            }
+            if let Some(checksum) = &mut frame.content_checksum {
+                checksum.update(&self.buf[start..]);
+            }
+            self.pos = start;
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read> Read for Lz4Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
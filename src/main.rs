@@ -1,92 +1,2378 @@
-use crossbeam_channel::unbounded;
-use rand::Rng;
-use std::cmp;
-use std::collections::HashMap;
-use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use crossbeam_channel::{Receiver, Sender};
+use sequencer::acks::{AckServer, Acks};
+use sequencer::admin::{AdminAddr, AdminServer, Command, Request};
+use sequencer::arbiter::{Arbiter, FeedSet};
+use sequencer::arbitration::Arbitration;
+use sequencer::checkpoint::Checkpoint;
+use sequencer::checksum::Checksum;
+use sequencer::config::{Change, ConfigFile, ConfigWatcher, FeedEntry, SinkConfig};
+use sequencer::conflate::Conflation;
+use sequencer::diff;
+use sequencer::dump::{self, Decode};
+use sequencer::error::{self, supervise, FailurePolicy, SequencerError};
+use sequencer::fanout::{ConsumerLag, FanOut, SlowConsumerPolicy, Subscriber};
+use sequencer::gapfill::{GapFiller, TcpGapFiller};
+use sequencer::gaps;
+use sequencer::health::{Health, HealthConfig, HealthFile, HealthServer};
+use sequencer::hugepages::HugePages;
+use sequencer::ingress::{Ingress, IngressOrder, IngressSocket};
+use sequencer::journal::{
+    self, Compression, JournalReader, JournalSource, JournalWriter, Rotation, Segments,
+};
+#[cfg(feature = "kafka")]
+use sequencer::kafka::{KafkaConfig, KafkaSink};
+use sequencer::latency::{Latency, LatencySink};
+use sequencer::message_level::{self, MessageSink};
+use sequencer::metrics::FeedId;
+use sequencer::mitch::{MitchLogin, MitchRecoverySource, MitchReplayFiller};
+use sequencer::numa::{self, NumaNode, Topology};
+use sequencer::pacing::Pacing;
+use sequencer::pcap::{PcapSource, Speed};
+use sequencer::peer::{PeerCache, PeerGapFiller, PeerServer, PEER_BLOCKS};
+use sequencer::pitch::{PitchGapFiller, PitchLogin, PitchSpinSource};
+use sequencer::placeholders::PlaceholderSink;
+use sequencer::pool::BufferPool;
+use sequencer::protocol::custom::Framing;
+use sequencer::protocol::fast::Templates;
+use sequencer::protocol::Protocol;
+use sequencer::publisher::{MulticastPublisher, PublishPayload};
+#[cfg(feature = "quic")]
+use sequencer::quic::{self, QuicFeed, QuicFeedConfig};
+use sequencer::recorder::{RawPacket, RawRecorder};
+use sequencer::recovery::TcpSnapshotSource;
+use sequencer::retransmit::{RetransmitConfig, RetransmitServer};
+use sequencer::sandbox::{self, User};
+use sequencer::session::{ExitAtSessionEnd, TimeOfDay};
+use sequencer::shutdown::Shutdown;
+use sequencer::sim::{self, SimConfig};
+use sequencer::sink::{self, Batching, Sink, TextSink};
+use sequencer::soupbintcp::{SoupBinTcpConfig, SoupBinTcpSource};
+use sequencer::stale::{JournalCheck, StalePolicy};
+use sequencer::standby::{Heartbeat, Standby, StandbySink};
+use sequencer::systemd::{self, Watchdog};
+#[cfg(feature = "tls")]
+use sequencer::tcp::TlsConfig;
+use sequencer::tcp::{LengthPrefix, TcpFeed, TcpFeedConfig};
+use sequencer::timeout::{ChannelTimeout, GapTimeout};
+use sequencer::udp::{self, FeedConfig, Timestamping, UdpFeed, MAX_BATCH};
+use sequencer::verify;
+#[cfg(all(feature = "af_xdp", target_os = "linux"))]
+use sequencer::xdp::{AfXdpSource, XdpConfig, XdpMode};
+use sequencer::{
+    Block, BlockHeader, BufferLimit, ChannelId, LateJoin, OutputLimit, OutputPolicy,
+    OverflowPolicy, Payload, SequencedEvent, Sequencer, SYNC_WINDOW,
+};
+use std::io::{self, IsTerminal, Write};
+use std::iter;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
 
-const BUFFER_LEN: usize = 10_000;
+type Packet = Block<Payload>;
+type SetLogLevel = Box<dyn Fn(&str) -> Result<(), String> + Send>;
+// Threads return how many blocks a sink consumed
+type SinkThread = JoinHandle<Result<u64, SequencerError>>;
+type FeedThread = JoinHandle<Result<(), SequencerError>>;
+type NamedSink = (&'static str, Box<dyn Sink + Send>);
+// Sink threads, the lag of each if fanned out, and what subscribes more
+type Consumers = (
+    Vec<SinkThread>,
+    Vec<ConsumerLag>,
+    Option<Subscriber<Packet>>,
+);
+// A sink added while running and how far behind it is
+type Consumer = (SinkThread, ConsumerLag);
 
-#[derive(Clone, Hash, Eq, PartialEq, Ord)]
-struct BlockHeader {
-    seqnum: u64,
-    n_messages: u16,
+// How often --config is reread
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+// Between rewrites of --health-file
+const HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn generate_blocks(n_blocks: usize) -> Vec<Packet> {
+    let mut res = Vec::new();
+
+    // let mut rng = rand::thread_rng();
+    let mut seqnum = 0;
+    for _i in 0..n_blocks {
+        // let n_messages = rng.gen_range(1..50);
+        let n_messages = 1; // for easy debugging
+        let header = BlockHeader {
+            channel: 0,
+            seqnum,
+            n_messages,
+            ..Default::default()
+        };
+        res.push(Block::new(header, seqnum.to_be_bytes().to_vec().into()));
+        seqnum += n_messages as u64
+    }
+    res
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LogFormat {
+    Text,
+    // One JSON object per line for log aggregation
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}", s)),
+        }
+    }
+}
+
+// Offline tools, run instead of sequencing
+#[derive(Subcommand, Clone, Debug)]
+enum Tool {
+    /// Print a journal's records and, with --decode, their messages
+    Dump {
+        journal: PathBuf,
+        /// raw (payload bytes in hex), itch50, mdp3 or opra
+        #[arg(long, default_value = "raw")]
+        decode: Decode,
+    },
+    /// Compare two journals block by block, matched by seqnum, and print the blocks only one has or that differ, failing if any do
+    Diff { a: PathBuf, b: PathBuf },
+    /// Check a journal's seqnums, crcs and timestamps and print a gap report, failing if it has problems
+    Verify { journal: PathBuf },
+}
+
+// Numbers --ingress inputs when any are given, sequences UDP multicast feeds
+// when any --udp groups are, otherwise or with --sim --feeds simulated feeds
+#[derive(Parser, Clone, Debug)]
+#[command(version, about)]
+struct Config {
+    #[command(subcommand)]
+    tool: Option<Tool>,
+    /// TOML file of settings, reread every second to apply timeouts, the log level, added sinks and changed feeds. Flags given win over it.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    // Sinks from --config
+    #[arg(skip)]
+    file_sinks: Vec<SinkConfig>,
+    // Whether --udp feeds came from --config
+    #[arg(skip)]
+    file_feeds: bool,
+    // Loaded from --fast-templates
+    #[arg(skip)]
+    templates: Option<Arc<Templates>>,
+    // From --config, for custom protocols
+    #[arg(skip)]
+    framing: Option<Framing>,
+    /// Gap timeout
+    #[arg(long, default_value_t = 10)]
+    timeout_ms: u64,
+    /// When a gap times out: fixed (after --timeout-ms), packets:N (after N newer blocks), adaptive:M (M times the average inter-arrival time) or tuned:M (M times the longest a gap has taken to fill), the latter three at most --timeout-ms
+    #[arg(long, default_value = "fixed")]
+    gap_timeout: GapTimeout,
+    /// CHANNEL=POLICY, a channel's --gap-timeout in place of the default. Can be repeated.
+    #[arg(long)]
+    channel_gap_timeout: Vec<ChannelTimeout>,
+    /// Microseconds between timeout checks, 0 to check on every packet
+    #[arg(long, default_value_t = 1000)]
+    timeout_tick_us: u64,
+    /// Events queued for the sink before --output-policy applies, 0 for unbounded
+    #[arg(long, default_value_t = 65_536)]
+    output_queue: usize,
+    /// block (lossless, stops reading feeds) or drop-oldest (replaces the oldest queued blocks with gaps)
+    #[arg(long, default_value = "block")]
+    output_policy: OutputPolicy,
+    /// Most blocks a sink can be behind the others before --slow-consumer applies, 0 for no limit. Under --output-policy block it should be below --output-queue.
+    #[arg(long, default_value_t = 0)]
+    max_consumer_lag: u64,
+    /// disconnect (stop the sink getting anything) or gap-notify (send it gaps in place of blocks until it catches up)
+    #[arg(long, default_value = "disconnect")]
+    slow_consumer: SlowConsumerPolicy,
+    /// Sink, such as websocket, that gets only the latest queued block of each --conflation key while its --output-queue is full, instead of waiting or losing the oldest
+    #[arg(long)]
+    conflate: Vec<String>,
+    /// What --conflate sinks keep the latest block of: channel, or symbol (ITCH 5.0 stock locate) within a channel
+    #[arg(long, default_value = "channel")]
+    conflation: Conflation,
+    /// Sink that gets a block per message in place of each block, numbered by message seqnum (block seqnum + index) and holding just that message
+    #[arg(long)]
+    message_level: Vec<String>,
+    /// Sink that gets placeholders for the seqnums of each gap after it, empty messages of no type, so its message counters stay aligned with the feed's
+    #[arg(long)]
+    placeholders: Vec<String>,
+    /// When a feed fails: retry (rejoin after a backoff), degrade (carry on with the other feeds) or shutdown (drain and stop)
+    #[arg(long, default_value = "degrade")]
+    feed_failure: FailurePolicy,
+    /// When a sink fails: retry (carry on after a backoff, losing the events that failed), degrade (carry on with the other sinks) or shutdown (drain and stop)
+    #[arg(long, default_value = "shutdown")]
+    sink_failure: FailurePolicy,
+    /// Most consecutive blocks each sink is handed at once, 1 not to batch
+    #[arg(long, default_value_t = 1)]
+    sink_batch: usize,
+    /// Longest a block waits for others to batch with under --sink-batch, 0 for only those already queued
+    #[arg(long, default_value_t = 0)]
+    sink_batch_us: u64,
+    /// Spool blocks beyond --output-queue to a temporary file in this directory instead of applying --output-policy
+    #[arg(long)]
+    spill_dir: Option<PathBuf>,
+    /// Reorder buffer capacity per channel
+    #[arg(long, default_value_t = sequencer::BUFFER_LEN)]
+    reorder_buffer: usize,
+    /// Most blocks buffered per channel, 0 for unbounded
+    #[arg(long, default_value_t = 0)]
+    max_buffered: usize,
+    /// Most payload bytes buffered per channel, 0 for unbounded
+    #[arg(long, default_value_t = 0)]
+    max_buffered_bytes: usize,
+    /// flush-with-gap, drop-newest or block when the reorder buffer is full
+    #[arg(long, default_value = "flush-with-gap")]
+    overflow: OverflowPolicy,
+    /// Number of simulated feeds
+    #[arg(long, default_value_t = 2)]
+    feeds: usize,
+    /// Sequence simulated feeds even if --udp, --replay or --replay-journal is given
+    #[arg(long)]
+    sim: bool,
+    /// Seed of the simulated feeds' impairments, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Fraction of each simulated feed's blocks lost
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+    /// Most positions a simulated block arrives late by
+    #[arg(long, default_value_t = 4)]
+    reorder_window: usize,
+    /// Fraction of each simulated feed's blocks duplicated
+    #[arg(long, default_value_t = 0.0)]
+    duplication: f64,
+    /// Most microseconds between a simulated feed's packets
+    #[arg(long, default_value_t = 50)]
+    jitter_us: u64,
+    /// Journal sequenced blocks are written to
+    #[arg(long, default_value = "messages.journal")]
+    sink: PathBuf,
+    /// Write the sink as text instead of a binary journal
+    #[arg(long)]
+    text: bool,
+    /// Session id written to the journal header
+    #[arg(long, default_value = "")]
+    session: String,
+    /// Milliseconds between journal fsyncs, 0 to leave syncing to the OS
+    #[arg(long, default_value_t = journal::FSYNC_MS)]
+    fsync_ms: u64,
+    /// Compress the journal as it is written: none or lz4. Readers decompress it as they read.
+    #[arg(long, default_value = "none")]
+    compression: Compression,
+    /// Roll the journal into a new segment once it reaches this many bytes, 0 to not roll by size
+    #[arg(long, default_value_t = 0)]
+    segment_bytes: u64,
+    /// Roll the journal into a new segment after this many seconds, 0 to not roll by time
+    #[arg(long, default_value_t = 0)]
+    segment_secs: u64,
+    /// Retire journal segments older than this many seconds, 0 to keep them
+    #[arg(long, default_value_t = 0)]
+    retention_secs: u64,
+    /// Compress retired segments with this command, such as "zstd -q --rm", instead of deleting them
+    #[arg(long)]
+    retention_compress: Option<String>,
+    /// File the journal checkpoints each channel's next seqnum to, resuming
+    /// from it on restart
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Most milliseconds between checkpoints
+    #[arg(long, default_value_t = 1000)]
+    checkpoint_ms: u64,
+    /// Multicast group:port to re-publish the sequenced stream to as MoldUDP64
+    #[arg(long)]
+    publish: Option<SocketAddrV4>,
+    /// Multicast TTL of the published stream
+    #[arg(long, default_value_t = 1)]
+    publish_ttl: u32,
+    /// Most packets per millisecond of the published stream, 0 for unlimited
+    #[arg(long, default_value_t = 0)]
+    publish_packets_per_ms: u64,
+    /// Most bytes per millisecond of the published stream, 0 for unlimited
+    #[arg(long, default_value_t = 0)]
+    publish_bytes_per_ms: u64,
+    /// Microseconds of the --publish-*-per-ms rates the published stream may send back to back, such as after a recovered gap releases a backlog
+    #[arg(long, default_value_t = 100)]
+    publish_burst_us: u64,
+    /// Journal of the published stream, with its seqnums, for retransmission
+    #[arg(long)]
+    publish_journal: Option<PathBuf>,
+    /// Address --publish-ack-consumer consumers log in to over TCP to acknowledge what they received and ask for what they missed
+    #[arg(long)]
+    publish_ack: Option<SocketAddr>,
+    /// Consumer whose acknowledgement every published packet is kept until, by login name
+    #[arg(long)]
+    publish_ack_consumer: Vec<String>,
+    /// Bytes of unacknowledged packets kept in memory, the rest go to --publish-ack-dir if given
+    #[arg(long, default_value_t = 64 << 20)]
+    publish_ack_memory: usize,
+    /// Directory to keep unacknowledged packets in past --publish-ack-memory
+    #[arg(long)]
+    publish_ack_dir: Option<PathBuf>,
+    /// Also relay the sequenced stream to this host:port, one datagram per block or gap
+    #[arg(long)]
+    relay: Option<SocketAddr>,
+    /// Also archive the sequenced stream to Kafka through these brokers (host:port,...)
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    kafka_brokers: Option<String>,
+    /// Topic of --kafka-brokers
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value = "sequencer")]
+    kafka_topic: String,
+    /// How long Kafka records wait to be batched
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value_t = 5)]
+    kafka_linger_ms: u64,
+    /// How long failing Kafka deliveries are retried
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value_t = 30_000)]
+    kafka_timeout_ms: u64,
+    /// Also write the sequenced stream to a shared-memory ring at this path, such as /dev/shm/sequencer
+    #[arg(long)]
+    shm: Option<PathBuf>,
+    /// Slots in the --shm ring, rounded up to a power of two
+    #[arg(long, default_value_t = sequencer::shm::SHM_SLOTS)]
+    shm_slots: usize,
+    /// Most payload bytes of a block in the --shm ring
+    #[arg(long, default_value_t = sequencer::shm::SHM_SLOT_LEN)]
+    shm_slot_len: usize,
+    /// Serve a sampled view of the sequenced stream to dashboards over WebSocket on this address
+    #[arg(long)]
+    websocket: Option<SocketAddr>,
+    /// Least time between blocks of a channel sent to --websocket clients
+    #[arg(long, default_value_t = sequencer::websocket::SAMPLE_MS)]
+    websocket_sample_ms: u64,
+    /// Heartbeat how far each sink got to a standby listening on this host:port
+    #[arg(long)]
+    heartbeat: Option<SocketAddr>,
+    /// Milliseconds between heartbeats
+    #[arg(long, default_value_t = sequencer::standby::HEARTBEAT_MS)]
+    heartbeat_ms: u64,
+    /// Stand by for a primary heartbeating to this host:port, holding back every sink until its heartbeats stop and then carrying on from where the primary's left off
+    #[arg(long)]
+    standby: Option<SocketAddr>,
+    /// Milliseconds without heartbeats before taking over from the primary
+    #[arg(long, default_value_t = sequencer::standby::STANDBY_TIMEOUT_MS)]
+    standby_timeout_ms: u64,
+    /// Serve admin commands on this host:port or Unix socket path
+    #[arg(long)]
+    admin: Option<AdminAddr>,
+    /// Serve GET /live and GET /ready on this host:port over HTTP. Ready means every feed is receiving, no more than --reorder-buffer blocks are buffered and the consumer is less than --output-queue events behind.
+    #[arg(long)]
+    health: Option<SocketAddr>,
+    /// Rewrite this file every second while ready and remove it while not, for supervisors checking its age
+    #[arg(long)]
+    health_file: Option<PathBuf>,
+    /// Once sockets are bound and the journal is open, switch to this user and its groups for good. Start as root to bind privileged ports and force --rcvbuf past net.core.rmem_max.
+    #[arg(long)]
+    user: Option<String>,
+    /// Once set up, refuse syscalls a running sequencer never makes, such as exec, ptrace and mount (linux on x86_64 or aarch64)
+    #[arg(long)]
+    seccomp: bool,
+    /// End every channel's session daily at this UTC time (HH:MM), for feeds
+    /// that don't mark the end of their sessions
+    #[arg(long)]
+    session_end: Option<TimeOfDay>,
+    /// Stop once every session has ended instead of waiting for the next
+    #[arg(long)]
+    exit_at_session_end: bool,
+    /// File every gap's seqnums, detection time, resolution and duration are written to at each --session-end (the gaps since the last) and on exit, JSON if it ends in .json and otherwise CSV
+    #[arg(long)]
+    gap_report: Option<PathBuf>,
+    /// Address to serve retransmission requests on, from --publish-journal if set or else --sink
+    #[arg(long)]
+    retransmit: Option<SocketAddr>,
+    /// Bytes per second each retransmission client is limited to, 0 for unlimited
+    #[arg(long, default_value_t = 0)]
+    retransmit_rate: u64,
+    /// Retransmission clients served at once
+    #[arg(long, default_value_t = 16)]
+    retransmit_max_clients: usize,
+    /// Multicast group:port to join, once per feed
+    #[arg(long)]
+    udp: Vec<SocketAddrV4>,
+    /// Local interface to join multicast groups on
+    #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED)]
+    interface: Ipv4Addr,
+    /// Local interface each --udp feed joins on, in order. Feeds without one use --interface.
+    #[arg(long)]
+    udp_interface: Vec<Ipv4Addr>,
+    /// Sender each --udp feed only receives from (source-specific join), in order. 0.0.0.0 or none for any.
+    #[arg(long)]
+    udp_source: Vec<Ipv4Addr>,
+    /// Set SO_REUSEADDR and SO_REUSEPORT on --udp sockets so other processes can share them
+    #[arg(long)]
+    reuse_port: bool,
+    /// SO_RCVBUF of --udp sockets in bytes, 0 for the kernel's default
+    #[arg(long, default_value_t = 0)]
+    rcvbuf: usize,
+    /// Channel each --udp feed is sequenced in, in order. Feeds without one use channel 0.
+    #[arg(long)]
+    udp_channel: Vec<ChannelId>,
+    /// Protocol of each --udp feed, in order, so feeds of a channel can frame the same messages differently. Feeds without one use --protocol.
+    #[arg(long)]
+    udp_protocol: Vec<Protocol>,
+    /// QUIC server to receive a feed's datagrams from, sequenced like a --udp feed's
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic: Vec<SocketAddr>,
+    /// Channel each --quic feed is sequenced in, in order. Feeds without one use channel 0.
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic_channel: Vec<ChannelId>,
+    /// Name --quic servers' certificates have to be for, their address if not given
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic_server_name: Option<String>,
+    /// PEM file of the certificates of the authorities trusted to sign --quic servers' certificates
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic_ca: Option<PathBuf>,
+    /// UDP address taking client inputs without seqnums, a datagram each, to number, journal and publish in place of sequencing feeds
+    #[arg(long)]
+    ingress: Vec<SocketAddr>,
+    /// arrival (as received), round-robin (an input from each --ingress address in turn) or priority (highest first), ordering inputs received within --ingress-window-us of each other. Ties go to the earlier address, then the earlier input.
+    #[arg(long, default_value = "arrival")]
+    ingress_order: IngressOrder,
+    /// Priority of each --ingress address's inputs, in order. Addresses without one have priority 0.
+    #[arg(long)]
+    ingress_priority: Vec<u8>,
+    /// Offset of the byte of an input that is its priority, in place of its address's
+    #[arg(long)]
+    ingress_priority_field: Option<usize>,
+    /// How long --ingress-order round-robin and priority hold inputs to order them together
+    #[arg(long, default_value_t = 100)]
+    ingress_window_us: u64,
+    /// Channel --ingress inputs are numbered in
+    #[arg(long, default_value_t = 0)]
+    ingress_channel: ChannelId,
+    /// raw, moldudp64, mdp3, fast, pitch, mitch, opra or custom (framed as the --config file's [framing] says)
+    #[arg(long, default_value = "raw")]
+    protocol: Protocol,
+    /// FAST templates to sequence fast feeds by their messages' MsgSeqNum instead of a packet prefix
+    #[arg(long)]
+    fast_templates: Option<PathBuf>,
+    /// Retransmission server to request timed out gaps from, the gap request proxy with --protocol pitch or replay channel with --protocol mitch
+    #[arg(long)]
+    gap_fill: Option<SocketAddr>,
+    /// Extra attempts after a failed gap fill request
+    #[arg(long, default_value_t = 2)]
+    gap_fill_retries: u32,
+    /// Timeout of each gap fill request
+    #[arg(long, default_value_t = 100)]
+    gap_fill_timeout_ms: u64,
+    /// Peer instance to ask for timed out gaps before --gap-fill, once per peer
+    #[arg(long)]
+    peer: Vec<SocketAddr>,
+    /// Timeout of each request to a --peer
+    #[arg(long, default_value_t = 20)]
+    peer_timeout_ms: u64,
+    /// Address to serve peers the blocks sequenced recently on
+    #[arg(long)]
+    peer_listen: Option<SocketAddr>,
+    /// Blocks of each channel kept for peers
+    #[arg(long, default_value_t = PEER_BLOCKS)]
+    peer_blocks: usize,
+    /// Snapshot server to resync channels from when a gap can't be filled, the spin server with --protocol pitch or recovery channel with --protocol mitch
+    #[arg(long)]
+    snapshot: Option<SocketAddr>,
+    /// Timeout of each snapshot request
+    #[arg(long, default_value_t = 1000)]
+    snapshot_timeout_ms: u64,
+    /// Gap response multicast line the --gap-fill gap request proxy replays on
+    #[arg(long)]
+    pitch_gap_feed: Option<SocketAddrV4>,
+    /// Gap request proxy and spin server login username
+    #[arg(long, default_value = "")]
+    pitch_username: String,
+    /// Gap request proxy and spin server login password
+    #[arg(long, default_value = "")]
+    pitch_password: String,
+    /// Gap request proxy and spin server login session sub ID
+    #[arg(long, default_value = "")]
+    pitch_session_sub_id: String,
+    /// Replay and recovery channel login username
+    #[arg(long, default_value = "")]
+    mitch_username: String,
+    /// Replay and recovery channel login password
+    #[arg(long, default_value = "")]
+    mitch_password: String,
+    /// pcapng file every received datagram is recorded to
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Capture to replay, datagrams are matched to feeds by their --udp address
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// Journal to replay as one feed, at its journaled timing scaled by --speed
+    #[arg(long)]
+    replay_journal: Option<PathBuf>,
+    /// Replay speed multiplier, 0 for as fast as possible
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Seqnum each channel starts at, defaults to the protocol's
+    #[arg(long, visible_alias = "start-seqnum")]
+    first_seqnum: Option<u64>,
+    /// Start channels where the feeds are when joining midway: off, first
+    /// (the first block's seqnum) or max (the highest feed's first seqnum
+    /// within --late-join-window-ms)
+    #[arg(long, default_value = "off")]
+    late_join: LateJoin,
+    /// How long --late-join max waits for every feed's first block
+    #[arg(long, default_value_t = SYNC_WINDOW.as_millis() as u64)]
+    late_join_window_ms: u64,
+    /// SoupBinTCP server to stream from as an extra feed
+    #[arg(long)]
+    soupbintcp: Option<SocketAddr>,
+    /// SoupBinTCP login username
+    #[arg(long, default_value = "")]
+    soup_username: String,
+    /// SoupBinTCP login password
+    #[arg(long, default_value = "")]
+    soup_password: String,
+    /// SoupBinTCP session to join, empty for the current one
+    #[arg(long, default_value = "")]
+    soup_session: String,
+    /// First SoupBinTCP seqnum to request, 0 for only new messages
+    #[arg(long, default_value_t = 1)]
+    soup_seqnum: u64,
+    /// TCP server to stream length-prefixed packets of --protocol from as an extra feed
+    #[arg(long)]
+    tcp: Vec<SocketAddr>,
+    /// Channel each --tcp feed is sequenced in, in order. Feeds without one use channel 0.
+    #[arg(long)]
+    tcp_channel: Vec<ChannelId>,
+    /// Length prefix of --tcp packets: u16 or u32, big endian
+    #[arg(long, default_value = "u16")]
+    tcp_prefix: LengthPrefix,
+    /// PEM file of the certificates of the authorities trusted to sign --tcp servers' certificates, connecting with TLS if given
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tcp_ca: Option<PathBuf>,
+    /// Name --tcp servers' certificates have to be for, their address if not given
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tcp_server_name: Option<String>,
+    /// Backwards seqnum jump treated as a feed restart, 0 to only reset on a new session id
+    #[arg(long, default_value_t = sequencer::RESET_JUMP)]
+    reset_jump: u64,
+    /// first-wins, prefer-primary (feed 0, failing over when it is down) or line-quality
+    #[arg(long, default_value = "first-wins")]
+    arbitration: Arbitration,
+    /// Milliseconds without packets or heartbeats before a feed is reported down, 0 to disable
+    #[arg(long, default_value_t = 1000)]
+    feed_timeout_ms: u64,
+    /// Milliseconds feeds may report different sessions of a channel before it stops being sequenced until they agree, 0 to not compare them
+    #[arg(long, default_value_t = 1000)]
+    session_check_ms: u64,
+    /// Trailer every --udp, --tcp and --quic packet ends with: off or crc32c (u32 BE over the rest of the packet). Packets it doesn't match are dropped and counted as corrupt.
+    #[arg(long, default_value = "off")]
+    checksum: Checksum,
+    /// Blocks behind their channel's expected seqnum: count (drop and count them) or verify (also compare each with its record in --sink, logging those that differ)
+    #[arg(long, default_value = "count")]
+    stale: StalePolicy,
+    /// Spin on sockets and feed queues instead of sleeping
+    #[arg(long)]
+    busy_poll: bool,
+    /// SO_BUSY_POLL microseconds of --busy-poll sockets, 0 to leave unset (needs CAP_NET_ADMIN)
+    #[arg(long, default_value_t = 0)]
+    busy_poll_us: u32,
+    /// Datagrams each --udp feed receives per recvmmsg call, at most 64. 1 receives one per recv.
+    #[arg(long, default_value_t = 1)]
+    recv_batch: usize,
+    /// Receive --udp feeds with io_uring multishot recvmsg, falling back to recvmmsg where the kernel or build lacks it
+    #[arg(long)]
+    io_uring: bool,
+    /// Receive buffers shared by --udp feeds kept for reuse
+    #[arg(long, default_value_t = 4096)]
+    pool_buffers: usize,
+    /// Bytes of each receive buffer. Longer datagrams are dropped or truncated.
+    #[arg(long, default_value_t = 9216)]
+    pool_buffer_len: usize,
+    /// off, 2m or 1g huge pages for the receive buffers and --shm ring, falling back to ordinary pages (with transparent huge pages where the kernel has them) if not enough are reserved
+    #[arg(long, default_value = "off")]
+    huge_pages: HugePages,
+    /// NIC to capture --udp feeds from with AF_XDP instead of sockets
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    #[arg(long)]
+    af_xdp: Option<String>,
+    /// NIC queue the AF_XDP socket is bound to
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    #[arg(long, default_value_t = 0)]
+    xdp_queue: u32,
+    /// auto, driver or skb (generic XDP on any device)
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    #[arg(long, default_value = "auto")]
+    xdp_mode: XdpMode,
+    /// UMEM frames AF_XDP receives into
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    #[arg(long, default_value_t = sequencer::xdp::XDP_FRAMES)]
+    xdp_frames: u32,
+    /// Receive timestamps timeouts count from: off, software (kernel) or hardware (NIC)
+    #[arg(long, default_value = "off")]
+    timestamping: Timestamping,
+    /// Cores to pin the arbiter and then each --udp feed thread to, in order
+    #[arg(long, value_delimiter = ',')]
+    pin_cores: Vec<usize>,
+    /// NUMA node to run threads on and allocate buffers and queues from, or auto for the node of the NIC feeds are received on. --pin-cores still pins within it.
+    #[arg(long)]
+    numa_node: Option<NumaNode>,
+    /// Track received to sequenced and consumed latency percentiles
+    #[arg(long)]
+    latency: bool,
+    /// File the latency distributions are written to on exit, implies --latency
+    #[arg(long)]
+    latency_dump: Option<PathBuf>,
+    /// Log level or tracing filter directives such as sequencer=debug
+    #[arg(long, default_value = "info")]
+    log_level: String,
+    /// text or json
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+    /// Seconds between stats lines, 0 to disable
+    #[arg(long, default_value_t = 1)]
+    stats_interval_s: u64,
+}
+
+impl Config {
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    fn batching(&self) -> Batching {
+        Batching {
+            max_blocks: self.sink_batch,
+            max_latency: Duration::from_micros(self.sink_batch_us),
+        }
+    }
+
+    // Arbiter polls can be up to twice the gap timeout apart
+    fn health_config(&self) -> HealthConfig {
+        let default = HealthConfig::default();
+        HealthConfig {
+            max_buffered: self.reorder_buffer,
+            max_output_depth: match self.output_queue {
+                0 => default.max_output_depth,
+                n => n,
+            },
+            stall: default.stall.max(self.timeout() * 3),
+        }
+    }
+
+    fn output_limit(&self) -> OutputLimit {
+        OutputLimit {
+            capacity: (self.output_queue > 0).then_some(self.output_queue),
+            policy: self.output_policy,
+        }
+    }
+
+    // Each sink is a consumer of its own
+    fn sinks(
+        &self,
+        rotation: &Rotation,
+        checkpoint: Option<&Checkpoint>,
+    ) -> Result<Vec<NamedSink>, SequencerError> {
+        let journal = self
+            .journal(rotation, checkpoint)
+            .map_err(SequencerError::sink("journal"))?;
+        let mut sinks = vec![("journal", journal)];
+        for sink in self.sink_configs() {
+            sinks.push((
+                sink.name(),
+                sink.open().map_err(SequencerError::sink(sink.name()))?,
+            ));
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(brokers) = &self.kafka_brokers {
+            let kafka = KafkaConfig {
+                brokers: brokers.clone(),
+                topic: self.kafka_topic.clone(),
+                linger: Duration::from_millis(self.kafka_linger_ms),
+                message_timeout: Duration::from_millis(self.kafka_timeout_ms),
+            };
+            let kafka = KafkaSink::create(&kafka).map_err(SequencerError::sink("kafka"))?;
+            sinks.push(("kafka", Box::new(kafka)));
+        }
+        let addr = match self.publish {
+            Some(addr) => addr,
+            None => return Ok(sinks),
+        };
+        let session = journal::session(&self.session);
+        let failed = |e| SequencerError::Sink {
+            sink: "publisher",
+            source: e,
+        };
+        let mut publisher =
+            MulticastPublisher::bind(addr, self.interface, session).map_err(failed)?;
+        publisher.set_ttl(self.publish_ttl).map_err(failed)?;
+        if let Some(path) = &self.publish_journal {
+            let journal =
+                JournalWriter::create(path, &session, self.fsync_interval()).map_err(failed)?;
+            publisher.set_journal(journal);
+        }
+        if self.protocol == Protocol::MoldUdp64 {
+            publisher.set_payload(PublishPayload::MoldMessages);
+        }
+        publisher.set_pacing(Pacing {
+            packets_per_ms: (self.publish_packets_per_ms > 0)
+                .then_some(self.publish_packets_per_ms),
+            bytes_per_ms: (self.publish_bytes_per_ms > 0).then_some(self.publish_bytes_per_ms),
+            burst: Duration::from_micros(self.publish_burst_us),
+        });
+        if let Some(addr) = self.publish_ack {
+            let dir = self.publish_ack_dir.as_deref();
+            let acks = Acks::new(&self.publish_ack_consumer, self.publish_ack_memory, dir)
+                .map_err(failed)?;
+            let server = AckServer::bind(addr, acks.clone()).map_err(failed)?;
+            if let Ok(addr) = server.local_addr() {
+                info!(%addr, consumers = ?self.publish_ack_consumer, "taking acks");
+            }
+            server.spawn().map_err(failed)?;
+            publisher.set_acks(acks);
+        }
+        sinks.push(("publisher", Box::new(publisher)));
+        Ok(sinks)
+    }
+
+    // The sinks given by flags and then by --config
+    fn sink_configs(&self) -> Vec<SinkConfig> {
+        let mut sinks = Vec::new();
+        if let Some(addr) = self.relay {
+            sinks.push(SinkConfig::Relay { addr });
+        }
+        if let Some(path) = &self.shm {
+            sinks.push(SinkConfig::Shm {
+                path: path.clone(),
+                slots: self.shm_slots,
+                slot_len: self.shm_slot_len,
+                huge_pages: self.huge_pages,
+            });
+        }
+        if let Some(addr) = self.websocket {
+            sinks.push(SinkConfig::Websocket {
+                addr,
+                sample_ms: self.websocket_sample_ms,
+            });
+        }
+        sinks.extend(self.file_sinks.iter().cloned());
+        sinks
+    }
+
+    // Parse flags, then take settings not given by them from --config
+    fn load() -> Result<(Self, Option<ConfigFile>), SequencerError> {
+        let matches = Config::command().get_matches();
+        let mut config = Config::from_arg_matches(&matches)
+            .map_err(|e| SequencerError::Config(e.to_string()))?;
+        if let Some(path) = &config.fast_templates {
+            let templates = Templates::load(path).map_err(SequencerError::io("fast templates"))?;
+            config.templates = Some(Arc::new(templates));
+        }
+        let file = match &config.config {
+            Some(path) => ConfigFile::load(path).map_err(SequencerError::io("config"))?,
+            None => return Ok((config.with_decoders()?, None)),
+        };
+        let unset = |id| matches.value_source(id) != Some(ValueSource::CommandLine);
+        // A framing makes the protocol custom unless another is given
+        config.framing = file.framing;
+        if file.framing.is_some() && unset("protocol") {
+            config.protocol = Protocol::Custom(None);
+        }
+        if let Some(ms) = file.timeout_ms.filter(|_| unset("timeout_ms")) {
+            config.timeout_ms = ms;
+        }
+        if let Some(ms) = file.feed_timeout_ms.filter(|_| unset("feed_timeout_ms")) {
+            config.feed_timeout_ms = ms;
+        }
+        if let Some(level) = file.log_level.as_ref().filter(|_| unset("log_level")) {
+            config.log_level = level.clone();
+        }
+        if let Some(cores) = file.pin_cores.as_ref().filter(|_| unset("pin_cores")) {
+            config.pin_cores = cores.clone();
+        }
+        if let Some(path) = file.journal.as_ref().filter(|_| unset("sink")) {
+            config.sink = path.clone();
+        }
+        let feeds = file.feed_entries();
+        if !feeds.is_empty() && unset("udp") {
+            config.udp = feeds.iter().map(|f| f.group).collect();
+            config.udp_interface = feeds
+                .iter()
+                .map(|f| f.interface.unwrap_or(config.interface))
+                .collect();
+            config.udp_channel = feeds.iter().map(|f| f.channel).collect();
+            config.udp_protocol = feeds
+                .iter()
+                .map(|f| {
+                    f.protocol
+                        .clone()
+                        .unwrap_or_else(|| config.protocol.clone())
+                })
+                .collect();
+            config.udp_source = feeds
+                .iter()
+                .map(|f| f.source.unwrap_or(Ipv4Addr::UNSPECIFIED))
+                .collect();
+            config.file_feeds = true;
+        }
+        config.file_sinks = file.sinks.clone();
+        Ok((config.with_decoders()?, Some(file)))
+    }
+
+    // Fill in the protocols' templates and framing, which are given apart
+    fn with_decoders(mut self) -> Result<Self, SequencerError> {
+        self.protocol = self.decoder(&self.protocol);
+        self.udp_protocol = self.udp_protocol.iter().map(|p| self.decoder(p)).collect();
+        let mut protocols = iter::once(&self.protocol).chain(&self.udp_protocol);
+        if protocols.any(|p| *p == Protocol::Custom(None)) {
+            let e = "--protocol custom needs a [framing] in --config".to_string();
+            return Err(SequencerError::Config(e));
+        }
+        let mut protocols = iter::once(&self.protocol).chain(&self.udp_protocol);
+        if self.templates.is_some() && !protocols.any(|p| matches!(p, Protocol::Fast(_))) {
+            let e = "--fast-templates needs --protocol fast or a fast --udp-protocol".to_string();
+            return Err(SequencerError::Config(e));
+        }
+        Ok(self)
+    }
+
+    // `protocol` with what it needs to frame datagrams
+    fn decoder(&self, protocol: &Protocol) -> Protocol {
+        match protocol {
+            Protocol::Fast(None) => Protocol::Fast(self.templates.clone()),
+            Protocol::Custom(None) => Protocol::Custom(self.framing),
+            protocol => protocol.clone(),
+        }
+    }
+
+    fn journal(
+        &self,
+        rotation: &Rotation,
+        checkpoint: Option<&Checkpoint>,
+    ) -> io::Result<Box<dyn Sink + Send>> {
+        if self.text {
+            return Ok(Box::new(TextSink::create(&self.sink)?));
+        }
+        let mut journal = match checkpoint {
+            Some(checkpoint) => {
+                JournalWriter::resume(&self.sink, checkpoint, self.fsync_interval())?
+            }
+            None => JournalWriter::create_compressed(
+                &self.sink,
+                &journal::session(&self.session),
+                self.fsync_interval(),
+                self.compression,
+            )?,
+        };
+        journal.set_rotation(rotation.clone());
+        if let Some(segments) = self.segments() {
+            journal.set_segments(segments);
+        }
+        if let Some(path) = &self.checkpoint {
+            journal.set_checkpoint(path, Duration::from_millis(self.checkpoint_ms));
+        }
+        Ok(Box::new(journal))
+    }
+
+    // The checkpoint to resume from, if checkpointing and there is one
+    fn load_checkpoint(&self) -> Result<Option<Checkpoint>, SequencerError> {
+        let path = match &self.checkpoint {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if self.text {
+            return Err(SequencerError::Config(
+                "--checkpoint needs a binary journal".to_string(),
+            ));
+        }
+        if self.compression != Compression::None {
+            return Err(SequencerError::Config(
+                "--checkpoint needs an uncompressed journal".to_string(),
+            ));
+        }
+        Checkpoint::load(path).map_err(SequencerError::io("checkpoint"))
+    }
+
+    // None unless rolling or retiring segments
+    fn segments(&self) -> Option<Segments> {
+        let secs = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        let segments = Segments {
+            max_bytes: (self.segment_bytes > 0).then_some(self.segment_bytes),
+            max_age: secs(self.segment_secs),
+            retention: secs(self.retention_secs),
+            compress: self.retention_compress.clone(),
+        };
+        (segments.rolls() || segments.retention.is_some()).then_some(segments)
+    }
+
+    fn replay_speed(&self) -> Speed {
+        if self.speed > 0.0 {
+            Speed::Multiplier(self.speed)
+        } else {
+            Speed::AsFastAsPossible
+        }
+    }
+
+    fn fsync_interval(&self) -> Option<Duration> {
+        match self.fsync_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    fn retransmit(&self) -> Option<(SocketAddr, &Path, RetransmitConfig)> {
+        let addr = self.retransmit?;
+        let path = self.publish_journal.as_deref().unwrap_or(&self.sink);
+        let config = RetransmitConfig {
+            max_clients: self.retransmit_max_clients,
+            bytes_per_sec: (self.retransmit_rate > 0).then_some(self.retransmit_rate),
+            ..Default::default()
+        };
+        Some((addr, path, config))
+    }
+
+    fn buffer_limit(&self) -> BufferLimit {
+        let nonzero = |n: usize| (n > 0).then_some(n);
+        BufferLimit {
+            max_entries: nonzero(self.max_buffered),
+            max_bytes: nonzero(self.max_buffered_bytes),
+            policy: self.overflow,
+        }
+    }
+
+    // Returns what changes the log level or filter directives after
+    fn init_logging(&self) -> Result<SetLogLevel, SequencerError> {
+        let filter = EnvFilter::try_new(&self.log_level)
+            .map_err(|e| SequencerError::Config(format!("bad log level: {}", e)))?;
+        let logger = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_ansi(io::stdout().is_terminal());
+        let parse = |level: &str| EnvFilter::try_new(level).map_err(|e| e.to_string());
+        match self.log_format {
+            LogFormat::Text => {
+                let logger = logger.with_filter_reloading();
+                let handle = logger.reload_handle();
+                logger.init();
+                Ok(Box::new(move |level| {
+                    handle.reload(parse(level)?).map_err(|e| e.to_string())
+                }))
+            }
+            LogFormat::Json => {
+                let logger = logger.json().with_filter_reloading();
+                let handle = logger.reload_handle();
+                logger.init();
+                Ok(Box::new(move |level| {
+                    handle.reload(parse(level)?).map_err(|e| e.to_string())
+                }))
+            }
+        }
+    }
+
+    fn stats_interval(&self) -> Option<Duration> {
+        match self.stats_interval_s {
+            0 => None,
+            s => Some(Duration::from_secs(s)),
+        }
+    }
+
+    fn tcp_feeds(&self) -> io::Result<Vec<TcpFeedConfig>> {
+        #[cfg(feature = "tls")]
+        let roots = match &self.tcp_ca {
+            Some(path) => Some(sequencer::tls::load_certs(path)?),
+            None => None,
+        };
+        let feeds = self.tcp.iter().enumerate().map(|(i, addr)| TcpFeedConfig {
+            addr: *addr,
+            prefix: self.tcp_prefix,
+            protocol: self.protocol.clone(),
+            channel: self.tcp_channel.get(i).copied().unwrap_or(0),
+            #[cfg(feature = "tls")]
+            tls: roots.as_ref().map(|roots| TlsConfig {
+                server_name: match &self.tcp_server_name {
+                    Some(name) => name.clone(),
+                    None => addr.ip().to_string(),
+                },
+                roots: roots.clone(),
+            }),
+        });
+        Ok(feeds.collect())
+    }
+
+    fn soupbintcp(&self) -> Option<SoupBinTcpConfig> {
+        self.soupbintcp.map(|addr| SoupBinTcpConfig {
+            addr,
+            username: self.soup_username.clone(),
+            password: self.soup_password.clone(),
+            session: journal::session(&self.soup_session),
+            seqnum: self.soup_seqnum,
+            channel: 0,
+        })
+    }
+
+    fn mitch_login(&self) -> MitchLogin {
+        MitchLogin {
+            username: self.mitch_username.clone(),
+            password: self.mitch_password.clone(),
+        }
+    }
+
+    fn pitch_login(&self) -> PitchLogin {
+        PitchLogin {
+            session_sub_id: self.pitch_session_sub_id.clone(),
+            username: self.pitch_username.clone(),
+            password: self.pitch_password.clone(),
+        }
+    }
+
+    // The --gap-fill server's client
+    fn gap_filler(
+        &self,
+        addr: SocketAddr,
+    ) -> io::Result<Box<dyn GapFiller<Block<Payload>> + Send>> {
+        let timeout = Duration::from_millis(self.gap_fill_timeout_ms);
+        Ok(match self.protocol {
+            Protocol::Pitch => Box::new(self.pitch_gap_filler(addr, timeout)?),
+            Protocol::Mitch => Box::new(MitchReplayFiller::new(addr, self.mitch_login(), timeout)),
+            _ => Box::new(TcpGapFiller::new(addr, self.gap_fill_retries, timeout)),
+        })
+    }
+
+    // A PITCH gap request proxy's replays come on their own line rather than
+    // in the response
+    fn pitch_gap_filler(&self, addr: SocketAddr, timeout: Duration) -> io::Result<PitchGapFiller> {
+        let line = self.pitch_gap_feed.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--protocol pitch needs --pitch-gap-feed",
+            )
+        })?;
+        let replays = udp::bind_multicast(&FeedConfig {
+            interface: self.interface,
+            group: *line.ip(),
+            port: line.port(),
+            protocol: Protocol::Pitch,
+            channel: 0,
+            source: None,
+            reuse_port: true,
+            recv_buffer: None,
+        })?;
+        Ok(PitchGapFiller::new(
+            addr,
+            self.pitch_login(),
+            replays,
+            timeout,
+        ))
+    }
+
+    fn sim(&self) -> SimConfig {
+        SimConfig {
+            seed: self.seed.unwrap_or_else(rand::random),
+            loss: self.loss,
+            reorder_window: self.reorder_window,
+            duplication: self.duplication,
+            interval: Duration::ZERO,
+            jitter: Duration::from_micros(self.jitter_us),
+        }
+    }
+
+    // --udp feeds with their per feed flags
+    fn feed_entries(&self) -> Vec<FeedEntry> {
+        self.udp
+            .iter()
+            .enumerate()
+            .map(|(i, addr)| FeedEntry {
+                group: *addr,
+                interface: self.udp_interface.get(i).copied(),
+                channel: self.udp_channel.get(i).copied().unwrap_or(0),
+                source: self
+                    .udp_source
+                    .get(i)
+                    .copied()
+                    .filter(|s| !s.is_unspecified()),
+                protocol: self.udp_protocol.get(i).cloned(),
+            })
+            .collect()
+    }
+
+    fn udp_feeds(&self) -> Vec<FeedConfig> {
+        self.feed_entries()
+            .iter()
+            .map(|entry| self.udp_feed(entry))
+            .collect()
+    }
+
+    fn udp_feed(&self, entry: &FeedEntry) -> FeedConfig {
+        FeedConfig {
+            interface: entry.interface.unwrap_or(self.interface),
+            group: *entry.group.ip(),
+            port: entry.group.port(),
+            protocol: match &entry.protocol {
+                Some(protocol) => self.decoder(protocol),
+                None => self.protocol.clone(),
+            },
+            channel: entry.channel,
+            source: entry.source,
+            reuse_port: self.reuse_port,
+            recv_buffer: (self.rcvbuf > 0).then_some(self.rcvbuf),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let started = Config::load().and_then(|(config, file)| {
+        let set_log_level = config.init_logging()?;
+        Ok((config, file, set_log_level))
+    });
+    let (config, file, set_log_level) = match started {
+        Ok(started) => started,
+        // Nothing to log to yet
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(tool) = &config.tool {
+        return match run_tool(tool) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    match run(config, file, set_log_level) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!(error = %e, "stopped");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Returns whether the tool found everything in order
+fn run_tool(tool: &Tool) -> Result<bool, SequencerError> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let (name, done) = match tool {
+        Tool::Dump { journal, decode } => (
+            "dump",
+            JournalReader::open(journal)
+                .and_then(|reader| dump::dump(reader, *decode, &mut out))
+                .map(|_| true),
+        ),
+        Tool::Diff { a, b } => (
+            "diff",
+            JournalReader::open(a).and_then(|a| {
+                let report = diff::diff(a, JournalReader::open(b)?)?;
+                writeln!(out, "{}", report)?;
+                Ok(report.same())
+            }),
+        ),
+        Tool::Verify { journal } => (
+            "verify",
+            JournalReader::open(journal).and_then(|reader| {
+                let report = verify::verify(reader);
+                writeln!(out, "{}", report)?;
+                Ok(report.ok())
+            }),
+        ),
+    };
+    match done.and_then(|ok| out.flush().map(|()| ok)) {
+        // Piped into head
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(true),
+        done => done.map_err(SequencerError::io(name)),
+    }
+}
+
+fn run(
+    config: Config,
+    file: Option<ConfigFile>,
+    set_log_level: SetLogLevel,
+) -> Result<(), SequencerError> {
+    if config.replay_journal.as_ref() == Some(&config.sink) {
+        return Err(SequencerError::Config(
+            "--replay-journal would be overwritten by --sink".to_string(),
+        ));
+    }
+    let acked = config.publish.is_some() && !config.publish_ack_consumer.is_empty();
+    if config.publish_ack.is_some() && !acked {
+        return Err(SequencerError::Config(
+            "--publish-ack needs --publish and a --publish-ack-consumer".to_string(),
+        ));
+    }
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    if config.af_xdp.is_some() && config.checksum != Checksum::Off {
+        return Err(SequencerError::Config(
+            "--checksum isn't checked under --af-xdp".to_string(),
+        ));
+    }
+    if config.conflate.iter().any(|c| c == "journal") {
+        return Err(SequencerError::Config(
+            "--conflate journal would leave blocks out of the journal".to_string(),
+        ));
+    }
+    if config.message_level.iter().any(|c| c == "journal") {
+        return Err(SequencerError::Config(
+            "--message-level journal would leave the journal unable to retransmit blocks"
+                .to_string(),
+        ));
+    }
+    if config.placeholders.iter().any(|c| c == "journal") {
+        return Err(SequencerError::Config(
+            "--placeholders journal would journal blocks that were never received".to_string(),
+        ));
+    }
+    if !config.message_level.is_empty() || !config.placeholders.is_empty() {
+        message_framing(&config)?;
+    }
+    if config.stale == StalePolicy::Verify && config.text {
+        return Err(SequencerError::Config(
+            "--stale verify needs a journal --sink, not --text".to_string(),
+        ));
+    }
+    if config.seccomp && config.retention_compress.is_some() {
+        return Err(SequencerError::Config(
+            "--seccomp refuses to run --retention-compress".to_string(),
+        ));
+    }
+    // Looked up before anything is bound, so a typo fails fast
+    let user = config
+        .user
+        .as_deref()
+        .map(User::lookup)
+        .transpose()
+        .map_err(SequencerError::io("user"))?;
+    // Before anything the hot path touches is allocated or spawned
+    if let Some(numa) = config.numa_node {
+        place_on_node(&config, numa);
+    }
+    let timeout = config.timeout();
+    let (mut sequencer, message_receiver) = match &config.spill_dir {
+        Some(dir) => Sequencer::<Packet>::with_spill(
+            timeout,
+            config.reorder_buffer,
+            config.output_queue.max(1),
+            dir,
+        )
+        .map_err(SequencerError::io("spill"))?,
+        None => Sequencer::with_output(timeout, config.reorder_buffer, config.output_limit()),
+    };
+    if config.reset_jump == 0 {
+        sequencer.set_reset_jump(None);
+    } else {
+        sequencer.set_reset_jump(Some(config.reset_jump));
+    }
+    sequencer.set_buffer_limit(config.buffer_limit());
+    sequencer.set_late_join(match config.late_join {
+        LateJoin::Max(_) => LateJoin::Max(Duration::from_millis(config.late_join_window_ms)),
+        late_join => late_join,
+    });
+    sequencer.set_arbitration(config.arbitration.policy());
+    sequencer.set_gap_timeout(config.gap_timeout);
+    for c in &config.channel_gap_timeout {
+        sequencer.set_channel_gap_timeout(c.channel, c.timeout);
+    }
+    sequencer.set_first_seqnum(
+        config
+            .first_seqnum
+            .unwrap_or_else(|| config.protocol.first_seqnum()),
+    );
+    let checkpoint = config.load_checkpoint()?;
+    if let Some(checkpoint) = &checkpoint {
+        info!(
+            channels = checkpoint.channels.len(),
+            journal_offset = checkpoint.journal_offset,
+            "resuming from checkpoint"
+        );
+        for (channel, seqnum) in &checkpoint.channels {
+            sequencer.set_next_seqnum(*channel, *seqnum);
+        }
+    }
+    if config.timeout_tick_us > 0 {
+        sequencer.set_timeout_tick(Some(Duration::from_micros(config.timeout_tick_us)));
+    }
+    if config.session_check_ms > 0 {
+        sequencer.set_session_check(Some(Duration::from_millis(config.session_check_ms)));
+    }
+    if config.stale == StalePolicy::Verify {
+        sequencer.set_stale_check(Box::new(JournalCheck::new(&config.sink)));
+    }
+    if config.feed_timeout_ms > 0 {
+        sequencer.set_feed_timeout(Some(Duration::from_millis(config.feed_timeout_ms)));
+    }
+    let gap_filler = config
+        .gap_fill
+        .map(|addr| config.gap_filler(addr))
+        .transpose()
+        .map_err(SequencerError::io("gap fill"))?;
+    if !config.peer.is_empty() {
+        let mut peers =
+            PeerGapFiller::new(&config.peer, Duration::from_millis(config.peer_timeout_ms));
+        if let Some(gap_filler) = gap_filler {
+            peers.set_fallback(gap_filler);
+        }
+        sequencer.set_gap_filler(Box::new(peers));
+    } else if let Some(gap_filler) = gap_filler {
+        sequencer.set_gap_filler(gap_filler);
+    }
+    if let Some(addr) = config.snapshot {
+        let timeout = Duration::from_millis(config.snapshot_timeout_ms);
+        match config.protocol {
+            Protocol::Pitch => {
+                let source = PitchSpinSource::new(addr, config.pitch_login(), timeout);
+                sequencer.set_snapshot_source(Box::new(source));
+            }
+            Protocol::Mitch => {
+                let source = MitchRecoverySource::new(addr, config.mitch_login(), timeout);
+                sequencer.set_snapshot_source(Box::new(source));
+            }
+            _ => sequencer.set_snapshot_source(Box::new(TcpSnapshotSource::new(addr, timeout))),
+        }
+    }
+    let latency = (config.latency || config.latency_dump.is_some()).then(Arc::<Latency>::default);
+    if let Some(latency) = &latency {
+        sequencer.set_latency(Arc::clone(latency));
+    }
+    if config.gap_report.is_some() {
+        sequencer.record_gaps();
+    }
+    let shutdown = Shutdown::default();
+    shutdown
+        .on_signals()
+        .map_err(SequencerError::io("signals"))?;
+    // Sockets systemd bound, taken by what is configured to bind them
+    let mut inherited = systemd::Inherited::take();
+    if !inherited.is_empty() {
+        info!(?inherited, "sockets from systemd");
+    }
+    let rotation = Rotation::default();
+    let health = Health::new(sequencer.metrics(), config.health_config());
+    let mut arbiter = Arbiter::new(sequencer, timeout);
+    arbiter.set_shutdown(shutdown.clone());
+    arbiter.set_busy_poll(config.busy_poll);
+    arbiter.set_stats_interval(config.stats_interval());
+
+    if let Some(addr) = &config.admin {
+        let mut server =
+            AdminServer::bind(addr, arbiter.control()).map_err(SequencerError::io("admin"))?;
+        if !config.text {
+            server.set_rotation(rotation.clone());
+        }
+        info!(?addr, "serving admin");
+        server.spawn().map_err(SequencerError::io("admin"))?;
+    }
+
+    if let Some(addr) = config.health {
+        let server =
+            HealthServer::bind(addr, health.clone()).map_err(SequencerError::io("health"))?;
+        info!(%addr, "serving health");
+        server.spawn().map_err(SequencerError::io("health"))?;
+    }
+    if let Some(path) = &config.health_file {
+        HealthFile::new(path, health.clone(), HEALTH_INTERVAL)
+            .spawn(shutdown.clone())
+            .map_err(SequencerError::io("health file"))?;
+    }
+
+    if let Some(at) = config.session_end {
+        let control = arbiter.control();
+        let gap_report = config.gap_report.clone();
+        thread::Builder::new()
+            .name("session end".to_string())
+            .spawn(move || loop {
+                thread::sleep(at.until_next(SystemTime::now()));
+                info!("session end time");
+                send_command(&control, Command::EndSession);
+                if let Some(path) = &gap_report {
+                    send_command(&control, Command::SessionGapReport(path.clone()));
+                }
+            })
+            .map_err(SequencerError::io("session end"))?;
+    }
+
+    // Start consumer threads
+    let mut sinks = config.sinks(&rotation, checkpoint.as_ref())?;
+    if config.heartbeat.is_some() || config.standby.is_some() {
+        sinks = high_availability(sinks, &config).map_err(SequencerError::io("standby"))?;
+    }
+    // Left out of high_availability() so a standby serves peers while holding
+    // back everything else
+    if let Some(addr) = config.peer_listen {
+        let cache = PeerCache::new(config.peer_blocks);
+        let server = PeerServer::bind(addr, cache.clone()).map_err(SequencerError::io("peers"))?;
+        if let Ok(addr) = server.local_addr() {
+            info!(%addr, "serving peers");
+        }
+        server.spawn().map_err(SequencerError::io("peers"))?;
+        sinks.push(("peers", Box::new(cache)));
+    }
+    if config.exit_at_session_end {
+        let exit = ExitAtSessionEnd::new(shutdown.clone());
+        sinks.push(("session end", Box::new(exit)));
+    }
+    if !config.message_level.is_empty() {
+        let framing = message_framing(&config)?;
+        for name in &config.message_level {
+            if !sinks.iter().any(|(n, _)| n == name) {
+                warn!(sink = name, "no such sink to hand messages");
+            }
+        }
+        sinks = sinks
+            .into_iter()
+            .map(
+                |(name, sink)| match config.message_level.iter().any(|m| m == name) {
+                    true => (name, Box::new(MessageSink::new(sink, framing)) as Box<_>),
+                    false => (name, sink),
+                },
+            )
+            .collect();
+    }
+    // Outside --message-level, so placeholders are split into messages too
+    if !config.placeholders.is_empty() {
+        let framing = message_framing(&config)?;
+        for name in &config.placeholders {
+            if !sinks.iter().any(|(n, _)| n == name) {
+                warn!(sink = name, "no such sink to hand placeholders");
+            }
+        }
+        sinks = sinks
+            .into_iter()
+            .map(
+                |(name, sink)| match config.placeholders.iter().any(|p| p == name) {
+                    true => (
+                        name,
+                        Box::new(PlaceholderSink::new(sink, framing)) as Box<_>,
+                    ),
+                    false => (name, sink),
+                },
+            )
+            .collect();
+    }
+    if let Some(latency) = &latency {
+        let (name, sink) = sinks.remove(0);
+        sinks.insert(
+            0,
+            (name, Box::new(LatencySink::new(sink, Arc::clone(latency)))),
+        );
+    }
+    // Sinks added by --config are subscribed to the fan-out while running
+    let (consumers, lags, subscriber) =
+        spawn_consumers(sinks, message_receiver, &config, &shutdown, file.is_some())
+            .map_err(SequencerError::io("consumers"))?;
+    // Each sink's watermark and lag next to the sequencer's stats line
+    if let Some(interval) = config.stats_interval().filter(|_| !lags.is_empty()) {
+        let (lags, shutdown) = (lags.clone(), shutdown.clone());
+        thread::Builder::new()
+            .name("consumer stats".to_string())
+            .spawn(move || {
+                while !shutdown.requested() {
+                    thread::sleep(interval);
+                    for lag in &lags {
+                        info!(consumer = %lag.stats(), "lag");
+                    }
+                }
+            })
+            .map_err(SequencerError::io("consumer stats"))?;
+    }
+    if let Some((addr, path, retransmit)) = config.retransmit() {
+        let server = RetransmitServer::bind(addr, path, retransmit)
+            .map_err(SequencerError::io("retransmit"))?;
+        if let Ok(addr) = server.local_addr() {
+            info!(%addr, "serving retransmissions");
+        }
+        server.spawn().map_err(SequencerError::io("retransmit"))?;
+    }
+
+    let recorder = match &config.record {
+        Some(path) => Some(RawRecorder::create(path).map_err(SequencerError::io("record"))?),
+        None => None,
+    };
+    // Ids of the --udp feeds, which --config can add to and remove from
+    let mut udp_ids = None;
+    let replaying = config.replay.is_some() || config.replay_journal.is_some();
+    let mut threads = if !config.ingress.is_empty() {
+        spawn_ingress(&config, checkpoint.as_ref(), &mut arbiter, &shutdown)
+    } else if config.sim
+        || (!replaying
+            && config.udp.is_empty()
+            && config.tcp.is_empty()
+            && quic_feeds(&config) == 0)
+    {
+        spawn_simulated_feeds(config.feeds, &config.sim(), &mut arbiter)
+    } else if let Some(path) = &config.replay {
+        spawn_replay(path, &config, &mut arbiter)
+    } else if let Some(path) = &config.replay_journal {
+        spawn_journal_replay(path, &config, &mut arbiter)
+    } else if let Some(spawned) = spawn_af_xdp(&config, &mut arbiter, &shutdown) {
+        spawned
+    } else {
+        let feeds = arbiter.feed_set();
+        let pool = BufferPool::with_huge_pages(
+            config.pool_buffers,
+            pool_buffer_len(&config),
+            config.huge_pages,
+        );
+        let recorder = recorder.as_ref().map(RawRecorder::sender);
+        let (ids, threads) = config
+            .udp_feeds()
+            .iter()
+            .map(|f| {
+                let socket = inherited.take_udp(f.port);
+                spawn_udp_feed(
+                    &config,
+                    f,
+                    socket,
+                    &feeds,
+                    &pool,
+                    recorder.clone(),
+                    &shutdown,
+                )
+            })
+            .collect::<io::Result<(Vec<_>, Vec<_>)>>()
+            .map_err(SequencerError::io("udp"))?;
+        udp_ids = Some((ids, feeds, pool));
+        Ok(threads)
+    }
+    .map_err(SequencerError::io("feeds"))?;
+    if let Some(soup) = config.soupbintcp() {
+        let thread = spawn_soupbintcp(&soup, &mut arbiter, &config, &shutdown)
+            .map_err(SequencerError::io("soupbintcp"))?;
+        threads.push(thread);
+    }
+    threads.extend(spawn_tcp_feeds(&config, &arbiter, &shutdown)?);
+    threads.extend(spawn_quic_feeds(&config, &arbiter, &shutdown)?);
+    let added = Arc::<Mutex<Vec<Consumer>>>::default();
+    let added_feeds = Arc::<Mutex<Vec<FeedThread>>>::default();
+    // Kept only to be changed, the arbiter runs until every FeedSet is gone
+    let udp_ids = udp_ids.filter(|_| config.file_feeds);
+    if let (Some(path), Some(file)) = (&config.config, file) {
+        let control = arbiter.control();
+        let (limit, added) = (config.output_limit(), Arc::clone(&added));
+        // Feeds the file lists and their ids, if they are the ones sequenced
+        let mut live = udp_ids.map(|(ids, feeds, pool)| {
+            let entries = file.feed_entries().into_iter().zip(ids).collect::<Vec<_>>();
+            (entries, feeds, pool)
+        });
+        let (c, shutdown, added_feeds) =
+            (config.clone(), shutdown.clone(), Arc::clone(&added_feeds));
+        let watcher = ConfigWatcher::new(path, file).map_err(SequencerError::io("config"))?;
+        watcher
+            .spawn(CONFIG_POLL, move |change| {
+                if !matches!(change, Change::NeedsRestart(_)) {
+                    info!(?change, "config changed");
+                }
+                match change {
+                    Change::Timeout(t) => send_command(&control, Command::SetTimeout(t)),
+                    Change::FeedTimeout(t) => send_command(&control, Command::SetFeedTimeout(t)),
+                    Change::LogLevel(level) => {
+                        if let Err(e) = set_log_level(&level) {
+                            warn!(level, error = e, "bad log level");
+                        }
+                    }
+                    Change::AddSink(sink) => {
+                        let added_sink = sink.open().and_then(|s| {
+                            let name = sink.name();
+                            // Always given a fan-out with --config
+                            let subscriber =
+                                subscriber.as_ref().ok_or(io::ErrorKind::Unsupported)?;
+                            let (receiver, lag) = subscriber.subscribe(name, limit);
+                            let thread = spawn_sink(
+                                name,
+                                s,
+                                receiver,
+                                Some(lag.clone()),
+                                c.sink_failure,
+                                c.batching(),
+                                &shutdown,
+                            )?;
+                            Ok((thread, lag))
+                        });
+                        match added_sink {
+                            Ok(consumer) => lock(&added).push(consumer),
+                            Err(e) => warn!(?sink, error = %e, "failed to add sink"),
+                        }
+                    }
+                    Change::AddFeed(entry) => match &mut live {
+                        Some((entries, feeds, pool)) => {
+                            let f = c.udp_feed(&entry);
+                            // Only feeds given at startup are recorded
+                            match spawn_udp_feed(&c, &f, None, feeds, pool, None, &shutdown) {
+                                Ok((id, thread)) => {
+                                    entries.push((entry, id));
+                                    lock(&added_feeds).push(thread);
+                                }
+                                Err(e) => warn!(?entry, error = %e, "failed to add feed"),
+                            }
+                        }
+                        None => warn!(setting = "feeds", "config change needs a restart"),
+                    },
+                    Change::RemoveFeed(entry) => match &mut live {
+                        Some((entries, feeds, _)) => {
+                            if let Some(i) = entries.iter().position(|(e, _)| *e == entry) {
+                                // Its thread ends on its next send
+                                feeds.remove_feed(entries.remove(i).1);
+                            }
+                        }
+                        None => warn!(setting = "feeds", "config change needs a restart"),
+                    },
+                    Change::NeedsRestart(setting) => {
+                        warn!(setting, "config change needs a restart")
+                    }
+                }
+            })
+            .map_err(SequencerError::io("config"))?;
+    }
+
+    // Groups are joined and the journal is open
+    if let Some(user) = &user {
+        user.switch().map_err(SequencerError::io("user"))?;
+        info!(user = user.name, uid = user.uid, "dropped privileges");
+    }
+    if config.seccomp {
+        sandbox::seccomp().map_err(SequencerError::io("seccomp"))?;
+        info!("seccomp filter applied");
+    }
+    if systemd::notify("READY=1\nSTATUS=sequencing").map_err(SequencerError::io("notify"))? {
+        if let Some(interval) = systemd::watchdog_interval() {
+            info!(?interval, "pinging the systemd watchdog");
+            Watchdog::new(health, interval)
+                .spawn(shutdown.clone())
+                .map_err(SequencerError::io("watchdog"))?;
+        }
+    }
+
+    // Sequence on this thread, the only one touching sequencing state, until
+    // all feeds stop
+    pin_to_core(config.pin_cores.first().copied());
+    let sequencer = arbiter.run();
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        warn!(error = %e, "failed to notify systemd");
+    }
+    // Everything is torn down even after a failure, which is returned after
+    let mut failed = Ok(());
+    let mut check = |result: Result<(), SequencerError>| {
+        if let Err(e) = result {
+            error!(error = %e, "failed");
+            if failed.is_ok() {
+                failed = Err(e);
+            }
+        }
+    };
+    for t in threads
+        .into_iter()
+        .chain(mem::take(&mut *lock(&added_feeds)))
+    {
+        check(error::join(t).and_then(|r| r));
+    }
+    let seqnum = sequencer.seqnum(0);
+    let stats = sequencer.stats();
+    info!(%stats, "stats");
+    if stats.feeds.len() > 1 {
+        info!(lines = %stats.line_report(), "line quality");
+    }
+    if let Some(timeout) = stats.recommended_timeout() {
+        info!(?timeout, current = ?sequencer.timeout(), "recommended gap timeout");
+    }
+    if let Some(path) = &config.gap_report {
+        let gaps = sequencer.gaps();
+        check(gaps::export(&gaps, path).map_err(SequencerError::io("gap report")));
+        info!(gaps = gaps.len(), path = %path.display(), "gap report");
+    }
+    drop(sequencer); // To end consumer threads' iter
+    let mut n_consumed = None;
+    for c in consumers {
+        let n = error::join(c).and_then(|r| r);
+        n_consumed = n_consumed.or(n.as_ref().ok().copied());
+        check(n.map(drop));
+    }
+    for lag in &lags {
+        info!(consumer = %lag.stats(), "lag");
+    }
+    for (consumer, lag) in mem::take(&mut *lock(&added)) {
+        check(error::join(consumer).and_then(|r| r).map(drop));
+        info!(consumer = %lag.stats(), "lag");
+    }
+    if let Some(recorder) = recorder {
+        check(recorder.finish().map_err(SequencerError::io("record")));
+    }
+    if let Some(latency) = &latency {
+        info!(%latency, "latency");
+        if let Some(path) = &config.latency_dump {
+            check(
+                latency
+                    .dump(path)
+                    .map_err(SequencerError::io("latency dump")),
+            );
+        }
+    }
+
+    info!(blocks = n_consumed.unwrap_or(0), seqnum, "consumed");
+    failed
+}
+
+// Poisoned only by a thread that panicked, whose join reports it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-impl PartialOrd for BlockHeader {
-    fn partial_cmp(&self, r: &Self) -> Option<cmp::Ordering> {
-        self.seqnum.partial_cmp(&r.seqnum)
+// Apply an admin command without waiting for it
+fn send_command(control: &Sender<Request>, command: Command) {
+    let (reply, _) = crossbeam_channel::bounded(1);
+    if let Err(e) = control.send(Request { command, reply }) {
+        warn!(command = ?e.0.command, "sequencer stopped");
     }
 }
 
-#[derive(Clone, Eq, Ord)]
-struct BlockMeta {
-    seqnum: u64,
-    ts: Instant,
+// How --message-level sinks' blocks are split and --placeholders are framed,
+// the same for every feed
+fn message_framing(config: &Config) -> Result<message_level::Framing, SequencerError> {
+    let framing = message_level::Framing::for_protocol(&config.protocol);
+    let mut protocols = config.udp_protocol.iter();
+    match framing {
+        Some(f) if protocols.all(|p| message_level::Framing::for_protocol(p) == Some(f)) => Ok(f),
+        _ => {
+            let e = "--message-level and --placeholders need feeds framed alike, not mdp3 or fast";
+            Err(SequencerError::Config(e.to_string()))
+        }
+    }
 }
 
-impl PartialEq for BlockMeta {
-    fn eq(&self, r: &Self) -> bool {
-        self.seqnum == r.seqnum
+// A thread per sink, each with its own queue fanned out from the sequencer's
+// if there is more than one or more may be added. Threads return the number
+// of blocks consumed.
+fn spawn_consumers(
+    mut sinks: Vec<NamedSink>,
+    receiver: Receiver<SequencedEvent<Packet>>,
+    config: &Config,
+    shutdown: &Shutdown,
+    dynamic: bool,
+) -> io::Result<Consumers> {
+    let (policy, batching) = (config.sink_failure, config.batching());
+    if sinks.len() == 1 && !dynamic && config.conflate.is_empty() {
+        if let Some((name, sink)) = sinks.pop() {
+            let consumer = spawn_sink(name, sink, receiver, None, policy, batching, shutdown)?;
+            return Ok((vec![consumer], Vec::new(), None));
+        }
+    }
+    let mut fan_out = FanOut::new();
+    if config.max_consumer_lag > 0 {
+        fan_out.set_max_lag(config.max_consumer_lag, config.slow_consumer);
     }
+    let subscriber = dynamic.then(|| fan_out.subscriber());
+    for name in &config.conflate {
+        if !sinks.iter().any(|(n, _)| n == name) {
+            warn!(sink = name, "no such sink to conflate");
+        }
+    }
+    let mut lags = Vec::new();
+    let consumers = sinks
+        .into_iter()
+        .map(|(name, sink)| {
+            let (receiver, lag) = match config.conflate.iter().any(|c| c == name) {
+                true => {
+                    let key = Box::new(config.conflation);
+                    fan_out.subscribe_conflated(name, config.output_limit(), key)
+                }
+                false => fan_out.subscribe(name, config.output_limit()),
+            };
+            lags.push(lag.clone());
+            spawn_sink(name, sink, receiver, Some(lag), policy, batching, shutdown)
+        })
+        .collect::<io::Result<_>>()?;
+    fan_out.spawn(receiver)?;
+    Ok((consumers, lags, subscriber))
 }
 
-impl PartialOrd for BlockMeta {
-    fn partial_cmp(&self, r: &Self) -> Option<cmp::Ordering> {
-        self.seqnum.partial_cmp(&r.seqnum)
+// Consume on a thread of its own, reporting progress to `lag` if fanned out.
+// A retried sink picks up with the event after the ones it failed on.
+fn spawn_sink(
+    name: &'static str,
+    mut sink: Box<dyn Sink + Send>,
+    receiver: Receiver<SequencedEvent<Packet>>,
+    lag: Option<ConsumerLag>,
+    policy: FailurePolicy,
+    batching: Batching,
+    shutdown: &Shutdown,
+) -> io::Result<SinkThread> {
+    let shutdown = shutdown.clone();
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            supervise(policy, &shutdown, || {
+                let consumed = |event: &SequencedEvent<Packet>| {
+                    if let Some(lag) = &lag {
+                        lag.consumed(event);
+                    }
+                };
+                sink::run_batched(receiver.clone(), &mut sink, batching, consumed)
+                    .map_err(SequencerError::sink(name))
+            })
+        })
+}
+
+// Room for io_uring's header ahead of each datagram
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+fn pool_buffer_len(config: &Config) -> usize {
+    match config.io_uring {
+        true => config.pool_buffer_len + sequencer::uring::RECV_OVERHEAD,
+        false => config.pool_buffer_len,
     }
 }
 
-impl Hash for BlockMeta {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.seqnum.hash(state);
+#[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+fn pool_buffer_len(config: &Config) -> usize {
+    config.pool_buffer_len
+}
+
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+fn run_uring(feed: UdpFeed, sender: Sender<Vec<Packet>>) -> io::Result<()> {
+    feed.run_uring(sender)
+}
+
+#[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+fn run_uring(feed: UdpFeed, sender: Sender<Vec<Packet>>) -> io::Result<()> {
+    warn!("built without the io_uring feature, falling back to recvmmsg");
+    feed.run_batched(sender)
+}
+
+// Pin the calling thread, which keeps running if the core is unavailable
+fn pin_to_core(core: Option<usize>) {
+    if let Some(id) = core {
+        if !core_affinity::set_for_current(core_affinity::CoreId { id }) {
+            warn!(
+                thread = thread::current().name(),
+                core = id,
+                "could not pin"
+            );
+        }
     }
 }
 
-fn consume(sink: &mut File, block: BlockHeader) {
-    write!(sink, "{}\n", block.seqnum).unwrap();
+// Bind the calling thread, and so every thread it spawns, to a NUMA node,
+// carrying on unplaced if the node can't be found
+fn place_on_node(config: &Config, numa: NumaNode) {
+    let topology = Topology::system();
+    let node = match numa {
+        NumaNode::Node(node) => Some(node),
+        NumaNode::Auto => {
+            let nic = receiving_nic(config);
+            let node = nic
+                .as_deref()
+                .map(|nic| topology.interface_node(nic))
+                .transpose();
+            match node {
+                Ok(node) => node.flatten(),
+                Err(e) => {
+                    warn!(?nic, error = %e, "could not find the NIC's NUMA node");
+                    None
+                }
+            }
+        }
+    };
+    let node = match node {
+        Some(node) => node,
+        None => {
+            warn!("no NUMA node for the receiving NIC, not placing");
+            return;
+        }
+    };
+    let placed = topology
+        .node_cpus(node)
+        .and_then(|cpus| numa::bind(node, &cpus).map(|()| cpus));
+    match placed {
+        Ok(cpus) => {
+            info!(node, cpus = cpus.len(), "placed on NUMA node");
+            for core in config.pin_cores.iter().filter(|c| !cpus.contains(c)) {
+                warn!(core, node, "--pin-cores core is on another NUMA node");
+            }
+        }
+        Err(e) => warn!(node, error = %e, "could not place on NUMA node"),
+    }
 }
 
-fn generate_blocks(n_blocks: usize) -> Vec<BlockHeader> {
-    let mut res = Vec::new();
+// --af-xdp's NIC, or the one with the --interface address
+fn receiving_nic(config: &Config) -> Option<String> {
+    #[cfg(all(feature = "af_xdp", target_os = "linux"))]
+    if let Some(device) = &config.af_xdp {
+        return Some(device.clone());
+    }
+    let addr = config.udp_interface.first().copied();
+    numa::interface_with_addr(addr.unwrap_or(config.interface))
+        .ok()
+        .flatten()
+}
 
-    // let mut rng = rand::thread_rng();
-    let mut seqnum = 0;
-    for _i in 0..n_blocks {
-        // let n_messages = rng.gen_range(1..50);
-        let n_messages = 1; // for easy debugging
-        res.push(BlockHeader { seqnum, n_messages });
-        seqnum += n_messages as u64
+// Join a --udp feed, set up as the flags say, on `socket` if systemd bound
+// one for it
+fn open_udp_feed(
+    config: &Config,
+    feed_config: &FeedConfig,
+    socket: Option<UdpSocket>,
+    pool: &BufferPool,
+    shutdown: &Shutdown,
+) -> io::Result<UdpFeed> {
+    let mut feed = match socket {
+        Some(socket) => UdpFeed::with_socket(socket, feed_config)?,
+        None => UdpFeed::join(feed_config)?,
+    };
+    feed.set_pool(pool.clone());
+    feed.set_timestamping(config.timestamping)?;
+    feed.set_shutdown(shutdown.clone())?;
+    if config.busy_poll {
+        let us = (config.busy_poll_us > 0).then_some(config.busy_poll_us);
+        feed.set_busy_poll(us)?;
+    }
+    Ok(feed)
+}
+
+// A feed thread's end of its queue to the arbiter
+enum FeedSender {
+    Blocks(Sender<Packet>),
+    Batches(Sender<Vec<Packet>>, usize),
+    Uring(Sender<Vec<Packet>>),
+}
+
+impl FeedSender {
+    fn run(&self, mut feed: UdpFeed) -> io::Result<()> {
+        match self {
+            FeedSender::Blocks(s) => feed.run(s.clone()),
+            FeedSender::Batches(s, batch) => {
+                feed.set_recv_batch(*batch);
+                feed.run_batched(s.clone())
+            }
+            FeedSender::Uring(s) => {
+                // Batch size of the recvmmsg fallback
+                feed.set_recv_batch(MAX_BATCH);
+                run_uring(feed, s.clone())
+            }
+        }
+    }
+}
+
+// Join a --udp feed and receive it on a thread of its own, rejoining if it
+// fails and --feed-failure is retry
+fn spawn_udp_feed(
+    config: &Config,
+    feed_config: &FeedConfig,
+    socket: Option<UdpSocket>,
+    feeds: &FeedSet<Packet>,
+    pool: &BufferPool,
+    recorder: Option<Sender<RawPacket>>,
+    shutdown: &Shutdown,
+) -> io::Result<(FeedId, FeedThread)> {
+    let feed = open_udp_feed(config, feed_config, socket, pool, shutdown)?;
+    let socket = feed.socket_info()?;
+    let (i, sender) = if config.io_uring {
+        let (i, s) = feeds.add_batch_feed(MAX_BATCH);
+        (i, FeedSender::Uring(s))
+    } else if config.recv_batch > 1 {
+        let (i, s) = feeds.add_batch_feed(config.recv_batch);
+        (i, FeedSender::Batches(s, config.recv_batch))
+    } else {
+        let (i, s) = feeds.add_feed();
+        (i, FeedSender::Blocks(s))
+    };
+    if let Some(requested) = feed_config.recv_buffer {
+        if socket.recv_buffer < requested {
+            warn!(
+                feed = i,
+                requested,
+                effective = socket.recv_buffer,
+                "receive buffer capped, raise net.core.rmem_max or start as root"
+            );
+        }
+    }
+    info!(
+        feed = i,
+        group = %feed_config.group,
+        port = feed_config.port,
+        interface = %feed_config.interface,
+        source = ?feed_config.source,
+        recv_buffer = socket.recv_buffer,
+        reuse_address = socket.reuse_address,
+        reuse_port = socket.reuse_port,
+        "joined"
+    );
+
+    let core = config.pin_cores.get(i + 1).copied();
+    let metrics = feeds.metrics(i);
+    let (config, feed_config) = (config.clone(), feed_config.clone());
+    let (pool, shutdown) = (pool.clone(), shutdown.clone());
+    let mut joined = Some(feed);
+    let thread = thread::Builder::new()
+        .name(format!("feed {}", i))
+        .spawn(move || {
+            let _span = info_span!("feed", feed = i).entered();
+            pin_to_core(core);
+            supervise(config.feed_failure, &shutdown, || {
+                let mut feed = match joined.take() {
+                    Some(feed) => feed,
+                    None => {
+                        let feed = open_udp_feed(&config, &feed_config, None, &pool, &shutdown)
+                            .map_err(SequencerError::feed(i))?;
+                        info!("rejoined");
+                        feed
+                    }
+                };
+                if let Some(recorder) = &recorder {
+                    feed.record_to(i, recorder.clone());
+                }
+                feed.set_metrics(Arc::clone(&metrics));
+                feed.set_checksum(config.checksum);
+                sender.run(feed).map_err(SequencerError::feed(i))
+            })
+        })?;
+    Ok((i, thread))
+}
+
+// One thread receiving every --udp feed from --af-xdp's queue, if given
+#[cfg(all(feature = "af_xdp", target_os = "linux"))]
+fn spawn_af_xdp(
+    config: &Config,
+    arbiter: &mut Arbiter<Packet>,
+    shutdown: &Shutdown,
+) -> Option<io::Result<Vec<FeedThread>>> {
+    let device = config.af_xdp.clone()?;
+    let xdp = XdpConfig {
+        device,
+        queue: config.xdp_queue,
+        mode: config.xdp_mode,
+        frames: config.xdp_frames,
+        feeds: config.udp_feeds(),
+    };
+    let mut source = match AfXdpSource::bind(&xdp) {
+        Ok(source) => source,
+        Err(e) => return Some(Err(e)),
+    };
+    source.set_shutdown(shutdown.clone());
+    info!(
+        device = %xdp.device,
+        queue = xdp.queue,
+        mode = ?source.mode(),
+        zero_copy = source.zero_copy(),
+        feeds = xdp.feeds.len(),
+        "af_xdp attached"
+    );
+    let senders: Vec<_> = xdp
+        .feeds
+        .iter()
+        .map(|_| arbiter.add_batch_feed(MAX_BATCH))
+        .collect();
+    let core = config.pin_cores.get(1).copied();
+    let thread = thread::Builder::new()
+        .name("af_xdp".to_string())
+        .spawn(move || {
+            pin_to_core(core);
+            source.run(&senders).map_err(SequencerError::io("af_xdp"))
+        });
+    Some(thread.map(|t| vec![t]))
+}
+
+#[cfg(not(all(feature = "af_xdp", target_os = "linux")))]
+fn spawn_af_xdp(
+    _config: &Config,
+    _arbiter: &mut Arbiter<Packet>,
+    _shutdown: &Shutdown,
+) -> Option<io::Result<Vec<FeedThread>>> {
+    None
+}
+
+// Logged in to the server on a thread of its own, logging in again if the
+// session fails and --feed-failure is retry
+fn spawn_soupbintcp(
+    soup: &SoupBinTcpConfig,
+    arbiter: &mut Arbiter<Packet>,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> io::Result<FeedThread> {
+    let connect = |soup: &SoupBinTcpConfig, shutdown: &Shutdown| {
+        let mut source = SoupBinTcpSource::connect(soup)?;
+        source.set_shutdown(shutdown.clone());
+        info!(
+            addr = %soup.addr,
+            session = %String::from_utf8_lossy(source.session()),
+            seqnum = source.seqnum(),
+            "soupbintcp logged in"
+        );
+        Ok(source)
+    };
+    let mut connected = Some(connect(soup, shutdown)?);
+    let s = arbiter.add_feed();
+    let (soup, shutdown, policy) = (soup.clone(), shutdown.clone(), config.feed_failure);
+    thread::Builder::new()
+        .name("soupbintcp".to_string())
+        .spawn(move || {
+            supervise(policy, &shutdown, || {
+                let source = match connected.take() {
+                    Some(source) => source,
+                    None => connect(&soup, &shutdown).map_err(SequencerError::io("soupbintcp"))?,
+                };
+                source
+                    .run(s.clone())
+                    .map_err(SequencerError::io("soupbintcp"))
+            })
+        })
+}
+
+// A thread per --tcp feed, reconnecting as --feed-failure says
+fn spawn_tcp_feeds(
+    config: &Config,
+    arbiter: &Arbiter<Packet>,
+    shutdown: &Shutdown,
+) -> Result<Vec<FeedThread>, SequencerError> {
+    let tcp_feeds = config.tcp_feeds().map_err(SequencerError::io("tcp ca"))?;
+    if tcp_feeds.is_empty() {
+        return Ok(Vec::new());
+    }
+    let feeds = arbiter.feed_set();
+    let mut threads = Vec::new();
+    for (i, tcp) in tcp_feeds.into_iter().enumerate() {
+        let connect = |tcp: &TcpFeedConfig, shutdown: &Shutdown| {
+            let mut feed = TcpFeed::connect(tcp)?;
+            feed.set_shutdown(shutdown.clone());
+            info!(addr = %tcp.addr, channel = tcp.channel, "tcp connected");
+            Ok::<_, io::Error>(feed)
+        };
+        let mut connected = Some(connect(&tcp, shutdown).map_err(SequencerError::io("tcp"))?);
+        let (id, sender) = feeds.add_feed();
+        let metrics = feeds.metrics(id);
+        let (shutdown, policy, checksum) = (shutdown.clone(), config.feed_failure, config.checksum);
+        let thread = thread::Builder::new()
+            .name(format!("tcp {}", i))
+            .spawn(move || {
+                let _span = info_span!("feed", feed = id).entered();
+                supervise(policy, &shutdown, || {
+                    let mut feed = match connected.take() {
+                        Some(feed) => feed,
+                        None => connect(&tcp, &shutdown).map_err(SequencerError::feed(id))?,
+                    };
+                    feed.set_metrics(Arc::clone(&metrics));
+                    feed.set_checksum(checksum);
+                    feed.run(sender.clone()).map_err(SequencerError::feed(id))
+                })
+            })
+            .map_err(SequencerError::io("tcp"))?;
+        threads.push(thread);
+    }
+    Ok(threads)
+}
+
+#[cfg(feature = "quic")]
+fn quic_feeds(config: &Config) -> usize {
+    config.quic.len()
+}
+
+#[cfg(not(feature = "quic"))]
+fn quic_feeds(_config: &Config) -> usize {
+    0
+}
+
+// A thread per --quic feed, reconnecting as --feed-failure says
+#[cfg(feature = "quic")]
+fn spawn_quic_feeds(
+    config: &Config,
+    arbiter: &Arbiter<Packet>,
+    shutdown: &Shutdown,
+) -> Result<Vec<FeedThread>, SequencerError> {
+    if config.quic.is_empty() {
+        return Ok(Vec::new());
     }
-    return res;
+    let roots = match &config.quic_ca {
+        Some(path) => quic::load_certs(path).map_err(SequencerError::io("quic ca"))?,
+        None => return Err(SequencerError::Config("--quic needs --quic-ca".to_string())),
+    };
+    let feeds = arbiter.feed_set();
+    let mut threads = Vec::new();
+    for (i, addr) in config.quic.iter().enumerate() {
+        let quic = QuicFeedConfig {
+            server: *addr,
+            server_name: match &config.quic_server_name {
+                Some(name) => name.clone(),
+                None => addr.ip().to_string(),
+            },
+            roots: roots.clone(),
+            protocol: config.protocol.clone(),
+            channel: config.quic_channel.get(i).copied().unwrap_or(0),
+        };
+        let connect = |quic: &QuicFeedConfig, shutdown: &Shutdown| {
+            let mut feed = QuicFeed::connect(quic)?;
+            feed.set_shutdown(shutdown.clone());
+            info!(server = %feed.remote_addr(), channel = quic.channel, "quic connected");
+            Ok::<_, io::Error>(feed)
+        };
+        let failed = SequencerError::io("quic");
+        let mut connected = Some(connect(&quic, shutdown).map_err(failed)?);
+        let (id, sender) = feeds.add_feed();
+        let metrics = feeds.metrics(id);
+        let (shutdown, policy, checksum) = (shutdown.clone(), config.feed_failure, config.checksum);
+        let thread = thread::Builder::new()
+            .name(format!("quic {}", i))
+            .spawn(move || {
+                let _span = info_span!("feed", feed = id).entered();
+                supervise(policy, &shutdown, || {
+                    let mut feed = match connected.take() {
+                        Some(feed) => feed,
+                        None => connect(&quic, &shutdown).map_err(SequencerError::feed(id))?,
+                    };
+                    feed.set_metrics(Arc::clone(&metrics));
+                    feed.set_checksum(checksum);
+                    feed.run(sender.clone()).map_err(SequencerError::feed(id))
+                })
+            })
+            .map_err(SequencerError::io("quic"))?;
+        threads.push(thread);
+    }
+    Ok(threads)
+}
+
+#[cfg(not(feature = "quic"))]
+fn spawn_quic_feeds(
+    _config: &Config,
+    _arbiter: &Arbiter<Packet>,
+    _shutdown: &Shutdown,
+) -> Result<Vec<FeedThread>, SequencerError> {
+    Ok(Vec::new())
 }
 
-fn shuffle_blocks(blocks: &Vec<BlockHeader>, thread_num: usize) -> Vec<BlockHeader> {
-    // Shuffle 1/100 of the messages to simulate UDP
-    let mut res = blocks.clone();
-    for i in 1..blocks.len() / 100 {
-        let index = i * 4 + thread_num;
-        res.swap(index, index + 1);
+// Sinks of a primary heartbeating their progress or of a standby holding
+// them back, or both for a standby with a standby of its own
+fn high_availability(sinks: Vec<NamedSink>, config: &Config) -> io::Result<Vec<NamedSink>> {
+    let standby = match config.standby {
+        Some(addr) => {
+            let standby = Standby::new(Duration::from_millis(config.standby_timeout_ms));
+            standby.listen(addr)?;
+            info!(%addr, "standing by");
+            Some(standby)
+        }
+        None => None,
+    };
+    let mut heartbeat = match config.heartbeat {
+        Some(addr) => Some(Heartbeat::new(
+            addr,
+            Duration::from_millis(config.heartbeat_ms),
+        )?),
+        None => None,
+    };
+    let sinks = sinks
+        .into_iter()
+        .map(|(name, mut sink)| {
+            if let Some(heartbeat) = &mut heartbeat {
+                sink = Box::new(heartbeat.track(name, sink));
+            }
+            if let Some(standby) = &standby {
+                sink = Box::new(StandbySink::new(sink, name, standby.clone()));
+            }
+            (name, sink)
+        })
+        .collect();
+    if let Some(mut heartbeat) = heartbeat {
+        if let Some(standby) = &standby {
+            heartbeat.set_standby(standby.clone());
+        }
+        heartbeat.spawn()?;
     }
+    Ok(sinks)
+}
 
-    return res;
+fn spawn_replay(
+    path: &Path,
+    config: &Config,
+    arbiter: &mut Arbiter<Packet>,
+) -> io::Result<Vec<FeedThread>> {
+    let mut source = PcapSource::open(path)?;
+    source.set_protocol(config.protocol.clone());
+    source.set_speed(config.replay_speed());
+    let mut senders = Vec::new();
+    for (i, (addr, feed)) in config.udp.iter().zip(config.udp_feeds()).enumerate() {
+        source.add_feed_in(*addr, feed.channel);
+        source.set_feed_protocol(i, feed.protocol);
+        senders.push(arbiter.add_feed());
+    }
+    let thread = thread::Builder::new()
+        .name("replay".to_string())
+        .spawn(move || source.run(&senders).map_err(SequencerError::io("replay")))?;
+    Ok(vec![thread])
 }
 
-fn main() {
-    let n_sides = 2;
-    let cur_block = Arc::new(Mutex::new(BlockMeta {
-        seqnum: 0,
-        ts: Instant::now(),
-    }));
-    let new_blocks = Arc::new(Mutex::new(
-        HashMap::<BlockMeta, BlockHeader>::with_capacity(BUFFER_LEN),
-    ));
-    let (message_sender, message_receiver) = unbounded::<BlockHeader>();
+fn spawn_journal_replay(
+    path: &Path,
+    config: &Config,
+    arbiter: &mut Arbiter<Packet>,
+) -> io::Result<Vec<FeedThread>> {
+    let mut source = JournalSource::open(path)?;
+    source.set_speed(config.replay_speed());
+    let sender = arbiter.add_feed();
+    let thread = thread::Builder::new()
+        .name("replay".to_string())
+        .spawn(move || source.run(&sender).map_err(SequencerError::io("replay")))?;
+    Ok(vec![thread])
+}
+
+fn spawn_ingress(
+    config: &Config,
+    checkpoint: Option<&Checkpoint>,
+    arbiter: &mut Arbiter<Packet>,
+    shutdown: &Shutdown,
+) -> io::Result<Vec<FeedThread>> {
+    let channel = config.ingress_channel;
+    // Numbering picks up where the journal left off
+    let seqnum = checkpoint
+        .and_then(|c| c.channels.get(&channel).copied())
+        .or(config.first_seqnum)
+        .unwrap_or_else(|| config.protocol.first_seqnum());
+    let mut ingress = Ingress::new(channel, seqnum);
+    ingress.set_policy(config.ingress_order.policy());
+    // Arrival order is the same however inputs are batched
+    if config.ingress_order != IngressOrder::Arrival {
+        ingress.set_window(Duration::from_micros(config.ingress_window_us));
+    }
+    let (input_sender, inputs) = crossbeam_channel::unbounded();
+    let mut threads = Vec::new();
+    for (i, addr) in config.ingress.iter().enumerate() {
+        let priority = config.ingress_priority.get(i).copied().unwrap_or(0);
+        let mut socket = IngressSocket::bind(*addr, i)?;
+        socket.set_priority(priority);
+        if let Some(offset) = config.ingress_priority_field {
+            socket.set_priority_field(offset);
+        }
+        socket.set_shutdown(shutdown.clone())?;
+        info!(addr = %socket.local_addr()?, priority, "taking inputs");
+        let sender = input_sender.clone();
+        let thread = thread::Builder::new()
+            .name(format!("ingress {}", i))
+            .spawn(move || socket.run(sender).map_err(SequencerError::io("ingress")))?;
+        threads.push(thread);
+    }
+    drop(input_sender);
+    let sender = arbiter.add_feed();
+    let thread = thread::Builder::new()
+        .name("ingress".to_string())
+        .spawn(move || {
+            ingress
+                .run(inputs, sender)
+                .map_err(SequencerError::io("ingress"))
+        })?;
+    threads.push(thread);
+    Ok(threads)
+}
 
+fn spawn_simulated_feeds(
+    n_sides: usize,
+    sim: &SimConfig,
+    arbiter: &mut Arbiter<Packet>,
+) -> io::Result<Vec<FeedThread>> {
     // Generate some dummy test messages
     let n_blocks = 10_000;
     let mut blocks = generate_blocks(n_blocks);
@@ -95,116 +2381,22 @@ fn main() {
     // To test multiple timeouts swap 3 and 4
     blocks.swap(2, 3);
 
-    // Start consumer thread
-    let r2 = message_receiver.clone();
-    let n_consumed = Arc::new(Mutex::new(0 as u64));
-    let n_consumed1 = Arc::clone(&n_consumed);
-    let consumer = thread::spawn(move || {
-        let mut n_consumed1 = n_consumed1.lock().unwrap();
-        let mut sink = File::create("messages.txt").unwrap();
-        // Sequence up to n messages
-        for block in r2.iter() {
-            consume(&mut sink, block);
-            *n_consumed1 += 1;
-        }
-    });
-
     // Start producer threads
-    let timeout = Duration::from_millis(10);
+    info!(feeds = n_sides, seed = sim.seed, "simulating");
     let mut threads = Vec::new();
     for i in 0..n_sides {
         let name = format!("feed {}", i);
         let builder = thread::Builder::new().name(name.clone());
 
-        let cur_block = Arc::clone(&cur_block);
-        let blocks = shuffle_blocks(&blocks, i); // Shuffle messages to simulate UDP's Out Of Order
-        let new_blocks = Arc::clone(&new_blocks);
-        let s = message_sender.clone();
-        let thread = builder
-            .spawn(move || {
-                // TODO: poll network with timeout. On timeout Flush timed out sequence numbers from new_blocks
-                for b in blocks {
-                    // Receive udp packet which has a block of messages
-                    {
-                        let mut cur_block = cur_block.lock().unwrap();
-                        cur_block.ts = Instant::now();
-                        let mut new_blocks = new_blocks.lock().unwrap();
-                        if b.seqnum == cur_block.seqnum {
-                            cur_block.seqnum += b.n_messages as u64;
-                            s.send(b).unwrap();
-                        } else if b.seqnum > cur_block.seqnum {
-                            let meta = BlockMeta {
-                                seqnum: b.seqnum,
-                                ts: cur_block.ts,
-                            };
-                            if new_blocks.insert(meta.clone(), b).is_none() {
-                                println!("Out of order {} (expected {})", meta.seqnum, cur_block.seqnum);
-                            }
-                        }
-                        // Flush in order sequence numbers from new_blocks
-                        while let Some(new_block) = new_blocks.remove(&cur_block) {
-                            cur_block.seqnum += new_block.n_messages as u64;
-                            s.send(new_block).unwrap();
-                            cur_block.ts = Instant::now();
-                        }
-                        // Flush timed out sequence numbers from new_blocks
-                        if new_blocks.len() > 0 {
-                            let mut block_metas = Vec::<BlockMeta>::new();
-                            // Pass 1: Collect timed out blocks. Find minimum seqnum
-                            let mut min_seqnum = std::u64::MAX;
-                            for meta in new_blocks.keys() {
-                                let duration = cur_block.ts.duration_since(meta.ts);
-                                if duration > timeout {
-                                    println!("Timeout {} (duration {:?} > {:?})", meta.seqnum, duration, timeout);
-                                    block_metas.push(meta.clone());
-                                    if meta.seqnum < min_seqnum {
-                                        min_seqnum = meta.seqnum
-                                    }
-                                }
-                            }
-
-                            if block_metas.len() > 0 {
-                                // Pass 2: Collect seqnums earlier than minimum seqnum
-                                for meta in new_blocks.keys() {
-                                    if meta.seqnum < min_seqnum {
-                                        block_metas.push(meta.clone());
-                                    }
-                                }
-                                block_metas.sort_by_key(|b| b.seqnum);
-
-                                println!(
-                                    "Flushing {} blocks from {} to {}",
-                                    block_metas.len(),
-                                    block_metas[0].seqnum,
-                                    block_metas[block_metas.len() - 1].seqnum
-                                );
-                                for m in block_metas {
-                                    let b = new_blocks.remove(&m).unwrap();
-                                    cur_block.seqnum = b.seqnum + b.n_messages as u64;
-                                    s.send(b).unwrap();
-                                }
-                            }
-                        }
-                    }
-                    // Simulate time between packets
-                    let n = rand::thread_rng().gen_range(0..50);
-                    thread::sleep(std::time::Duration::from_micros(n));
-                }
-            })
-            .unwrap();
+        // Lose, reorder and duplicate like UDP
+        let schedule = sim.schedule(&blocks, i);
+        let s = arbiter.add_feed();
+        let thread = builder.spawn(move || {
+            sim::run(schedule, s);
+            Ok(())
+        })?;
         threads.push(thread);
     }
 
-    // Wait for them all to stop
-    for t in threads {
-        t.join().unwrap();
-    }
-    drop(message_sender); // To end consumer thread's iter
-    consumer.join().unwrap();
-
-    println!(
-        "Consumed {} blocks, ending seqnum {}",
-        *n_consumed.clone().lock().unwrap(),
-        cur_block.clone().lock().unwrap().seqnum
-    );
+    Ok(threads)
 }
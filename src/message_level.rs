@@ -0,0 +1,244 @@
+// Message level sequencing, for consumers that track messages' seqnums
+// rather than blocks'. A sink wrapped in a MessageSink gets a block per
+// message, numbered the block's seqnum plus the message's index, whose
+// payload is just that message still in its protocol's framing. So each is
+// a block of one message any sink can handle, sharing the received buffer
+// instead of copying it. Gaps already count messages and are passed on as
+// they are.
+use crate::metrics::FeedId;
+use crate::pool::Payload;
+use crate::protocol::{custom, moldudp64, opra, pitch, ParseError, Protocol};
+use crate::sink::Sink;
+use crate::{Block, BlockHeader, ChannelId, GapReason, Session};
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// How a block's payload is split into its messages
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    // Each after a u16 BE length, as in MoldUDP64
+    LengthPrefixed,
+    // PITCH and MITCH messages, which start with their own length
+    Pitch,
+    Opra,
+    Custom(custom::MessageFraming),
+}
+
+impl Framing {
+    // None for protocols whose blocks can't be split, such as MDP3's whose
+    // seqnums count packets
+    pub fn for_protocol(protocol: &Protocol) -> Option<Self> {
+        match protocol {
+            // Raw blocks are taken to be of MoldUDP64 framed messages too
+            Protocol::Raw | Protocol::MoldUdp64 => Some(Framing::LengthPrefixed),
+            Protocol::Pitch | Protocol::Mitch => Some(Framing::Pitch),
+            Protocol::Opra => Some(Framing::Opra),
+            Protocol::Custom(framing) => framing.and_then(|f| f.messages).map(Framing::Custom),
+            Protocol::Mdp3 | Protocol::Fast(_) => None,
+        }
+    }
+
+    // Where each of a block's messages is in `payload`, framing included
+    pub fn messages(
+        &self,
+        payload: &[u8],
+        n_messages: u16,
+    ) -> Result<Vec<Range<usize>>, ParseError> {
+        let lens: Vec<usize> = match self {
+            Framing::LengthPrefixed => moldudp64::Messages::new(payload, n_messages)
+                .map(|m| m.map(|m| 2 + m.len()))
+                .collect::<Result<_, _>>()?,
+            Framing::Pitch => pitch::Messages::new(payload, n_messages)
+                .map(|m| m.map(|m| 2 + m.body.len()))
+                .collect::<Result<_, _>>()?,
+            Framing::Opra => opra::Messages::new(payload, n_messages)
+                .map(|m| m.map(|m| opra::MESSAGE_HEADER_LEN + m.data.len()))
+                .collect::<Result<_, _>>()?,
+            Framing::Custom(framing) => {
+                // Messages are yielded without their length prefixes
+                let prefix = match *framing {
+                    custom::MessageFraming::Fixed(_) => 0,
+                    custom::MessageFraming::LengthPrefixed { width, .. } => width,
+                };
+                custom::Messages::new(*framing, payload, n_messages)
+                    .map(|m| m.map(|m| prefix + m.len()))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let mut at = 0;
+        Ok(lens
+            .into_iter()
+            .map(|len| {
+                at += len;
+                at - len..at
+            })
+            .collect())
+    }
+
+    // Appends an empty message of no type, for a seqnum with no message
+    pub fn write_placeholder(&self, buf: &mut Vec<u8>) {
+        match *self {
+            Framing::LengthPrefixed => buf.extend_from_slice(&[0, 0]),
+            // Just the length and a kind of 0
+            Framing::Pitch => buf.extend_from_slice(&[2, 0]),
+            Framing::Opra => buf.extend_from_slice(&[0; opra::MESSAGE_HEADER_LEN]),
+            Framing::Custom(custom::MessageFraming::Fixed(len)) => buf.resize(buf.len() + len, 0),
+            Framing::Custom(custom::MessageFraming::LengthPrefixed {
+                width,
+                endian,
+                inclusive,
+            }) => {
+                let len = if inclusive { width as u64 } else { 0 };
+                match endian {
+                    custom::Endian::Big => buf.extend_from_slice(&len.to_be_bytes()[8 - width..]),
+                    custom::Endian::Little => buf.extend_from_slice(&len.to_le_bytes()[..width]),
+                }
+            }
+        }
+    }
+}
+
+// Hands `inner` a block per message of each block
+pub struct MessageSink<S> {
+    inner: S,
+    framing: Framing,
+    // Reused for the messages of each call
+    messages: Vec<Block<Payload>>,
+}
+
+impl<S> MessageSink<S> {
+    pub fn new(inner: S, framing: Framing) -> Self {
+        Self {
+            inner,
+            framing,
+            messages: Vec::new(),
+        }
+    }
+
+    // Splits `block` onto the end of `self.messages`. One that won't split is
+    // passed on whole rather than lost.
+    fn split(&mut self, block: &Block<Payload>) {
+        let ranges = match self
+            .framing
+            .messages(&block.payload, block.header.n_messages)
+        {
+            Ok(ranges) if ranges.len() > 1 => ranges,
+            Ok(_) => return self.messages.push(block.clone()),
+            Err(e) => {
+                let (channel, seqnum) = (block.header.channel, block.header.seqnum);
+                warn!(channel, seqnum, error = %e, "block not split into messages");
+                return self.messages.push(block.clone());
+            }
+        };
+        // Owned payloads are moved to a buffer the messages can share
+        let payload = match block.payload.is_pooled() {
+            true => block.payload.clone(),
+            false => {
+                let len = block.payload.len();
+                Payload::shared(Arc::new(block.payload.to_vec()), 0..len)
+            }
+        };
+        let n_messages = ranges.len();
+        for (i, range) in ranges.into_iter().enumerate() {
+            let header = BlockHeader {
+                seqnum: block.header.seqnum + i as u64,
+                n_messages: 1,
+                // Marks the end of the block's messages
+                end_of_session: block.header.end_of_session && i + 1 == n_messages,
+                ..block.header
+            };
+            self.messages.push(Block {
+                header,
+                payload: payload.slice(range),
+                received: block.received,
+            });
+        }
+    }
+}
+
+impl<S: Sink> Sink for MessageSink<S> {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.on_blocks(std::slice::from_ref(block))
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        self.messages.clear();
+        for block in blocks {
+            self.split(block);
+        }
+        let done = self.inner.on_blocks(&self.messages);
+        // Not holding on to the received buffers
+        self.messages.clear();
+        done
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        self.inner.on_gap(channel, range, reason)
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        self.inner.on_reset(channel, session, seqnum)
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.inner.on_resync(channel, seqnum, snapshot)
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.inner.on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.inner.on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.inner.on_end_of_session(channel, session, seqnum)
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.inner.on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.inner.on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.inner.on_idle()
+    }
+
+    fn published(&self) -> Option<u64> {
+        self.inner.published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.inner.resume_published(seqnum)
+    }
+}
@@ -0,0 +1,385 @@
+use crate::protocol::{ParseError, Parsed};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// Index of a feed in the order it was added to the arbiter
+pub type FeedId = usize;
+
+// Times the longest reorder delay a recommended gap timeout waits
+pub const REORDER_MARGIN: f64 = 2.0;
+
+#[derive(Debug, Default)]
+pub struct FeedMetrics {
+    pub packets: AtomicU64,
+    // Blocks from this feed another feed already delivered or buffered
+    pub duplicates: AtomicU64,
+    pub heartbeats: AtomicU64,
+    // Packets that skipped seqnums of this feed's own stream
+    pub gaps: AtomicU64,
+    // Blocks held back because another feed was active
+    pub standby: AtomicU64,
+    // Datagrams dropped as unparseable, or whose message count disagreed
+    // with their framing
+    pub malformed: AtomicU64,
+    // Messages counted by a block's header that its payload was too short for
+    pub truncated: AtomicU64,
+    // Packets dropped because their checksum trailer didn't match
+    pub corrupt: AtomicU64,
+    // Line quality, of blocks with messages
+    pub messages: AtomicU64,
+    // Seqnums the feed's own stream skipped
+    pub lost: AtomicU64,
+    // Blocks behind the feed's own stream
+    pub reordered: AtomicU64,
+    // Furthest such a block was behind, in seqnums, and longest it came in
+    // after the block that skipped it
+    pub max_reorder_distance: AtomicU64,
+    pub max_reorder_delay_ns: AtomicU64,
+    // Blocks of seqnums no other feed had delivered yet
+    pub first: AtomicU64,
+    // Blocks another feed had a copy of first, and how far behind it they
+    // came in total
+    pub late: AtomicU64,
+    pub lateness_ns: AtomicU64,
+    // Stale blocks from this feed that differed from the delivered ones
+    pub mismatched: AtomicU64,
+}
+
+impl FeedMetrics {
+    pub fn record_lateness(&self, lateness: Duration) {
+        let ns = lateness.as_nanos().min(u64::MAX as u128) as u64;
+        self.late.fetch_add(1, Ordering::Relaxed);
+        self.lateness_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    pub fn record_reorder(&self, distance: u64, delay: Duration) {
+        let ns = delay.as_nanos().min(u64::MAX as u128) as u64;
+        self.reordered.fetch_add(1, Ordering::Relaxed);
+        self.max_reorder_distance
+            .fetch_max(distance, Ordering::Relaxed);
+        self.max_reorder_delay_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    // Count a received datagram that didn't parse or disagreed with its
+    // framing
+    pub fn record_parsed(&self, parsed: &Result<Parsed, ParseError>) {
+        match parsed {
+            Ok(p) if p.malformed() => {
+                self.malformed.fetch_add(1, Ordering::Relaxed);
+                self.truncated
+                    .fetch_add(p.missing as u64, Ordering::Relaxed);
+            }
+            Ok(_) => {}
+            Err(_) => {
+                self.malformed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Counters updated by the sequencer. Shared behind an Arc so any thread can
+// take a snapshot without touching sequencing state.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // Buffer went from empty to holding an out of order block
+    pub gaps: AtomicU64,
+    // Blocks delivered from the reorder buffer once the gap before them closed
+    pub recovered: AtomicU64,
+    // Gaps skipped because they timed out
+    pub timeouts: AtomicU64,
+    // Seqnums skipped on timeout, reset, overflow or consumer lag
+    pub dropped: AtomicU64,
+    // Blocks discarded because their seqnum was already seen
+    pub duplicates: AtomicU64,
+    // Duplicates behind their channel's expected seqnum, and those of them
+    // that differed from the block delivered there
+    pub stale: AtomicU64,
+    pub mismatched: AtomicU64,
+    pub max_reorder_depth: AtomicUsize,
+    // Longest time from a block's receive timestamp to the sequencer
+    pub max_rx_delay_ns: AtomicU64,
+    // New sessions or seqnum wraparounds
+    pub resets: AtomicU64,
+    // Gaps recovered from a snapshot
+    pub resyncs: AtomicU64,
+    // Times a channel's reorder buffer hit its limit
+    pub overflows: AtomicU64,
+    // Times the arbitration policy switched active feed
+    pub failovers: AtomicU64,
+    // Blocks discarded while feeds disagreed on their channel's session
+    pub diverged: AtomicU64,
+    // Events waiting for the consumer as of the last one sent
+    pub output_depth: AtomicUsize,
+    pub max_output_depth: AtomicUsize,
+    // Seqnums dropped from the output queue because the consumer fell behind
+    pub lagged: AtomicU64,
+    // Blocks spooled to disk because the consumer fell behind, and how many
+    // of them are still there
+    pub spilled: AtomicU64,
+    pub spill_depth: AtomicUsize,
+    // Feed being sequenced, usize::MAX for all of them
+    pub active_feed: AtomicUsize,
+    // Feed liveness polls, and as of the last one the feeds down and the
+    // blocks buffered across channels, for health checks
+    pub polls: AtomicU64,
+    pub feeds_down: AtomicUsize,
+    pub buffered: AtomicUsize,
+    arbitration: Mutex<&'static str>,
+    // Only locked to add a feed or take a snapshot
+    feeds: Mutex<Vec<Arc<FeedMetrics>>>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FeedStats {
+    pub packets: u64,
+    pub duplicates: u64,
+    pub heartbeats: u64,
+    pub gaps: u64,
+    pub standby: u64,
+    pub malformed: u64,
+    pub truncated: u64,
+    pub corrupt: u64,
+    pub messages: u64,
+    pub lost: u64,
+    pub reordered: u64,
+    pub max_reorder_distance: u64,
+    pub max_reorder_delay: Duration,
+    pub first: u64,
+    pub late: u64,
+    pub lateness: Duration,
+    pub mismatched: u64,
+    pub active: bool,
+}
+
+fn ratio(n: u64, of: u64) -> f64 {
+    match of {
+        0 => 0.0,
+        of => n as f64 / of as f64,
+    }
+}
+
+impl FeedStats {
+    // Fraction of seqnums the feed had first
+    pub fn win_rate(&self) -> f64 {
+        ratio(self.first, self.first + self.late)
+    }
+
+    // Mean time the feed's copies come in behind the first copy, of those
+    // that weren't first
+    pub fn mean_lateness(&self) -> Duration {
+        match self.late {
+            0 => Duration::ZERO,
+            late => self.lateness / late.min(u32::MAX as u64) as u32,
+        }
+    }
+
+    // Fraction of the feed's own stream missing
+    pub fn loss_rate(&self) -> f64 {
+        ratio(self.lost, self.lost + self.messages)
+    }
+
+    // Fraction of the feed's blocks that came in behind its own stream
+    pub fn reorder_rate(&self) -> f64 {
+        ratio(self.reordered, self.first + self.late)
+    }
+}
+
+// Each feed's line quality on a line, so a degraded A or B line stands out
+pub struct LineReport<'a>(pub &'a [FeedStats]);
+
+impl fmt::Display for LineReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, feed) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "feed {} won {:.2}% late {:?} lost {:.4}% reordered {:.4}%",
+                i,
+                feed.win_rate() * 100.0,
+                feed.mean_lateness(),
+                feed.loss_rate() * 100.0,
+                feed.reorder_rate() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Point in time copy of Metrics
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SequencerStats {
+    pub gaps: u64,
+    pub recovered: u64,
+    pub timeouts: u64,
+    pub dropped: u64,
+    pub duplicates: u64,
+    pub stale: u64,
+    pub mismatched: u64,
+    pub max_reorder_depth: usize,
+    pub max_rx_delay: Duration,
+    pub resets: u64,
+    pub resyncs: u64,
+    pub overflows: u64,
+    pub arbitration: &'static str,
+    pub failovers: u64,
+    pub diverged: u64,
+    pub output_depth: usize,
+    pub max_output_depth: usize,
+    pub lagged: u64,
+    pub spilled: u64,
+    pub spill_depth: usize,
+    pub feeds: Vec<FeedStats>,
+}
+
+impl Metrics {
+    // Metrics of a feed, allocating any missing feeds up to it
+    pub fn feed(&self, id: FeedId) -> Arc<FeedMetrics> {
+        let mut feeds = self.feeds.lock().unwrap_or_else(PoisonError::into_inner);
+        while feeds.len() <= id {
+            feeds.push(Arc::default());
+        }
+        Arc::clone(&feeds[id])
+    }
+
+    pub fn set_arbitration(&self, name: &'static str) {
+        *self
+            .arbitration
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = name;
+    }
+
+    pub fn record_depth(&self, depth: usize) {
+        self.max_reorder_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn record_rx_delay(&self, delay: Duration) {
+        let ns = delay.as_nanos().min(u64::MAX as u128) as u64;
+        self.max_rx_delay_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SequencerStats {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let active = self.active_feed.load(Ordering::Relaxed);
+        SequencerStats {
+            gaps: load(&self.gaps),
+            recovered: load(&self.recovered),
+            timeouts: load(&self.timeouts),
+            dropped: load(&self.dropped),
+            duplicates: load(&self.duplicates),
+            stale: load(&self.stale),
+            mismatched: load(&self.mismatched),
+            max_reorder_depth: self.max_reorder_depth.load(Ordering::Relaxed),
+            max_rx_delay: Duration::from_nanos(load(&self.max_rx_delay_ns)),
+            resets: load(&self.resets),
+            resyncs: load(&self.resyncs),
+            overflows: load(&self.overflows),
+            arbitration: *self
+                .arbitration
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            failovers: load(&self.failovers),
+            diverged: load(&self.diverged),
+            output_depth: self.output_depth.load(Ordering::Relaxed),
+            max_output_depth: self.max_output_depth.load(Ordering::Relaxed),
+            lagged: load(&self.lagged),
+            spilled: load(&self.spilled),
+            spill_depth: self.spill_depth.load(Ordering::Relaxed),
+            feeds: self
+                .feeds
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .enumerate()
+                .map(|(i, f)| FeedStats {
+                    packets: load(&f.packets),
+                    duplicates: load(&f.duplicates),
+                    heartbeats: load(&f.heartbeats),
+                    gaps: load(&f.gaps),
+                    standby: load(&f.standby),
+                    malformed: load(&f.malformed),
+                    truncated: load(&f.truncated),
+                    corrupt: load(&f.corrupt),
+                    messages: load(&f.messages),
+                    lost: load(&f.lost),
+                    reordered: load(&f.reordered),
+                    max_reorder_distance: load(&f.max_reorder_distance),
+                    max_reorder_delay: Duration::from_nanos(load(&f.max_reorder_delay_ns)),
+                    first: load(&f.first),
+                    late: load(&f.late),
+                    lateness: Duration::from_nanos(load(&f.lateness_ns)),
+                    mismatched: load(&f.mismatched),
+                    active: active == usize::MAX || active == i,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SequencerStats {
+    pub fn line_report(&self) -> LineReport<'_> {
+        LineReport(&self.feeds)
+    }
+
+    // A gap timeout that would have waited out every reordered block seen so
+    // far with room to spare, in place of guessing one. None until a feed
+    // has reordered.
+    pub fn recommended_timeout(&self) -> Option<Duration> {
+        self.feeds
+            .iter()
+            .map(|f| f.max_reorder_delay)
+            .filter(|delay| !delay.is_zero())
+            .max()
+            .map(|delay| delay.mul_f64(REORDER_MARGIN))
+    }
+}
+
+impl fmt::Display for SequencerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "gaps {} recovered {} timeouts {} dropped {} duplicates {} stale {} mismatched {} max depth {} max rx delay {:?} resets {} resyncs {} overflows {} arbitration {} failovers {} diverged {} output depth {} max output depth {} lagged {} spilled {} spill depth {}",
+            self.gaps,
+            self.recovered,
+            self.timeouts,
+            self.dropped,
+            self.duplicates,
+            self.stale,
+            self.mismatched,
+            self.max_reorder_depth,
+            self.max_rx_delay,
+            self.resets,
+            self.resyncs,
+            self.overflows,
+            self.arbitration,
+            self.failovers,
+            self.diverged,
+            self.output_depth,
+            self.max_output_depth,
+            self.lagged,
+            self.spilled,
+            self.spill_depth
+        )?;
+        for (i, feed) in self.feeds.iter().enumerate() {
+            write!(
+                f,
+                " feed {}{} packets {} duplicates {} heartbeats {} gaps {} standby {} malformed {} truncated {} corrupt {} mismatched {}",
+                i,
+                if feed.active { " (active)" } else { "" },
+                feed.packets,
+                feed.duplicates,
+                feed.heartbeats,
+                feed.gaps,
+                feed.standby,
+                feed.malformed,
+                feed.truncated,
+                feed.corrupt,
+                feed.mismatched
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+// Recovery from LSE Millennium's MITCH servers. A replay channel takes replay
+// requests over TCP and sends the group's messages back on the same
+// connection, and a recovery channel sends snapshots of the group's books.
+// Both sessions start with a login.
+use crate::gapfill::GapFiller;
+use crate::pitch::{unexpected, Session};
+use crate::protocol::mitch::{self, Control, PacketKind, ACCEPTED};
+use crate::protocol::pitch;
+use crate::recovery::{Snapshot, SnapshotSource};
+use crate::{Block, BlockHeader, ChannelId, Payload};
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::Duration;
+use tracing::debug;
+
+#[derive(Clone, Debug, Default)]
+pub struct MitchLogin {
+    pub username: String,
+    pub password: String,
+}
+
+// The market data group a channel sequences, see Protocol::channel()
+pub fn group(channel: ChannelId) -> u8 {
+    channel as u8
+}
+
+fn login(addr: SocketAddr, login: &MitchLogin, timeout: Duration) -> io::Result<Session> {
+    let mut session = Session::connect(addr, timeout)?;
+    send(
+        &mut session,
+        &Control::Login {
+            username: pitch::padded(&login.username),
+            password: pitch::padded(&login.password),
+        },
+    )?;
+    loop {
+        let packet = session.next_packet()?;
+        for control in controls(&packet)? {
+            match control {
+                Control::LoginResponse { status: ACCEPTED } => return Ok(session),
+                Control::LoginResponse { status } => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("login rejected: {}", status as char),
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn send(session: &mut Session, control: &Control) -> io::Result<()> {
+    let mut buf = Vec::new();
+    mitch::write_header(&mut buf, 0, 0);
+    mitch::write_control(&mut buf, control);
+    session.send(&buf)
+}
+
+// Session messages of `packet`
+fn controls(packet: &[u8]) -> io::Result<Vec<Control>> {
+    let parsed = mitch::parse(packet).map_err(|e| unexpected(e.to_string()))?;
+    let mut controls = Vec::new();
+    for msg in parsed.messages() {
+        let msg = msg.map_err(|e| unexpected(e.to_string()))?;
+        match mitch::parse_control(&msg) {
+            Some(Ok(control)) => controls.push(control),
+            Some(Err(e)) => return Err(unexpected(e.to_string())),
+            None => {}
+        }
+    }
+    Ok(controls)
+}
+
+// Requests gaps from a replay channel, which sends the replayed packets after
+// its response. The session is kept between requests.
+pub struct MitchReplayFiller {
+    addr: SocketAddr,
+    login: MitchLogin,
+    session: Option<Session>,
+    pub timeout: Duration,
+}
+
+impl MitchReplayFiller {
+    pub fn new(addr: SocketAddr, login: MitchLogin, timeout: Duration) -> Self {
+        Self {
+            addr,
+            login,
+            session: None,
+            timeout,
+        }
+    }
+
+    fn replay(
+        &mut self,
+        channel: ChannelId,
+        range: &Range<u64>,
+    ) -> io::Result<Vec<Block<Payload>>> {
+        let group = group(channel);
+        let sequence = u32::try_from(range.start)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "seqnum past u32"))?;
+        let count = (range.end - range.start) as u16;
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => self
+                .session
+                .insert(login(self.addr, &self.login, self.timeout)?),
+        };
+        session.set_timeout(self.timeout);
+        send(
+            session,
+            &Control::ReplayRequest {
+                group,
+                sequence,
+                count,
+            },
+        )?;
+        let mut accepted = false;
+        let mut blocks = Vec::new();
+        // The response says how many of them it replays
+        let (mut next, mut end) = (range.start, range.end);
+        while next < end {
+            let packet = session.next_packet()?;
+            let parsed = mitch::parse(&packet).map_err(|e| unexpected(e.to_string()))?;
+            if parsed.kind == PacketKind::Unsequenced {
+                for control in controls(&packet)? {
+                    match control {
+                        Control::ReplayResponse {
+                            group: g,
+                            sequence: s,
+                            count,
+                            status,
+                        } if g == group && s == sequence => {
+                            if status != ACCEPTED {
+                                return Err(io::Error::other(format!(
+                                    "replay refused: {}",
+                                    status as char
+                                )));
+                            }
+                            accepted = true;
+                            end = end.min(range.start + count as u64);
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            if !accepted || parsed.kind != PacketKind::Data || parsed.unit != group {
+                continue;
+            }
+            let start = parsed.header.seqnum;
+            next = next.max(start + parsed.header.n_messages as u64);
+            let header = BlockHeader {
+                channel,
+                ..parsed.header
+            };
+            blocks.push(Block::new(header, parsed.payload.into()));
+        }
+        Ok(blocks)
+    }
+}
+
+impl GapFiller<Block<Payload>> for MitchReplayFiller {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        let count = (range.end - range.start).min(u16::MAX as u64);
+        let range = range.start..range.start + count;
+        match self.replay(channel, &range) {
+            Ok(blocks) => {
+                debug!(
+                    channel,
+                    start = range.start,
+                    end = range.end,
+                    blocks = blocks.len(),
+                    "gap replayed"
+                );
+                Ok(blocks)
+            }
+            Err(e) => {
+                // Logged in again next time, replays of this request may
+                // still be coming on the old session
+                self.session = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+// Snapshots a group's books from a recovery channel: asks for all of them as
+// of now and takes everything up to snapshot complete
+pub struct MitchRecoverySource {
+    addr: SocketAddr,
+    login: MitchLogin,
+    pub timeout: Duration,
+}
+
+impl MitchRecoverySource {
+    pub fn new(addr: SocketAddr, login: MitchLogin, timeout: Duration) -> Self {
+        Self {
+            addr,
+            login,
+            timeout,
+        }
+    }
+}
+
+impl SnapshotSource<Block<Payload>> for MitchRecoverySource {
+    fn snapshot(&mut self, channel: ChannelId) -> io::Result<Snapshot<Block<Payload>>> {
+        let mut session = login(self.addr, &self.login, self.timeout)?;
+        session.set_timeout(self.timeout);
+        send(
+            &mut session,
+            &Control::SnapshotRequest {
+                sequence: 0,
+                segment: pitch::padded(""),
+                instrument: 0,
+            },
+        )?;
+        let mut blocks = Vec::new();
+        let sequence = loop {
+            let packet = session.next_packet()?;
+            let parsed = mitch::parse(&packet).map_err(|e| unexpected(e.to_string()))?;
+            // The books' messages, less any session messages among them
+            let mut payload = Vec::new();
+            let mut n_messages = 0;
+            let mut complete = None;
+            for msg in parsed.messages() {
+                let msg = msg.map_err(|e| unexpected(e.to_string()))?;
+                match mitch::parse_control(&msg) {
+                    Some(control) => match control.map_err(|e| unexpected(e.to_string()))? {
+                        Control::SnapshotResponse { status, .. } if status != ACCEPTED => {
+                            return Err(io::Error::other(format!(
+                                "snapshot refused: {}",
+                                status as char
+                            )));
+                        }
+                        Control::SnapshotComplete { sequence, .. } => {
+                            complete = Some(sequence);
+                            break;
+                        }
+                        _ => {}
+                    },
+                    None => {
+                        payload.push(2 + msg.body.len() as u8);
+                        payload.push(msg.kind);
+                        payload.extend_from_slice(msg.body);
+                        n_messages += 1;
+                    }
+                }
+            }
+            if n_messages > 0 {
+                let header = BlockHeader {
+                    channel,
+                    seqnum: parsed.header.seqnum,
+                    n_messages,
+                    ..Default::default()
+                };
+                blocks.push(Block::new(header, payload.into()));
+            }
+            if let Some(sequence) = complete {
+                break sequence;
+            }
+        };
+        send(&mut session, &Control::Logout)?;
+        debug!(channel, blocks = blocks.len(), "snapshotted");
+        Ok(Snapshot {
+            // The snapshot includes its sequence
+            seqnum: sequence as u64 + 1,
+            blocks,
+        })
+    }
+}
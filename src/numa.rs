@@ -0,0 +1,172 @@
+// Placement on one NUMA node of a multi-socket host, the one the receiving NIC
+// is attached to, so datagrams, the buffers they land in, the queues between
+// threads and the threads touching them don't cross sockets. A thread bound
+// to a node runs on its CPUs and allocates from its memory, and the threads
+// it spawns inherit both.
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NumaNode {
+    // The node of the NIC feeds are received on
+    Auto,
+    Node(usize),
+}
+
+impl FromStr for NumaNode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(NumaNode::Auto),
+            _ => s
+                .parse()
+                .map(NumaNode::Node)
+                .map_err(|_| format!("NUMA node {} is not auto or a number", s)),
+        }
+    }
+}
+
+// Nodes, their CPUs and the nodes of NICs as sysfs has them
+#[derive(Clone, Debug)]
+pub struct Topology {
+    root: PathBuf,
+}
+
+impl Topology {
+    pub fn system() -> Self {
+        Self::at("/sys")
+    }
+
+    // Read from a tree laid out like /sys
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn node_cpus(&self, node: usize) -> io::Result<Vec<usize>> {
+        let path = self
+            .root
+            .join(format!("devices/system/node/node{}/cpulist", node));
+        let list = fs::read_to_string(path)?;
+        parse_cpu_list(list.trim()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // None if the NIC isn't attached to a node, such as on single node hosts
+    // and virtual interfaces
+    pub fn interface_node(&self, interface: &str) -> io::Result<Option<usize>> {
+        let path = self
+            .root
+            .join(format!("class/net/{}/device/numa_node", interface));
+        let node = match fs::read_to_string(path) {
+            Ok(node) => node,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // -1 without one
+        Ok(node.trim().parse().ok())
+    }
+}
+
+// CPU ids of a list such as "0-3,8,10-11"
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+        let bad = || format!("bad CPU list {:?}", list);
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, end),
+            None => (range, range),
+        };
+        let start: usize = start.parse().map_err(|_| bad())?;
+        let end: usize = end.parse().map_err(|_| bad())?;
+        if end < start {
+            return Err(bad());
+        }
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+// Run the calling thread on `cpus` and prefer `node`'s memory for what
+// it allocates from now on
+#[cfg(target_os = "linux")]
+pub fn bind(node: usize, cpus: &[usize]) -> io::Result<()> {
+    const MPOL_PREFERRED: libc::c_long = 1;
+
+    // SAFETY: all zeroes is an empty cpu_set_t
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        // SAFETY: CPU_SET ignores CPUs past the end of the set
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+    // SAFETY: set outlives the call
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let bits = libc::c_ulong::BITS as usize;
+    let mut mask = vec![0 as libc::c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    // SAFETY: the kernel reads maxnode - 1 bits, all in mask
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind(_node: usize, _cpus: &[usize]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Name of the interface with address `addr`
+#[cfg(target_os = "linux")]
+pub fn interface_with_addr(addr: Ipv4Addr) -> io::Result<Option<String>> {
+    let mut addrs = std::ptr::null_mut();
+    // SAFETY: addrs is freed below
+    if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut name = None;
+    let mut next = addrs;
+    while !next.is_null() && name.is_none() {
+        // SAFETY: getifaddrs returned a list of valid entries
+        let ifa = unsafe { &*next };
+        next = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: ifa_addr points at a sockaddr of the family it says
+        let found = unsafe {
+            match (*ifa.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sin = &*ifa.ifa_addr.cast::<libc::sockaddr_in>();
+                    Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)) == addr
+                }
+                _ => false,
+            }
+        };
+        if found {
+            // SAFETY: ifa_name is a nul terminated string in the list
+            let ifa_name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+            name = Some(ifa_name.to_string_lossy().into_owned());
+        }
+    }
+    // SAFETY: nothing points into the list any more
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn interface_with_addr(_addr: Ipv4Addr) -> io::Result<Option<String>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
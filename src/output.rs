@@ -0,0 +1,379 @@
+use crate::conflate::BoxedConflationKey;
+use crate::metrics::Metrics;
+use crate::spool::Spool;
+use crate::{ChannelId, GapReason, Sequenced, SequencedEvent};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use tracing::{error, warn};
+
+// What to do when the consumer falls a full output queue behind
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputPolicy {
+    // Wait for the consumer, which in turn stops reading feeds. Lossless.
+    #[default]
+    Block,
+    // Hold up to another queue's worth of events, then replace the oldest of
+    // their blocks with a gap
+    DropOldest,
+}
+
+impl FromStr for OutputPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OutputPolicy::Block),
+            "drop-oldest" => Ok(OutputPolicy::DropOldest),
+            _ => Err(format!("unknown output policy {}", s)),
+        }
+    }
+}
+
+// Bound on the queue between the sequencer and its consumer. None is
+// unbounded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OutputLimit {
+    pub capacity: Option<usize>,
+    pub policy: OutputPolicy,
+}
+
+// The consumer dropped its receiver
+#[derive(Debug)]
+pub(crate) struct HungUp;
+
+// The sequencer's end of the output queue
+pub(crate) struct Output<T> {
+    sender: Sender<SequencedEvent<T>>,
+    limit: OutputLimit,
+    // Events that did not fit in the channel yet, oldest first
+    backlog: RefCell<VecDeque<SequencedEvent<T>>>,
+    // Takes the place of the backlog when spilling to disk
+    spool: Option<RefCell<Spool<T>>>,
+    metrics: Arc<Metrics>,
+    // Set once the consumer is gone, after which events are discarded
+    hung_up: Cell<bool>,
+    conflation: Option<Conflated<T>>,
+}
+
+// Where the backlog's block of each key is. Positions count every event
+// ever backlogged, so they stay put as the backlog is flushed, and are
+// checked before use since dropping the oldest blocks can move them.
+struct Conflated<T> {
+    key: BoxedConflationKey<T>,
+    positions: RefCell<HashMap<u64, u64>>,
+    // Position of the front of the backlog
+    front: Cell<u64>,
+    // Blocks replaced since take_conflated()
+    replaced: Cell<u64>,
+}
+
+impl<T: Sequenced> Output<T> {
+    pub(crate) fn new(
+        limit: OutputLimit,
+        spool: Option<Spool<T>>,
+        metrics: Arc<Metrics>,
+    ) -> (Self, Receiver<SequencedEvent<T>>) {
+        let (sender, receiver) = match limit.capacity {
+            Some(capacity) => bounded(capacity.max(1)),
+            None => unbounded(),
+        };
+        let output = Self {
+            sender,
+            limit,
+            backlog: RefCell::default(),
+            spool: spool.map(RefCell::new),
+            metrics,
+            hung_up: Cell::new(false),
+            conflation: None,
+        };
+        (output, receiver)
+    }
+
+    // Queue what doesn't fit in the channel without waiting, each block
+    // replacing the one backlogged with the same key. Past the capacity the
+    // oldest blocks are dropped as under DropOldest.
+    pub(crate) fn set_conflation(&mut self, key: BoxedConflationKey<T>) {
+        self.conflation = Some(Conflated {
+            key,
+            positions: RefCell::default(),
+            front: Cell::new(0),
+            replaced: Cell::new(0),
+        });
+    }
+
+    // Blocks replaced by conflation since the last call
+    pub(crate) fn take_conflated(&self) -> u64 {
+        self.conflation.as_ref().map_or(0, |c| c.replaced.take())
+    }
+
+    pub(crate) fn send(&self, event: SequencedEvent<T>) {
+        if !self.hung_up.get() {
+            let sent = self.try_send(event);
+            self.check(sent);
+        }
+    }
+
+    fn check(&self, sent: Result<(), HungUp>) {
+        if sent.is_err() {
+            error!("consumer hung up, discarding output");
+            self.hung_up.set(true);
+        }
+    }
+
+    // Whether the consumer has dropped its receiver
+    pub(crate) fn hung_up(&self) -> bool {
+        self.hung_up.get()
+    }
+
+    // send() that fails instead of discarding once the consumer is gone
+    pub(crate) fn try_send(&self, event: SequencedEvent<T>) -> Result<(), HungUp> {
+        if let Some(spool) = &self.spool {
+            self.try_flush()?;
+            let mut spool = spool.borrow_mut();
+            let event = match spool.is_empty() {
+                true => match self.sender.try_send(event) {
+                    Ok(()) => None,
+                    Err(TrySendError::Full(event)) => Some(event),
+                    Err(TrySendError::Disconnected(_)) => return Err(HungUp),
+                },
+                false => Some(event),
+            };
+            if let Some(event) = event {
+                if spool.push(event) {
+                    self.metrics.spilled.fetch_add(1, Relaxed);
+                }
+            }
+            drop(spool);
+            self.record_depth();
+            return Ok(());
+        }
+        match self.limit.policy {
+            OutputPolicy::Block if self.conflation.is_none() => {
+                self.sender.send(event).map_err(|_| HungUp)?
+            }
+            _ => {
+                self.try_flush()?;
+                let mut backlog = self.backlog.borrow_mut();
+                if !backlog.is_empty() {
+                    self.backlog(&mut backlog, event);
+                } else {
+                    match self.sender.try_send(event) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(event)) => self.backlog(&mut backlog, event),
+                        Err(TrySendError::Disconnected(_)) => return Err(HungUp),
+                    }
+                }
+                let capacity = self.limit.capacity.unwrap_or(usize::MAX);
+                while backlog.len() > capacity && self.drop_oldest(&mut backlog) {}
+            }
+        }
+        self.record_depth();
+        Ok(())
+    }
+
+    fn backlog(&self, backlog: &mut VecDeque<SequencedEvent<T>>, event: SequencedEvent<T>) {
+        let conflation = match &self.conflation {
+            Some(conflation) => conflation,
+            None => return backlog.push_back(event),
+        };
+        let key = match &event {
+            SequencedEvent::Block(b) => conflation.key.key(b),
+            _ => None,
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                // Later blocks go after it, not in place of blocks before it
+                conflation.positions.borrow_mut().clear();
+                return backlog.push_back(event);
+            }
+        };
+        let front = conflation.front.get();
+        let mut positions = conflation.positions.borrow_mut();
+        let queued = positions
+            .get(&key)
+            .and_then(|&at| at.checked_sub(front))
+            .and_then(|i| backlog.get_mut(i as usize));
+        if let Some(SequencedEvent::Block(queued)) = queued {
+            if conflation.key.key(queued) == Some(key) {
+                let n = queued.n_messages() as u64;
+                self.metrics.lagged.fetch_add(n, Relaxed);
+                self.metrics.dropped.fetch_add(n, Relaxed);
+                conflation.replaced.set(conflation.replaced.get() + 1);
+                if let SequencedEvent::Block(b) = event {
+                    *queued = b;
+                }
+                return;
+            }
+        }
+        positions.insert(key, front + backlog.len() as u64);
+        backlog.push_back(event);
+    }
+
+    // Drop the backlog, so dropping the output doesn't wait on the consumer
+    pub(crate) fn discard(&self) {
+        let mut backlog = self.backlog.borrow_mut();
+        if let Some(conflation) = &self.conflation {
+            conflation
+                .front
+                .set(conflation.front.get() + backlog.len() as u64);
+        }
+        backlog.clear();
+    }
+
+    // Move what fits of the backlog to the channel
+    pub(crate) fn flush(&self) {
+        if !self.hung_up.get() {
+            let flushed = self.try_flush();
+            self.check(flushed);
+        }
+    }
+
+    pub(crate) fn try_flush(&self) -> Result<(), HungUp> {
+        if let Some(spool) = &self.spool {
+            let mut spool = spool.borrow_mut();
+            // The only sender, so there is room until the channel is full
+            while !spool.is_empty() && !self.sender.is_full() {
+                match spool.pop() {
+                    Ok(Some(event)) => self.sender.send(event).map_err(|_| HungUp)?,
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!(error = %e, "failed to read spilled output");
+                        break;
+                    }
+                }
+            }
+            drop(spool);
+            self.record_depth();
+            return Ok(());
+        }
+        let mut backlog = self.backlog.borrow_mut();
+        while let Some(event) = backlog.pop_front() {
+            match self.sender.try_send(event) {
+                Ok(()) => {
+                    if let Some(conflation) = &self.conflation {
+                        conflation.front.set(conflation.front.get() + 1);
+                    }
+                }
+                Err(TrySendError::Full(event)) => {
+                    backlog.push_front(event);
+                    break;
+                }
+                Err(TrySendError::Disconnected(_)) => return Err(HungUp),
+            }
+        }
+        drop(backlog);
+        self.record_depth();
+        Ok(())
+    }
+
+    // Events waiting for the consumer
+    pub(crate) fn depth(&self) -> usize {
+        let spooled = self.spool.as_ref().map_or(0, |s| s.borrow().len());
+        self.sender.len() + self.backlog.borrow().len() + spooled
+    }
+
+    fn record_depth(&self) {
+        let depth = self.depth();
+        self.metrics.output_depth.store(depth, Relaxed);
+        self.metrics.max_output_depth.fetch_max(depth, Relaxed);
+        if let Some(spool) = &self.spool {
+            let spilled = spool.borrow().spilled();
+            self.metrics.spill_depth.store(spilled, Relaxed);
+        }
+    }
+
+    // Replace the oldest block in the backlog with a gap, merged into the gap
+    // its channel's events end with if it is right before it. Returns false
+    // if there are no blocks to drop.
+    fn drop_oldest(&self, backlog: &mut VecDeque<SequencedEvent<T>>) -> bool {
+        let i = match backlog
+            .iter()
+            .position(|e| matches!(e, SequencedEvent::Block(_)))
+        {
+            Some(i) => i,
+            // Nothing but small events, which are kept
+            None => return false,
+        };
+        let (channel, from, to) = match &backlog[i] {
+            SequencedEvent::Block(b) => {
+                (b.channel(), b.seqnum(), b.seqnum() + b.n_messages() as u64)
+            }
+            _ => unreachable!(),
+        };
+        warn!(
+            channel,
+            seqnum = from,
+            "consumer lagging, dropping oldest block"
+        );
+        self.metrics.lagged.fetch_add(to - from, Relaxed);
+        self.metrics.dropped.fetch_add(to - from, Relaxed);
+        let prev = backlog
+            .range_mut(..i)
+            .rev()
+            .find(|e| event_channel(e) == Some(channel));
+        if let Some(SequencedEvent::Gap {
+            to: end,
+            reason: GapReason::Lagged,
+            ..
+        }) = prev
+        {
+            if *end == from {
+                *end = to;
+                backlog.remove(i);
+                return true;
+            }
+        }
+        backlog[i] = SequencedEvent::Gap {
+            channel,
+            from,
+            to,
+            reason: GapReason::Lagged,
+        };
+        true
+    }
+}
+
+fn event_channel<T: Sequenced>(event: &SequencedEvent<T>) -> Option<ChannelId> {
+    match event {
+        SequencedEvent::Block(b) => Some(b.channel()),
+        SequencedEvent::Gap { channel, .. }
+        | SequencedEvent::SessionReset { channel, .. }
+        | SequencedEvent::Resynced { channel, .. }
+        | SequencedEvent::EndOfSession { channel, .. }
+        | SequencedEvent::SessionsDiverged { channel, .. }
+        | SequencedEvent::SessionsAgreed { channel, .. } => Some(*channel),
+        SequencedEvent::FeedDown { .. } | SequencedEvent::FeedUp { .. } => None,
+    }
+}
+
+impl<T> Drop for Output<T> {
+    // Nothing is lost on shutdown, the consumer gets the whole backlog
+    fn drop(&mut self) {
+        for event in self.backlog.get_mut().drain(..) {
+            if self.sender.send(event).is_err() {
+                return;
+            }
+        }
+        if let Some(spool) = &mut self.spool {
+            let spool = spool.get_mut();
+            loop {
+                let event = match spool.pop() {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!(error = %e, "failed to read spilled output");
+                        break;
+                    }
+                };
+                if self.sender.send(event).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
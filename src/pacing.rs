@@ -0,0 +1,93 @@
+// Pacing of a published stream, so a flush of blocks released at once, such
+// as after a gap is recovered, goes out at a steady rate instead of as a
+// microburst that overflows switch buffers and consumers' receive queues.
+//
+// Each rate is a virtual schedule (GCRA): every packet moves the time the
+// stream is "due" on by its cost at the rate, and a packet is held back
+// until the stream is no more than `burst` ahead of the clock. So up to
+// `burst` worth of the rate goes back to back after a quiet spell, and no
+// more than the rate over any longer span.
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Left of a wait that is spun rather than slept, since sleeps overshoot by
+// about this much
+const SPIN: Duration = Duration::from_micros(100);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Pacing {
+    // None for unlimited
+    pub packets_per_ms: Option<u64>,
+    pub bytes_per_ms: Option<u64>,
+    // How far ahead of either rate the stream may get
+    pub burst: Duration,
+}
+
+impl Pacing {
+    pub fn is_off(&self) -> bool {
+        self.packets_per_ms.is_none() && self.bytes_per_ms.is_none()
+    }
+}
+
+pub struct Pacer {
+    pacing: Pacing,
+    // When each rate is due, None until the first packet
+    packets_due: Option<Instant>,
+    bytes_due: Option<Instant>,
+    // Total time packets were held back
+    held: Duration,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            packets_due: None,
+            bytes_due: None,
+            held: Duration::ZERO,
+        }
+    }
+
+    // How long to hold a packet of `len` bytes back at `now`, counting it as
+    // sent once that is up
+    pub fn delay(&mut self, len: usize, now: Instant) -> Duration {
+        let burst = self.pacing.burst;
+        let mut delay = Duration::ZERO;
+        let rates = [
+            (&mut self.packets_due, self.pacing.packets_per_ms, 1),
+            (&mut self.bytes_due, self.pacing.bytes_per_ms, len as u64),
+        ];
+        for (due, per_ms, cost) in rates {
+            let per_ms = match per_ms {
+                Some(per_ms) if per_ms > 0 => per_ms,
+                _ => continue,
+            };
+            let at = due.map_or(now, |due| due.max(now));
+            delay = delay.max((at - now).saturating_sub(burst));
+            let nanos = (cost as u128 * 1_000_000 / per_ms as u128) as u64;
+            *due = Some(at + Duration::from_nanos(nanos));
+        }
+        self.held += delay;
+        delay
+    }
+
+    // Block until a packet of `len` bytes may be sent
+    pub fn wait(&mut self, len: usize) {
+        let now = Instant::now();
+        let delay = self.delay(len, now);
+        if delay.is_zero() {
+            return;
+        }
+        let until = now + delay;
+        if delay > SPIN {
+            thread::sleep(delay - SPIN);
+        }
+        while Instant::now() < until {
+            std::hint::spin_loop();
+        }
+    }
+
+    pub fn held(&self) -> Duration {
+        self.held
+    }
+}
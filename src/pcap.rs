@@ -0,0 +1,591 @@
+// pcapng capture format, https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html
+//
+// UDP datagrams are wrapped in synthesized Ethernet/IPv4/UDP headers so
+// captures open in standard tools. Each feed is its own interface.
+use crate::metrics::FeedId;
+use crate::protocol::Protocol;
+use crate::{Block, ChannelId, Payload};
+use crossbeam_channel::Sender;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SHB: u32 = 0x0A0D_0D0A;
+const IDB: u32 = 0x0000_0001;
+const EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+pub const LINKTYPE_ETHERNET: u16 = 1;
+const OPT_END: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+// Timestamps are in nanoseconds
+const TSRESOL_NS: u8 = 9;
+
+pub const ETH_LEN: usize = 14;
+pub const IPV4_LEN: usize = 20;
+pub const UDP_LEN: usize = 8;
+pub const FRAME_HEADER_LEN: usize = ETH_LEN + IPV4_LEN + UDP_LEN;
+
+pub struct PcapngWriter<W: Write> {
+    w: W,
+    n_interfaces: u32,
+    frame: Vec<u8>,
+}
+
+fn pad4(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = 0_u32;
+    for pair in header.chunks(2) {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// Ethernet/IPv4/UDP frame carrying `payload` from `src` to `dst`
+pub fn udp_frame(buf: &mut Vec<u8>, src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) {
+    buf.clear();
+    let dst_ip = dst.ip().octets();
+    // Ethernet: multicast MACs map the low 23 bits of the group
+    buf.extend_from_slice(&[0x01, 0x00, 0x5e, dst_ip[1] & 0x7f, dst_ip[2], dst_ip[3]]);
+    buf.extend_from_slice(&[0; 6]);
+    buf.extend_from_slice(&0x0800_u16.to_be_bytes());
+    // IPv4
+    let ip_start = buf.len();
+    let total_len = (IPV4_LEN + UDP_LEN + payload.len()) as u16;
+    buf.extend_from_slice(&[0x45, 0]);
+    buf.extend_from_slice(&total_len.to_be_bytes());
+    buf.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+    buf.extend_from_slice(&src.ip().octets());
+    buf.extend_from_slice(&dst_ip);
+    let checksum = ipv4_checksum(&buf[ip_start..]);
+    buf[ip_start + 10..ip_start + 12].copy_from_slice(&checksum.to_be_bytes());
+    // UDP, checksum 0 means none
+    buf.extend_from_slice(&src.port().to_be_bytes());
+    buf.extend_from_slice(&dst.port().to_be_bytes());
+    buf.extend_from_slice(&((UDP_LEN + payload.len()) as u16).to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(payload);
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        // Section header block with no options and unknown section length
+        let len = 28_u32;
+        w.write_all(&SHB.to_le_bytes())?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&BYTE_ORDER_MAGIC.to_le_bytes())?;
+        w.write_all(&1_u16.to_le_bytes())?;
+        w.write_all(&0_u16.to_le_bytes())?;
+        w.write_all(&(-1_i64).to_le_bytes())?;
+        w.write_all(&len.to_le_bytes())?;
+        Ok(Self {
+            w,
+            n_interfaces: 0,
+            frame: Vec::new(),
+        })
+    }
+
+    fn write_interface(&mut self) -> io::Result<()> {
+        // Header, linktype, reserved, snaplen, if_tsresol option, end option, trailer
+        let len = 8 + 2 + 2 + 4 + 8 + 4 + 4_u32;
+        self.w.write_all(&IDB.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        self.w.write_all(&0_u16.to_le_bytes())?;
+        self.w.write_all(&0_u32.to_le_bytes())?;
+        self.w.write_all(&OPT_IF_TSRESOL.to_le_bytes())?;
+        self.w.write_all(&1_u16.to_le_bytes())?;
+        self.w.write_all(&[TSRESOL_NS, 0, 0, 0])?;
+        self.w.write_all(&OPT_END.to_le_bytes())?;
+        self.w.write_all(&0_u16.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.n_interfaces += 1;
+        Ok(())
+    }
+
+    // Write an Ethernet frame captured on `interface` at `ts`
+    pub fn write_frame(&mut self, interface: u32, ts: SystemTime, frame: &[u8]) -> io::Result<()> {
+        while self.n_interfaces <= interface {
+            self.write_interface()?;
+        }
+        let ns = ts.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let padding = pad4(frame.len());
+        let len = (32 + frame.len() + padding) as u32;
+        self.w.write_all(&EPB.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&interface.to_le_bytes())?;
+        self.w.write_all(&((ns >> 32) as u32).to_le_bytes())?;
+        self.w.write_all(&(ns as u32).to_le_bytes())?;
+        self.w.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.w.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.w.write_all(frame)?;
+        self.w.write_all(&[0; 3][..padding])?;
+        self.w.write_all(&len.to_le_bytes())
+    }
+
+    pub fn write_udp(
+        &mut self,
+        interface: u32,
+        ts: SystemTime,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut frame = std::mem::take(&mut self.frame);
+        udp_frame(&mut frame, src, dst, payload);
+        let res = self.write_frame(interface, ts, &frame);
+        self.frame = frame;
+        res
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+const PCAP_MAGIC_US: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+const SPB: u32 = 0x0000_0003;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+
+// A captured link layer frame
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub interface: u32,
+    pub linktype: u16,
+    pub ts: SystemTime,
+    pub data: Vec<u8>,
+}
+
+struct Interface {
+    linktype: u16,
+    // Timestamp units per second
+    tsresol: u64,
+}
+
+enum Format {
+    Pcap { linktype: u16, tsresol: u64 },
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+// Reads classic pcap and pcapng captures of either byte order
+pub struct PcapReader<R: Read> {
+    r: R,
+    big_endian: bool,
+    format: Format,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let mut magic = [0_u8; 4];
+        r.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == SHB {
+            let mut reader = Self {
+                r,
+                big_endian: false,
+                format: Format::Pcapng {
+                    interfaces: Vec::new(),
+                },
+            };
+            reader.read_section_header()?;
+            return Ok(reader);
+        }
+
+        let (big_endian, tsresol) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_US, _) => (false, 1_000_000),
+            (PCAP_MAGIC_NS, _) => (false, 1_000_000_000),
+            (_, PCAP_MAGIC_US) => (true, 1_000_000),
+            (_, PCAP_MAGIC_NS) => (true, 1_000_000_000),
+            _ => return Err(invalid("not a pcap or pcapng file")),
+        };
+        let mut header = [0_u8; 20];
+        r.read_exact(&mut header)?;
+        let mut reader = Self {
+            r,
+            big_endian,
+            format: Format::Pcap {
+                linktype: 0,
+                tsresol,
+            },
+        };
+        let linktype = reader.u32(&header[16..20]) as u16;
+        reader.format = Format::Pcap { linktype, tsresol };
+        Ok(reader)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    // Called after the block type of a section header block is read
+    fn read_section_header(&mut self) -> io::Result<()> {
+        let mut head = [0_u8; 8];
+        self.r.read_exact(&mut head)?;
+        self.big_endian = match u32::from_le_bytes(head[4..8].try_into().unwrap()) {
+            BYTE_ORDER_MAGIC => false,
+            m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid("bad pcapng byte order magic")),
+        };
+        let len = self.u32(&head[0..4]) as usize;
+        if len < 12 {
+            return Err(invalid("short section header block"));
+        }
+        let mut rest = vec![0_u8; len - 12];
+        self.r.read_exact(&mut rest)?;
+        self.format = Format::Pcapng {
+            interfaces: Vec::new(),
+        };
+        Ok(())
+    }
+
+    fn ts(secs: u64, frac: u64, tsresol: u64) -> SystemTime {
+        let ns = frac as u128 * 1_000_000_000 / tsresol as u128;
+        UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_nanos(ns as u64)
+    }
+
+    // Next frame, or None at end of file
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        match self.format {
+            Format::Pcap { linktype, tsresol } => {
+                let mut header = [0_u8; 16];
+                if !read_or_eof(&mut self.r, &mut header)? {
+                    return Ok(None);
+                }
+                let secs = self.u32(&header[0..4]) as u64;
+                let frac = self.u32(&header[4..8]) as u64;
+                let caplen = self.u32(&header[8..12]) as usize;
+                let mut data = vec![0_u8; caplen];
+                self.r.read_exact(&mut data)?;
+                Ok(Some(Frame {
+                    interface: 0,
+                    linktype,
+                    ts: Self::ts(secs, frac, tsresol),
+                    data,
+                }))
+            }
+            Format::Pcapng { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    fn next_pcapng_frame(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            let mut head = [0_u8; 4];
+            if !read_or_eof(&mut self.r, &mut head)? {
+                return Ok(None);
+            }
+            if u32::from_le_bytes(head) == SHB {
+                self.read_section_header()?;
+                continue;
+            }
+            let block_type = self.u32(&head);
+            let mut len = [0_u8; 4];
+            self.r.read_exact(&mut len)?;
+            let len = self.u32(&len) as usize;
+            if len < 12 {
+                return Err(invalid("short pcapng block"));
+            }
+            // Body plus trailing length
+            let mut body = vec![0_u8; len - 8];
+            self.r.read_exact(&mut body)?;
+            let body = &body[..len - 12];
+            match block_type {
+                IDB => {
+                    if body.len() < 8 {
+                        return Err(invalid("short interface description block"));
+                    }
+                    let linktype = self.u16(&body[0..2]);
+                    let mut tsresol = 1_000_000;
+                    let mut opts = &body[8..];
+                    while opts.len() >= 4 {
+                        let code = self.u16(&opts[0..2]);
+                        let opt_len = self.u16(&opts[2..4]) as usize;
+                        if code == OPT_END || opts.len() < 4 + opt_len {
+                            break;
+                        }
+                        if code == OPT_IF_TSRESOL && opt_len >= 1 {
+                            let r = opts[4];
+                            tsresol = if r & 0x80 == 0 {
+                                10_u64.pow(r as u32)
+                            } else {
+                                1 << (r & 0x7f)
+                            };
+                        }
+                        opts = &opts[4 + opt_len + pad4(opt_len)..];
+                    }
+                    if let Format::Pcapng { interfaces } = &mut self.format {
+                        interfaces.push(Interface { linktype, tsresol });
+                    }
+                }
+                EPB => {
+                    if body.len() < 20 {
+                        return Err(invalid("short enhanced packet block"));
+                    }
+                    let interface = self.u32(&body[0..4]);
+                    let ts = ((self.u32(&body[4..8]) as u64) << 32) | self.u32(&body[8..12]) as u64;
+                    let caplen = (self.u32(&body[12..16]) as usize).min(body.len() - 20);
+                    let (linktype, tsresol) = match self.interface(interface) {
+                        Some(i) => i,
+                        None => return Err(invalid("packet on undeclared interface")),
+                    };
+                    return Ok(Some(Frame {
+                        interface,
+                        linktype,
+                        ts: Self::ts(ts / tsresol, ts % tsresol, tsresol),
+                        data: body[20..20 + caplen].to_vec(),
+                    }));
+                }
+                SPB => {
+                    if body.len() < 4 {
+                        return Err(invalid("short simple packet block"));
+                    }
+                    let (linktype, _) = match self.interface(0) {
+                        Some(i) => i,
+                        None => return Err(invalid("packet on undeclared interface")),
+                    };
+                    let orig = self.u32(&body[0..4]) as usize;
+                    let data = body[4..4 + orig.min(body.len() - 4)].to_vec();
+                    return Ok(Some(Frame {
+                        interface: 0,
+                        linktype,
+                        // Simple packet blocks have no timestamp
+                        ts: UNIX_EPOCH,
+                        data,
+                    }));
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn interface(&self, id: u32) -> Option<(u16, u64)> {
+        match &self.format {
+            Format::Pcapng { interfaces } => {
+                interfaces.get(id as usize).map(|i| (i.linktype, i.tsresol))
+            }
+            Format::Pcap { .. } => None,
+        }
+    }
+}
+
+// read_exact that returns false on a clean end of file
+fn read_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+// Source, destination and payload of an IPv4 UDP frame
+pub fn parse_udp(linktype: u16, frame: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    let ip = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            // 802.1Q VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            }
+            if ethertype != 0x0800 {
+                return None;
+            }
+            frame.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => {
+            if frame.len() < 16 || frame[14..16] != [0x08, 0x00] {
+                return None;
+            }
+            &frame[16..]
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 => frame,
+        _ => return None,
+    };
+    if ip.len() < IPV4_LEN || ip[0] >> 4 != 4 || ip[9] != 17 {
+        return None;
+    }
+    let ihl = (ip[0] & 0x0f) as usize * 4;
+    let total_len = (u16::from_be_bytes([ip[2], ip[3]]) as usize).min(ip.len());
+    let udp = ip.get(ihl..total_len)?;
+    if udp.len() < UDP_LEN {
+        return None;
+    }
+    let src_ip = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let dst_ip = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+    let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+    let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+    let udp_len = (u16::from_be_bytes([udp[4], udp[5]]) as usize).clamp(UDP_LEN, udp.len());
+    Some((
+        SocketAddrV4::new(src_ip, src_port),
+        SocketAddrV4::new(dst_ip, dst_port),
+        &udp[UDP_LEN..udp_len],
+    ))
+}
+
+pub type Replayed = (FeedId, SystemTime, Block<Payload>);
+
+// Replay speed of a PcapSource
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    // Original inter-packet timing divided by the factor
+    Multiplier(f64),
+    AsFastAsPossible,
+}
+
+struct ReplayFeed {
+    addr: SocketAddrV4,
+    channel: Option<ChannelId>,
+    protocol: Option<Protocol>,
+}
+
+// Replays the UDP payloads of a capture into feed queues. A datagram goes to
+// the feed whose address matches its destination, where 0.0.0.0 matches any
+// group on that port.
+pub struct PcapSource<R: Read> {
+    reader: PcapReader<R>,
+    // Feeds without a channel or protocol of their own use `channel` and
+    // `protocol`
+    feeds: Vec<ReplayFeed>,
+    protocol: Protocol,
+    channel: ChannelId,
+    speed: Speed,
+}
+
+impl PcapSource<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapSource<R> {
+    pub fn new(r: R) -> io::Result<Self> {
+        Ok(Self {
+            reader: PcapReader::new(r)?,
+            feeds: Vec::new(),
+            protocol: Protocol::Raw,
+            channel: 0,
+            speed: Speed::Multiplier(1.0),
+        })
+    }
+
+    // Feed id of the next added address is its index
+    pub fn add_feed(&mut self, addr: SocketAddrV4) {
+        self.feeds.push(ReplayFeed {
+            addr,
+            channel: None,
+            protocol: None,
+        });
+    }
+
+    pub fn add_feed_in(&mut self, addr: SocketAddrV4, channel: ChannelId) {
+        self.feeds.push(ReplayFeed {
+            addr,
+            channel: Some(channel),
+            protocol: None,
+        });
+    }
+
+    // Decode `feed` with `protocol` instead of the source's
+    pub fn set_feed_protocol(&mut self, feed: FeedId, protocol: Protocol) {
+        self.feeds[feed].protocol = Some(protocol);
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    pub fn set_channel(&mut self, channel: ChannelId) {
+        self.channel = channel;
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    fn feed(&self, dst: SocketAddrV4) -> Option<FeedId> {
+        self.feeds.iter().position(|ReplayFeed { addr: f, .. }| {
+            f.port() == dst.port() && (f.ip().is_unspecified() || f.ip() == dst.ip())
+        })
+    }
+
+    // Next matching datagram as (feed, capture timestamp, block)
+    pub fn next_block(&mut self) -> io::Result<Option<Replayed>> {
+        while let Some(frame) = self.reader.next_frame()? {
+            let (_, dst, payload) = match parse_udp(frame.linktype, &frame.data) {
+                Some(udp) => udp,
+                None => continue,
+            };
+            let feed = match self.feed(dst) {
+                Some(f) => f,
+                None => continue,
+            };
+            let ReplayFeed {
+                channel, protocol, ..
+            } = &self.feeds[feed];
+            let protocol = protocol.as_ref().unwrap_or(&self.protocol);
+            if let Some((mut header, payload)) = protocol.parse(payload) {
+                let channel = channel.unwrap_or(self.channel);
+                header.channel = protocol.channel(channel, &header);
+                return Ok(Some((feed, frame.ts, Block::new(header, payload.into()))));
+            }
+        }
+        Ok(None)
+    }
+
+    // Send every matching datagram to senders[feed], sleeping to reproduce
+    // the capture's timing
+    pub fn run(mut self, senders: &[Sender<Block<Payload>>]) -> io::Result<()> {
+        let mut start: Option<(SystemTime, Instant)> = None;
+        while let Some((feed, ts, block)) = self.next_block()? {
+            if let Speed::Multiplier(multiplier) = self.speed {
+                let (first_ts, started) = *start.get_or_insert((ts, Instant::now()));
+                let offset = ts.duration_since(first_ts).unwrap_or_default();
+                let due = started + offset.div_f64(multiplier);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+            if let Some(s) = senders.get(feed) {
+                if s.send(block).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
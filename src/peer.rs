@@ -0,0 +1,288 @@
+// Instances on different network paths miss different blocks, so they serve
+// each other what they missed before anyone asks the exchange, which is slower
+// to answer. Peers speak the retransmission protocol from memory: a peer is
+// asked with a TcpGapFiller and a RetransmitServer can stand in for one.
+use crate::gapfill::{GapFiller, TcpGapFiller};
+use crate::sink::Sink;
+use crate::{Block, ChannelId, Payload, Session};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// Blocks of each channel kept for peers by default
+pub const PEER_BLOCKS: usize = 65_536;
+// Most seqnums served per request, like RetransmitConfig::max_range
+const MAX_RANGE: u64 = 100_000;
+// Peer connections idle for this long are closed
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Cache {
+    max_blocks: usize,
+    // In seqnum order
+    channels: HashMap<ChannelId, VecDeque<Block<Payload>>>,
+}
+
+// The latest blocks of each channel sequenced, kept as a sink for PeerServer
+// to serve. Clones share the blocks.
+#[derive(Clone, Default)]
+pub struct PeerCache {
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl PeerCache {
+    pub fn new(max_blocks: usize) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(Cache {
+                max_blocks,
+                channels: HashMap::new(),
+            })),
+        }
+    }
+
+    // Blocks of `channel` with seqnums in `range`
+    pub fn get(&self, channel: ChannelId, range: Range<u64>) -> Vec<Block<Payload>> {
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let blocks = match cache.channels.get(&channel) {
+            Some(blocks) => blocks,
+            None => return Vec::new(),
+        };
+        // The block before the first one past start may contain start
+        let i = blocks.partition_point(|b| b.header.seqnum <= range.start);
+        blocks
+            .range(i.saturating_sub(1)..)
+            .skip_while(|b| {
+                b.header.seqnum.saturating_add(b.header.n_messages as u64) <= range.start
+            })
+            .take_while(|b| b.header.seqnum < range.end)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.channels.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&self, channel: ChannelId) {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.channels.remove(&channel);
+    }
+}
+
+impl Sink for PeerCache {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        // Pooled buffers go back to the pool instead of waiting here
+        let payload = if block.payload.is_pooled() {
+            Payload::from(&block.payload[..])
+        } else {
+            block.payload.clone()
+        };
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let max_blocks = cache.max_blocks;
+        let blocks = cache.channels.entry(block.header.channel).or_default();
+        if blocks.len() >= max_blocks {
+            blocks.pop_front();
+        }
+        if max_blocks > 0 {
+            blocks.push_back(Block::new(block.header.clone(), payload));
+        }
+        Ok(())
+    }
+
+    // Seqnums start over, so the old ones can't be served
+    fn on_reset(&mut self, channel: ChannelId, _session: Session, _seqnum: u64) -> io::Result<()> {
+        self.clear(channel);
+        Ok(())
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        _seqnum: u64,
+        _snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.clear(channel);
+        Ok(())
+    }
+}
+
+// Serves a PeerCache to peers' TcpGapFillers, one thread per connection.
+// Requests and responses are a RetransmitServer's.
+pub struct PeerServer {
+    listener: TcpListener,
+    cache: PeerCache,
+}
+
+impl PeerServer {
+    pub fn bind(addr: SocketAddr, cache: PeerCache) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            cache,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Accept peers until the listener fails
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+            let cache = self.cache.clone();
+            thread::Builder::new()
+                .name(format!("peer {}", peer))
+                .spawn(move || {
+                    if let Err(e) = serve(stream, &cache) {
+                        warn!(%peer, error = %e, "peer failed");
+                    }
+                })?;
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("peers".to_string())
+            .spawn(move || self.run())
+    }
+}
+
+fn serve(mut stream: TcpStream, cache: &PeerCache) -> io::Result<()> {
+    stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+    stream.set_write_timeout(Some(PEER_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    loop {
+        let mut req = [0_u8; 20];
+        match stream.read_exact(&mut req) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let channel = u32::from_be_bytes(req[0..4].try_into().unwrap());
+        let start = u64::from_be_bytes(req[4..12].try_into().unwrap());
+        let end = u64::from_be_bytes(req[12..20].try_into().unwrap());
+        let end = end.min(start.saturating_add(MAX_RANGE));
+
+        let mut w = io::BufWriter::new(&stream);
+        for block in cache.get(channel, start..end) {
+            let len = 8 + 2 + block.payload.len();
+            if len > u16::MAX as usize {
+                continue;
+            }
+            w.write_all(&(len as u16).to_be_bytes())?;
+            w.write_all(&block.header.seqnum.to_be_bytes())?;
+            w.write_all(&block.header.n_messages.to_be_bytes())?;
+            w.write_all(&block.payload)?;
+        }
+        w.write_all(&0_u16.to_be_bytes())?;
+        w.flush()?;
+    }
+}
+
+// Asks each peer in turn for what is still missing of a gap, then the
+// fallback (usually the exchange's retransmission server) for the rest
+pub struct PeerGapFiller {
+    peers: Vec<TcpGapFiller>,
+    fallback: Option<Box<dyn GapFiller<Block<Payload>> + Send>>,
+}
+
+impl PeerGapFiller {
+    // Peers are asked once each, waiting at most `timeout`
+    pub fn new(peers: &[SocketAddr], timeout: Duration) -> Self {
+        Self {
+            peers: peers
+                .iter()
+                .map(|addr| TcpGapFiller::new(*addr, 0, timeout))
+                .collect(),
+            fallback: None,
+        }
+    }
+
+    pub fn set_fallback(&mut self, fallback: Box<dyn GapFiller<Block<Payload>> + Send>) {
+        self.fallback = Some(fallback);
+    }
+}
+
+// Parts of `range` no block in `blocks` covers
+fn missing(range: &Range<u64>, blocks: &[Block<Payload>]) -> Vec<Range<u64>> {
+    let mut covered: Vec<_> = blocks
+        .iter()
+        .map(|b| {
+            let n_messages = (b.header.n_messages as u64).max(1);
+            b.header.seqnum..b.header.seqnum.saturating_add(n_messages)
+        })
+        .collect();
+    covered.sort_by_key(|r| r.start);
+    let mut missing = Vec::new();
+    let mut next = range.start;
+    for r in covered {
+        if r.start > next {
+            missing.push(next..r.start.min(range.end));
+        }
+        next = next.max(r.end);
+        if next >= range.end {
+            return missing;
+        }
+    }
+    missing.push(next..range.end);
+    missing
+}
+
+impl GapFiller<Block<Payload>> for PeerGapFiller {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        let mut blocks = Vec::new();
+        let mut error = None;
+        let fallback = self
+            .fallback
+            .as_mut()
+            .map(|f| f.as_mut() as &mut dyn GapFiller<Block<Payload>>);
+        let fillers = self
+            .peers
+            .iter_mut()
+            .map(|p| p as &mut dyn GapFiller<Block<Payload>>)
+            .chain(fallback);
+        for (i, filler) in fillers.enumerate() {
+            let ranges = missing(&range, &blocks);
+            if ranges.is_empty() {
+                break;
+            }
+            for r in ranges {
+                match filler.fill(channel, r.clone()) {
+                    Ok(filled) => {
+                        error = None;
+                        debug!(
+                            channel,
+                            start = r.start,
+                            end = r.end,
+                            filler = i,
+                            blocks = filled.len(),
+                            "filled"
+                        );
+                        blocks.extend(filled.into_iter().filter(|b| b.header.channel == channel));
+                    }
+                    Err(e) => {
+                        debug!(channel, start = r.start, end = r.end, filler = i, error = %e, "fill failed");
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+        }
+        match error {
+            Some(e) if blocks.is_empty() => Err(e),
+            _ => Ok(blocks),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+// Recovery from Cboe's PITCH servers. A gap request proxy (GRP) takes gap
+// requests over TCP and replays the units' messages on a gap response
+// multicast line, and a spin server sends a unit's book as of a sequence, for
+// resyncing. Both sessions start with a login.
+use crate::gapfill::GapFiller;
+use crate::protocol::pitch::{self, Control, PacketKind, ACCEPTED};
+use crate::recovery::{Snapshot, SnapshotSource};
+use crate::{Block, BlockHeader, ChannelId, Payload};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+#[derive(Clone, Debug, Default)]
+pub struct PitchLogin {
+    pub session_sub_id: String,
+    pub username: String,
+    pub password: String,
+}
+
+// The unit a channel sequences, see Protocol::channel()
+pub fn unit(channel: ChannelId) -> u8 {
+    channel as u8
+}
+
+pub(crate) fn unexpected(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// A session of unit header framed packets, as PITCH's and MITCH's TCP
+// servers send
+pub(crate) struct Session {
+    stream: TcpStream,
+    // Received bytes not yet parsed into packets
+    pending: Vec<u8>,
+    deadline: Instant,
+}
+
+impl Session {
+    pub(crate) fn connect(addr: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Self {
+            stream,
+            pending: Vec::new(),
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    pub(crate) fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.stream.write_all(packet)
+    }
+
+    // Wait for `timeout` from now at most for what is asked next
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Instant::now() + timeout;
+    }
+
+    // The next whole packet, timing out at the deadline
+    pub(crate) fn next_packet(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = [0_u8; 65_536];
+        loop {
+            if let Some(len) = pitch::packet_len(&self.pending) {
+                return Ok(self.pending.drain(..len).collect());
+            }
+            let left = self.deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(left))?;
+            match self.stream.read(&mut buf)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => self.pending.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+}
+
+// A logged in GRP or spin server session
+fn login(addr: SocketAddr, login: &PitchLogin, timeout: Duration) -> io::Result<Session> {
+    let mut session = Session::connect(addr, timeout)?;
+    send(
+        &mut session,
+        &Control::Login {
+            session_sub_id: pitch::padded(&login.session_sub_id),
+            username: pitch::padded(&login.username),
+            password: pitch::padded(&login.password),
+        },
+    )?;
+    loop {
+        for control in next_controls(&mut session)? {
+            match control {
+                Control::LoginResponse { status: ACCEPTED } => return Ok(session),
+                Control::LoginResponse { status } => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("login rejected: {}", status as char),
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn send(session: &mut Session, control: &Control) -> io::Result<()> {
+    let mut buf = Vec::new();
+    pitch::write_header(&mut buf, 0, 0);
+    pitch::write_control(&mut buf, control);
+    session.send(&buf)
+}
+
+// Session messages of the next packet
+fn next_controls(session: &mut Session) -> io::Result<Vec<Control>> {
+    let packet = session.next_packet()?;
+    let parsed = pitch::parse(&packet).map_err(|e| unexpected(e.to_string()))?;
+    let mut controls = Vec::new();
+    for msg in parsed.messages() {
+        let msg = msg.map_err(|e| unexpected(e.to_string()))?;
+        match pitch::parse_control(&msg) {
+            Some(Ok(control)) => controls.push(control),
+            Some(Err(e)) => return Err(unexpected(e.to_string())),
+            None => {}
+        }
+    }
+    Ok(controls)
+}
+
+// Requests gaps from a GRP and collects the replayed messages from its gap
+// response line. The session is kept between requests, GRPs limit logins.
+pub struct PitchGapFiller {
+    addr: SocketAddr,
+    login: PitchLogin,
+    // Joined to the gap response line
+    replays: UdpSocket,
+    session: Option<Session>,
+    pub timeout: Duration,
+}
+
+impl PitchGapFiller {
+    pub fn new(addr: SocketAddr, login: PitchLogin, replays: UdpSocket, timeout: Duration) -> Self {
+        Self {
+            addr,
+            login,
+            replays,
+            session: None,
+            timeout,
+        }
+    }
+
+    // Ask for `count` messages of `unit` from `sequence`, failing if the GRP
+    // refuses
+    fn request(&mut self, unit: u8, sequence: u32, count: u16) -> io::Result<()> {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => self
+                .session
+                .insert(login(self.addr, &self.login, self.timeout)?),
+        };
+        session.set_timeout(self.timeout);
+        send(
+            session,
+            &Control::GapRequest {
+                unit,
+                sequence,
+                count,
+            },
+        )?;
+        loop {
+            for control in next_controls(session)? {
+                match control {
+                    Control::GapResponse {
+                        unit: u,
+                        sequence: s,
+                        status,
+                        ..
+                    } if u == unit && s == sequence => {
+                        if status == ACCEPTED {
+                            return Ok(());
+                        }
+                        return Err(io::Error::other(format!(
+                            "gap request refused: {}",
+                            status as char
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Replays of `unit` within `range` until all of it came or the timeout
+    fn replays(
+        &mut self,
+        channel: ChannelId,
+        range: &Range<u64>,
+    ) -> io::Result<Vec<Block<Payload>>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut received = vec![false; (range.end - range.start) as usize];
+        let mut blocks = Vec::new();
+        let mut buf = vec![0_u8; 65_536];
+        while received.contains(&false) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            self.replays.set_read_timeout(Some(left))?;
+            let n = match self.replays.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            };
+            let packet = match pitch::parse(&buf[..n]) {
+                Ok(p) if p.kind == PacketKind::Data && p.unit == unit(channel) => p,
+                _ => continue,
+            };
+            let start = packet.header.seqnum;
+            let end = start + packet.header.n_messages as u64;
+            if end <= range.start || start >= range.end {
+                continue;
+            }
+            for seqnum in start.max(range.start)..end.min(range.end) {
+                received[(seqnum - range.start) as usize] = true;
+            }
+            let header = BlockHeader {
+                channel,
+                ..packet.header
+            };
+            blocks.push(Block::new(header, packet.payload.into()));
+        }
+        Ok(blocks)
+    }
+}
+
+impl GapFiller<Block<Payload>> for PitchGapFiller {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        let sequence = u32::try_from(range.start)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "seqnum past u32"))?;
+        let count = (range.end - range.start).min(u16::MAX as u64) as u16;
+        let range = range.start..range.start + count as u64;
+        // Replays of earlier requests that came too late
+        self.replays.set_nonblocking(true)?;
+        let mut buf = [0_u8; 1];
+        while self.replays.recv(&mut buf).is_ok() {}
+        self.replays.set_nonblocking(false)?;
+
+        if let Err(e) = self.request(unit(channel), sequence, count) {
+            // Logged in again next time
+            self.session = None;
+            return Err(e);
+        }
+        let blocks = self.replays(channel, &range)?;
+        debug!(
+            channel,
+            start = range.start,
+            end = range.end,
+            blocks = blocks.len(),
+            "gap replayed"
+        );
+        Ok(blocks)
+    }
+}
+
+// Spins a unit's book from a spin server: waits for it to say which sequence
+// an image is available as of, asks for that and takes everything up to spin
+// finished
+pub struct PitchSpinSource {
+    addr: SocketAddr,
+    login: PitchLogin,
+    pub timeout: Duration,
+}
+
+impl PitchSpinSource {
+    pub fn new(addr: SocketAddr, login: PitchLogin, timeout: Duration) -> Self {
+        Self {
+            addr,
+            login,
+            timeout,
+        }
+    }
+}
+
+impl SnapshotSource<Block<Payload>> for PitchSpinSource {
+    fn snapshot(&mut self, channel: ChannelId) -> io::Result<Snapshot<Block<Payload>>> {
+        let mut session = login(self.addr, &self.login, self.timeout)?;
+        session.set_timeout(self.timeout);
+        let mut requested = None;
+        let mut finished = false;
+        let mut blocks = Vec::new();
+        while !finished {
+            let packet = session.next_packet()?;
+            let parsed = pitch::parse(&packet).map_err(|e| unexpected(e.to_string()))?;
+            // The book's messages, less any session messages among them
+            let mut payload = Vec::new();
+            let mut n_messages = 0;
+            for msg in parsed.messages() {
+                let msg = msg.map_err(|e| unexpected(e.to_string()))?;
+                let control = match pitch::parse_control(&msg) {
+                    Some(control) => control.map_err(|e| unexpected(e.to_string()))?,
+                    None => {
+                        if requested.is_some() {
+                            payload.push(2 + msg.body.len() as u8);
+                            payload.push(msg.kind);
+                            payload.extend_from_slice(msg.body);
+                            n_messages += 1;
+                        }
+                        continue;
+                    }
+                };
+                match control {
+                    Control::SpinImageAvailable { sequence } if requested.is_none() => {
+                        send(&mut session, &Control::SpinRequest { sequence })?;
+                        requested = Some(sequence);
+                    }
+                    Control::SpinResponse {
+                        sequence, status, ..
+                    } if Some(sequence) == requested && status != ACCEPTED => {
+                        return Err(io::Error::other(format!(
+                            "spin refused: {}",
+                            status as char
+                        )));
+                    }
+                    Control::SpinFinished { sequence } if Some(sequence) == requested => {
+                        finished = true;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            if n_messages > 0 {
+                let header = BlockHeader {
+                    channel,
+                    seqnum: parsed.header.seqnum,
+                    n_messages,
+                    ..Default::default()
+                };
+                blocks.push(Block::new(header, payload.into()));
+            }
+        }
+        debug!(channel, blocks = blocks.len(), "spun");
+        Ok(Snapshot {
+            // The image includes its sequence
+            seqnum: requested.unwrap_or_default() as u64 + 1,
+            blocks,
+        })
+    }
+}
@@ -0,0 +1,145 @@
+// Placeholders for seqnums given up on, for consumers such as book builders
+// that count messages and would get out of step with the feed's counters if
+// seqnums were just skipped. After each gap a PlaceholderSink hands its sink
+// blocks of the gap's seqnums with an empty message of no type for each, in
+// the protocol's framing so they parse like any other block. The gap itself
+// is still passed on first, so the sink knows they aren't real.
+use crate::message_level::Framing;
+use crate::metrics::FeedId;
+use crate::pool::Payload;
+use crate::sink::Sink;
+use crate::{Block, BlockHeader, ChannelId, GapReason, Session};
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct PlaceholderSink<S> {
+    inner: S,
+    framing: Framing,
+    // Of the latest block or reset of each channel, for the placeholders'
+    sessions: HashMap<ChannelId, Session>,
+}
+
+impl<S> PlaceholderSink<S> {
+    pub fn new(inner: S, framing: Framing) -> Self {
+        Self {
+            inner,
+            framing,
+            sessions: HashMap::new(),
+        }
+    }
+
+    // Blocks of as many placeholders as they can count, sharing one buffer
+    fn placeholders(&self, channel: ChannelId, range: Range<u64>) -> Vec<Block<Payload>> {
+        let mut one = Vec::new();
+        self.framing.write_placeholder(&mut one);
+        let most = (range.end - range.start).min(u16::MAX as u64) as usize;
+        let buf = Arc::new(one.repeat(most));
+        let session = self.sessions.get(&channel).copied().unwrap_or_default();
+        let mut blocks = Vec::new();
+        let mut seqnum = range.start;
+        while seqnum < range.end {
+            let n_messages = (range.end - seqnum).min(u16::MAX as u64) as u16;
+            let header = BlockHeader {
+                channel,
+                session,
+                seqnum,
+                n_messages,
+                ..Default::default()
+            };
+            let len = n_messages as usize * one.len();
+            blocks.push(Block::new(header, Payload::shared(buf.clone(), 0..len)));
+            seqnum += n_messages as u64;
+        }
+        blocks
+    }
+}
+
+impl<S: Sink> Sink for PlaceholderSink<S> {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.on_blocks(std::slice::from_ref(block))
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        for block in blocks {
+            let header = &block.header;
+            self.sessions.insert(header.channel, header.session);
+        }
+        self.inner.on_blocks(blocks)
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        self.inner.on_gap(channel, range.clone(), reason)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+        let blocks = self.placeholders(channel, range);
+        self.inner.on_blocks(&blocks)
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        self.sessions.insert(channel, session);
+        self.inner.on_reset(channel, session, seqnum)
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.inner.on_resync(channel, seqnum, snapshot)
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.inner.on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.inner.on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.inner.on_end_of_session(channel, session, seqnum)
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.inner.on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.inner.on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.inner.on_idle()
+    }
+
+    fn published(&self) -> Option<u64> {
+        self.inner.published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.inner.resume_published(seqnum)
+    }
+}
@@ -0,0 +1,276 @@
+use crate::hugepages::{HugePages, Region};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use std::fmt;
+use std::ops::{Deref, DerefMut, Range};
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Weak};
+use tracing::warn;
+
+// Reusable receive buffers. Datagrams are received straight into one and
+// blocks' payloads point into it, so payload bytes are never copied between
+// the socket, the reorder buffer and the sink. A buffer goes back to its pool
+// when the last payload in it is dropped. The pool allocates when it runs dry
+// and keeps at most `capacity` free buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    free: Sender<Memory>,
+    take: Receiver<Memory>,
+    buf_len: usize,
+    allocated: AtomicUsize,
+    // Whether the buffers allocated up front are in explicit huge pages
+    huge: bool,
+}
+
+// A buffer's bytes, on the heap or in a slice of the pool's region no other
+// buffer has
+#[derive(Default)]
+enum Memory {
+    #[default]
+    Empty,
+    Heap(Vec<u8>),
+    Region {
+        region: Arc<Region>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl BufferPool {
+    // `capacity` buffers of `buf_len` bytes, all allocated up front
+    pub fn new(capacity: usize, buf_len: usize) -> Self {
+        let (free, take) = bounded(capacity);
+        for _ in 0..capacity {
+            free.send(Memory::Heap(vec![0; buf_len])).unwrap();
+        }
+        Self::with_free(free, take, buf_len, false)
+    }
+
+    // new() with the buffers allocated up front in one region of huge pages,
+    // or of ordinary pages if there aren't enough
+    pub fn with_huge_pages(capacity: usize, buf_len: usize, pages: HugePages) -> Self {
+        if pages == HugePages::Off {
+            return Self::new(capacity, buf_len);
+        }
+        let region = match Region::new(capacity * buf_len, pages) {
+            Ok(region) => Arc::new(region),
+            Err(e) => {
+                warn!(error = %e, "could not map the buffer pool, using the heap");
+                return Self::new(capacity, buf_len);
+            }
+        };
+        let (free, take) = bounded(capacity);
+        for i in 0..capacity {
+            let memory = Memory::Region {
+                region: Arc::clone(&region),
+                offset: i * buf_len,
+                len: buf_len,
+            };
+            free.send(memory).unwrap();
+        }
+        Self::with_free(free, take, buf_len, region.is_huge())
+    }
+
+    fn with_free(free: Sender<Memory>, take: Receiver<Memory>, buf_len: usize, huge: bool) -> Self {
+        let capacity = take.len();
+        Self {
+            inner: Arc::new(Inner {
+                free,
+                take,
+                buf_len,
+                allocated: AtomicUsize::new(capacity),
+                huge,
+            }),
+        }
+    }
+
+    pub fn get(&self) -> Buffer {
+        let data = self.inner.take.try_recv().unwrap_or_else(|_| {
+            self.inner.allocated.fetch_add(1, Relaxed);
+            Memory::Heap(vec![0; self.inner.buf_len])
+        });
+        Buffer {
+            data,
+            pool: Arc::downgrade(&self.inner),
+        }
+    }
+
+    pub fn buf_len(&self) -> usize {
+        self.inner.buf_len
+    }
+
+    // Buffers ever allocated, which stops growing once the pool is warm
+    pub fn allocated(&self) -> usize {
+        self.inner.allocated.load(Relaxed)
+    }
+
+    pub fn free(&self) -> usize {
+        self.inner.take.len()
+    }
+
+    // Whether the buffers allocated up front are in explicit huge pages. Ones
+    // allocated once the pool runs dry never are.
+    pub fn huge_pages(&self) -> bool {
+        self.inner.huge
+    }
+}
+
+// A buffer taken from a BufferPool, returned to it on drop
+pub struct Buffer {
+    data: Memory,
+    pool: Weak<Inner>,
+}
+
+impl Buffer {
+    // Share `range` of the buffer as a payload
+    pub fn into_payload(self, range: Range<usize>) -> Payload {
+        assert!(range.end <= self.len());
+        Payload(Repr::Pooled {
+            buf: Arc::new(self),
+            range,
+        })
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.data {
+            Memory::Empty => &[],
+            Memory::Heap(v) => v,
+            // SAFETY: within the region, which outlives the buffer
+            Memory::Region {
+                region,
+                offset,
+                len,
+            } => unsafe { slice::from_raw_parts(region.base().add(*offset), *len) },
+        }
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.data {
+            Memory::Empty => &mut [],
+            Memory::Heap(v) => v,
+            // SAFETY: within the region, and no other buffer has this range
+            Memory::Region {
+                region,
+                offset,
+                len,
+            } => unsafe { slice::from_raw_parts_mut(region.base().add(*offset), *len) },
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            // Dropped instead if the pool is full
+            let _ = pool.free.try_send(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+// Bytes of a block, in a pooled buffer or owned
+#[derive(Clone)]
+pub struct Payload(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Owned(Vec<u8>),
+    Pooled {
+        buf: Arc<Buffer>,
+        range: Range<usize>,
+    },
+    // Memory the caller manages, like AF_XDP frames
+    Shared {
+        buf: Arc<dyn AsRef<[u8]> + Send + Sync>,
+        range: Range<usize>,
+    },
+}
+
+impl Payload {
+    // `range` of `buf`, which is dropped with the last payload sharing it
+    pub fn shared(buf: Arc<dyn AsRef<[u8]> + Send + Sync>, range: Range<usize>) -> Self {
+        assert!(range.end <= (*buf).as_ref().len());
+        Self(Repr::Shared { buf, range })
+    }
+
+    // Whether the payload points into a receive buffer instead of owning a copy
+    pub fn is_pooled(&self) -> bool {
+        !matches!(self.0, Repr::Owned(_))
+    }
+
+    // `range` of the payload, sharing its buffer if it isn't owned
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.len());
+        let within = |outer: &Range<usize>| outer.start + range.start..outer.start + range.end;
+        match &self.0 {
+            Repr::Owned(v) => Self(Repr::Owned(v[range].to_vec())),
+            Repr::Pooled { buf, range: outer } => Self(Repr::Pooled {
+                buf: Arc::clone(buf),
+                range: within(outer),
+            }),
+            Repr::Shared { buf, range: outer } => Self(Repr::Shared {
+                buf: Arc::clone(buf),
+                range: within(outer),
+            }),
+        }
+    }
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self(Repr::Owned(Vec::new()))
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(v: Vec<u8>) -> Self {
+        Self(Repr::Owned(v))
+    }
+}
+
+impl From<&[u8]> for Payload {
+    fn from(v: &[u8]) -> Self {
+        Self(Repr::Owned(v.to_vec()))
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Owned(v) => v,
+            Repr::Pooled { buf, range } => &buf[range.clone()],
+            Repr::Shared { buf, range } => &(**buf).as_ref()[range.clone()],
+        }
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> PartialEq<T> for Payload {
+    fn eq(&self, r: &T) -> bool {
+        **self == *r.as_ref()
+    }
+}
+
+impl Eq for Payload {}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,262 @@
+// Framing of proprietary feeds declared in the config file instead of written
+// as a framer, such as:
+//
+//   [framing]
+//   header_len = 16
+//   seqnum = { offset = 4, width = 8 }
+//   count = { offset = 12, width = 2, endian = "little" }
+//   messages = { length_prefixed = { width = 2 } }
+//
+// The seqnum is the first message's and fields are big endian unless they say
+// otherwise. Without a count, packets are one message or as many as the
+// message framing finds. With both, only the whole messages counted are
+// sequenced and the rest is `missing` or `trailing`, as with MoldUDP64.
+// A session field's bytes are the session id, so a feed can be arbitrated
+// with a MoldUDP64 feed of the same messages.
+use super::ParseError;
+use crate::{BlockHeader, Session};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+// An unsigned integer of 1 to 8 bytes in the header
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    pub offset: usize,
+    pub width: usize,
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+impl Field {
+    pub fn end(&self) -> usize {
+        self.offset + self.width
+    }
+
+    // None if `buf` is too short for it
+    pub fn read(&self, buf: &[u8]) -> Option<u64> {
+        let bytes = buf.get(self.offset..self.end())?;
+        let mut n = 0;
+        match self.endian {
+            Endian::Big => bytes.iter().for_each(|b| n = n << 8 | *b as u64),
+            Endian::Little => bytes.iter().rev().for_each(|b| n = n << 8 | *b as u64),
+        }
+        Some(n)
+    }
+}
+
+// How the messages after the header are delimited
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum MessageFraming {
+    // Every message is this many bytes
+    Fixed(usize),
+    // Each message starts with its length, counting the prefix too if
+    // `inclusive`
+    LengthPrefixed {
+        width: usize,
+        #[serde(default)]
+        endian: Endian,
+        #[serde(default)]
+        inclusive: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Framing {
+    // Bytes before the first message
+    pub header_len: usize,
+    pub seqnum: Field,
+    pub count: Option<Field>,
+    pub messages: Option<MessageFraming>,
+    // Up to 10 bytes, endian is ignored
+    pub session: Option<Field>,
+    #[serde(default = "first_seqnum")]
+    pub first_seqnum: u64,
+}
+
+fn first_seqnum() -> u64 {
+    1
+}
+
+impl Framing {
+    // Why the framing can't frame anything, if it can't
+    pub fn validate(&self) -> Result<(), String> {
+        let widths = [
+            ("seqnum", Some(self.seqnum.width), 8),
+            ("count", self.count.map(|c| c.width), 2),
+            ("session", self.session.map(|s| s.width), 10),
+        ];
+        for (name, width, max) in widths {
+            match width {
+                Some(w) if !(1..=max).contains(&w) => {
+                    return Err(format!("{} width {} is not 1 to {}", name, w, max))
+                }
+                _ => {}
+            }
+        }
+        let fields = [
+            ("seqnum", Some(self.seqnum)),
+            ("count", self.count),
+            ("session", self.session),
+        ];
+        for (name, field) in fields {
+            match field {
+                Some(f) if f.end() > self.header_len => {
+                    return Err(format!("{} is past header_len {}", name, self.header_len))
+                }
+                _ => {}
+            }
+        }
+        match self.messages {
+            Some(MessageFraming::Fixed(0)) => Err("fixed messages can't be empty".to_string()),
+            Some(MessageFraming::LengthPrefixed { width, .. }) if !(1..=4).contains(&width) => {
+                Err(format!("length prefix width {} is not 1 to 4", width))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    pub header: BlockHeader,
+    // Messages following the header, only the whole ones counted if the
+    // messages are framed
+    pub payload: &'a [u8],
+    // Messages the count says there are but the packet is too short for
+    pub missing: u16,
+    // Bytes after the messages counted, left out of the payload
+    pub trailing: usize,
+}
+
+pub fn parse<'a>(framing: &Framing, buf: &'a [u8]) -> Result<Packet<'a>, ParseError> {
+    if buf.len() < framing.header_len {
+        return Err(ParseError::Truncated);
+    }
+    let seqnum = framing.seqnum.read(buf).ok_or(ParseError::Invalid)?;
+    let count = match framing.count {
+        Some(field) => Some(field.read(buf).ok_or(ParseError::Invalid)? as u16),
+        None => None,
+    };
+    let mut session = Session::default();
+    if let Some(field) = framing.session {
+        session[..field.width].copy_from_slice(&buf[field.offset..field.end()]);
+    }
+    let payload = &buf[framing.header_len..];
+    let header = BlockHeader {
+        session,
+        seqnum,
+        ..Default::default()
+    };
+    let messages = match framing.messages {
+        Some(messages) => messages,
+        None => {
+            return Ok(Packet {
+                header: BlockHeader {
+                    n_messages: count.unwrap_or(1),
+                    ..header
+                },
+                payload,
+                missing: 0,
+                trailing: 0,
+            })
+        }
+    };
+    let mut msgs = Messages::new(messages, payload, count.unwrap_or(u16::MAX));
+    let mut n_messages = 0;
+    while let Some(Ok(_)) = msgs.next() {
+        n_messages += 1;
+    }
+    // Left where the first message that isn't whole starts
+    let used = payload.len() - msgs.buf.len();
+    Ok(Packet {
+        header: BlockHeader {
+            n_messages,
+            ..header
+        },
+        payload: &payload[..used],
+        missing: count.map_or(0, |count| count - n_messages),
+        trailing: payload.len() - used,
+    })
+}
+
+impl Packet<'_> {
+    pub fn malformed(&self) -> bool {
+        self.missing > 0 || self.trailing > 0
+    }
+}
+
+// Iterates the messages of a payload, without their length prefixes
+pub struct Messages<'a> {
+    framing: MessageFraming,
+    buf: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Messages<'a> {
+    // `buf` is a packet's payload, such as a sequenced block's, with at most
+    // `count` messages
+    pub fn new(framing: MessageFraming, buf: &'a [u8], count: u16) -> Self {
+        Self {
+            framing,
+            buf,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<&'a [u8], ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.buf.is_empty() {
+            return None;
+        }
+        self.remaining -= 1;
+        let (start, end) = match self.framing {
+            MessageFraming::Fixed(len) => (0, len),
+            MessageFraming::LengthPrefixed {
+                width,
+                endian,
+                inclusive,
+            } => {
+                let prefix = Field {
+                    offset: 0,
+                    width,
+                    endian,
+                };
+                let len = match prefix.read(self.buf) {
+                    Some(len) => len as usize,
+                    None => {
+                        self.remaining = 0;
+                        return Some(Err(ParseError::Truncated));
+                    }
+                };
+                match inclusive {
+                    true if len < width => {
+                        self.remaining = 0;
+                        return Some(Err(ParseError::Invalid));
+                    }
+                    true => (width, len),
+                    false => (width, width + len),
+                }
+            }
+        };
+        if self.buf.len() < end {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        let msg = &self.buf[start..end];
+        self.buf = &self.buf[end..];
+        Some(Ok(msg))
+    }
+}
@@ -0,0 +1,1205 @@
+// FIX/FAST 1.1 encoded feeds, such as MOEX's and Euronext's.
+//
+// Fields are stop bit encoded: 7 bits per byte, the high bit set on a field's
+// last byte. A message is a presence map, a template id and the template's
+// fields, the map saying which fields are in the stream and which come from
+// their operators. Dictionaries are reset at the start of every packet, so
+// packets decode on their own whichever line they arrived on.
+//
+// Packets are framed one of two ways:
+// - Prefixed (MOEX): MsgSeqNum: u32 LE, then messages. Seqnums count packets.
+// - By template: messages only, the seqnum is the first message's MsgSeqNum
+//   (tag 34) and seqnums count messages
+use super::ParseError;
+use crate::BlockHeader;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const HEADER_LEN: usize = 4;
+// FIX tag of MsgSeqNum
+pub const SEQNUM_ID: u32 = 34;
+pub const SEQNUM_NAME: &str = "MsgSeqNum";
+
+const STOP_BIT: u8 = 0x80;
+// Dictionary key of the template id, which has an implicit copy operator
+const TEMPLATE_ID_KEY: &str = "\0template";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    UInt(u64),
+    Int(i64),
+    Decimal { exponent: i32, mantissa: i64 },
+    Str(String),
+    Bytes(Vec<u8>),
+    Sequence(Vec<Vec<Field>>),
+    Group(Vec<Field>),
+}
+
+impl Value {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::UInt(v) => Some(*v),
+            Value::Int(v) => u64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub id: Option<u32>,
+    pub value: Value,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    pub template_id: u32,
+    // Fields present, in template order. Absent optional fields are left out.
+    pub fields: Vec<Field>,
+}
+
+impl Message {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| &f.value)
+    }
+
+    // MsgSeqNum, by tag or by name for templates without ids
+    pub fn seqnum(&self) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|f| f.id == Some(SEQNUM_ID))
+            .or_else(|| self.fields.iter().find(|f| f.name == SEQNUM_NAME))
+            .and_then(|f| f.value.as_u64())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    UInt32,
+    Int32,
+    UInt64,
+    Int64,
+    Ascii,
+    Unicode,
+    Bytes,
+    Decimal,
+}
+
+impl Kind {
+    fn signed(self) -> bool {
+        matches!(self, Kind::Int32 | Kind::Int64)
+    }
+
+    fn integer(self) -> bool {
+        matches!(
+            self,
+            Kind::UInt32 | Kind::Int32 | Kind::UInt64 | Kind::Int64
+        )
+    }
+
+    fn max(self) -> i128 {
+        match self {
+            Kind::UInt32 => u32::MAX as i128,
+            Kind::Int32 => i32::MAX as i128,
+            Kind::UInt64 => u64::MAX as i128,
+            _ => i64::MAX as i128,
+        }
+    }
+
+    fn min(self) -> i128 {
+        match self {
+            Kind::Int32 => i32::MIN as i128,
+            Kind::Int64 => i64::MIN as i128,
+            _ => 0,
+        }
+    }
+
+    // An initial value as written in a template
+    fn initial(self, s: &str) -> Result<Value, String> {
+        let bad = || format!("bad initial value {:?}", s);
+        Ok(match self {
+            Kind::UInt32 | Kind::UInt64 => Value::UInt(s.trim().parse().map_err(|_| bad())?),
+            Kind::Int32 | Kind::Int64 => Value::Int(s.trim().parse().map_err(|_| bad())?),
+            Kind::Ascii | Kind::Unicode => Value::Str(s.to_string()),
+            Kind::Bytes => Value::Bytes(hex(s).ok_or_else(bad)?),
+            Kind::Decimal => parse_decimal(s.trim()).ok_or_else(bad)?,
+        })
+    }
+}
+
+fn hex(s: &str) -> Option<Vec<u8>> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "12.5" is mantissa 125 exponent -1, "-3e2" is -3 exponent 2
+fn parse_decimal(s: &str) -> Option<Value> {
+    let (number, mut exponent) = match s.split_once(['e', 'E']) {
+        Some((n, e)) => (n, e.parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let digits = match number.split_once('.') {
+        Some((int, frac)) => {
+            exponent = exponent.checked_sub(frac.len() as i32)?;
+            format!("{}{}", int, frac)
+        }
+        None => number.to_string(),
+    };
+    let mut mantissa: i64 = digits.parse().ok()?;
+    // Normalized, so 1.50 and 1.5 are the same value
+    while mantissa != 0 && mantissa % 10 == 0 {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    Some(Value::Decimal { exponent, mantissa })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OpKind {
+    None,
+    Constant,
+    Default,
+    Copy,
+    Increment,
+    Delta,
+    Tail,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Operator {
+    kind: OpKind,
+    initial: Option<Value>,
+    // Dictionary key, the field's name unless the template gives one
+    key: String,
+}
+
+impl Operator {
+    // Whether the field takes a bit of its presence map
+    fn uses_bit(&self, optional: bool) -> bool {
+        match self.kind {
+            OpKind::None | OpKind::Delta => false,
+            OpKind::Constant => optional,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Body {
+    Scalar(Kind, Operator),
+    // A decimal whose exponent and mantissa have operators of their own
+    Decimal(Operator, Operator),
+    Sequence(Box<Instruction>, Vec<Instruction>),
+    Group(Vec<Instruction>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Instruction {
+    name: String,
+    id: Option<u32>,
+    optional: bool,
+    body: Body,
+}
+
+impl Instruction {
+    fn bits(&self) -> usize {
+        match &self.body {
+            Body::Scalar(_, op) => op.uses_bit(self.optional) as usize,
+            Body::Decimal(exponent, mantissa) => {
+                exponent.uses_bit(self.optional) as usize + mantissa.uses_bit(false) as usize
+            }
+            Body::Sequence(length, _) => length.bits(),
+            Body::Group(_) => self.optional as usize,
+        }
+    }
+}
+
+// Groups and sequence elements have a presence map of their own if any of
+// their fields use one
+fn needs_pmap(fields: &[Instruction]) -> bool {
+    fields.iter().any(|f| f.bits() > 0)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Template {
+    pub id: u32,
+    pub name: String,
+    fields: Vec<Instruction>,
+}
+
+// The templates a feed's messages are encoded with, by id
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Templates {
+    templates: HashMap<u32, Template>,
+}
+
+impl Templates {
+    // From a FAST 1.1 template definition document. Dynamic template
+    // references are not supported.
+    pub fn parse(xml: &str) -> Result<Self, String> {
+        let root = xml::parse(xml)?;
+        let mut elements = Vec::new();
+        if root.name == "template" {
+            elements.push(&root);
+        } else {
+            elements.extend(root.children.iter().filter(|e| e.name == "template"));
+        }
+        let by_name: HashMap<&str, &xml::Element> = elements
+            .iter()
+            .filter_map(|e| Some((e.attr("name")?, *e)))
+            .collect();
+        let mut templates = HashMap::new();
+        for element in elements {
+            let name = element.attr("name").unwrap_or_default();
+            let id = element
+                .attr("id")
+                .ok_or_else(|| format!("template {} has no id", name))?;
+            let id = id
+                .parse()
+                .map_err(|_| format!("template {} has bad id {}", name, id))?;
+            let fields = instructions(element, &by_name, 0)
+                .map_err(|e| format!("template {}: {}", name, e))?;
+            let template = Template {
+                id,
+                name: name.to_string(),
+                fields,
+            };
+            if templates.insert(id, template).is_some() {
+                return Err(format!("template id {} is used twice", id));
+            }
+        }
+        Ok(Self { templates })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let xml = fs::read_to_string(path)?;
+        Self::parse(&xml).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Template> {
+        self.templates.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+}
+
+// Static template references are inlined, this deep at most
+const MAX_REF_DEPTH: usize = 16;
+
+fn instructions(
+    parent: &xml::Element,
+    templates: &HashMap<&str, &xml::Element>,
+    depth: usize,
+) -> Result<Vec<Instruction>, String> {
+    let mut fields = Vec::new();
+    for e in &parent.children {
+        match e.name.as_str() {
+            "templateRef" => {
+                let name = e
+                    .attr("name")
+                    .ok_or("dynamic template references are not supported")?;
+                let referenced = templates
+                    .get(name)
+                    .ok_or_else(|| format!("no template {}", name))?;
+                if depth >= MAX_REF_DEPTH {
+                    return Err(format!("template references to {} nest too deep", name));
+                }
+                fields.extend(instructions(referenced, templates, depth + 1)?);
+            }
+            // Not fields
+            "typeRef" | "length" => {}
+            _ => fields.push(instruction(e, templates, depth)?),
+        }
+    }
+    Ok(fields)
+}
+
+fn instruction(
+    e: &xml::Element,
+    templates: &HashMap<&str, &xml::Element>,
+    depth: usize,
+) -> Result<Instruction, String> {
+    let name = e
+        .attr("name")
+        .ok_or_else(|| format!("{} has no name", e.name))?
+        .to_string();
+    let id = match e.attr("id") {
+        Some(id) => Some(
+            id.parse()
+                .map_err(|_| format!("{} has bad id {}", name, id))?,
+        ),
+        None => None,
+    };
+    let optional = match e.attr("presence") {
+        None | Some("mandatory") => false,
+        Some("optional") => true,
+        Some(p) => return Err(format!("{} has bad presence {}", name, p)),
+    };
+    let kind = match e.name.as_str() {
+        "uInt32" | "length" => Kind::UInt32,
+        "int32" => Kind::Int32,
+        "uInt64" => Kind::UInt64,
+        "int64" => Kind::Int64,
+        "string" if e.attr("charset") == Some("unicode") => Kind::Unicode,
+        "string" => Kind::Ascii,
+        "byteVector" => Kind::Bytes,
+        "decimal" => Kind::Decimal,
+        "sequence" => {
+            let length = match e.children.iter().find(|c| c.name == "length") {
+                Some(l) => {
+                    let mut l = l.clone();
+                    if l.attr("name").is_none() {
+                        l.attrs
+                            .push(("name".to_string(), format!("{}.length", name)));
+                    }
+                    instruction(&l, templates, depth)?
+                }
+                None => Instruction {
+                    name: format!("{}.length", name),
+                    id: None,
+                    optional,
+                    body: Body::Scalar(
+                        Kind::UInt32,
+                        Operator {
+                            kind: OpKind::None,
+                            initial: None,
+                            key: format!("{}.length", name),
+                        },
+                    ),
+                },
+            };
+            let length = Instruction { optional, ..length };
+            let fields = instructions(e, templates, depth)?;
+            return Ok(Instruction {
+                name,
+                id,
+                optional,
+                body: Body::Sequence(Box::new(length), fields),
+            });
+        }
+        "group" => {
+            let fields = instructions(e, templates, depth)?;
+            return Ok(Instruction {
+                name,
+                id,
+                optional,
+                body: Body::Group(fields),
+            });
+        }
+        other => return Err(format!("unknown field type {}", other)),
+    };
+    let key = name.clone();
+    let body = match (
+        kind,
+        e.children.iter().find(|c| c.name == "exponent"),
+        e.children.iter().find(|c| c.name == "mantissa"),
+    ) {
+        (Kind::Decimal, exponent, mantissa) if exponent.is_some() || mantissa.is_some() => {
+            let op = |part: Option<&xml::Element>, kind, suffix| match part {
+                Some(part) => operator(part, kind, &format!("{}.{}", key, suffix)),
+                None => Ok(Operator {
+                    kind: OpKind::None,
+                    initial: None,
+                    key: format!("{}.{}", key, suffix),
+                }),
+            };
+            Body::Decimal(
+                op(exponent, Kind::Int32, "exponent")?,
+                op(mantissa, Kind::Int64, "mantissa")?,
+            )
+        }
+        _ => Body::Scalar(kind, operator(e, kind, &key)?),
+    };
+    let field = Instruction {
+        name,
+        id,
+        optional,
+        body,
+    };
+    check(&field, kind)?;
+    Ok(field)
+}
+
+fn operator(e: &xml::Element, kind: Kind, key: &str) -> Result<Operator, String> {
+    let ops = [
+        ("constant", OpKind::Constant),
+        ("default", OpKind::Default),
+        ("copy", OpKind::Copy),
+        ("increment", OpKind::Increment),
+        ("delta", OpKind::Delta),
+        ("tail", OpKind::Tail),
+    ];
+    for c in &e.children {
+        if let Some((_, op)) = ops.iter().find(|(name, _)| *name == c.name) {
+            let initial = c.attr("value").map(|v| kind.initial(v)).transpose()?;
+            let key = c.attr("key").unwrap_or(key).to_string();
+            return Ok(Operator {
+                kind: *op,
+                initial,
+                key,
+            });
+        }
+    }
+    Ok(Operator {
+        kind: OpKind::None,
+        initial: None,
+        key: key.to_string(),
+    })
+}
+
+// Operators the spec doesn't allow for a field
+fn check(field: &Instruction, kind: Kind) -> Result<(), String> {
+    let ops = match &field.body {
+        Body::Scalar(_, op) => vec![(op, kind, field.optional)],
+        Body::Decimal(exponent, mantissa) => vec![
+            (exponent, Kind::Int32, field.optional),
+            (mantissa, Kind::Int64, false),
+        ],
+        _ => return Ok(()),
+    };
+    for (op, kind, optional) in ops {
+        let ok = match op.kind {
+            OpKind::Constant => op.initial.is_some(),
+            OpKind::Default => optional || op.initial.is_some(),
+            OpKind::Increment => kind.integer(),
+            OpKind::Tail => matches!(kind, Kind::Ascii | Kind::Unicode | Kind::Bytes),
+            _ => true,
+        };
+        if !ok {
+            return Err(format!(
+                "{:?} operator not allowed on {}",
+                op.kind, field.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Stop bit encoded fields of a buffer
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    // Up to and including the byte with the stop bit
+    fn stop_bytes(&mut self) -> Result<&'a [u8], ParseError> {
+        let rest = &self.buf[self.pos..];
+        let len = rest
+            .iter()
+            .position(|b| b & STOP_BIT != 0)
+            .ok_or(ParseError::Truncated)?
+            + 1;
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let rest = &self.buf[self.pos..];
+        if rest.len() < len {
+            return Err(ParseError::Truncated);
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn uint(&mut self) -> Result<u64, ParseError> {
+        let mut v: u64 = 0;
+        for b in self.stop_bytes()? {
+            if v >> 57 != 0 {
+                return Err(ParseError::Invalid);
+            }
+            v = v << 7 | (b & 0x7f) as u64;
+        }
+        Ok(v)
+    }
+
+    fn int(&mut self) -> Result<i64, ParseError> {
+        let bytes = self.stop_bytes()?;
+        // Sign extended from the first byte's highest data bit
+        let mut v: i64 = if bytes[0] & 0x40 != 0 { -1 } else { 0 };
+        for b in bytes {
+            if (v >> 56) != 0 && (v >> 56) != -1 {
+                return Err(ParseError::Invalid);
+            }
+            v = v << 7 | (b & 0x7f) as i64;
+        }
+        Ok(v)
+    }
+
+    // 0 is null and everything else is one more than it would be
+    fn nullable_uint(&mut self, nullable: bool) -> Result<Option<u64>, ParseError> {
+        match self.uint()? {
+            0 if nullable => Ok(None),
+            v if nullable => Ok(Some(v - 1)),
+            v => Ok(Some(v)),
+        }
+    }
+
+    // 0 is null and positive values are one more than they would be
+    fn nullable_int(&mut self, nullable: bool) -> Result<Option<i64>, ParseError> {
+        match self.int()? {
+            0 if nullable => Ok(None),
+            v if nullable && v > 0 => Ok(Some(v - 1)),
+            v => Ok(Some(v)),
+        }
+    }
+
+    fn ascii(&mut self, nullable: bool) -> Result<Option<String>, ParseError> {
+        let bytes = self.stop_bytes()?;
+        let s = match bytes {
+            [STOP_BIT] if nullable => return Ok(None),
+            [STOP_BIT] => String::new(),
+            [0, STOP_BIT] if nullable => String::new(),
+            [0, STOP_BIT] => "\0".to_string(),
+            _ => bytes.iter().map(|b| (b & 0x7f) as char).collect(),
+        };
+        Ok(Some(s))
+    }
+
+    fn byte_vector(&mut self, nullable: bool) -> Result<Option<Vec<u8>>, ParseError> {
+        match self.nullable_uint(nullable)? {
+            Some(len) => Ok(Some(self.bytes(len as usize)?.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn pmap(&mut self) -> Result<Pmap<'a>, ParseError> {
+        Ok(Pmap {
+            bytes: self.stop_bytes()?,
+            bit: 0,
+        })
+    }
+}
+
+// Bits are taken from the highest data bit of the first byte on. Bits past
+// the end are 0.
+struct Pmap<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl Pmap<'_> {
+    fn next(&mut self) -> bool {
+        let (byte, bit) = (self.bit / 7, self.bit % 7);
+        self.bit += 1;
+        self.bytes.get(byte).is_some_and(|b| b & (0x40 >> bit) != 0)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Prev {
+    Empty,
+    Assigned(Value),
+}
+
+// Decodes a packet's messages, one after another
+pub struct Decoder<'t> {
+    templates: &'t Templates,
+    // Undefined entries are missing
+    dictionary: HashMap<String, Prev>,
+}
+
+impl<'t> Decoder<'t> {
+    pub fn new(templates: &'t Templates) -> Self {
+        Self {
+            templates,
+            dictionary: HashMap::new(),
+        }
+    }
+
+    // For the start of a packet
+    pub fn reset(&mut self) {
+        self.dictionary.clear();
+    }
+
+    // The message at the start of `buf` and its length
+    pub fn decode(&mut self, buf: &[u8]) -> Result<(Message, usize), ParseError> {
+        let mut r = Reader { buf, pos: 0 };
+        let mut pmap = r.pmap()?;
+        let template_id = if pmap.next() {
+            let id = r.uint()?;
+            let id = u32::try_from(id).map_err(|_| ParseError::Invalid)?;
+            self.dictionary.insert(
+                TEMPLATE_ID_KEY.to_string(),
+                Prev::Assigned(Value::UInt(id as u64)),
+            );
+            id
+        } else {
+            match self.dictionary.get(TEMPLATE_ID_KEY) {
+                Some(Prev::Assigned(Value::UInt(id))) => *id as u32,
+                _ => return Err(ParseError::Invalid),
+            }
+        };
+        let template = self.templates.get(template_id).ok_or(ParseError::Invalid)?;
+        let fields = self.fields(&template.fields, &mut r, &mut pmap)?;
+        Ok((
+            Message {
+                template_id,
+                fields,
+            },
+            r.pos,
+        ))
+    }
+
+    fn fields(
+        &mut self,
+        instructions: &[Instruction],
+        r: &mut Reader,
+        pmap: &mut Pmap,
+    ) -> Result<Vec<Field>, ParseError> {
+        let mut fields = Vec::with_capacity(instructions.len());
+        for i in instructions {
+            if let Some(value) = self.field(i, r, pmap)? {
+                fields.push(Field {
+                    name: i.name.clone(),
+                    id: i.id,
+                    value,
+                });
+            }
+        }
+        Ok(fields)
+    }
+
+    fn field(
+        &mut self,
+        i: &Instruction,
+        r: &mut Reader,
+        pmap: &mut Pmap,
+    ) -> Result<Option<Value>, ParseError> {
+        match &i.body {
+            Body::Scalar(kind, op) => self.scalar(*kind, op, i.optional, r, pmap),
+            Body::Decimal(exponent, mantissa) => {
+                let exponent = match self.scalar(Kind::Int32, exponent, i.optional, r, pmap)? {
+                    Some(Value::Int(e)) => e as i32,
+                    _ => return Ok(None),
+                };
+                match self.scalar(Kind::Int64, mantissa, false, r, pmap)? {
+                    Some(Value::Int(mantissa)) => Ok(Some(Value::Decimal { exponent, mantissa })),
+                    _ => Err(ParseError::Invalid),
+                }
+            }
+            Body::Group(instructions) => {
+                if i.optional && !pmap.next() {
+                    return Ok(None);
+                }
+                let fields = self.element(instructions, r, pmap)?;
+                Ok(Some(Value::Group(fields)))
+            }
+            Body::Sequence(length, instructions) => {
+                let len = match self.field(length, r, pmap)? {
+                    Some(len) => len.as_u64().ok_or(ParseError::Invalid)?,
+                    None => return Ok(None),
+                };
+                // Elements take a byte or more unless every field is a
+                // constant, so a length past the end is more likely garbage
+                if len as usize > r.buf.len() - r.pos {
+                    return Err(ParseError::Truncated);
+                }
+                let elements = (0..len)
+                    .map(|_| self.element(instructions, r, pmap))
+                    .collect::<Result<_, _>>()?;
+                Ok(Some(Value::Sequence(elements)))
+            }
+        }
+    }
+
+    // A group's fields, with their own presence map if they use one
+    fn element(
+        &mut self,
+        instructions: &[Instruction],
+        r: &mut Reader,
+        pmap: &mut Pmap,
+    ) -> Result<Vec<Field>, ParseError> {
+        if needs_pmap(instructions) {
+            let mut own = r.pmap()?;
+            self.fields(instructions, r, &mut own)
+        } else {
+            self.fields(instructions, r, pmap)
+        }
+    }
+
+    fn scalar(
+        &mut self,
+        kind: Kind,
+        op: &Operator,
+        optional: bool,
+        r: &mut Reader,
+        pmap: &mut Pmap,
+    ) -> Result<Option<Value>, ParseError> {
+        let value = match op.kind {
+            OpKind::None => read(kind, r, optional)?,
+            OpKind::Constant => {
+                if optional && !pmap.next() {
+                    None
+                } else {
+                    op.initial.clone()
+                }
+            }
+            OpKind::Default => {
+                if pmap.next() {
+                    read(kind, r, optional)?
+                } else {
+                    op.initial.clone()
+                }
+            }
+            OpKind::Copy | OpKind::Increment | OpKind::Tail => {
+                let value = if pmap.next() {
+                    let read = read(kind, r, optional)?;
+                    match (op.kind, read) {
+                        (OpKind::Tail, Some(tail)) => Some(with_tail(self.base(op)?, tail)?),
+                        (_, read) => read,
+                    }
+                } else {
+                    match self.dictionary.get(&op.key) {
+                        Some(Prev::Assigned(prev)) if op.kind == OpKind::Increment => {
+                            Some(increment(kind, prev)?)
+                        }
+                        Some(Prev::Assigned(prev)) => Some(prev.clone()),
+                        Some(Prev::Empty) if optional => None,
+                        Some(Prev::Empty) => return Err(ParseError::Invalid),
+                        None => match &op.initial {
+                            Some(initial) => Some(initial.clone()),
+                            None if optional => None,
+                            None => return Err(ParseError::Invalid),
+                        },
+                    }
+                };
+                self.assign(op, value.clone());
+                value
+            }
+            OpKind::Delta => {
+                // Integers are a difference, decimals a difference of each
+                // part and the rest how much to cut from the base and what
+                // to put in its place
+                let value = match r.nullable_int(optional)? {
+                    Some(delta) if kind.integer() => Some(add(kind, self.base(op)?, delta)?),
+                    Some(exponent) if kind == Kind::Decimal => {
+                        let mantissa = r.int()?;
+                        Some(add_decimal(self.base(op)?, exponent, mantissa)?)
+                    }
+                    Some(subtract) => {
+                        let diff = read(kind, r, false)?.ok_or(ParseError::Invalid)?;
+                        Some(with_delta(self.base(op)?, subtract, diff)?)
+                    }
+                    None => None,
+                };
+                if value.is_some() {
+                    self.assign(op, value.clone());
+                }
+                value
+            }
+        };
+        Ok(value)
+    }
+
+    fn assign(&mut self, op: &Operator, value: Option<Value>) {
+        let prev = match value {
+            Some(value) => Prev::Assigned(value),
+            None => Prev::Empty,
+        };
+        self.dictionary.insert(op.key.clone(), prev);
+    }
+
+    // What a delta or tail applies to: the previous value, or else the
+    // initial value, or else the type's zero
+    fn base(&self, op: &Operator) -> Result<Option<Value>, ParseError> {
+        match self.dictionary.get(&op.key) {
+            Some(Prev::Assigned(prev)) => Ok(Some(prev.clone())),
+            Some(Prev::Empty) => Err(ParseError::Invalid),
+            None => Ok(op.initial.clone()),
+        }
+    }
+}
+
+fn read(kind: Kind, r: &mut Reader, nullable: bool) -> Result<Option<Value>, ParseError> {
+    Ok(match kind {
+        Kind::UInt32 | Kind::UInt64 => r
+            .nullable_uint(nullable)?
+            .map(|v| in_range(kind, v as i128).map(|v| Value::UInt(v as u64)))
+            .transpose()?,
+        Kind::Int32 | Kind::Int64 => r
+            .nullable_int(nullable)?
+            .map(|v| in_range(kind, v as i128).map(|v| Value::Int(v as i64)))
+            .transpose()?,
+        Kind::Ascii => r.ascii(nullable)?.map(Value::Str),
+        Kind::Unicode => r
+            .byte_vector(nullable)?
+            .map(|b| Value::Str(String::from_utf8_lossy(&b).into_owned())),
+        Kind::Bytes => r.byte_vector(nullable)?.map(Value::Bytes),
+        Kind::Decimal => match r.nullable_int(nullable)? {
+            Some(exponent) => Some(Value::Decimal {
+                exponent: i32::try_from(exponent).map_err(|_| ParseError::Invalid)?,
+                mantissa: r.int()?,
+            }),
+            None => None,
+        },
+    })
+}
+
+fn in_range(kind: Kind, v: i128) -> Result<i128, ParseError> {
+    if v < kind.min() || v > kind.max() {
+        return Err(ParseError::Invalid);
+    }
+    Ok(v)
+}
+
+fn integer(v: &Value) -> Result<i128, ParseError> {
+    match v {
+        Value::UInt(v) => Ok(*v as i128),
+        Value::Int(v) => Ok(*v as i128),
+        _ => Err(ParseError::Invalid),
+    }
+}
+
+fn of_kind(kind: Kind, v: i128) -> Result<Value, ParseError> {
+    let v = in_range(kind, v)?;
+    Ok(if kind.signed() {
+        Value::Int(v as i64)
+    } else {
+        Value::UInt(v as u64)
+    })
+}
+
+fn increment(kind: Kind, prev: &Value) -> Result<Value, ParseError> {
+    of_kind(kind, integer(prev)? + 1)
+}
+
+fn add(kind: Kind, base: Option<Value>, delta: i64) -> Result<Value, ParseError> {
+    let base = base.as_ref().map(integer).transpose()?.unwrap_or(0);
+    of_kind(kind, base + delta as i128)
+}
+
+fn add_decimal(base: Option<Value>, exponent: i64, mantissa: i64) -> Result<Value, ParseError> {
+    let (e, m) = match base {
+        Some(Value::Decimal { exponent, mantissa }) => (exponent, mantissa),
+        None => (0, 0),
+        _ => return Err(ParseError::Invalid),
+    };
+    Ok(Value::Decimal {
+        exponent: i32::try_from(e as i64 + exponent).map_err(|_| ParseError::Invalid)?,
+        mantissa: m.checked_add(mantissa).ok_or(ParseError::Invalid)?,
+    })
+}
+
+fn bytes_of(v: Option<Value>) -> Result<(Vec<u8>, bool), ParseError> {
+    match v {
+        None => Ok((Vec::new(), true)),
+        Some(Value::Str(s)) => Ok((s.into_bytes(), true)),
+        Some(Value::Bytes(b)) => Ok((b, false)),
+        _ => Err(ParseError::Invalid),
+    }
+}
+
+fn of_bytes(bytes: Vec<u8>, string: bool) -> Value {
+    if string {
+        Value::Str(String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        Value::Bytes(bytes)
+    }
+}
+
+// The base with as many bytes as the tail has replaced from its end
+fn with_tail(base: Option<Value>, tail: Value) -> Result<Value, ParseError> {
+    let (mut base, _) = bytes_of(base)?;
+    let (tail, string) = bytes_of(Some(tail))?;
+    base.truncate(base.len().saturating_sub(tail.len()));
+    base.extend_from_slice(&tail);
+    Ok(of_bytes(base, string))
+}
+
+// The base with `subtract` bytes removed from its end and `diff` appended,
+// or for a negative `subtract`, -subtract - 1 removed from its front and
+// `diff` prepended
+fn with_delta(base: Option<Value>, subtract: i64, diff: Value) -> Result<Value, ParseError> {
+    let (base, _) = bytes_of(base)?;
+    let (diff, string) = bytes_of(Some(diff))?;
+    let n = if subtract < 0 {
+        -(subtract + 1)
+    } else {
+        subtract
+    } as usize;
+    if n > base.len() {
+        return Err(ParseError::Invalid);
+    }
+    let bytes = if subtract < 0 {
+        [&diff[..], &base[n..]].concat()
+    } else {
+        [&base[..base.len() - n], &diff[..]].concat()
+    };
+    Ok(of_bytes(bytes, string))
+}
+
+// Iterates the messages of a packet, such as a sequenced block's payload
+pub struct Messages<'a, 't> {
+    decoder: Decoder<'t>,
+    buf: &'a [u8],
+}
+
+impl<'a, 't> Messages<'a, 't> {
+    pub fn new(templates: &'t Templates, buf: &'a [u8]) -> Self {
+        Self {
+            decoder: Decoder::new(templates),
+            buf,
+        }
+    }
+}
+
+impl Iterator for Messages<'_, '_> {
+    type Item = Result<Message, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        match self.decoder.decode(self.buf) {
+            Ok((msg, len)) => {
+                self.buf = &self.buf[len..];
+                Some(Ok(msg))
+            }
+            Err(e) => {
+                self.buf = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    pub header: BlockHeader,
+    // The messages
+    pub payload: &'a [u8],
+}
+
+// A prefixed packet. Seqnums count packets, so n_messages is always 1.
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let seqnum = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    Ok(Packet {
+        header: BlockHeader {
+            seqnum: seqnum as u64,
+            n_messages: 1,
+            ..Default::default()
+        },
+        payload: &buf[HEADER_LEN..],
+    })
+}
+
+// A packet of messages only, sequenced by the first message's MsgSeqNum with
+// a seqnum for each message
+pub fn parse_with<'a>(templates: &Templates, buf: &'a [u8]) -> Result<Packet<'a>, ParseError> {
+    let mut seqnum = None;
+    let mut n_messages: u16 = 0;
+    for msg in Messages::new(templates, buf) {
+        let msg = msg?;
+        if seqnum.is_none() {
+            seqnum = Some(msg.seqnum().ok_or(ParseError::Invalid)?);
+        }
+        n_messages = n_messages.checked_add(1).ok_or(ParseError::Invalid)?;
+    }
+    Ok(Packet {
+        header: BlockHeader {
+            seqnum: seqnum.ok_or(ParseError::Truncated)?,
+            n_messages,
+            ..Default::default()
+        },
+        payload: buf,
+    })
+}
+
+// Stop bit encoding, for feeds and tests that write FAST
+pub fn write_uint(buf: &mut Vec<u8>, v: u64) {
+    let n = (1..10).find(|n| v >> (7 * n) == 0).unwrap_or(10);
+    for i in (0..n).rev() {
+        buf.push((v >> (7 * i)) as u8 & 0x7f);
+    }
+    *buf.last_mut().unwrap() |= STOP_BIT;
+}
+
+pub fn write_int(buf: &mut Vec<u8>, v: i64) {
+    // Enough bytes that the first one's highest data bit is the sign
+    let n = (1..10)
+        .find(|n| {
+            let rest = v >> (7 * n - 1);
+            rest == 0 || rest == -1
+        })
+        .unwrap_or(10);
+    for i in (0..n).rev() {
+        buf.push((v >> (7 * i)) as u8 & 0x7f);
+    }
+    *buf.last_mut().unwrap() |= STOP_BIT;
+}
+
+pub fn write_ascii(buf: &mut Vec<u8>, s: &str) {
+    if s.is_empty() {
+        buf.push(STOP_BIT);
+        return;
+    }
+    buf.extend(s.bytes().map(|b| b & 0x7f));
+    *buf.last_mut().unwrap() |= STOP_BIT;
+}
+
+// A presence map of `bits`, in the order fields take them
+pub fn write_pmap(buf: &mut Vec<u8>, bits: &[bool]) {
+    let n = bits.len().div_ceil(7).max(1);
+    for byte in 0..n {
+        let mut b = 0;
+        for bit in 0..7 {
+            if bits.get(byte * 7 + bit) == Some(&true) {
+                b |= 0x40 >> bit;
+            }
+        }
+        buf.push(b);
+    }
+    *buf.last_mut().unwrap() |= STOP_BIT;
+}
+
+// Just enough XML for template definitions: elements and their attributes.
+// Text, comments, processing instructions and namespace prefixes are dropped.
+mod xml {
+    #[derive(Clone, Debug, Default)]
+    pub struct Element {
+        pub name: String,
+        pub attrs: Vec<(String, String)>,
+        pub children: Vec<Element>,
+    }
+
+    impl Element {
+        pub fn attr(&self, name: &str) -> Option<&str> {
+            self.attrs
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    fn local(name: &str) -> String {
+        name.rsplit(':').next().unwrap_or(name).to_string()
+    }
+
+    fn unescape(s: &str) -> String {
+        s.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    pub fn parse(s: &str) -> Result<Element, String> {
+        // Open elements, the innermost last
+        let mut stack = vec![Element::default()];
+        let mut rest = s;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            let skipped = [("<?", "?>"), ("<!--", "-->"), ("<!", ">")]
+                .iter()
+                .find(|(open, _)| rest.starts_with(open));
+            if let Some((open, close)) = skipped {
+                let end = rest
+                    .find(close)
+                    .ok_or_else(|| format!("unterminated {}", open))?;
+                rest = &rest[end + close.len()..];
+                continue;
+            }
+            let end = tag_end(rest).ok_or("unterminated tag")?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().filter(|_| !stack.is_empty());
+                match element {
+                    Some(e) if e.name == local(name.trim()) => {
+                        stack.last_mut().unwrap().children.push(e)
+                    }
+                    _ => return Err(format!("unexpected </{}>", name.trim())),
+                }
+                continue;
+            }
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = start_tag(tag)?;
+            if empty {
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(element);
+            }
+        }
+        if stack.len() != 1 {
+            return Err(format!("unclosed <{}>", stack.last().unwrap().name));
+        }
+        let mut document = stack.pop().unwrap();
+        match document.children.len() {
+            1 => Ok(document.children.pop().unwrap()),
+            0 => Err("no elements".to_string()),
+            _ => Err("more than one root element".to_string()),
+        }
+    }
+
+    // Index of the tag's '>', skipping any in quoted attribute values
+    fn tag_end(s: &str) -> Option<usize> {
+        let mut quote = None;
+        for (i, c) in s.char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (None, '>') => return Some(i),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn start_tag(tag: &str) -> Result<Element, String> {
+        let tag = tag.trim();
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let mut element = Element {
+            name: local(&tag[..name_end]),
+            ..Default::default()
+        };
+        let mut rest = tag[name_end..].trim_start();
+        while !rest.is_empty() {
+            let eq = rest
+                .find('=')
+                .ok_or_else(|| format!("bad attribute in <{}>", element.name))?;
+            let name = rest[..eq].trim();
+            let value = rest[eq + 1..].trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| format!("unquoted attribute {}", name))?;
+            let end = value[1..]
+                .find(quote)
+                .ok_or_else(|| format!("unterminated attribute {}", name))?;
+            if !name.starts_with("xmlns") {
+                element
+                    .attrs
+                    .push((local(name), unescape(&value[1..1 + end])));
+            }
+            rest = value[end + 2..].trim_start();
+        }
+        Ok(element)
+    }
+}
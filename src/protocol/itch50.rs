@@ -0,0 +1,273 @@
+// https://www.nasdaqtrader.com/content/technicalsupport/specifications/dataproducts/NQTVITCHSpecification.pdf
+//
+// Decodes the messages of a sequenced MoldUDP64 block. All integers are big
+// endian and prices have 4 implied decimal places.
+use super::moldudp64::Messages;
+use super::ParseError;
+use crate::{Block, Sequenced};
+
+pub type Stock = [u8; 8];
+
+// type: u8, stock locate: u16, tracking number: u16, timestamp: u48
+pub const HEADER_LEN: usize = 1 + 2 + 2 + 6;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Header {
+    pub stock_locate: u16,
+    pub tracking_number: u16,
+    // Nanoseconds since midnight
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SystemEvent {
+    pub header: Header,
+    // O, S, Q, M, E or C
+    pub event_code: u8,
+}
+
+// 'A', or 'F' when attributed to a market participant
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AddOrder {
+    pub header: Header,
+    pub order_ref: u64,
+    pub side: Side,
+    pub shares: u32,
+    pub stock: Stock,
+    pub price: u32,
+    pub attribution: Option<[u8; 4]>,
+}
+
+// 'E', or 'C' when executed at a price other than the order's
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OrderExecuted {
+    pub header: Header,
+    pub order_ref: u64,
+    pub executed_shares: u32,
+    pub match_number: u64,
+    // (printable, execution price) for 'C'
+    pub price: Option<(bool, u32)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OrderCancel {
+    pub header: Header,
+    pub order_ref: u64,
+    pub cancelled_shares: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OrderDelete {
+    pub header: Header,
+    pub order_ref: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OrderReplace {
+    pub header: Header,
+    pub original_order_ref: u64,
+    pub new_order_ref: u64,
+    pub shares: u32,
+    pub price: u32,
+}
+
+// Execution of a non-displayed order
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Trade {
+    pub header: Header,
+    pub order_ref: u64,
+    pub side: Side,
+    pub shares: u32,
+    pub stock: Stock,
+    pub price: u32,
+    pub match_number: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CrossTrade {
+    pub header: Header,
+    pub shares: u64,
+    pub stock: Stock,
+    pub cross_price: u32,
+    pub match_number: u64,
+    pub cross_type: u8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BrokenTrade {
+    pub header: Header,
+    pub match_number: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Message<'a> {
+    SystemEvent(SystemEvent),
+    AddOrder(AddOrder),
+    OrderExecuted(OrderExecuted),
+    OrderCancel(OrderCancel),
+    OrderDelete(OrderDelete),
+    OrderReplace(OrderReplace),
+    Trade(Trade),
+    CrossTrade(CrossTrade),
+    BrokenTrade(BrokenTrade),
+    // Types without a struct yet, body is everything after the type byte
+    Other { kind: u8, body: &'a [u8] },
+}
+
+// Length of a message type, or None for types decoded as Other
+fn message_len(kind: u8) -> Option<usize> {
+    match kind {
+        b'S' => Some(12),
+        b'A' => Some(36),
+        b'F' => Some(40),
+        b'E' => Some(31),
+        b'C' => Some(36),
+        b'X' => Some(23),
+        b'D' => Some(19),
+        b'U' => Some(35),
+        b'P' => Some(44),
+        b'Q' => Some(40),
+        b'B' => Some(19),
+        _ => None,
+    }
+}
+
+// Reads fields in order. Lengths are checked before any field is read.
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.buf.split_at(N);
+        self.buf = rest;
+        field.try_into().unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+
+    fn u48(&mut self) -> u64 {
+        let b: [u8; 6] = self.take();
+        let mut n = [0_u8; 8];
+        n[2..].copy_from_slice(&b);
+        u64::from_be_bytes(n)
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.take())
+    }
+
+    fn side(&mut self) -> Result<Side, ParseError> {
+        match self.u8() {
+            b'B' => Ok(Side::Buy),
+            b'S' => Ok(Side::Sell),
+            _ => Err(ParseError::Invalid),
+        }
+    }
+
+    fn header(&mut self) -> Header {
+        Header {
+            stock_locate: self.u16(),
+            tracking_number: self.u16(),
+            timestamp: self.u48(),
+        }
+    }
+}
+
+pub fn parse(msg: &[u8]) -> Result<Message<'_>, ParseError> {
+    let (&kind, body) = msg.split_first().ok_or(ParseError::Truncated)?;
+    let len = match message_len(kind) {
+        Some(len) => len,
+        None => return Ok(Message::Other { kind, body }),
+    };
+    if msg.len() < len {
+        return Err(ParseError::Truncated);
+    }
+    let mut f = Fields { buf: body };
+    let header = f.header();
+
+    let msg = match kind {
+        b'S' => Message::SystemEvent(SystemEvent {
+            header,
+            event_code: f.u8(),
+        }),
+        b'A' | b'F' => Message::AddOrder(AddOrder {
+            header,
+            order_ref: f.u64(),
+            side: f.side()?,
+            shares: f.u32(),
+            stock: f.take(),
+            price: f.u32(),
+            attribution: (kind == b'F').then(|| f.take()),
+        }),
+        b'E' | b'C' => Message::OrderExecuted(OrderExecuted {
+            header,
+            order_ref: f.u64(),
+            executed_shares: f.u32(),
+            match_number: f.u64(),
+            price: (kind == b'C').then(|| (f.u8() == b'Y', f.u32())),
+        }),
+        b'X' => Message::OrderCancel(OrderCancel {
+            header,
+            order_ref: f.u64(),
+            cancelled_shares: f.u32(),
+        }),
+        b'D' => Message::OrderDelete(OrderDelete {
+            header,
+            order_ref: f.u64(),
+        }),
+        b'U' => Message::OrderReplace(OrderReplace {
+            header,
+            original_order_ref: f.u64(),
+            new_order_ref: f.u64(),
+            shares: f.u32(),
+            price: f.u32(),
+        }),
+        b'P' => Message::Trade(Trade {
+            header,
+            order_ref: f.u64(),
+            side: f.side()?,
+            shares: f.u32(),
+            stock: f.take(),
+            price: f.u32(),
+            match_number: f.u64(),
+        }),
+        b'Q' => Message::CrossTrade(CrossTrade {
+            header,
+            shares: f.u64(),
+            stock: f.take(),
+            cross_price: f.u32(),
+            match_number: f.u64(),
+            cross_type: f.u8(),
+        }),
+        b'B' => Message::BrokenTrade(BrokenTrade {
+            header,
+            match_number: f.u64(),
+        }),
+        _ => unreachable!(),
+    };
+    Ok(msg)
+}
+
+// Decoded messages of a block sequenced from a MoldUDP64 feed
+pub fn messages<P: AsRef<[u8]>>(
+    block: &Block<P>,
+) -> impl Iterator<Item = Result<Message<'_>, ParseError>> {
+    Messages::new(block.payload.as_ref(), block.n_messages()).map(|m| m.and_then(parse))
+}
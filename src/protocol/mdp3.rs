@@ -0,0 +1,111 @@
+// CME MDP 3.0 packets. All integers are little endian.
+//
+// Packet header: MsgSeqNum: u32, SendingTime: u64 (ns since the unix epoch)
+// Message: size: u16 (including this field), SBE header, SBE body
+// SBE header: block length: u16, template id: u16, schema id: u16, version: u16
+use super::ParseError;
+use crate::BlockHeader;
+
+pub const HEADER_LEN: usize = 4 + 8;
+pub const SBE_HEADER_LEN: usize = 2 + 2 + 2 + 2;
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    // Seqnums count packets, not messages, so n_messages is always 1
+    pub header: BlockHeader,
+    pub sending_time: u64,
+    // Messages following the header
+    pub payload: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Message<'a> {
+    // Length of the root block, repeating groups follow it
+    pub block_length: u16,
+    pub template_id: u16,
+    pub schema_id: u16,
+    pub version: u16,
+    // Everything after the SBE header
+    pub body: &'a [u8],
+}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let seqnum = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    Ok(Packet {
+        header: BlockHeader {
+            seqnum: seqnum as u64,
+            n_messages: 1,
+            ..Default::default()
+        },
+        sending_time: u64::from_le_bytes(buf[4..12].try_into().unwrap()),
+        payload: &buf[HEADER_LEN..],
+    })
+}
+
+impl<'a> Packet<'a> {
+    pub fn messages(&self) -> Messages<'a> {
+        Messages::new(self.payload)
+    }
+}
+
+// Iterates the SBE messages of a packet
+pub struct Messages<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Messages<'a> {
+    // `buf` is a packet's payload, such as a sequenced block's
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<Message<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let buf = self.buf;
+        if buf.len() < 2 {
+            self.buf = &[];
+            return Some(Err(ParseError::Truncated));
+        }
+        let size = u16_at(buf, 0) as usize;
+        if size < 2 + SBE_HEADER_LEN || buf.len() < size {
+            self.buf = &[];
+            return Some(Err(ParseError::Truncated));
+        }
+        self.buf = &buf[size..];
+        Some(Ok(Message {
+            block_length: u16_at(buf, 2),
+            template_id: u16_at(buf, 4),
+            schema_id: u16_at(buf, 6),
+            version: u16_at(buf, 8),
+            body: &buf[2 + SBE_HEADER_LEN..size],
+        }))
+    }
+}
+
+pub fn write_header(buf: &mut Vec<u8>, seqnum: u32, sending_time: u64) {
+    buf.extend_from_slice(&seqnum.to_le_bytes());
+    buf.extend_from_slice(&sending_time.to_le_bytes());
+}
+
+pub fn write_message(buf: &mut Vec<u8>, msg: &Message) {
+    let size = 2 + SBE_HEADER_LEN + msg.body.len();
+    buf.extend_from_slice(&(size as u16).to_le_bytes());
+    buf.extend_from_slice(&msg.block_length.to_le_bytes());
+    buf.extend_from_slice(&msg.template_id.to_le_bytes());
+    buf.extend_from_slice(&msg.schema_id.to_le_bytes());
+    buf.extend_from_slice(&msg.version.to_le_bytes());
+    buf.extend_from_slice(msg.body);
+}
@@ -0,0 +1,202 @@
+// LSE Millennium MITCH. All integers are little endian.
+//
+// Unit Header: length: u16 (of the whole packet), count: u8, market data
+// group: u8, sequence: u32 (of the first message)
+// Message: length: u8 (including itself), type: u8, body
+//
+// That is PITCH's unit header with the market data group in the unit's place,
+// so packets are framed by pitch::parse() and a Packet's unit is its group.
+// Each group has its own sequence, a replay channel to request gaps from and
+// a recovery channel to snapshot its books from.
+use super::ParseError;
+
+pub use super::pitch::{
+    packet_len, parse, write_header, write_message, Message, Messages, Packet, PacketKind,
+    HEADER_LEN,
+};
+
+// Replay and recovery channel messages
+pub const LOGIN: u8 = 0x01;
+pub const LOGIN_RESPONSE: u8 = 0x02;
+pub const REPLAY_REQUEST: u8 = 0x03;
+pub const REPLAY_RESPONSE: u8 = 0x04;
+pub const LOGOUT: u8 = 0x05;
+pub const SNAPSHOT_REQUEST: u8 = 0x81;
+pub const SNAPSHOT_RESPONSE: u8 = 0x82;
+pub const SNAPSHOT_COMPLETE: u8 = 0x83;
+
+// Status of login, replay and snapshot responses that accepts them
+pub const ACCEPTED: u8 = b'A';
+
+pub const USERNAME_LEN: usize = 6;
+pub const PASSWORD_LEN: usize = 10;
+pub const SEGMENT_LEN: usize = 6;
+
+// Replay and recovery channel session messages. Login fields and segments are
+// padded with spaces, see pitch::padded().
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Control {
+    Login {
+        username: [u8; USERNAME_LEN],
+        password: [u8; PASSWORD_LEN],
+    },
+    LoginResponse {
+        status: u8,
+    },
+    Logout,
+    ReplayRequest {
+        group: u8,
+        sequence: u32,
+        count: u16,
+    },
+    ReplayResponse {
+        group: u8,
+        sequence: u32,
+        count: u16,
+        status: u8,
+    },
+    // Sequence 0 for the books as of now, a blank segment and instrument 0
+    // for all of them
+    SnapshotRequest {
+        sequence: u32,
+        segment: [u8; SEGMENT_LEN],
+        instrument: u32,
+    },
+    // Sequence of the last message the snapshot includes
+    SnapshotResponse {
+        sequence: u32,
+        orders: u32,
+        status: u8,
+    },
+    SnapshotComplete {
+        sequence: u32,
+        segment: [u8; SEGMENT_LEN],
+        instrument: u32,
+        flags: u8,
+    },
+}
+
+fn u32_at(body: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(body[at..at + 4].try_into().unwrap())
+}
+
+fn u16_at(body: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([body[at], body[at + 1]])
+}
+
+// None for messages that aren't session messages
+pub fn parse_control(msg: &Message) -> Option<Result<Control, ParseError>> {
+    let body = msg.body;
+    let (len, control): (usize, fn(&[u8]) -> Control) = match msg.kind {
+        LOGIN => (USERNAME_LEN + PASSWORD_LEN, |b| Control::Login {
+            username: b[..6].try_into().unwrap(),
+            password: b[6..16].try_into().unwrap(),
+        }),
+        LOGIN_RESPONSE => (1, |b| Control::LoginResponse { status: b[0] }),
+        LOGOUT => (0, |_| Control::Logout),
+        REPLAY_REQUEST => (7, |b| Control::ReplayRequest {
+            group: b[0],
+            sequence: u32_at(b, 1),
+            count: u16_at(b, 5),
+        }),
+        REPLAY_RESPONSE => (8, |b| Control::ReplayResponse {
+            group: b[0],
+            sequence: u32_at(b, 1),
+            count: u16_at(b, 5),
+            status: b[7],
+        }),
+        SNAPSHOT_REQUEST => (4 + SEGMENT_LEN + 4, |b| Control::SnapshotRequest {
+            sequence: u32_at(b, 0),
+            segment: b[4..10].try_into().unwrap(),
+            instrument: u32_at(b, 10),
+        }),
+        SNAPSHOT_RESPONSE => (9, |b| Control::SnapshotResponse {
+            sequence: u32_at(b, 0),
+            orders: u32_at(b, 4),
+            status: b[8],
+        }),
+        SNAPSHOT_COMPLETE => (4 + SEGMENT_LEN + 4 + 1, |b| Control::SnapshotComplete {
+            sequence: u32_at(b, 0),
+            segment: b[4..10].try_into().unwrap(),
+            instrument: u32_at(b, 10),
+            flags: b[14],
+        }),
+        _ => return None,
+    };
+    if body.len() < len {
+        return Some(Err(ParseError::Truncated));
+    }
+    Some(Ok(control(body)))
+}
+
+// Append `control` to the packet in `buf`
+pub fn write_control(buf: &mut Vec<u8>, control: &Control) {
+    let mut body = Vec::new();
+    let kind = match *control {
+        Control::Login { username, password } => {
+            body.extend_from_slice(&username);
+            body.extend_from_slice(&password);
+            LOGIN
+        }
+        Control::LoginResponse { status } => {
+            body.push(status);
+            LOGIN_RESPONSE
+        }
+        Control::Logout => LOGOUT,
+        Control::ReplayRequest {
+            group,
+            sequence,
+            count,
+        } => {
+            body.push(group);
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&count.to_le_bytes());
+            REPLAY_REQUEST
+        }
+        Control::ReplayResponse {
+            group,
+            sequence,
+            count,
+            status,
+        } => {
+            body.push(group);
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&count.to_le_bytes());
+            body.push(status);
+            REPLAY_RESPONSE
+        }
+        Control::SnapshotRequest {
+            sequence,
+            segment,
+            instrument,
+        } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&segment);
+            body.extend_from_slice(&instrument.to_le_bytes());
+            SNAPSHOT_REQUEST
+        }
+        Control::SnapshotResponse {
+            sequence,
+            orders,
+            status,
+        } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&orders.to_le_bytes());
+            body.push(status);
+            SNAPSHOT_RESPONSE
+        }
+        Control::SnapshotComplete {
+            sequence,
+            segment,
+            instrument,
+            flags,
+        } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&segment);
+            body.extend_from_slice(&instrument.to_le_bytes());
+            body.push(flags);
+            SNAPSHOT_COMPLETE
+        }
+    };
+    write_message(buf, kind, &body);
+}
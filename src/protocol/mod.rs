@@ -0,0 +1,188 @@
+use crate::{BlockHeader, ChannelId};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub mod custom;
+pub mod fast;
+pub mod itch50;
+pub mod mdp3;
+pub mod mitch;
+pub mod moldudp64;
+pub mod opra;
+pub mod pitch;
+pub mod relay;
+pub mod soupbintcp;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    // Datagram is shorter than its header or a message length says
+    Truncated,
+    // A field holds a value the spec does not allow
+    Invalid,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Truncated => write!(f, "truncated packet"),
+            ParseError::Invalid => write!(f, "invalid field"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// A packet's block. Only MoldUDP64, PITCH, MITCH and OPRA count messages the
+// payload can be checked against, see moldudp64::Packet for `missing` and `trailing`.
+#[derive(Clone, Debug)]
+pub struct Parsed<'a> {
+    pub header: BlockHeader,
+    pub payload: &'a [u8],
+    pub missing: u16,
+    pub trailing: usize,
+}
+
+impl<'a> Parsed<'a> {
+    fn new(header: BlockHeader, payload: &'a [u8]) -> Self {
+        Self {
+            header,
+            payload,
+            missing: 0,
+            trailing: 0,
+        }
+    }
+
+    pub fn malformed(&self) -> bool {
+        self.missing > 0 || self.trailing > 0
+    }
+}
+
+// Wire framing of the datagrams a feed receives
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Protocol {
+    // seqnum: u64 BE, n_messages: u16 BE
+    #[default]
+    Raw,
+    MoldUdp64,
+    // CME MDP 3.0, seqnums count packets and there are no sessions
+    Mdp3,
+    // FIX/FAST messages after a MsgSeqNum prefix, or with templates sequenced
+    // by the messages' own MsgSeqNum fields
+    Fast(Option<Arc<fast::Templates>>),
+    // Cboe PITCH, each unit sequenced in its own channel
+    Pitch,
+    // Declared in the config file, see custom::Framing
+    Custom(Option<custom::Framing>),
+    // LSE Millennium MITCH, framed as PITCH with market data groups for units
+    Mitch,
+    // OPRA binary blocks, each line sequenced in its own channel
+    Opra,
+}
+
+impl Protocol {
+    // Seqnum of a session's first block
+    pub fn first_seqnum(&self) -> u64 {
+        match self {
+            Protocol::Raw => 0,
+            Protocol::Custom(framing) => framing.map_or(1, |f| f.first_seqnum),
+            Protocol::MoldUdp64
+            | Protocol::Mdp3
+            | Protocol::Fast(_)
+            | Protocol::Pitch
+            | Protocol::Mitch
+            | Protocol::Opra => 1,
+        }
+    }
+
+    // Channel a block parsed from a feed sequenced in `feed` goes in. PITCH
+    // units and MITCH market data groups are channels of their own, the
+    // feed's channel times 256 plus the unit.
+    pub fn channel(&self, feed: ChannelId, header: &BlockHeader) -> ChannelId {
+        match self {
+            Protocol::Pitch | Protocol::Mitch => feed << 8 | header.channel,
+            _ => feed,
+        }
+    }
+
+    // Header and payload of a block that should be sequenced. Heartbeats and
+    // end of session have no messages. Malformed packets return None.
+    pub fn parse<'a>(&self, buf: &'a [u8]) -> Option<(BlockHeader, &'a [u8])> {
+        self.parse_checked(buf).ok().map(|p| (p.header, p.payload))
+    }
+
+    // parse() saying why a packet was dropped, and whether the block's framing
+    // disagreed with its header
+    pub fn parse_checked<'a>(&self, buf: &'a [u8]) -> Result<Parsed<'a>, ParseError> {
+        match self {
+            Protocol::Raw => {
+                let header = crate::udp::parse_header(buf).ok_or(ParseError::Truncated)?;
+                Ok(Parsed::new(header, &buf[crate::udp::HEADER_LEN..]))
+            }
+            Protocol::MoldUdp64 => moldudp64::parse(buf).map(|p| Parsed {
+                header: p.header,
+                payload: p.payload,
+                missing: p.missing,
+                trailing: p.trailing,
+            }),
+            Protocol::Mdp3 => mdp3::parse(buf).map(|p| Parsed::new(p.header, p.payload)),
+            Protocol::Fast(None) => fast::parse(buf).map(|p| Parsed::new(p.header, p.payload)),
+            Protocol::Fast(Some(templates)) => {
+                fast::parse_with(templates, buf).map(|p| Parsed::new(p.header, p.payload))
+            }
+            Protocol::Pitch | Protocol::Mitch => match pitch::parse(buf)? {
+                // Only session messages, which multicast feeds don't have
+                p if p.kind == pitch::PacketKind::Unsequenced => Err(ParseError::Invalid),
+                p => Ok(Parsed {
+                    header: p.header,
+                    payload: p.payload,
+                    missing: p.missing,
+                    trailing: p.trailing,
+                }),
+            },
+            // Only framed once the config file is loaded
+            Protocol::Custom(None) => Err(ParseError::Invalid),
+            Protocol::Custom(Some(framing)) => custom::parse(framing, buf).map(|p| Parsed {
+                header: p.header,
+                payload: p.payload,
+                missing: p.missing,
+                trailing: p.trailing,
+            }),
+            Protocol::Opra => opra::parse(buf).map(|p| Parsed {
+                header: p.header,
+                payload: p.payload,
+                missing: p.missing,
+                trailing: p.trailing,
+            }),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Protocol::Raw),
+            "moldudp64" => Ok(Protocol::MoldUdp64),
+            "mdp3" => Ok(Protocol::Mdp3),
+            "fast" => Ok(Protocol::Fast(None)),
+            "pitch" => Ok(Protocol::Pitch),
+            "mitch" => Ok(Protocol::Mitch),
+            "opra" => Ok(Protocol::Opra),
+            "custom" => Ok(Protocol::Custom(None)),
+            _ => Err(format!("unknown protocol {}", s)),
+        }
+    }
+}
+
+// By name as FromStr takes it, for feeds with their own protocol in config
+// files. Templates and framings are filled in from the rest of the config.
+impl<'de> Deserialize<'de> for Protocol {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
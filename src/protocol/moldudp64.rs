@@ -0,0 +1,129 @@
+// https://www.nasdaqtrader.com/content/technicalsupport/specifications/dataproducts/moldudp64.pdf
+use super::ParseError;
+use crate::{BlockHeader, Session, SESSION_LEN};
+
+// session: [u8; 10], seqnum: u64 BE, message count: u16 BE
+pub const HEADER_LEN: usize = SESSION_LEN + 8 + 2;
+pub const END_OF_SESSION: u16 = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PacketKind {
+    Data,
+    // No messages, seqnum is the next expected seqnum
+    Heartbeat,
+    EndOfSession,
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    pub session: Session,
+    // n_messages is 0 for heartbeats and end of session
+    pub header: BlockHeader,
+    pub kind: PacketKind,
+    // Message blocks following the header, only the whole ones counted
+    pub payload: &'a [u8],
+    // Messages the count says there are but the payload is too short for.
+    // n_messages only counts those present, leaving the rest a gap.
+    pub missing: u16,
+    // Bytes after the messages counted, left out of the payload
+    pub trailing: usize,
+}
+
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let session = buf[0..SESSION_LEN].try_into().unwrap();
+    let seqnum = u64::from_be_bytes(buf[10..18].try_into().unwrap());
+    let count = u16::from_be_bytes(buf[18..20].try_into().unwrap());
+    let (kind, count) = match count {
+        0 => (PacketKind::Heartbeat, 0),
+        END_OF_SESSION => (PacketKind::EndOfSession, 0),
+        n => (PacketKind::Data, n),
+    };
+    let payload = &buf[HEADER_LEN..];
+    let (mut n_messages, mut len) = (0, 0);
+    for msg in Messages::new(payload, count) {
+        match msg {
+            Ok(msg) => len += 2 + msg.len(),
+            Err(_) => break,
+        }
+        n_messages += 1;
+    }
+
+    Ok(Packet {
+        session,
+        header: BlockHeader {
+            channel: 0,
+            session,
+            seqnum,
+            n_messages,
+            end_of_session: kind == PacketKind::EndOfSession,
+        },
+        kind,
+        payload: &payload[..len],
+        missing: count - n_messages,
+        trailing: payload.len() - len,
+    })
+}
+
+impl<'a> Packet<'a> {
+    // Whether the count disagrees with the message framing
+    pub fn malformed(&self) -> bool {
+        self.missing > 0 || self.trailing > 0
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages::new(self.payload, self.header.n_messages)
+    }
+}
+
+// Iterates the length-prefixed message blocks of a packet
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Messages<'a> {
+    // `buf` is a packet's payload, such as a sequenced block's
+    pub fn new(buf: &'a [u8], count: u16) -> Self {
+        Self {
+            buf,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<&'a [u8], ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        if self.buf.len() < 2 {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+        if self.buf.len() < 2 + len {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        let msg = &self.buf[2..2 + len];
+        self.buf = &self.buf[2 + len..];
+        Some(Ok(msg))
+    }
+}
+
+pub fn write_header(buf: &mut Vec<u8>, session: &Session, seqnum: u64, count: u16) {
+    buf.extend_from_slice(session);
+    buf.extend_from_slice(&seqnum.to_be_bytes());
+    buf.extend_from_slice(&count.to_be_bytes());
+}
+
+pub fn write_message(buf: &mut Vec<u8>, msg: &[u8]) {
+    buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+    buf.extend_from_slice(msg);
+}
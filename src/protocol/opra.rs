@@ -0,0 +1,200 @@
+// OPRA binary blocks, as on the options SIP's 48 lines. All integers are big
+// endian.
+//
+// Block header: version: u8, block size: u16 (of the whole block), data feed
+// indicator: u8, retransmission indicator: u8, session indicator: u8, block
+// sequence number: u32 (of the first message), messages in block: u8, block
+// timestamp: seconds: u32 and nanoseconds: u32, checksum: u16
+// Message header: participant id: u8, category: u8, type: u8, indicator: u8,
+// transaction id: u32, participant reference: u64, data length: u16, data
+//
+// Each line has its own sequence and is sent on an A and a B group, so a
+// line's two feeds are arbitrated in the line's channel.
+use super::ParseError;
+use crate::{BlockHeader, NO_SESSION};
+
+pub const HEADER_LEN: usize = 1 + 2 + 1 + 1 + 1 + 4 + 1 + 8 + 2;
+pub const MESSAGE_HEADER_LEN: usize = 1 + 1 + 1 + 1 + 4 + 8 + 2;
+
+pub const LINES: u8 = 48;
+
+// Retransmission indicator of a block resent for a retransmission request,
+// blocks sent the first time have a space
+pub const RETRANSMITTED: u8 = b'V';
+
+// Control messages of the category, those of the types after it end the line's
+// session
+pub const CONTROL: u8 = b'H';
+pub const END_OF_DAY: u8 = b'J';
+pub const END_OF_TRANSMISSIONS: u8 = b'K';
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    // The session is the session indicator
+    pub header: BlockHeader,
+    pub version: u8,
+    pub data_feed: u8,
+    pub retransmission: bool,
+    pub seconds: u32,
+    pub nanos: u32,
+    pub checksum: u16,
+    // Messages following the header, only the whole ones counted
+    pub payload: &'a [u8],
+    // Messages the count says there are but the block is too short for
+    pub missing: u16,
+    // Bytes after the messages counted, left out of the payload
+    pub trailing: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Message<'a> {
+    pub participant: u8,
+    pub category: u8,
+    pub kind: u8,
+    pub indicator: u8,
+    pub transaction_id: u32,
+    pub participant_reference: u64,
+    pub data: &'a [u8],
+}
+
+impl Message<'_> {
+    pub fn ends_session(&self) -> bool {
+        self.category == CONTROL && matches!(self.kind, END_OF_DAY | END_OF_TRANSMISSIONS)
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let size = u16_at(buf, 1) as usize;
+    if size < HEADER_LEN {
+        return Err(ParseError::Invalid);
+    }
+    let count = buf[10] as u16;
+    let mut session = NO_SESSION;
+    session[0] = buf[5];
+    let payload = &buf[HEADER_LEN..size.min(buf.len())];
+    let (mut n_messages, mut used, mut end_of_session) = (0, 0, false);
+    for msg in Messages::new(payload, count) {
+        match msg {
+            Ok(msg) => {
+                used += MESSAGE_HEADER_LEN + msg.data.len();
+                end_of_session |= msg.ends_session();
+            }
+            Err(_) => break,
+        }
+        n_messages += 1;
+    }
+    Ok(Packet {
+        header: BlockHeader {
+            session,
+            seqnum: u32_at(buf, 6) as u64,
+            n_messages,
+            end_of_session,
+            ..Default::default()
+        },
+        version: buf[0],
+        data_feed: buf[3],
+        retransmission: buf[4] == RETRANSMITTED,
+        seconds: u32_at(buf, 11),
+        nanos: u32_at(buf, 15),
+        checksum: u16_at(buf, 19),
+        payload: &payload[..used],
+        missing: count - n_messages,
+        trailing: buf.len() - HEADER_LEN - used,
+    })
+}
+
+impl<'a> Packet<'a> {
+    pub fn malformed(&self) -> bool {
+        self.missing > 0 || self.trailing > 0
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages::new(self.payload, self.header.n_messages)
+    }
+}
+
+// Iterates the messages of a block
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Messages<'a> {
+    // `buf` is a block's payload, such as a sequenced block's
+    pub fn new(buf: &'a [u8], count: u16) -> Self {
+        Self {
+            buf,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<Message<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let buf = self.buf;
+        if buf.len() < MESSAGE_HEADER_LEN {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        let len = MESSAGE_HEADER_LEN + u16_at(buf, 16) as usize;
+        if buf.len() < len {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        self.buf = &buf[len..];
+        Some(Ok(Message {
+            participant: buf[0],
+            category: buf[1],
+            kind: buf[2],
+            indicator: buf[3],
+            transaction_id: u32_at(buf, 4),
+            participant_reference: u64::from_be_bytes(buf[8..16].try_into().unwrap()),
+            data: &buf[MESSAGE_HEADER_LEN..len],
+        }))
+    }
+}
+
+// Start `buf` over with a block header of no messages, which write_message()
+// counts
+pub fn write_header(buf: &mut Vec<u8>, session: u8, seqnum: u32, retransmission: bool) {
+    buf.clear();
+    buf.push(0);
+    buf.extend_from_slice(&(HEADER_LEN as u16).to_be_bytes());
+    buf.push(b'O');
+    buf.push(if retransmission { RETRANSMITTED } else { b' ' });
+    buf.push(session);
+    buf.extend_from_slice(&seqnum.to_be_bytes());
+    buf.push(0);
+    buf.extend_from_slice(&[0; 8 + 2]);
+}
+
+// Append a message to the block in `buf`, updating its header
+pub fn write_message(buf: &mut Vec<u8>, category: u8, kind: u8, data: &[u8]) {
+    buf.push(b'A');
+    buf.push(category);
+    buf.push(kind);
+    buf.push(b' ');
+    buf.extend_from_slice(&[0; 4 + 8]);
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    let size = buf.len() as u16;
+    buf[1..3].copy_from_slice(&size.to_be_bytes());
+    buf[10] += 1;
+}
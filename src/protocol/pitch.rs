@@ -0,0 +1,357 @@
+// Cboe Multicast PITCH, as on its US equities and options feeds. All integers
+// are little endian.
+//
+// Sequenced Unit Header: length: u16 (of the whole packet), count: u8,
+// unit: u8, sequence: u32 (of the first message)
+// Message: length: u8 (including itself), type: u8, body
+//
+// Each unit has its own sequence, so units are channels. A count of 0 is a
+// heartbeat whose sequence is the unit's next one. Sequence 0 is unsequenced,
+// such as the session messages of spin servers and gap request proxies.
+use super::ParseError;
+use crate::BlockHeader;
+
+pub const HEADER_LEN: usize = 2 + 1 + 1 + 4;
+
+// Gap request proxy and spin server session messages
+pub const LOGIN: u8 = 0x01;
+pub const LOGIN_RESPONSE: u8 = 0x02;
+pub const GAP_REQUEST: u8 = 0x03;
+pub const GAP_RESPONSE: u8 = 0x04;
+pub const SPIN_IMAGE_AVAILABLE: u8 = 0x80;
+pub const SPIN_REQUEST: u8 = 0x81;
+pub const SPIN_RESPONSE: u8 = 0x82;
+pub const SPIN_FINISHED: u8 = 0x83;
+
+// Status of login, gap and spin responses that accepts them
+pub const ACCEPTED: u8 = b'A';
+
+pub const SESSION_SUB_ID_LEN: usize = 4;
+pub const USERNAME_LEN: usize = 4;
+pub const PASSWORD_LEN: usize = 10;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PacketKind {
+    Data,
+    // No messages, seqnum is the unit's next seqnum
+    Heartbeat,
+    Unsequenced,
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet<'a> {
+    // The channel is the unit
+    pub header: BlockHeader,
+    pub unit: u8,
+    pub kind: PacketKind,
+    // Messages following the header, only the whole ones counted
+    pub payload: &'a [u8],
+    // Messages the count says there are but the packet is too short for.
+    // n_messages only counts those present, leaving the rest a gap.
+    pub missing: u16,
+    // Bytes after the messages counted, left out of the payload
+    pub trailing: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Message<'a> {
+    pub kind: u8,
+    pub body: &'a [u8],
+}
+
+// Length of the first packet in `buf`, or None if it has not been fully
+// received, for packets over TCP
+pub fn packet_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    (buf.len() >= len).then_some(len.max(HEADER_LEN))
+}
+
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    if len < HEADER_LEN {
+        return Err(ParseError::Invalid);
+    }
+    let count = buf[2] as u16;
+    let unit = buf[3];
+    let sequence = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    let kind = match (sequence, count) {
+        (0, _) => PacketKind::Unsequenced,
+        (_, 0) => PacketKind::Heartbeat,
+        _ => PacketKind::Data,
+    };
+    // Anything past the header's length is trailing, a short packet is
+    // missing messages
+    let payload = &buf[HEADER_LEN..len.min(buf.len())];
+    let (mut n_messages, mut used) = (0, 0);
+    for msg in Messages::new(payload, count) {
+        match msg {
+            Ok(msg) => used += 2 + msg.body.len(),
+            Err(_) => break,
+        }
+        n_messages += 1;
+    }
+    Ok(Packet {
+        header: BlockHeader {
+            channel: unit as u32,
+            seqnum: sequence as u64,
+            n_messages,
+            ..Default::default()
+        },
+        unit,
+        kind,
+        payload: &payload[..used],
+        missing: count - n_messages,
+        trailing: buf.len() - HEADER_LEN - used,
+    })
+}
+
+impl<'a> Packet<'a> {
+    pub fn malformed(&self) -> bool {
+        self.missing > 0 || self.trailing > 0
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages::new(self.payload, self.header.n_messages)
+    }
+}
+
+// Iterates the messages of a packet
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Messages<'a> {
+    // `buf` is a packet's payload, such as a sequenced block's
+    pub fn new(buf: &'a [u8], count: u16) -> Self {
+        Self {
+            buf,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<Message<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let len = match self.buf.first() {
+            Some(len) => *len as usize,
+            None => {
+                self.remaining = 0;
+                return Some(Err(ParseError::Truncated));
+            }
+        };
+        if len < 2 {
+            self.remaining = 0;
+            return Some(Err(ParseError::Invalid));
+        }
+        if self.buf.len() < len {
+            self.remaining = 0;
+            return Some(Err(ParseError::Truncated));
+        }
+        let msg = Message {
+            kind: self.buf[1],
+            body: &self.buf[2..len],
+        };
+        self.buf = &self.buf[len..];
+        Some(Ok(msg))
+    }
+}
+
+// Start `buf` over with a header of no messages, which write_message() counts
+pub fn write_header(buf: &mut Vec<u8>, unit: u8, sequence: u32) {
+    buf.clear();
+    buf.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+    buf.push(0);
+    buf.push(unit);
+    buf.extend_from_slice(&sequence.to_le_bytes());
+}
+
+// Append a message to the packet in `buf`, updating its header
+pub fn write_message(buf: &mut Vec<u8>, kind: u8, body: &[u8]) {
+    buf.push((2 + body.len()) as u8);
+    buf.push(kind);
+    buf.extend_from_slice(body);
+    let len = buf.len() as u16;
+    buf[0..2].copy_from_slice(&len.to_le_bytes());
+    buf[2] += 1;
+}
+
+// Session messages of gap request proxies and spin servers. Login fields
+// are padded with spaces, see padded().
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Control {
+    Login {
+        session_sub_id: [u8; SESSION_SUB_ID_LEN],
+        username: [u8; USERNAME_LEN],
+        password: [u8; PASSWORD_LEN],
+    },
+    LoginResponse {
+        status: u8,
+    },
+    GapRequest {
+        unit: u8,
+        sequence: u32,
+        count: u16,
+    },
+    GapResponse {
+        unit: u8,
+        sequence: u32,
+        count: u16,
+        status: u8,
+    },
+    // Sequence of the last message a spin would include
+    SpinImageAvailable {
+        sequence: u32,
+    },
+    SpinRequest {
+        sequence: u32,
+    },
+    SpinResponse {
+        sequence: u32,
+        count: u32,
+        status: u8,
+    },
+    SpinFinished {
+        sequence: u32,
+    },
+}
+
+fn u32_at(body: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(body[at..at + 4].try_into().unwrap())
+}
+
+fn u16_at(body: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([body[at], body[at + 1]])
+}
+
+// An alphanumeric field, cut to fit or padded with spaces
+pub fn padded<const N: usize>(s: &str) -> [u8; N] {
+    let mut field = [b' '; N];
+    let len = s.len().min(N);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    field
+}
+
+// None for messages that aren't session messages
+pub fn parse_control(msg: &Message) -> Option<Result<Control, ParseError>> {
+    let body = msg.body;
+    let (len, control): (usize, fn(&[u8]) -> Control) = match msg.kind {
+        LOGIN => (SESSION_SUB_ID_LEN + USERNAME_LEN + 2 + PASSWORD_LEN, |b| {
+            Control::Login {
+                session_sub_id: b[..4].try_into().unwrap(),
+                username: b[4..8].try_into().unwrap(),
+                password: b[10..20].try_into().unwrap(),
+            }
+        }),
+        LOGIN_RESPONSE => (1, |b| Control::LoginResponse { status: b[0] }),
+        GAP_REQUEST => (7, |b| Control::GapRequest {
+            unit: b[0],
+            sequence: u32_at(b, 1),
+            count: u16_at(b, 5),
+        }),
+        GAP_RESPONSE => (8, |b| Control::GapResponse {
+            unit: b[0],
+            sequence: u32_at(b, 1),
+            count: u16_at(b, 5),
+            status: b[7],
+        }),
+        SPIN_IMAGE_AVAILABLE => (4, |b| Control::SpinImageAvailable {
+            sequence: u32_at(b, 0),
+        }),
+        SPIN_REQUEST => (4, |b| Control::SpinRequest {
+            sequence: u32_at(b, 0),
+        }),
+        SPIN_RESPONSE => (9, |b| Control::SpinResponse {
+            sequence: u32_at(b, 0),
+            count: u32_at(b, 4),
+            status: b[8],
+        }),
+        SPIN_FINISHED => (4, |b| Control::SpinFinished {
+            sequence: u32_at(b, 0),
+        }),
+        _ => return None,
+    };
+    if body.len() < len {
+        return Some(Err(ParseError::Truncated));
+    }
+    Some(Ok(control(body)))
+}
+
+// Append `control` to the packet in `buf`
+pub fn write_control(buf: &mut Vec<u8>, control: &Control) {
+    let mut body = Vec::new();
+    let kind = match *control {
+        Control::Login {
+            session_sub_id,
+            username,
+            password,
+        } => {
+            body.extend_from_slice(&session_sub_id);
+            body.extend_from_slice(&username);
+            body.extend_from_slice(b"  ");
+            body.extend_from_slice(&password);
+            LOGIN
+        }
+        Control::LoginResponse { status } => {
+            body.push(status);
+            LOGIN_RESPONSE
+        }
+        Control::GapRequest {
+            unit,
+            sequence,
+            count,
+        } => {
+            body.push(unit);
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&count.to_le_bytes());
+            GAP_REQUEST
+        }
+        Control::GapResponse {
+            unit,
+            sequence,
+            count,
+            status,
+        } => {
+            body.push(unit);
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&count.to_le_bytes());
+            body.push(status);
+            GAP_RESPONSE
+        }
+        Control::SpinImageAvailable { sequence } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            SPIN_IMAGE_AVAILABLE
+        }
+        Control::SpinRequest { sequence } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            SPIN_REQUEST
+        }
+        Control::SpinResponse {
+            sequence,
+            count,
+            status,
+        } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            body.extend_from_slice(&count.to_le_bytes());
+            body.push(status);
+            SPIN_RESPONSE
+        }
+        Control::SpinFinished { sequence } => {
+            body.extend_from_slice(&sequence.to_le_bytes());
+            SPIN_FINISHED
+        }
+    };
+    write_message(buf, kind, &body);
+}
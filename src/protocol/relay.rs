@@ -0,0 +1,108 @@
+// Our own framing of the sequenced stream for shipping it to another host,
+// one record per datagram. Records are numbered from 1 with no gaps, so a
+// receiver sees any datagram it lost.
+//
+// Datagram: len: u16 BE (bytes after this field), record: u64 BE, kind: u8,
+// channel: u32 BE, session: [u8; 10], seqnum: u64 BE (of the block, or the
+// first one skipped), count: u64 BE (n_messages of a block, or seqnums
+// skipped), payload
+use super::ParseError;
+use crate::{BlockHeader, ChannelId, Session, SESSION_LEN};
+use std::ops::Range;
+
+pub const HEADER_LEN: usize = 2 + 8 + 1 + 4 + SESSION_LEN + 8 + 8;
+// Most payload that fits in a UDP datagram over IPv4
+pub const MAX_PAYLOAD: usize = 65_507 - HEADER_LEN;
+const KIND_BLOCK: u8 = 0;
+const KIND_GAP: u8 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Packet<'a> {
+    Block {
+        record: u64,
+        header: BlockHeader,
+        payload: &'a [u8],
+    },
+    // Seqnums in `range` of `channel` were skipped
+    Gap {
+        record: u64,
+        channel: ChannelId,
+        range: Range<u64>,
+    },
+}
+
+pub fn parse(buf: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ParseError::Truncated);
+    }
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < 2 + len || len < HEADER_LEN - 2 {
+        return Err(ParseError::Truncated);
+    }
+    let record = u64::from_be_bytes(buf[2..10].try_into().unwrap());
+    let channel = u32::from_be_bytes(buf[11..15].try_into().unwrap());
+    let session: Session = buf[15..25].try_into().unwrap();
+    let seqnum = u64::from_be_bytes(buf[25..33].try_into().unwrap());
+    let count = u64::from_be_bytes(buf[33..41].try_into().unwrap());
+    match buf[10] {
+        KIND_BLOCK => Ok(Packet::Block {
+            record,
+            header: BlockHeader {
+                channel,
+                session,
+                seqnum,
+                n_messages: u16::try_from(count).map_err(|_| ParseError::Invalid)?,
+                end_of_session: false,
+            },
+            payload: &buf[HEADER_LEN..2 + len],
+        }),
+        KIND_GAP => Ok(Packet::Gap {
+            record,
+            channel,
+            range: seqnum..seqnum.saturating_add(count),
+        }),
+        _ => Err(ParseError::Invalid),
+    }
+}
+
+// Replaces what buf holds. `payload` must be at most MAX_PAYLOAD bytes.
+pub fn write_block(buf: &mut Vec<u8>, record: u64, header: &BlockHeader, payload: &[u8]) {
+    let (channel, session, seqnum) = (header.channel, &header.session, header.seqnum);
+    write_header(buf, record, KIND_BLOCK, channel, session, seqnum);
+    buf.extend_from_slice(&(header.n_messages as u64).to_be_bytes());
+    buf.extend_from_slice(payload);
+    let len = (buf.len() - 2) as u16;
+    buf[0..2].copy_from_slice(&len.to_be_bytes());
+}
+
+// Replaces what buf holds
+pub fn write_gap(buf: &mut Vec<u8>, record: u64, channel: ChannelId, range: Range<u64>) {
+    write_header(
+        buf,
+        record,
+        KIND_GAP,
+        channel,
+        &[0; SESSION_LEN],
+        range.start,
+    );
+    buf.extend_from_slice(&(range.end - range.start).to_be_bytes());
+    let len = (buf.len() - 2) as u16;
+    buf[0..2].copy_from_slice(&len.to_be_bytes());
+}
+
+fn write_header(
+    buf: &mut Vec<u8>,
+    record: u64,
+    kind: u8,
+    channel: ChannelId,
+    session: &Session,
+    seqnum: u64,
+) {
+    buf.clear();
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(&record.to_be_bytes());
+    buf.push(kind);
+    buf.extend_from_slice(&channel.to_be_bytes());
+    buf.extend_from_slice(session);
+    buf.extend_from_slice(&seqnum.to_be_bytes());
+}
@@ -0,0 +1,181 @@
+// https://www.nasdaqtrader.com/content/technicalsupport/specifications/dataproducts/soupbintcp.pdf
+//
+// Packet: length: u16 BE (bytes after this field), type: u8, payload. Alpha
+// fields are left justified and numeric fields right justified ASCII, both
+// padded with spaces.
+use super::ParseError;
+use crate::{Session, SESSION_LEN};
+
+pub const USERNAME_LEN: usize = 6;
+pub const PASSWORD_LEN: usize = 10;
+pub const SEQNUM_LEN: usize = 20;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServerPacket<'a> {
+    Debug(&'a [u8]),
+    // `seqnum` is the seqnum of the next sequenced packet
+    LoginAccepted { session: Session, seqnum: u64 },
+    // 'A' not authorized or 'S' session not available
+    LoginRejected { reason: u8 },
+    Sequenced(&'a [u8]),
+    Unsequenced(&'a [u8]),
+    Heartbeat,
+    EndOfSession,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientPacket<'a> {
+    Debug(&'a [u8]),
+    // An all space session requests the current one, seqnum 0 the most
+    // recent message
+    LoginRequest {
+        username: &'a [u8],
+        password: &'a [u8],
+        session: Session,
+        seqnum: u64,
+    },
+    Unsequenced(&'a [u8]),
+    Heartbeat,
+    LogoutRequest,
+}
+
+// Length of the first packet in `buf` including its length field, or None if
+// it has not been fully received
+pub fn packet_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < 2 {
+        return None;
+    }
+    let len = 2 + u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    (buf.len() >= len).then_some(len)
+}
+
+fn split(packet: &[u8]) -> Result<(u8, &[u8]), ParseError> {
+    let len = packet_len(packet).ok_or(ParseError::Truncated)?;
+    match packet[2..len].split_first() {
+        Some((kind, body)) => Ok((*kind, body)),
+        None => Err(ParseError::Truncated),
+    }
+}
+
+fn numeric(field: &[u8]) -> Result<u64, ParseError> {
+    let s = std::str::from_utf8(field).map_err(|_| ParseError::Invalid)?;
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(0);
+    }
+    s.parse().map_err(|_| ParseError::Invalid)
+}
+
+fn fixed(body: &[u8], len: usize) -> Result<(), ParseError> {
+    if body.len() < len {
+        return Err(ParseError::Truncated);
+    }
+    Ok(())
+}
+
+// `packet` starts at its length field
+pub fn parse_server(packet: &[u8]) -> Result<ServerPacket<'_>, ParseError> {
+    let (kind, body) = split(packet)?;
+    match kind {
+        b'+' => Ok(ServerPacket::Debug(body)),
+        b'A' => {
+            fixed(body, SESSION_LEN + SEQNUM_LEN)?;
+            Ok(ServerPacket::LoginAccepted {
+                session: body[..SESSION_LEN].try_into().unwrap(),
+                seqnum: numeric(&body[SESSION_LEN..SESSION_LEN + SEQNUM_LEN])?,
+            })
+        }
+        b'J' => {
+            fixed(body, 1)?;
+            Ok(ServerPacket::LoginRejected { reason: body[0] })
+        }
+        b'S' => Ok(ServerPacket::Sequenced(body)),
+        b'U' => Ok(ServerPacket::Unsequenced(body)),
+        b'H' => Ok(ServerPacket::Heartbeat),
+        b'Z' => Ok(ServerPacket::EndOfSession),
+        _ => Err(ParseError::Invalid),
+    }
+}
+
+pub fn parse_client(packet: &[u8]) -> Result<ClientPacket<'_>, ParseError> {
+    let (kind, body) = split(packet)?;
+    match kind {
+        b'+' => Ok(ClientPacket::Debug(body)),
+        b'L' => {
+            let session_at = USERNAME_LEN + PASSWORD_LEN;
+            let seqnum_at = session_at + SESSION_LEN;
+            fixed(body, seqnum_at + SEQNUM_LEN)?;
+            Ok(ClientPacket::LoginRequest {
+                username: body[..USERNAME_LEN].trim_ascii_end(),
+                password: body[USERNAME_LEN..session_at].trim_ascii_end(),
+                session: body[session_at..seqnum_at].try_into().unwrap(),
+                seqnum: numeric(&body[seqnum_at..seqnum_at + SEQNUM_LEN])?,
+            })
+        }
+        b'U' => Ok(ClientPacket::Unsequenced(body)),
+        b'R' => Ok(ClientPacket::Heartbeat),
+        b'O' => Ok(ClientPacket::LogoutRequest),
+        _ => Err(ParseError::Invalid),
+    }
+}
+
+fn write_packet(buf: &mut Vec<u8>, kind: u8, body: &[&[u8]]) {
+    let len = 1 + body.iter().map(|b| b.len()).sum::<usize>();
+    buf.extend_from_slice(&(len as u16).to_be_bytes());
+    buf.push(kind);
+    for b in body {
+        buf.extend_from_slice(b);
+    }
+}
+
+fn alpha(s: &[u8], len: usize) -> Vec<u8> {
+    let mut field = vec![b' '; len];
+    let n = s.len().min(len);
+    field[..n].copy_from_slice(&s[..n]);
+    field
+}
+
+fn write_numeric(n: u64) -> Vec<u8> {
+    format!("{:>width$}", n, width = SEQNUM_LEN).into_bytes()
+}
+
+pub fn write_login(
+    buf: &mut Vec<u8>,
+    username: &str,
+    password: &str,
+    session: &Session,
+    seqnum: u64,
+) {
+    let username = alpha(username.as_bytes(), USERNAME_LEN);
+    let password = alpha(password.as_bytes(), PASSWORD_LEN);
+    let seqnum = write_numeric(seqnum);
+    write_packet(buf, b'L', &[&username, &password, session, &seqnum]);
+}
+
+pub fn write_client_heartbeat(buf: &mut Vec<u8>) {
+    write_packet(buf, b'R', &[]);
+}
+
+pub fn write_logout(buf: &mut Vec<u8>) {
+    write_packet(buf, b'O', &[]);
+}
+
+pub fn write_login_accepted(buf: &mut Vec<u8>, session: &Session, seqnum: u64) {
+    write_packet(buf, b'A', &[session, &write_numeric(seqnum)]);
+}
+
+pub fn write_login_rejected(buf: &mut Vec<u8>, reason: u8) {
+    write_packet(buf, b'J', &[&[reason]]);
+}
+
+pub fn write_sequenced(buf: &mut Vec<u8>, msg: &[u8]) {
+    write_packet(buf, b'S', &[msg]);
+}
+
+pub fn write_server_heartbeat(buf: &mut Vec<u8>) {
+    write_packet(buf, b'H', &[]);
+}
+
+pub fn write_end_of_session(buf: &mut Vec<u8>) {
+    write_packet(buf, b'Z', &[]);
+}
@@ -0,0 +1,248 @@
+use crate::acks::Acks;
+use crate::journal::JournalWriter;
+use crate::pacing::{Pacer, Pacing};
+use crate::protocol::moldudp64::{self, END_OF_SESSION, HEADER_LEN};
+use crate::protocol::relay;
+use crate::sink::Sink;
+use crate::BlockHeader;
+use crate::{Block, ChannelId, GapReason, Payload, Session};
+use socket2::SockRef;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+// What the payload of a sequenced block holds
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PublishPayload {
+    // Anything, published as a single message
+    #[default]
+    Opaque,
+    // n_messages length-prefixed MoldUDP64 message blocks, published as is
+    MoldMessages,
+}
+
+// Re-broadcasts the sequenced stream as one MoldUDP64 session with its own
+// seqnums, which count messages from 1 with no gaps whatever channels and
+// gaps the input had.
+pub struct MulticastPublisher {
+    socket: UdpSocket,
+    dst: SocketAddrV4,
+    session: Session,
+    payload: PublishPayload,
+    // Seqnum of the next published message
+    seqnum: u64,
+    // Record of what was published for retransmission
+    journal: Option<JournalWriter>,
+    acks: Option<Acks>,
+    pacer: Option<Pacer>,
+    buf: Vec<u8>,
+}
+
+impl MulticastPublisher {
+    // Publish to `dst` out of `interface`, 0.0.0.0 for the default route
+    pub fn bind(dst: SocketAddrV4, interface: Ipv4Addr, session: Session) -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+        if dst.ip().is_multicast() {
+            // std has no IP_MULTICAST_IF
+            SockRef::from(&socket).set_multicast_if_v4(&interface)?;
+            socket.set_multicast_loop_v4(true)?;
+        }
+        Ok(Self {
+            socket,
+            dst,
+            session,
+            payload: PublishPayload::Opaque,
+            seqnum: 1,
+            journal: None,
+            acks: None,
+            pacer: None,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.socket.set_multicast_ttl_v4(ttl)
+    }
+
+    // Journal every published packet under channel 0 with its published
+    // seqnum. Records are flushed as they are published so a retransmission
+    // server reading the journal can serve them straight away.
+    pub fn set_journal(&mut self, journal: JournalWriter) {
+        self.journal = Some(journal);
+    }
+
+    // Retain every published packet until the consumers of `acks` ack it
+    pub fn set_acks(&mut self, acks: Acks) {
+        self.acks = Some(acks);
+    }
+
+    pub fn set_payload(&mut self, payload: PublishPayload) {
+        self.payload = payload;
+    }
+
+    // Hold blocks back to `pacing`'s rates, which slows the sink and leaves
+    // the backlog queued in front of it
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacer = (!pacing.is_off()).then(|| Pacer::new(pacing));
+    }
+
+    // Total time blocks were held back by pacing
+    pub fn paced(&self) -> Duration {
+        self.pacer.as_ref().map_or(Duration::ZERO, Pacer::held)
+    }
+
+    // Seqnum the next published message gets
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    fn send_header_only(&mut self, count: u16) -> io::Result<()> {
+        self.buf.clear();
+        moldudp64::write_header(&mut self.buf, &self.session, self.seqnum, count);
+        self.socket.send_to(&self.buf, self.dst)?;
+        Ok(())
+    }
+
+    // Tell listeners the session is alive while there is nothing to publish
+    pub fn heartbeat(&mut self) -> io::Result<()> {
+        self.send_header_only(0)
+    }
+
+    pub fn end_session(&mut self) -> io::Result<()> {
+        self.send_header_only(END_OF_SESSION)
+    }
+}
+
+impl Sink for MulticastPublisher {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.buf.clear();
+        let count = match self.payload {
+            PublishPayload::Opaque => 1,
+            PublishPayload::MoldMessages => block.header.n_messages,
+        };
+        moldudp64::write_header(&mut self.buf, &self.session, self.seqnum, count);
+        match self.payload {
+            PublishPayload::Opaque => moldudp64::write_message(&mut self.buf, &block.payload),
+            PublishPayload::MoldMessages => self.buf.extend_from_slice(&block.payload),
+        }
+        // Before it is sent, so it can be resent as soon as it is missed
+        if let Some(acks) = &self.acks {
+            acks.retain(self.seqnum, count, &self.buf)?;
+        }
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(self.buf.len());
+        }
+        self.socket.send_to(&self.buf, self.dst)?;
+        if let Some(journal) = &mut self.journal {
+            let header = BlockHeader {
+                channel: 0,
+                session: self.session,
+                seqnum: self.seqnum,
+                n_messages: count,
+                end_of_session: false,
+            };
+            journal.append(&header, SystemTime::now(), &self.buf[HEADER_LEN..])?;
+            journal.flush()?;
+        }
+        self.seqnum += count as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.journal {
+            Some(journal) => journal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn published(&self) -> Option<u64> {
+        Some(self.seqnum)
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.seqnum = seqnum;
+    }
+}
+
+impl Drop for MulticastPublisher {
+    fn drop(&mut self) {
+        let _ = self.end_session();
+    }
+}
+
+// Ships the sequenced stream to one host in relay framing, keeping each
+// block's channel, session and seqnum. Nothing is sent back, so a receiver
+// that isn't listening just misses datagrams, which it sees from the
+// relay's record numbers once it is.
+pub struct UnicastPublisher {
+    socket: UdpSocket,
+    dst: SocketAddr,
+    // Record number of the next datagram
+    record: u64,
+    buf: Vec<u8>,
+}
+
+impl UnicastPublisher {
+    pub fn bind(dst: SocketAddr) -> io::Result<Self> {
+        let any = match dst {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        Ok(Self {
+            socket: UdpSocket::bind(any)?,
+            dst,
+            record: 1,
+            buf: Vec::new(),
+        })
+    }
+
+    // Record number the next datagram gets
+    pub fn record(&self) -> u64 {
+        self.record
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        self.socket.send_to(&self.buf, self.dst)?;
+        self.record += 1;
+        Ok(())
+    }
+}
+
+impl Sink for UnicastPublisher {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        if block.payload.len() > relay::MAX_PAYLOAD {
+            let header = &block.header;
+            warn!(
+                channel = header.channel,
+                seqnum = header.seqnum,
+                len = block.payload.len(),
+                "block too big for a datagram, relaying a gap"
+            );
+            let range = header.seqnum..header.seqnum + header.n_messages as u64;
+            relay::write_gap(&mut self.buf, self.record, header.channel, range);
+        } else {
+            relay::write_block(&mut self.buf, self.record, &block.header, &block.payload);
+        }
+        self.send()
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        _reason: GapReason,
+    ) -> io::Result<()> {
+        relay::write_gap(&mut self.buf, self.record, channel, range);
+        self.send()
+    }
+
+    fn published(&self) -> Option<u64> {
+        Some(self.record)
+    }
+
+    fn resume_published(&mut self, record: u64) {
+        self.record = record;
+    }
+}
@@ -0,0 +1,175 @@
+// Feeds received as QUIC datagrams (RFC 9221) instead of UDP multicast, from
+// publishers that moved their feeds onto QUIC. The sequencer connects to the
+// publisher, verifying its certificate, and frames and sequences each
+// datagram like a UDP datagram of the feed's protocol. Payloads stay in the
+// datagrams quinn received them into.
+use crate::checksum::Checksum;
+use crate::metrics::FeedMetrics;
+use crate::protocol::Protocol;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::tls;
+use crate::{Block, ChannelId, Payload};
+use crossbeam_channel::Sender;
+use quinn::rustls::pki_types::CertificateDer;
+use quinn::{ClientConfig, Connection, ConnectionError, Endpoint};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::{self, Runtime};
+
+#[derive(Clone, Debug)]
+pub struct QuicFeedConfig {
+    pub server: SocketAddr,
+    // Name the server's certificate has to be for
+    pub server_name: String,
+    // Certificates of the authorities trusted to sign it
+    pub roots: Vec<CertificateDer<'static>>,
+    pub protocol: Protocol,
+    // Channel the feed's blocks are sequenced in
+    pub channel: ChannelId,
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn closed(e: ConnectionError) -> io::Error {
+    let kind = match e {
+        ConnectionError::TimedOut => io::ErrorKind::TimedOut,
+        ConnectionError::ConnectionClosed(_) | ConnectionError::ApplicationClosed(_) => {
+            io::ErrorKind::ConnectionAborted
+        }
+        _ => io::ErrorKind::ConnectionReset,
+    };
+    io::Error::new(kind, e)
+}
+
+pub use crate::tls::load_certs;
+
+pub struct QuicFeed {
+    // Drives the connection, on the feed's thread
+    runtime: Runtime,
+    // The connection closes with it
+    _endpoint: Endpoint,
+    connection: Connection,
+    protocol: Protocol,
+    channel: ChannelId,
+    metrics: Option<Arc<FeedMetrics>>,
+    checksum: Checksum,
+    shutdown: Option<Shutdown>,
+}
+
+impl QuicFeed {
+    // Connect to the server, blocking until the handshake is done
+    pub fn connect(config: &QuicFeedConfig) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let roots = tls::root_store(&config.roots)?;
+        let client = ClientConfig::with_root_certificates(Arc::new(roots)).map_err(invalid)?;
+        let local: SocketAddr = match config.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let (endpoint, connection) = runtime.block_on(async {
+            let mut endpoint = Endpoint::client(local)?;
+            endpoint.set_default_client_config(client);
+            let connecting = endpoint
+                .connect(config.server, &config.server_name)
+                .map_err(invalid)?;
+            let connection = connecting.await.map_err(closed)?;
+            Ok::<_, io::Error>((endpoint, connection))
+        })?;
+        Ok(Self {
+            runtime,
+            _endpoint: endpoint,
+            connection,
+            protocol: config.protocol.clone(),
+            channel: config.channel,
+            metrics: None,
+            checksum: Checksum::Off,
+            shutdown: None,
+        })
+    }
+
+    // Stop run() once `shutdown` is requested, even if nothing arrives
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // Count malformed datagrams in the sequencer's metrics of the feed
+    pub fn set_metrics(&mut self, metrics: Arc<FeedMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // Check and strip a trailer from every datagram before parsing it
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    // Returns Ok(None) if nothing arrives for SHUTDOWN_POLL or a datagram has
+    // nothing to sequence. Fails once the connection is closed.
+    pub fn recv(&mut self) -> io::Result<Option<Block<Payload>>> {
+        let read = self.runtime.block_on(async {
+            tokio::time::timeout(SHUTDOWN_POLL, self.connection.read_datagram()).await
+        });
+        match read {
+            Ok(Ok(datagram)) => Ok(self.block(datagram)),
+            Ok(Err(e)) => Err(closed(e)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn block<D: AsRef<[u8]> + Send + Sync + 'static>(&self, datagram: D) -> Option<Block<Payload>> {
+        let buf = datagram.as_ref();
+        let checked = match self.checksum.check(buf) {
+            Some(checked) => checked,
+            None => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.corrupt.fetch_add(1, Ordering::Relaxed);
+                }
+                return None;
+            }
+        };
+        let parsed = self.protocol.parse_checked(checked);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_parsed(&parsed);
+        }
+        let (mut header, payload) = parsed.ok().map(|p| (p.header, p.payload))?;
+        let start = (payload.as_ptr() as usize).checked_sub(buf.as_ptr() as usize);
+        let range = start
+            .map(|start| start..start + payload.len())
+            .filter(|range| range.end <= buf.len());
+        let payload = match range {
+            Some(range) => Payload::shared(Arc::new(datagram), range),
+            // Not a slice of the datagram
+            None => payload.into(),
+        };
+        header.channel = self.protocol.channel(self.channel, &header);
+        let mut b = Block::new(header, payload);
+        b.received = Some(Instant::now());
+        Some(b)
+    }
+
+    // Receive until the arbiter hangs up, shutdown is requested or the
+    // connection closes
+    pub fn run(mut self, sender: Sender<Block<Payload>>) -> io::Result<()> {
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                self.connection.close(0_u32.into(), b"shutdown");
+                return Ok(());
+            }
+            if let Some(b) = self.recv()? {
+                if sender.send(b).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::metrics::FeedId;
+use crate::pcap::PcapngWriter;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::net::SocketAddrV4;
+use std::path::Path;
+use std::thread;
+use std::time::SystemTime;
+
+// A datagram as it came off the wire, before sequencing
+#[derive(Clone, Debug)]
+pub struct RawPacket {
+    pub feed: FeedId,
+    pub ts: SystemTime,
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub data: Vec<u8>,
+}
+
+// Writes every received datagram to a pcapng file on its own thread so
+// recording never stalls the feeds. Each feed is a pcapng interface.
+pub struct RawRecorder {
+    sender: Sender<RawPacket>,
+    thread: thread::JoinHandle<io::Result<()>>,
+}
+
+impl RawRecorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let writer = PcapngWriter::new(BufWriter::new(File::create(path)?))?;
+        let (sender, receiver) = unbounded();
+        let thread = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || Self::record(writer, receiver))?;
+        Ok(Self { sender, thread })
+    }
+
+    fn record(
+        mut writer: PcapngWriter<BufWriter<File>>,
+        receiver: Receiver<RawPacket>,
+    ) -> io::Result<()> {
+        for p in receiver.iter() {
+            writer.write_udp(p.feed as u32, p.ts, p.src, p.dst, &p.data)?;
+            // Flush between bursts so a killed process loses little
+            if receiver.is_empty() {
+                writer.flush()?;
+            }
+        }
+        writer.flush()
+    }
+
+    // Handle for a feed thread to send its datagrams to
+    pub fn sender(&self) -> Sender<RawPacket> {
+        self.sender.clone()
+    }
+
+    // Wait for every sender to be dropped and the file to be flushed
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.thread.join().unwrap()
+    }
+}
@@ -0,0 +1,68 @@
+use crate::udp::{parse_header, HEADER_LEN};
+use crate::{Block, ChannelId, Payload};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+// State of a channel as of just before `seqnum`, for consumers that build
+// books. Applying blocks from `seqnum` on brings it up to date.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot<T> {
+    pub seqnum: u64,
+    pub blocks: Vec<T>,
+}
+
+// Asked for a snapshot when a gap could not be filled, so the channel is
+// resynced instead of skipping the gap
+pub trait SnapshotSource<T> {
+    fn snapshot(&mut self, channel: ChannelId) -> io::Result<Snapshot<T>>;
+}
+
+// Requests snapshots from a snapshot server over TCP.
+//
+// Request: channel: u32 BE
+// Response: seqnum: u64 BE, then frames of len: u16 BE followed by a raw
+// datagram (seqnum: u64 BE, n_messages: u16 BE, payload) like a gap fill
+// response. A frame of len 0 ends the response.
+pub struct TcpSnapshotSource {
+    addr: SocketAddr,
+    pub timeout: Duration,
+}
+
+impl TcpSnapshotSource {
+    pub fn new(addr: SocketAddr, timeout: Duration) -> Self {
+        Self { addr, timeout }
+    }
+}
+
+impl SnapshotSource<Block<Payload>> for TcpSnapshotSource {
+    fn snapshot(&mut self, channel: ChannelId) -> io::Result<Snapshot<Block<Payload>>> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(&channel.to_be_bytes())?;
+
+        let mut seqnum = [0_u8; 8];
+        stream.read_exact(&mut seqnum)?;
+        let mut snapshot = Snapshot {
+            seqnum: u64::from_be_bytes(seqnum),
+            blocks: Vec::new(),
+        };
+        let mut buf = vec![0_u8; u16::MAX as usize];
+        loop {
+            let mut len = [0_u8; 2];
+            stream.read_exact(&mut len)?;
+            let len = u16::from_be_bytes(len) as usize;
+            if len == 0 {
+                return Ok(snapshot);
+            }
+            stream.read_exact(&mut buf[..len])?;
+            let mut header = parse_header(&buf[..len])
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short frame"))?;
+            header.channel = channel;
+            snapshot
+                .blocks
+                .push(Block::new(header, buf[HEADER_LEN..len].into()));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+// The sequencer's reorder logic for one channel as a pure state machine, for
+// testing exhaustively and for places without std, such as next to an FPGA
+// feed handler. It does no I/O, reads no clock and never allocates, and only
+// uses core (`make no-std` builds it on its own). Copies of a packet from
+// any number of feeds may be given to it, the first one wins.
+//
+// The machine only keeps packets' headers. Callers keep the packets it says
+// to hold, in their own storage at slot(seqnum), and do what the actions
+// say in the order they come. Time is in whatever ticks callers count in,
+// such as ns since start.
+//
+// Packets are held in a ring of N slots, by seqnum, so only those less than
+// N seqnums ahead of the next expected one can be. A packet further ahead is
+// discarded as an overflow, and whatever was held then times out as usual.
+use core::iter::FusedIterator;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Header {
+    pub seqnum: u64,
+    pub n_messages: u16,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Discarded {
+    // Already delivered or held, or got past
+    Duplicate,
+    // Too far ahead to hold
+    Overflow,
+    // Ahead, but without messages it isn't worth holding
+    Empty,
+    // Its seqnums would run past u64::MAX
+    Invalid,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    // Deliver the packet of `seqnum`, the one given or one held
+    Deliver { seqnum: u64, n_messages: u16 },
+    // Keep the packet given until it is delivered or discarded
+    Hold { seqnum: u64 },
+    // Seqnums not received yet, such as to ask for a retransmission of
+    Missing { from: u64, to: u64 },
+    // The packet of `seqnum`, given or held, is not needed
+    Discard { seqnum: u64, reason: Discarded },
+    // Seqnums given up on after the gap timeout
+    Gap { from: u64, to: u64 },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Held {
+    seqnum: u64,
+    n_messages: u16,
+    arrived: u64,
+}
+
+pub struct Reorder<const N: usize> {
+    // Expected seqnum
+    next: u64,
+    // Seqnum after the highest held or delivered
+    high: u64,
+    // Slots below this were cleared of packets `next` got past
+    swept: u64,
+    slots: [Option<Held>; N],
+    held: usize,
+    // How long a packet is held before the gap before it is given up on
+    timeout: u64,
+}
+
+impl<const N: usize> Reorder<N> {
+    pub const fn new(next: u64, timeout: u64) -> Self {
+        Self {
+            next,
+            high: next,
+            swept: next,
+            slots: [None; N],
+            held: 0,
+            timeout,
+        }
+    }
+
+    // Where a held packet of `seqnum` is kept
+    pub fn slot(seqnum: u64) -> usize {
+        (seqnum % N as u64) as usize
+    }
+
+    pub fn next_seqnum(&self) -> u64 {
+        self.next
+    }
+
+    pub fn held(&self) -> usize {
+        self.held
+    }
+
+    // When on_tick() next has something to give up on, if anything is held
+    pub fn deadline(&self) -> Option<u64> {
+        let held = self.slots.iter().flatten();
+        held.map(|h| h.arrived.saturating_add(self.timeout)).min()
+    }
+
+    // What to do with a packet that arrived at `now`, then with the held
+    // packets it lets through
+    pub fn on_packet(&mut self, header: Header, now: u64) -> Actions<'_, N> {
+        let (seqnum, n_messages) = (header.seqnum, header.n_messages);
+        let mut first = [None, None];
+        let end = match seqnum.checked_add(n_messages as u64) {
+            Some(end) => end,
+            None => {
+                let reason = Discarded::Invalid;
+                first[0] = Some(Action::Discard { seqnum, reason });
+                return Actions {
+                    reorder: self,
+                    first,
+                    skip_to: 0,
+                };
+            }
+        };
+        if seqnum < self.next || self.is_held(seqnum) {
+            let reason = Discarded::Duplicate;
+            first[0] = Some(Action::Discard { seqnum, reason });
+        } else if seqnum == self.next {
+            first[0] = Some(Action::Deliver { seqnum, n_messages });
+            self.next = end;
+            self.high = self.high.max(end);
+        } else if seqnum - self.next >= N as u64 {
+            let reason = Discarded::Overflow;
+            first[0] = Some(Action::Discard { seqnum, reason });
+        } else {
+            if seqnum > self.high {
+                first[0] = Some(Action::Missing {
+                    from: self.high,
+                    to: seqnum,
+                });
+            }
+            first[1] = Some(match n_messages {
+                0 => Action::Discard {
+                    seqnum,
+                    reason: Discarded::Empty,
+                },
+                _ => {
+                    let arrived = now;
+                    self.hold(Held {
+                        seqnum,
+                        n_messages,
+                        arrived,
+                    });
+                    Action::Hold { seqnum }
+                }
+            });
+            self.high = self.high.max(end);
+        }
+        Actions {
+            reorder: self,
+            first,
+            skip_to: 0,
+        }
+    }
+
+    // Give up on the gaps before packets held since `timeout` before `now`,
+    // delivering those packets and what they let through
+    pub fn on_tick(&mut self, now: u64) -> Actions<'_, N> {
+        let timed_out = self.slots.iter().flatten();
+        let skip_to = timed_out
+            .filter(|h| now.saturating_sub(h.arrived) >= self.timeout)
+            .map(|h| h.seqnum)
+            .max();
+        Actions {
+            reorder: self,
+            first: [None, None],
+            skip_to: skip_to.unwrap_or(0),
+        }
+    }
+
+    fn is_held(&self, seqnum: u64) -> bool {
+        self.slots[Self::slot(seqnum)].is_some_and(|h| h.seqnum == seqnum)
+    }
+
+    fn hold(&mut self, held: Held) {
+        self.slots[Self::slot(held.seqnum)] = Some(held);
+        self.held += 1;
+    }
+
+    fn take(&mut self, seqnum: u64) -> Option<Held> {
+        let slot = &mut self.slots[Self::slot(seqnum)];
+        let held = slot.filter(|h| h.seqnum == seqnum)?;
+        *slot = None;
+        self.held -= 1;
+        Some(held)
+    }
+
+    fn next_action(&mut self, skip_to: u64) -> Option<Action> {
+        // Packets held from before `next` got past them
+        if self.held == 0 {
+            self.swept = self.next;
+        }
+        if self.next - self.swept > N as u64 {
+            // Quicker to look in every slot than at every seqnum
+            let held = self.slots.iter().flatten().map(|h| h.seqnum);
+            if let Some(seqnum) = held.filter(|s| *s < self.next).min() {
+                self.take(seqnum);
+                let reason = Discarded::Duplicate;
+                return Some(Action::Discard { seqnum, reason });
+            }
+            self.swept = self.next;
+        }
+        while self.swept < self.next {
+            let seqnum = self.swept;
+            self.swept += 1;
+            if self.take(seqnum).is_some() {
+                let reason = Discarded::Duplicate;
+                return Some(Action::Discard { seqnum, reason });
+            }
+        }
+        if let Some(held) = self.take(self.next) {
+            self.next += held.n_messages as u64;
+            self.high = self.high.max(self.next);
+            let (seqnum, n_messages) = (held.seqnum, held.n_messages);
+            return Some(Action::Deliver { seqnum, n_messages });
+        }
+        if self.next < skip_to {
+            let from = self.next;
+            // Up to the next packet held
+            let to = (from + 1..skip_to)
+                .find(|s| self.is_held(*s))
+                .unwrap_or(skip_to);
+            self.next = to;
+            return Some(Action::Gap { from, to });
+        }
+        None
+    }
+}
+
+// Every action must be taken, in order. Those not iterated to are left to
+// the actions of the next packet or tick.
+#[must_use]
+pub struct Actions<'a, const N: usize> {
+    reorder: &'a mut Reorder<N>,
+    // For the packet given, before what it lets through
+    first: [Option<Action>; 2],
+    // Give up on the seqnums before this one
+    skip_to: u64,
+}
+
+impl<const N: usize> Iterator for Actions<'_, N> {
+    type Item = Action;
+
+    fn next(&mut self) -> Option<Action> {
+        if let Some(action) = self.first.iter_mut().find_map(Option::take) {
+            return Some(action);
+        }
+        self.reorder.next_action(self.skip_to)
+    }
+}
+
+impl<const N: usize> FusedIterator for Actions<'_, N> {}
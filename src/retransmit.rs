@@ -0,0 +1,271 @@
+use crate::journal::{JournalReader, Record};
+use crate::ChannelId;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(Clone, Debug)]
+pub struct RetransmitConfig {
+    // Connections beyond this are closed straight away
+    pub max_clients: usize,
+    // Most seqnums served per request, the rest of a range is cut off
+    pub max_range: u64,
+    // Per client, None for unlimited
+    pub bytes_per_sec: Option<u64>,
+    // Idle clients are disconnected after this
+    pub timeout: Duration,
+}
+
+impl Default for RetransmitConfig {
+    fn default() -> Self {
+        Self {
+            max_clients: 16,
+            max_range: 100_000,
+            bytes_per_sec: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// Offsets of a journal's records by channel and seqnum, extended as the
+// journal grows
+pub(crate) struct Index {
+    path: PathBuf,
+    // Offset of the first record not indexed yet
+    end: u64,
+    // (seqnum, offset) in journal order. Cleared when a channel's seqnums go
+    // backwards, so only the latest session is served.
+    channels: HashMap<ChannelId, Vec<(u64, u64)>>,
+}
+
+impl Index {
+    // Nothing is indexed until the first refresh
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            end: 0,
+            channels: HashMap::new(),
+        }
+    }
+
+    fn refresh(&mut self) -> io::Result<()> {
+        let mut reader = if self.end == 0 {
+            JournalReader::open(&self.path)?
+        } else {
+            JournalReader::open_at(&self.path, self.end)?
+        };
+        loop {
+            let offset = reader.offset();
+            match reader.next() {
+                Some(Ok(r)) => {
+                    let records = self.channels.entry(r.channel).or_default();
+                    if records.last().is_some_and(|(s, _)| *s >= r.seqnum) {
+                        records.clear();
+                    }
+                    records.push((r.seqnum, offset));
+                    self.end = reader.offset();
+                }
+                // A record still being written ends the journal for now
+                Some(Err(_)) | None => {
+                    self.end = self.end.max(offset);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Offset to start reading `channel` at to find `start`
+    fn seek(&self, channel: ChannelId, start: u64) -> Option<u64> {
+        let records = self.channels.get(&channel)?;
+        // The record before the first one past start may contain start
+        let i = records.partition_point(|(s, _)| *s <= start);
+        records.get(i.saturating_sub(1)).map(|(_, offset)| *offset)
+    }
+
+    // The record of `channel` starting at `seqnum`, refreshing the index
+    // first if it hasn't got that far
+    pub(crate) fn find(&mut self, channel: ChannelId, seqnum: u64) -> io::Result<Option<Record>> {
+        let indexed = self.channels.get(&channel).and_then(|r| r.last());
+        if indexed.is_none_or(|(s, _)| *s < seqnum) {
+            self.refresh()?;
+        }
+        let offset = match self.channels.get(&channel).and_then(|r| {
+            let i = r.partition_point(|(s, _)| *s < seqnum);
+            r.get(i).filter(|(s, _)| *s == seqnum)
+        }) {
+            Some((_, offset)) => *offset,
+            None => return Ok(None),
+        };
+        match JournalReader::open_at(&self.path, offset)?.next() {
+            Some(r) => r.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// Caps a client's bytes per second
+struct RateLimit {
+    bytes_per_sec: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl RateLimit {
+    fn wait(&mut self, bytes: usize) {
+        self.sent += bytes as u64;
+        let due = Duration::from_secs_f64(self.sent as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
+
+// Serves seqnum ranges of a journal to TcpGapFiller clients, one thread per
+// connection. A connection may send any number of requests.
+//
+// Request: channel: u32 BE, start: u64 BE, end (exclusive): u64 BE
+// Response: frames of len: u16 BE followed by seqnum: u64 BE,
+// n_messages: u16 BE, payload. A frame of len 0 ends the response.
+pub struct RetransmitServer {
+    listener: TcpListener,
+    config: RetransmitConfig,
+    index: Arc<Mutex<Index>>,
+    clients: Arc<AtomicUsize>,
+}
+
+impl RetransmitServer {
+    pub fn bind<P: AsRef<Path>>(
+        addr: SocketAddr,
+        journal: P,
+        config: RetransmitConfig,
+    ) -> io::Result<Self> {
+        let mut index = Index::new(journal.as_ref());
+        index.refresh()?;
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            config,
+            index: Arc::new(Mutex::new(index)),
+            clients: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Accept clients until the listener fails
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+            if self.clients.fetch_add(1, Ordering::Relaxed) >= self.config.max_clients {
+                self.clients.fetch_sub(1, Ordering::Relaxed);
+                warn!(%peer, "retransmit client rejected, too many clients");
+                continue;
+            }
+            let client = Client {
+                stream,
+                config: self.config.clone(),
+                index: Arc::clone(&self.index),
+            };
+            let clients = Arc::clone(&self.clients);
+            thread::Builder::new()
+                .name(format!("retransmit {}", peer))
+                .spawn(move || {
+                    if let Err(e) = client.run() {
+                        warn!(%peer, error = %e, "retransmit client failed");
+                    }
+                    clients.fetch_sub(1, Ordering::Relaxed);
+                })?;
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("retransmit".to_string())
+            .spawn(move || self.run())
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    config: RetransmitConfig,
+    index: Arc<Mutex<Index>>,
+}
+
+impl Client {
+    fn run(mut self) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(self.config.timeout))?;
+        self.stream.set_write_timeout(Some(self.config.timeout))?;
+        self.stream.set_nodelay(true)?;
+        let mut rate = self.config.bytes_per_sec.map(|bytes_per_sec| RateLimit {
+            bytes_per_sec,
+            started: Instant::now(),
+            sent: 0,
+        });
+        loop {
+            let mut req = [0_u8; 20];
+            match self.stream.read_exact(&mut req) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let channel = u32::from_be_bytes(req[0..4].try_into().unwrap());
+            let start = u64::from_be_bytes(req[4..12].try_into().unwrap());
+            let end = u64::from_be_bytes(req[12..20].try_into().unwrap());
+            let end = end.min(start.saturating_add(self.config.max_range));
+            self.serve(channel, start, end, rate.as_mut())?;
+        }
+    }
+
+    fn serve(
+        &mut self,
+        channel: ChannelId,
+        start: u64,
+        end: u64,
+        mut rate: Option<&mut RateLimit>,
+    ) -> io::Result<()> {
+        let (path, offset) = {
+            let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+            index.refresh()?;
+            (index.path.clone(), index.seek(channel, start))
+        };
+        let mut w = io::BufWriter::new(&self.stream);
+        if let Some(offset) = offset {
+            for record in JournalReader::open_at(path, offset)? {
+                let r = match record {
+                    Ok(r) => r,
+                    Err(_) => break,
+                };
+                if r.channel != channel || r.seqnum.saturating_add(r.n_messages as u64) <= start {
+                    continue;
+                }
+                if r.seqnum >= end {
+                    break;
+                }
+                let len = 8 + 2 + r.payload.len();
+                if len > u16::MAX as usize {
+                    continue;
+                }
+                w.write_all(&(len as u16).to_be_bytes())?;
+                w.write_all(&r.seqnum.to_be_bytes())?;
+                w.write_all(&r.n_messages.to_be_bytes())?;
+                w.write_all(&r.payload)?;
+                if let Some(rate) = rate.as_mut() {
+                    w.flush()?;
+                    rate.wait(2 + len);
+                }
+            }
+        }
+        w.write_all(&0_u16.to_be_bytes())?;
+        w.flush()
+    }
+}
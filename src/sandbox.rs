@@ -0,0 +1,249 @@
+// Giving up what setup needed once it is done. Started as root, the sequencer
+// can bind privileged ports, force receive buffers past net.core.rmem_max and
+// open journals anywhere, then switch to an unprivileged user for good, so a
+// bad packet on a market-data network can't take over more than that user.
+// On linux a seccomp filter can also refuse syscalls it never makes once
+// running, such as exec, ptrace and mount, with EPERM.
+use std::io;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    // Primary group
+    pub gid: u32,
+}
+
+impl User {
+    #[cfg(unix)]
+    pub fn lookup(name: &str) -> io::Result<Self> {
+        use std::ffi::CString;
+
+        let c_name = CString::new(name)?;
+        let mut buf = vec![0 as libc::c_char; 16_384];
+        loop {
+            // SAFETY: all zeroes is a valid passwd, filled in from buf
+            let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut found = std::ptr::null_mut();
+            // SAFETY: every pointer outlives the call
+            let err = unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut found,
+                )
+            };
+            match err {
+                0 if found.is_null() => {
+                    let msg = format!("no user {}", name);
+                    return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+                }
+                0 => {
+                    return Ok(Self {
+                        name: name.to_string(),
+                        uid: pwd.pw_uid,
+                        gid: pwd.pw_gid,
+                    })
+                }
+                libc::ERANGE => buf.resize(buf.len() * 2, 0),
+                err => return Err(io::Error::from_raw_os_error(err)),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn lookup(_name: &str) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    // Switch every thread to the user, its primary group and its
+    // supplementary groups. Nothing if this process already runs as it,
+    // otherwise it has to be root.
+    #[cfg(unix)]
+    pub fn switch(&self) -> io::Result<()> {
+        use std::ffi::CString;
+
+        let check = |result: libc::c_int| match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        };
+        // SAFETY: these only read the process's credentials
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if (uid, gid) == (self.uid, self.gid) {
+            return Ok(());
+        }
+        let name = CString::new(self.name.as_str())?;
+        // SAFETY: name outlives the call. libc applies each to every thread.
+        unsafe {
+            check(libc::initgroups(name.as_ptr(), self.gid as _))?;
+            check(libc::setgid(self.gid))?;
+            check(libc::setuid(self.uid))?;
+        }
+        // SAFETY: as above
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::other("could get root back"));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn switch(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+// Refuse the syscalls below in every thread, now and in threads spawned
+// later. It can't be undone.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn seccomp() -> io::Result<()> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    // AUDIT_ARCH_X86_64 and AUDIT_ARCH_AARCH64 of linux/audit.h
+    #[cfg(target_arch = "x86_64")]
+    const ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const ARCH: u32 = 0xc000_00b7;
+    // Offsets of nr and arch in seccomp_data
+    const NR: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (BPF_JMP | BPF_JEQ | BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let deny = stmt(
+        BPF_RET | BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    );
+    // Syscalls of another ABI would get around the numbers below
+    let mut filter = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(BPF_LD | BPF_W | BPF_ABS, NR),
+    ];
+    // x32 syscalls have the x86_64 arch and bit 30 set
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        libc::sock_filter {
+            code: (BPF_JMP | libc::BPF_JGE | BPF_K) as u16,
+            jt: 0,
+            jf: 1,
+            k: 0x4000_0000,
+        },
+        deny,
+    ]);
+    for nr in DENIED {
+        filter.extend([jump(*nr as u32, 0, 1), deny]);
+    }
+    filter.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    // Needed to filter without CAP_SYS_ADMIN, and synced to every thread
+    // SAFETY: prctl only sets a flag of this thread
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: prog and the filter it points at outlive the call, which
+    // copies them
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        // The id of a thread that couldn't be synced
+        r if r > 0 => Err(io::Error::other(format!("thread {} not filtered", r))),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+pub fn seccomp() -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Running other programs, reaching into other processes, changing the
+// system or the filesystem tree and changing credentials
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_fsopen,
+    libc::SYS_fsmount,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_syslog,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_personality,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setfsuid,
+    libc::SYS_setfsgid,
+    libc::SYS_setgroups,
+    libc::SYS_capset,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+];
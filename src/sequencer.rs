@@ -0,0 +1,1523 @@
+use crate::arbitration::{ArbitrationPolicy, FirstWins};
+use crate::clock::{Clock, RealClock};
+use crate::events::Events;
+use crate::gapfill::GapFiller;
+use crate::gaps::{GapRecord, OpenGaps, Resolution};
+use crate::latency::Latency;
+use crate::metrics::{FeedId, FeedMetrics, Metrics, SequencerStats};
+use crate::output::{Output, OutputLimit, OutputPolicy};
+use crate::recovery::SnapshotSource;
+use crate::spool::{Codec, Spill, Spool};
+use crate::stale::StaleCheck;
+use crate::timeout::{GapTimeout, TimeoutPolicy};
+use crate::{
+    BlockMeta, ChannelId, GapReason, Sequenced, SequencedEvent, Session, BUFFER_LEN, NO_SESSION,
+};
+use crossbeam_channel::Receiver;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+type BoxedGapFiller<T> = Box<dyn GapFiller<T> + Send>;
+type BoxedClock = Box<dyn Clock + Send>;
+type BoxedSnapshotSource<T> = Box<dyn SnapshotSource<T> + Send>;
+type BoxedStaleCheck<T> = Box<dyn StaleCheck<T> + Send>;
+
+// Default backwards jump in seqnum that starts a new epoch
+pub const RESET_JUMP: u64 = 1 << 20;
+
+// What to do when a block takes a channel's reorder buffer past its limit
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    // Skip the gap at the head of the buffer and deliver up to the next gap
+    #[default]
+    FlushWithGap,
+    // Discard the block that did not fit
+    DropNewest,
+    // Stop reading feeds until timeouts drain the buffer below its limit
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flush-with-gap" => Ok(OverflowPolicy::FlushWithGap),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "block" => Ok(OverflowPolicy::Block),
+            _ => Err(format!("unknown overflow policy {}", s)),
+        }
+    }
+}
+
+// Where a channel seen for the first time starts, for joining a session
+// midway instead of treating everything before it as one big gap
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LateJoin {
+    // At the first seqnum
+    #[default]
+    Off,
+    // At the seqnum of the first block to arrive
+    First,
+    // At the highest of the feeds' first seqnums within the window after the
+    // first block, so a feed that is behind does not hold the others back.
+    // Blocks are held until the window ends.
+    Max(Duration),
+}
+
+impl FromStr for LateJoin {
+    type Err = String;
+
+    // Max takes its window from SYNC_WINDOW
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(LateJoin::Off),
+            "first" => Ok(LateJoin::First),
+            "max" => Ok(LateJoin::Max(SYNC_WINDOW)),
+            _ => Err(format!("unknown late join mode {}", s)),
+        }
+    }
+}
+
+// Default window of LateJoin::Max
+pub const SYNC_WINDOW: Duration = Duration::from_millis(50);
+
+// Bound on each channel's reorder buffer. None is unbounded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BufferLimit {
+    pub max_entries: Option<usize>,
+    // Sum of buffered blocks' Sequenced::size
+    pub max_bytes: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl BufferLimit {
+    fn exceeded(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    fn reached(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries >= max)
+            || self.max_bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+// Sequencing state of one channel's seqnum space
+struct ChannelState<T> {
+    cur_block: BlockMeta,
+    // Out of order blocks by seqnum and when they were buffered
+    new_blocks: BTreeMap<u64, (Instant, T)>,
+    new_bytes: usize,
+    // Copies from inactive feeds by seqnum and when they arrived, used when
+    // the active feed has a gap or falls `timeout` behind
+    standby: BTreeMap<u64, (Instant, T)>,
+    // (buffered at, seqnum, arrivals before it) in arrival order, so the
+    // oldest block is always at the front. Entries of blocks already
+    // delivered are skipped when they reach it.
+    deadlines: VecDeque<(Instant, u64, u64)>,
+    // Blocks of seqnums not seen before, delivered or buffered
+    arrivals: u64,
+    timeout: Box<dyn TimeoutPolicy>,
+    session: Session,
+    // Blocks still arriving from the session before a reset are stale
+    prev_session: Session,
+    // Take the next block's seqnum as the expected one
+    adopt: bool,
+    // Held while a LateJoin::Max window is open
+    sync: Option<LateSync<T>>,
+    gaps: OpenGaps,
+}
+
+struct LateSync<T> {
+    until: Instant,
+    // Feed, whether it was active, and the block in arrival order
+    held: Vec<(FeedId, bool, T)>,
+}
+
+struct FeedState {
+    metrics: Arc<FeedMetrics>,
+    last_seen: Instant,
+    down: bool,
+    // Its blocks are discarded until it is resumed
+    paused: bool,
+    // Its blocks are discarded for good
+    removed: bool,
+    // Next seqnum of each channel in this feed's own stream
+    next: HashMap<ChannelId, u64>,
+    // Seqnums each channel's stream last skipped, and when
+    skipped: HashMap<ChannelId, (Range<u64>, Instant)>,
+    // Session of each channel's latest block on this feed
+    sessions: HashMap<ChannelId, FeedSession>,
+}
+
+struct FeedSession {
+    session: Session,
+    // First and latest blocks of it
+    since: Instant,
+    last: Instant,
+}
+
+// Where a block was in its feed's own stream
+struct Advance {
+    // Seqnums it skipped
+    lost: u64,
+    // Before the seqnum the feed was up to, without being a restart
+    behind: bool,
+    // How far before, and how long after the feed skipped it
+    distance: u64,
+    delay: Duration,
+}
+
+// Seqnums kept per channel to score feeds against the first copy
+const ARRIVAL_WINDOW: usize = 4096;
+
+enum Arrival {
+    First,
+    // Behind the first copy by this much
+    Late(Duration),
+    // Before the window
+    Old,
+}
+
+// When a channel's most recent seqnums first arrived on any feed
+#[derive(Default)]
+struct FirstArrivals {
+    session: Session,
+    at: BTreeMap<u64, Instant>,
+}
+
+impl FirstArrivals {
+    fn record(&mut self, session: Session, seqnum: u64, at: Instant) -> Arrival {
+        if session != self.session {
+            self.session = session;
+            self.at.clear();
+        }
+        if let Some(first) = self.at.get(&seqnum) {
+            return Arrival::Late(at.saturating_duration_since(*first));
+        }
+        if self.at.len() >= ARRIVAL_WINDOW {
+            if self.at.first_key_value().is_some_and(|(s, _)| seqnum < *s) {
+                return Arrival::Old;
+            }
+            self.at.pop_first();
+        }
+        self.at.insert(seqnum, at);
+        Arrival::First
+    }
+}
+
+// State every channel uses
+struct Shared<T> {
+    timeout: Duration,
+    output: Output<T>,
+    gap_filler: Option<BoxedGapFiller<T>>,
+    snapshot_source: Option<BoxedSnapshotSource<T>>,
+    stale_check: Option<BoxedStaleCheck<T>>,
+    metrics: Arc<Metrics>,
+    reset_jump: Option<u64>,
+    limit: BufferLimit,
+    latency: Option<Arc<Latency>>,
+    clock: BoxedClock,
+    // Every gap closed so far, for the gap report, if gaps are recorded
+    gaps: Option<Vec<GapRecord>>,
+}
+
+// Merges blocks from N feeds into a stream ordered by seqnum within each
+// channel. Blocks that arrive early are buffered until the gap before them is
+// filled or `timeout` elapses, at which point the gap filler (if any) is asked
+// for the missing blocks before the gap is skipped.
+pub struct Sequencer<T> {
+    channels: HashMap<ChannelId, ChannelState<T>>,
+    // Session each channel last saw end, whose stragglers are stale
+    ended: HashMap<ChannelId, Session>,
+    // Feeds reporting different sessions of a channel for longer than this
+    // have diverged. None doesn't compare them.
+    session_check: Option<Duration>,
+    // Channels not sequenced until their feeds agree on the session again
+    diverged: HashSet<ChannelId>,
+    buffer_len: usize,
+    // Expected seqnum of a channel's first block
+    first_seqnum: u64,
+    late_join: LateJoin,
+    gap_timeout: GapTimeout,
+    // Channels' own gap timeouts in place of `gap_timeout`
+    channel_timeouts: HashMap<ChannelId, GapTimeout>,
+    feeds: Vec<FeedState>,
+    first_arrivals: HashMap<ChannelId, FirstArrivals>,
+    // A feed silent for longer than this is reported down
+    feed_timeout: Option<Duration>,
+    // Timeouts are only polled by the owner every tick instead of on every
+    // push
+    timeout_tick: Option<Duration>,
+    arbitration: Box<dyn ArbitrationPolicy>,
+    active: Option<FeedId>,
+    shared: Shared<T>,
+}
+
+impl<T: Sequenced> Sequencer<T> {
+    pub fn new(timeout: Duration) -> (Self, Receiver<SequencedEvent<T>>) {
+        Self::with_buffer_len(timeout, BUFFER_LEN)
+    }
+
+    // new() with the output as an Iterator
+    pub fn with_events(timeout: Duration) -> (Self, Events<T>) {
+        let (sequencer, receiver) = Self::new(timeout);
+        (sequencer, Events::new(receiver))
+    }
+
+    // `buffer_len` is the reorder buffer capacity preallocated per channel
+    pub fn with_buffer_len(
+        timeout: Duration,
+        buffer_len: usize,
+    ) -> (Self, Receiver<SequencedEvent<T>>) {
+        Self::with_output(timeout, buffer_len, OutputLimit::default())
+    }
+
+    // with_buffer_len() with the output queue bounded by `limit`. Dropping
+    // the sequencer waits for the consumer to take any backlog.
+    pub fn with_output(
+        timeout: Duration,
+        buffer_len: usize,
+        limit: OutputLimit,
+    ) -> (Self, Receiver<SequencedEvent<T>>) {
+        Self::with_spool(timeout, buffer_len, limit, None)
+    }
+
+    fn with_spool(
+        timeout: Duration,
+        buffer_len: usize,
+        limit: OutputLimit,
+        spool: Option<Spool<T>>,
+    ) -> (Self, Receiver<SequencedEvent<T>>) {
+        let metrics = Arc::<Metrics>::default();
+        let (output, receiver) = Output::new(limit, spool, Arc::clone(&metrics));
+        metrics.set_arbitration(FirstWins.name());
+        metrics.active_feed.store(usize::MAX, Relaxed);
+        let sequencer = Self {
+            channels: HashMap::new(),
+            ended: HashMap::new(),
+            session_check: None,
+            diverged: HashSet::new(),
+            buffer_len,
+            first_seqnum: 0,
+            late_join: LateJoin::Off,
+            gap_timeout: GapTimeout::Fixed,
+            channel_timeouts: HashMap::new(),
+            feeds: Vec::new(),
+            first_arrivals: HashMap::new(),
+            feed_timeout: None,
+            timeout_tick: None,
+            arbitration: Box::new(FirstWins),
+            active: None,
+            shared: Shared {
+                timeout,
+                output,
+                gap_filler: None,
+                snapshot_source: None,
+                stale_check: None,
+                metrics,
+                reset_jump: Some(RESET_JUMP),
+                limit: BufferLimit::default(),
+                latency: None,
+                clock: Box::new(RealClock),
+                gaps: None,
+            },
+        };
+        (sequencer, receiver)
+    }
+
+    // Time deadlines and feed liveness by `clock` instead of the system's.
+    // Blocks' receive timestamps must come from the same clock.
+    pub fn set_clock(&mut self, clock: BoxedClock) {
+        let now = clock.now();
+        for feed in &mut self.feeds {
+            feed.last_seen = now;
+        }
+        self.shared.clock = clock;
+    }
+
+    pub fn set_gap_filler(&mut self, gap_filler: BoxedGapFiller<T>) {
+        self.shared.gap_filler = Some(gap_filler);
+    }
+
+    // Resync channels from snapshots when the gap filler (if any) can't fill
+    // a timed out gap
+    pub fn set_snapshot_source(&mut self, snapshot_source: BoxedSnapshotSource<T>) {
+        self.shared.snapshot_source = Some(snapshot_source);
+    }
+
+    // Compare blocks behind their channel's expected seqnum with the ones
+    // delivered there, logging and counting those that differ
+    pub fn set_stale_check(&mut self, stale_check: BoxedStaleCheck<T>) {
+        self.shared.stale_check = Some(stale_check);
+    }
+
+    // A block this far below a channel's expected seqnum means the feed
+    // restarted or wrapped its counter rather than being a late duplicate.
+    // None only resets on a session id change.
+    pub fn set_reset_jump(&mut self, reset_jump: Option<u64>) {
+        self.shared.reset_jump = reset_jump;
+    }
+
+    // Seqnum channels start at, such as 1 for MoldUDP64 and MDP 3.0. Only
+    // affects channels that have not been seen yet.
+    pub fn set_first_seqnum(&mut self, seqnum: u64) {
+        self.first_seqnum = seqnum;
+    }
+
+    // Resume `channel` at `seqnum`, as from a Checkpoint, instead of at the
+    // first seqnum or a late join. Whatever was missed up to the next block
+    // received is a gap like any other, so the gap filler is asked for it.
+    // Only affects a channel that has not been seen yet.
+    pub fn set_next_seqnum(&mut self, channel: ChannelId, seqnum: u64) {
+        let (buffer_len, now) = (self.buffer_len, self.shared.clock.now());
+        self.channels
+            .entry(channel)
+            .or_insert_with(|| ChannelState::new(seqnum, buffer_len, LateJoin::Off, now));
+    }
+
+    // Start channels not seen yet where the feeds are instead of at the first
+    // seqnum, announced by a SessionReset
+    pub fn set_late_join(&mut self, late_join: LateJoin) {
+        self.late_join = late_join;
+    }
+
+    pub fn set_buffer_limit(&mut self, limit: BufferLimit) {
+        self.shared.limit = limit;
+    }
+
+    // True once the consumer has dropped its receiver. Events are discarded
+    // from then on, so there is no point sequencing further.
+    pub fn consumer_gone(&self) -> bool {
+        self.shared.output.hung_up()
+    }
+
+    // With OverflowPolicy::Block, true while a channel's reorder buffer is at
+    // its limit and no more blocks should be pushed
+    pub fn is_full(&self) -> bool {
+        let limit = &self.shared.limit;
+        limit.policy == OverflowPolicy::Block
+            && self
+                .channels
+                .values()
+                .any(|c| limit.reached(c.new_blocks.len(), c.new_bytes))
+    }
+
+    pub fn set_arbitration(&mut self, arbitration: Box<dyn ArbitrationPolicy>) {
+        self.shared.metrics.set_arbitration(arbitration.name());
+        self.arbitration = arbitration;
+        self.update_active();
+    }
+
+    // Count a failover if the policy switched feeds
+    fn update_active(&mut self) {
+        let active = self.arbitration.active();
+        if active == self.active {
+            return;
+        }
+        if let (Some(_), Some(feed)) = (self.active, active) {
+            warn!(feed, "failover");
+            self.shared.metrics.failovers.fetch_add(1, Relaxed);
+        }
+        self.active = active;
+        let metric = active.unwrap_or(usize::MAX);
+        self.shared.metrics.active_feed.store(metric, Relaxed);
+    }
+
+    // How long a gap is waited on before it is skipped. Gaps already waiting
+    // time out by the new timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.shared.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.shared.timeout
+    }
+
+    // How channels without their own gap timeout decide a gap has timed out.
+    // Channels already sequencing start over with the new policy.
+    pub fn set_gap_timeout(&mut self, gap_timeout: GapTimeout) {
+        self.gap_timeout = gap_timeout;
+        for (channel, state) in self.channels.iter_mut() {
+            if !self.channel_timeouts.contains_key(channel) {
+                state.timeout = gap_timeout.policy();
+            }
+        }
+    }
+
+    pub fn set_channel_gap_timeout(&mut self, channel: ChannelId, gap_timeout: GapTimeout) {
+        self.channel_timeouts.insert(channel, gap_timeout);
+        if let Some(state) = self.channels.get_mut(&channel) {
+            state.timeout = gap_timeout.policy();
+        }
+    }
+
+    pub fn gap_timeout(&self, channel: ChannelId) -> GapTimeout {
+        self.channel_timeouts
+            .get(&channel)
+            .copied()
+            .unwrap_or(self.gap_timeout)
+    }
+
+    pub fn set_feed_timeout(&mut self, feed_timeout: Option<Duration>) {
+        self.feed_timeout = feed_timeout;
+    }
+
+    // Refuse to sequence a channel whose live feeds have each been reporting
+    // a different session for longer than `window`, as when A and B lines
+    // are joined to different sessions, instead of interleaving the two. A
+    // feed moving to a new session has `window` for the others to follow.
+    pub fn set_session_check(&mut self, window: Option<Duration>) {
+        self.session_check = window;
+    }
+
+    // Channels whose feeds disagree on the session
+    pub fn diverged(&self) -> impl Iterator<Item = ChannelId> + '_ {
+        self.diverged.iter().copied()
+    }
+
+    // Stop checking the pushed block's channel for timeouts on every push, so
+    // timeouts cost nothing per packet and only poll_timeouts expires blocks.
+    // Whoever owns the sequencer calls it every `tick`, which the Arbiter
+    // does. Gaps then take up to `tick` longer to time out.
+    pub fn set_timeout_tick(&mut self, tick: Option<Duration>) {
+        self.timeout_tick = tick;
+    }
+
+    pub fn timeout_tick(&self) -> Option<Duration> {
+        self.timeout_tick
+    }
+
+    // Record received to sequenced latency of blocks with a receive timestamp
+    pub fn set_latency(&mut self, latency: Arc<Latency>) {
+        self.shared.latency = Some(latency);
+    }
+
+    pub fn latency(&self) -> Option<&Arc<Latency>> {
+        self.shared.latency.as_ref()
+    }
+
+    // Live counters that can be read from other threads
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.shared.metrics)
+    }
+
+    pub fn stats(&self) -> SequencerStats {
+        self.shared.metrics.snapshot()
+    }
+
+    // Keep a record of the gaps from now on, for gaps()
+    pub fn record_gaps(&mut self) {
+        self.shared.gaps.get_or_insert_with(Vec::new);
+    }
+
+    pub fn records_gaps(&self) -> bool {
+        self.shared.gaps.is_some()
+    }
+
+    // Forget the gaps closed so far, such as once they are reported at the
+    // end of a session. Those still open are kept.
+    pub fn clear_gaps(&mut self) {
+        if let Some(gaps) = &mut self.shared.gaps {
+            gaps.clear();
+        }
+    }
+
+    // Every gap recorded in the order they were noticed, those still open
+    // with how long they have been
+    pub fn gaps(&self) -> Vec<GapRecord> {
+        let now = self.shared.clock.now();
+        let mut gaps = self.shared.gaps.clone().unwrap_or_default();
+        for state in self.channels.values() {
+            gaps.extend(state.gaps.records(now));
+        }
+        gaps.sort_by_key(|gap| gap.detected);
+        gaps
+    }
+
+    // Next expected seqnum of a channel
+    pub fn seqnum(&self, channel: ChannelId) -> u64 {
+        self.channels
+            .get(&channel)
+            .map(|c| c.cur_block.seqnum)
+            .unwrap_or(0)
+    }
+
+    // Next expected seqnum of every channel seen so far
+    pub fn seqnums(&self) -> impl Iterator<Item = (ChannelId, u64)> + '_ {
+        self.channels
+            .iter()
+            .map(|(id, c)| (*id, c.cur_block.seqnum))
+    }
+
+    // Earliest time a buffered block may time out. Can be early, polling
+    // timeouts then does nothing.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.channels
+            .values()
+            .filter_map(|c| {
+                let (ts, _, _) = c.deadlines.front()?;
+                Some(*ts + c.timeout.max_wait(self.shared.timeout))
+            })
+            .min()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.channels.values().map(|c| c.new_blocks.len()).sum()
+    }
+
+    pub fn push(&mut self, b: T) {
+        self.push_from(0, b);
+    }
+
+    // Feeds added so far, removed ones included
+    pub fn n_feeds(&self) -> usize {
+        self.feeds.len()
+    }
+
+    // Register a feed so it is watched for liveness before its first packet
+    pub fn add_feed(&mut self) -> FeedId {
+        let id = self.feeds.len();
+        self.feeds.push(FeedState {
+            metrics: self.shared.metrics.feed(id),
+            last_seen: self.shared.clock.now(),
+            down: false,
+            paused: false,
+            removed: false,
+            next: HashMap::new(),
+            skipped: HashMap::new(),
+            sessions: HashMap::new(),
+        });
+        id
+    }
+
+    // Push blocks received together on `feed`, in order
+    pub fn push_batch_from<I: IntoIterator<Item = T>>(&mut self, feed_id: FeedId, blocks: I) {
+        for b in blocks {
+            self.push_from(feed_id, b);
+        }
+    }
+
+    // Push a block received on `feed`. Blocks with no messages are
+    // heartbeats that only show the feed is alive.
+    pub fn push_from(&mut self, feed_id: FeedId, b: T) {
+        while self.feeds.len() <= feed_id {
+            self.add_feed();
+        }
+        let now = self.shared.clock.now();
+        let state = &mut self.feeds[feed_id];
+        if state.paused || state.removed {
+            return;
+        }
+        state.last_seen = now;
+        if state.down {
+            info!(feed = feed_id, "feed up");
+            state.down = false;
+            self.arbitration.on_feed_up(feed_id);
+            self.shared
+                .output
+                .send(SequencedEvent::FeedUp { feed: feed_id });
+        }
+        // Seqnums no feed gets to, which would overflow
+        if b.seqnum().checked_add(b.n_messages() as u64).is_none() {
+            self.feeds[feed_id].metrics.malformed.fetch_add(1, Relaxed);
+            return;
+        }
+        let channel = b.channel();
+        let at = b.received().unwrap_or(now);
+        let advance = state.advance(channel, &b, self.shared.reset_jump, at);
+        let gap = advance.lost > 0;
+        self.arbitration.on_packet(feed_id, gap);
+        self.update_active();
+        if let Some(received) = b.received() {
+            let delay = now.saturating_duration_since(received);
+            self.shared.metrics.record_rx_delay(delay);
+        }
+        if self.session_check.is_some() && self.check_session(feed_id, channel, &b, now) {
+            self.feeds[feed_id].metrics.packets.fetch_add(1, Relaxed);
+            self.shared.metrics.diverged.fetch_add(1, Relaxed);
+            return;
+        }
+        if b.end_of_session() {
+            self.feeds[feed_id].metrics.packets.fetch_add(1, Relaxed);
+            self.end_session(channel, b.session(), Some(b.seqnum()));
+            return;
+        }
+        let feed = &self.feeds[feed_id].metrics;
+        feed.packets.fetch_add(1, Relaxed);
+        if gap {
+            feed.gaps.fetch_add(1, Relaxed);
+            feed.lost.fetch_add(advance.lost, Relaxed);
+        }
+        if b.n_messages() == 0 {
+            feed.heartbeats.fetch_add(1, Relaxed);
+            return;
+        }
+        feed.messages.fetch_add(b.n_messages() as u64, Relaxed);
+        if advance.behind {
+            feed.record_reorder(advance.distance, advance.delay);
+        }
+        let received = b.received().unwrap_or(now);
+        let first_arrivals = self.first_arrivals.entry(channel).or_default();
+        match first_arrivals.record(b.session(), b.seqnum(), received) {
+            Arrival::First => {
+                feed.first.fetch_add(1, Relaxed);
+            }
+            Arrival::Late(lateness) => feed.record_lateness(lateness),
+            Arrival::Old => {}
+        }
+        if b.session() != NO_SESSION && self.ended.get(&channel) == Some(&b.session()) {
+            ChannelState::<T>::duplicate(feed, &self.shared);
+            return;
+        }
+        let active = self.active.is_none_or(|a| a == feed_id);
+
+        let (first_seqnum, buffer_len) = (self.first_seqnum, self.buffer_len);
+        let gap_timeout = self.gap_timeout(channel);
+        let state = self.channels.entry(channel).or_insert_with(|| {
+            let mut state = ChannelState::new(first_seqnum, buffer_len, self.late_join, now);
+            state.timeout = gap_timeout.policy();
+            state
+        });
+        if let Some(sync) = &mut state.sync {
+            sync.held.push((feed_id, active, b));
+            if now >= sync.until {
+                state.finish_sync(channel, &self.feeds, &mut self.shared);
+            }
+        } else if active {
+            state.push(channel, b, feed, &mut self.shared);
+        } else {
+            state.push_standby(channel, b, feed, &mut self.shared);
+        }
+        if self.timeout_tick.is_none() {
+            state.poll_timeouts(channel, &mut self.shared);
+        }
+    }
+
+    // Record the session `feed` reports for `channel` in `b` and see whether
+    // the live feeds still agree on it. Returns true while they don't. Once
+    // they agree again the channel carries on in their session from `b`.
+    fn check_session(&mut self, feed_id: FeedId, channel: ChannelId, b: &T, now: Instant) -> bool {
+        let window = self.session_check.unwrap_or_default();
+        let session = b.session();
+        if session != NO_SESSION {
+            let sessions = &mut self.feeds[feed_id].sessions;
+            match sessions.get_mut(&channel) {
+                Some(s) if s.session == session => s.last = now,
+                _ => {
+                    let s = FeedSession {
+                        session,
+                        since: now,
+                        last: now,
+                    };
+                    sessions.insert(channel, s);
+                }
+            }
+        }
+        // Until diverged, a session only counts once a feed has stuck to it
+        // for the window
+        let was_diverged = self.diverged.contains(&channel);
+        let reporting: Vec<(FeedId, Session)> = self
+            .feeds
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| !f.down && !f.paused && !f.removed)
+            .filter_map(|(id, f)| {
+                let s = f.sessions.get(&channel)?;
+                let counts = was_diverged || now.saturating_duration_since(s.since) > window;
+                let live = now.saturating_duration_since(s.last) <= window;
+                (counts && live).then_some((id, s.session))
+            })
+            .collect();
+        let diverged = reporting.iter().any(|(_, s)| *s != reporting[0].1);
+        match (was_diverged, diverged) {
+            (false, true) => {
+                warn!(
+                    channel,
+                    feeds = reporting.len(),
+                    "feeds disagree on the session"
+                );
+                self.diverged.insert(channel);
+                let event = SequencedEvent::SessionsDiverged {
+                    channel,
+                    sessions: reporting,
+                };
+                self.shared.output.send(event);
+            }
+            (true, false) => {
+                info!(channel, "feeds agree on the session again");
+                self.diverged.remove(&channel);
+                let agreed = reporting.first().map_or(session, |(_, s)| *s);
+                let event = SequencedEvent::SessionsAgreed {
+                    channel,
+                    session: agreed,
+                };
+                self.shared.output.send(event);
+                if let Some(state) = self.channels.get_mut(&channel) {
+                    if state.session != agreed {
+                        state.reset(channel, agreed, b.seqnum(), &mut self.shared);
+                    }
+                }
+            }
+            _ => {}
+        }
+        diverged
+    }
+
+    // Flush timed out sequence numbers from every channel, and what the
+    // output queue's backlog now has room for
+    pub fn poll_timeouts(&mut self) {
+        self.shared.output.flush();
+        let now = self.shared.clock.now();
+        for (channel, state) in self.channels.iter_mut() {
+            if state.sync.as_ref().is_some_and(|s| now >= s.until) {
+                state.finish_sync(*channel, &self.feeds, &mut self.shared);
+            }
+            state.poll_timeouts(*channel, &mut self.shared);
+        }
+    }
+
+    // Deliver everything still buffered, standby copies included, reporting
+    // what is missing in between as gaps. For shutting down, later blocks
+    // that would have filled the gaps are dropped as duplicates.
+    pub fn drain(&mut self) {
+        for (channel, state) in self.channels.iter_mut() {
+            state.finish_sync(*channel, &self.feeds, &mut self.shared);
+            state.drain(*channel, GapReason::Shutdown, &mut self.shared);
+        }
+    }
+
+    // End every channel's session, as at the end of the trading day. Each is
+    // drained and forgotten after an EndOfSession, so its next block starts a
+    // new session at the first seqnum or a late join. Returns how many
+    // channels were ended.
+    pub fn end_sessions(&mut self) -> usize {
+        let channels: Vec<_> = self.channels.keys().copied().collect();
+        channels
+            .into_iter()
+            .filter(|channel| self.end_session(*channel, NO_SESSION, None))
+            .count()
+    }
+
+    // End `channel`'s session, marked by a feed at `seqnum` or else where it
+    // got to. Only the first feed's marker of a session counts. Returns false
+    // if there was nothing to end.
+    fn end_session(&mut self, channel: ChannelId, session: Session, seqnum: Option<u64>) -> bool {
+        if session != NO_SESSION && self.ended.insert(channel, session) == Some(session) {
+            return false;
+        }
+        let mut state = match self.channels.remove(&channel) {
+            Some(state) => state,
+            None => return false,
+        };
+        state.finish_sync(channel, &self.feeds, &mut self.shared);
+        let seqnum = state.end(channel, seqnum, &mut self.shared);
+        for feed in &mut self.feeds {
+            feed.next.remove(&channel);
+            feed.skipped.remove(&channel);
+        }
+        self.first_arrivals.remove(&channel);
+        info!(channel, seqnum, stats = %self.stats(), "end of session");
+        let ended = SequencedEvent::EndOfSession {
+            channel,
+            session: state.session,
+            seqnum,
+        };
+        self.shared.output.send(ended);
+        true
+    }
+
+    // Discard a feed's blocks until it is resumed, so it goes down once the
+    // feed timeout passes like a feed that went silent. Returns false if
+    // there is no such feed.
+    pub fn pause_feed(&mut self, feed: FeedId) -> bool {
+        self.set_paused(feed, true)
+    }
+
+    pub fn resume_feed(&mut self, feed: FeedId) -> bool {
+        self.set_paused(feed, false)
+    }
+
+    fn set_paused(&mut self, feed: FeedId, paused: bool) -> bool {
+        match self.feeds.get_mut(feed) {
+            Some(state) => {
+                match paused {
+                    true => info!(feed, "feed paused"),
+                    false => info!(feed, "feed resumed"),
+                }
+                state.paused = paused;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Stop sequencing a feed's blocks and arbitrating over it, failing over
+    // if it was active. Its id is not reused. Returns false if there is no
+    // such feed.
+    pub fn remove_feed(&mut self, feed: FeedId) -> bool {
+        let state = match self.feeds.get_mut(feed) {
+            Some(state) if !state.removed => state,
+            Some(_) => return true,
+            None => return false,
+        };
+        info!(feed, "feed removed");
+        state.removed = true;
+        state.down = true;
+        self.arbitration.on_feed_removed(feed);
+        self.update_active();
+        true
+    }
+
+    // Deliver what a channel has buffered, reporting what is missing in
+    // between as gaps, and start it over at the seqnum of its next block.
+    // Returns false if the channel has not been seen.
+    pub fn reset_channel(&mut self, channel: ChannelId) -> bool {
+        match self.channels.get_mut(&channel) {
+            Some(state) => {
+                warn!(channel, "resetting channel");
+                state.restart(channel, &mut self.shared);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Report feeds that have gone silent for longer than the feed timeout,
+    // and update the metrics health checks read
+    pub fn poll_liveness(&mut self) {
+        if let Some(feed_timeout) = self.feed_timeout {
+            let now = self.shared.clock.now();
+            for (feed, state) in self.feeds.iter_mut().enumerate() {
+                let silent = now.saturating_duration_since(state.last_seen);
+                if !state.down && silent > feed_timeout {
+                    warn!(feed, ?silent, "feed down");
+                    state.down = true;
+                    self.arbitration.on_feed_down(feed);
+                    let down = SequencedEvent::FeedDown { feed, silent };
+                    self.shared.output.send(down);
+                }
+            }
+            self.update_active();
+        }
+        let metrics = &self.shared.metrics;
+        metrics.polls.fetch_add(1, Relaxed);
+        let down = self.feeds.iter().filter(|f| f.down && !f.removed).count();
+        metrics.feeds_down.store(down, Relaxed);
+        let buffered = self.channels.values().map(|c| c.new_blocks.len()).sum();
+        metrics.buffered.store(buffered, Relaxed);
+    }
+}
+
+impl<T: Sequenced + Spill> Sequencer<T> {
+    // with_buffer_len() with blocks beyond `high_water` queued for the
+    // consumer spooled to a temporary file in `dir` and replayed as it
+    // catches up, so a stalled consumer neither stops the feeds nor loses
+    // data
+    pub fn with_spill(
+        timeout: Duration,
+        buffer_len: usize,
+        high_water: usize,
+        dir: &Path,
+    ) -> io::Result<(Self, Receiver<SequencedEvent<T>>)> {
+        let codec = Codec {
+            spill: T::spill,
+            unspill: T::unspill,
+            received: T::received,
+        };
+        let spool = Spool::create(dir, codec)?;
+        let limit = OutputLimit {
+            capacity: Some(high_water),
+            policy: OutputPolicy::Block,
+        };
+        Ok(Self::with_spool(timeout, buffer_len, limit, Some(spool)))
+    }
+}
+
+// Seqnum after `b`'s messages. Those of blocks pushed can't overflow, but a
+// gap filler's or a snapshot's could.
+fn end<T: Sequenced>(b: &T) -> u64 {
+    b.seqnum().saturating_add(b.n_messages() as u64)
+}
+
+impl FeedState {
+    // Track the feed's own stream. Returns true if `b` skipped seqnums of it.
+    fn advance<T: Sequenced>(
+        &mut self,
+        channel: ChannelId,
+        b: &T,
+        reset_jump: Option<u64>,
+        now: Instant,
+    ) -> Advance {
+        let end = b.seqnum().saturating_add(b.n_messages() as u64);
+        let next = self.next.entry(channel).or_insert(b.seqnum());
+        let lost = b.seqnum().saturating_sub(*next);
+        let restarted = reset_jump.is_some_and(|jump| b.seqnum().saturating_add(jump) < *next);
+        let behind = b.seqnum() < *next && !restarted;
+        let distance = if behind { *next - b.seqnum() } else { 0 };
+        let delay = match self.skipped.get(&channel) {
+            Some((skipped, at)) if behind && skipped.contains(&b.seqnum()) => {
+                now.saturating_duration_since(*at)
+            }
+            _ => Duration::ZERO,
+        };
+        if lost > 0 {
+            self.skipped.insert(channel, (*next..b.seqnum(), now));
+        }
+        if end > *next || restarted {
+            *next = end;
+        }
+        Advance {
+            lost,
+            behind,
+            distance,
+            delay,
+        }
+    }
+}
+
+impl<T: Sequenced> Shared<T> {
+    fn block(&self, b: T) {
+        if let (Some(latency), Some(received)) = (&self.latency, b.received()) {
+            latency.record_sequenced(received);
+        }
+        self.output.send(SequencedEvent::Block(b));
+    }
+
+    fn gap(&self, channel: ChannelId, from: u64, to: u64, reason: GapReason) {
+        let gap = SequencedEvent::Gap {
+            channel,
+            from,
+            to,
+            reason,
+        };
+        self.output.send(gap);
+    }
+}
+
+impl<T: Sequenced> ChannelState<T> {
+    fn new(seqnum: u64, buffer_len: usize, late_join: LateJoin, now: Instant) -> Self {
+        let sync = match late_join {
+            LateJoin::Max(window) => Some(LateSync {
+                until: now + window,
+                held: Vec::new(),
+            }),
+            LateJoin::Off | LateJoin::First => None,
+        };
+        Self {
+            cur_block: BlockMeta { seqnum, ts: now },
+            new_blocks: BTreeMap::new(),
+            new_bytes: 0,
+            standby: BTreeMap::new(),
+            deadlines: VecDeque::with_capacity(buffer_len),
+            arrivals: 0,
+            timeout: GapTimeout::Fixed.policy(),
+            session: NO_SESSION,
+            prev_session: NO_SESSION,
+            adopt: late_join == LateJoin::First,
+            sync,
+            gaps: OpenGaps::default(),
+        }
+    }
+
+    // Start at the highest of the feeds' first seqnums held in the sync
+    // window, dropping what was held from before it
+    fn finish_sync(&mut self, channel: ChannelId, feeds: &[FeedState], shared: &mut Shared<T>) {
+        let mut held = match self.sync.take() {
+            Some(sync) => sync.held,
+            None => return,
+        };
+        let mut firsts = HashMap::new();
+        for (feed, _, b) in &held {
+            let first = firsts.entry(*feed).or_insert(b.seqnum());
+            *first = b.seqnum().min(*first);
+        }
+        let start = match firsts.values().max() {
+            Some(start) => *start,
+            None => return,
+        };
+        info!(channel, seqnum = start, feeds = firsts.len(), "late join");
+        if start != self.cur_block.seqnum {
+            let session = held
+                .iter()
+                .find(|(_, _, b)| b.seqnum() == start)
+                .map_or(NO_SESSION, |(_, _, b)| b.session());
+            self.reset(channel, session, start, shared);
+        }
+        // Stable, so copies of a seqnum stay in arrival order
+        held.sort_by_key(|(_, _, b)| b.seqnum());
+        for (feed, active, b) in held {
+            if b.seqnum() < start {
+                continue;
+            }
+            let metrics = &feeds[feed].metrics;
+            match active {
+                true => self.push(channel, b, metrics, shared),
+                false => self.push_standby(channel, b, metrics, shared),
+            }
+        }
+    }
+
+    // Blocks below the expected seqnum or already buffered are duplicates.
+    // The first copy to arrive is the one delivered.
+    fn push(&mut self, channel: ChannelId, b: T, feed: &FeedMetrics, shared: &mut Shared<T>) {
+        // Kernel timestamps across feeds are not quite in arrival order, so a
+        // deadline can expire a little after one queued before it
+        self.cur_block.ts = b.received().unwrap_or_else(|| shared.clock.now());
+        let session = b.session();
+        if self.adopt {
+            self.adopt = false;
+            if b.seqnum() != self.cur_block.seqnum {
+                self.reset(channel, session, b.seqnum(), shared);
+            }
+        } else if session != NO_SESSION && session != self.session {
+            if self.session == NO_SESSION {
+                self.session = session;
+            } else if session == self.prev_session {
+                Self::duplicate(feed, shared);
+                return;
+            } else {
+                self.reset(channel, session, b.seqnum(), shared);
+            }
+        } else if let Some(jump) = shared.reset_jump {
+            if b.seqnum().saturating_add(jump) < self.cur_block.seqnum {
+                self.reset(channel, session, b.seqnum(), shared);
+            }
+        }
+
+        if b.seqnum() == self.cur_block.seqnum {
+            if let Some((_, (opened, _))) = self.new_blocks.first_key_value() {
+                let waited = self.cur_block.ts.saturating_duration_since(*opened);
+                self.timeout.on_filled(waited);
+            }
+            self.arrived(self.cur_block.ts);
+            let from = self.cur_block.seqnum;
+            self.cur_block.seqnum = end(&b);
+            shared.block(b);
+            self.resolved(from, Resolution::Recovered, shared);
+        } else if b.seqnum() > self.cur_block.seqnum {
+            if self.new_blocks.is_empty() {
+                shared.metrics.gaps.fetch_add(1, Relaxed);
+            }
+            let received = self.received_end();
+            let limit = shared.limit;
+            let (len, bytes) = (self.new_blocks.len() + 1, self.new_bytes + b.size());
+            match self.new_blocks.entry(b.seqnum()) {
+                Entry::Occupied(_) => Self::duplicate(feed, shared),
+                Entry::Vacant(e)
+                    if limit.policy == OverflowPolicy::DropNewest && limit.exceeded(len, bytes) =>
+                {
+                    warn!(channel, seqnum = e.key(), "overflow, dropping");
+                    shared.metrics.overflows.fetch_add(1, Relaxed);
+                }
+                Entry::Vacant(e) => {
+                    debug!(
+                        channel,
+                        seqnum = e.key(),
+                        expected = self.cur_block.seqnum,
+                        "out of order"
+                    );
+                    let (ts, seqnum) = (self.cur_block.ts, *e.key());
+                    e.insert((ts, b));
+                    if seqnum > received && shared.gaps.is_some() {
+                        self.gaps.open(channel, received..seqnum, ts);
+                    }
+                    self.arrived(ts);
+                    self.deadlines.push_back((ts, seqnum, self.arrivals));
+                    self.new_bytes = bytes;
+                    shared.metrics.record_depth(len);
+                    self.check_limit(channel, shared);
+                }
+            }
+        } else {
+            Self::stale(channel, &b, feed, shared);
+        }
+        self.flush_in_order(shared);
+    }
+
+    fn arrived(&mut self, now: Instant) {
+        self.arrivals += 1;
+        self.timeout.on_arrival(now);
+    }
+
+    // Keep a copy from an inactive feed in case the active one misses it
+    fn push_standby(
+        &mut self,
+        channel: ChannelId,
+        b: T,
+        feed: &FeedMetrics,
+        shared: &mut Shared<T>,
+    ) {
+        let seqnum = b.seqnum();
+        let old = b.session() != NO_SESSION && b.session() != self.session;
+        if old || self.new_blocks.contains_key(&seqnum) {
+            Self::duplicate(feed, shared);
+            return;
+        }
+        if seqnum < self.cur_block.seqnum {
+            Self::stale(channel, &b, feed, shared);
+            return;
+        }
+        match self.standby.entry(seqnum) {
+            Entry::Occupied(_) => Self::duplicate(feed, shared),
+            Entry::Vacant(e) => {
+                e.insert((b.received().unwrap_or_else(|| shared.clock.now()), b));
+                feed.standby.fetch_add(1, Relaxed);
+            }
+        }
+        self.flush_in_order(shared);
+    }
+
+    // The standby copy of the next seqnum, if the active feed has a gap
+    // there or has fallen the gap timeout behind
+    fn take_standby(&mut self, timeout: Duration, now: Instant) -> Option<T> {
+        let (ts, _) = self.standby.get(&self.cur_block.seqnum)?;
+        let waited = now.saturating_duration_since(*ts);
+        if self.new_blocks.is_empty() && waited <= self.timeout.max_wait(timeout) {
+            return None;
+        }
+        self.standby.remove(&self.cur_block.seqnum).map(|(_, b)| b)
+    }
+
+    fn duplicate(feed: &FeedMetrics, shared: &Shared<T>) {
+        shared.metrics.duplicates.fetch_add(1, Relaxed);
+        feed.duplicates.fetch_add(1, Relaxed);
+    }
+
+    // A duplicate of a seqnum already delivered or skipped
+    fn stale(channel: ChannelId, b: &T, feed: &FeedMetrics, shared: &mut Shared<T>) {
+        Self::duplicate(feed, shared);
+        shared.metrics.stale.fetch_add(1, Relaxed);
+        let check = match shared.stale_check.as_mut() {
+            Some(check) => check,
+            None => return,
+        };
+        match check.matches(b) {
+            Ok(Some(false)) => {
+                warn!(
+                    channel,
+                    seqnum = b.seqnum(),
+                    "stale copy differs from the block delivered"
+                );
+                shared.metrics.mismatched.fetch_add(1, Relaxed);
+                feed.mismatched.fetch_add(1, Relaxed);
+            }
+            Ok(_) => {}
+            Err(e) => debug!(channel, seqnum = b.seqnum(), error = %e, "stale check failed"),
+        }
+    }
+
+    // Deliver what is left of the old epoch and start a new one at `seqnum`
+    fn reset(&mut self, channel: ChannelId, session: Session, seqnum: u64, shared: &mut Shared<T>) {
+        warn!(
+            channel,
+            seqnum,
+            expected = self.cur_block.seqnum,
+            "session reset"
+        );
+        while let Some(b) = self.pop_head() {
+            self.skip_to(channel, b, GapReason::SessionReset, shared);
+        }
+        self.deadlines.clear();
+        self.standby.clear();
+
+        shared.metrics.resets.fetch_add(1, Relaxed);
+        self.prev_session = self.session;
+        self.session = session;
+        self.cur_block.seqnum = seqnum;
+        let reset = SequencedEvent::SessionReset {
+            channel,
+            session,
+            seqnum,
+        };
+        shared.output.send(reset);
+    }
+
+    // Deliver what is buffered and adopt the next block's seqnum
+    fn restart(&mut self, channel: ChannelId, shared: &mut Shared<T>) {
+        while let Some(b) = self.pop_head() {
+            self.skip_to(channel, b, GapReason::SessionReset, shared);
+        }
+        self.deadlines.clear();
+        self.standby.clear();
+        self.adopt = true;
+    }
+
+    fn take(&mut self, seqnum: u64) -> Option<T> {
+        let (_, b) = self.new_blocks.remove(&seqnum)?;
+        self.new_bytes -= b.size();
+        Some(b)
+    }
+
+    // Remove the lowest buffered block
+    fn pop_head(&mut self) -> Option<T> {
+        let (_, (_, b)) = self.new_blocks.pop_first()?;
+        self.new_bytes -= b.size();
+        Some(b)
+    }
+
+    // Deliver `b`, reporting the seqnums before it that were never received
+    fn skip_to(&mut self, channel: ChannelId, b: T, reason: GapReason, shared: &mut Shared<T>) {
+        if b.seqnum() > self.cur_block.seqnum {
+            let (from, to) = (self.cur_block.seqnum, b.seqnum());
+            shared.metrics.dropped.fetch_add(to - from, Relaxed);
+            shared.gap(channel, from, to, reason);
+            self.cur_block.seqnum = to;
+            self.resolved(from, Resolution::Abandoned(reason), shared);
+        }
+        let from = self.cur_block.seqnum;
+        self.cur_block.seqnum = end(&b);
+        shared.block(b);
+        self.resolved(from, Resolution::Recovered, shared);
+    }
+
+    // The seqnums from `from` up to the expected one were got past by `how`
+    fn resolved(&mut self, from: u64, how: Resolution, shared: &mut Shared<T>) {
+        if let (false, Some(closed)) = (self.gaps.is_empty(), &mut shared.gaps) {
+            let now = shared.clock.now();
+            let got_past = from..self.cur_block.seqnum;
+            self.gaps.resolve(got_past, how, now, closed);
+        }
+    }
+
+    // Seqnum after the highest block received, delivered or buffered
+    fn received_end(&self) -> u64 {
+        let buffered = self.new_blocks.last_key_value();
+        let end = buffered.map_or(0, |(_, (_, b))| end(b));
+        end.max(self.cur_block.seqnum)
+    }
+
+    // Deliver what is buffered and what the gap filler has up to `seqnum`,
+    // where a feed said the session ended. Returns the seqnum it ended at.
+    fn end(&mut self, channel: ChannelId, seqnum: Option<u64>, shared: &mut Shared<T>) -> u64 {
+        self.drain(channel, GapReason::SessionReset, shared);
+        let end = match seqnum {
+            Some(end) if end > self.cur_block.seqnum => end,
+            _ => return self.cur_block.seqnum,
+        };
+        if self.fill_gap(channel, end, shared) {
+            self.drain(channel, GapReason::SessionReset, shared);
+        }
+        if end > self.cur_block.seqnum {
+            let from = self.cur_block.seqnum;
+            shared.metrics.dropped.fetch_add(end - from, Relaxed);
+            shared.gap(channel, from, end, GapReason::SessionReset);
+            self.cur_block.seqnum = end;
+            self.resolved(from, Resolution::Abandoned(GapReason::SessionReset), shared);
+        }
+        end
+    }
+
+    fn drain(&mut self, channel: ChannelId, reason: GapReason, shared: &mut Shared<T>) {
+        for (seqnum, e) in std::mem::take(&mut self.standby) {
+            if let Entry::Vacant(v) = self.new_blocks.entry(seqnum) {
+                self.new_bytes += e.1.size();
+                v.insert(e);
+            }
+        }
+        while let Some(b) = self.pop_head() {
+            if b.seqnum() >= self.cur_block.seqnum {
+                self.skip_to(channel, b, reason, shared);
+            }
+        }
+        self.deadlines.clear();
+    }
+
+    // Apply the overflow policy after buffering a block
+    fn check_limit(&mut self, channel: ChannelId, shared: &mut Shared<T>) {
+        let limit = shared.limit;
+        match limit.policy {
+            OverflowPolicy::FlushWithGap => {
+                if !limit.exceeded(self.new_blocks.len(), self.new_bytes) {
+                    return;
+                }
+                shared.metrics.overflows.fetch_add(1, Relaxed);
+                while limit.exceeded(self.new_blocks.len(), self.new_bytes) {
+                    let b = self.pop_head().unwrap();
+                    warn!(channel, seqnum = b.seqnum(), "overflow, flushing");
+                    self.skip_to(channel, b, GapReason::Overflow, shared);
+                    self.flush_in_order(shared);
+                }
+            }
+            OverflowPolicy::Block => {
+                if limit.reached(self.new_blocks.len(), self.new_bytes) {
+                    shared.metrics.overflows.fetch_add(1, Relaxed);
+                }
+            }
+            // Checked before buffering
+            OverflowPolicy::DropNewest => {}
+        }
+    }
+
+    // Flush in order sequence numbers from new_blocks
+    fn flush_in_order(&mut self, shared: &mut Shared<T>) {
+        self.flush_resolving(Resolution::Recovered, shared);
+    }
+
+    // flush_in_order(), the seqnums of gaps it gets past resolved by `how`
+    fn flush_resolving(&mut self, how: Resolution, shared: &mut Shared<T>) {
+        let (now, from) = (shared.clock.now(), self.cur_block.seqnum);
+        loop {
+            let new_block = match self.take(self.cur_block.seqnum) {
+                Some(b) => b,
+                None => match self.take_standby(shared.timeout, now) {
+                    Some(b) => b,
+                    None => break,
+                },
+            };
+            self.cur_block.seqnum = end(&new_block);
+            shared.block(new_block);
+            shared.metrics.recovered.fetch_add(1, Relaxed);
+            self.cur_block.ts = now;
+        }
+        self.resolved(from, how, shared);
+        // Standby copies of what has been delivered are no longer needed
+        let cur = self.cur_block.seqnum;
+        if self
+            .standby
+            .first_key_value()
+            .is_some_and(|(s, _)| *s < cur)
+        {
+            self.standby = self.standby.split_off(&cur);
+        }
+    }
+
+    // Flush timed out sequence numbers from new_blocks along with every
+    // block before them
+    fn poll_timeouts(&mut self, channel: ChannelId, shared: &mut Shared<T>) {
+        if self.new_blocks.is_empty() {
+            self.deadlines.clear();
+            // The active feed may have fallen behind its standby copies
+            self.flush_in_order(shared);
+            return;
+        }
+        let now = shared.clock.now();
+        // Only the front of the deadline queue can have timed out
+        let mut expired = Vec::new();
+        while let Some(&(ts, seqnum, arrivals)) = self.deadlines.front() {
+            let duration = now.duration_since(ts);
+            let newer = self.arrivals - arrivals;
+            if !self.timeout.expired(duration, newer, shared.timeout) {
+                break;
+            }
+            self.deadlines.pop_front();
+            if self.new_blocks.get(&seqnum).is_some_and(|(t, _)| *t == ts) {
+                let policy = self.timeout.name();
+                debug!(channel, seqnum, ?duration, newer, policy, "timeout");
+                expired.push((ts, seqnum, arrivals));
+            }
+        }
+
+        let last = match expired.iter().map(|(_, seqnum, _)| *seqnum).max() {
+            Some(last) => last,
+            None => return,
+        };
+        let head = *self.new_blocks.keys().next().unwrap();
+        if self.fill_gap(channel, head, shared) || self.resync(channel, shared) {
+            // Whatever is still buffered times out again on the next poll
+            for e in expired.into_iter().rev() {
+                self.deadlines.push_front(e);
+            }
+            return;
+        }
+
+        warn!(
+            channel,
+            blocks = self.new_blocks.range(..=last).count(),
+            from = head,
+            to = last,
+            "flushing timed out blocks"
+        );
+        shared.metrics.timeouts.fetch_add(1, Relaxed);
+        while self.new_blocks.keys().next().is_some_and(|s| *s <= last) {
+            let b = self.pop_head().unwrap();
+            self.skip_to(channel, b, GapReason::Timeout, shared);
+        }
+        self.flush_in_order(shared);
+    }
+
+    // Jump to a snapshot of the channel past the gap and replay what is
+    // buffered after it. Returns true if the channel was resynced.
+    fn resync(&mut self, channel: ChannelId, shared: &mut Shared<T>) -> bool {
+        let source = match shared.snapshot_source.as_mut() {
+            Some(s) => s,
+            None => return false,
+        };
+        let snapshot = match source.snapshot(channel) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!(channel, error = %e, "snapshot failed");
+                return false;
+            }
+        };
+        let seqnum = snapshot.seqnum;
+        if seqnum <= self.cur_block.seqnum {
+            warn!(
+                channel,
+                seqnum,
+                expected = self.cur_block.seqnum,
+                "snapshot is older than the gap"
+            );
+            return false;
+        }
+        // The snapshot already includes these
+        while self.new_blocks.keys().next().is_some_and(|s| *s < seqnum) {
+            self.pop_head();
+        }
+        info!(channel, seqnum, blocks = snapshot.blocks.len(), "resynced");
+        shared.metrics.resyncs.fetch_add(1, Relaxed);
+        let from = self.cur_block.seqnum;
+        self.cur_block.seqnum = seqnum;
+        self.resolved(from, Resolution::Resynced, shared);
+        let resynced = SequencedEvent::Resynced {
+            channel,
+            seqnum,
+            snapshot: snapshot.blocks,
+        };
+        shared.output.send(resynced);
+        self.flush_in_order(shared);
+        true
+    }
+
+    // Ask the gap filler for seqnums up to `end`. Returns true if the gap at
+    // the head of the buffer was at least partially filled.
+    fn fill_gap(&mut self, channel: ChannelId, end: u64, shared: &mut Shared<T>) -> bool {
+        let gap_filler = match shared.gap_filler.as_mut() {
+            Some(g) => g,
+            None => return false,
+        };
+        let start = self.cur_block.seqnum;
+        let blocks = match gap_filler.fill(channel, start..end) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!(channel, start, end, error = %e, "gap fill failed");
+                return false;
+            }
+        };
+        let now = shared.clock.now();
+        for b in blocks {
+            if b.channel() == channel && b.seqnum() >= start {
+                if let Entry::Vacant(e) = self.new_blocks.entry(b.seqnum()) {
+                    self.new_bytes += b.size();
+                    self.deadlines.push_back((now, b.seqnum(), self.arrivals));
+                    e.insert((now, b));
+                }
+            }
+        }
+        self.flush_resolving(Resolution::Retransmitted, shared);
+        if self.cur_block.seqnum > start {
+            info!(channel, start, end = self.cur_block.seqnum, "recovered");
+            return true;
+        }
+        false
+    }
+}
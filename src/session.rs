@@ -0,0 +1,93 @@
+// Unattended daily operation: ending sessions at a time of day for feeds
+// that don't mark the end themselves, and stopping once they have ended
+use crate::shutdown::Shutdown;
+use crate::sink::Sink;
+use crate::{Block, ChannelId, Payload, Session};
+use std::collections::HashSet;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// HH:MM or HH:MM:SS in UTC
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimeOfDay {
+    // Since midnight
+    secs: u64,
+}
+
+impl TimeOfDay {
+    pub fn new(hours: u64, minutes: u64, seconds: u64) -> Option<Self> {
+        (hours < 24 && minutes < 60 && seconds < 60).then_some(Self {
+            secs: hours * 3600 + minutes * 60 + seconds,
+        })
+    }
+
+    // How long after `now` it is next this time, a day if it is now
+    pub fn until_next(&self, now: SystemTime) -> Duration {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let today = Duration::from_secs(since_epoch.as_secs() % DAY)
+            + Duration::from_nanos(since_epoch.subsec_nanos() as u64);
+        let at = Duration::from_secs(self.secs);
+        match at.checked_sub(today) {
+            Some(wait) if !wait.is_zero() => wait,
+            _ => at + Duration::from_secs(DAY) - today,
+        }
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad time of day {}, expected HH:MM or HH:MM:SS", s);
+        let fields = s
+            .split(':')
+            .map(|f| f.parse::<u64>().map_err(|_| bad()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match fields[..] {
+            [h, m] => Self::new(h, m, 0),
+            [h, m, s] => Self::new(h, m, s),
+            _ => None,
+        }
+        .ok_or_else(bad)
+    }
+}
+
+// Sink that requests shutdown once every channel it has seen blocks of has
+// ended its session
+pub struct ExitAtSessionEnd {
+    shutdown: Shutdown,
+    channels: HashSet<ChannelId>,
+}
+
+impl ExitAtSessionEnd {
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self {
+            shutdown,
+            channels: HashSet::new(),
+        }
+    }
+}
+
+impl Sink for ExitAtSessionEnd {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.channels.insert(block.header.channel);
+        Ok(())
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        _session: Session,
+        _seqnum: u64,
+    ) -> io::Result<()> {
+        if self.channels.remove(&channel) && self.channels.is_empty() {
+            info!("every session ended, shutting down");
+            self.shutdown.request();
+        }
+        Ok(())
+    }
+}
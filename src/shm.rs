@@ -0,0 +1,416 @@
+// Shared-memory ring of sequenced blocks that other processes on the box read
+// with no sockets or syscalls. One writer, any number of readers, each with
+// its own cursor. Readers that fall a whole ring behind are told how many
+// records they missed. Integers are native endian.
+//
+// Header (HEADER_LEN bytes): magic "SEQSHM\0\0", version: u32, slot_len: u32,
+// n_slots: u32 (a power of two), closed: u32 (set once the writer is done),
+// then at offset 64 written: u64 (records published so far)
+//
+// n_slots slots of slot_len bytes follow. A slot holds record i of the stream
+// at i % n_slots:
+//   seq: u64, 2i + 1 while the record is written and 2i + 2 once it is
+//   kind: u32 (KIND_BLOCK or KIND_GAP), channel: u32, seqnum: u64,
+//   count: u64 (n_messages of a block, seqnums skipped by a gap),
+//   ts: u64 (ns since the unix epoch when written), len: u32, pad: u32,
+//   payload: len bytes
+//
+// A reader copies record i out of its slot only while seq is 2i + 2 before
+// and after the copy, like a seqlock.
+use crate::hugepages::{self, HugePages};
+use crate::journal::Record;
+use crate::sink::Sink;
+use crate::{Block, ChannelId, GapReason, Payload, Sequenced};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{
+    fence, AtomicU32, AtomicU64,
+    Ordering::{Acquire, Relaxed, Release},
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+pub const MAGIC: [u8; 8] = *b"SEQSHM\0\0";
+pub const VERSION: u32 = 1;
+pub const HEADER_LEN: usize = 128;
+// seq, kind, channel, seqnum, count, ts, len, pad
+pub const SLOT_HEADER_LEN: usize = 8 + 4 + 4 + 8 + 8 + 8 + 4 + 4;
+pub const KIND_BLOCK: u32 = 0;
+pub const KIND_GAP: u32 = 1;
+// Slots of a ring and payload bytes of each by default
+pub const SHM_SLOTS: usize = 65_536;
+pub const SHM_SLOT_LEN: usize = 2_048;
+
+const CLOSED_OFFSET: usize = 20;
+const WRITTEN_OFFSET: usize = 64;
+const CACHE_LINE: usize = 64;
+
+struct Mapping {
+    base: *mut u8,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    len: usize,
+}
+
+// SAFETY: only reached through atomics and seqlocked copies
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    #[cfg(unix)]
+    fn new(file: &File, len: usize, writable: bool) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let prot = match writable {
+            true => libc::PROT_READ | libc::PROT_WRITE,
+            false => libc::PROT_READ,
+        };
+        // SAFETY: a new mapping, which aliases nothing in this process
+        let p = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        match p {
+            libc::MAP_FAILED => Err(io::Error::last_os_error()),
+            p => Ok(Self {
+                base: p.cast(),
+                len,
+            }),
+        }
+    }
+
+    // Rings are mmapped files, which only unix has here
+    #[cfg(not(unix))]
+    fn new(_file: &File, _len: usize, _writable: bool) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "shm rings need unix",
+        ))
+    }
+
+    fn atomic_u32(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: offsets are aligned and within the mapping
+        unsafe { &*self.base.add(offset).cast::<AtomicU32>() }
+    }
+
+    fn atomic_u64(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: offsets are aligned and within the mapping
+        unsafe { &*self.base.add(offset).cast::<AtomicU64>() }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: mapped in new
+        unsafe { libc::munmap(self.base.cast(), self.len) };
+    }
+}
+
+// Number of slots and their offset in the mapping
+struct Layout {
+    slot_len: usize,
+    mask: u64,
+}
+
+impl Layout {
+    fn slot(&self, i: u64) -> usize {
+        HEADER_LEN + (i & self.mask) as usize * self.slot_len
+    }
+
+    fn capacity(&self) -> usize {
+        self.slot_len - SLOT_HEADER_LEN
+    }
+}
+
+// Writes the sequenced stream into the ring at `path`, such as a file under
+// /dev/shm
+pub struct ShmSink {
+    map: Mapping,
+    layout: Layout,
+    written: u64,
+    huge: bool,
+}
+
+impl ShmSink {
+    // A ring of `n_slots`, rounded up to a power of two, slots each holding
+    // up to `slot_len` bytes of payload. Blocks that don't fit are published
+    // as gaps.
+    pub fn create<P: AsRef<Path>>(path: P, n_slots: usize, slot_len: usize) -> io::Result<Self> {
+        Self::with_huge_pages(path, n_slots, slot_len, HugePages::Off)
+    }
+
+    // create() in huge pages of `pages`' size. A ring on hugetlbfs, such as
+    // under /dev/hugepages, is in huge pages anyway and any other is backed
+    // by transparent huge pages if the kernel has them for shared memory.
+    pub fn with_huge_pages<P: AsRef<Path>>(
+        path: P,
+        n_slots: usize,
+        slot_len: usize,
+        pages: HugePages,
+    ) -> io::Result<Self> {
+        let n_slots = n_slots.max(1).next_power_of_two();
+        let slot_len = (SLOT_HEADER_LEN + slot_len).next_multiple_of(CACHE_LINE);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let hugetlbfs = hugepages::hugetlbfs_page_len(&file)?;
+        let len = HEADER_LEN + n_slots * slot_len;
+        let len = match hugetlbfs {
+            Some(page_len) => len.next_multiple_of(page_len),
+            None => pages.round_up(len),
+        };
+        file.set_len(len as u64)?;
+        let map = Mapping::new(&file, len, true)?;
+        let huge = hugetlbfs.is_some();
+        if pages != HugePages::Off && !huge {
+            if let Err(e) = hugepages::advise(map.base, len) {
+                warn!(error = %e, "no transparent huge pages for the shm ring");
+            }
+        }
+        // SAFETY: the header is within the mapping and no reader trusts it
+        // until the magic is there
+        unsafe {
+            let header = map.base;
+            ptr::copy_nonoverlapping(VERSION.to_ne_bytes().as_ptr(), header.add(8), 4);
+            ptr::copy_nonoverlapping((slot_len as u32).to_ne_bytes().as_ptr(), header.add(12), 4);
+            ptr::copy_nonoverlapping((n_slots as u32).to_ne_bytes().as_ptr(), header.add(16), 4);
+            fence(Release);
+            ptr::copy_nonoverlapping(MAGIC.as_ptr(), header, MAGIC.len());
+        }
+        Ok(Self {
+            map,
+            layout: Layout {
+                slot_len,
+                mask: n_slots as u64 - 1,
+            },
+            written: 0,
+            huge,
+        })
+    }
+
+    // Whether the ring is in explicit huge pages
+    pub fn huge_pages(&self) -> bool {
+        self.huge
+    }
+
+    // Records published so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    fn publish(&mut self, kind: u32, channel: ChannelId, seqnum: u64, count: u64, payload: &[u8]) {
+        let i = self.written;
+        let offset = self.layout.slot(i);
+        let seq = self.map.atomic_u64(offset);
+        seq.store(2 * i + 1, Relaxed);
+        fence(Release);
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut fields = [0_u8; SLOT_HEADER_LEN - 8];
+        fields[0..4].copy_from_slice(&kind.to_ne_bytes());
+        fields[4..8].copy_from_slice(&channel.to_ne_bytes());
+        fields[8..16].copy_from_slice(&seqnum.to_ne_bytes());
+        fields[16..24].copy_from_slice(&count.to_ne_bytes());
+        fields[24..32].copy_from_slice(&ts.to_ne_bytes());
+        fields[32..36].copy_from_slice(&(payload.len() as u32).to_ne_bytes());
+        // SAFETY: the slot is within the mapping and payload fits in it.
+        // Readers discard whatever they copy while seq is odd.
+        unsafe {
+            let slot = self.map.base.add(offset);
+            ptr::copy_nonoverlapping(fields.as_ptr(), slot.add(8), fields.len());
+            ptr::copy_nonoverlapping(payload.as_ptr(), slot.add(SLOT_HEADER_LEN), payload.len());
+        }
+        seq.store(2 * i + 2, Release);
+        self.written = i + 1;
+        self.map
+            .atomic_u64(WRITTEN_OFFSET)
+            .store(self.written, Release);
+    }
+}
+
+impl Drop for ShmSink {
+    fn drop(&mut self) {
+        self.map.atomic_u32(CLOSED_OFFSET).store(1, Release);
+    }
+}
+
+impl Sink for ShmSink {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        let payload = &block.payload[..];
+        let (channel, seqnum, n) = (block.channel(), block.seqnum(), block.n_messages());
+        if payload.len() > self.layout.capacity() {
+            warn!(
+                channel,
+                seqnum,
+                len = payload.len(),
+                "block too big for a shm slot, publishing a gap"
+            );
+            self.publish(KIND_GAP, channel, seqnum, n as u64, &[]);
+            return Ok(());
+        }
+        self.publish(KIND_BLOCK, channel, seqnum, n as u64, payload);
+        Ok(())
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        _reason: GapReason,
+    ) -> io::Result<()> {
+        let count = range.end - range.start;
+        self.publish(KIND_GAP, channel, range.start, count, &[]);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ShmEvent {
+    Block(Record),
+    // Seqnums in `range` of `channel` were skipped
+    Gap {
+        channel: ChannelId,
+        range: Range<u64>,
+    },
+    // The writer lapped this reader, which skipped `records` records to
+    // catch up
+    Lagged {
+        records: u64,
+    },
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Reads the ring a ShmSink writes, from wherever the writer is when opened
+pub struct ShmReader {
+    map: Mapping,
+    layout: Layout,
+    // Record read next
+    next: u64,
+    buf: Vec<u8>,
+}
+
+impl ShmReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_LEN {
+            return Err(invalid("not a shm ring"));
+        }
+        let map = Mapping::new(&file, len, false)?;
+        let mut header = [0_u8; 20];
+        // SAFETY: the header is within the mapping
+        unsafe { ptr::copy_nonoverlapping(map.base, header.as_mut_ptr(), header.len()) };
+        fence(Acquire);
+        if header[0..8] != MAGIC {
+            return Err(invalid("not a shm ring"));
+        }
+        let u32_at = |i: usize| u32::from_ne_bytes(header[i..i + 4].try_into().unwrap());
+        if u32_at(8) != VERSION {
+            return Err(invalid("unsupported shm ring version"));
+        }
+        let (slot_len, n_slots) = (u32_at(12) as usize, u32_at(16) as usize);
+        if !n_slots.is_power_of_two()
+            || slot_len < SLOT_HEADER_LEN
+            || slot_len % 8 != 0
+            || HEADER_LEN + n_slots * slot_len > len
+        {
+            return Err(invalid("corrupt shm ring header"));
+        }
+        let next = map.atomic_u64(WRITTEN_OFFSET).load(Acquire);
+        Ok(Self {
+            map,
+            layout: Layout {
+                slot_len,
+                mask: n_slots as u64 - 1,
+            },
+            next,
+            buf: Vec::new(),
+        })
+    }
+
+    // Start at the oldest record still in the ring instead
+    pub fn rewind(&mut self) {
+        let written = self.map.atomic_u64(WRITTEN_OFFSET).load(Acquire);
+        self.next = written.saturating_sub(self.layout.mask + 1);
+    }
+
+    // Whether the writer has finished, after which reads that return None
+    // never return anything again
+    pub fn is_closed(&self) -> bool {
+        self.map.atomic_u32(CLOSED_OFFSET).load(Acquire) != 0
+    }
+
+    // The next record, or None if the reader has caught up with the writer
+    pub fn try_read(&mut self) -> Option<ShmEvent> {
+        let i = self.next;
+        let offset = self.layout.slot(i);
+        let seq = self.map.atomic_u64(offset);
+        let before = seq.load(Acquire);
+        if before < 2 * i + 2 {
+            // Not written yet
+            return None;
+        }
+        if before > 2 * i + 2 {
+            return Some(self.catch_up());
+        }
+        let mut fields = [0_u8; SLOT_HEADER_LEN - 8];
+        // SAFETY: the slot is within the mapping. A copy that races the
+        // writer is torn, which the second load of seq catches.
+        unsafe {
+            let slot = self.map.base.add(offset);
+            ptr::copy_nonoverlapping(slot.add(8), fields.as_mut_ptr(), fields.len());
+            let len = u32::from_ne_bytes(fields[32..36].try_into().unwrap()) as usize;
+            let len = len.min(self.layout.capacity());
+            self.buf.resize(len, 0);
+            ptr::copy_nonoverlapping(slot.add(SLOT_HEADER_LEN), self.buf.as_mut_ptr(), len);
+        }
+        fence(Acquire);
+        if seq.load(Relaxed) != before {
+            return Some(self.catch_up());
+        }
+        self.next = i + 1;
+
+        let u32_at = |i: usize| u32::from_ne_bytes(fields[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_ne_bytes(fields[i..i + 8].try_into().unwrap());
+        let (channel, seqnum, count) = (u32_at(4), u64_at(8), u64_at(16));
+        let event = match u32_at(0) {
+            KIND_GAP => ShmEvent::Gap {
+                channel,
+                range: seqnum..seqnum + count,
+            },
+            _ => ShmEvent::Block(Record {
+                channel,
+                seqnum,
+                n_messages: count as u16,
+                ts: UNIX_EPOCH + Duration::from_nanos(u64_at(24)),
+                payload: self.buf.clone(),
+            }),
+        };
+        Some(event)
+    }
+
+    // Skip to the oldest record the writer isn't about to overwrite
+    fn catch_up(&mut self) -> ShmEvent {
+        let written = self.map.atomic_u64(WRITTEN_OFFSET).load(Acquire);
+        let oldest = (written + 1).saturating_sub(self.layout.mask + 1);
+        let records = oldest.saturating_sub(self.next).max(1);
+        self.next += records;
+        ShmEvent::Lagged { records }
+    }
+}
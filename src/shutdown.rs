@@ -0,0 +1,28 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How often blocked receive loops check for shutdown
+pub const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+// Set once to stop receive loops and the arbiter. Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Request shutdown on SIGINT or SIGTERM. Only one handler can be set per
+    // process.
+    pub fn on_signals(&self) -> io::Result<()> {
+        let shutdown = self.clone();
+        ctrlc::set_handler(move || shutdown.request()).map_err(io::Error::other)
+    }
+}
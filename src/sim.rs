@@ -0,0 +1,74 @@
+use crate::Block;
+use crossbeam_channel::Sender;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Network impairments applied to each simulated feed. Every feed gets its own
+// RNG seeded from `seed` and its feed number, so a feed's stream is the same
+// on every run with the same seed.
+#[derive(Clone, Debug, Default)]
+pub struct SimConfig {
+    pub seed: u64,
+    // Fraction of blocks dropped
+    pub loss: f64,
+    // Most positions a block can arrive after where it was sent, 0 for in
+    // order
+    pub reorder_window: usize,
+    // Fraction of blocks sent twice
+    pub duplication: f64,
+    // Time between sends of a feed
+    pub interval: Duration,
+    // Most extra time added to each interval
+    pub jitter: Duration,
+}
+
+impl SimConfig {
+    pub fn rng(&self, feed: usize) -> StdRng {
+        // Spread feed numbers so neighbouring seeds don't share streams
+        let feed = (feed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        StdRng::seed_from_u64(self.seed ^ feed)
+    }
+
+    // What `feed` receives of `blocks`, each with the delay before it is
+    // received
+    pub fn schedule<T: Clone>(&self, blocks: &[T], feed: usize) -> Vec<(Duration, T)> {
+        let mut rng = self.rng(feed);
+        let mut sent = Vec::with_capacity(blocks.len());
+        for (i, b) in blocks.iter().enumerate() {
+            if rng.gen_bool(self.loss.clamp(0.0, 1.0)) {
+                continue;
+            }
+            let copies = 1 + rng.gen_bool(self.duplication.clamp(0.0, 1.0)) as usize;
+            for _ in 0..copies {
+                // Late by up to the window, ties keep sending order
+                let late = rng.gen_range(0..=self.reorder_window);
+                sent.push((i + late, b.clone()));
+            }
+        }
+        sent.sort_by_key(|(pos, _)| *pos);
+
+        let jitter = self.jitter.as_nanos() as u64;
+        sent.into_iter()
+            .map(|(_, b)| {
+                let extra = Duration::from_nanos(rng.gen_range(0..=jitter));
+                (self.interval + extra, b)
+            })
+            .collect()
+    }
+}
+
+// Send a schedule to the arbiter in real time. Blocks are received as they
+// are sent.
+pub fn run<P>(schedule: Vec<(Duration, Block<P>)>, sender: Sender<Block<P>>) {
+    for (delay, mut b) in schedule {
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        b.received = Some(Instant::now());
+        if sender.send(b).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use crate::metrics::FeedId;
+use crate::{Block, ChannelId, GapReason, Payload, SequencedEvent, Session};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::info;
+
+// How long run() waits for an event before calling on_idle()
+pub const IDLE: Duration = Duration::from_millis(50);
+
+// How run_batched() gathers consecutive blocks for on_blocks()
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Batching {
+    // Most blocks in a batch, one not to batch
+    pub max_blocks: usize,
+    // Longest the first block of a batch waits for more, zero for only those
+    // already queued
+    pub max_latency: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_blocks: 1,
+            max_latency: Duration::ZERO,
+        }
+    }
+}
+
+// Receives the sequenced stream
+pub trait Sink<P = Payload> {
+    fn on_block(&mut self, block: &Block<P>) -> io::Result<()>;
+
+    // Consecutive blocks with no other events between them
+    fn on_blocks(&mut self, blocks: &[Block<P>]) -> io::Result<()> {
+        blocks.iter().try_for_each(|b| self.on_block(b))
+    }
+
+    // Seqnums in `range` of `channel` were skipped
+    fn on_gap(
+        &mut self,
+        _channel: ChannelId,
+        _range: Range<u64>,
+        _reason: GapReason,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Following blocks of `channel` are from a new session starting at `seqnum`
+    fn on_reset(&mut self, _channel: ChannelId, _session: Session, _seqnum: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    // `channel` jumped to a snapshot as of just before `seqnum`
+    fn on_resync(
+        &mut self,
+        _channel: ChannelId,
+        _seqnum: u64,
+        _snapshot: &[Block<P>],
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_feed_down(&mut self, _feed: FeedId, _silent: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_feed_up(&mut self, _feed: FeedId) -> io::Result<()> {
+        Ok(())
+    }
+
+    // The session of `channel` ended before `seqnum`
+    fn on_end_of_session(
+        &mut self,
+        _channel: ChannelId,
+        _session: Session,
+        _seqnum: u64,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Feeds report different sessions of `channel`, which is not sequenced
+    // until they agree
+    fn on_sessions_diverged(
+        &mut self,
+        _channel: ChannelId,
+        _sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_sessions_agreed(&mut self, _channel: ChannelId, _session: Session) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // No events arrived for IDLE
+    fn on_idle(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Seqnum of the next message published, for sinks numbering what they
+    // publish themselves, which a standby taking over must carry on from
+    fn published(&self) -> Option<u64> {
+        None
+    }
+
+    // Number the next message published `seqnum`
+    fn resume_published(&mut self, _seqnum: u64) {}
+}
+
+impl<P, S: Sink<P> + ?Sized> Sink<P> for Box<S> {
+    fn on_block(&mut self, block: &Block<P>) -> io::Result<()> {
+        (**self).on_block(block)
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<P>]) -> io::Result<()> {
+        (**self).on_blocks(blocks)
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        (**self).on_gap(channel, range, reason)
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        (**self).on_reset(channel, session, seqnum)
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<P>],
+    ) -> io::Result<()> {
+        (**self).on_resync(channel, seqnum, snapshot)
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        (**self).on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        (**self).on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        (**self).on_end_of_session(channel, session, seqnum)
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        (**self).on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        (**self).on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        (**self).on_idle()
+    }
+
+    fn published(&self) -> Option<u64> {
+        (**self).published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        (**self).resume_published(seqnum)
+    }
+}
+
+// Every event goes to each sink in order
+impl<P, S: Sink<P>> Sink<P> for Vec<S> {
+    fn on_block(&mut self, block: &Block<P>) -> io::Result<()> {
+        self.iter_mut().try_for_each(|s| s.on_block(block))
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<P>]) -> io::Result<()> {
+        self.iter_mut().try_for_each(|s| s.on_blocks(blocks))
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_gap(channel, range.clone(), reason))
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_reset(channel, session, seqnum))
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<P>],
+    ) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_resync(channel, seqnum, snapshot))
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_feed_down(feed, silent))
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.iter_mut().try_for_each(|s| s.on_feed_up(feed))
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_end_of_session(channel, session, seqnum))
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_sessions_diverged(channel, sessions))
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.iter_mut()
+            .try_for_each(|s| s.on_sessions_agreed(channel, session))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.iter_mut().try_for_each(|s| s.flush())
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.iter_mut().try_for_each(|s| s.on_idle())
+    }
+}
+
+// One "seqnum payload_len" line per block
+pub struct TextSink {
+    w: BufWriter<File>,
+}
+
+impl TextSink {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            w: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl Sink for TextSink {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        writeln!(self.w, "{} {}", block.header.seqnum, block.payload.len())
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        let mut lines = String::new();
+        for block in blocks {
+            lines.push_str(&format!(
+                "{} {}\n",
+                block.header.seqnum,
+                block.payload.len()
+            ));
+        }
+        self.w.write_all(lines.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+impl Sink for crate::journal::JournalWriter {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.append_block(block, SystemTime::now())
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        self.append_blocks(blocks, SystemTime::now())
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, _session: Session, seqnum: u64) -> io::Result<()> {
+        self.set_next(channel, seqnum);
+        Ok(())
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        _snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.set_next(channel, seqnum);
+        Ok(())
+    }
+
+    // The day's journal is moved aside once every channel in it has ended
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        _session: Session,
+        _seqnum: u64,
+    ) -> io::Result<()> {
+        if let Some(rotated) = self.end_session(channel)? {
+            info!(to = %rotated.display(), "end of session, rotated journal");
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sync()
+    }
+}
+
+// Deliver events to `sink` until the sequencer hangs up. Returns the number
+// of blocks delivered.
+pub fn run<P, S: Sink<P> + ?Sized>(
+    receiver: Receiver<SequencedEvent<Block<P>>>,
+    sink: &mut S,
+) -> io::Result<u64> {
+    run_with(receiver, sink, |_| {})
+}
+
+// run() calling `delivered` with each event once the sink has it, such as
+// ConsumerLag::consumed()
+pub fn run_with<P, S: Sink<P> + ?Sized>(
+    receiver: Receiver<SequencedEvent<Block<P>>>,
+    sink: &mut S,
+    delivered: impl FnMut(&SequencedEvent<Block<P>>),
+) -> io::Result<u64> {
+    run_batched(receiver, sink, Batching::default(), delivered)
+}
+
+// run_with() handing consecutive blocks to on_blocks() together, as many
+// as `batching` allows. Only other events and the end of the stream hold up
+// a batch less than its latency.
+pub fn run_batched<P, S: Sink<P> + ?Sized>(
+    receiver: Receiver<SequencedEvent<Block<P>>>,
+    sink: &mut S,
+    batching: Batching,
+    mut delivered: impl FnMut(&SequencedEvent<Block<P>>),
+) -> io::Result<u64> {
+    let max_blocks = batching.max_blocks.max(1);
+    let mut n_blocks = 0;
+    let mut batch = Vec::with_capacity(max_blocks);
+    // Received while batching
+    let mut next = None;
+    let mut hung_up = false;
+    while !hung_up {
+        let event = match next.take() {
+            Some(event) => event,
+            None => match receiver.recv_timeout(IDLE) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    sink.on_idle()?;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        let block = match event {
+            SequencedEvent::Block(block) => block,
+            event => {
+                deliver(sink, &event)?;
+                delivered(&event);
+                continue;
+            }
+        };
+        batch.push(block);
+        let until = Instant::now() + batching.max_latency;
+        while batch.len() < max_blocks {
+            match receiver.recv_deadline(until) {
+                Ok(SequencedEvent::Block(block)) => batch.push(block),
+                Ok(event) => {
+                    next = Some(event);
+                    break;
+                }
+                Err(e) => {
+                    hung_up = e.is_disconnected();
+                    break;
+                }
+            }
+        }
+        sink.on_blocks(&batch)?;
+        n_blocks += batch.len() as u64;
+        for block in batch.drain(..) {
+            delivered(&SequencedEvent::Block(block));
+        }
+    }
+    sink.flush()?;
+    Ok(n_blocks)
+}
+
+// Hand `event` to the method of `sink` for it
+pub fn deliver<P, S: Sink<P> + ?Sized>(
+    sink: &mut S,
+    event: &SequencedEvent<Block<P>>,
+) -> io::Result<()> {
+    match event {
+        SequencedEvent::Block(block) => sink.on_block(block),
+        SequencedEvent::Gap {
+            channel,
+            from,
+            to,
+            reason,
+        } => sink.on_gap(*channel, *from..*to, *reason),
+        SequencedEvent::SessionReset {
+            channel,
+            session,
+            seqnum,
+        } => sink.on_reset(*channel, *session, *seqnum),
+        SequencedEvent::Resynced {
+            channel,
+            seqnum,
+            snapshot,
+        } => sink.on_resync(*channel, *seqnum, snapshot),
+        SequencedEvent::FeedDown { feed, silent } => sink.on_feed_down(*feed, *silent),
+        SequencedEvent::FeedUp { feed } => sink.on_feed_up(*feed),
+        SequencedEvent::EndOfSession {
+            channel,
+            session,
+            seqnum,
+        } => sink.on_end_of_session(*channel, *session, *seqnum),
+        SequencedEvent::SessionsDiverged { channel, sessions } => {
+            sink.on_sessions_diverged(*channel, sessions)
+        }
+        SequencedEvent::SessionsAgreed { channel, session } => {
+            sink.on_sessions_agreed(*channel, *session)
+        }
+    }
+}
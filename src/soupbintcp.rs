@@ -0,0 +1,224 @@
+use crate::protocol::soupbintcp::{self, ServerPacket};
+use crate::shutdown::Shutdown;
+use crate::{Block, BlockHeader, ChannelId, Payload, Session};
+use crossbeam_channel::Sender;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+// Clients must send something at least this often
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// Servers send heartbeats every second, so this long without one means the
+// connection is dead
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Debug)]
+pub struct SoupBinTcpConfig {
+    pub addr: SocketAddr,
+    pub username: String,
+    pub password: String,
+    // All spaces for the server's current session
+    pub session: Session,
+    // First seqnum to receive, 0 for only new messages
+    pub seqnum: u64,
+    // Channel the stream's blocks are sequenced in
+    pub channel: ChannelId,
+}
+
+// Logs in to a SoupBinTCP server and turns its sequenced packets into blocks
+// of one message each, so a TCP recovery stream can be a feed next to UDP
+// ones. Server heartbeats become blocks with no messages.
+pub struct SoupBinTcpSource {
+    stream: TcpStream,
+    channel: ChannelId,
+    session: Session,
+    // Seqnum of the next sequenced packet
+    seqnum: u64,
+    last_sent: Instant,
+    last_recv: Instant,
+    ended: bool,
+    shutdown: Option<Shutdown>,
+    // Received bytes not yet parsed into packets
+    pending: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+fn unexpected(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl SoupBinTcpSource {
+    pub fn connect(config: &SoupBinTcpConfig) -> io::Result<Self> {
+        let stream = TcpStream::connect(config.addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(HEARTBEAT_INTERVAL))?;
+        let mut source = Self {
+            stream,
+            channel: config.channel,
+            session: config.session,
+            seqnum: config.seqnum,
+            last_sent: Instant::now(),
+            last_recv: Instant::now(),
+            ended: false,
+            shutdown: None,
+            pending: Vec::new(),
+            buf: vec![0; 65_536],
+        };
+
+        let mut login = Vec::new();
+        soupbintcp::write_login(
+            &mut login,
+            &config.username,
+            &config.password,
+            &config.session,
+            config.seqnum,
+        );
+        source.send(&login)?;
+        loop {
+            let packet = match source.next_packet()? {
+                Some(p) => p,
+                None if source.last_recv.elapsed() > SERVER_TIMEOUT => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "no login response"));
+                }
+                None => continue,
+            };
+            match soupbintcp::parse_server(&packet) {
+                Ok(ServerPacket::LoginAccepted { session, seqnum }) => {
+                    source.session = session;
+                    source.seqnum = seqnum;
+                    return Ok(source);
+                }
+                Ok(ServerPacket::LoginRejected { reason }) => {
+                    let msg = format!("login rejected ({})", reason as char);
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+                }
+                Ok(ServerPacket::Debug(_)) | Ok(ServerPacket::Heartbeat) => {}
+                Ok(p) => return Err(unexpected(format!("{:?} before login accepted", p))),
+                Err(e) => return Err(unexpected(e.to_string())),
+            }
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    // Seqnum of the next sequenced packet
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    // True once the server ended the session
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    // Log out and stop run() once `shutdown` is requested
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.stream.write_all(packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    // Next complete packet, or None if the read timed out first
+    fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(len) = soupbintcp::packet_len(&self.pending) {
+                self.last_recv = Instant::now();
+                return Ok(Some(self.pending.drain(..len).collect()));
+            }
+            match self.stream.read(&mut self.buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.pending.extend_from_slice(&self.buf[..n]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Keep the server from timing us out and notice a dead server
+    fn heartbeat(&mut self) -> io::Result<()> {
+        if self.last_recv.elapsed() > SERVER_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no packets from server",
+            ));
+        }
+        if self.last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+            let mut packet = Vec::new();
+            soupbintcp::write_client_heartbeat(&mut packet);
+            self.send(&packet)?;
+        }
+        Ok(())
+    }
+
+    fn block(&self, n_messages: u16, payload: &[u8]) -> Block<Payload> {
+        let header = BlockHeader {
+            channel: self.channel,
+            session: self.session,
+            seqnum: self.seqnum,
+            n_messages,
+            end_of_session: false,
+        };
+        Block::new(header, payload.into())
+    }
+
+    // Returns Ok(None) on read timeout or a packet with nothing to sequence.
+    // End of session is a block marking it.
+    pub fn recv(&mut self) -> io::Result<Option<Block<Payload>>> {
+        self.heartbeat()?;
+        let packet = match self.next_packet()? {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        match soupbintcp::parse_server(&packet) {
+            Ok(ServerPacket::Sequenced(msg)) => {
+                let b = self.block(1, msg);
+                self.seqnum += 1;
+                Ok(Some(b))
+            }
+            Ok(ServerPacket::Heartbeat) => Ok(Some(self.block(0, &[]))),
+            Ok(ServerPacket::EndOfSession) => {
+                self.ended = true;
+                let mut b = self.block(0, &[]);
+                b.header.end_of_session = true;
+                Ok(Some(b))
+            }
+            Ok(ServerPacket::Debug(_)) | Ok(ServerPacket::Unsequenced(_)) => Ok(None),
+            Ok(p) => Err(unexpected(format!("{:?} after login", p))),
+            Err(e) => Err(unexpected(e.to_string())),
+        }
+    }
+
+    pub fn logout(mut self) -> io::Result<()> {
+        let mut packet = Vec::new();
+        soupbintcp::write_logout(&mut packet);
+        self.send(&packet)
+    }
+
+    // Receive until the server ends the session, the arbiter hangs up or
+    // shutdown is requested
+    pub fn run(mut self, sender: Sender<Block<Payload>>) -> io::Result<()> {
+        while !self.ended {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return self.logout();
+            }
+            if let Some(b) = self.recv()? {
+                if sender.send(b).is_err() {
+                    return self.logout();
+                }
+            }
+        }
+        // The server closes the connection after ending the session
+        Ok(())
+    }
+}
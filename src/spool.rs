@@ -0,0 +1,221 @@
+// Temporary on-disk segment that output events queue in while the consumer is
+// behind. Only blocks are written out, the rarer events between them wait in
+// memory in their place. All integers are little endian.
+//
+// Record: len: u32 (bytes after this field), received: i64 (ns after the
+// spool was created, i64::MIN for none), then the block as Spill writes it
+use crate::{Block, BlockHeader, SequencedEvent, SESSION_LEN};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// How blocks are written to and read back from a spool
+pub trait Spill: Sized {
+    fn spill(&self, buf: &mut Vec<u8>);
+    // `received` is what Sequenced::received() returned when it was spilled
+    fn unspill(buf: &[u8], received: Option<Instant>) -> io::Result<Self>;
+}
+
+// channel, session, seqnum, n_messages
+const BLOCK_HEADER_LEN: usize = 4 + SESSION_LEN + 8 + 2;
+
+impl<P: AsRef<[u8]> + From<Vec<u8>>> Spill for Block<P> {
+    fn spill(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.header.channel.to_le_bytes());
+        buf.extend_from_slice(&self.header.session);
+        buf.extend_from_slice(&self.header.seqnum.to_le_bytes());
+        buf.extend_from_slice(&self.header.n_messages.to_le_bytes());
+        buf.extend_from_slice(self.payload.as_ref());
+    }
+
+    fn unspill(buf: &[u8], received: Option<Instant>) -> io::Result<Self> {
+        if buf.len() < BLOCK_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "short spilled block",
+            ));
+        }
+        let header = BlockHeader {
+            channel: u32::from_le_bytes(buf[0..4].try_into().unwrap()),
+            session: buf[4..14].try_into().unwrap(),
+            seqnum: u64::from_le_bytes(buf[14..22].try_into().unwrap()),
+            n_messages: u16::from_le_bytes(buf[22..24].try_into().unwrap()),
+            end_of_session: false,
+        };
+        let mut block = Block::new(header, buf[BLOCK_HEADER_LEN..].to_vec().into());
+        block.received = received;
+        Ok(block)
+    }
+}
+
+// Encoding of a spool's blocks, captured where T: Spill is known
+pub(crate) struct Codec<T> {
+    pub(crate) spill: fn(&T, &mut Vec<u8>),
+    pub(crate) unspill: fn(&[u8], Option<Instant>) -> io::Result<T>,
+    pub(crate) received: fn(&T) -> Option<Instant>,
+}
+
+enum Pending<T> {
+    Event(SequencedEvent<T>),
+    // This many blocks are next in the segment
+    Spilled(usize),
+}
+
+// Distinguishes the spools of one process
+static SPOOLS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) struct Spool<T> {
+    codec: Codec<T>,
+    // Two handles on a file that is already unlinked, so nothing is left
+    // behind if the process dies
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    // Whether the writer holds records the reader can't see yet
+    unflushed: bool,
+    pending: VecDeque<Pending<T>>,
+    len: usize,
+    spilled: usize,
+    epoch: Instant,
+    record: Vec<u8>,
+}
+
+impl<T> Spool<T> {
+    pub(crate) fn create(dir: &Path, codec: Codec<T>) -> io::Result<Self> {
+        let n = SPOOLS.fetch_add(1, Relaxed);
+        let path = dir.join(format!("sequencer-{}-{}.spill", process::id(), n));
+        let writer = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let reader = File::open(&path);
+        fs::remove_file(&path)?;
+        Ok(Self {
+            codec,
+            writer: BufWriter::new(writer),
+            reader: BufReader::new(reader?),
+            unflushed: false,
+            pending: VecDeque::new(),
+            len: 0,
+            spilled: 0,
+            epoch: Instant::now(),
+            record: Vec::new(),
+        })
+    }
+
+    // Events queued, spilled or not
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Blocks in the segment
+    pub(crate) fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    // Queue `event` behind the others, writing it out if it is a block.
+    // Returns whether it was written out.
+    pub(crate) fn push(&mut self, event: SequencedEvent<T>) -> bool {
+        self.len += 1;
+        let block = match event {
+            SequencedEvent::Block(block) => block,
+            event => {
+                self.pending.push_back(Pending::Event(event));
+                return false;
+            }
+        };
+        if let Err(e) = self.write(&block) {
+            // Held in memory instead, which at least keeps it
+            warn!(error = %e, "failed to spill output, holding it in memory");
+            self.pending
+                .push_back(Pending::Event(SequencedEvent::Block(block)));
+            return false;
+        }
+        self.spilled += 1;
+        match self.pending.back_mut() {
+            Some(Pending::Spilled(n)) => *n += 1,
+            _ => self.pending.push_back(Pending::Spilled(1)),
+        }
+        true
+    }
+
+    fn write(&mut self, block: &T) -> io::Result<()> {
+        let received = match (self.codec.received)(block) {
+            Some(t) => match t.checked_duration_since(self.epoch) {
+                Some(d) => d.as_nanos() as i64,
+                None => -(self.epoch.duration_since(t).as_nanos() as i64),
+            },
+            None => i64::MIN,
+        };
+        let r = &mut self.record;
+        r.clear();
+        r.extend_from_slice(&received.to_le_bytes());
+        (self.codec.spill)(block, r);
+        self.writer.write_all(&(r.len() as u32).to_le_bytes())?;
+        self.writer.write_all(r)?;
+        self.unflushed = true;
+        Ok(())
+    }
+
+    // Take the oldest event
+    pub(crate) fn pop(&mut self) -> io::Result<Option<SequencedEvent<T>>> {
+        let event = match self.pending.front_mut() {
+            None => return Ok(None),
+            Some(Pending::Event(_)) => match self.pending.pop_front() {
+                Some(Pending::Event(event)) => event,
+                _ => unreachable!(),
+            },
+            Some(Pending::Spilled(n)) => {
+                *n -= 1;
+                if *n == 0 {
+                    self.pending.pop_front();
+                }
+                self.spilled -= 1;
+                let block = self.read()?;
+                if self.spilled == 0 {
+                    self.rewind()?;
+                }
+                SequencedEvent::Block(block)
+            }
+        };
+        self.len -= 1;
+        Ok(Some(event))
+    }
+
+    fn read(&mut self) -> io::Result<T> {
+        if self.unflushed {
+            self.writer.flush()?;
+            self.unflushed = false;
+        }
+        let mut len = [0_u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        self.record.resize(len, 0);
+        self.reader.read_exact(&mut self.record)?;
+        let ns = i64::from_le_bytes(self.record[0..8].try_into().unwrap());
+        let received = match ns {
+            i64::MIN => None,
+            ns if ns >= 0 => Some(self.epoch + Duration::from_nanos(ns as u64)),
+            ns => self
+                .epoch
+                .checked_sub(Duration::from_nanos(ns.unsigned_abs())),
+        };
+        (self.codec.unspill)(&self.record[8..], received)
+    }
+
+    // Start the drained segment over so it doesn't grow without bound
+    fn rewind(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.get_ref().set_len(0)?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+// Blocks behind their channel's expected seqnum are copies of ones already
+// delivered, from a slower feed or a late retransmission. They are dropped
+// and counted as stale. Under StalePolicy::Verify each one is also compared
+// with the block delivered at its seqnum, so a feed whose copies differ,
+// corrupted upstream of the sequencer, is logged and counted.
+use crate::retransmit::Index;
+use crate::{Block, Payload};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StalePolicy {
+    #[default]
+    Count,
+    // Against the journal the sequenced stream is written to
+    Verify,
+}
+
+impl FromStr for StalePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(StalePolicy::Count),
+            "verify" => Ok(StalePolicy::Verify),
+            _ => Err(format!("unknown stale policy {}", s)),
+        }
+    }
+}
+
+// Asked whether a stale block is the same as the one delivered at its seqnum
+pub trait StaleCheck<T> {
+    // None if it doesn't know the delivered block, such as one not written
+    // yet or a gap that was skipped
+    fn matches(&mut self, b: &T) -> io::Result<Option<bool>>;
+}
+
+// Compares stale blocks with their records in a journal, which may not exist
+// until the first lookup
+pub struct JournalCheck {
+    index: Index,
+}
+
+impl JournalCheck {
+    pub fn new<P: AsRef<Path>>(journal: P) -> Self {
+        Self {
+            index: Index::new(journal.as_ref()),
+        }
+    }
+}
+
+impl StaleCheck<Block<Payload>> for JournalCheck {
+    fn matches(&mut self, b: &Block<Payload>) -> io::Result<Option<bool>> {
+        let record = self.index.find(b.header.channel, b.header.seqnum)?;
+        Ok(record.map(|r| r.n_messages == b.header.n_messages && r.payload[..] == b.payload[..]))
+    }
+}
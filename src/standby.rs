@@ -0,0 +1,643 @@
+// Hot standby. A primary heartbeats how far each of its sinks got to a
+// standby consuming the same feeds, whose own sinks hold back what they are
+// given until the heartbeats stop. The standby then takes over, each sink
+// carrying on from where the primary's sink of the same name left off.
+//
+// A heartbeat is a datagram per sink, all integers little endian: magic
+// "SQHB", name_len: u8, name, published: u64 (u64::MAX for sinks that don't
+// number what they publish), n: u16, then n times channel: u32, session:
+// [u8; 10], next seqnum: u64
+use crate::clock::{Clock, RealClock};
+use crate::metrics::FeedId;
+use crate::sink::{self, Sink};
+use crate::{Block, ChannelId, GapReason, Payload, SequencedEvent, Session, SESSION_LEN};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const MAGIC: [u8; 4] = *b"SQHB";
+pub const HEARTBEAT_MS: u64 = 100;
+pub const STANDBY_TIMEOUT_MS: u64 = 1000;
+// Most events a StandbySink holds back
+pub const STANDBY_EVENTS: usize = 1 << 20;
+// channel, session, next
+const POSITION_LEN: usize = 4 + SESSION_LEN + 8;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    pub session: Session,
+    // Seqnum after the last the sink handled
+    pub next: u64,
+}
+
+// How far a sink got
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+    pub channels: BTreeMap<ChannelId, Position>,
+    pub published: Option<u64>,
+}
+
+impl Progress {
+    fn advance(&mut self, channel: ChannelId, session: Session, next: u64) {
+        self.channels.insert(channel, Position { session, next });
+    }
+
+    // Whether the sink handled what ends before `end` of `channel`'s
+    // `session`. `current` is the session the channel is in now, which
+    // anything of another session is older than if the sink got to it.
+    pub fn covers(
+        &self,
+        channel: ChannelId,
+        session: Session,
+        end: u64,
+        current: Option<&Session>,
+    ) -> bool {
+        match self.channels.get(&channel) {
+            Some(p) if p.session == session => end <= p.next,
+            Some(p) => current == Some(&p.session),
+            None => false,
+        }
+    }
+
+    pub fn encode(&self, name: &str, buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.extend_from_slice(&MAGIC);
+        buf.push(name.len() as u8);
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(&self.published.unwrap_or(u64::MAX).to_le_bytes());
+        buf.extend_from_slice(&(self.channels.len() as u16).to_le_bytes());
+        for (channel, p) in &self.channels {
+            buf.extend_from_slice(&channel.to_le_bytes());
+            buf.extend_from_slice(&p.session);
+            buf.extend_from_slice(&p.next.to_le_bytes());
+        }
+    }
+
+    // (sink name, progress) of a heartbeat
+    pub fn decode(buf: &[u8]) -> Option<(String, Progress)> {
+        let rest = buf.strip_prefix(&MAGIC)?;
+        let (&name_len, rest) = rest.split_first()?;
+        let name = std::str::from_utf8(rest.get(..name_len as usize)?).ok()?;
+        let rest = &rest[name_len as usize..];
+        let published = u64::from_le_bytes(rest.get(..8)?.try_into().unwrap());
+        let n = u16::from_le_bytes(rest.get(8..10)?.try_into().unwrap()) as usize;
+        let positions = &rest[10..];
+        if positions.len() != n * POSITION_LEN {
+            return None;
+        }
+        let mut progress = Progress {
+            channels: BTreeMap::new(),
+            published: (published != u64::MAX).then_some(published),
+        };
+        for p in positions.chunks_exact(POSITION_LEN) {
+            let channel = u32::from_le_bytes(p[..4].try_into().unwrap());
+            let session = p[4..4 + SESSION_LEN].try_into().unwrap();
+            let next = u64::from_le_bytes(p[4 + SESSION_LEN..].try_into().unwrap());
+            progress.advance(channel, session, next);
+        }
+        Some((name.to_string(), progress))
+    }
+}
+
+// Passes everything to `inner`, keeping track of how far it got for a
+// Heartbeat. Progress is only heartbeat once flushed, at most every interval
+// and when idle, so a standby never skips what a primary lost by dying.
+pub struct Tracked<S> {
+    inner: S,
+    flushed: Arc<Mutex<Progress>>,
+    progress: Progress,
+    // Whether `progress` is ahead of `flushed`
+    dirty: bool,
+    interval: Duration,
+    last_flush: Instant,
+    sessions: HashMap<ChannelId, Session>,
+}
+
+impl<S: Sink> Tracked<S> {
+    fn advance(&mut self, channel: ChannelId, next: u64) -> io::Result<()> {
+        let session = self.sessions.get(&channel).copied().unwrap_or_default();
+        self.progress.advance(channel, session, next);
+        self.progress.published = self.inner.published();
+        self.dirty = true;
+        if self.last_flush.elapsed() >= self.interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sink> Sink for Tracked<S> {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.inner.on_block(block)?;
+        let header = &block.header;
+        self.sessions.insert(header.channel, header.session);
+        let end = header.seqnum.saturating_add(header.n_messages as u64);
+        self.advance(header.channel, end)
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        self.inner.on_blocks(blocks)?;
+        blocks.iter().try_for_each(|block| {
+            let header = &block.header;
+            self.sessions.insert(header.channel, header.session);
+            let end = header.seqnum.saturating_add(header.n_messages as u64);
+            self.advance(header.channel, end)
+        })
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        self.inner.on_gap(channel, range.clone(), reason)?;
+        self.advance(channel, range.end)
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        self.inner.on_reset(channel, session, seqnum)?;
+        self.sessions.insert(channel, session);
+        self.advance(channel, seqnum)
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.inner.on_resync(channel, seqnum, snapshot)?;
+        self.advance(channel, seqnum)
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.inner.on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.inner.on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.inner.on_end_of_session(channel, session, seqnum)?;
+        self.sessions.insert(channel, session);
+        self.advance(channel, seqnum)
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.inner.on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.inner.on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.last_flush = Instant::now();
+        if self.dirty {
+            *lock(&self.flushed) = self.progress.clone();
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.inner.on_idle()?;
+        if self.dirty {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn published(&self) -> Option<u64> {
+        self.inner.published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.inner.resume_published(seqnum)
+    }
+}
+
+// Sends the progress of tracked sinks to a standby every `interval`
+pub struct Heartbeat {
+    socket: UdpSocket,
+    interval: Duration,
+    sinks: Vec<(&'static str, Arc<Mutex<Progress>>)>,
+    standby: Option<Standby>,
+}
+
+impl Heartbeat {
+    pub fn new(standby: SocketAddr, interval: Duration) -> io::Result<Self> {
+        let any = match standby {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(any)?;
+        socket.connect(standby)?;
+        Ok(Self {
+            socket,
+            interval,
+            sinks: Vec::new(),
+            standby: None,
+        })
+    }
+
+    // Heartbeat how far `sink` gets as `name`. It is flushed every interval
+    // for that.
+    pub fn track<S: Sink>(&mut self, name: &'static str, sink: S) -> Tracked<S> {
+        let progress = Progress {
+            published: sink.published(),
+            ..Default::default()
+        };
+        let flushed = Arc::new(Mutex::new(progress.clone()));
+        self.sinks.push((name, Arc::clone(&flushed)));
+        Tracked {
+            inner: sink,
+            flushed,
+            progress,
+            dirty: false,
+            interval: self.interval,
+            last_flush: Instant::now(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    // Only heartbeat once `standby` took over, for a standby of a standby
+    pub fn set_standby(&mut self, standby: Standby) {
+        self.standby = Some(standby);
+    }
+
+    pub fn send(&self) -> io::Result<()> {
+        if self.standby.as_ref().is_some_and(|s| !s.took_over()) {
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        for (name, progress) in &self.sinks {
+            lock(progress).encode(name, &mut buf);
+            match self.socket.send(&buf) {
+                // Nothing listening yet
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                sent => {
+                    sent?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("heartbeat".to_string())
+            .spawn(move || loop {
+                self.send()?;
+                thread::sleep(self.interval);
+            })
+    }
+}
+
+struct Primary {
+    last: Instant,
+    sinks: HashMap<String, Progress>,
+    took_over: bool,
+    heard_since: bool,
+}
+
+struct Shared {
+    timeout: Duration,
+    clock: Box<dyn Clock + Send + Sync>,
+    primary: Mutex<Primary>,
+}
+
+// The primary as the standby hears it. Clones share it.
+#[derive(Clone)]
+pub struct Standby(Arc<Shared>);
+
+impl Standby {
+    // Take over once `timeout` passes without heartbeats, counting from now
+    // in case the primary never starts
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_clock(timeout, Box::new(RealClock))
+    }
+
+    pub fn with_clock(timeout: Duration, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        let primary = Primary {
+            last: clock.now(),
+            sinks: HashMap::new(),
+            took_over: false,
+            heard_since: false,
+        };
+        Self(Arc::new(Shared {
+            timeout,
+            clock,
+            primary: Mutex::new(primary),
+        }))
+    }
+
+    // Returns whether `buf` was a heartbeat
+    pub fn on_heartbeat(&self, buf: &[u8]) -> bool {
+        let (name, progress) = match Progress::decode(buf) {
+            Some(heartbeat) => heartbeat,
+            None => return false,
+        };
+        let mut primary = lock(&self.0.primary);
+        if primary.took_over {
+            if !primary.heard_since {
+                warn!("heartbeats from the primary after taking over from it");
+                primary.heard_since = true;
+            }
+            return true;
+        }
+        primary.last = self.0.clock.now();
+        primary.sinks.insert(name, progress);
+        true
+    }
+
+    // Receive heartbeats on `addr`
+    pub fn listen(&self, addr: SocketAddr) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        let socket = UdpSocket::bind(addr)?;
+        let standby = self.clone();
+        thread::Builder::new()
+            .name("standby".to_string())
+            .spawn(move || {
+                let mut buf = [0_u8; 65_536];
+                loop {
+                    let (n, from) = socket.recv_from(&mut buf)?;
+                    if !standby.on_heartbeat(&buf[..n]) {
+                        warn!(%from, "not a heartbeat");
+                    }
+                }
+            })
+    }
+
+    // What the primary's sink `name` last heartbeat
+    pub fn progress(&self, name: &str) -> Option<Progress> {
+        lock(&self.0.primary).sinks.get(name).cloned()
+    }
+
+    // Whether the primary went quiet, deciding so once `timeout` passed
+    // since its last heartbeat. Stays true.
+    pub fn took_over(&self) -> bool {
+        let mut primary = lock(&self.0.primary);
+        if !primary.took_over && self.0.clock.now() - primary.last > self.0.timeout {
+            warn!(silent = ?self.0.timeout, "primary stopped heartbeating, taking over");
+            primary.took_over = true;
+        }
+        primary.took_over
+    }
+}
+
+struct Held {
+    channel: ChannelId,
+    session: Session,
+    end: u64,
+    event: SequencedEvent<Block<Payload>>,
+}
+
+// Holds back what `inner` is given until the standby takes over, then gives
+// it what the primary's sink of the same name didn't get to and everything
+// after. What the primary heartbeats its sink got to is dropped as it does.
+pub struct StandbySink<S> {
+    inner: S,
+    name: &'static str,
+    standby: Standby,
+    held: VecDeque<Held>,
+    max_held: usize,
+    sessions: HashMap<ChannelId, Session>,
+    live: bool,
+    dropped: u64,
+}
+
+impl<S: Sink> StandbySink<S> {
+    pub fn new(inner: S, name: &'static str, standby: Standby) -> Self {
+        Self {
+            inner,
+            name,
+            standby,
+            held: VecDeque::new(),
+            max_held: STANDBY_EVENTS,
+            sessions: HashMap::new(),
+            live: false,
+            dropped: 0,
+        }
+    }
+
+    // Hold back at most `max` events, dropping the oldest beyond it
+    pub fn set_max_held(&mut self, max: usize) {
+        self.max_held = max;
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    fn live(&mut self) -> io::Result<bool> {
+        if !self.live && self.standby.took_over() {
+            self.release()?;
+            self.live = true;
+        }
+        Ok(self.live)
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        let progress = self.standby.progress(self.name);
+        if let Some(seqnum) = progress.as_ref().and_then(|p| p.published) {
+            self.inner.resume_published(seqnum);
+        }
+        let mut released = 0;
+        for held in std::mem::take(&mut self.held) {
+            if !self.covered(progress.as_ref(), &held) {
+                sink::deliver(&mut self.inner, &held.event)?;
+                released += 1;
+            }
+        }
+        let channels = progress.map_or(0, |p| p.channels.len());
+        info!(
+            sink = self.name,
+            channels, released, "took over from the primary"
+        );
+        Ok(())
+    }
+
+    fn covered(&self, progress: Option<&Progress>, held: &Held) -> bool {
+        let current = self.sessions.get(&held.channel);
+        progress.is_some_and(|p| p.covers(held.channel, held.session, held.end, current))
+    }
+
+    fn hold(&mut self, channel: ChannelId, end: u64, event: SequencedEvent<Block<Payload>>) {
+        let session = self.sessions.get(&channel).copied().unwrap_or_default();
+        self.held.push_back(Held {
+            channel,
+            session,
+            end,
+            event,
+        });
+        let progress = self.standby.progress(self.name);
+        while self
+            .held
+            .front()
+            .is_some_and(|h| self.covered(progress.as_ref(), h))
+        {
+            self.held.pop_front();
+        }
+        while self.held.len() > self.max_held {
+            self.held.pop_front();
+            if self.dropped == 0 {
+                warn!(
+                    sink = self.name,
+                    "primary is too far behind, dropping the oldest held back events"
+                );
+            }
+            self.dropped += 1;
+        }
+    }
+}
+
+impl<S: Sink> Sink for StandbySink<S> {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_block(block);
+        }
+        let header = &block.header;
+        self.sessions.insert(header.channel, header.session);
+        let end = header.seqnum.saturating_add(header.n_messages as u64);
+        self.hold(header.channel, end, SequencedEvent::Block(block.clone()));
+        Ok(())
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_blocks(blocks);
+        }
+        blocks.iter().try_for_each(|b| self.on_block(b))
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        reason: GapReason,
+    ) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_gap(channel, range, reason);
+        }
+        let gap = SequencedEvent::Gap {
+            channel,
+            from: range.start,
+            to: range.end,
+            reason,
+        };
+        self.hold(channel, range.end, gap);
+        Ok(())
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, session: Session, seqnum: u64) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_reset(channel, session, seqnum);
+        }
+        self.sessions.insert(channel, session);
+        let reset = SequencedEvent::SessionReset {
+            channel,
+            session,
+            seqnum,
+        };
+        self.hold(channel, seqnum, reset);
+        Ok(())
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_resync(channel, seqnum, snapshot);
+        }
+        let resync = SequencedEvent::Resynced {
+            channel,
+            seqnum,
+            snapshot: snapshot.to_vec(),
+        };
+        self.hold(channel, seqnum, resync);
+        Ok(())
+    }
+
+    // About this instance's own feeds, so never held back
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.inner.on_feed_down(feed, silent)
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.inner.on_feed_up(feed)
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        if self.live()? {
+            return self.inner.on_end_of_session(channel, session, seqnum);
+        }
+        self.sessions.insert(channel, session);
+        let end = SequencedEvent::EndOfSession {
+            channel,
+            session,
+            seqnum,
+        };
+        self.hold(channel, seqnum, end);
+        Ok(())
+    }
+
+    // Of this instance's own feeds too
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        self.inner.on_sessions_diverged(channel, sessions)
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, session: Session) -> io::Result<()> {
+        self.inner.on_sessions_agreed(channel, session)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    // Takes over if due, so a quiet stream is handed off too
+    fn on_idle(&mut self) -> io::Result<()> {
+        self.live()?;
+        self.inner.on_idle()
+    }
+
+    fn published(&self) -> Option<u64> {
+        self.inner.published()
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.inner.resume_published(seqnum)
+    }
+}
@@ -0,0 +1,167 @@
+// Running as a systemd service: sockets a socket unit bound for it
+// (LISTEN_FDS, see sd_listen_fds(3)) and notifications to the service
+// manager (NOTIFY_SOCKET, see sd_notify(3)) for Type=notify units and their
+// WatchdogSec=. Outside systemd there are no sockets and notifications go
+// nowhere.
+use crate::health::Health;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use std::env;
+use std::io;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+#[cfg(unix)]
+use tracing::warn;
+
+// First descriptor systemd passes
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+// Sockets passed by systemd, taken by whatever binds the same address
+#[derive(Debug, Default)]
+pub struct Inherited {
+    udp: Vec<UdpSocket>,
+    tcp: Vec<TcpListener>,
+}
+
+impl Inherited {
+    // The sockets passed to this process, if any. Unsets LISTEN_FDS so child
+    // processes don't take them too.
+    #[cfg(unix)]
+    pub fn take() -> Self {
+        use socket2::{Socket, Type};
+        use std::os::fd::FromRawFd;
+
+        let mut inherited = Self::default();
+        let ours =
+            env::var("LISTEN_PID").ok().and_then(|p| p.parse().ok()) == Some(std::process::id());
+        let n: i32 = match env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()) {
+            Some(n) if ours => n,
+            _ => return inherited,
+        };
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+        for fd in LISTEN_FDS_START..LISTEN_FDS_START + n {
+            // SAFETY: systemd passed these to this process alone
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            if let Err(e) = socket.set_cloexec(true) {
+                warn!(fd, error = %e, "bad inherited socket");
+                continue;
+            }
+            match socket.r#type() {
+                Ok(Type::DGRAM) => inherited.udp.push(socket.into()),
+                Ok(Type::STREAM) => inherited.tcp.push(socket.into()),
+                _ => warn!(fd, "inherited socket is not udp or tcp"),
+            }
+        }
+        inherited
+    }
+
+    #[cfg(not(unix))]
+    pub fn take() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.udp.is_empty() && self.tcp.is_empty()
+    }
+
+    // The UDP socket bound to `port`, for a multicast feed to join its group on
+    pub fn take_udp(&mut self, port: u16) -> Option<UdpSocket> {
+        let i = self
+            .udp
+            .iter()
+            .position(|s| s.local_addr().is_ok_and(|a| a.port() == port))?;
+        Some(self.udp.remove(i))
+    }
+
+    // The listener bound to `addr`, or to its port on every address
+    pub fn take_tcp(&mut self, addr: SocketAddr) -> Option<TcpListener> {
+        let i = self.tcp.iter().position(|l| {
+            l.local_addr()
+                .is_ok_and(|a| a == addr || (a.port() == addr.port() && a.ip().is_unspecified()))
+        })?;
+        Some(self.tcp.remove(i))
+    }
+}
+
+// Send `state`, such as "READY=1", to the service manager. Returns false if
+// there is none.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::UnixDatagram;
+
+        let socket = UnixDatagram::unbound()?;
+        match path.as_bytes() {
+            #[cfg(target_os = "linux")]
+            [b'@', name @ ..] => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            _ => {
+                socket.send_to(state.as_bytes(), &path)?;
+            }
+        }
+        Ok(true)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, state);
+        Ok(false)
+    }
+}
+
+// How often WatchdogSec= wants to hear from this process, if it is set
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid = env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|p| p.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|d| !d.is_zero())
+}
+
+// Pings the watchdog at half its interval while the arbiter is live, so
+// systemd restarts a sequencer that has wedged
+pub struct Watchdog {
+    health: Health,
+    interval: Duration,
+}
+
+impl Watchdog {
+    pub fn new(health: Health, interval: Duration) -> Self {
+        Self { health, interval }
+    }
+
+    pub fn run(self, shutdown: Shutdown) -> io::Result<()> {
+        let mut next = Instant::now();
+        while !shutdown.requested() {
+            if Instant::now() >= next {
+                let status = self.health.check();
+                if status.live {
+                    notify("WATCHDOG=1")?;
+                } else {
+                    debug!(%status, "not pinging the watchdog");
+                }
+                next += self.interval / 2;
+            }
+            thread::sleep(SHUTDOWN_POLL.min(self.interval / 2));
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self, shutdown: Shutdown) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || self.run(shutdown))
+    }
+}
@@ -0,0 +1,275 @@
+// Feeds streamed over TCP, such as another sequencer's output or a vendor's
+// TCP feed, as packets of the feed's protocol each behind a length prefix.
+// Each packet is framed and sequenced like a UDP datagram of the protocol, so
+// a TCP line can be a feed next to UDP ones. Packets of no bytes are
+// heartbeats. With the tls feature the stream can be TLS.
+use crate::checksum::Checksum;
+use crate::metrics::FeedMetrics;
+use crate::protocol::Protocol;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::{Block, ChannelId, Payload};
+use crossbeam_channel::Sender;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// Longer packets mean the stream is out of step or not length-prefixed
+pub const MAX_PACKET: usize = 1 << 20;
+
+// Big endian length of the packet after it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LengthPrefix {
+    #[default]
+    U16,
+    U32,
+}
+
+impl LengthPrefix {
+    // Bytes of the prefix itself
+    pub fn bytes(&self) -> usize {
+        match self {
+            LengthPrefix::U16 => 2,
+            LengthPrefix::U32 => 4,
+        }
+    }
+
+    // Length of the packet at the start of `buf`, if its prefix is all there
+    fn read(&self, buf: &[u8]) -> Option<usize> {
+        match self {
+            LengthPrefix::U16 => Some(u16::from_be_bytes(buf.get(..2)?.try_into().ok()?) as usize),
+            LengthPrefix::U32 => Some(u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize),
+        }
+    }
+
+    // `packet` behind its prefix, as a server writes it
+    pub fn write(&self, buf: &mut Vec<u8>, packet: &[u8]) {
+        match self {
+            LengthPrefix::U16 => buf.extend_from_slice(&(packet.len() as u16).to_be_bytes()),
+            LengthPrefix::U32 => buf.extend_from_slice(&(packet.len() as u32).to_be_bytes()),
+        }
+        buf.extend_from_slice(packet);
+    }
+}
+
+impl FromStr for LengthPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u16" => Ok(LengthPrefix::U16),
+            "u32" => Ok(LengthPrefix::U32),
+            _ => Err(format!("unknown length prefix {}", s)),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    // Name the server's certificate has to be for
+    pub server_name: String,
+    // Certificates of the authorities trusted to sign it
+    pub roots: Vec<rustls::pki_types::CertificateDer<'static>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpFeedConfig {
+    pub addr: SocketAddr,
+    pub prefix: LengthPrefix,
+    pub protocol: Protocol,
+    // Channel the feed's blocks are sequenced in
+    pub channel: ChannelId,
+    // Plain TCP if None
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_stream(tcp: TcpStream, tls: &TlsConfig) -> io::Result<Stream> {
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+
+    let invalid = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+    let roots = crate::tls::root_store(&tls.roots)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(tls.server_name.clone())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut conn = ClientConnection::new(Arc::new(config), name).map_err(invalid)?;
+    let mut tcp = tcp;
+    tcp.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)?;
+    }
+    tcp.set_read_timeout(Some(SHUTDOWN_POLL))?;
+    Ok(Stream::Tls(Box::new(StreamOwned::new(conn, tcp))))
+}
+
+pub struct TcpFeed {
+    stream: Stream,
+    addr: SocketAddr,
+    prefix: LengthPrefix,
+    protocol: Protocol,
+    channel: ChannelId,
+    metrics: Option<Arc<FeedMetrics>>,
+    checksum: Checksum,
+    shutdown: Option<Shutdown>,
+    // Received bytes not yet split into packets
+    pending: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl TcpFeed {
+    // Connect to the server, finishing the TLS handshake if there is one
+    pub fn connect(config: &TcpFeedConfig) -> io::Result<Self> {
+        let tcp = TcpStream::connect_timeout(&config.addr, CONNECT_TIMEOUT)?;
+        tcp.set_nodelay(true)?;
+        tcp.set_read_timeout(Some(SHUTDOWN_POLL))?;
+        #[cfg(feature = "tls")]
+        let stream = match &config.tls {
+            Some(tls) => tls_stream(tcp, tls)?,
+            None => Stream::Plain(tcp),
+        };
+        #[cfg(not(feature = "tls"))]
+        let stream = Stream::Plain(tcp);
+        Ok(Self {
+            stream,
+            addr: config.addr,
+            prefix: config.prefix,
+            protocol: config.protocol.clone(),
+            channel: config.channel,
+            metrics: None,
+            checksum: Checksum::Off,
+            shutdown: None,
+            pending: Vec::new(),
+            buf: vec![0; 65_536],
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Stop run() once `shutdown` is requested, even if nothing arrives
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // Count malformed packets in the sequencer's metrics of the feed
+    pub fn set_metrics(&mut self, metrics: Arc<FeedMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // Check and strip a trailer from every packet before parsing it
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+    }
+
+    // Next complete packet, or None if the read timed out first
+    fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(len) = self.prefix.read(&self.pending) {
+                let prefix = self.prefix.bytes();
+                if len > MAX_PACKET {
+                    let msg = format!("{} byte packet", len);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+                if self.pending.len() >= prefix + len {
+                    let packet = self.pending[prefix..prefix + len].to_vec();
+                    self.pending.drain(..prefix + len);
+                    return Ok(Some(packet));
+                }
+            }
+            match self.stream.read(&mut self.buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.pending.extend_from_slice(&self.buf[..n]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The block in `packet`, its payload left in place
+    fn block(&self, packet: Vec<u8>) -> Option<Block<Payload>> {
+        let checked = match self.checksum.check(&packet) {
+            Some(checked) => checked,
+            None => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.corrupt.fetch_add(1, Ordering::Relaxed);
+                }
+                return None;
+            }
+        };
+        let parsed = self.protocol.parse_checked(checked);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_parsed(&parsed);
+        }
+        let (mut header, payload) = parsed.ok().map(|p| (p.header, p.payload))?;
+        let start = (payload.as_ptr() as usize).checked_sub(packet.as_ptr() as usize);
+        let range = start
+            .map(|start| start..start + payload.len())
+            .filter(|range| range.end <= packet.len());
+        let payload = match range {
+            Some(range) => Payload::shared(Arc::new(packet), range),
+            // Not a slice of the packet
+            None => payload.into(),
+        };
+        header.channel = self.protocol.channel(self.channel, &header);
+        let mut b = Block::new(header, payload);
+        b.received = Some(Instant::now());
+        Some(b)
+    }
+
+    // Returns Ok(None) on read timeout, a heartbeat or a packet with nothing
+    // to sequence. Fails once the server closes the connection.
+    pub fn recv(&mut self) -> io::Result<Option<Block<Payload>>> {
+        match self.next_packet()? {
+            Some(packet) if !packet.is_empty() => Ok(self.block(packet)),
+            _ => Ok(None),
+        }
+    }
+
+    // Receive until the arbiter hangs up, shutdown is requested or the
+    // connection closes
+    pub fn run(mut self, sender: Sender<Block<Payload>>) -> io::Result<()> {
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            if let Some(b) = self.recv()? {
+                if sender.send(b).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
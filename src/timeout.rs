@@ -0,0 +1,236 @@
+use crate::ChannelId;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Weight of the latest inter-arrival time in Adaptive's average
+const ARRIVAL_ALPHA: f64 = 0.1;
+// Adaptive never waits less than this, so a burst doesn't time out gaps
+// before a reordered packet can arrive
+pub const MIN_ADAPTIVE: Duration = Duration::from_micros(100);
+
+// Decides when a channel's gap has been waited on long enough. Each channel
+// has its own policy. `timeout` is the sequencer's, see set_timeout().
+pub trait TimeoutPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    // A block of a seqnum the channel had not seen arrived
+    fn on_arrival(&mut self, _now: Instant) {}
+
+    // A gap was filled by a block arriving `waited` after the block past it
+    fn on_filled(&mut self, _waited: Duration) {}
+
+    // Longest a gap waits, for scheduling polls
+    fn max_wait(&self, timeout: Duration) -> Duration {
+        timeout
+    }
+
+    // Whether a block buffered `waited` ago, with `newer` blocks arriving
+    // since, has timed out. Must only become true as either grows, as only
+    // the oldest buffered blocks are checked.
+    fn expired(&self, waited: Duration, _newer: u64, timeout: Duration) -> bool {
+        waited > self.max_wait(timeout)
+    }
+}
+
+// Waits the sequencer's timeout
+#[derive(Clone, Debug, Default)]
+pub struct Fixed;
+
+impl TimeoutPolicy for Fixed {
+    fn name(&self) -> &'static str {
+        "fixed"
+    }
+}
+
+// Times out once `packets` newer blocks have arrived, or after the
+// sequencer's timeout if the channel goes quiet first
+#[derive(Clone, Debug)]
+pub struct PacketCount {
+    packets: u64,
+}
+
+impl PacketCount {
+    pub fn new(packets: u64) -> Self {
+        Self { packets }
+    }
+}
+
+impl TimeoutPolicy for PacketCount {
+    fn name(&self) -> &'static str {
+        "packets"
+    }
+
+    fn expired(&self, waited: Duration, newer: u64, timeout: Duration) -> bool {
+        newer >= self.packets || waited > timeout
+    }
+}
+
+// Waits `multiplier` times the exponentially weighted inter-arrival time, at
+// least MIN_ADAPTIVE and at most the sequencer's timeout, which is also the
+// wait until there is an average
+#[derive(Clone, Debug)]
+pub struct Adaptive {
+    multiplier: f64,
+    last: Option<Instant>,
+    // Seconds
+    average: Option<f64>,
+}
+
+impl Adaptive {
+    pub fn new(multiplier: f64) -> Self {
+        Self {
+            multiplier,
+            last: None,
+            average: None,
+        }
+    }
+
+    // Average inter-arrival time so far
+    pub fn average(&self) -> Option<Duration> {
+        self.average.map(Duration::from_secs_f64)
+    }
+}
+
+impl TimeoutPolicy for Adaptive {
+    fn name(&self) -> &'static str {
+        "adaptive"
+    }
+
+    fn on_arrival(&mut self, now: Instant) {
+        if let Some(last) = self.last.replace(now) {
+            let sample = now.saturating_duration_since(last).as_secs_f64();
+            let average = self.average.get_or_insert(sample);
+            *average += ARRIVAL_ALPHA * (sample - *average);
+        }
+    }
+
+    fn max_wait(&self, timeout: Duration) -> Duration {
+        match self.average {
+            Some(average) => Duration::from_secs_f64(average * self.multiplier)
+                .clamp(MIN_ADAPTIVE, timeout.max(MIN_ADAPTIVE)),
+            None => timeout,
+        }
+    }
+}
+
+// Waits `multiplier` times the longest a gap on the channel has taken to
+// fill, at least MIN_ADAPTIVE and at most the sequencer's timeout, which is
+// also the wait until a gap has filled. A block later than the wait times its
+// gap out instead of filling it and goes unseen, so the multiplier is the
+// headroom for reordering worse than any seen so far.
+#[derive(Clone, Debug)]
+pub struct Tuned {
+    multiplier: f64,
+    longest: Option<Duration>,
+}
+
+impl Tuned {
+    pub fn new(multiplier: f64) -> Self {
+        Self {
+            multiplier,
+            longest: None,
+        }
+    }
+
+    // Longest a gap has taken to fill so far
+    pub fn longest(&self) -> Option<Duration> {
+        self.longest
+    }
+}
+
+impl TimeoutPolicy for Tuned {
+    fn name(&self) -> &'static str {
+        "tuned"
+    }
+
+    fn on_filled(&mut self, waited: Duration) {
+        self.longest = self.longest.max(Some(waited));
+    }
+
+    fn max_wait(&self, timeout: Duration) -> Duration {
+        match self.longest {
+            Some(longest) => longest
+                .mul_f64(self.multiplier)
+                .clamp(MIN_ADAPTIVE, timeout.max(MIN_ADAPTIVE)),
+            None => timeout,
+        }
+    }
+}
+
+// Built in policies by name
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GapTimeout {
+    #[default]
+    Fixed,
+    // After this many newer blocks
+    Packets(u64),
+    // This many times the average inter-arrival time
+    Adaptive(f64),
+    // This many times the longest a gap has taken to fill
+    Tuned(f64),
+}
+
+impl GapTimeout {
+    pub fn policy(&self) -> Box<dyn TimeoutPolicy> {
+        match self {
+            GapTimeout::Fixed => Box::new(Fixed),
+            GapTimeout::Packets(packets) => Box::new(PacketCount::new(*packets)),
+            GapTimeout::Adaptive(multiplier) => Box::new(Adaptive::new(*multiplier)),
+            GapTimeout::Tuned(multiplier) => Box::new(Tuned::new(*multiplier)),
+        }
+    }
+}
+
+// fixed, packets:N, adaptive:MULTIPLIER or tuned:MULTIPLIER
+impl FromStr for GapTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            format!(
+                "bad gap timeout {}, expected fixed, packets:N, adaptive:M or tuned:M",
+                s
+            )
+        };
+        match s.split_once(':') {
+            None if s == "fixed" => Ok(GapTimeout::Fixed),
+            Some(("packets", n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(GapTimeout::Packets(n)),
+                _ => Err(bad()),
+            },
+            Some(("adaptive", m)) => match m.parse::<f64>() {
+                Ok(m) if m.is_finite() && m > 0.0 => Ok(GapTimeout::Adaptive(m)),
+                _ => Err(bad()),
+            },
+            Some(("tuned", m)) => match m.parse::<f64>() {
+                Ok(m) if m.is_finite() && m > 0.0 => Ok(GapTimeout::Tuned(m)),
+                _ => Err(bad()),
+            },
+            _ => Err(bad()),
+        }
+    }
+}
+
+// CHANNEL=POLICY, a channel's gap timeout in place of the default
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelTimeout {
+    pub channel: ChannelId,
+    pub timeout: GapTimeout,
+}
+
+impl FromStr for ChannelTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, timeout) = s
+            .split_once('=')
+            .ok_or_else(|| format!("bad channel gap timeout {}, expected CHANNEL=POLICY", s))?;
+        let channel = channel
+            .parse()
+            .map_err(|_| format!("bad channel {}", channel))?;
+        Ok(Self {
+            channel,
+            timeout: timeout.parse()?,
+        })
+    }
+}
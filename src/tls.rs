@@ -0,0 +1,28 @@
+// Certificates for authenticating the servers feeds are received from, over
+// TLS or QUIC
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::RootCertStore;
+use std::io;
+use std::path::Path;
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+// The certificates of a PEM file, such as a CA bundle
+pub fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .map_err(invalid)?
+        .collect::<Result<_, _>>()
+        .map_err(invalid)
+}
+
+// Trusting `roots` to sign servers' certificates
+pub fn root_store(roots: &[CertificateDer<'static>]) -> io::Result<RootCertStore> {
+    let mut store = RootCertStore::empty();
+    for cert in roots {
+        store.add(cert.clone()).map_err(invalid)?;
+    }
+    Ok(store)
+}
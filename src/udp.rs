@@ -0,0 +1,743 @@
+use crate::checksum::Checksum;
+use crate::metrics::{FeedId, FeedMetrics};
+use crate::pool::{Buffer, BufferPool};
+use crate::protocol::Protocol;
+use crate::recorder::RawPacket;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::{Block, BlockHeader, ChannelId, Payload};
+use crossbeam_channel::Sender;
+use socket2::{Domain, SockRef, Socket, Type};
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const MAX_DATAGRAM: usize = 65_536;
+// seqnum: u64 BE, n_messages: u16 BE
+pub const HEADER_LEN: usize = 10;
+// Most datagrams received per recvmmsg call
+pub const MAX_BATCH: usize = 64;
+// Free buffers kept by a feed's own pool
+pub const POOL_LEN: usize = 1_024;
+// Buffers each io_uring feed keeps provided to the kernel
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub const URING_BUFFERS: u16 = 1_024;
+
+#[derive(Clone, Debug)]
+pub struct FeedConfig {
+    pub interface: Ipv4Addr,
+    pub group: Ipv4Addr,
+    pub port: u16,
+    pub protocol: Protocol,
+    // Channel the feed's blocks are sequenced in
+    pub channel: ChannelId,
+    // Only receive the group from this sender (IGMPv3 source-specific join)
+    pub source: Option<Ipv4Addr>,
+    // SO_REUSEADDR and SO_REUSEPORT, so other processes can join the same
+    // group and port
+    pub reuse_port: bool,
+    // SO_RCVBUF, the kernel's default if not given
+    pub recv_buffer: Option<usize>,
+}
+
+// Socket options as the kernel applied them, which can differ from what was
+// asked for. Linux doubles SO_RCVBUF and caps it at net.core.rmem_max.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SocketInfo {
+    pub recv_buffer: usize,
+    pub reuse_address: bool,
+    pub reuse_port: bool,
+}
+
+// Bind a socket to `config`'s port and join its group on its interface
+pub fn bind_multicast(config: &FeedConfig) -> io::Result<UdpSocket> {
+    let socket = bind_port(config)?;
+    join_multicast(&socket, config)?;
+    Ok(socket)
+}
+
+fn bind_port(config: &FeedConfig) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    if config.reuse_port {
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+    }
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.port).into())?;
+    Ok(socket.into())
+}
+
+// Join `config`'s group on its interface with a socket already bound to its
+// port, such as one systemd bound
+pub fn join_multicast(socket: &UdpSocket, config: &FeedConfig) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+    if let Some(bytes) = config.recv_buffer {
+        socket.set_recv_buffer_size(bytes)?;
+        // Past net.core.rmem_max as root, or with CAP_NET_ADMIN
+        #[cfg(target_os = "linux")]
+        if socket.recv_buffer_size()? < bytes {
+            let _ = force_recv_buffer(&socket, bytes);
+        }
+    }
+    socket.set_multicast_if_v4(&config.interface)?;
+    match config.source {
+        Some(source) => socket.join_ssm_v4(&source, &config.group, &config.interface),
+        None => socket.join_multicast_v4(&config.group, &config.interface),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn force_recv_buffer(socket: &SockRef, bytes: usize) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let bytes = bytes.min(libc::c_int::MAX as usize) as libc::c_int;
+    // SAFETY: bytes outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUFFORCE,
+            (&bytes as *const libc::c_int).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+pub fn socket_info(socket: &UdpSocket) -> io::Result<SocketInfo> {
+    let socket = SockRef::from(socket);
+    Ok(SocketInfo {
+        recv_buffer: socket.recv_buffer_size()?,
+        reuse_address: socket.reuse_address()?,
+        #[cfg(unix)]
+        reuse_port: socket.reuse_port()?,
+        #[cfg(not(unix))]
+        reuse_port: false,
+    })
+}
+
+// Where receive timestamps come from
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Timestamping {
+    // When the feed thread got the datagram
+    #[default]
+    Off,
+    // When the kernel got it (SO_TIMESTAMPING)
+    Software,
+    // When the NIC got it, falling back to the kernel's. The NIC must also
+    // have timestamping enabled, such as with hwstamp_ctl.
+    Hardware,
+}
+
+impl FromStr for Timestamping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Timestamping::Off),
+            "software" => Ok(Timestamping::Software),
+            "hardware" => Ok(Timestamping::Hardware),
+            _ => Err(format!("unknown timestamping {}", s)),
+        }
+    }
+}
+
+pub struct UdpFeed {
+    socket: UdpSocket,
+    protocol: Protocol,
+    channel: ChannelId,
+    dst: SocketAddrV4,
+    recorder: Option<(FeedId, Sender<RawPacket>)>,
+    // Where malformed datagrams are counted
+    metrics: Option<Arc<FeedMetrics>>,
+    timestamping: Timestamping,
+    checksum: Checksum,
+    shutdown: Option<Shutdown>,
+    pool: BufferPool,
+    // What the next datagram is received into
+    buf: Buffer,
+    batch: Option<RecvBatch>,
+}
+
+pub fn parse_header(buf: &[u8]) -> Option<BlockHeader> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+    Some(BlockHeader {
+        channel: 0,
+        seqnum: u64::from_be_bytes(buf[0..8].try_into().unwrap()),
+        n_messages: u16::from_be_bytes(buf[8..10].try_into().unwrap()),
+        ..Default::default()
+    })
+}
+
+impl UdpFeed {
+    pub fn join(config: &FeedConfig) -> io::Result<Self> {
+        Self::with_socket(bind_port(config)?, config)
+    }
+
+    // join() on a socket already bound to `config`'s port
+    pub fn with_socket(socket: UdpSocket, config: &FeedConfig) -> io::Result<Self> {
+        join_multicast(&socket, config)?;
+        let pool = BufferPool::new(POOL_LEN, MAX_DATAGRAM);
+        Ok(Self {
+            socket,
+            protocol: config.protocol.clone(),
+            channel: config.channel,
+            dst: SocketAddrV4::new(config.group, config.port),
+            recorder: None,
+            metrics: None,
+            timestamping: Timestamping::Off,
+            checksum: Checksum::Off,
+            shutdown: None,
+            buf: pool.get(),
+            pool,
+            batch: None,
+        })
+    }
+
+    // Receive into buffers from `pool`, which can be shared with other feeds.
+    // Datagrams longer than its buffers are dropped or truncated.
+    pub fn set_pool(&mut self, pool: BufferPool) {
+        self.buf = pool.get();
+        if let Some(batch) = &self.batch {
+            self.batch = Some(RecvBatch::with_pool(batch.capacity(), pool.clone()));
+        }
+        self.pool = pool;
+    }
+
+    // Have recv_batch() receive up to `len` datagrams per syscall
+    pub fn set_recv_batch(&mut self, len: usize) {
+        self.batch = Some(RecvBatch::with_pool(len, self.pool.clone()));
+    }
+
+    // Stop run() once `shutdown` is requested, even if nothing arrives
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) -> io::Result<()> {
+        self.socket.set_read_timeout(Some(SHUTDOWN_POLL))?;
+        self.shutdown = Some(shutdown);
+        Ok(())
+    }
+
+    pub fn set_timestamping(&mut self, timestamping: Timestamping) -> io::Result<()> {
+        enable_timestamping(&self.socket, timestamping)?;
+        self.timestamping = timestamping;
+        Ok(())
+    }
+
+    // Send a copy of every datagram to a RawRecorder as `feed`
+    pub fn record_to(&mut self, feed: FeedId, recorder: Sender<RawPacket>) {
+        self.recorder = Some((feed, recorder));
+    }
+
+    // Count malformed datagrams in the sequencer's metrics of the feed
+    pub fn set_metrics(&mut self, metrics: Arc<FeedMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // Check and strip a trailer from every datagram before parsing it
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn socket_info(&self) -> io::Result<SocketInfo> {
+        socket_info(&self.socket)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    // Spin on a non-blocking socket instead of sleeping in recv. `busy_poll_us`
+    // also has the kernel poll the NIC for that long (SO_BUSY_POLL), which
+    // needs CAP_NET_ADMIN.
+    pub fn set_busy_poll(&self, busy_poll_us: Option<u32>) -> io::Result<()> {
+        self.socket.set_nonblocking(true)?;
+        #[cfg(target_os = "linux")]
+        if let Some(us) = busy_poll_us {
+            SockRef::from(&self.socket).set_busy_poll(us)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        if busy_poll_us.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SO_BUSY_POLL is linux only",
+            ));
+        }
+        Ok(())
+    }
+
+    // Returns Ok(None) on read timeout or a datagram with nothing to sequence
+    pub fn recv(&mut self) -> io::Result<Option<Block<Payload>>> {
+        let res = match self.timestamping {
+            Timestamping::Off => self
+                .socket
+                .recv_from(&mut self.buf)
+                .map(|(n, src)| (n, src, None)),
+            _ => recv_timestamped(&self.socket, &mut self.buf),
+        };
+        match res {
+            Ok((n, src, ts)) => {
+                self.record(src, &self.buf[..n]);
+                let buf = mem::replace(&mut self.buf, self.pool.get());
+                Ok(self.block(buf, 0..n, ts))
+            }
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Append the blocks of up to a batch of datagrams to `blocks`, adding none
+    // on read timeout. Receives one datagram per call if set_recv_batch wasn't.
+    pub fn recv_batch(&mut self, blocks: &mut Vec<Block<Payload>>) -> io::Result<()> {
+        let mut batch = match self.batch.take() {
+            Some(batch) => batch,
+            None => {
+                blocks.extend(self.recv()?);
+                return Ok(());
+            }
+        };
+        let res = batch.recv(&self.socket);
+        if res.is_ok() {
+            for (buf, n, src, ts) in batch.take() {
+                self.record(src, &buf[..n]);
+                blocks.extend(self.block(buf, 0..n, ts));
+            }
+        }
+        self.batch = Some(batch);
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) if is_timeout(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // The block in the datagram at `data` in `buf`, its payload left in place
+    fn block(
+        &self,
+        buf: Buffer,
+        data: Range<usize>,
+        ts: Option<SystemTime>,
+    ) -> Option<Block<Payload>> {
+        let data = match self.checksum.check(&buf[data.clone()]) {
+            Some(checked) => data.start..data.start + checked.len(),
+            None => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.corrupt.fetch_add(1, Ordering::Relaxed);
+                }
+                return None;
+            }
+        };
+        let parsed = self.protocol.parse_checked(&buf[data.clone()]);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_parsed(&parsed);
+        }
+        let (mut header, payload) = parsed.ok().map(|p| (p.header, p.payload))?;
+        let start = (payload.as_ptr() as usize).checked_sub(buf.as_ptr() as usize);
+        let range = start.map(|start| start..start + payload.len());
+        let payload = match range {
+            Some(range) if range.start >= data.start && range.end <= data.end => {
+                buf.into_payload(range)
+            }
+            // Not a slice of the datagram
+            _ => payload.into(),
+        };
+        header.channel = self.protocol.channel(self.channel, &header);
+        let mut b = Block::new(header, payload);
+        b.received = Some(ts.map_or_else(Instant::now, instant_of));
+        Some(b)
+    }
+
+    fn record(&self, src: SocketAddr, data: &[u8]) {
+        if let (Some((feed, recorder)), SocketAddr::V4(src)) = (&self.recorder, src) {
+            let packet = RawPacket {
+                feed: *feed,
+                ts: SystemTime::now(),
+                src,
+                dst: self.dst,
+                data: data.to_vec(),
+            };
+            // Recording stops if the recorder is gone but sequencing carries on
+            let _ = recorder.send(packet);
+        }
+    }
+
+    // Receive until the arbiter hangs up or shutdown is requested
+    pub fn run(mut self, sender: Sender<Block<Payload>>) -> io::Result<()> {
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            match self.recv()? {
+                Some(b) => {
+                    if sender.send(b).is_err() {
+                        return Ok(());
+                    }
+                }
+                None => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    // run() handing the arbiter a batch of blocks at a time
+    pub fn run_batched(mut self, sender: Sender<Vec<Block<Payload>>>) -> io::Result<()> {
+        let len = self.batch.as_ref().map_or(1, RecvBatch::capacity);
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            let mut blocks = Vec::with_capacity(len);
+            self.recv_batch(&mut blocks)?;
+            if blocks.is_empty() {
+                std::hint::spin_loop();
+            } else if sender.send(blocks).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+impl UdpFeed {
+    // run_batched() receiving with io_uring instead of recvmmsg, falling back
+    // to run_batched() on kernels without multishot recvmsg. Needs pool
+    // buffers uring::RECV_OVERHEAD bytes longer than datagrams.
+    pub fn run_uring(self, sender: Sender<Vec<Block<Payload>>>) -> io::Result<()> {
+        use crate::uring::UringRecv;
+
+        let mut ring = match UringRecv::new(&self.socket, self.pool.clone(), URING_BUFFERS) {
+            Ok(ring) => ring,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                tracing::warn!(error = %e, "io_uring unsupported, falling back to recvmmsg");
+                return self.run_batched(sender);
+            }
+            Err(e) => return Err(e),
+        };
+        let timeout = self.shutdown.as_ref().map(|_| SHUTDOWN_POLL);
+        let mut received = Vec::new();
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            match ring.recv(&mut received, timeout) {
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    tracing::warn!(error = %e, "io_uring unsupported, falling back to recvmmsg");
+                    drop(ring);
+                    return self.run_batched(sender);
+                }
+                res => res?,
+            }
+            let blocks: Vec<_> = received
+                .drain(..)
+                .filter_map(|r| {
+                    self.record(r.src, &r.buf[r.data.clone()]);
+                    self.block(r.buf, r.data, r.ts)
+                })
+                .collect();
+            if !blocks.is_empty() && sender.send(blocks).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+// Map a wall clock receive timestamp onto the monotonic clock timeouts use
+fn instant_of(ts: SystemTime) -> Instant {
+    let now = Instant::now();
+    let age = SystemTime::now().duration_since(ts).unwrap_or_default();
+    now.checked_sub(age).unwrap_or(now)
+}
+
+// Have the kernel timestamp datagrams received on `socket`
+#[cfg(target_os = "linux")]
+pub fn enable_timestamping(socket: &UdpSocket, timestamping: Timestamping) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut flags = match timestamping {
+        Timestamping::Off => 0,
+        _ => libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE,
+    };
+    if timestamping == Timestamping::Hardware {
+        flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+    }
+    let flags = flags as libc::c_int;
+    // SAFETY: the option value is a c_int that outlives the call
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            (&flags as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_timestamping(_socket: &UdpSocket, timestamping: Timestamping) -> io::Result<()> {
+    match timestamping {
+        Timestamping::Off => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_TIMESTAMPING is linux only",
+        )),
+    }
+}
+
+// recv_from that also returns the datagram's SO_TIMESTAMPING timestamp, the
+// hardware one if there is one
+#[cfg(target_os = "linux")]
+pub fn recv_timestamped(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<SystemTime>)> {
+    use std::mem;
+    use std::os::fd::AsRawFd;
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // SAFETY: all zeroes is a valid sockaddr_in and msghdr
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    // Room for SCM_TIMESTAMPING's three timespecs, u64s for alignment
+    let mut control = [0_u64; 16];
+    msg.msg_name = (&mut addr as *mut libc::sockaddr_in).cast();
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    // SAFETY: msg points at buffers that outlive the call
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: the kernel filled in msg
+    let ts = unsafe { rx_timestamp(&msg) };
+    Ok((n as usize, socket_addr(&addr), ts))
+}
+
+// SCM_TIMESTAMPING timestamp of a datagram recvmsg filled `msg` in for
+//
+// SAFETY: `msg` must have been filled in by the kernel
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn rx_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
+    use std::time::UNIX_EPOCH;
+
+    let mut ts = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING {
+            let stamps: [libc::timespec; 3] =
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+            // Software, deprecated, raw hardware. Unset ones are zero.
+            ts = [stamps[2], stamps[0]]
+                .into_iter()
+                .find(|t| t.tv_sec != 0 || t.tv_nsec != 0)
+                .map(|t| UNIX_EPOCH + Duration::new(t.tv_sec as u64, t.tv_nsec as u32));
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    ts
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn socket_addr(addr: &libc::sockaddr_in) -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        u16::from_be(addr.sin_port),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_timestamped(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<SystemTime>)> {
+    socket.recv_from(buf).map(|(n, src)| (n, src, None))
+}
+
+// Pooled buffers to receive up to MAX_BATCH datagrams into with one recvmmsg
+// call. Off linux it receives one datagram per call.
+pub struct RecvBatch {
+    pool: BufferPool,
+    bufs: Vec<Buffer>,
+    // Buffer index, length, source and receive timestamp of each received
+    // datagram
+    received: Vec<(usize, usize, SocketAddr, Option<SystemTime>)>,
+}
+
+impl RecvBatch {
+    // Room for `len` datagrams, at least 1 and at most MAX_BATCH
+    pub fn new(len: usize) -> Self {
+        let len = len.clamp(1, MAX_BATCH);
+        Self::with_pool(len, BufferPool::new(len, MAX_DATAGRAM))
+    }
+
+    pub fn with_pool(len: usize, pool: BufferPool) -> Self {
+        let len = len.clamp(1, MAX_BATCH);
+        Self {
+            bufs: (0..len).map(|_| pool.get()).collect(),
+            pool,
+            received: Vec::with_capacity(len),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bufs.len()
+    }
+
+    // Wait for at least one datagram and take whatever else is already queued
+    // up to capacity, returning how many were received. SO_TIMESTAMPING
+    // timestamps are returned when enabled.
+    #[cfg(target_os = "linux")]
+    pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        use std::mem;
+        use std::os::fd::AsRawFd;
+
+        const CONTROL_LEN: usize = 16;
+        let len = self.bufs.len();
+        // SAFETY: all zeroes is a valid iovec, sockaddr_in and mmsghdr
+        let mut iovs: [libc::iovec; MAX_BATCH] = unsafe { mem::zeroed() };
+        let mut addrs: [libc::sockaddr_in; MAX_BATCH] = unsafe { mem::zeroed() };
+        let mut hdrs: [libc::mmsghdr; MAX_BATCH] = unsafe { mem::zeroed() };
+        let mut control = [[0_u64; CONTROL_LEN]; MAX_BATCH];
+        for i in 0..len {
+            iovs[i].iov_base = self.bufs[i].as_mut_ptr().cast();
+            iovs[i].iov_len = self.bufs[i].len();
+            let msg = &mut hdrs[i].msg_hdr;
+            msg.msg_name = (&mut addrs[i] as *mut libc::sockaddr_in).cast();
+            msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            msg.msg_iov = &mut iovs[i];
+            msg.msg_iovlen = 1;
+            msg.msg_control = control[i].as_mut_ptr().cast();
+            msg.msg_controllen = mem::size_of_val(&control[i]) as _;
+        }
+        // SAFETY: the headers point at buffers that outlive the call
+        let n = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                hdrs.as_mut_ptr(),
+                len as libc::c_uint,
+                libc::MSG_WAITFORONE,
+                std::ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.received.clear();
+        for (i, hdr) in hdrs.iter().enumerate().take(n as usize) {
+            let len = hdr.msg_len as usize;
+            if hdr.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                tracing::warn!(
+                    len,
+                    buf_len = self.bufs[i].len(),
+                    "dropped truncated datagram"
+                );
+                continue;
+            }
+            // SAFETY: the kernel filled in the first n headers
+            let ts = unsafe { rx_timestamp(&hdr.msg_hdr) };
+            self.received.push((i, len, socket_addr(&addrs[i]), ts));
+        }
+        Ok(self.received.len())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        let (n, src) = socket.recv_from(&mut self.bufs[0])?;
+        self.received.clear();
+        self.received.push((0, n, src, None));
+        Ok(1)
+    }
+
+    // Datagrams from the last recv with their source and receive timestamp
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr, Option<SystemTime>)> {
+        self.received
+            .iter()
+            .map(|(i, n, src, ts)| (&self.bufs[*i][..*n], *src, *ts))
+    }
+
+    // Hand over the buffers of the datagrams from the last recv with their
+    // length, replacing them with fresh ones from the pool
+    pub fn take(
+        &mut self,
+    ) -> impl Iterator<Item = (Buffer, usize, SocketAddr, Option<SystemTime>)> + '_ {
+        let (pool, bufs) = (&self.pool, &mut self.bufs);
+        self.received
+            .drain(..)
+            .map(move |(i, n, src, ts)| (mem::replace(&mut bufs[i], pool.get()), n, src, ts))
+    }
+}
+
+// UdpFeed as a task. No recording.
+#[cfg(feature = "tokio")]
+pub struct AsyncUdpFeed {
+    socket: tokio::net::UdpSocket,
+    protocol: Protocol,
+    channel: ChannelId,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncUdpFeed {
+    // Must be called within a tokio runtime
+    pub fn join(config: &FeedConfig) -> io::Result<Self> {
+        let socket = bind_multicast(config)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: tokio::net::UdpSocket::from_std(socket)?,
+            protocol: config.protocol.clone(),
+            channel: config.channel,
+            buf: vec![0; MAX_DATAGRAM],
+        })
+    }
+
+    // Returns Ok(None) for a datagram with nothing to sequence
+    pub async fn recv(&mut self) -> io::Result<Option<Block<Payload>>> {
+        let n = self.socket.recv(&mut self.buf).await?;
+        Ok(self
+            .protocol
+            .parse(&self.buf[..n])
+            .map(|(mut header, payload)| {
+                header.channel = self.protocol.channel(self.channel, &header);
+                Block::new(header, payload.into())
+            }))
+    }
+
+    // Receive until the arbiter hangs up
+    pub async fn run(
+        mut self,
+        sender: crate::async_arbiter::FeedSender<Block<Payload>>,
+    ) -> io::Result<()> {
+        loop {
+            if let Some(b) = self.recv().await? {
+                if sender.send(b).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use crate::pool::{Buffer, BufferPool};
+use crate::udp::{rx_timestamp, socket_addr};
+use io_uring::types::{BufRingEntry, Fd, RecvMsgOut, SubmitArgs, Timespec};
+use io_uring::{cqueue, opcode, IoUring, Probe};
+use std::alloc::{self, Layout};
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU16, Ordering::Release};
+use std::time::{Duration, SystemTime};
+
+// Buffer group the receive buffers are provided to
+const BUF_GROUP: u16 = 0;
+// Room for SCM_TIMESTAMPING's three timespecs
+const CONTROL_LEN: usize = 128;
+// Bytes of each buffer ahead of the datagram: io_uring_recvmsg_out's four
+// u32s, the source address and control messages
+pub const RECV_OVERHEAD: usize = 16 + mem::size_of::<libc::sockaddr_in>() + CONTROL_LEN;
+const PAGE: usize = 4096;
+
+// A datagram io_uring received straight into a pooled buffer
+pub struct Received {
+    pub buf: Buffer,
+    // Where the datagram is in buf
+    pub data: Range<usize>,
+    pub src: SocketAddr,
+    pub ts: Option<SystemTime>,
+}
+
+// Ring of buffers shared with the kernel (IORING_REGISTER_PBUF_RING) that
+// receives take buffers from without a syscall per buffer
+struct BufRing {
+    entries: *mut BufRingEntry,
+    layout: Layout,
+    mask: u16,
+    tail: u16,
+}
+
+impl BufRing {
+    fn new(len: u16) -> Self {
+        let size = mem::size_of::<BufRingEntry>() * len as usize;
+        let layout = Layout::from_size_align(size, PAGE).unwrap();
+        // SAFETY: the layout isn't zero sized
+        let entries = unsafe { alloc::alloc_zeroed(layout) }.cast::<BufRingEntry>();
+        if entries.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Self {
+            entries,
+            layout,
+            mask: len - 1,
+            tail: 0,
+        }
+    }
+
+    // Queue `buf` as `bid`, which the kernel sees after publish()
+    fn push(&mut self, bid: u16, buf: &mut Buffer) {
+        // SAFETY: masked indexes are within the ring
+        let entry = unsafe { &mut *self.entries.add((self.tail & self.mask) as usize) };
+        entry.set_addr(buf.as_mut_ptr() as u64);
+        entry.set_len(buf.len() as u32);
+        entry.set_bid(bid);
+        self.tail = self.tail.wrapping_add(1);
+    }
+
+    fn publish(&self) {
+        // SAFETY: the tail overlays the first entry's reserved field, which
+        // the kernel reads atomically
+        unsafe {
+            let tail = BufRingEntry::tail(self.entries) as *const AtomicU16;
+            (*tail).store(self.tail, Release);
+        }
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        // SAFETY: allocated with this layout in new
+        unsafe { alloc::dealloc(self.entries.cast(), self.layout) }
+    }
+}
+
+// Receives datagrams with one multishot recvmsg that the kernel keeps
+// completing into buffers from a BufferPool, with no per-datagram syscalls.
+// Each buffer also holds the source address and control messages ahead of the
+// datagram, so the pool's buffers must be RECV_OVERHEAD bytes longer than
+// datagrams.
+pub struct UringRecv {
+    // Dropped first so the kernel is done with the buffers and msg
+    ring: IoUring,
+    buf_ring: BufRing,
+    fd: i32,
+    pool: BufferPool,
+    // Buffers the kernel can receive into by buffer id
+    provided: Vec<Option<Buffer>>,
+    // Name and control lengths of the multishot recvmsg, which reads it for as
+    // long as the receive is armed
+    msg: Box<libc::msghdr>,
+    armed: bool,
+    // Whether any datagram has been received, before which EINVAL means the
+    // kernel can't do multishot recvmsg
+    received: bool,
+}
+
+impl UringRecv {
+    // Receive from `socket` into `n_buffers`, rounded up to a power of two,
+    // buffers from `pool`. Fails with Unsupported if the kernel lacks
+    // io_uring, recvmsg or buffer rings.
+    pub fn new(socket: &UdpSocket, pool: BufferPool, n_buffers: u16) -> io::Result<Self> {
+        let n_buffers = n_buffers
+            .max(1)
+            .checked_next_power_of_two()
+            .unwrap_or(1 << 15);
+        let ring = IoUring::new(64).map_err(unsupported)?;
+        let mut probe = Probe::new();
+        ring.submitter()
+            .register_probe(&mut probe)
+            .map_err(unsupported)?;
+        if !probe.is_supported(opcode::RecvMsg::CODE) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring lacks recvmsg",
+            ));
+        }
+        let buf_ring = BufRing::new(n_buffers);
+        // SAFETY: the ring's memory lives until after the io_uring is dropped
+        unsafe {
+            ring.submitter().register_buf_ring_with_flags(
+                buf_ring.entries as u64,
+                n_buffers,
+                BUF_GROUP,
+                0,
+            )
+        }
+        .map_err(unsupported)?;
+        // SAFETY: all zeroes is a valid msghdr
+        let mut msg: Box<libc::msghdr> = Box::new(unsafe { mem::zeroed() });
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_controllen = CONTROL_LEN as _;
+        let mut recv = Self {
+            ring,
+            buf_ring,
+            fd: socket.as_raw_fd(),
+            pool,
+            provided: (0..n_buffers).map(|_| None).collect(),
+            msg,
+            armed: false,
+            received: false,
+        };
+        for bid in 0..n_buffers {
+            recv.provide(bid);
+        }
+        recv.buf_ring.publish();
+        Ok(recv)
+    }
+
+    // Hand the kernel a fresh buffer as `bid`
+    fn provide(&mut self, bid: u16) {
+        let mut buf = self.pool.get();
+        self.buf_ring.push(bid, &mut buf);
+        self.provided[bid as usize] = Some(buf);
+    }
+
+    fn arm(&mut self) -> io::Result<()> {
+        let entry = opcode::RecvMsgMulti::new(Fd(self.fd), &*self.msg, BUF_GROUP).build();
+        // SAFETY: msg lives as long as the ring
+        while unsafe { self.ring.submission().push(&entry) }.is_err() {
+            self.ring.submit()?;
+        }
+        self.armed = true;
+        Ok(())
+    }
+
+    // Wait up to `timeout`, or indefinitely, for datagrams and append them to
+    // `out`
+    pub fn recv(&mut self, out: &mut Vec<Received>, timeout: Option<Duration>) -> io::Result<()> {
+        if !self.armed {
+            self.arm()?;
+        }
+        if self.ring.completion().is_empty() {
+            let res = match timeout {
+                Some(timeout) => {
+                    let ts = Timespec::new()
+                        .sec(timeout.as_secs())
+                        .nsec(timeout.subsec_nanos());
+                    let args = SubmitArgs::new().timespec(&ts);
+                    self.ring.submitter().submit_with_args(1, &args)
+                }
+                None => self.ring.submit_and_wait(1),
+            };
+            match res {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ETIME) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut res = Ok(());
+        let n = self.ring.completion().len();
+        for _ in 0..n {
+            let cqe = self.ring.completion().next().unwrap();
+            let (result, flags) = (cqe.result(), cqe.flags());
+            if !cqueue::more(flags) {
+                self.armed = false;
+            }
+            if result < 0 {
+                res = match -result {
+                    // Out of buffers until consumers drop some, re-armed on the
+                    // next call
+                    libc::ENOBUFS => continue,
+                    libc::EINVAL if !self.received => Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "kernel lacks multishot recvmsg",
+                    )),
+                    e => Err(io::Error::from_raw_os_error(e)),
+                };
+                break;
+            }
+            let bid = match cqueue::buffer_select(flags) {
+                Some(bid) => bid,
+                None => continue,
+            };
+            let buf = self.provided[bid as usize].take().unwrap();
+            self.provide(bid);
+            self.received = true;
+            out.extend(self.parse(buf, result as usize));
+        }
+        self.buf_ring.publish();
+        res
+    }
+
+    fn parse(&self, buf: Buffer, len: usize) -> Option<Received> {
+        let out = RecvMsgOut::parse(&buf[..len], &self.msg).ok()?;
+        if out.is_payload_truncated() {
+            tracing::warn!(buf_len = buf.len(), "dropped truncated datagram");
+            return None;
+        }
+        // SAFETY: the name field is a sockaddr_in the kernel filled in
+        let addr: libc::sockaddr_in =
+            unsafe { std::ptr::read_unaligned(out.name_data().as_ptr().cast()) };
+        let control = out.control_data();
+        // SAFETY: all zeroes is a valid msghdr
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_control = control.as_ptr() as *mut _;
+        msg.msg_controllen = control.len() as _;
+        // SAFETY: the kernel filled in the control messages
+        let ts = unsafe { rx_timestamp(&msg) };
+        let payload = out.payload_data();
+        let start = payload.as_ptr() as usize - buf.as_ptr() as usize;
+        let data = start..start + payload.len();
+        Some(Received {
+            buf,
+            data,
+            src: socket_addr(&addr),
+            ts,
+        })
+    }
+}
+
+fn unsupported(e: io::Error) -> io::Error {
+    let unsupported = matches!(
+        e.raw_os_error(),
+        Some(libc::ENOSYS) | Some(libc::EPERM) | Some(libc::EINVAL)
+    ) || e.kind() == io::ErrorKind::InvalidInput;
+    match unsupported {
+        true => io::Error::new(io::ErrorKind::Unsupported, e),
+        false => e,
+    }
+}
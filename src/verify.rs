@@ -0,0 +1,125 @@
+// Checks a journal is fit for research use: each channel's seqnums only go
+// up with no missing ranges, every record's crc matches and timestamps don't
+// go backwards
+use crate::journal::JournalReader;
+use crate::ChannelId;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::ops::Range;
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChannelReport {
+    pub records: u64,
+    pub first: u64,
+    // Seqnum after the highest seen
+    pub next: u64,
+    pub missing: Vec<Range<u64>>,
+    // (offset, seqnum) of records below `next` when read
+    pub backwards: Vec<(u64, u64)>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    pub records: u64,
+    pub channels: BTreeMap<ChannelId, ChannelReport>,
+    // Offsets of records whose crc didn't match, which are skipped
+    pub crc_mismatches: Vec<u64>,
+    // Offsets of records timestamped before the record before them
+    pub ts_regressions: Vec<u64>,
+    // Offset of what could not be read and why, if reading stopped before the
+    // end of the journal
+    pub unreadable: Option<(u64, String)>,
+}
+
+impl Report {
+    pub fn ok(&self) -> bool {
+        self.crc_mismatches.is_empty()
+            && self.ts_regressions.is_empty()
+            && self.unreadable.is_none()
+            && self
+                .channels
+                .values()
+                .all(|c| c.missing.is_empty() && c.backwards.is_empty())
+    }
+}
+
+pub fn verify<R: Read>(mut reader: JournalReader<R>) -> Report {
+    let mut report = Report::default();
+    let mut last_ts = SystemTime::UNIX_EPOCH;
+    loop {
+        let offset = reader.offset();
+        let record = match reader.next() {
+            Some(Ok(record)) => record,
+            // Skipped if the reader got past it
+            Some(Err(_)) if reader.offset() > offset => {
+                report.crc_mismatches.push(offset);
+                continue;
+            }
+            Some(Err(e)) => {
+                report.unreadable = Some((offset, e.to_string()));
+                break;
+            }
+            None => break,
+        };
+        report.records += 1;
+        if record.ts < last_ts {
+            report.ts_regressions.push(offset);
+        }
+        last_ts = last_ts.max(record.ts);
+
+        let end = record.seqnum.saturating_add(record.n_messages as u64);
+        let channel = report
+            .channels
+            .entry(record.channel)
+            .or_insert(ChannelReport {
+                first: record.seqnum,
+                next: record.seqnum,
+                ..Default::default()
+            });
+        channel.records += 1;
+        if record.seqnum > channel.next {
+            channel.missing.push(channel.next..record.seqnum);
+        } else if record.seqnum < channel.next {
+            channel.backwards.push((offset, record.seqnum));
+        }
+        channel.next = channel.next.max(end);
+    }
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "records {} channels {}",
+            self.records,
+            self.channels.len()
+        )?;
+        for (id, channel) in &self.channels {
+            let missing: u64 = channel.missing.iter().map(|r| r.end - r.start).sum();
+            writeln!(
+                f,
+                "channel {} seqnums {}..{} records {} missing {}",
+                id, channel.first, channel.next, channel.records, missing
+            )?;
+            for range in &channel.missing {
+                writeln!(f, "  gap {}..{}", range.start, range.end)?;
+            }
+            for (offset, seqnum) in &channel.backwards {
+                writeln!(f, "  seqnum {} went backwards at {}", seqnum, offset)?;
+            }
+        }
+        for offset in &self.crc_mismatches {
+            writeln!(f, "crc mismatch at {}", offset)?;
+        }
+        for offset in &self.ts_regressions {
+            writeln!(f, "timestamp went backwards at {}", offset)?;
+        }
+        if let Some((offset, error)) = &self.unreadable {
+            writeln!(f, "unreadable from {}: {}", offset, error)?;
+        }
+        write!(f, "{}", if self.ok() { "ok" } else { "FAILED" })
+    }
+}
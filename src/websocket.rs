@@ -0,0 +1,268 @@
+// Serves a sampled view of the sequenced stream over WebSocket for
+// dashboards, one JSON object per text frame:
+//   {"type":"block","channel":0,"seqnum":10,"n_messages":1,"len":64}
+//   {"type":"gap","channel":0,"from":11,"to":12,"reason":"timeout"}
+//   {"type":"reset","channel":0,"seqnum":1}
+//   {"type":"resync","channel":0,"seqnum":20,"blocks":3}
+//   {"type":"feed_down","feed":1,"silent_ms":500}
+//   {"type":"feed_up","feed":1}
+//   {"type":"end_of_session","channel":0,"seqnum":30}
+//   {"type":"sessions_diverged","channel":0,"feeds":[0,1]}
+//   {"type":"sessions_agreed","channel":0}
+// Blocks are sampled to at most one per channel per sample interval, everything
+// else is always sent. Each client has its own queue and thread, and a client
+// too slow to keep up loses frames rather than holding up the stream.
+use crate::metrics::FeedId;
+use crate::sink::Sink;
+use crate::{Block, ChannelId, GapReason, Payload, Session};
+use crossbeam_channel::{bounded, Sender, TrySendError};
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+use tungstenite::{Message, Utf8Bytes};
+
+// Frames queued per client before it loses them
+const CLIENT_QUEUE_LEN: usize = 1024;
+// Least ms between blocks of a channel sent by default
+pub const SAMPLE_MS: u64 = 100;
+
+#[derive(Debug, Default)]
+pub struct WebSocketMetrics {
+    pub clients: AtomicU64,
+    // Frames queued for clients, summed over them
+    pub frames: AtomicU64,
+    // Frames slow clients lost
+    pub dropped: AtomicU64,
+}
+
+pub struct WebSocketSink {
+    addr: SocketAddr,
+    clients: Arc<Mutex<Vec<Sender<Utf8Bytes>>>>,
+    sample_interval: Duration,
+    // When each channel's last block was sent
+    last_sent: HashMap<ChannelId, Instant>,
+    metrics: Arc<WebSocketMetrics>,
+}
+
+impl WebSocketSink {
+    // Accept dashboards on `addr` from a thread of its own
+    pub fn bind(addr: SocketAddr, sample_interval: Duration) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Arc::<Mutex<Vec<Sender<Utf8Bytes>>>>::default();
+        let metrics = Arc::<WebSocketMetrics>::default();
+        let (c, m) = (Arc::clone(&clients), Arc::clone(&metrics));
+        thread::Builder::new()
+            .name("websocket".to_string())
+            .spawn(move || accept(listener, c, m))?;
+        Ok(Self {
+            addr,
+            clients,
+            sample_interval,
+            last_sent: HashMap::new(),
+            metrics,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn metrics(&self) -> Arc<WebSocketMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn broadcast(&self, frame: String) {
+        let frame = Utf8Bytes::from(frame);
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(()) => {
+                self.metrics.frames.fetch_add(1, Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.metrics.dropped.fetch_add(1, Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.metrics.clients.fetch_sub(1, Relaxed);
+                false
+            }
+        });
+    }
+}
+
+fn accept(
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<Sender<Utf8Bytes>>>>,
+    metrics: Arc<WebSocketMetrics>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!(error = %e, "websocket accept failed");
+                continue;
+            }
+        };
+        let (sender, receiver) = bounded(CLIENT_QUEUE_LEN);
+        clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        metrics.clients.fetch_add(1, Relaxed);
+        let spawned = thread::Builder::new()
+            .name("websocket client".to_string())
+            .spawn(move || {
+                if let Err(e) = serve(stream, receiver) {
+                    debug!(error = %e, "websocket client gone");
+                }
+            });
+        if let Err(e) = spawned {
+            info!(error = %e, "failed to start websocket client thread");
+        }
+    }
+}
+
+// Send a client its frames until either end hangs up
+fn serve(
+    stream: TcpStream,
+    frames: crossbeam_channel::Receiver<Utf8Bytes>,
+) -> Result<(), tungstenite::Error> {
+    let peer = stream.peer_addr().ok();
+    let mut ws = tungstenite::accept(stream).map_err(|e| match e {
+        tungstenite::HandshakeError::Failure(e) => e,
+        tungstenite::HandshakeError::Interrupted(_) => tungstenite::Error::ConnectionClosed,
+    })?;
+    info!(?peer, "websocket client connected");
+    for frame in frames.iter() {
+        ws.send(Message::Text(frame))?;
+    }
+    ws.close(None)?;
+    ws.flush()
+}
+
+fn reason(reason: GapReason) -> &'static str {
+    match reason {
+        GapReason::Timeout => "timeout",
+        GapReason::SessionReset => "session_reset",
+        GapReason::Overflow => "overflow",
+        GapReason::Shutdown => "shutdown",
+        GapReason::Lagged => "lagged",
+    }
+}
+
+impl Sink for WebSocketSink {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        let now = Instant::now();
+        let channel = block.header.channel;
+        if let Some(last) = self.last_sent.get(&channel) {
+            if now.duration_since(*last) < self.sample_interval {
+                return Ok(());
+            }
+        }
+        self.last_sent.insert(channel, now);
+        self.broadcast(format!(
+            r#"{{"type":"block","channel":{},"seqnum":{},"n_messages":{},"len":{}}}"#,
+            channel,
+            block.header.seqnum,
+            block.header.n_messages,
+            block.payload.len()
+        ));
+        Ok(())
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        gap_reason: GapReason,
+    ) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"gap","channel":{},"from":{},"to":{},"reason":"{}"}}"#,
+            channel,
+            range.start,
+            range.end,
+            reason(gap_reason)
+        ));
+        Ok(())
+    }
+
+    fn on_reset(&mut self, channel: ChannelId, _session: Session, seqnum: u64) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"reset","channel":{},"seqnum":{}}}"#,
+            channel, seqnum
+        ));
+        Ok(())
+    }
+
+    fn on_resync(
+        &mut self,
+        channel: ChannelId,
+        seqnum: u64,
+        snapshot: &[Block<Payload>],
+    ) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"resync","channel":{},"seqnum":{},"blocks":{}}}"#,
+            channel,
+            seqnum,
+            snapshot.len()
+        ));
+        Ok(())
+    }
+
+    fn on_feed_down(&mut self, feed: FeedId, silent: Duration) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"feed_down","feed":{},"silent_ms":{}}}"#,
+            feed,
+            silent.as_millis()
+        ));
+        Ok(())
+    }
+
+    fn on_feed_up(&mut self, feed: FeedId) -> io::Result<()> {
+        self.broadcast(format!(r#"{{"type":"feed_up","feed":{}}}"#, feed));
+        Ok(())
+    }
+
+    fn on_end_of_session(
+        &mut self,
+        channel: ChannelId,
+        _session: Session,
+        seqnum: u64,
+    ) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"end_of_session","channel":{},"seqnum":{}}}"#,
+            channel, seqnum
+        ));
+        Ok(())
+    }
+
+    fn on_sessions_diverged(
+        &mut self,
+        channel: ChannelId,
+        sessions: &[(FeedId, Session)],
+    ) -> io::Result<()> {
+        let feeds: Vec<_> = sessions.iter().map(|(feed, _)| feed.to_string()).collect();
+        self.broadcast(format!(
+            r#"{{"type":"sessions_diverged","channel":{},"feeds":[{}]}}"#,
+            channel,
+            feeds.join(",")
+        ));
+        Ok(())
+    }
+
+    fn on_sessions_agreed(&mut self, channel: ChannelId, _session: Session) -> io::Result<()> {
+        self.broadcast(format!(
+            r#"{{"type":"sessions_agreed","channel":{}}}"#,
+            channel
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,708 @@
+// AF_XDP capture: an XDP program on the NIC steers the configured groups'
+// datagrams to a socket bound to one of its queues, which receives them into
+// UMEM, memory shared with the kernel (and with zero-copy drivers, the NIC).
+// Blocks' payloads point into the UMEM frames they arrived in, which go back
+// to the kernel when the last payload in them is dropped.
+use crate::metrics::FeedId;
+use crate::pcap::{parse_udp, FRAME_HEADER_LEN, LINKTYPE_ETHERNET};
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::udp::{bind_multicast, FeedConfig, MAX_BATCH};
+use crate::{Block, Payload};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::net::{SocketAddrV4, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Bytes of each UMEM frame, which holds one Ethernet frame
+pub const FRAME_SIZE: usize = 4096;
+// UMEM frames of a source by default
+pub const XDP_FRAMES: u32 = 4096;
+
+// bpf(2) commands, map, program and attach types
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_MAP_UPDATE_ELEM: libc::c_int = 2;
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_LINK_CREATE: libc::c_int = 28;
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+const XDP_FLAGS_SKB_MODE: u32 = 1 << 1;
+const XDP_FLAGS_DRV_MODE: u32 = 1 << 2;
+const XDP_PASS: i32 = 2;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const LOG_LEN: usize = 64 * 1024;
+
+// Where the XDP program runs
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XdpMode {
+    // Driver if the NIC supports it, else generic
+    Auto,
+    // In the driver, before an skb is allocated
+    Driver,
+    // Generic XDP on skbs, which works on any device but copies
+    Skb,
+}
+
+impl FromStr for XdpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(XdpMode::Auto),
+            "driver" => Ok(XdpMode::Driver),
+            "skb" => Ok(XdpMode::Skb),
+            _ => Err(format!("unknown xdp mode {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct XdpConfig {
+    // NIC the program is attached to
+    pub device: String,
+    pub queue: u32,
+    pub mode: XdpMode,
+    // UMEM frames, rounded up to a power of two
+    pub frames: u32,
+    // Feed ids are indexes. Each is joined on its interface so the NIC and
+    // switches keep forwarding the group, and steered by group and port, where
+    // 0.0.0.0 matches any group on that port.
+    pub feeds: Vec<FeedConfig>,
+}
+
+fn os_error(res: libc::c_int) -> io::Result<libc::c_int> {
+    match res {
+        -1 => Err(io::Error::last_os_error()),
+        res => Ok(res),
+    }
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &mut T) -> io::Result<OwnedFd> {
+    // SAFETY: attr is a bpf_attr prefix of its size
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    let fd = os_error(fd as libc::c_int)?;
+    // SAFETY: the kernel returned a new fd
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn setsockopt<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: value is valid for its size
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_XDP,
+            name,
+            (value as *const T).cast(),
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    os_error(res).map(drop)
+}
+
+fn mmap(len: usize, flags: libc::c_int, fd: RawFd, offset: u64) -> io::Result<*mut u8> {
+    // SAFETY: a new mapping, which aliases nothing
+    let p = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            flags,
+            fd,
+            offset as libc::off_t,
+        )
+    };
+    match p {
+        libc::MAP_FAILED => Err(io::Error::last_os_error()),
+        p => Ok(p.cast()),
+    }
+}
+
+// The frames the kernel receives into
+struct Umem {
+    base: *mut u8,
+    len: usize,
+}
+
+// SAFETY: frames are only written by the kernel while on the fill or rx ring,
+// which no Frame is
+unsafe impl Send for Umem {}
+unsafe impl Sync for Umem {}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        // SAFETY: mapped in bind
+        unsafe { libc::munmap(self.base.cast(), self.len) };
+    }
+}
+
+// A received Ethernet frame, handed back to the fill ring once dropped
+struct Frame {
+    umem: Arc<Umem>,
+    addr: u64,
+    len: usize,
+    recycle: Sender<u64>,
+}
+
+impl AsRef<[u8]> for Frame {
+    fn as_ref(&self) -> &[u8] {
+        // SAFETY: the kernel checked the descriptor is within the UMEM
+        unsafe { std::slice::from_raw_parts(self.umem.base.add(self.addr as usize), self.len) }
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        // Gone with the source otherwise
+        let _ = self.recycle.send(self.addr & !(FRAME_SIZE as u64 - 1));
+    }
+}
+
+// A single producer, single consumer ring mapped from the socket
+struct Ring {
+    map: *mut u8,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut u8,
+    mask: u32,
+    // Our end's index, published by storing it to producer or consumer
+    index: u32,
+}
+
+// SAFETY: only the thread owning the source touches our end
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn map<D>(
+        fd: RawFd,
+        offsets: &libc::xdp_ring_offset,
+        len: u32,
+        pgoff: u64,
+    ) -> io::Result<Self> {
+        let map_len = offsets.desc as usize + len as usize * mem::size_of::<D>();
+        let map = mmap(map_len, libc::MAP_SHARED | libc::MAP_POPULATE, fd, pgoff)?;
+        // SAFETY: the offsets are within the mapping
+        unsafe {
+            Ok(Self {
+                map,
+                map_len,
+                producer: map.add(offsets.producer as usize).cast(),
+                consumer: map.add(offsets.consumer as usize).cast(),
+                descs: map.add(offsets.desc as usize),
+                mask: len - 1,
+                index: 0,
+            })
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: an aligned u32 in the mapping
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: an aligned u32 in the mapping
+        unsafe { &*self.consumer }
+    }
+
+    fn desc<D>(&self, index: u32) -> *mut D {
+        // SAFETY: masked indexes are within the ring
+        unsafe { self.descs.cast::<D>().add((index & self.mask) as usize) }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // SAFETY: mapped in map
+        unsafe { libc::munmap(self.map.cast(), self.map_len) };
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn {
+        code,
+        regs: dst | src << 4,
+        off,
+        imm,
+    }
+}
+
+// Redirect IPv4 UDP frames to `feeds` to the socket in the xskmap at the
+// receive queue, passing everything else to the kernel's stack
+fn filter_program(feeds: &[SocketAddrV4], map: RawFd) -> Vec<Insn> {
+    const LDXW: u8 = 0x61;
+    const LDXH: u8 = 0x69;
+    const LDXB: u8 = 0x71;
+    const MOV: u8 = 0xbf;
+    const MOV_IMM: u8 = 0xb7;
+    const ADD_IMM: u8 = 0x07;
+    const JGT: u8 = 0x2d;
+    const JNE_IMM: u8 = 0x55;
+    const JNE32_IMM: u8 = 0x56;
+    const JA: u8 = 0x05;
+    const LD_IMM64: u8 = 0x18;
+    const CALL: u8 = 0x85;
+    const EXIT: u8 = 0x95;
+    // Loads are in host order
+    let be16 = |b: [u8; 2]| u16::from_ne_bytes(b) as i32;
+
+    let mut p = vec![
+        // r2 = data, r3 = data_end
+        insn(LDXW, 2, 1, 0, 0),
+        insn(LDXW, 3, 1, 4, 0),
+        insn(MOV, 4, 2, 0, 0),
+        insn(ADD_IMM, 4, 0, 0, FRAME_HEADER_LEN as i32),
+    ];
+    let mut to_pass = vec![p.len()];
+    p.push(insn(JGT, 4, 3, 0, 0));
+    // Ethertype IPv4, version 4 without options, UDP
+    for (code, off, imm) in [(LDXH, 12, be16([8, 0])), (LDXB, 14, 0x45), (LDXB, 23, 17)] {
+        p.push(insn(code, 5, 2, off, 0));
+        to_pass.push(p.len());
+        p.push(insn(JNE_IMM, 5, 0, 0, imm));
+    }
+    // r5 = destination address, r6 = destination port
+    p.push(insn(LDXW, 5, 2, 30, 0));
+    p.push(insn(LDXH, 6, 2, 36, 0));
+    let mut to_redirect = Vec::new();
+    for feed in feeds {
+        if !feed.ip().is_unspecified() {
+            let ip = u32::from_ne_bytes(feed.ip().octets()) as i32;
+            p.push(insn(JNE32_IMM, 5, 0, 2, ip));
+        }
+        p.push(insn(JNE_IMM, 6, 0, 1, be16(feed.port().to_be_bytes())));
+        to_redirect.push(p.len());
+        p.push(insn(JA, 0, 0, 0, 0));
+    }
+    to_pass.push(p.len());
+    p.push(insn(JA, 0, 0, 0, 0));
+
+    // bpf_redirect_map(map, rx_queue_index, XDP_PASS if no socket is there)
+    let redirect = p.len();
+    p.push(insn(LDXW, 2, 1, 16, 0));
+    p.push(insn(LD_IMM64, 1, BPF_PSEUDO_MAP_FD, 0, map));
+    p.push(insn(0, 0, 0, 0, 0));
+    p.push(insn(MOV_IMM, 3, 0, 0, XDP_PASS));
+    p.push(insn(CALL, 0, 0, 0, BPF_FUNC_REDIRECT_MAP));
+    p.push(insn(EXIT, 0, 0, 0, 0));
+    let pass = p.len();
+    p.push(insn(MOV_IMM, 0, 0, 0, XDP_PASS));
+    p.push(insn(EXIT, 0, 0, 0, 0));
+
+    for (jumps, target) in [(to_pass, pass), (to_redirect, redirect)] {
+        for i in jumps {
+            p[i].off = (target - i - 1) as i16;
+        }
+    }
+    p
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+fn load_program(insns: &[Insn]) -> io::Result<OwnedFd> {
+    let license = b"GPL\0";
+    let mut name = [0; 16];
+    name[..9].copy_from_slice(b"sequencer");
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        prog_name: name,
+        expected_attach_type: BPF_XDP,
+        ..Default::default()
+    };
+    match bpf(BPF_PROG_LOAD, &mut attr) {
+        Err(e) if e.raw_os_error() == Some(libc::EACCES) => {
+            // Load again for the verifier's reasons
+            let mut log = vec![0_u8; LOG_LEN];
+            attr.log_level = 1;
+            attr.log_size = LOG_LEN as u32;
+            attr.log_buf = log.as_mut_ptr() as u64;
+            let res = bpf(BPF_PROG_LOAD, &mut attr);
+            let len = log.iter().position(|&b| b == 0).unwrap_or(LOG_LEN);
+            res.map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", e, String::from_utf8_lossy(&log[..len])),
+                )
+            })
+        }
+        res => res,
+    }
+}
+
+// Receives the datagrams of several feeds from one NIC queue with AF_XDP
+pub struct AfXdpSource {
+    // Dropped first, which detaches the program
+    _link: OwnedFd,
+    _prog: OwnedFd,
+    _map: OwnedFd,
+    fill: Ring,
+    rx: Ring,
+    _completion: Ring,
+    socket: OwnedFd,
+    umem: Arc<Umem>,
+    // Frames dropped since they were last put back on the fill ring
+    recycle: (Sender<u64>, Receiver<u64>),
+    feeds: Vec<FeedConfig>,
+    // Hold the feeds' group memberships
+    _joined: Vec<UdpSocket>,
+    mode: XdpMode,
+    zero_copy: bool,
+    shutdown: Option<Shutdown>,
+}
+
+impl AfXdpSource {
+    // Join `config`'s feeds and attach the program steering them to a socket
+    // on its queue. Needs CAP_NET_ADMIN and CAP_BPF (or root).
+    pub fn bind(config: &XdpConfig) -> io::Result<Self> {
+        let device = CString::new(config.device.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: device is nul terminated
+        let ifindex = unsafe { libc::if_nametoindex(device.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no device {}", config.device),
+            ));
+        }
+        let joined = config
+            .feeds
+            .iter()
+            .map(bind_multicast)
+            .collect::<io::Result<Vec<_>>>()?;
+        let frames = config.frames.max(MAX_BATCH as u32).next_power_of_two();
+
+        let len = frames as usize * FRAME_SIZE;
+        let umem = Arc::new(Umem {
+            base: mmap(len, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)?,
+            len,
+        });
+        // SAFETY: creates a new socket
+        let fd = os_error(unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) })?;
+        // SAFETY: a new fd
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let reg = libc::xdp_umem_reg {
+            addr: umem.base as u64,
+            len: len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        setsockopt(fd, libc::XDP_UMEM_REG, &reg)?;
+        setsockopt(fd, libc::XDP_UMEM_FILL_RING, &frames)?;
+        setsockopt(fd, libc::XDP_UMEM_COMPLETION_RING, &frames)?;
+        setsockopt(fd, libc::XDP_RX_RING, &frames)?;
+        // SAFETY: all zeroes is valid
+        let mut offsets: libc::xdp_mmap_offsets = unsafe { mem::zeroed() };
+        let mut optlen = mem::size_of_val(&offsets) as libc::socklen_t;
+        // SAFETY: offsets is valid for optlen
+        os_error(unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_XDP,
+                libc::XDP_MMAP_OFFSETS,
+                (&mut offsets as *mut libc::xdp_mmap_offsets).cast(),
+                &mut optlen,
+            )
+        })?;
+        let mut fill = Ring::map::<u64>(fd, &offsets.fr, frames, libc::XDP_UMEM_PGOFF_FILL_RING)?;
+        let completion = Ring::map::<u64>(
+            fd,
+            &offsets.cr,
+            frames,
+            libc::XDP_UMEM_PGOFF_COMPLETION_RING,
+        )?;
+        let rx =
+            Ring::map::<libc::xdp_desc>(fd, &offsets.rx, frames, libc::XDP_PGOFF_RX_RING as u64)?;
+        for i in 0..frames {
+            // SAFETY: we own the fill ring's producer end
+            unsafe { *fill.desc::<u64>(i) = i as u64 * FRAME_SIZE as u64 };
+        }
+        fill.index = frames;
+        fill.producer().store(frames, Ordering::Release);
+
+        let zero_copy = match config.mode {
+            XdpMode::Skb => false,
+            _ => xsk_bind(fd, ifindex, config.queue, libc::XDP_ZEROCOPY).is_ok(),
+        };
+        if !zero_copy {
+            xsk_bind(fd, ifindex, config.queue, libc::XDP_COPY)?;
+        }
+
+        let mut attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_XSKMAP,
+            key_size: 4,
+            value_size: 4,
+            max_entries: config.queue + 1,
+        };
+        let map = bpf(BPF_MAP_CREATE, &mut attr)?;
+        let (key, value) = (config.queue, fd as u32);
+        let mut attr = MapElemAttr {
+            map_fd: map.as_raw_fd() as u32,
+            key: &key as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            ..Default::default()
+        };
+        bpf(BPF_MAP_UPDATE_ELEM, &mut attr)?;
+        let groups: Vec<_> = config
+            .feeds
+            .iter()
+            .map(|f| SocketAddrV4::new(f.group, f.port))
+            .collect();
+        let prog = load_program(&filter_program(&groups, map.as_raw_fd()))?;
+        let attach = |flags| {
+            let mut attr = LinkCreateAttr {
+                prog_fd: prog.as_raw_fd() as u32,
+                target_ifindex: ifindex,
+                attach_type: BPF_XDP,
+                flags,
+            };
+            bpf(BPF_LINK_CREATE, &mut attr)
+        };
+        let (link, mode) = match config.mode {
+            XdpMode::Driver => (attach(XDP_FLAGS_DRV_MODE)?, XdpMode::Driver),
+            XdpMode::Skb => (attach(XDP_FLAGS_SKB_MODE)?, XdpMode::Skb),
+            XdpMode::Auto => match attach(XDP_FLAGS_DRV_MODE) {
+                Ok(link) => (link, XdpMode::Driver),
+                Err(_) => (attach(XDP_FLAGS_SKB_MODE)?, XdpMode::Skb),
+            },
+        };
+
+        Ok(Self {
+            _link: link,
+            _prog: prog,
+            _map: map,
+            fill,
+            rx,
+            _completion: completion,
+            socket,
+            umem,
+            recycle: unbounded(),
+            feeds: config.feeds.clone(),
+            _joined: joined,
+            mode,
+            zero_copy,
+            shutdown: None,
+        })
+    }
+
+    // Driver or Skb, whichever the program was attached in
+    pub fn mode(&self) -> XdpMode {
+        self.mode
+    }
+
+    // Whether the NIC receives straight into the UMEM
+    pub fn zero_copy(&self) -> bool {
+        self.zero_copy
+    }
+
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // Put dropped frames back on the fill ring
+    fn refill(&mut self) {
+        let free = self.fill.mask + 1
+            - self
+                .fill
+                .index
+                .wrapping_sub(self.fill.consumer().load(Ordering::Acquire));
+        let mut n = 0;
+        for addr in self.recycle.1.try_iter().take(free as usize) {
+            // SAFETY: the slot was consumed by the kernel
+            unsafe { *self.fill.desc::<u64>(self.fill.index.wrapping_add(n)) = addr };
+            n += 1;
+        }
+        if n > 0 {
+            self.fill.index = self.fill.index.wrapping_add(n);
+            self.fill
+                .producer()
+                .store(self.fill.index, Ordering::Release);
+        }
+    }
+
+    // Wait up to `timeout`, or indefinitely, for frames and append the blocks
+    // in up to a batch of them to `out` with their feeds
+    pub fn recv(
+        &mut self,
+        out: &mut Vec<(FeedId, Block<Payload>)>,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        self.refill();
+        let available = || {
+            self.rx
+                .producer()
+                .load(Ordering::Acquire)
+                .wrapping_sub(self.rx.index)
+        };
+        let mut n = available();
+        if n == 0 {
+            let mut pollfd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+            // SAFETY: pollfd is valid
+            match os_error(unsafe { libc::poll(&mut pollfd, 1, ms) }) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+                res => res?,
+            };
+            n = available();
+        }
+        let n = n.min(MAX_BATCH as u32);
+        let received = Instant::now();
+        for i in 0..n {
+            // SAFETY: the kernel produced the descriptor
+            let desc = unsafe { ptr::read(self.rx.desc::<libc::xdp_desc>(self.rx.index + i)) };
+            let frame = Arc::new(Frame {
+                umem: Arc::clone(&self.umem),
+                addr: desc.addr,
+                len: desc.len as usize,
+                recycle: self.recycle.0.clone(),
+            });
+            if let Some((feed, mut block)) = self.block(frame) {
+                block.received = Some(received);
+                out.push((feed, block));
+            }
+        }
+        self.rx.index = self.rx.index.wrapping_add(n);
+        self.rx.consumer().store(self.rx.index, Ordering::Release);
+        Ok(())
+    }
+
+    // The block in `frame`, its payload left in the frame
+    fn block(&self, frame: Arc<Frame>) -> Option<(FeedId, Block<Payload>)> {
+        let data = (*frame).as_ref();
+        let (_, dst, datagram) = parse_udp(LINKTYPE_ETHERNET, data)?;
+        let feed = self.feeds.iter().position(|f| {
+            f.port == dst.port() && (f.group.is_unspecified() || f.group == *dst.ip())
+        })?;
+        let config = &self.feeds[feed];
+        let (mut header, payload) = config.protocol.parse(datagram)?;
+        let start = (payload.as_ptr() as usize).checked_sub(data.as_ptr() as usize);
+        let payload = match start {
+            Some(start) if start + payload.len() <= data.len() => {
+                let range = start..start + payload.len();
+                Payload::shared(frame.clone(), range)
+            }
+            // Not a slice of the frame
+            _ => payload.into(),
+        };
+        header.channel = config.protocol.channel(config.channel, &header);
+        Some((feed, Block::new(header, payload)))
+    }
+
+    // Send each feed's blocks to senders[feed] a batch at a time until the
+    // arbiter hangs up or shutdown is requested
+    pub fn run(mut self, senders: &[Sender<Vec<Block<Payload>>>]) -> io::Result<()> {
+        let timeout = self.shutdown.as_ref().map(|_| SHUTDOWN_POLL);
+        let mut received = Vec::new();
+        let mut batches: Vec<Vec<Block<Payload>>> = senders.iter().map(|_| Vec::new()).collect();
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.requested()) {
+                return Ok(());
+            }
+            self.recv(&mut received, timeout)?;
+            for (feed, block) in received.drain(..) {
+                if let Some(batch) = batches.get_mut(feed) {
+                    batch.push(block);
+                }
+            }
+            for (batch, sender) in batches.iter_mut().zip(senders) {
+                if !batch.is_empty() && sender.send(mem::take(batch)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn xsk_bind(fd: RawFd, ifindex: u32, queue: u32, flags: u16) -> io::Result<()> {
+    let addr = libc::sockaddr_xdp {
+        sxdp_family: libc::AF_XDP as u16,
+        sxdp_flags: flags,
+        sxdp_ifindex: ifindex,
+        sxdp_queue_id: queue,
+        sxdp_shared_umem_fd: 0,
+    };
+    // SAFETY: addr is a sockaddr_xdp
+    let res = unsafe {
+        libc::bind(
+            fd,
+            (&addr as *const libc::sockaddr_xdp).cast(),
+            mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    };
+    os_error(res).map(drop)
+}
@@ -0,0 +1,163 @@
+use sequencer::acks::{AckServer, Acks, ACK, LOGIN, RESEND, WATERMARK};
+use sequencer::journal;
+use sequencer::protocol::moldudp64;
+use sequencer::publisher::MulticastPublisher;
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, Payload};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+fn consumers() -> Vec<String> {
+    vec!["risk".to_string(), "clearing".to_string()]
+}
+
+// Packets of 10 bytes with 2 messages each, seqnums 1, 3, 5...
+fn retain(acks: &Acks, n: u64) {
+    for i in 0..n {
+        acks.retain(1 + 2 * i, 2, &[i as u8; 10]).unwrap();
+    }
+}
+
+fn firsts(packets: Vec<Vec<u8>>) -> Vec<u8> {
+    packets.iter().map(|p| p[0]).collect()
+}
+
+#[test]
+fn retains_until_every_consumer_acks() {
+    let acks = Acks::new(&consumers(), 1_000, None).unwrap();
+    retain(&acks, 4);
+    assert_eq!(acks.retained(), (4, 0));
+    // Packets are retained until the slowest consumer is past them
+    assert!(acks.ack("risk", 8).unwrap());
+    assert_eq!(acks.retained(), (4, 0));
+    assert!(acks.ack("clearing", 4).unwrap());
+    assert_eq!(acks.retained(), (3, 0));
+    assert_eq!(acks.watermark("clearing"), Some(4));
+    // Watermarks don't go back
+    acks.ack("clearing", 2).unwrap();
+    assert_eq!(acks.watermark("clearing"), Some(4));
+    assert!(!acks.ack("nobody", 100).unwrap());
+
+    // The packet holding seqnum 4 is the one from 3
+    assert_eq!(firsts(acks.packets_from(4).unwrap()), [1, 2, 3]);
+    assert_eq!(firsts(acks.packets_from(7).unwrap()), [3]);
+    acks.ack("clearing", 9).unwrap();
+    assert_eq!(acks.retained(), (1, 0));
+    acks.ack("risk", 9).unwrap();
+    assert_eq!(acks.retained(), (0, 0));
+}
+
+#[test]
+fn retains_past_the_memory_limit_in_a_file() {
+    let dir = std::env::temp_dir();
+    // Room for 2 packets in memory
+    let acks = Acks::new(&consumers(), 25, Some(&dir)).unwrap();
+    retain(&acks, 5);
+    assert_eq!(acks.retained(), (2, 3));
+    assert_eq!(firsts(acks.packets_from(0).unwrap()), [0, 1, 2, 3, 4]);
+    assert_eq!(acks.packets_from(0).unwrap()[1], [1; 10]);
+    for consumer in consumers() {
+        acks.ack(&consumer, 5).unwrap();
+    }
+    assert_eq!(acks.retained(), (2, 1));
+    assert_eq!(firsts(acks.packets_from(0).unwrap()), [2, 3, 4]);
+
+    // Spilled packets all acked, the file starts over
+    for consumer in consumers() {
+        acks.ack(&consumer, 7).unwrap();
+    }
+    retain(&acks, 1);
+    assert_eq!(acks.retained(), (2, 1));
+    assert_eq!(firsts(acks.packets_from(0).unwrap()), [3, 4, 0]);
+}
+
+fn login(stream: &mut TcpStream, name: &str) -> u64 {
+    let mut buf = vec![LOGIN, name.len() as u8];
+    buf.extend_from_slice(name.as_bytes());
+    stream.write_all(&buf).unwrap();
+    let mut reply = [0; 9];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[0], WATERMARK);
+    u64::from_be_bytes(reply[1..].try_into().unwrap())
+}
+
+fn send(stream: &mut TcpStream, kind: u8, seqnum: u64) {
+    let mut buf = vec![kind];
+    buf.extend_from_slice(&seqnum.to_be_bytes());
+    stream.write_all(&buf).unwrap();
+}
+
+// Seqnums of the packets resent from `start`
+fn resend(stream: &mut TcpStream, start: u64) -> Vec<u64> {
+    send(stream, RESEND, start);
+    let mut seqnums = Vec::new();
+    loop {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        let len = u16::from_be_bytes(len) as usize;
+        if len == 0 {
+            return seqnums;
+        }
+        let mut packet = vec![0; len];
+        stream.read_exact(&mut packet).unwrap();
+        seqnums.push(moldudp64::parse(&packet).unwrap().header.seqnum);
+    }
+}
+
+#[test]
+fn consumers_ack_and_get_what_they_missed() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let dst = match receiver.local_addr().unwrap() {
+        SocketAddr::V4(addr) => addr,
+        _ => unreachable!(),
+    };
+    let acks = Acks::new(&["risk".to_string()], 1 << 20, None).unwrap();
+    let server = AckServer::bind("127.0.0.1:0".parse().unwrap(), acks.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn().unwrap();
+    let mut publisher = MulticastPublisher::bind(dst, *dst.ip(), journal::session("OUT")).unwrap();
+    publisher.set_acks(acks.clone());
+    for seqnum in 1..=4 {
+        let header = BlockHeader {
+            seqnum,
+            n_messages: 1,
+            ..Default::default()
+        };
+        let block: Block<Payload> = Block::new(header, b"x".as_slice().into());
+        publisher.on_block(&block).unwrap();
+    }
+    // Heartbeats aren't retained
+    publisher.heartbeat().unwrap();
+    assert_eq!(acks.retained(), (4, 0));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(login(&mut stream, "risk"), 0);
+    assert_eq!(resend(&mut stream, 2), [2, 3, 4]);
+    send(&mut stream, ACK, 3);
+    // Processed in order, so the ack is in before the resend is answered
+    assert_eq!(resend(&mut stream, 1), [3, 4]);
+    assert_eq!(acks.retained(), (2, 0));
+
+    // Logging in again picks up where it left off
+    drop(stream);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(login(&mut stream, "risk"), 3);
+
+    // Unknown consumers are hung up on
+    let mut stranger = TcpStream::connect(addr).unwrap();
+    let mut buf = vec![LOGIN, 4];
+    buf.extend_from_slice(b"spam");
+    stranger.write_all(&buf).unwrap();
+    let mut reply = Vec::new();
+    stranger
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(stranger.read_to_end(&mut reply).unwrap(), 0);
+}
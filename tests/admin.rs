@@ -0,0 +1,159 @@
+use sequencer::admin::{AdminAddr, AdminServer, Command};
+use sequencer::arbiter::Arbiter;
+use sequencer::journal::{self, JournalReader, JournalWriter, Rotation};
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn parses_commands() {
+    let parse = |s: &str| s.parse::<Command>();
+    assert_eq!(parse("stats"), Ok(Command::Stats));
+    assert_eq!(parse("lines"), Ok(Command::Lines));
+    assert_eq!(parse("reset-channel 3"), Ok(Command::ResetChannel(3)));
+    assert_eq!(
+        parse("set-timeout 250"),
+        Ok(Command::SetTimeout(Duration::from_millis(250)))
+    );
+    assert_eq!(
+        parse("set-feed-timeout 0"),
+        Ok(Command::SetFeedTimeout(None))
+    );
+    assert_eq!(parse("pause-feed B"), Ok(Command::PauseFeed(1)));
+    assert_eq!(parse("resume-feed 0"), Ok(Command::ResumeFeed(0)));
+    assert_eq!(parse("remove-feed c"), Ok(Command::RemoveFeed(2)));
+    assert_eq!(parse("rotate-journal"), Ok(Command::RotateJournal));
+    assert_eq!(parse("end-session"), Ok(Command::EndSession));
+    assert_eq!(
+        parse("gap-report /tmp/gaps.csv"),
+        Ok(Command::GapReport("/tmp/gaps.csv".into()))
+    );
+    assert!(parse("reset-channel").is_err());
+    assert!(parse("set-timeout soon").is_err());
+    assert!(parse("stats now").is_err());
+    assert!(parse("restart").is_err());
+}
+
+#[test]
+fn reset_channel_adopts_next_seqnum() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(10));
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    assert!(sequencer.reset_channel(0));
+    assert!(!sequencer.reset_channel(1));
+    sequencer.push(block(100));
+    sequencer.push(block(101));
+    assert_eq!(sequencer.seqnum(0), 102);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 6);
+    assert_eq!(
+        events[1],
+        SequencedEvent::Gap {
+            channel: 0,
+            from: 1,
+            to: 2,
+            reason: GapReason::SessionReset,
+        }
+    );
+    assert!(matches!(
+        events[3],
+        SequencedEvent::SessionReset { seqnum: 100, .. }
+    ));
+}
+
+#[test]
+fn paused_feed_is_discarded() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(10));
+    sequencer.add_feed();
+    assert!(sequencer.pause_feed(0));
+    assert!(!sequencer.pause_feed(1));
+    sequencer.push_from(0, block(0));
+    assert!(sequencer.resume_feed(0));
+    sequencer.push_from(0, block(0));
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events, [SequencedEvent::Block(block(0))]);
+}
+
+#[test]
+fn serves_commands_to_the_arbiter() {
+    let (sequencer, _receiver) = Sequencer::new(Duration::from_secs(10));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(1));
+    let feed = arbiter.add_feed();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let server = AdminServer::bind(&AdminAddr::Tcp(addr), arbiter.control()).unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn().unwrap();
+    let arbiter = arbiter.spawn().unwrap();
+    feed.send(block(0)).unwrap();
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut command = |line: &str| {
+        writeln!(&stream, "{}", line).unwrap();
+        replies.next().unwrap().unwrap()
+    };
+    // Wait for the block to be sequenced
+    while !command("stats").ends_with("channel 0 seqnum 1") {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(command("lines").starts_with("ok feed 0 won 100.00%"));
+    assert_eq!(command("set-timeout 5"), "ok");
+    assert_eq!(command("pause-feed A"), "ok");
+    assert_eq!(command("pause-feed C"), "error no feed 2");
+    assert_eq!(command("reset-channel 0"), "ok");
+    assert_eq!(command("reset-channel 9"), "error no channel 9");
+    assert_eq!(command("end-session"), "ok ended 1 channels");
+    assert_eq!(command("rotate-journal"), "error no journal to rotate");
+    assert_eq!(command("bogus"), "error unknown command bogus");
+    assert_eq!(command("remove-feed B"), "error no feed 1");
+    // The arbiter stops with its only feed gone
+    assert_eq!(command("remove-feed A"), "ok");
+
+    drop(feed);
+    let sequencer = arbiter.join().unwrap();
+    assert_eq!(sequencer.timeout(), Duration::from_millis(5));
+}
+
+#[test]
+fn rotation_moves_the_journal_aside() {
+    let path = std::env::temp_dir().join(format!("rotate-{}.journal", std::process::id()));
+    let rotated = path.with_extension("journal.1");
+    let _ = std::fs::remove_file(&rotated);
+    let session = journal::session("SESSION1");
+    let rotation = Rotation::default();
+    let ts = UNIX_EPOCH + Duration::from_secs(1);
+    {
+        let mut writer = JournalWriter::create(&path, &session, None).unwrap();
+        writer.set_rotation(rotation.clone());
+        writer.append(&block(0).header, ts, b"a").unwrap();
+        rotation.request();
+        writer.append(&block(1).header, ts, b"b").unwrap();
+    }
+
+    let seqnums = |path| {
+        JournalReader::open(path)
+            .unwrap()
+            .map(|r| r.unwrap().seqnum)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(seqnums(&rotated), [0]);
+    assert_eq!(seqnums(&path), [1]);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rotated).unwrap();
+}
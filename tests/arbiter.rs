@@ -0,0 +1,130 @@
+use sequencer::arbiter::Arbiter;
+use sequencer::arbitration::PreferPrimary;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn busy_poll_sequences_and_times_out() {
+    let (sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(10));
+    arbiter.set_busy_poll(true);
+    let feeds: Vec<_> = (0..2)
+        .map(|_| {
+            let s = arbiter.add_feed();
+            thread::spawn(move || {
+                for seqnum in [0, 1, 2, 4] {
+                    s.send(block(seqnum)).unwrap();
+                }
+                // Keep the queue open until 3 times out
+                thread::sleep(Duration::from_millis(50));
+            })
+        })
+        .collect();
+    let sequencer = arbiter.run();
+    for f in feeds {
+        f.join().unwrap();
+    }
+    assert_eq!(sequencer.seqnum(0), 5);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 5);
+    assert_eq!(
+        events[3],
+        SequencedEvent::Gap {
+            channel: 0,
+            from: 3,
+            to: 4,
+            reason: GapReason::Timeout,
+        }
+    );
+}
+
+#[test]
+fn spawned_arbiter_is_the_only_sender_of_events() {
+    // Too long to time out however the feed threads are scheduled
+    let (sequencer, receiver) = Sequencer::new(Duration::from_secs(10));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(10));
+    let feeds: Vec<_> = (0..2)
+        .map(|i| {
+            let s = arbiter.add_feed();
+            thread::spawn(move || {
+                // Each feed has every other block
+                for seqnum in (i..100).step_by(2) {
+                    s.send(block(seqnum)).unwrap();
+                }
+            })
+        })
+        .collect();
+    let arbiter = arbiter.spawn().unwrap();
+    assert_eq!(arbiter.thread().name(), Some("arbiter"));
+    for f in feeds {
+        f.join().unwrap();
+    }
+    let sequencer = arbiter.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 100);
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| match e {
+            SequencedEvent::Block(b) => Some(b.header.seqnum),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(seqnums, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn feeds_are_added_and_removed_while_running() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(10));
+    sequencer.set_arbitration(Box::new(PreferPrimary::new(0)));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(10));
+    let feeds = arbiter.feed_set();
+    let a = arbiter.add_feed();
+    let arbiter = arbiter.spawn().unwrap();
+    for seqnum in 0..10 {
+        a.send(block(seqnum)).unwrap();
+    }
+    let mut events: Vec<_> = receiver.iter().take(10).collect();
+
+    let (id, b) = feeds.add_feed();
+    assert_eq!(id, 1);
+    feeds.remove_feed(0);
+    // Sends fail once the arbiter has let go of the feed
+    while a.send(block(9)).is_ok() {
+        thread::sleep(Duration::from_millis(1));
+    }
+    // Failed over to the only feed left
+    for seqnum in 10..20 {
+        b.send(block(seqnum)).unwrap();
+    }
+    drop(b);
+    // Nothing left that could add a feed
+    drop(feeds);
+    let sequencer = arbiter.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 20);
+    assert_eq!(sequencer.stats().failovers, 1);
+    drop(sequencer);
+
+    events.extend(receiver.iter());
+    let seqnums: Vec<_> = events
+        .into_iter()
+        .filter_map(|e| match e {
+            SequencedEvent::Block(b) => Some(b.header.seqnum),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(seqnums, (0..20).collect::<Vec<_>>());
+}
@@ -0,0 +1,81 @@
+use sequencer::arbitration::{LineQuality, PreferPrimary};
+use sequencer::{Block, BlockHeader, Sequencer};
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64, feed: u8) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![feed])
+}
+
+#[test]
+fn prefer_primary_fills_gaps_from_standby() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_arbitration(Box::new(PreferPrimary::new(0)));
+    // B is ahead of A, A loses 2
+    for (feed, seqnum) in [(1, 0), (1, 1), (0, 0), (1, 2), (0, 1), (0, 3), (1, 3)] {
+        sequencer.push_from(feed, block(seqnum, feed as u8));
+    }
+    let stats = sequencer.stats();
+    assert_eq!(sequencer.seqnum(0), 4);
+    drop(sequencer);
+
+    let blocks: Vec<_> = receiver.iter().filter_map(|e| e.into_block()).collect();
+    assert_eq!(
+        blocks,
+        vec![block(0, 0), block(1, 0), block(2, 1), block(3, 0)]
+    );
+    assert_eq!(stats.arbitration, "prefer-primary");
+    assert!(stats.feeds[0].active);
+    assert!(!stats.feeds[1].active);
+    assert_eq!(stats.feeds[0].gaps, 1);
+    assert_eq!(stats.feeds[1].standby, 3);
+}
+
+#[test]
+fn prefer_primary_fails_over_when_primary_is_down() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_arbitration(Box::new(PreferPrimary::new(0)));
+    sequencer.set_feed_timeout(Some(Duration::from_millis(10)));
+    sequencer.push_from(0, block(0, 0));
+    thread::sleep(Duration::from_millis(20));
+    sequencer.push_from(1, block(0, 1));
+    sequencer.push_from(1, block(1, 1));
+    assert_eq!(sequencer.seqnum(0), 1);
+
+    sequencer.poll_liveness();
+    sequencer.push_from(1, block(2, 1));
+    assert_eq!(sequencer.seqnum(0), 3);
+    let stats = sequencer.stats();
+    assert_eq!(stats.failovers, 1);
+    assert!(stats.feeds[1].active);
+}
+
+#[test]
+fn line_quality_switches_to_cleaner_feed() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_arbitration(Box::<LineQuality>::default());
+    for seqnum in 0..200 {
+        if seqnum % 5 != 4 {
+            sequencer.push_from(0, block(seqnum, 0));
+        }
+        sequencer.push_from(1, block(seqnum, 1));
+    }
+    let stats = sequencer.stats();
+    assert_eq!(sequencer.seqnum(0), 200);
+    drop(sequencer);
+
+    let mut seen = HashSet::new();
+    for b in receiver.iter().filter_map(|e| e.into_block()) {
+        assert!(seen.insert(b.header.seqnum));
+    }
+    assert_eq!(seen.len(), 200);
+    assert_eq!(stats.failovers, 1);
+    assert!(stats.feeds[1].active);
+}
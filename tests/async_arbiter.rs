@@ -0,0 +1,50 @@
+#![cfg(feature = "tokio")]
+use sequencer::async_arbiter::AsyncArbiter;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[tokio::test]
+async fn sequences_feed_tasks() {
+    let (sequencer, events) = Sequencer::new(Duration::from_millis(10));
+    let (mut arbiter, mut output) = AsyncArbiter::new(sequencer, events, Duration::from_millis(5));
+    // Both feeds lose 3, feed 1 lags behind
+    for (feed, delay) in [(0, 0), (1, 2)] {
+        let sender = arbiter.add_feed();
+        tokio::spawn(async move {
+            for seqnum in [0, 1, 2, 4, 5] {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                sender.send(block(seqnum)).await.unwrap();
+            }
+            // Keep the queue open until 3 times out
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(sender.feed(), feed);
+        });
+    }
+    let sequencer = tokio::spawn(arbiter.run()).await.unwrap();
+    assert_eq!(sequencer.seqnum(0), 6);
+    drop(sequencer);
+
+    let mut received = Vec::new();
+    while let Some(e) = output.recv().await {
+        received.push(e);
+    }
+    let mut expected: Vec<_> = (0..3).map(|s| SequencedEvent::Block(block(s))).collect();
+    expected.push(SequencedEvent::Gap {
+        channel: 0,
+        from: 3,
+        to: 4,
+        reason: GapReason::Timeout,
+    });
+    expected.extend((4..6).map(|s| SequencedEvent::Block(block(s))));
+    assert_eq!(received, expected);
+}
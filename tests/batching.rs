@@ -0,0 +1,141 @@
+use crossbeam_channel::unbounded;
+use sequencer::journal::{self, JournalReader, JournalWriter};
+use sequencer::sink::{self, Batching, Sink, TextSink};
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, Payload, SequencedEvent};
+use std::io;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn block(seqnum: u64) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8; 2].into())
+}
+
+// The seqnums of each batch, and the range of each gap
+#[derive(Default)]
+struct Batches(Vec<Vec<u64>>);
+
+impl Sink for Batches {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        self.0.push(vec![block.header.seqnum]);
+        Ok(())
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block<Payload>]) -> io::Result<()> {
+        self.0
+            .push(blocks.iter().map(|b| b.header.seqnum).collect());
+        Ok(())
+    }
+
+    fn on_gap(&mut self, _channel: ChannelId, range: Range<u64>, _: GapReason) -> io::Result<()> {
+        self.0.push(vec![range.start, range.end]);
+        Ok(())
+    }
+}
+
+#[test]
+fn batches_consecutive_blocks() {
+    let (sender, receiver) = unbounded();
+    for seqnum in 0..3 {
+        sender.send(SequencedEvent::Block(block(seqnum))).unwrap();
+    }
+    sender
+        .send(SequencedEvent::Gap {
+            channel: 0,
+            from: 3,
+            to: 4,
+            reason: GapReason::Timeout,
+        })
+        .unwrap();
+    for seqnum in 4..9 {
+        sender.send(SequencedEvent::Block(block(seqnum))).unwrap();
+    }
+    drop(sender);
+    let batching = Batching {
+        max_blocks: 3,
+        max_latency: Duration::ZERO,
+    };
+    let mut sink = Batches::default();
+    let mut delivered = Vec::new();
+    let n_blocks = sink::run_batched(receiver, &mut sink, batching, |event| {
+        delivered.push(event.clone().into_block().map(|b| b.header.seqnum))
+    })
+    .unwrap();
+    assert_eq!(n_blocks, 8);
+    // A gap ends a batch early
+    assert_eq!(
+        sink.0,
+        [vec![0, 1, 2], vec![3, 4], vec![4, 5, 6], vec![7, 8]]
+    );
+    let mut expected: Vec<_> = (0..9).map(Some).collect();
+    expected[3] = None;
+    assert_eq!(delivered, expected);
+}
+
+#[test]
+fn batches_wait_up_to_their_latency() {
+    let (sender, receiver) = unbounded();
+    sender.send(SequencedEvent::Block(block(0))).unwrap();
+    let late = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        for seqnum in 1..3 {
+            sender.send(SequencedEvent::Block(block(seqnum))).unwrap();
+        }
+    });
+    let batching = Batching {
+        max_blocks: 2,
+        max_latency: Duration::from_secs(5),
+    };
+    let mut sink = Batches::default();
+    sink::run_batched(receiver, &mut sink, batching, |_| {}).unwrap();
+    late.join().unwrap();
+    // The last is delivered once the stream ends, not after the latency
+    assert_eq!(sink.0, [vec![0, 1], vec![2]]);
+
+    // Unbatched by default
+    let (sender, receiver) = unbounded();
+    for seqnum in 0..2 {
+        sender.send(SequencedEvent::Block(block(seqnum))).unwrap();
+    }
+    drop(sender);
+    let mut sink = Batches::default();
+    assert_eq!(sink::run(receiver, &mut sink).unwrap(), 2);
+    assert_eq!(sink.0, [vec![0], vec![1]]);
+}
+
+#[test]
+fn file_sinks_write_batches_like_single_blocks() {
+    let dir = std::env::temp_dir();
+    let blocks: Vec<_> = (0..4).map(block).collect();
+    let text = dir.join(format!("batching-{}.txt", std::process::id()));
+    let mut sink = TextSink::create(&text).unwrap();
+    sink.on_block(&blocks[0]).unwrap();
+    sink.on_blocks(&blocks[1..]).unwrap();
+    sink.flush().unwrap();
+    let lines = std::fs::read_to_string(&text).unwrap();
+    std::fs::remove_file(&text).unwrap();
+    assert_eq!(lines, "0 2\n1 2\n2 2\n3 2\n");
+
+    let path = dir.join(format!("batching-{}.journal", std::process::id()));
+    let ts = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut writer = JournalWriter::create(&path, &journal::session("S"), None).unwrap();
+    writer.append_block(&blocks[0], ts).unwrap();
+    writer.append_blocks(&blocks[1..], ts).unwrap();
+    writer.sync().unwrap();
+    let records: Vec<_> = JournalReader::open(&path)
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(records.len(), 4);
+    for (r, b) in records.iter().zip(&blocks) {
+        assert_eq!((r.seqnum, r.ts), (b.header.seqnum, ts));
+        assert_eq!(r.payload, b.payload.to_vec());
+    }
+}
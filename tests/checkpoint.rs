@@ -0,0 +1,153 @@
+use sequencer::checkpoint::Checkpoint;
+use sequencer::gapfill::GapFiller;
+use sequencer::journal::{self, JournalReader, JournalWriter};
+use sequencer::{Block, BlockHeader, ChannelId, Sequencer};
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("checkpoint-{}-{}", name, std::process::id()))
+}
+
+fn header(channel: ChannelId, seqnum: u64, n_messages: u16) -> BlockHeader {
+    BlockHeader {
+        channel,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn round_trip() {
+    let checkpoint = Checkpoint {
+        journal_offset: 1234,
+        channels: [(0, 10), (3, 7)].into_iter().collect(),
+    };
+    let path = temp("round-trip");
+    assert_eq!(Checkpoint::load(&path).unwrap(), None);
+    checkpoint.save(&path).unwrap();
+    let loaded = Checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, Some(checkpoint.clone()));
+
+    let mut buf = checkpoint.encode();
+    buf[12] ^= 1;
+    assert!(Checkpoint::decode(&buf).is_err());
+    assert!(Checkpoint::decode(&buf[..10]).is_err());
+}
+
+#[test]
+fn journal_resumes_from_its_checkpoint() {
+    let (path, checkpoint_path) = (temp("journal"), temp("journal.checkpoint"));
+    let session = journal::session("DAY1");
+    let ts = SystemTime::now();
+    let checkpoint = {
+        let mut writer = JournalWriter::create(&path, &session, None).unwrap();
+        writer.set_checkpoint(&checkpoint_path, Duration::from_secs(60));
+        writer.append(&header(0, 1, 1), ts, b"a").unwrap();
+        writer.append(&header(1, 5, 3), ts, b"b").unwrap();
+        writer.sync().unwrap();
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap().unwrap();
+        assert_eq!(checkpoint, writer.checkpoint());
+        // Not covered by the checkpoint
+        writer.append(&header(0, 2, 1), ts, b"c").unwrap();
+        checkpoint
+    };
+    assert_eq!(checkpoint.channels, [(0, 2), (1, 8)].into_iter().collect());
+
+    {
+        let mut writer = JournalWriter::resume(&path, &checkpoint, None).unwrap();
+        writer.set_checkpoint(&checkpoint_path, Duration::from_secs(60));
+        writer.append(&header(0, 2, 1), ts, b"d").unwrap();
+        writer.sync().unwrap();
+    }
+    let resumed = Checkpoint::load(&checkpoint_path).unwrap().unwrap();
+    let reader = JournalReader::open(&path).unwrap();
+    assert_eq!(reader.session(), &session);
+    let records: Vec<_> = reader.map(|r| r.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&checkpoint_path).unwrap();
+
+    assert_eq!(resumed.channels, [(0, 3), (1, 8)].into_iter().collect());
+    let payloads: Vec<_> = records.iter().map(|r| r.payload.clone()).collect();
+    assert_eq!(payloads, vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec()]);
+}
+
+#[test]
+fn checkpoint_follows_the_journal_it_rotated_to() {
+    let (path, checkpoint_path) = (temp("rotated"), temp("rotated.checkpoint"));
+    let session = journal::session("DAY1");
+    let ts = SystemTime::now();
+    let mut writer = JournalWriter::create(&path, &session, None).unwrap();
+    writer.set_checkpoint(&checkpoint_path, Duration::from_secs(60));
+    writer.append(&header(0, 1, 1), ts, b"a").unwrap();
+    let rotated = writer.rotate().unwrap();
+    // Gone before syncing again
+    std::mem::forget(writer);
+
+    let checkpoint = Checkpoint::load(&checkpoint_path).unwrap().unwrap();
+    {
+        let mut writer = JournalWriter::resume(&path, &checkpoint, None).unwrap();
+        writer.append(&header(0, 2, 1), ts, b"b").unwrap();
+    }
+    let records: Vec<_> = JournalReader::open(&path)
+        .unwrap()
+        .map(|r| r.unwrap().payload)
+        .collect();
+    for path in [&path, &rotated, &checkpoint_path] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(records, [b"b".to_vec()]);
+}
+
+#[test]
+fn journal_behind_its_checkpoint_is_rejected() {
+    let path = temp("behind");
+    JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    let checkpoint = Checkpoint {
+        journal_offset: 1 << 20,
+        ..Default::default()
+    };
+    let resumed = JournalWriter::resume(&path, &checkpoint, None);
+    std::fs::remove_file(&path).unwrap();
+    assert!(resumed.is_err());
+}
+
+// Serves the seqnums missed while down
+struct Downtime(Vec<Block<Vec<u8>>>);
+
+impl GapFiller<Block<Vec<u8>>> for Downtime {
+    fn fill(&mut self, _channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Vec<u8>>>> {
+        Ok(self
+            .0
+            .iter()
+            .filter(|b| range.contains(&b.header.seqnum))
+            .cloned()
+            .collect())
+    }
+}
+
+#[test]
+fn sequencer_recovers_the_downtime_window() {
+    let block = |seqnum| Block::new(header(0, seqnum, 1), vec![seqnum as u8]);
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(1));
+    sequencer.set_gap_filler(Box::new(Downtime((10..20).map(block).collect())));
+    sequencer.set_next_seqnum(0, 10);
+    sequencer.push(block(20));
+    thread::sleep(Duration::from_millis(5));
+    sequencer.poll_timeouts();
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| e.into_block())
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, (10..=20).collect::<Vec<_>>());
+    assert_eq!(stats.dropped, 0);
+}
@@ -0,0 +1,105 @@
+use sequencer::checksum::{Checksum, TRAILER_LEN};
+use sequencer::metrics::FeedMetrics;
+use sequencer::protocol::{moldudp64, Protocol};
+use sequencer::tcp::{LengthPrefix, TcpFeed, TcpFeedConfig};
+use sequencer::udp::{FeedConfig, UdpFeed};
+use std::io::Write;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+
+const SESSION: [u8; 10] = *b"SESSION001";
+
+// A MoldUDP64 packet with its trailer, the last byte flipped if `corrupt`
+fn packet(seqnum: u64, corrupt: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    moldudp64::write_header(&mut buf, &SESSION, seqnum, 1);
+    moldudp64::write_message(&mut buf, b"abc");
+    Checksum::Crc32c.append(&mut buf);
+    if corrupt {
+        *buf.last_mut().unwrap() ^= 1;
+    }
+    buf
+}
+
+#[test]
+fn checks_and_strips_the_trailer() {
+    let mut buf = b"abc".to_vec();
+    Checksum::Crc32c.append(&mut buf);
+    assert_eq!(buf.len(), 3 + TRAILER_LEN);
+    assert_eq!(Checksum::Crc32c.check(&buf), Some(&b"abc"[..]));
+    buf[0] = b'x';
+    assert_eq!(Checksum::Crc32c.check(&buf), None);
+    assert_eq!(Checksum::Crc32c.check(&buf[..3]), None);
+    assert_eq!(Checksum::Off.check(&buf), Some(&buf[..]));
+
+    let mut off = b"abc".to_vec();
+    Checksum::Off.append(&mut off);
+    assert_eq!(off, b"abc");
+    assert_eq!("crc32c".parse(), Ok(Checksum::Crc32c));
+    assert_eq!("off".parse(), Ok(Checksum::Off));
+    assert!("md5".parse::<Checksum>().is_err());
+}
+
+#[test]
+fn udp_feed_drops_corrupt_datagrams() {
+    let config = FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 6),
+        port: 0,
+        protocol: Protocol::MoldUdp64,
+        channel: 0,
+        source: None,
+        reuse_port: true,
+        recv_buffer: None,
+    };
+    let mut feed = UdpFeed::join(&config).unwrap();
+    feed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let metrics = Arc::<FeedMetrics>::default();
+    feed.set_metrics(Arc::clone(&metrics));
+    feed.set_checksum(Checksum::Crc32c);
+    let dst = (config.group, feed.local_addr().unwrap().port());
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.set_multicast_loop_v4(true).unwrap();
+    sender.send_to(&packet(1, true), dst).unwrap();
+    sender.send_to(&packet(2, false), dst).unwrap();
+
+    assert!(feed.recv().unwrap().is_none());
+    let b = feed.recv().unwrap().unwrap();
+    assert_eq!(b.header.seqnum, 2);
+    // Without the trailer
+    assert_eq!(&b.payload[..], &packet(2, false)[20..25]);
+    assert_eq!(metrics.corrupt.load(Relaxed), 1);
+    assert_eq!(metrics.malformed.load(Relaxed), 0);
+}
+
+#[test]
+fn tcp_feed_drops_corrupt_packets() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = TcpFeedConfig {
+        addr: listener.local_addr().unwrap(),
+        prefix: LengthPrefix::U16,
+        protocol: Protocol::MoldUdp64,
+        channel: 0,
+        #[cfg(feature = "tls")]
+        tls: None,
+    };
+    let mut feed = TcpFeed::connect(&config).unwrap();
+    let metrics = Arc::<FeedMetrics>::default();
+    feed.set_metrics(Arc::clone(&metrics));
+    feed.set_checksum(Checksum::Crc32c);
+    let (mut client, _) = listener.accept().unwrap();
+    let mut stream = Vec::new();
+    for (seqnum, corrupt) in [(1, false), (2, true), (3, false)] {
+        LengthPrefix::U16.write(&mut stream, &packet(seqnum, corrupt));
+    }
+    client.write_all(&stream).unwrap();
+
+    let mut seqnums = Vec::new();
+    while seqnums.len() < 2 {
+        seqnums.extend(feed.recv().unwrap().map(|b| b.header.seqnum));
+    }
+    assert_eq!(seqnums, [1, 3]);
+    assert_eq!(metrics.corrupt.load(Relaxed), 1);
+}
@@ -0,0 +1,113 @@
+use sequencer::clock::{Clock, MockClock};
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::time::Duration;
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+fn gap(from: u64, to: u64) -> Event {
+    SequencedEvent::Gap {
+        channel: 0,
+        from,
+        to,
+        reason: GapReason::Timeout,
+    }
+}
+
+#[test]
+fn mock_clock_only_moves_when_advanced() {
+    let clock = MockClock::new();
+    let start = clock.now();
+    assert_eq!(clock.now(), start);
+    clock.clone().advance(Duration::from_millis(5));
+    assert_eq!(clock.now() - start, Duration::from_millis(5));
+}
+
+#[test]
+fn gap_times_out_once_the_clock_passes_its_deadline() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(100));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    clock.advance(Duration::from_millis(100));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![SequencedEvent::Block(block(0))]
+    );
+
+    clock.advance(Duration::from_millis(1));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![gap(1, 2), SequencedEvent::Block(block(2))]
+    );
+}
+
+#[test]
+fn cascaded_gaps_time_out_by_their_own_deadlines() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(100));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    clock.advance(Duration::from_millis(50));
+    sequencer.push(block(4));
+    clock.advance(Duration::from_millis(60));
+    sequencer.poll_timeouts();
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block(0)),
+            gap(1, 2),
+            SequencedEvent::Block(block(2)),
+        ]
+    );
+
+    // 4 was buffered 60ms ago
+    clock.advance(Duration::from_millis(40));
+    sequencer.poll_timeouts();
+    assert_eq!(receiver.try_iter().count(), 0);
+    clock.advance(Duration::from_millis(1));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![gap(3, 4), SequencedEvent::Block(block(4))]
+    );
+    assert_eq!(sequencer.stats().timeouts, 2);
+}
+
+#[test]
+fn feed_goes_down_by_the_clock() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(100));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_feed_timeout(Some(Duration::from_secs(1)));
+    sequencer.push(block(0));
+    clock.advance(Duration::from_secs(1));
+    sequencer.poll_liveness();
+    clock.advance(Duration::from_millis(1));
+    sequencer.poll_liveness();
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block(0)),
+            SequencedEvent::FeedDown {
+                feed: 0,
+                silent: Duration::from_millis(1001),
+            },
+        ]
+    );
+}
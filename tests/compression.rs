@@ -0,0 +1,220 @@
+use sequencer::config::{ConfigFile, SinkConfig};
+use sequencer::journal::{self, Compression, JournalReader, JournalWriter};
+use sequencer::lz4::{self, Lz4Reader, Lz4Writer};
+use sequencer::BlockHeader;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "compression-{}-{}.journal",
+        name,
+        std::process::id()
+    ))
+}
+
+fn compress(data: &[u8], chunk: usize) -> Vec<u8> {
+    let mut writer = Lz4Writer::new(Vec::new()).unwrap();
+    for chunk in data.chunks(chunk) {
+        writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap();
+    writer.get_ref().clone()
+}
+
+fn decompress(frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    Lz4Reader::new(frame)?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// Compressible, but not trivially
+fn data(len: usize) -> Vec<u8> {
+    let mut x = 1_u32;
+    (0..len)
+        .map(|i| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            if i % 64 < 40 {
+                (i % 7) as u8
+            } else {
+                (x >> 24) as u8
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn xxh32_matches_the_reference() {
+    assert_eq!(lz4::xxh32(b""), 0x02CC_5D05);
+    assert_eq!(lz4::xxh32(b"a"), 0x550D_7456);
+    assert_eq!(lz4::xxh32(b"abc"), 0x32D1_53FF);
+    assert_eq!(
+        lz4::xxh32(b"Nobody inspects the spammish repetition"),
+        0xE229_3B2F
+    );
+}
+
+#[test]
+fn empty_frame_matches_the_lz4_tool() {
+    let frame = compress(b"", 1);
+    assert_eq!(
+        frame,
+        [0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02]
+    );
+    assert!(decompress(&frame).unwrap().is_empty());
+}
+
+#[test]
+fn round_trips() {
+    for len in [1, 12, 13, 100, 65_536, 65_537, 300_000] {
+        let data = data(len);
+        for chunk in [7, 4096, len] {
+            let frame = compress(&data, chunk);
+            assert_eq!(decompress(&frame).unwrap(), data, "{} in {}s", len, chunk);
+        }
+    }
+    let zeros = vec![0_u8; 100_000];
+    let frame = compress(&zeros, 100_000);
+    assert!(frame.len() < 1000);
+    assert_eq!(decompress(&frame).unwrap(), zeros);
+}
+
+#[test]
+fn reads_linked_blocks() {
+    // No checksums and linked blocks: "abcd" stored, then a match of it
+    let mut frame = vec![0x04, 0x22, 0x4D, 0x18, 0x40, 0x40];
+    frame.push((lz4::xxh32(&[0x40, 0x40]) >> 8) as u8);
+    frame.extend_from_slice(&(4_u32 | 1 << 31).to_le_bytes());
+    frame.extend_from_slice(b"abcd");
+    frame.extend_from_slice(&4_u32.to_le_bytes());
+    frame.extend_from_slice(&[0x00, 4, 0, 0x00]);
+    frame.extend_from_slice(&0_u32.to_le_bytes());
+    assert_eq!(decompress(&frame).unwrap(), b"abcdabcd");
+}
+
+#[test]
+fn detects_corruption() {
+    let data = data(10_000);
+    let frame = compress(&data, 10_000);
+    let mut corrupt = frame.clone();
+    // The content checksum
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert!(decompress(&corrupt).is_err());
+    // A frame cut between blocks reads as far as it goes, within a block fails
+    let unfinished = &frame[..frame.len() - 8];
+    assert_eq!(decompress(unfinished).unwrap(), data);
+    assert!(decompress(&frame[..frame.len() - 20]).is_err());
+}
+
+fn append(writer: &mut JournalWriter, seqnum: u64) {
+    let header = BlockHeader {
+        channel: 1,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    let ts = UNIX_EPOCH + Duration::from_secs(seqnum);
+    writer.append(&header, ts, &[seqnum as u8; 100]).unwrap();
+}
+
+#[test]
+fn compressed_journals_read_like_any_other() {
+    let path = temp("journal");
+    let mut writer =
+        JournalWriter::create_compressed(&path, &journal::session("DAY1"), None, Compression::Lz4)
+            .unwrap();
+    for seqnum in 0..100 {
+        append(&mut writer, seqnum);
+    }
+    // Readable before the journal is finished
+    writer.flush().unwrap();
+    let record = JournalReader::open(&path)
+        .unwrap()
+        .nth(50)
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.seqnum, 50);
+    let checkpoint = writer.checkpoint();
+    drop(writer);
+
+    assert!(fs::metadata(&path).unwrap().len() < 100 * 100);
+    let reader = JournalReader::open(&path).unwrap();
+    assert_eq!(reader.session(), b"DAY1      ");
+    let records: Vec<_> = reader.map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), 100);
+    assert_eq!(records[99].payload, [99; 100]);
+    assert_eq!(records[99].ts, UNIX_EPOCH + Duration::from_secs(99));
+
+    let mut reader = JournalReader::open(&path).unwrap();
+    reader.nth(9).unwrap().unwrap();
+    let at = reader.offset();
+    let mut reader = JournalReader::open_at(&path, at).unwrap();
+    assert_eq!(reader.next().unwrap().unwrap().seqnum, 10);
+    assert_eq!(reader.count(), 89);
+
+    assert!(JournalWriter::resume(&path, &checkpoint, None).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rotated_compressed_journals_are_finished() {
+    let path = temp("rotate");
+    let mut writer =
+        JournalWriter::create_compressed(&path, &journal::session(""), None, Compression::Lz4)
+            .unwrap();
+    append(&mut writer, 0);
+    let rotated = writer.rotate().unwrap();
+    append(&mut writer, 1);
+    drop(writer);
+    for (path, seqnum) in [(&rotated, 0), (&path, 1)] {
+        let mut frame = fs::read(path).unwrap();
+        // Ends the frame, so a bad checksum is caught
+        assert_eq!(frame[frame.len() - 8..frame.len() - 4], [0; 4]);
+        let records: Vec<_> = JournalReader::open(path)
+            .unwrap()
+            .map(|r| r.unwrap().seqnum)
+            .collect();
+        assert_eq!(records, [seqnum]);
+        *frame.last_mut().unwrap() ^= 1;
+        fs::write(path, frame).unwrap();
+        assert!(JournalReader::open(path).unwrap().any(|r| r.is_err()));
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn compression_is_per_sink() {
+    assert_eq!("lz4".parse(), Ok(Compression::Lz4));
+    assert_eq!("none".parse(), Ok(Compression::None));
+    assert!("zstd".parse::<Compression>().is_err());
+    let file = ConfigFile::parse(
+        r#"
+        [[sinks]]
+        type = "journal"
+        path = "archive.journal"
+        compression = "lz4"
+
+        [[sinks]]
+        type = "journal"
+        path = "plain.journal"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        file.sinks,
+        [
+            SinkConfig::Journal {
+                path: "archive.journal".into(),
+                compression: Compression::Lz4,
+                fsync_ms: journal::FSYNC_MS,
+            },
+            SinkConfig::Journal {
+                path: "plain.journal".into(),
+                compression: Compression::None,
+                fsync_ms: journal::FSYNC_MS,
+            }
+        ]
+    );
+}
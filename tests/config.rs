@@ -0,0 +1,118 @@
+use sequencer::config::{Change, ConfigFile, ConfigWatcher, FeedEntry, SinkConfig};
+use sequencer::hugepages::HugePages;
+use std::fs;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+timeout_ms = 100
+pin_cores = [0, 2]
+journal = "a.journal"
+
+[[feeds]]
+group = "233.54.12.111:26477"
+interface = "10.0.0.5"
+channel = 1
+
+[[feeds]]
+group = "233.54.12.112:26477"
+source = "10.1.1.1"
+
+[[sinks]]
+type = "shm"
+path = "/dev/shm/sequencer"
+"#;
+
+#[test]
+fn parses_feeds_and_sinks() {
+    let file = ConfigFile::parse(CONFIG).unwrap();
+    assert_eq!(file.timeout_ms, Some(100));
+    assert_eq!(file.feed_timeout_ms, None);
+    assert_eq!(file.pin_cores, Some(vec![0, 2]));
+    assert_eq!(
+        file.feeds[0],
+        FeedEntry {
+            group: "233.54.12.111:26477".parse().unwrap(),
+            interface: Some("10.0.0.5".parse().unwrap()),
+            channel: 1,
+            source: None,
+            protocol: None,
+        }
+    );
+    assert_eq!(file.feeds[1].channel, 0);
+    assert_eq!(
+        file.sinks,
+        [SinkConfig::Shm {
+            path: "/dev/shm/sequencer".into(),
+            slots: sequencer::shm::SHM_SLOTS,
+            slot_len: sequencer::shm::SHM_SLOT_LEN,
+            huge_pages: HugePages::Off,
+        }]
+    );
+    assert!(ConfigFile::parse("timeout = 100").is_err());
+    assert!(ConfigFile::parse("[[sinks]]\ntype = \"carrier pigeon\"").is_err());
+}
+
+#[test]
+fn only_safe_changes_apply_while_running() {
+    let old = ConfigFile::parse(CONFIG).unwrap();
+    let new = ConfigFile::parse(&format!(
+        "log_level = \"debug\"\nfeed_timeout_ms = 0\n{}\n{}",
+        CONFIG.replace("timeout_ms = 100", "timeout_ms = 50"),
+        "[[sinks]]\ntype = \"relay\"\naddr = \"127.0.0.1:9000\""
+    ))
+    .unwrap();
+    assert_eq!(
+        old.changes(&new),
+        [
+            Change::Timeout(Duration::from_millis(50)),
+            Change::FeedTimeout(None),
+            Change::LogLevel("debug".to_string()),
+            Change::AddSink(SinkConfig::Relay {
+                addr: "127.0.0.1:9000".parse().unwrap()
+            }),
+        ]
+    );
+    // Settings dropped from the file keep their value
+    assert_eq!(
+        new.changes(&old),
+        [
+            Change::Timeout(Duration::from_millis(100)),
+            Change::NeedsRestart("removed sinks"),
+        ]
+    );
+
+    let moved = ConfigFile::parse(&CONFIG.replace("111:26477", "111:26478")).unwrap();
+    let feed = |group: &str| FeedEntry {
+        group: group.parse().unwrap(),
+        interface: Some("10.0.0.5".parse().unwrap()),
+        channel: 1,
+        source: None,
+        protocol: None,
+    };
+    assert_eq!(
+        old.changes(&moved),
+        [
+            Change::RemoveFeed(feed("233.54.12.111:26477")),
+            Change::AddFeed(feed("233.54.12.111:26478")),
+        ]
+    );
+    assert_eq!(old.changes(&old), []);
+}
+
+#[test]
+fn watcher_reports_changes_once() {
+    let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+    fs::write(&path, "timeout_ms = 10").unwrap();
+    let mut watcher = ConfigWatcher::new(&path, ConfigFile::load(&path).unwrap()).unwrap();
+    assert_eq!(watcher.poll(), []);
+
+    fs::write(&path, "timeout_ms = 20").unwrap();
+    assert_eq!(watcher.poll(), [Change::Timeout(Duration::from_millis(20))]);
+    assert_eq!(watcher.poll(), []);
+    // A bad edit is skipped rather than applied
+    fs::write(&path, "timeout_ms = \"soon\"").unwrap();
+    assert_eq!(watcher.poll(), []);
+    fs::write(&path, "timeout_ms = 20\nlog_level = \"warn\"").unwrap();
+    assert_eq!(watcher.poll(), [Change::LogLevel("warn".to_string())]);
+    fs::remove_file(&path).unwrap();
+}
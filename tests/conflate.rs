@@ -0,0 +1,141 @@
+use crossbeam_channel::unbounded;
+use sequencer::conflate::{Conflation, ConflationKey};
+use sequencer::fanout::FanOut;
+use sequencer::protocol::moldudp64;
+use sequencer::{Block, BlockHeader, GapReason, OutputLimit, OutputPolicy, SequencedEvent};
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn block(channel: u32, seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+// ITCH 5.0 messages of the stock locates, a type byte then the locate
+fn itch(channel: u32, seqnum: u64, locates: &[u16]) -> Block<Vec<u8>> {
+    let mut payload = Vec::new();
+    for locate in locates {
+        let mut msg = vec![b'A'];
+        msg.extend_from_slice(&locate.to_be_bytes());
+        moldudp64::write_message(&mut payload, &msg);
+    }
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages: locates.len() as u16,
+        ..Default::default()
+    };
+    Block::new(header, payload)
+}
+
+#[test]
+fn a_behind_consumer_gets_the_latest_block_of_each_channel() {
+    let (sender, receiver) = unbounded();
+    let mut fan_out = FanOut::new();
+    // Dropped first once the fan-out stops, so it doesn't wait on the other
+    let (journal, _) = fan_out.subscribe("journal", OutputLimit::default());
+    // Never waited on though its policy says block
+    let limit = OutputLimit {
+        capacity: Some(4),
+        policy: OutputPolicy::Block,
+    };
+    let (dashboard, lag) =
+        fan_out.subscribe_conflated("dashboard", limit, Box::new(Conflation::Channel));
+    let fan_out = fan_out.spawn(receiver).unwrap();
+
+    let gap = SequencedEvent::Gap {
+        channel: 1,
+        from: 4,
+        to: 5,
+        reason: GapReason::Timeout,
+    };
+    let mut events: Vec<Event> = Vec::new();
+    events.extend((1..=8).map(|s| SequencedEvent::Block(block(0, s))));
+    events.extend((1..=3).map(|s| SequencedEvent::Block(block(1, s))));
+    events.push(gap.clone());
+    // Not in place of the block before the gap
+    events.push(SequencedEvent::Block(block(0, 9)));
+    for event in &events {
+        sender.send(event.clone()).unwrap();
+    }
+    drop(sender);
+
+    // The journal is read first, so everything is queued for the dashboard
+    // before it reads anything
+    assert_eq!(journal.iter().collect::<Vec<_>>(), events);
+    let conflated: Vec<_> = dashboard.iter().inspect(|e| lag.consumed(e)).collect();
+    fan_out.join().unwrap();
+    // The first 4 fit in its queue
+    let mut expected: Vec<_> = events[..4].to_vec();
+    expected.extend([
+        SequencedEvent::Block(block(0, 8)),
+        SequencedEvent::Block(block(1, 3)),
+        gap,
+        SequencedEvent::Block(block(0, 9)),
+    ]);
+    assert_eq!(conflated, expected);
+    let stats = lag.stats();
+    // 5 to 7 of channel 0 and 1 to 2 of channel 1
+    assert_eq!((stats.conflated, stats.lagged), (5, 5));
+    // Replaced blocks aren't waited on
+    assert_eq!(stats.behind, 0);
+}
+
+#[test]
+fn keys_blocks_by_channel_or_symbol() {
+    let one_symbol = itch(3, 1, &[7, 7]);
+    assert_eq!(Conflation::Channel.key(&one_symbol), Some(3 << 16));
+    assert_eq!(Conflation::Symbol.key(&one_symbol), Some(3 << 16 | 7));
+    assert_eq!(Conflation::Symbol.key(&itch(3, 1, &[8])), Some(3 << 16 | 8));
+    // Not conflated with anything, so always delivered
+    assert_eq!(Conflation::Symbol.key(&itch(3, 1, &[7, 8])), None);
+    assert_eq!(Conflation::Symbol.key(&itch(3, 1, &[])), None);
+    assert_eq!(Conflation::Symbol.key(&block(3, 1)), None);
+
+    assert_eq!("symbol".parse(), Ok(Conflation::Symbol));
+    assert!("message".parse::<Conflation>().is_err());
+}
+
+#[test]
+fn conflates_by_symbol_within_a_channel() {
+    let (sender, receiver) = unbounded();
+    let mut fan_out = FanOut::new();
+    let (journal, _) = fan_out.subscribe("journal", OutputLimit::default());
+    let limit = OutputLimit {
+        capacity: Some(4),
+        policy: OutputPolicy::DropOldest,
+    };
+    let (dashboard, lag) =
+        fan_out.subscribe_conflated("dashboard", limit, Box::new(Conflation::Symbol));
+    let fan_out = fan_out.spawn(receiver).unwrap();
+    // The first 4 fit in its queue
+    let blocks = [
+        itch(5, 1, &[1]),
+        itch(5, 2, &[1]),
+        itch(5, 3, &[1]),
+        itch(0, 1, &[1]),
+        itch(0, 2, &[2]),
+        itch(0, 3, &[1, 1]),
+        itch(0, 5, &[2]),
+        itch(0, 6, &[1, 2]),
+        itch(0, 8, &[2]),
+    ];
+    for b in &blocks {
+        sender.send(SequencedEvent::Block(b.clone())).unwrap();
+    }
+    drop(sender);
+
+    assert_eq!(journal.iter().count(), blocks.len());
+    let conflated: Vec<_> = dashboard.iter().filter_map(|e| e.into_block()).collect();
+    fan_out.join().unwrap();
+    // 5 replaced 2, and the mixed block has no key so the blocks after it
+    // queue behind it
+    let expected = [0, 1, 2, 3, 6, 5, 7, 8].map(|i| blocks[i].clone());
+    assert_eq!(conflated, expected);
+    assert_eq!(lag.stats().conflated, 1);
+}
@@ -0,0 +1,172 @@
+use sequencer::config::{Change, ConfigFile};
+use sequencer::protocol::custom::{self, Endian, Field, Framing, MessageFraming, Messages};
+use sequencer::protocol::{ParseError, Protocol};
+
+const FRAMING: &str = r#"
+[framing]
+header_len = 16
+seqnum = { offset = 4, width = 8 }
+count = { offset = 12, width = 2, endian = "little" }
+messages = { length_prefixed = { width = 2 } }
+"#;
+
+fn framing() -> Framing {
+    ConfigFile::parse(FRAMING).unwrap().framing.unwrap()
+}
+
+// A header of `framing()` followed by length prefixed `msgs`
+fn packet(seqnum: u64, count: u16, msgs: &[&[u8]]) -> Vec<u8> {
+    let mut buf = vec![0xee; 4];
+    buf.extend_from_slice(&seqnum.to_be_bytes());
+    buf.extend_from_slice(&count.to_le_bytes());
+    buf.extend_from_slice(&[0; 2]);
+    for msg in msgs {
+        buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        buf.extend_from_slice(msg);
+    }
+    buf
+}
+
+#[test]
+fn parses_the_framing_from_the_config_file() {
+    let framing = framing();
+    assert_eq!(framing.header_len, 16);
+    assert_eq!(
+        framing.count,
+        Some(Field {
+            offset: 12,
+            width: 2,
+            endian: Endian::Little,
+        })
+    );
+    assert_eq!(
+        framing.messages,
+        Some(MessageFraming::LengthPrefixed {
+            width: 2,
+            endian: Endian::Big,
+            inclusive: false,
+        })
+    );
+    assert_eq!(framing.first_seqnum, 1);
+    assert_eq!("custom".parse(), Ok(Protocol::Custom(None)));
+    assert_eq!(Protocol::Custom(Some(framing)).first_seqnum(), 1);
+
+    // Taking effect needs a restart
+    let changed = FRAMING.replace("header_len = 16", "header_len = 20");
+    let changes = ConfigFile::parse(FRAMING)
+        .unwrap()
+        .changes(&ConfigFile::parse(&changed).unwrap());
+    assert_eq!(changes, [Change::NeedsRestart("framing")]);
+}
+
+#[test]
+fn rejects_framings_that_cant_frame() {
+    let bad = [
+        "header_len = 8\nseqnum = { offset = 0, width = 9 }",
+        "header_len = 8\nseqnum = { offset = 4, width = 8 }",
+        "header_len = 8\nseqnum = { offset = 0, width = 4 }\ncount = { offset = 4, width = 4 }",
+        "header_len = 8\nseqnum = { offset = 0, width = 4 }\nmessages = { fixed = 0 }",
+        "header_len = 8\nseqnum = { offset = 0, width = 4, endian = \"middle\" }",
+        "header_len = 8\nseqnum = { offset = 0, width = 4 }\nmessages = { length_prefixed = { width = 8 } }",
+        "header_len = 16\nseqnum = { offset = 0, width = 4 }\nsession = { offset = 4, width = 11 }",
+    ];
+    for framing in bad {
+        let file = format!("[framing]\n{}", framing);
+        assert!(ConfigFile::parse(&file).is_err(), "{}", framing);
+    }
+}
+
+#[test]
+fn frames_length_prefixed_messages() {
+    let protocol = Protocol::Custom(Some(framing()));
+    let buf = packet(1_000, 2, &[b"abc", b"de"]);
+    let parsed = protocol.parse_checked(&buf).unwrap();
+    assert_eq!((parsed.header.seqnum, parsed.header.n_messages), (1_000, 2));
+    assert_eq!(parsed.payload, &buf[16..]);
+    assert!(!parsed.malformed());
+    let framing = framing();
+    let msgs: Vec<_> = Messages::new(framing.messages.unwrap(), parsed.payload, 2)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(msgs, [&b"abc"[..], b"de"]);
+
+    // Cut short, the count says more than there is
+    let parsed = custom::parse(&framing, &buf[..buf.len() - 1]).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.missing), (1, 1));
+    let mut long = buf.clone();
+    long.push(0);
+    let parsed = custom::parse(&framing, &long).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.trailing), (2, 1));
+    assert_eq!(
+        protocol.parse_checked(&buf[..10]).unwrap_err(),
+        ParseError::Truncated
+    );
+}
+
+#[test]
+fn frames_fixed_messages_without_a_count() {
+    let framing = Framing {
+        header_len: 4,
+        seqnum: Field {
+            offset: 0,
+            width: 4,
+            endian: Endian::Little,
+        },
+        count: None,
+        messages: Some(MessageFraming::Fixed(3)),
+        session: None,
+        first_seqnum: 0,
+    };
+    let mut buf = 7_u32.to_le_bytes().to_vec();
+    buf.extend_from_slice(b"aaabbbcc");
+    let parsed = custom::parse(&framing, &buf).unwrap();
+    // As many as are whole
+    assert_eq!((parsed.header.seqnum, parsed.header.n_messages), (7, 2));
+    assert_eq!((parsed.missing, parsed.trailing), (0, 2));
+    assert_eq!(Protocol::Custom(Some(framing)).first_seqnum(), 0);
+
+    // Without message framing a packet is one message
+    let framing = Framing {
+        messages: None,
+        ..framing
+    };
+    let parsed = custom::parse(&framing, &buf).unwrap();
+    assert_eq!(parsed.header.n_messages, 1);
+    assert_eq!(parsed.payload, b"aaabbbcc");
+}
+
+#[test]
+fn inclusive_prefixes_count_themselves() {
+    let framing = Framing {
+        header_len: 2,
+        seqnum: Field {
+            offset: 0,
+            width: 2,
+            endian: Endian::Big,
+        },
+        count: Some(Field {
+            offset: 0,
+            width: 1,
+            endian: Endian::Big,
+        }),
+        messages: Some(MessageFraming::LengthPrefixed {
+            width: 1,
+            endian: Endian::Big,
+            inclusive: true,
+        }),
+        session: None,
+        first_seqnum: 1,
+    };
+    // seqnum 0x0102, count 1, then one message of 3 bytes
+    let buf = [1, 2, 4, b'x', b'y', b'z'];
+    let parsed = custom::parse(&framing, &buf).unwrap();
+    assert_eq!(
+        (parsed.header.seqnum, parsed.header.n_messages),
+        (0x0102, 1)
+    );
+    let msgs: Vec<_> = Messages::new(framing.messages.unwrap(), parsed.payload, 1).collect();
+    assert_eq!(msgs, [Ok(&b"xyz"[..])]);
+    // A length shorter than its own prefix
+    let parsed = custom::parse(&framing, &[1, 2, 0, 0]).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.missing), (0, 1));
+}
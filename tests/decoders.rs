@@ -0,0 +1,132 @@
+use sequencer::config::ConfigFile;
+use sequencer::pcap::{PcapSource, PcapngWriter};
+use sequencer::protocol::custom::{Endian, Field, Framing, MessageFraming};
+use sequencer::protocol::{moldudp64, Protocol};
+use sequencer::Sequencer;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, UNIX_EPOCH};
+
+const SESSION: [u8; 10] = *b"SESSION001";
+
+fn moldudp64(seqnum: u64, msg: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    moldudp64::write_header(&mut buf, &SESSION, seqnum, 1);
+    moldudp64::write_message(&mut buf, msg);
+    buf
+}
+
+// The same messages with a little endian seqnum ahead of the session
+fn proprietary(seqnum: u64, msg: &[u8]) -> Vec<u8> {
+    let mut buf = seqnum.to_le_bytes().to_vec();
+    buf.extend_from_slice(&SESSION);
+    buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+    buf.extend_from_slice(msg);
+    buf
+}
+
+fn framing() -> Framing {
+    Framing {
+        header_len: 18,
+        seqnum: Field {
+            offset: 0,
+            width: 8,
+            endian: Endian::Little,
+        },
+        count: None,
+        messages: Some(MessageFraming::LengthPrefixed {
+            width: 2,
+            endian: Endian::Big,
+            inclusive: false,
+        }),
+        session: Some(Field {
+            offset: 8,
+            width: 10,
+            endian: Endian::Big,
+        }),
+        first_seqnum: 1,
+    }
+}
+
+#[test]
+fn differently_framed_feeds_decode_to_the_same_blocks() {
+    let custom = Protocol::Custom(Some(framing()));
+    let (a_buf, b_buf) = (moldudp64(5, b"abc"), proprietary(5, b"abc"));
+    let (a, a_payload) = Protocol::MoldUdp64.parse(&a_buf).unwrap();
+    let (b, b_payload) = custom.parse(&b_buf).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(a_payload, b_payload);
+}
+
+#[test]
+fn feeds_name_their_protocol_in_the_config_file() {
+    let file = ConfigFile::parse(
+        r#"
+[[feeds]]
+group = "239.1.1.1:5000"
+protocol = "moldudp64"
+
+[[feeds]]
+group = "239.1.1.2:5000"
+"#,
+    )
+    .unwrap();
+    let protocols: Vec<_> = file.feeds.iter().map(|f| f.protocol.clone()).collect();
+    assert_eq!(protocols, [Some(Protocol::MoldUdp64), None]);
+
+    let bad = "[[feeds]]\ngroup = \"239.1.1.1:5000\"\nprotocol = \"smoke signals\"";
+    assert!(ConfigFile::parse(bad).is_err());
+}
+
+#[test]
+fn replayed_feeds_are_arbitrated_across_protocols() {
+    let src = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000);
+    let a = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 5000);
+    let b = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 2), 5001);
+
+    // Feed b is missing 3, which feed a has
+    let mut capture = Vec::new();
+    let mut writer = PcapngWriter::new(&mut capture).unwrap();
+    let mut datagrams = Vec::new();
+    for seqnum in 1..=5 {
+        datagrams.push((a, moldudp64(seqnum, &[seqnum as u8])));
+        if seqnum != 3 {
+            datagrams.push((b, proprietary(seqnum, &[seqnum as u8])));
+        }
+    }
+    for (i, (dst, datagram)) in datagrams.iter().enumerate() {
+        let ts = UNIX_EPOCH + Duration::from_millis(i as u64);
+        writer.write_udp(0, ts, src, *dst, datagram).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+
+    let mut source = PcapSource::new(capture.as_slice()).unwrap();
+    source.set_protocol(Protocol::MoldUdp64);
+    source.add_feed(a);
+    source.add_feed(b);
+    source.set_feed_protocol(1, Protocol::Custom(Some(framing())));
+
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(Protocol::MoldUdp64.first_seqnum());
+    let mut feeds = Vec::new();
+    while let Some((feed, _, block)) = source.next_block().unwrap() {
+        feeds.push(feed);
+        sequencer.push_from(feed, block);
+    }
+    let stats = sequencer.stats();
+    drop(sequencer);
+    assert_eq!(feeds, [0, 1, 0, 1, 0, 0, 1, 0, 1]);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| e.into_block())
+        .map(|block| (block.header.seqnum, block.payload.to_vec()))
+        .collect();
+    assert_eq!(
+        seqnums,
+        (1..=5)
+            .map(|s| (s, vec![0, 1, s as u8]))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(stats.feeds[1].duplicates, 4);
+}
@@ -0,0 +1,113 @@
+use sequencer::diff::{self, Difference, Report};
+use sequencer::journal::{self, JournalReader, JournalWriter};
+use sequencer::BlockHeader;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("diff-{}-{}", name, std::process::id()))
+}
+
+// (channel, seqnum, n_messages, payload) of each record
+fn write(path: &PathBuf, records: &[(u32, u64, u16, &[u8])]) {
+    let mut writer = JournalWriter::create(path, &journal::session("DAY1"), None).unwrap();
+    for (channel, seqnum, n_messages, payload) in records {
+        let header = BlockHeader {
+            channel: *channel,
+            seqnum: *seqnum,
+            n_messages: *n_messages,
+            ..Default::default()
+        };
+        writer.append(&header, SystemTime::now(), payload).unwrap();
+    }
+    writer.sync().unwrap();
+}
+
+fn diffed(name: &str, a: &[(u32, u64, u16, &[u8])], b: &[(u32, u64, u16, &[u8])]) -> Report {
+    let (path_a, path_b) = (temp(&format!("{}-a", name)), temp(&format!("{}-b", name)));
+    write(&path_a, a);
+    write(&path_b, b);
+    let report = diff::diff(
+        JournalReader::open(&path_a).unwrap(),
+        JournalReader::open(&path_b).unwrap(),
+    )
+    .unwrap();
+    fs::remove_file(path_a).unwrap();
+    fs::remove_file(path_b).unwrap();
+    report
+}
+
+#[test]
+fn same_blocks_in_another_order_are_the_same() {
+    let a: &[(u32, u64, u16, &[u8])] = &[(0, 1, 2, b"ab"), (1, 1, 1, b"x"), (0, 3, 1, b"c")];
+    // As an arbiter might write them, channels interleaved differently
+    let b: &[(u32, u64, u16, &[u8])] = &[(1, 1, 1, b"x"), (0, 3, 1, b"c"), (0, 1, 2, b"ab")];
+    let report = diffed("same", a, b);
+    assert!(report.same());
+    assert_eq!(report.records, (3, 3));
+    assert_eq!(report.same, 3);
+    assert!(report.to_string().ends_with("same"));
+}
+
+#[test]
+fn reports_missing_extra_and_different_blocks() {
+    let a: &[(u32, u64, u16, &[u8])] = &[
+        (0, 1, 1, b"a"),
+        (0, 2, 2, b"bc"),
+        (0, 4, 1, b"d"),
+        (0, 5, 1, b"e"),
+        (0, 6, 1, b"f"),
+        (2, 1, 1, b"z"),
+    ];
+    let b: &[(u32, u64, u16, &[u8])] = &[
+        (0, 1, 1, b"a"),
+        // Blocked differently
+        (0, 2, 1, b"b"),
+        (0, 3, 1, b"c"),
+        (0, 6, 1, b"F"),
+        (0, 7, 3, b"ghi"),
+    ];
+    let report = diffed("different", a, b);
+    assert!(!report.same());
+    assert_eq!(report.records, (6, 5));
+    assert_eq!(report.same, 1);
+    // 4 and 5 are joined into one range
+    assert_eq!(report.only_a[&0], vec![4..6]);
+    assert_eq!(report.only_a[&2], vec![1..2]);
+    assert_eq!(report.only_b[&0], vec![3..4, 7..10]);
+    assert_eq!(
+        report.different,
+        [
+            (0, 2, Difference::Messages(2, 1)),
+            (0, 6, Difference::Payload)
+        ]
+    );
+    let text = report.to_string();
+    assert!(text.contains("only in a channel 0 seqnums 4..6"));
+    assert!(text.contains("only in b channel 0 seqnums 7..10"));
+    assert!(text.contains("channel 0 seqnum 6 payloads differ"));
+    assert!(text.ends_with("DIFFERENT"));
+}
+
+#[test]
+fn an_empty_journal_has_none_of_the_other() {
+    let a: &[(u32, u64, u16, &[u8])] = &[(0, 1, 1, b"a"), (0, 2, 1, b"b")];
+    let report = diffed("empty", a, &[]);
+    assert_eq!(report.only_a[&0], vec![1..3]);
+    assert!(report.only_b.is_empty());
+    assert_eq!(report.records, (2, 0));
+}
+
+#[test]
+fn blocks_ending_at_the_last_seqnum() {
+    let last = u64::MAX;
+    let a: &[(u32, u64, u16, &[u8])] = &[(0, last - 1, 1, b"a"), (0, last, 1, b"b")];
+    let b: &[(u32, u64, u16, &[u8])] = &[(0, last, 1, b"b")];
+    let report = diffed("last", a, b);
+    assert_eq!(report.same, 1);
+    assert_eq!(report.only_a[&0], vec![last - 1..last]);
+    // Its end can only be saturated
+    let report = diffed("last-only", a, &[]);
+    assert_eq!(report.only_a[&0], vec![last - 1..last]);
+}
@@ -0,0 +1,111 @@
+use sequencer::dump::{self, Decode};
+use sequencer::journal::{self, JournalReader, JournalWriter};
+use sequencer::protocol::{mdp3, moldudp64, opra};
+use sequencer::BlockHeader;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dump-{}-{}", name, std::process::id()))
+}
+
+// The lines dump prints of a journal of `blocks`
+fn dumped(name: &str, blocks: &[(u64, u16, Vec<u8>)], decode: Decode) -> Vec<String> {
+    let path = temp(name);
+    let ts = UNIX_EPOCH + Duration::new(1_700_000_000, 123);
+    {
+        let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+        for (seqnum, n_messages, payload) in blocks {
+            let header = BlockHeader {
+                channel: 2,
+                seqnum: *seqnum,
+                n_messages: *n_messages,
+                ..Default::default()
+            };
+            writer.append(&header, ts, payload).unwrap();
+        }
+        writer.sync().unwrap();
+    }
+    let mut out = Vec::new();
+    let n = dump::dump(JournalReader::open(&path).unwrap(), decode, &mut out).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(n, blocks.len() as u64);
+    String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn prints_raw_payloads_in_hex() {
+    let lines = dumped(
+        "raw",
+        &[(5, 1, vec![0xde, 0xad]), (6, 0, Vec::new())],
+        Decode::Raw,
+    );
+    assert_eq!(
+        lines,
+        vec![
+            "session DAY1      ",
+            "1700000000.000000123 channel 2 seqnum 5 messages 1 bytes 2",
+            "  dead",
+            "1700000000.000000123 channel 2 seqnum 6 messages 0 bytes 0",
+        ]
+    );
+}
+
+#[test]
+fn decodes_itch_messages() {
+    let mut delete = vec![b'D'];
+    delete.extend_from_slice(&7_u16.to_be_bytes());
+    delete.extend_from_slice(&0_u16.to_be_bytes());
+    delete.extend_from_slice(&[0; 6]);
+    delete.extend_from_slice(&42_u64.to_be_bytes());
+    let mut payload = Vec::new();
+    moldudp64::write_message(&mut payload, &delete);
+    moldudp64::write_message(&mut payload, b"D");
+
+    let lines = dumped("itch", &[(10, 2, payload)], Decode::Itch50);
+    assert_eq!(lines.len(), 4);
+    assert!(lines[2].starts_with("  10 OrderDelete("));
+    assert!(lines[2].contains("order_ref: 42"));
+    assert_eq!(lines[3], "  11 truncated packet");
+}
+
+#[test]
+fn decodes_mdp3_messages() {
+    let msg = mdp3::Message {
+        block_length: 4,
+        template_id: 46,
+        schema_id: 1,
+        version: 9,
+        body: &[1, 2, 3, 4],
+    };
+    let mut payload = Vec::new();
+    mdp3::write_message(&mut payload, &msg);
+    mdp3::write_message(&mut payload, &msg);
+
+    let lines = dumped("mdp3", &[(3, 1, payload)], Decode::Mdp3);
+    assert_eq!(lines[2], "  3.0 template 46 schema 1 version 9 bytes 4");
+    assert_eq!(lines[3], "  3.1 template 46 schema 1 version 9 bytes 4");
+}
+
+#[test]
+fn decodes_opra_messages() {
+    let mut buf = Vec::new();
+    opra::write_header(&mut buf, b'1', 20, false);
+    opra::write_message(&mut buf, b'a', b' ', b"abcd");
+    opra::write_message(&mut buf, b'k', b'F', b"ef");
+    let payload = buf[opra::HEADER_LEN..].to_vec();
+
+    let lines = dumped("opra", &[(20, 2, payload)], Decode::Opra);
+    assert_eq!(lines[2], "  20 participant A category a type   bytes 4");
+    assert_eq!(lines[3], "  21 participant A category k type F bytes 2");
+}
+
+#[test]
+fn parses_decoders() {
+    assert_eq!("itch50".parse(), Ok(Decode::Itch50));
+    assert!("fix".parse::<Decode>().is_err());
+}
@@ -0,0 +1,58 @@
+use sequencer::{Block, BlockHeader, Sequencer};
+use std::collections::HashSet;
+use std::time::Duration;
+
+fn block(seqnum: u64, payload: u8) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![payload])
+}
+
+#[test]
+fn ab_feeds_deliver_each_seqnum_once() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    // Feed 0 is in order, feed 1 has 3 and 4 swapped and lags behind feed 0
+    let a: Vec<u64> = (0..100).collect();
+    let mut b = a.clone();
+    b.swap(3, 4);
+    for i in 0..a.len() {
+        sequencer.push_from(0, block(a[i], 0));
+        if i >= 2 {
+            sequencer.push_from(1, block(b[i - 2], 1));
+        }
+    }
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let mut seen = HashSet::new();
+    for block in receiver.iter().filter_map(|e| e.into_block()) {
+        assert!(
+            seen.insert(block.header.seqnum),
+            "{} twice",
+            block.header.seqnum
+        );
+        assert_eq!(block.payload, vec![0]);
+    }
+    assert_eq!(seen.len(), 100);
+    assert_eq!(stats.feeds[0].duplicates, 0);
+    assert_eq!(stats.feeds[1].duplicates, 98);
+    assert_eq!(stats.duplicates, 98);
+}
+
+#[test]
+fn buffered_duplicate_keeps_first_copy() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.push_from(0, block(1, 0));
+    sequencer.push_from(1, block(1, 1));
+    sequencer.push_from(1, block(0, 1));
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let blocks: Vec<_> = receiver.iter().filter_map(|e| e.into_block()).collect();
+    assert_eq!(blocks, vec![block(0, 1), block(1, 0)]);
+    assert_eq!(stats.feeds[1].duplicates, 1);
+}
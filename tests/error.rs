@@ -0,0 +1,68 @@
+use sequencer::error::{self, supervise, FailurePolicy, SequencerError};
+use sequencer::shutdown::Shutdown;
+use std::error::Error;
+use std::io;
+use std::thread;
+
+fn failure() -> SequencerError {
+    SequencerError::feed(1)(io::Error::other("socket closed"))
+}
+
+#[test]
+fn parses_failure_policies() {
+    assert_eq!("retry".parse(), Ok(FailurePolicy::Retry));
+    assert_eq!("degrade".parse(), Ok(FailurePolicy::Degrade));
+    assert_eq!("shutdown".parse(), Ok(FailurePolicy::Shutdown));
+    assert!("ignore".parse::<FailurePolicy>().is_err());
+}
+
+#[test]
+fn errors_name_what_failed() {
+    let e = failure();
+    assert_eq!(e.to_string(), "feed 1: socket closed");
+    assert_eq!(e.source().unwrap().to_string(), "socket closed");
+    let e = SequencerError::sink("journal")(io::ErrorKind::StorageFull.into());
+    assert!(e.to_string().starts_with("sink journal: "));
+
+    let panicked = thread::Builder::new()
+        .name("feed 0".to_string())
+        .spawn(|| panic!("bad packet"))
+        .unwrap();
+    let e = error::join(panicked).unwrap_err();
+    assert_eq!(e.to_string(), "feed 0 thread panicked");
+}
+
+#[test]
+fn policies_retry_degrade_or_shut_down() {
+    let shutdown = Shutdown::default();
+    let mut attempts = 0;
+    let retried = supervise(FailurePolicy::Retry, &shutdown, || {
+        attempts += 1;
+        match attempts {
+            1 | 2 => Err(failure()),
+            _ => Ok(attempts),
+        }
+    });
+    assert_eq!(retried.unwrap(), 3);
+
+    let degraded = supervise(FailurePolicy::Degrade, &shutdown, || {
+        Err::<u64, _>(failure())
+    });
+    assert_eq!(degraded.unwrap(), 0);
+    assert!(!shutdown.requested());
+
+    let stopped = supervise(FailurePolicy::Shutdown, &shutdown, || {
+        Err::<(), _>(failure())
+    });
+    assert!(matches!(stopped, Err(SequencerError::Feed { feed: 1, .. })));
+    assert!(shutdown.requested());
+
+    // Nothing more is retried once shutting down
+    let mut attempts = 0;
+    let retried = supervise(FailurePolicy::Retry, &shutdown, || {
+        attempts += 1;
+        Err::<(), _>(failure())
+    });
+    assert!(retried.is_ok());
+    assert_eq!(attempts, 1);
+}
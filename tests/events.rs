@@ -0,0 +1,93 @@
+use sequencer::events::Events;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn pending_takes_what_is_sequenced_so_far() {
+    let (mut sequencer, events) = Sequencer::with_events(Duration::from_secs(60));
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    let seqnums: Vec<_> = events
+        .pending()
+        .filter_map(|e| e.into_block())
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, vec![0]);
+
+    sequencer.push(block(1));
+    assert_eq!(events.pending().count(), 2);
+    assert_eq!(events.pending().count(), 0);
+}
+
+#[test]
+fn iterates_until_the_sequencer_is_dropped() {
+    let (mut sequencer, events) = Sequencer::with_events(Duration::from_millis(1));
+    let consumer = thread::spawn(move || events.collect::<Vec<_>>());
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    sequencer.drain();
+    drop(sequencer);
+
+    assert_eq!(
+        consumer.join().unwrap(),
+        vec![
+            SequencedEvent::Block(block(0)),
+            SequencedEvent::Gap {
+                channel: 0,
+                from: 1,
+                to: 2,
+                reason: GapReason::Shutdown
+            },
+            SequencedEvent::Block(block(2)),
+        ]
+    );
+}
+
+#[test]
+fn blocks_leave_out_the_rest() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    let events = Events::from(receiver);
+    sequencer.push(block(1));
+    sequencer.push(block(0));
+    sequencer.drain();
+    drop(sequencer);
+    let blocks: Vec<_> = events.blocks().collect();
+    assert_eq!(blocks, vec![block(0), block(1)]);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_arbiter_output_is_a_stream() {
+    use futures_core::Stream;
+    use sequencer::async_arbiter::AsyncArbiter;
+    use sequencer::events::EventStream;
+    use std::pin::Pin;
+
+    let (sequencer, events) = Sequencer::new(Duration::from_millis(10));
+    let (mut arbiter, output) = AsyncArbiter::new(sequencer, events, Duration::from_millis(5));
+    let sender = arbiter.add_feed();
+    tokio::spawn(async move {
+        for seqnum in 0..3 {
+            sender.send(block(seqnum)).await.unwrap();
+        }
+    });
+    tokio::spawn(arbiter.run());
+
+    let mut stream = EventStream::from(output);
+    let mut seqnums = Vec::new();
+    while let Some(e) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        seqnums.extend(e.into_block().map(|b| b.header.seqnum));
+    }
+    assert_eq!(seqnums, vec![0, 1, 2]);
+}
@@ -0,0 +1,284 @@
+use crossbeam_channel::Receiver;
+use sequencer::fanout::{BlockFilter, ConsumerLag, FanOut, SlowConsumerPolicy};
+use sequencer::protocol::moldudp64;
+use sequencer::{
+    Block, BlockHeader, GapReason, OutputLimit, OutputPolicy, SequencedEvent, Sequencer,
+};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+#[test]
+fn every_consumer_gets_the_whole_stream() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::ZERO);
+    let mut fan_out = FanOut::new();
+    let consumers: Vec<_> = ["journal", "sampler", "strategy"]
+        .iter()
+        .map(|name| fan_out.subscribe(name, OutputLimit::default()))
+        .collect();
+    let fan_out = fan_out.spawn(receiver).unwrap();
+
+    let mut expected = Vec::new();
+    for seqnum in (0..100).filter(|&s| s != 10) {
+        sequencer.push(block(seqnum));
+        if seqnum == 11 {
+            expected.push(SequencedEvent::Gap {
+                channel: 0,
+                from: 10,
+                to: 11,
+                reason: GapReason::Timeout,
+            });
+        }
+        expected.push(SequencedEvent::Block(block(seqnum)));
+    }
+    drop(sequencer);
+    fan_out.join().unwrap();
+    for (receiver, lag) in consumers {
+        let events: Vec<Event> = receiver.iter().collect();
+        assert_eq!(events, expected);
+        let stats = lag.stats();
+        // Nothing was read until the stream ended
+        assert_eq!((stats.max_depth, stats.lagged), (100, 0));
+    }
+}
+
+#[test]
+fn slow_consumer_lags_without_holding_up_the_others() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    let mut fan_out = FanOut::new();
+    let (fast, _) = fan_out.subscribe("fast", OutputLimit::default());
+    let slow_limit = OutputLimit {
+        capacity: Some(2),
+        policy: OutputPolicy::DropOldest,
+    };
+    let (slow, slow_lag) = fan_out.subscribe("slow", slow_limit);
+    let fan_out = fan_out.spawn(receiver).unwrap();
+    for seqnum in 0..50 {
+        sequencer.push(block(seqnum));
+    }
+    drop(sequencer);
+
+    let seqnums: Vec<_> = fast
+        .iter()
+        .map(|e| e.into_block().unwrap().header.seqnum)
+        .collect();
+    assert_eq!(seqnums, (0..50).collect::<Vec<_>>());
+    let stats = slow_lag.stats();
+    assert_eq!(stats.name, "slow");
+    assert_eq!((stats.lagged, stats.max_depth), (47, 4));
+
+    let events: Vec<Event> = slow.iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block(0)),
+            SequencedEvent::Block(block(1)),
+            SequencedEvent::Gap {
+                channel: 0,
+                from: 2,
+                to: 49,
+                reason: GapReason::Lagged,
+            },
+            SequencedEvent::Block(block(49)),
+        ]
+    );
+    fan_out.join().unwrap();
+}
+
+#[test]
+fn consumers_that_hang_up_are_dropped() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    let mut fan_out = FanOut::new();
+    let (gone, _) = fan_out.subscribe("gone", OutputLimit::default());
+    let (live, _) = fan_out.subscribe("live", OutputLimit::default());
+    drop(gone);
+    let fan_out = fan_out.spawn(receiver).unwrap();
+    for seqnum in 0..10 {
+        sequencer.push(block(seqnum));
+    }
+    drop(sequencer);
+    fan_out.join().unwrap();
+    assert_eq!(live.iter().count(), 10);
+}
+
+// A MoldUDP64 block of ITCH messages, each (type, stock locate)
+fn itch_block(channel: u32, seqnum: u64, messages: &[(u8, u16)]) -> Block<Vec<u8>> {
+    let mut payload = Vec::new();
+    for &(kind, locate) in messages {
+        let mut msg = vec![kind];
+        msg.extend_from_slice(&locate.to_be_bytes());
+        msg.extend_from_slice(&[0; 8]);
+        moldudp64::write_message(&mut payload, &msg);
+    }
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages: messages.len() as u16,
+        ..Default::default()
+    };
+    Block::new(header, payload)
+}
+
+// "channel/seqnum" of blocks and "channel/from..to" of gaps
+fn describe(events: Vec<Event>) -> Vec<String> {
+    events
+        .into_iter()
+        .map(|e| match e {
+            SequencedEvent::Block(b) => format!("{}/{}", b.header.channel, b.header.seqnum),
+            SequencedEvent::Gap {
+                channel, from, to, ..
+            } => format!("{}/{}..{}", channel, from, to),
+            e => panic!("unexpected event {:?}", e),
+        })
+        .collect()
+}
+
+#[test]
+fn filters_run_before_queueing() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::ZERO);
+    let mut fan_out = FanOut::new();
+    let (all, _) = fan_out.subscribe("all", OutputLimit::default());
+    let strategy = BlockFilter {
+        channels: Some(vec![1]),
+        stock_locates: Some(1..=10),
+        message_types: None,
+    };
+    let (strategy, strategy_lag) =
+        fan_out.subscribe_filtered("strategy", OutputLimit::default(), Box::new(strategy));
+    let trades = BlockFilter {
+        message_types: Some(vec![b'P']),
+        ..Default::default()
+    };
+    let (trades, _) =
+        fan_out.subscribe_filtered("trades", OutputLimit::default(), Box::new(trades));
+    let gaps = |e: &Event| matches!(e, SequencedEvent::Gap { .. });
+    let (gaps, _) = fan_out.subscribe_filtered("gaps", OutputLimit::default(), Box::new(gaps));
+    let fan_out = fan_out.spawn(receiver).unwrap();
+
+    sequencer.push(itch_block(1, 0, &[(b'A', 5)]));
+    sequencer.push(itch_block(1, 1, &[(b'A', 20)]));
+    sequencer.push(itch_block(1, 2, &[(b'A', 20), (b'P', 3)]));
+    sequencer.push(itch_block(2, 0, &[(b'A', 5)]));
+    // 4 times out straight away, 2 had two messages
+    sequencer.push(itch_block(1, 5, &[(b'A', 7)]));
+    drop(sequencer);
+    fan_out.join().unwrap();
+
+    let all = describe(all.iter().collect());
+    assert_eq!(all, ["1/0", "1/1", "1/2", "2/0", "1/4..5", "1/5"]);
+    let strategy = describe(strategy.iter().collect());
+    assert_eq!(strategy, ["1/0", "1/2", "1/4..5", "1/5"]);
+    // Skipped events never count towards its queue
+    assert_eq!(strategy_lag.stats().max_depth, 4);
+    let trades = describe(trades.iter().collect());
+    assert_eq!(trades, ["1/2", "1/4..5"]);
+    assert_eq!(describe(gaps.iter().collect()), ["1/4..5"]);
+}
+
+#[test]
+fn consumers_subscribe_while_running() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::ZERO);
+    let mut fan_out = FanOut::new();
+    let (journal, _) = fan_out.subscribe("journal", OutputLimit::default());
+    let subscriber = fan_out.subscriber();
+    let fan_out = fan_out.spawn(receiver).unwrap();
+
+    sequencer.push(block(0));
+    assert_eq!(journal.recv().unwrap(), SequencedEvent::Block(block(0)));
+    let (late, lag) = subscriber.subscribe("late", OutputLimit::default());
+    sequencer.push(block(1));
+    drop(sequencer);
+    drop(subscriber);
+    fan_out.join().unwrap();
+
+    assert_eq!(journal.iter().count(), 1);
+    let events: Vec<_> = late.iter().collect();
+    assert_eq!(events, [SequencedEvent::Block(block(1))]);
+    assert_eq!(lag.stats().name, "late");
+}
+
+// Reads a queue of one, reporting what it consumed, so it is never more than
+// a few blocks behind
+fn keep_up(receiver: Receiver<Event>, lag: ConsumerLag) -> JoinHandle<Vec<u64>> {
+    thread::spawn(move || {
+        let mut seqnums = Vec::new();
+        for event in receiver.iter() {
+            lag.consumed(&event);
+            seqnums.extend(event.into_block().map(|b| b.header.seqnum));
+        }
+        seqnums
+    })
+}
+
+fn slow_consumer(policy: SlowConsumerPolicy) -> (Vec<Event>, ConsumerLag, ConsumerLag) {
+    assert_eq!("gap-notify".parse(), Ok(SlowConsumerPolicy::GapNotify));
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    let mut fan_out = FanOut::new();
+    fan_out.set_max_lag(5, policy);
+    let one = OutputLimit {
+        capacity: Some(1),
+        policy: OutputPolicy::Block,
+    };
+    let (fast, fast_lag) = fan_out.subscribe("fast", one);
+    // Never read until the stream ends
+    let (slow, slow_lag) = fan_out.subscribe("slow", OutputLimit::default());
+    let fan_out = fan_out.spawn(receiver).unwrap();
+    let fast = keep_up(fast, fast_lag.clone());
+    for seqnum in 0..20 {
+        sequencer.push(block(seqnum));
+    }
+    drop(sequencer);
+    fan_out.join().unwrap();
+    assert_eq!(fast.join().unwrap(), (0..20).collect::<Vec<_>>());
+    let stats = fast_lag.stats();
+    assert_eq!(
+        (stats.watermark, stats.behind, stats.evicted),
+        (20, 0, false)
+    );
+    (slow.iter().collect(), slow_lag, fast_lag)
+}
+
+#[test]
+fn slow_consumers_are_evicted() {
+    let (events, slow_lag, _) = slow_consumer(SlowConsumerPolicy::Disconnect);
+    let expected: Vec<Event> = (0..6).map(|s| SequencedEvent::Block(block(s))).collect();
+    assert_eq!(events, expected);
+    let stats = slow_lag.stats();
+    assert!(stats.evicted);
+    assert_eq!((stats.watermark, stats.behind), (0, 6));
+}
+
+#[test]
+fn slow_consumers_can_be_sent_gaps_instead() {
+    let (events, slow_lag, _) = slow_consumer(SlowConsumerPolicy::GapNotify);
+    let mut expected: Vec<Event> = (0..6).map(|s| SequencedEvent::Block(block(s))).collect();
+    expected.extend((6..20).map(|s| SequencedEvent::Gap {
+        channel: 0,
+        from: s,
+        to: s + 1,
+        reason: GapReason::Lagged,
+    }));
+    assert_eq!(events, expected);
+    let stats = slow_lag.stats();
+    assert!(!stats.evicted);
+    assert_eq!((stats.behind, stats.downgraded), (6, 14));
+    // Caught up once it consumes what it was sent
+    for event in &events {
+        slow_lag.consumed(event);
+    }
+    assert_eq!(
+        (slow_lag.stats().behind, slow_lag.stats().watermark),
+        (0, 6)
+    );
+}
@@ -0,0 +1,244 @@
+use sequencer::protocol::fast::{self, Field, Messages, Templates, Value};
+use sequencer::protocol::{ParseError, Protocol};
+use std::sync::Arc;
+
+const TEMPLATES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Incremental refresh with a shared header -->
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+  <template name="Header" id="100">
+    <string name="MessageType" id="35"><constant value="X"/></string>
+    <uInt32 name="MsgSeqNum" id="34"><increment/></uInt32>
+    <uInt64 name="SendingTime" id="52"><copy/></uInt64>
+  </template>
+  <template name="Refresh" id="2">
+    <templateRef name="Header"/>
+    <sequence name="MDEntries">
+      <length name="NoMDEntries" id="268"/>
+      <uInt32 name="MDUpdateAction" id="279"><copy value="0"/></uInt32>
+      <string name="Symbol" id="55" presence="optional"><copy/></string>
+      <decimal name="MDEntryPx" id="270" presence="optional"><delta/></decimal>
+      <int64 name="MDEntrySize" id="271" presence="optional"/>
+    </sequence>
+  </template>
+</templates>
+"#;
+
+fn templates() -> Templates {
+    Templates::parse(TEMPLATES).unwrap()
+}
+
+// A Refresh with two entries, the second copying the first's action and
+// symbol and a delta from its price
+fn refresh(buf: &mut Vec<u8>, seqnum: u64) {
+    fast::write_pmap(buf, &[true, true, true]);
+    fast::write_uint(buf, 2);
+    fast::write_uint(buf, seqnum);
+    fast::write_uint(buf, 1_700_000_000);
+    fast::write_uint(buf, 2);
+
+    fast::write_pmap(buf, &[true, true]);
+    fast::write_uint(buf, 1);
+    fast::write_ascii(buf, "SBER");
+    fast::write_int(buf, -2);
+    fast::write_int(buf, 31_050);
+    // Nullable, so one more than the size
+    fast::write_int(buf, 11);
+
+    fast::write_pmap(buf, &[false, false]);
+    fast::write_int(buf, 1);
+    fast::write_int(buf, 5);
+    fast::write_int(buf, 0);
+}
+
+// A Refresh with no entries, everything in the header from the dictionary
+fn empty_refresh(buf: &mut Vec<u8>) {
+    fast::write_pmap(buf, &[false, false, false]);
+    fast::write_uint(buf, 0);
+}
+
+fn field<'a>(fields: &'a [Field], name: &str) -> Option<&'a Value> {
+    fields.iter().find(|f| f.name == name).map(|f| &f.value)
+}
+
+#[test]
+fn decodes_messages_with_their_operators() {
+    let templates = templates();
+    assert_eq!(templates.len(), 2);
+    let mut buf = Vec::new();
+    refresh(&mut buf, 100);
+    empty_refresh(&mut buf);
+    let msgs: Vec<_> = Messages::new(&templates, &buf)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(msgs.len(), 2);
+
+    let msg = &msgs[0];
+    assert_eq!(msg.template_id, 2);
+    assert_eq!(msg.get("MessageType"), Some(&Value::Str("X".to_string())));
+    assert_eq!(msg.seqnum(), Some(100));
+    let entries = match msg.get("MDEntries") {
+        Some(Value::Sequence(entries)) => entries,
+        other => panic!("not a sequence: {:?}", other),
+    };
+    assert_eq!(entries.len(), 2);
+    let px = |exponent, mantissa| Value::Decimal { exponent, mantissa };
+    assert_eq!(field(&entries[0], "MDUpdateAction"), Some(&Value::UInt(1)));
+    assert_eq!(field(&entries[0], "MDEntryPx"), Some(&px(-2, 31_050)));
+    assert_eq!(field(&entries[0], "MDEntrySize"), Some(&Value::Int(10)));
+    assert_eq!(field(&entries[1], "MDUpdateAction"), Some(&Value::UInt(1)));
+    assert_eq!(
+        field(&entries[1], "Symbol"),
+        Some(&Value::Str("SBER".to_string()))
+    );
+    assert_eq!(field(&entries[1], "MDEntryPx"), Some(&px(-2, 31_055)));
+    assert_eq!(field(&entries[1], "MDEntrySize"), None);
+
+    // Incremented and copied from the message before
+    let msg = &msgs[1];
+    assert_eq!(msg.seqnum(), Some(101));
+    assert_eq!(msg.get("SendingTime"), Some(&Value::UInt(1_700_000_000)));
+    assert_eq!(msg.get("MDEntries"), Some(&Value::Sequence(vec![])));
+}
+
+#[test]
+fn integers_round_trip() {
+    let xml = r#"<templates>
+      <template name="Ints" id="1">
+        <int64 name="Signed"/>
+        <uInt64 name="Unsigned"/>
+      </template>
+    </templates>"#;
+    let templates = Templates::parse(xml).unwrap();
+    let signed = [0, 1, -1, 63, 64, -64, -65, 8191, -8192, i64::MAX, i64::MIN];
+    let unsigned = [0, 1, 127, 128, 16_383, 16_384, u64::MAX];
+    for (s, u) in signed.iter().zip(unsigned.iter().cycle()) {
+        let mut buf = Vec::new();
+        fast::write_pmap(&mut buf, &[true]);
+        fast::write_uint(&mut buf, 1);
+        fast::write_int(&mut buf, *s);
+        fast::write_uint(&mut buf, *u);
+        let msg = Messages::new(&templates, &buf).next().unwrap().unwrap();
+        assert_eq!(msg.get("Signed"), Some(&Value::Int(*s)));
+        assert_eq!(msg.get("Unsigned"), Some(&Value::UInt(*u)));
+    }
+    // Too big for a u64
+    let mut buf = Vec::new();
+    fast::write_pmap(&mut buf, &[true]);
+    fast::write_uint(&mut buf, 1);
+    fast::write_int(&mut buf, 0);
+    buf.extend_from_slice(&[0x7f; 10]);
+    buf.push(0xff);
+    let last = Messages::new(&templates, &buf).next().unwrap();
+    assert_eq!(last, Err(ParseError::Invalid));
+}
+
+#[test]
+fn sequences_by_the_first_messages_seqnum() {
+    let templates = templates();
+    let mut buf = Vec::new();
+    refresh(&mut buf, 100);
+    empty_refresh(&mut buf);
+    empty_refresh(&mut buf);
+    let protocol = Protocol::Fast(Some(Arc::new(templates.clone())));
+    let (header, payload) = protocol.parse(&buf).unwrap();
+    assert_eq!((header.seqnum, header.n_messages), (100, 3));
+    assert_eq!(payload, &buf[..]);
+
+    // Dictionaries start over with each packet, so a packet can't lean on
+    // the one before
+    let mut alone = Vec::new();
+    empty_refresh(&mut alone);
+    assert_eq!(
+        protocol.parse_checked(&alone).unwrap_err(),
+        ParseError::Invalid
+    );
+    let truncated = &buf[..buf.len() - 3];
+    assert!(fast::parse_with(&templates, truncated).is_err());
+}
+
+#[test]
+fn sequences_by_a_packet_prefix() {
+    assert_eq!("fast".parse(), Ok(Protocol::Fast(None)));
+    let mut buf = 7_u32.to_le_bytes().to_vec();
+    refresh(&mut buf, 100);
+    let (header, payload) = Protocol::Fast(None).parse(&buf).unwrap();
+    assert_eq!((header.seqnum, header.n_messages), (7, 1));
+    assert_eq!(payload, &buf[fast::HEADER_LEN..]);
+    assert_eq!(Protocol::Fast(None).first_seqnum(), 1);
+
+    let templates = templates();
+    let msgs: Vec<_> = Messages::new(&templates, payload).collect();
+    assert_eq!(msgs[0].as_ref().unwrap().seqnum(), Some(100));
+    assert_eq!(
+        Protocol::Fast(None).parse_checked(&[1, 2]).unwrap_err(),
+        ParseError::Truncated
+    );
+}
+
+#[test]
+fn rejects_bad_templates() {
+    let bad = [
+        r#"<templates><template name="A"><uInt32 name="X"/></template></templates>"#,
+        r#"<templates><template name="A" id="1"><float name="X"/></template></templates>"#,
+        r#"<templates><template name="A" id="1"><templateRef/></template></templates>"#,
+        r#"<templates><template name="A" id="1"><templateRef name="B"/></template></templates>"#,
+        r#"<templates><template name="A" id="1"><uInt32 name="X"><constant/></uInt32></template></templates>"#,
+        r#"<templates><template name="A" id="1"><string name="X"><increment/></string></template></templates>"#,
+        r#"<templates><template name="A" id="1"></templates>"#,
+        r#"<templates><template name="A" id="1"/><template name="B" id="1"/></templates>"#,
+    ];
+    for xml in bad {
+        assert!(Templates::parse(xml).is_err(), "{}", xml);
+    }
+}
+
+#[test]
+fn decodes_groups_tails_and_decimal_parts() {
+    let xml = r#"<templates>
+      <template name="Parts" id="3">
+        <uInt32 name="MsgSeqNum" id="34"/>
+        <byteVector name="Data" presence="optional"><tail/></byteVector>
+        <decimal name="Size">
+          <exponent><default value="0"/></exponent>
+          <mantissa><delta/></mantissa>
+        </decimal>
+        <group name="Extra" presence="optional">
+          <int32 name="Flags"><default value="1"/></int32>
+        </group>
+      </template>
+    </templates>"#;
+    let templates = Templates::parse(xml).unwrap();
+    let mut buf = Vec::new();
+    fast::write_pmap(&mut buf, &[true, true, false, true]);
+    fast::write_uint(&mut buf, 3);
+    fast::write_uint(&mut buf, 5);
+    fast::write_uint(&mut buf, 4);
+    buf.extend_from_slice(b"abc");
+    fast::write_int(&mut buf, 100);
+    fast::write_pmap(&mut buf, &[false]);
+
+    fast::write_pmap(&mut buf, &[false, true, true, false]);
+    fast::write_uint(&mut buf, 6);
+    fast::write_uint(&mut buf, 3);
+    buf.extend_from_slice(b"xy");
+    fast::write_int(&mut buf, 2);
+    fast::write_int(&mut buf, -1);
+
+    let msgs: Vec<_> = Messages::new(&templates, &buf)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let size = |exponent, mantissa| Some(Value::Decimal { exponent, mantissa });
+    assert_eq!(msgs[0].get("Data"), Some(&Value::Bytes(b"abc".to_vec())));
+    assert_eq!(msgs[0].get("Size"), size(0, 100).as_ref());
+    let flags = Field {
+        name: "Flags".to_string(),
+        id: None,
+        value: Value::Int(1),
+    };
+    assert_eq!(msgs[0].get("Extra"), Some(&Value::Group(vec![flags])));
+    // The tail replaces the end of what came before
+    assert_eq!(msgs[1].seqnum(), Some(6));
+    assert_eq!(msgs[1].get("Data"), Some(&Value::Bytes(b"axy".to_vec())));
+    assert_eq!(msgs[1].get("Size"), size(2, 99).as_ref());
+    assert_eq!(msgs[1].get("Extra"), None);
+}
@@ -0,0 +1,117 @@
+use sequencer::clock::MockClock;
+use sequencer::metrics::FeedMetrics;
+use sequencer::protocol::moldudp64::{self, write_header, write_message};
+use sequencer::protocol::Protocol;
+use sequencer::udp::{FeedConfig, UdpFeed};
+use sequencer::{Block, GapReason, SequencedEvent, Sequencer};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+
+const SESSION: [u8; 10] = *b"SESSION001";
+
+// A packet counting `count` messages of which `msgs` are written
+fn packet(seqnum: u64, count: u16, msgs: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_header(&mut buf, &SESSION, seqnum, count);
+    for msg in msgs {
+        write_message(&mut buf, msg);
+    }
+    buf
+}
+
+#[test]
+fn truncated_payload_counts_the_messages_present() {
+    let mut buf = packet(10, 3, &[b"ab", b"cde"]);
+    let whole = buf.len() - moldudp64::HEADER_LEN;
+    // Half of the third message
+    buf.extend_from_slice(&[0, 4, b'f']);
+    let p = moldudp64::parse(&buf).unwrap();
+    assert_eq!(p.header.n_messages, 2);
+    assert_eq!(p.missing, 1);
+    assert_eq!(p.payload.len(), whole);
+    assert_eq!(p.trailing, 3);
+    assert!(p.malformed());
+    let msgs: Vec<_> = p.messages().map(|m| m.unwrap()).collect();
+    assert_eq!(msgs, vec![&b"ab"[..], &b"cde"[..]]);
+}
+
+#[test]
+fn bytes_past_the_count_are_left_out() {
+    let buf = packet(10, 1, &[b"ab", b"cde"]);
+    let p = Protocol::MoldUdp64.parse_checked(&buf).unwrap();
+    assert_eq!(p.header.n_messages, 1);
+    assert_eq!((p.missing, p.trailing), (0, 5));
+    assert_eq!(p.payload, &[0, 2, b'a', b'b']);
+
+    let whole = packet(10, 2, &[b"ab", b"cde"]);
+    assert!(!Protocol::MoldUdp64
+        .parse_checked(&whole)
+        .unwrap()
+        .malformed());
+    assert!(Protocol::MoldUdp64.parse_checked(&[0; 5]).is_err());
+}
+
+#[test]
+fn missing_messages_are_a_gap() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_clock(Box::new(clock.clone()));
+    let truncated = &packet(1, 3, &[b"a"])[..];
+    for buf in [truncated, &packet(4, 1, &[b"d"])] {
+        let (header, payload) = Protocol::MoldUdp64.parse(buf).unwrap();
+        sequencer.push(Block::new(header, payload.to_vec()));
+    }
+    clock.advance(Duration::from_millis(11));
+    sequencer.poll_timeouts();
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    let seqnums: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            SequencedEvent::Block(b) => Some(b.header.seqnum),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(seqnums, vec![1, 4]);
+    assert!(events.contains(&SequencedEvent::Gap {
+        channel: 0,
+        from: 2,
+        to: 4,
+        reason: GapReason::Timeout
+    }));
+}
+
+#[test]
+fn feed_counts_malformed_datagrams() {
+    let config = FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 4),
+        port: 0,
+        protocol: Protocol::MoldUdp64,
+        channel: 0,
+        source: None,
+        reuse_port: true,
+        recv_buffer: None,
+    };
+    let mut feed = UdpFeed::join(&config).unwrap();
+    feed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let metrics = Arc::<FeedMetrics>::default();
+    feed.set_metrics(Arc::clone(&metrics));
+    let dst = (config.group, feed.local_addr().unwrap().port());
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.set_multicast_loop_v4(true).unwrap();
+    sender.send_to(&packet(1, 1, &[b"a"]), dst).unwrap();
+    sender.send_to(&packet(2, 4, &[b"b"]), dst).unwrap();
+    sender.send_to(b"short", dst).unwrap();
+
+    let whole = feed.recv().unwrap().unwrap();
+    assert_eq!(whole.header.n_messages, 1);
+    let partial = feed.recv().unwrap().unwrap();
+    assert_eq!((partial.header.seqnum, partial.header.n_messages), (2, 1));
+    assert!(feed.recv().unwrap().is_none());
+    assert_eq!(metrics.malformed.load(Relaxed), 2);
+    assert_eq!(metrics.truncated.load(Relaxed), 3);
+}
@@ -0,0 +1,163 @@
+use sequencer::clock::MockClock;
+use sequencer::gapfill::GapFiller;
+use sequencer::gaps::{self, GapRecord, ReportFormat, Resolution};
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, Sequencer};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+const MS: Duration = Duration::from_millis(1);
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+// (seqnums, duration, resolution) of each gap
+fn summary(gaps: &[GapRecord]) -> Vec<(Range<u64>, Duration, Option<Resolution>)> {
+    gaps.iter()
+        .map(|g| (g.seqnums.clone(), g.duration, g.resolution))
+        .collect()
+}
+
+// Has every seqnum asked for
+struct Retransmitter;
+
+impl GapFiller<Block<Vec<u8>>> for Retransmitter {
+    fn fill(&mut self, _channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Vec<u8>>>> {
+        Ok(range.map(block).collect())
+    }
+}
+
+#[test]
+fn records_how_and_when_each_gap_was_resolved() {
+    let clock = MockClock::new();
+    let (mut sequencer, _receiver) = Sequencer::new(MS * 100);
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.record_gaps();
+    sequencer.push(block(0));
+    sequencer.push(block(3));
+    // Partly filled, so still open
+    clock.advance(MS * 5);
+    sequencer.push(block(1));
+    assert_eq!(summary(&sequencer.gaps()), [(1..3, MS * 5, None)]);
+    sequencer.push(block(2));
+
+    // Never arrives
+    sequencer.push(block(5));
+    // Not a gap of its own, the one before it is still open
+    sequencer.push(block(7));
+    clock.advance(MS * 101);
+    sequencer.poll_timeouts();
+
+    let abandoned = Some(Resolution::Abandoned(GapReason::Timeout));
+    assert_eq!(
+        summary(&sequencer.gaps()),
+        [
+            (1..3, MS * 5, Some(Resolution::Recovered)),
+            (4..5, MS * 101, abandoned),
+            (6..7, MS * 101, abandoned),
+        ]
+    );
+    assert_eq!(sequencer.seqnum(0), 8);
+}
+
+#[test]
+fn only_recorded_when_asked_and_until_cleared() {
+    let (mut sequencer, _receiver) = Sequencer::new(MS * 100);
+    sequencer.push(block(0));
+    sequencer.push(block(2));
+    sequencer.push(block(1));
+    assert!(!sequencer.records_gaps());
+    assert_eq!(sequencer.gaps(), []);
+
+    sequencer.record_gaps();
+    sequencer.push(block(4));
+    sequencer.push(block(3));
+    sequencer.push(block(6));
+    sequencer.clear_gaps();
+    // Still open
+    assert_eq!(summary(&sequencer.gaps()).len(), 1);
+    assert_eq!(sequencer.gaps()[0].seqnums, 5..6);
+}
+
+#[test]
+fn retransmitted_gaps_are_told_apart() {
+    let clock = MockClock::new();
+    let (mut sequencer, _receiver) = Sequencer::new(MS * 10);
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.record_gaps();
+    sequencer.set_gap_filler(Box::new(Retransmitter));
+    sequencer.push(block(0));
+    sequencer.push(block(4));
+    clock.advance(MS * 11);
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(0), 5);
+
+    // Cut short by a shutdown
+    sequencer.push(block(7));
+    sequencer.drain();
+    let shutdown = Some(Resolution::Abandoned(GapReason::Shutdown));
+    assert_eq!(
+        summary(&sequencer.gaps()),
+        [
+            (1..4, MS * 11, Some(Resolution::Retransmitted)),
+            (5..7, Duration::ZERO, shutdown),
+        ]
+    );
+}
+
+#[test]
+fn writes_csv_and_json() {
+    let gaps = vec![
+        GapRecord {
+            channel: 2,
+            seqnums: 10..12,
+            detected: UNIX_EPOCH + Duration::new(1_700_000_000, 5_000),
+            duration: Duration::from_micros(1_500),
+            resolution: Some(Resolution::Abandoned(GapReason::Overflow)),
+        },
+        GapRecord {
+            channel: 2,
+            seqnums: 20..21,
+            detected: UNIX_EPOCH + Duration::from_secs(1_700_000_001),
+            duration: MS,
+            resolution: None,
+        },
+    ];
+    let mut csv = Vec::new();
+    gaps::write_report(&gaps, ReportFormat::Csv, &mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "channel,from,to,detected,duration_us,resolution,reason\n\
+         2,10,12,1700000000.000005000,1500,abandoned,overflow\n\
+         2,20,21,1700000001.000000000,1000,open,\n"
+    );
+    let mut json = Vec::new();
+    gaps::write_report(&gaps, ReportFormat::Json, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("[\n  {\"channel\":2,\"from\":10,\"to\":12,"));
+    assert!(json.contains("\"resolution\":\"abandoned\",\"reason\":\"overflow\"},\n"));
+    assert!(json.ends_with("\"resolution\":\"open\",\"reason\":\"\"}\n]\n"));
+
+    assert_eq!(
+        ReportFormat::for_path(Path::new("gaps.JSON")),
+        ReportFormat::Json
+    );
+    assert_eq!(
+        ReportFormat::for_path(Path::new("gaps.txt")),
+        ReportFormat::Csv
+    );
+
+    let path = std::env::temp_dir().join(format!("gaps-{}.csv", std::process::id()));
+    gaps::export(&gaps, &path).unwrap();
+    let exported = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(exported.lines().count(), 3);
+}
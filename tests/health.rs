@@ -0,0 +1,130 @@
+use sequencer::clock::MockClock;
+use sequencer::health::{Health, HealthConfig, HealthFile, HealthServer};
+use sequencer::{Block, BlockHeader, Sequencer};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const FEED_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![0])
+}
+
+fn sequencer() -> (Sequencer<Block<Vec<u8>>>, MockClock) {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    // Events aren't looked at
+    thread::spawn(move || receiver.iter().count());
+    let clock = MockClock::new();
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_feed_timeout(Some(FEED_TIMEOUT));
+    (sequencer, clock)
+}
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).unwrap();
+    res
+}
+
+#[test]
+fn not_ready_while_a_feed_is_down_or_the_buffer_is_full() {
+    let (mut sequencer, clock) = sequencer();
+    let config = HealthConfig {
+        max_buffered: 2,
+        ..Default::default()
+    };
+    let health = Health::new(sequencer.metrics(), config);
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    sequencer.push_from(a, block(0));
+    sequencer.push_from(b, block(0));
+    sequencer.poll_liveness();
+    let status = health.check();
+    assert!(status.ready(), "{}", status);
+    assert_eq!(status.to_string(), "ok");
+
+    clock.advance(FEED_TIMEOUT * 2);
+    sequencer.push_from(a, block(1));
+    // Seqnum 2 is missing
+    sequencer.push_from(a, block(3));
+    sequencer.push_from(a, block(4));
+    sequencer.poll_liveness();
+    let status = health.check();
+    assert!(status.live);
+    assert!(!status.ready());
+    assert_eq!(status.problems, ["1 feeds down", "2 blocks buffered"]);
+
+    sequencer.push_from(b, block(2));
+    sequencer.poll_liveness();
+    assert!(health.check().ready());
+}
+
+#[test]
+fn not_live_once_polls_stop() {
+    let (mut sequencer, _clock) = sequencer();
+    let config = HealthConfig {
+        stall: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let health = Health::new(sequencer.metrics(), config);
+    sequencer.poll_liveness();
+    assert!(health.check().live);
+    thread::sleep(Duration::from_millis(100));
+    let status = health.check();
+    assert!(!status.live && !status.ready());
+    assert!(status.problems[0].starts_with("arbiter stalled"));
+    sequencer.poll_liveness();
+    assert!(health.check().live);
+}
+
+#[test]
+fn serves_liveness_and_readiness_over_http() {
+    let (mut sequencer, clock) = sequencer();
+    let health = Health::new(sequencer.metrics(), HealthConfig::default());
+    let server = HealthServer::bind("127.0.0.1:0".parse().unwrap(), health).unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn().unwrap();
+    let feed = sequencer.add_feed();
+    sequencer.push_from(feed, block(0));
+    sequencer.poll_liveness();
+
+    let ready = get(addr, "/ready");
+    assert!(ready.starts_with("HTTP/1.1 200 OK\r\n"), "{}", ready);
+    assert!(ready.ends_with("\r\n\r\nok\n"));
+    assert!(get(addr, "/").starts_with("HTTP/1.1 200"));
+    assert!(get(addr, "/metrics").starts_with("HTTP/1.1 404"));
+
+    clock.advance(FEED_TIMEOUT * 2);
+    sequencer.poll_liveness();
+    let ready = get(addr, "/ready");
+    assert!(ready.starts_with("HTTP/1.1 503"), "{}", ready);
+    assert!(ready.ends_with("1 feeds down\n"));
+    assert!(get(addr, "/live").starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn heartbeat_file_is_removed_while_not_ready() {
+    let path = std::env::temp_dir().join(format!("health-{}", std::process::id()));
+    let (mut sequencer, clock) = sequencer();
+    let health = Health::new(sequencer.metrics(), HealthConfig::default());
+    let file = HealthFile::new(&path, health, Duration::from_secs(1));
+    let feed = sequencer.add_feed();
+    sequencer.push_from(feed, block(0));
+    sequencer.poll_liveness();
+    assert!(file.beat().unwrap());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "ok\n");
+
+    clock.advance(FEED_TIMEOUT * 2);
+    sequencer.poll_liveness();
+    assert!(!file.beat().unwrap());
+    assert!(!path.exists());
+    assert!(!file.beat().unwrap());
+}
@@ -0,0 +1,73 @@
+use sequencer::hugepages::{HugePages, Region};
+use sequencer::pool::BufferPool;
+use sequencer::shm::{ShmEvent, ShmReader, ShmSink};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader};
+
+#[test]
+fn parses_and_rounds_to_page_sizes() {
+    assert_eq!("2m".parse(), Ok(HugePages::TwoMb));
+    assert_eq!("1g".parse(), Ok(HugePages::OneGb));
+    assert_eq!("off".parse(), Ok(HugePages::Off));
+    assert!("4k".parse::<HugePages>().is_err());
+    assert_eq!(HugePages::TwoMb.round_up(1), 2 << 20);
+    assert_eq!(HugePages::TwoMb.round_up(2 << 20), 2 << 20);
+    assert_eq!(HugePages::Off.round_up(5_000), 8 << 10);
+
+    // Huge pages if reserved, ordinary ones if not
+    let region = Region::new(1_000, HugePages::TwoMb).unwrap();
+    match region.is_huge() {
+        true => assert_eq!(region.len(), 2 << 20),
+        false => assert_eq!(region.len(), 4 << 10),
+    }
+    assert!(!Region::new(1_000, HugePages::Off).unwrap().is_huge());
+}
+
+#[test]
+fn pool_buffers_share_one_region() {
+    let pool = BufferPool::with_huge_pages(4, 1_000, HugePages::TwoMb);
+    let mut bufs: Vec<_> = (0..4).map(|_| pool.get()).collect();
+    assert_eq!((pool.free(), pool.allocated()), (0, 4));
+    for (i, buf) in bufs.iter_mut().enumerate() {
+        assert_eq!(buf.len(), 1_000);
+        assert!(buf.iter().all(|b| *b == 0));
+        buf.fill(i as u8);
+    }
+    // None overlaps another
+    for (i, buf) in bufs.iter().enumerate() {
+        assert!(buf.iter().all(|b| *b == i as u8));
+    }
+    let payload = bufs.pop().unwrap().into_payload(10..20);
+    assert_eq!(payload, [3; 10]);
+    drop(bufs);
+    assert_eq!(pool.free(), 3);
+    drop(payload);
+    assert_eq!(pool.free(), 4);
+
+    // Dry, it grows on the heap as before
+    let bufs: Vec<_> = (0..5).map(|_| pool.get()).collect();
+    assert_eq!(pool.allocated(), 5);
+    drop(bufs);
+    assert_eq!(pool.free(), 4);
+}
+
+#[test]
+fn shm_rings_are_whole_huge_pages() {
+    let path = std::env::temp_dir().join(format!("sequencer-shm-huge-{}", std::process::id()));
+    let mut sink = ShmSink::with_huge_pages(&path, 16, 64, HugePages::TwoMb).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 << 20);
+    let mut reader = ShmReader::open(&path).unwrap();
+    let header = BlockHeader {
+        channel: 1,
+        seqnum: 7,
+        n_messages: 1,
+        ..Default::default()
+    };
+    sink.on_block(&Block::new(header, b"abc".to_vec().into()))
+        .unwrap();
+    match reader.try_read() {
+        Some(ShmEvent::Block(r)) => assert_eq!((r.seqnum, &r.payload[..]), (7, &b"abc"[..])),
+        e => panic!("unexpected event {:?}", e),
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,227 @@
+use crossbeam_channel::unbounded;
+use sequencer::ingress::{Ingress, IngressOrder, IngressSocket, Input, RoundRobin};
+use sequencer::shutdown::Shutdown;
+use sequencer::Sequencer;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn input(gateway: usize, index: u64, arrived: Instant, payload: &[u8]) -> Input {
+    Input {
+        gateway,
+        index,
+        priority: 0,
+        arrived,
+        payload: payload.into(),
+    }
+}
+
+fn prioritized(priority: u8, input: Input) -> Input {
+    Input { priority, ..input }
+}
+
+fn payloads(ingress: &mut Ingress, inputs: Vec<Input>) -> Vec<(u64, Vec<u8>)> {
+    ingress
+        .sequence(inputs)
+        .into_iter()
+        .map(|b| (b.header.seqnum, b.payload.to_vec()))
+        .collect()
+}
+
+#[test]
+fn numbers_inputs_as_they_arrived() {
+    assert_eq!("priority".parse(), Ok(IngressOrder::Priority));
+    assert!("random".parse::<IngressOrder>().is_err());
+    let t = Instant::now();
+    let ms = Duration::from_millis(1);
+    let mut ingress = Ingress::new(3, 1);
+    let inputs = vec![
+        input(0, 1, t + ms, b"b"),
+        input(1, 0, t + 2 * ms, b"c"),
+        input(0, 0, t, b"a"),
+    ];
+    assert_eq!(
+        payloads(&mut ingress, inputs),
+        [(1, b"a".to_vec()), (2, b"b".to_vec()), (3, b"c".to_vec())]
+    );
+    let blocks = ingress.sequence(vec![input(0, 2, t, b"d")]);
+    assert_eq!(blocks[0].header.channel, 3);
+    assert_eq!(
+        (blocks[0].header.seqnum, blocks[0].header.n_messages),
+        (4, 1)
+    );
+    assert_eq!(ingress.seqnum(), 5);
+}
+
+#[test]
+fn higher_priorities_go_first() {
+    let t = Instant::now();
+    let ms = Duration::from_millis(1);
+    let mut ingress = Ingress::new(0, 10);
+    ingress.set_policy(IngressOrder::Priority.policy());
+    let inputs = vec![
+        prioritized(0, input(0, 0, t, b"a")),
+        prioritized(2, input(1, 0, t + ms, b"b")),
+        prioritized(1, input(2, 0, t + ms, b"c")),
+        prioritized(2, input(1, 1, t + 2 * ms, b"d")),
+    ];
+    assert_eq!(
+        payloads(&mut ingress, inputs),
+        [
+            (10, b"b".to_vec()),
+            (11, b"d".to_vec()),
+            (12, b"c".to_vec()),
+            (13, b"a".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn received_inputs_are_sequenced() {
+    let shutdown = Shutdown::default();
+    let (input_sender, inputs) = unbounded();
+    let mut sockets = Vec::new();
+    let mut threads = Vec::new();
+    for gateway in [0, 1] {
+        let mut socket = IngressSocket::bind("127.0.0.1:0".parse().unwrap(), gateway).unwrap();
+        socket.set_shutdown(shutdown.clone()).unwrap();
+        sockets.push(socket.local_addr().unwrap());
+        let sender = input_sender.clone();
+        threads.push(thread::spawn(move || socket.run(sender)));
+    }
+    drop(input_sender);
+    let (block_sender, blocks) = unbounded();
+    let ingress = thread::spawn(move || Ingress::new(0, 1).run(inputs, block_sender));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for i in 0..10_u8 {
+        client.send_to(&[i], sockets[i as usize % 2]).unwrap();
+    }
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(1);
+    let mut received = Vec::new();
+    for _ in 0..10 {
+        let block = blocks.recv_timeout(Duration::from_secs(5)).unwrap();
+        received.push(block.payload[0]);
+        sequencer.push(block);
+    }
+    shutdown.request();
+    for t in threads {
+        t.join().unwrap().unwrap();
+    }
+    ingress.join().unwrap().unwrap();
+    drop(sequencer);
+
+    // Every input once, in the order they were numbered
+    let mut sorted = received.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+    let sequenced: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| e.into_block())
+        .map(|b| (b.header.seqnum, b.payload[0]))
+        .collect();
+    assert_eq!(sequenced, (1..=10).zip(received).collect::<Vec<_>>());
+}
+
+#[test]
+fn round_robin_takes_turns_across_batches() {
+    assert_eq!("round-robin".parse(), Ok(IngressOrder::RoundRobin));
+    let t = Instant::now();
+    let mut ingress = Ingress::new(0, 1);
+    ingress.set_policy(Box::<RoundRobin>::default());
+    // Gateway 0 is the busiest and the quickest
+    let inputs = vec![
+        input(0, 0, t, b"a0"),
+        input(0, 1, t, b"a1"),
+        input(0, 2, t, b"a2"),
+        input(1, 0, t, b"b0"),
+        input(2, 0, t, b"c0"),
+        input(2, 1, t, b"c1"),
+    ];
+    let order: Vec<_> = payloads(&mut ingress, inputs)
+        .into_iter()
+        .map(|(_, p)| p)
+        .collect();
+    assert_eq!(order, [b"a0", b"b0", b"c0", b"a1", b"c1", b"a2"]);
+    // Gateway 1 goes first after 0 went last
+    let inputs = vec![input(0, 3, t, b"a3"), input(1, 1, t, b"b1")];
+    let order: Vec<_> = payloads(&mut ingress, inputs)
+        .into_iter()
+        .map(|(_, p)| p)
+        .collect();
+    assert_eq!(order, [b"b1", b"a3"]);
+}
+
+#[test]
+fn the_same_inputs_always_get_the_same_order() {
+    let t = Instant::now();
+    let ms = Duration::from_millis(1);
+    // Ties on arrival and priority across gateways and within one
+    let mut inputs = Vec::new();
+    for gateway in 0..3 {
+        for index in 0..4 {
+            let arrived = t + ms * (index as u32 / 2);
+            let payload = [gateway as u8, index as u8];
+            let input = input(gateway, index, arrived, &payload);
+            inputs.push(prioritized((gateway + index as usize) as u8 % 2, input));
+        }
+    }
+    for order in [
+        IngressOrder::Arrival,
+        IngressOrder::RoundRobin,
+        IngressOrder::Priority,
+    ] {
+        let mut expected = None;
+        // Rotations and reversals of the batch, as if received differently
+        for rotation in 0..inputs.len() {
+            for reversed in [false, true] {
+                let mut batch = inputs.clone();
+                batch.rotate_left(rotation);
+                if reversed {
+                    batch.reverse();
+                }
+                let mut ingress = Ingress::new(0, 1);
+                ingress.set_policy(order.policy());
+                let got = payloads(&mut ingress, batch);
+                assert_eq!(
+                    expected.get_or_insert_with(|| got.clone()),
+                    &got,
+                    "{:?}",
+                    order
+                );
+            }
+        }
+        // Ties broken by gateway, then by each gateway's order
+        let got = expected.unwrap();
+        if order == IngressOrder::Arrival {
+            assert_eq!(
+                &got[..3],
+                [(1, vec![0, 0]), (2, vec![0, 1]), (3, vec![1, 0])]
+            );
+        }
+    }
+}
+
+#[test]
+fn priorities_can_come_from_the_inputs() {
+    let shutdown = Shutdown::default();
+    let mut socket = IngressSocket::bind("127.0.0.1:0".parse().unwrap(), 0).unwrap();
+    socket.set_priority(5);
+    socket.set_priority_field(1);
+    socket.set_shutdown(shutdown.clone()).unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (sender, inputs) = unbounded();
+    let thread = thread::spawn(move || socket.run(sender));
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(&[b'a', 9], addr).unwrap();
+    client.send_to(b"b", addr).unwrap();
+    let received: Vec<_> = (0..2)
+        .map(|_| inputs.recv_timeout(Duration::from_secs(5)).unwrap())
+        .map(|i| (i.index, i.priority))
+        .collect();
+    // Too short for the field, the socket's priority
+    assert_eq!(received, [(0, 9), (1, 5)]);
+    shutdown.request();
+    thread.join().unwrap().unwrap();
+}
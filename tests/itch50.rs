@@ -0,0 +1,74 @@
+use sequencer::protocol::itch50::{self, AddOrder, Header, Message, OrderDelete, Side};
+use sequencer::protocol::{moldudp64, ParseError};
+use sequencer::{Block, BlockHeader};
+
+fn header(kind: u8, timestamp: u64) -> Vec<u8> {
+    let mut msg = vec![kind];
+    msg.extend_from_slice(&7_u16.to_be_bytes());
+    msg.extend_from_slice(&0_u16.to_be_bytes());
+    msg.extend_from_slice(&timestamp.to_be_bytes()[2..]);
+    msg
+}
+
+#[test]
+fn decodes_block_messages() {
+    let mut add = header(b'A', 34_200_000_000_123);
+    add.extend_from_slice(&42_u64.to_be_bytes());
+    add.push(b'S');
+    add.extend_from_slice(&100_u32.to_be_bytes());
+    add.extend_from_slice(b"AAPL    ");
+    add.extend_from_slice(&1_500_000_u32.to_be_bytes());
+    let mut delete = header(b'D', 34_200_000_000_456);
+    delete.extend_from_slice(&42_u64.to_be_bytes());
+    let directory = header(b'R', 0);
+    let truncated = header(b'X', 0);
+
+    let mut payload = Vec::new();
+    for msg in [&add, &delete, &directory, &truncated] {
+        moldudp64::write_message(&mut payload, msg);
+    }
+    let block = Block::new(
+        BlockHeader {
+            n_messages: 4,
+            ..Default::default()
+        },
+        payload,
+    );
+
+    let messages: Vec<_> = itch50::messages(&block).collect();
+    assert_eq!(
+        messages[0],
+        Ok(Message::AddOrder(AddOrder {
+            header: Header {
+                stock_locate: 7,
+                tracking_number: 0,
+                timestamp: 34_200_000_000_123,
+            },
+            order_ref: 42,
+            side: Side::Sell,
+            shares: 100,
+            stock: *b"AAPL    ",
+            price: 1_500_000,
+            attribution: None,
+        }))
+    );
+    assert_eq!(
+        messages[1],
+        Ok(Message::OrderDelete(OrderDelete {
+            header: Header {
+                stock_locate: 7,
+                tracking_number: 0,
+                timestamp: 34_200_000_000_456,
+            },
+            order_ref: 42,
+        }))
+    );
+    assert_eq!(
+        messages[2],
+        Ok(Message::Other {
+            kind: b'R',
+            body: &directory[1..],
+        })
+    );
+    assert_eq!(messages[3], Err(ParseError::Truncated));
+}
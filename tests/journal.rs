@@ -0,0 +1,128 @@
+use sequencer::journal::{self, JournalReader, JournalSource, JournalWriter};
+use sequencer::pcap::Speed;
+use sequencer::BlockHeader;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+#[test]
+fn journal_round_trip() {
+    let path = std::env::temp_dir().join(format!("journal-{}.journal", std::process::id()));
+    let session = journal::session("SESSION1");
+    let ts = UNIX_EPOCH + Duration::from_nanos(1_666_000_000_123_456_789);
+    {
+        let mut writer = JournalWriter::create(&path, &session, Some(Duration::ZERO)).unwrap();
+        for seqnum in 0..3 {
+            let header = BlockHeader {
+                channel: 7,
+                seqnum,
+                n_messages: 1,
+                ..Default::default()
+            };
+            writer.append(&header, ts, &[seqnum as u8; 3]).unwrap();
+        }
+    }
+
+    let reader = JournalReader::open(&path).unwrap();
+    assert_eq!(reader.session(), b"SESSION1  ");
+    let records: Vec<_> = reader.map(|r| r.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(records.len(), 3);
+    for (i, r) in records.iter().enumerate() {
+        assert_eq!((r.channel, r.seqnum, r.n_messages), (7, i as u64, 1));
+        assert_eq!(r.ts, ts);
+        assert_eq!(r.payload, vec![i as u8; 3]);
+    }
+}
+
+// Blocks 0..n of channel 3, `gap` apart
+fn recorded(name: &str, n: u64, gap: Duration) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("journal-{}-{}.journal", name, std::process::id()));
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    for seqnum in 0..n {
+        let header = BlockHeader {
+            channel: 3,
+            seqnum,
+            n_messages: 1,
+            ..Default::default()
+        };
+        let ts = UNIX_EPOCH + Duration::from_secs(1_700_000_000) + gap * seqnum as u32;
+        writer.append(&header, ts, &[seqnum as u8]).unwrap();
+    }
+    writer.sync().unwrap();
+    path
+}
+
+#[test]
+fn source_replays_the_journaled_blocks() {
+    let path = recorded("replay", 5, Duration::from_secs(1));
+    let mut source = JournalSource::open(&path).unwrap();
+    source.set_speed(Speed::AsFastAsPossible);
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let started = Instant::now();
+    source.run(&sender).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    std::fs::remove_file(&path).unwrap();
+    let blocks: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(blocks.len(), 5);
+    for (i, block) in blocks.iter().enumerate() {
+        assert_eq!(block.header.channel, 3);
+        assert_eq!(block.header.seqnum, i as u64);
+        assert_eq!(&block.header.session, b"DAY1      ");
+        assert_eq!(&*block.payload, [i as u8]);
+    }
+}
+
+#[test]
+fn source_reproduces_journaled_timing() {
+    // 200ms of records at 4x takes 50ms
+    let path = recorded("timing", 5, Duration::from_millis(50));
+    let mut source = JournalSource::open(&path).unwrap();
+    source.set_speed(Speed::Multiplier(4.0));
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let started = Instant::now();
+    source.run(&sender).unwrap();
+    let took = started.elapsed();
+    std::fs::remove_file(&path).unwrap();
+    assert!(took >= Duration::from_millis(50), "{:?}", took);
+    assert!(took < Duration::from_millis(200), "{:?}", took);
+    assert_eq!(receiver.try_iter().count(), 5);
+}
+
+#[test]
+fn source_stops_at_an_unreadable_record() {
+    let path = recorded("corrupt", 3, Duration::ZERO);
+    let mut buf = std::fs::read(&path).unwrap();
+    // Payload of the second record
+    let record = 4 + journal::RECORD_HEADER_LEN + 1;
+    buf[journal::FILE_HEADER_LEN + 2 * record - 1] ^= 0xff;
+    std::fs::write(&path, buf).unwrap();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let source = JournalSource::open(&path).unwrap();
+    assert!(source.run(&sender).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(receiver.try_iter().count(), 1);
+}
+
+#[test]
+fn reader_rejects_a_corrupt_length_without_allocating_it() {
+    let path = recorded("long", 2, Duration::ZERO);
+    let mut buf = std::fs::read(&path).unwrap();
+    // Length of the second record
+    let at = journal::FILE_HEADER_LEN + 4 + journal::RECORD_HEADER_LEN + 1;
+    buf[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&path, buf).unwrap();
+    let mut reader = JournalReader::open(&path).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    let e = reader.next().unwrap().unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+    // Nor written
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    let payload = vec![0; journal::MAX_PAYLOAD_LEN + 1];
+    let header = BlockHeader::default();
+    assert!(writer.append(&header, UNIX_EPOCH, &payload).is_err());
+    drop(writer);
+    std::fs::remove_file(&path).unwrap();
+}
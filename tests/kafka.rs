@@ -0,0 +1,40 @@
+#![cfg(feature = "kafka")]
+
+use sequencer::kafka::{KafkaConfig, KafkaSink, KafkaStats};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, GapReason};
+use std::time::Duration;
+
+#[test]
+fn undeliverable_records_are_counted_as_failed() {
+    // Nothing listens on port 1
+    let config = KafkaConfig {
+        brokers: "127.0.0.1:1".to_string(),
+        topic: "sequencer-test".to_string(),
+        linger: Duration::ZERO,
+        message_timeout: Duration::from_millis(200),
+    };
+    let mut sink = KafkaSink::create(&config).unwrap();
+    let metrics = sink.metrics();
+    for seqnum in 0..5 {
+        let header = BlockHeader {
+            channel: 1,
+            seqnum,
+            n_messages: 1,
+            ..Default::default()
+        };
+        sink.on_block(&Block::new(header, b"abc".to_vec().into()))
+            .unwrap();
+    }
+    sink.on_gap(1, 5..7, GapReason::Timeout).unwrap();
+    assert_eq!(metrics.stats().sent, 6);
+
+    sink.flush().unwrap();
+    let stats = KafkaStats {
+        sent: 6,
+        delivered: 0,
+        failed: 6,
+        in_flight: 0,
+    };
+    assert_eq!(metrics.stats(), stats);
+}
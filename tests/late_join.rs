@@ -0,0 +1,121 @@
+use sequencer::{Block, BlockHeader, LateJoin, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64, payload: u8) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![payload])
+}
+
+#[test]
+fn first_block_sets_the_start() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_late_join(LateJoin::First);
+    for seqnum in 1000..1003 {
+        sequencer.push(block(seqnum, 0));
+    }
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert!(matches!(
+        events[0],
+        SequencedEvent::SessionReset { seqnum: 1000, .. }
+    ));
+    let seqnums: Vec<_> = events
+        .into_iter()
+        .filter_map(|e| e.into_block())
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, vec![1000, 1001, 1002]);
+    assert_eq!(stats.gaps, 0);
+}
+
+#[test]
+fn off_treats_a_mid_session_start_as_a_gap() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(1));
+    sequencer.push(block(1000, 0));
+    thread::sleep(Duration::from_millis(5));
+    sequencer.poll_timeouts();
+    drop(sequencer);
+
+    let gap = receiver.iter().find_map(|e| match e {
+        SequencedEvent::Gap { from, to, .. } => Some((from, to)),
+        _ => None,
+    });
+    assert_eq!(gap, Some((0, 1000)));
+}
+
+#[test]
+fn max_starts_at_the_furthest_feed() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_late_join(LateJoin::Max(Duration::from_millis(20)));
+    // Feed 1 joined later in the session than feed 0
+    sequencer.push_from(0, block(100, 0));
+    sequencer.push_from(1, block(103, 1));
+    sequencer.push_from(0, block(101, 0));
+    sequencer.push_from(1, block(104, 1));
+    thread::sleep(Duration::from_millis(30));
+    sequencer.poll_timeouts();
+    sequencer.push_from(0, block(102, 0));
+    sequencer.push_from(0, block(103, 0));
+    sequencer.push_from(0, block(104, 0));
+    sequencer.push_from(0, block(105, 0));
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert!(matches!(
+        events[0],
+        SequencedEvent::SessionReset { seqnum: 103, .. }
+    ));
+    let blocks: Vec<_> = events.into_iter().filter_map(|e| e.into_block()).collect();
+    assert_eq!(blocks, vec![block(103, 1), block(104, 1), block(105, 0)]);
+    assert_eq!(stats.gaps, 0);
+}
+
+#[test]
+fn max_window_closes_on_drain() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_late_join(LateJoin::Max(Duration::from_secs(60)));
+    sequencer.push_from(0, block(7, 0));
+    sequencer.push_from(0, block(8, 0));
+    sequencer.drain();
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| e.into_block())
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, vec![7, 8]);
+}
+
+#[test]
+fn explicit_start_seqnum() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(500);
+    sequencer.push(block(501, 0));
+    sequencer.push(block(500, 0));
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| e.into_block())
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, vec![500, 501]);
+}
+
+#[test]
+fn parses_modes() {
+    assert_eq!("off".parse(), Ok(LateJoin::Off));
+    assert_eq!("first".parse(), Ok(LateJoin::First));
+    assert!(matches!("max".parse(), Ok(LateJoin::Max(_))));
+    assert!("last".parse::<LateJoin>().is_err());
+}
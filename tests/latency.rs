@@ -0,0 +1,53 @@
+use sequencer::latency::{Latency, LatencySink};
+use sequencer::sink::{self, Sink};
+use sequencer::{Block, BlockHeader, Payload, Sequencer};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn block(seqnum: u64, received: Option<Instant>) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    let mut b = Block::new(header, Payload::default());
+    b.received = received;
+    b
+}
+
+struct Discard;
+
+impl Sink for Discard {
+    fn on_block(&mut self, _block: &Block<Payload>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn tracks_sequenced_and_consumed_latency() {
+    let latency = Arc::<Latency>::default();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_latency(Arc::clone(&latency));
+    let received = Instant::now() - Duration::from_millis(5);
+    sequencer.push(block(1, Some(received)));
+    sequencer.push(block(0, Some(received)));
+    // Not timestamped, not counted
+    sequencer.push(block(2, None));
+    drop(sequencer);
+    let mut sink = LatencySink::new(Discard, Arc::clone(&latency));
+    assert_eq!(sink::run(receiver, &mut sink).unwrap(), 3);
+
+    let (sequenced, consumed) = (latency.sequenced(), latency.consumed());
+    assert_eq!((sequenced.len(), consumed.len()), (2, 2));
+    assert!(sequenced.min() >= 5_000_000);
+    assert!(consumed.min() >= sequenced.min());
+
+    let path = std::env::temp_dir().join(format!("latency-{}.hgrm", std::process::id()));
+    latency.dump(&path).unwrap();
+    let dump = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(dump.contains("# Received to consumed"));
+    assert!(dump.contains("Total count = 2"));
+    assert!(latency.to_string().starts_with("sequenced n 2 p50 "));
+}
@@ -0,0 +1,96 @@
+use sequencer::clock::MockClock;
+use sequencer::{Block, BlockHeader, Sequencer};
+use std::time::Duration;
+
+fn block(seqnum: u64, session: &[u8; 10]) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        session: *session,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn scores_feeds_against_the_first_copy() {
+    let clock = MockClock::new();
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_clock(Box::new(clock.clone()));
+    let session = b"SESSION001";
+    // A wins every seqnum it has, B trails it by 2ms and wins the one A lost
+    for seqnum in 0..10 {
+        if seqnum != 5 {
+            sequencer.push_from(0, block(seqnum, session));
+        }
+        clock.advance(Duration::from_millis(2));
+        sequencer.push_from(1, block(seqnum, session));
+    }
+    let stats = sequencer.stats();
+    let (a, b) = (&stats.feeds[0], &stats.feeds[1]);
+
+    assert_eq!((a.first, a.late, a.lost, a.messages), (9, 0, 1, 9));
+    assert_eq!((b.first, b.late, b.lost, b.messages), (1, 9, 0, 10));
+    assert_eq!(a.win_rate(), 1.0);
+    assert_eq!(b.win_rate(), 0.1);
+    assert_eq!(a.mean_lateness(), Duration::ZERO);
+    assert_eq!(b.mean_lateness(), Duration::from_millis(2));
+    assert_eq!(a.loss_rate(), 0.1);
+    assert_eq!(b.loss_rate(), 0.0);
+    assert_eq!(
+        stats.line_report().to_string(),
+        "feed 0 won 100.00% late 0ns lost 10.0000% reordered 0.0000% \
+         feed 1 won 10.00% late 2ms lost 0.0000% reordered 0.0000%"
+    );
+}
+
+#[test]
+fn counts_blocks_behind_the_feeds_own_stream() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    let session = b"SESSION001";
+    for seqnum in [0, 2, 1, 3] {
+        sequencer.push(block(seqnum, session));
+    }
+    let feed = &sequencer.stats().feeds[0];
+    assert_eq!(feed.reordered, 1);
+    assert_eq!(feed.reorder_rate(), 0.25);
+    // 1 was skipped when 2 arrived, then came in late
+    assert_eq!(feed.lost, 1);
+}
+
+#[test]
+fn a_new_session_starts_over() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.push_from(0, block(0, b"SESSION001"));
+    sequencer.push_from(1, block(0, b"SESSION002"));
+    let stats = sequencer.stats();
+    assert_eq!(stats.feeds[0].first, 1);
+    assert_eq!(stats.feeds[1].first, 1);
+}
+
+#[test]
+fn tracks_the_worst_reordering_and_recommends_a_timeout() {
+    let clock = MockClock::new();
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_clock(Box::new(clock.clone()));
+    let session = b"SESSION001";
+    assert_eq!(sequencer.stats().recommended_timeout(), None);
+    // 1 comes in 3ms after 2, then 4 and 5 up to 3 seqnums behind and 1ms
+    // after 6
+    sequencer.push(block(0, session));
+    sequencer.push(block(2, session));
+    clock.advance(Duration::from_millis(3));
+    sequencer.push(block(1, session));
+    sequencer.push(block(3, session));
+    sequencer.push(block(6, session));
+    clock.advance(Duration::from_millis(1));
+    sequencer.push(block(4, session));
+    sequencer.push(block(5, session));
+    let stats = sequencer.stats();
+    let feed = &stats.feeds[0];
+    assert_eq!(feed.reordered, 3);
+    assert_eq!(feed.max_reorder_distance, 3);
+    assert_eq!(feed.max_reorder_delay, Duration::from_millis(3));
+    assert_eq!(stats.recommended_timeout(), Some(Duration::from_millis(6)));
+}
@@ -0,0 +1,49 @@
+use sequencer::{Block, BlockHeader, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64, n_messages: u16) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn heartbeat_does_not_advance_seqnum() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.push_from(0, block(0, 1));
+    sequencer.push_from(0, block(1, 0));
+    sequencer.push_from(0, block(1, 1));
+    let stats = sequencer.stats();
+    assert_eq!(sequencer.seqnum(0), 2);
+    drop(sequencer);
+
+    let blocks: Vec<_> = receiver.iter().filter_map(|e| e.into_block()).collect();
+    assert_eq!(blocks, vec![block(0, 1), block(1, 1)]);
+    assert_eq!(stats.feeds[0].heartbeats, 1);
+    assert_eq!(stats.feeds[0].packets, 3);
+}
+
+#[test]
+fn silent_feed_goes_down_and_up() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_feed_timeout(Some(Duration::from_millis(10)));
+    let a = sequencer.add_feed();
+    let b = sequencer.add_feed();
+    thread::sleep(Duration::from_millis(20));
+    sequencer.push_from(a, block(0, 0));
+    sequencer.poll_liveness();
+    // Only reported once per outage
+    sequencer.poll_liveness();
+    sequencer.push_from(b, block(0, 0));
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0], SequencedEvent::FeedDown { feed, .. } if feed == b));
+    assert_eq!(events[1], SequencedEvent::FeedUp { feed: b });
+}
@@ -0,0 +1,50 @@
+use sequencer::protocol::mdp3::{self, Message};
+use sequencer::protocol::{ParseError, Protocol};
+use sequencer::{Block, Sequencer};
+use std::time::Duration;
+
+fn packet(seqnum: u32, templates: &[u16]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    mdp3::write_header(&mut buf, seqnum, 1_700_000_000_000_000_000);
+    for template_id in templates {
+        let msg = Message {
+            block_length: 4,
+            template_id: *template_id,
+            schema_id: 1,
+            version: 9,
+            body: &[1, 2, 3, 4],
+        };
+        mdp3::write_message(&mut buf, &msg);
+    }
+    buf
+}
+
+#[test]
+fn parses_packet_header_and_messages() {
+    let buf = packet(7, &[46, 32]);
+    let p = mdp3::parse(&buf).unwrap();
+    assert_eq!(p.header.seqnum, 7);
+    assert_eq!(p.header.n_messages, 1);
+    assert_eq!(p.sending_time, 1_700_000_000_000_000_000);
+    let templates: Vec<_> = p.messages().map(|m| m.unwrap().template_id).collect();
+    assert_eq!(templates, vec![46, 32]);
+
+    let truncated = &buf[..buf.len() - 1];
+    let last = mdp3::parse(truncated).unwrap().messages().last().unwrap();
+    assert_eq!(last, Err(ParseError::Truncated));
+}
+
+#[test]
+fn channels_sequence_independently() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(Protocol::Mdp3.first_seqnum());
+    // Packet seqnums start at 1 and count packets regardless of messages
+    for (channel, seqnum) in [(310, 1), (310, 2), (311, 1), (310, 4), (311, 2), (310, 3)] {
+        let buf = packet(seqnum, &[46, 46, 46]);
+        let (mut header, payload) = Protocol::Mdp3.parse(&buf).unwrap();
+        header.channel = channel;
+        sequencer.push(Block::new(header, payload.to_vec()));
+    }
+    assert_eq!(sequencer.seqnum(310), 5);
+    assert_eq!(sequencer.seqnum(311), 3);
+}
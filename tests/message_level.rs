@@ -0,0 +1,161 @@
+use sequencer::message_level::{Framing, MessageSink};
+use sequencer::protocol::{pitch, Protocol};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, Payload};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+// MoldUDP64 framed messages
+fn framed(msgs: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for msg in msgs {
+        buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        buf.extend_from_slice(msg);
+    }
+    buf
+}
+
+fn block(seqnum: u64, n_messages: u16, payload: Payload) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 3,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    };
+    Block::new(header, payload)
+}
+
+#[derive(Default)]
+struct Recorded {
+    blocks: Vec<Block<Payload>>,
+    gaps: Vec<(ChannelId, Range<u64>)>,
+}
+
+struct Record(Arc<Mutex<Recorded>>);
+
+impl Sink for Record {
+    fn on_block(&mut self, block: &Block<Payload>) -> std::io::Result<()> {
+        self.0.lock().unwrap().blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        _reason: GapReason,
+    ) -> std::io::Result<()> {
+        self.0.lock().unwrap().gaps.push((channel, range));
+        Ok(())
+    }
+}
+
+fn sink(framing: Framing) -> (MessageSink<Record>, Arc<Mutex<Recorded>>) {
+    let recorded = Arc::<Mutex<Recorded>>::default();
+    let sink = MessageSink::new(Record(Arc::clone(&recorded)), framing);
+    (sink, recorded)
+}
+
+impl Recorded {
+    fn seqnums(&self) -> Vec<u64> {
+        self.blocks.iter().map(|b| b.header.seqnum).collect()
+    }
+}
+
+#[test]
+fn hands_on_a_block_per_message_numbered_by_message() {
+    let (mut sink, recorded) = sink(Framing::LengthPrefixed);
+    let payload = Payload::from(framed(&[b"ab", b"c", b"def"]));
+    sink.on_block(&block(10, 3, payload)).unwrap();
+    let last = Payload::from(framed(&[b"g"]));
+    sink.on_blocks(&[block(13, 1, last)]).unwrap();
+    sink.on_gap(3, 14..16, GapReason::Timeout).unwrap();
+
+    let record = recorded.lock().unwrap();
+    assert_eq!(record.seqnums(), [10, 11, 12, 13]);
+    let payloads: Vec<_> = record.blocks.iter().map(|b| b.payload.to_vec()).collect();
+    assert_eq!(
+        payloads,
+        [
+            framed(&[b"ab"]),
+            framed(&[b"c"]),
+            framed(&[b"def"]),
+            framed(&[b"g"])
+        ]
+    );
+    for block in &record.blocks {
+        assert_eq!((block.header.channel, block.header.n_messages), (3, 1));
+        // Still a block the protocol's parser can read
+        let mut msgs = sequencer::protocol::moldudp64::Messages::new(&block.payload, 1);
+        assert!(msgs.next().unwrap().is_ok());
+    }
+    // Gaps already count messages
+    assert_eq!(record.gaps, [(3, 14..16)]);
+}
+
+#[test]
+fn messages_share_the_blocks_buffer() {
+    let mut packet = Vec::new();
+    pitch::write_header(&mut packet, 1, 1);
+    pitch::write_message(&mut packet, 0x21, b"first");
+    pitch::write_message(&mut packet, 0x22, b"second");
+    let buf = packet[pitch::HEADER_LEN..].to_vec();
+    let len = buf.len();
+    let shared = Payload::shared(Arc::new(buf.clone()), 0..len);
+    let (mut sink, recorded) = sink(Framing::Pitch);
+    let mut end = block(1, 2, shared);
+    end.header.end_of_session = true;
+    sink.on_block(&end).unwrap();
+    // Owned payloads are split too, copied once
+    sink.on_block(&block(3, 2, Payload::from(buf.clone())))
+        .unwrap();
+
+    let record = recorded.lock().unwrap();
+    assert_eq!(record.seqnums(), [1, 2, 3, 4]);
+    assert!(record.blocks.iter().all(|b| b.payload.is_pooled()));
+    assert_eq!(record.blocks[0].payload, &buf[..7]);
+    assert_eq!(record.blocks[1].payload, &buf[7..]);
+    let ends: Vec<_> = record
+        .blocks
+        .iter()
+        .map(|b| b.header.end_of_session)
+        .collect();
+    assert_eq!(ends, [false, true, false, false]);
+}
+
+#[test]
+fn blocks_that_wont_split_are_handed_on_whole() {
+    let (mut sink, recorded) = sink(Framing::LengthPrefixed);
+    // Says three messages but has two
+    let short = Payload::from(framed(&[b"ab", b"c"]));
+    sink.on_block(&block(0, 3, short.clone())).unwrap();
+    let record = recorded.lock().unwrap();
+    assert_eq!(record.seqnums(), [0]);
+    assert_eq!(record.blocks[0].header.n_messages, 3);
+    assert_eq!(record.blocks[0].payload, short);
+}
+
+#[test]
+fn framing_by_protocol() {
+    assert_eq!(
+        Framing::for_protocol(&Protocol::MoldUdp64),
+        Some(Framing::LengthPrefixed)
+    );
+    assert_eq!(
+        Framing::for_protocol(&Protocol::Mitch),
+        Some(Framing::Pitch)
+    );
+    assert_eq!(Framing::for_protocol(&Protocol::Opra), Some(Framing::Opra));
+    // Seqnums count packets
+    assert_eq!(Framing::for_protocol(&Protocol::Mdp3), None);
+    assert_eq!(Framing::for_protocol(&Protocol::Custom(None)), None);
+}
+
+#[test]
+fn slices_of_payloads() {
+    let payload = Payload::shared(Arc::new(vec![0, 1, 2, 3, 4, 5]), 1..5);
+    let slice = payload.slice(1..3);
+    assert_eq!(slice, [2, 3]);
+    assert_eq!(slice.slice(1..2), [3]);
+    assert_eq!(Payload::from(vec![1, 2, 3]).slice(2..3), [3]);
+}
@@ -0,0 +1,254 @@
+use sequencer::gapfill::GapFiller;
+use sequencer::mitch::{MitchLogin, MitchRecoverySource, MitchReplayFiller};
+use sequencer::protocol::mitch::{self, Control, PacketKind, ACCEPTED};
+use sequencer::protocol::{pitch, ParseError, Protocol};
+use sequencer::recovery::SnapshotSource;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const ADD_ORDER: u8 = b'A';
+const GROUP: u8 = b'C';
+
+fn packet(group: u8, sequence: u32, bodies: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    mitch::write_header(&mut buf, group, sequence);
+    for body in bodies {
+        mitch::write_message(&mut buf, ADD_ORDER, body);
+    }
+    buf
+}
+
+fn control(control: Control) -> Vec<u8> {
+    let mut buf = Vec::new();
+    mitch::write_header(&mut buf, 0, 0);
+    mitch::write_control(&mut buf, &control);
+    buf
+}
+
+// The session messages of the next packet the client sent
+fn read_controls(stream: &mut TcpStream) -> Vec<Control> {
+    let mut buf = vec![0_u8; mitch::HEADER_LEN];
+    stream.read_exact(&mut buf).unwrap();
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    buf.resize(len, 0);
+    stream.read_exact(&mut buf[mitch::HEADER_LEN..]).unwrap();
+    let packet = mitch::parse(&buf).unwrap();
+    packet
+        .messages()
+        .map(|msg| mitch::parse_control(&msg.unwrap()).unwrap().unwrap())
+        .collect()
+}
+
+fn accept_login(listener: &TcpListener) -> TcpStream {
+    let (mut stream, _) = listener.accept().unwrap();
+    match read_controls(&mut stream)[..] {
+        [Control::Login { username, password }] => {
+            assert_eq!(&username, b"user  ");
+            assert_eq!(&password, b"secret    ");
+        }
+        ref other => panic!("not a login: {:?}", other),
+    }
+    let response = control(Control::LoginResponse { status: ACCEPTED });
+    stream.write_all(&response).unwrap();
+    stream
+}
+
+fn login() -> MitchLogin {
+    MitchLogin {
+        username: "user".to_string(),
+        password: "secret".to_string(),
+    }
+}
+
+#[test]
+fn sequences_each_market_data_group_as_a_channel() {
+    assert_eq!("mitch".parse(), Ok(Protocol::Mitch));
+    assert_eq!(Protocol::Mitch.first_seqnum(), 1);
+    let buf = packet(GROUP, 40, &[b"abc", b"de"]);
+    let parsed = mitch::parse(&buf).unwrap();
+    assert_eq!((parsed.kind, parsed.unit), (PacketKind::Data, GROUP));
+    let (header, payload) = Protocol::Mitch.parse(&buf).unwrap();
+    assert_eq!((header.seqnum, header.n_messages), (40, 2));
+    assert_eq!(payload, &buf[mitch::HEADER_LEN..]);
+    assert_eq!(Protocol::Mitch.channel(1, &header), 1 << 8 | GROUP as u32);
+
+    let heartbeat = packet(GROUP, 42, &[]);
+    let (header, _) = Protocol::Mitch.parse(&heartbeat).unwrap();
+    assert_eq!((header.seqnum, header.n_messages), (42, 0));
+    let unsequenced = control(Control::Logout);
+    assert_eq!(
+        Protocol::Mitch.parse_checked(&unsequenced).unwrap_err(),
+        ParseError::Invalid
+    );
+}
+
+#[test]
+fn session_messages_round_trip() {
+    let controls = [
+        Control::Login {
+            username: pitch::padded("user"),
+            password: pitch::padded("secret"),
+        },
+        Control::LoginResponse { status: b'a' },
+        Control::Logout,
+        Control::ReplayRequest {
+            group: GROUP,
+            sequence: 7,
+            count: 3,
+        },
+        Control::ReplayResponse {
+            group: GROUP,
+            sequence: 7,
+            count: 3,
+            status: b'O',
+        },
+        Control::SnapshotRequest {
+            sequence: 0,
+            segment: pitch::padded("SET1"),
+            instrument: 133_215,
+        },
+        Control::SnapshotResponse {
+            sequence: 99,
+            orders: 12,
+            status: ACCEPTED,
+        },
+        Control::SnapshotComplete {
+            sequence: 99,
+            segment: pitch::padded(""),
+            instrument: 0,
+            flags: 0,
+        },
+    ];
+    for c in controls {
+        let buf = control(c);
+        let parsed = mitch::parse(&buf).unwrap();
+        let msg = parsed.messages().next().unwrap().unwrap();
+        assert_eq!(mitch::parse_control(&msg), Some(Ok(c)));
+    }
+    let msg = mitch::Message {
+        kind: mitch::SNAPSHOT_COMPLETE,
+        body: &[0; 4],
+    };
+    assert_eq!(mitch::parse_control(&msg), Some(Err(ParseError::Truncated)));
+}
+
+#[test]
+fn fills_gaps_from_the_replay_channel() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut stream = accept_login(&listener);
+        for _ in 0..3 {
+            let (group, sequence, count) = match read_controls(&mut stream)[..] {
+                [Control::ReplayRequest {
+                    group,
+                    sequence,
+                    count,
+                }] => (group, sequence, count),
+                ref other => panic!("not a replay request: {:?}", other),
+            };
+            let (status, count) = match sequence {
+                // Only some of what was asked for is still there
+                20 => (ACCEPTED, 1),
+                10 => (ACCEPTED, count),
+                _ => (b'S', 0),
+            };
+            let response = control(Control::ReplayResponse {
+                group,
+                sequence,
+                count,
+                status,
+            });
+            stream.write_all(&response).unwrap();
+            if status != ACCEPTED {
+                continue;
+            }
+            // Another group's messages are on the connection too
+            stream.write_all(&packet(b'D', sequence, &[b"x"])).unwrap();
+            let bodies: Vec<&[u8]> = vec![b"a"; count as usize];
+            stream.write_all(&packet(group, sequence, &bodies)).unwrap();
+        }
+    });
+
+    let mut filler = MitchReplayFiller::new(addr, login(), Duration::from_secs(5));
+    let channel = 2 << 8 | GROUP as u32;
+    let blocks = filler.fill(channel, 10..13).unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].header.channel, channel);
+    assert_eq!(
+        (blocks[0].header.seqnum, blocks[0].header.n_messages),
+        (10, 3)
+    );
+
+    let blocks = filler.fill(channel, 20..25).unwrap();
+    assert_eq!(
+        (blocks[0].header.seqnum, blocks[0].header.n_messages),
+        (20, 1)
+    );
+
+    let e = filler.fill(channel, 30..31).unwrap_err();
+    assert!(e.to_string().contains("refused"), "{}", e);
+    server.join().unwrap();
+}
+
+#[test]
+fn snapshots_from_the_recovery_channel() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut stream = accept_login(&listener);
+        match read_controls(&mut stream)[..] {
+            [Control::SnapshotRequest { sequence: 0, .. }] => {}
+            ref other => panic!("not a snapshot request: {:?}", other),
+        }
+        let response = control(Control::SnapshotResponse {
+            sequence: 77,
+            orders: 2,
+            status: ACCEPTED,
+        });
+        stream.write_all(&response).unwrap();
+        stream.write_all(&packet(0, 0, &[b"a"])).unwrap();
+        // The last of the books shares a packet with snapshot complete
+        let mut last = packet(0, 0, &[b"b"]);
+        mitch::write_control(
+            &mut last,
+            &Control::SnapshotComplete {
+                sequence: 77,
+                segment: pitch::padded(""),
+                instrument: 0,
+                flags: 0,
+            },
+        );
+        stream.write_all(&last).unwrap();
+        assert_eq!(read_controls(&mut stream), [Control::Logout]);
+    });
+
+    let mut source = MitchRecoverySource::new(addr, login(), Duration::from_secs(5));
+    let snapshot = source.snapshot(9).unwrap();
+    assert_eq!(snapshot.seqnum, 78);
+    let payloads: Vec<_> = snapshot.blocks.iter().map(|b| b.payload.to_vec()).collect();
+    assert_eq!(
+        payloads,
+        [vec![3, ADD_ORDER, b'a'], vec![3, ADD_ORDER, b'b']]
+    );
+    assert!(snapshot.blocks.iter().all(|b| b.header.channel == 9));
+    server.join().unwrap();
+}
+
+#[test]
+fn rejected_logins_fail() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_controls(&mut stream);
+        let response = control(Control::LoginResponse { status: b'a' });
+        stream.write_all(&response).unwrap();
+    });
+    let mut source = MitchRecoverySource::new(addr, login(), Duration::from_secs(5));
+    let e = source.snapshot(0).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    server.join().unwrap();
+}
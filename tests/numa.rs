@@ -0,0 +1,80 @@
+use sequencer::numa::{self, parse_cpu_list, NumaNode, Topology};
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::thread;
+
+// Two nodes, with eth0 on node 1 and a virtual interface on none
+fn sysfs() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("numa-{}", std::process::id()));
+    let write = |path: &str, contents: &str| {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    };
+    write("devices/system/node/node0/cpulist", "0-3,8-11\n");
+    write("devices/system/node/node1/cpulist", "4-7,12-15\n");
+    write("class/net/eth0/device/numa_node", "1\n");
+    write("class/net/eth1/device/numa_node", "-1\n");
+    fs::create_dir_all(root.join("class/net/veth0")).unwrap();
+    root
+}
+
+#[test]
+fn parses_nodes_and_cpu_lists() {
+    assert_eq!("auto".parse(), Ok(NumaNode::Auto));
+    assert_eq!("1".parse(), Ok(NumaNode::Node(1)));
+    assert!("first".parse::<NumaNode>().is_err());
+
+    assert_eq!(parse_cpu_list("0-2,5,7-8"), Ok(vec![0, 1, 2, 5, 7, 8]));
+    assert_eq!(parse_cpu_list(""), Ok(Vec::new()));
+    for bad in ["3-1", "a", "1-", "0,,x"] {
+        assert!(parse_cpu_list(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn finds_the_node_of_a_nic() {
+    let root = sysfs();
+    let topology = Topology::at(&root);
+    assert_eq!(topology.interface_node("eth0").unwrap(), Some(1));
+    assert_eq!(topology.interface_node("eth1").unwrap(), None);
+    assert_eq!(topology.interface_node("veth0").unwrap(), None);
+    assert_eq!(topology.node_cpus(1).unwrap(), [4, 5, 6, 7, 12, 13, 14, 15]);
+    assert!(topology.node_cpus(2).is_err());
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(
+        numa::interface_with_addr(Ipv4Addr::LOCALHOST).unwrap(),
+        Some("lo".to_string())
+    );
+    assert_eq!(
+        numa::interface_with_addr(Ipv4Addr::new(192, 0, 2, 1)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn binds_threads_to_a_node() {
+    let topology = Topology::system();
+    let cpus = match topology.node_cpus(0) {
+        Ok(cpus) => cpus,
+        // Without NUMA support in the kernel
+        Err(_) => return,
+    };
+    // Threads spawned by a bound thread inherit
+    let bound = thread::spawn(move || {
+        numa::bind(0, &cpus).unwrap();
+        thread::spawn(|| core_affinity::get_core_ids().unwrap())
+            .join()
+            .unwrap()
+            .iter()
+            .map(|c| c.id)
+            .collect::<Vec<_>>()
+    });
+    let on = bound.join().unwrap();
+    assert!(!on.is_empty());
+    assert!(on
+        .iter()
+        .all(|c| topology.node_cpus(0).unwrap().contains(c)));
+}
@@ -0,0 +1,134 @@
+use sequencer::config::{Change, ConfigFile, FeedEntry};
+use sequencer::protocol::opra::{self, CONTROL, END_OF_DAY};
+use sequencer::protocol::{ParseError, Protocol};
+use sequencer::{Block, Sequencer};
+use std::collections::HashSet;
+use std::time::Duration;
+
+const LAST_SALE: u8 = b'a';
+
+fn block(seqnum: u32, retransmission: bool, data: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    opra::write_header(&mut buf, b'1', seqnum, retransmission);
+    for data in data {
+        opra::write_message(&mut buf, LAST_SALE, b' ', data);
+    }
+    buf
+}
+
+#[test]
+fn parses_block_headers() {
+    let buf = block(1_000, false, &[b"abc", b"de"]);
+    let parsed = opra::parse(&buf).unwrap();
+    assert_eq!((parsed.header.seqnum, parsed.header.n_messages), (1_000, 2));
+    assert_eq!(parsed.header.session[0], b'1');
+    assert_eq!(parsed.data_feed, b'O');
+    assert!(!parsed.retransmission);
+    assert!(!parsed.header.end_of_session);
+    assert!(!parsed.malformed());
+    let msgs: Vec<_> = parsed.messages().map(|m| m.unwrap()).collect();
+    assert_eq!((msgs[0].category, msgs[0].data), (LAST_SALE, &b"abc"[..]));
+    assert_eq!(msgs[1].data, b"de");
+
+    let resent = block(1_000, true, &[b"abc"]);
+    assert!(opra::parse(&resent).unwrap().retransmission);
+    assert_eq!(opra::parse(&buf[..10]).unwrap_err(), ParseError::Truncated);
+}
+
+#[test]
+fn counts_only_whole_messages() {
+    let buf = block(7, false, &[b"abc", b"de"]);
+    let parsed = opra::parse(&buf[..buf.len() - 1]).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.missing), (1, 1));
+    assert!(parsed.malformed());
+
+    let mut long = buf.clone();
+    long.extend_from_slice(&[0; 2]);
+    let parsed = opra::parse(&long).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.trailing), (2, 2));
+    assert_eq!(parsed.payload, &buf[opra::HEADER_LEN..]);
+}
+
+#[test]
+fn end_of_day_ends_the_session() {
+    let mut buf = block(50, false, &[]);
+    opra::write_message(&mut buf, CONTROL, END_OF_DAY, &[]);
+    let (header, _) = Protocol::Opra.parse(&buf).unwrap();
+    assert!(header.end_of_session);
+    assert_eq!(header.n_messages, 1);
+}
+
+#[test]
+fn arbitrates_a_lines_a_and_b_feeds() {
+    assert_eq!("opra".parse(), Ok(Protocol::Opra));
+    assert_eq!(Protocol::Opra.first_seqnum(), 1);
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(1);
+    // A misses the block at 3, which B and then a retransmission have
+    for (feed, seqnum, retransmission) in [
+        (0, 1, false),
+        (1, 1, false),
+        (0, 5, false),
+        (1, 3, false),
+        (0, 3, true),
+        (1, 5, false),
+    ] {
+        let buf = block(seqnum, retransmission, &[b"x", b"y"]);
+        let (mut header, payload) = Protocol::Opra.parse(&buf).unwrap();
+        header.channel = Protocol::Opra.channel(12, &header);
+        sequencer.push_from(feed, Block::new(header, payload.to_vec()));
+    }
+    drop(sequencer);
+    let mut seen = HashSet::new();
+    for block in receiver.iter().filter_map(|e| e.into_block()) {
+        assert_eq!(block.header.channel, 12);
+        assert!(seen.insert(block.header.seqnum));
+    }
+    assert_eq!(seen, HashSet::from([1, 3, 5]));
+}
+
+const LINES: &str = r#"
+[[lines]]
+line = 1
+a = "233.43.202.1:11101"
+b = "233.43.202.65:11101"
+
+[[lines]]
+line = 48
+a = "233.43.202.48:11148"
+interface = "10.0.0.5"
+"#;
+
+#[test]
+fn maps_config_lines_to_channels() {
+    let file = ConfigFile::parse(LINES).unwrap();
+    let feeds = file.feed_entries();
+    let feed = |group: &str, channel, interface: Option<&str>| FeedEntry {
+        group: group.parse().unwrap(),
+        interface: interface.map(|i| i.parse().unwrap()),
+        channel,
+        source: None,
+        protocol: None,
+    };
+    assert_eq!(
+        feeds,
+        [
+            feed("233.43.202.1:11101", 1, None),
+            feed("233.43.202.65:11101", 1, None),
+            feed("233.43.202.48:11148", 48, Some("10.0.0.5")),
+        ]
+    );
+    assert!(ConfigFile::parse("[[lines]]\nline = 49\na = \"233.43.202.49:1\"").is_err());
+    assert!(ConfigFile::parse("[[lines]]\nline = 0\na = \"233.43.202.1:1\"").is_err());
+    let twice = format!("{}\n[[lines]]\nline = 1\na = \"233.43.202.2:1\"", LINES);
+    assert!(ConfigFile::parse(&twice).is_err());
+
+    // Dropping a line's B side removes just that feed
+    let changed = LINES.replace("b = \"233.43.202.65:11101\"\n", "");
+    let changes = file.changes(&ConfigFile::parse(&changed).unwrap());
+    assert_eq!(
+        changes,
+        [Change::RemoveFeed(feed("233.43.202.65:11101", 1, None))]
+    );
+    assert!(ConfigFile::default().feed_entries().is_empty());
+}
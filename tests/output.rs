@@ -0,0 +1,110 @@
+use sequencer::arbiter::Arbiter;
+use sequencer::{
+    Block, BlockHeader, GapReason, OutputLimit, OutputPolicy, SequencedEvent, Sequencer,
+};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn drop_oldest_replaces_lagging_blocks_with_a_gap() {
+    let limit = OutputLimit {
+        capacity: Some(2),
+        policy: OutputPolicy::DropOldest,
+    };
+    let (mut sequencer, receiver) = Sequencer::with_output(Duration::from_secs(1), 16, limit);
+    for seqnum in 0..6 {
+        sequencer.push(block(seqnum));
+    }
+    // 2 in the channel and 2 in the backlog
+    assert_eq!(sequencer.stats().output_depth, 4);
+
+    let mut events: Vec<_> = receiver.try_iter().collect();
+    sequencer.poll_timeouts();
+    events.extend(receiver.try_iter());
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block(0)),
+            SequencedEvent::Block(block(1)),
+            SequencedEvent::Gap {
+                channel: 0,
+                from: 2,
+                to: 5,
+                reason: GapReason::Lagged,
+            },
+            SequencedEvent::Block(block(5)),
+        ]
+    );
+    let stats = sequencer.stats();
+    assert_eq!((stats.lagged, stats.dropped), (3, 3));
+    assert_eq!(stats.max_output_depth, 4);
+}
+
+#[test]
+fn block_waits_for_a_slow_consumer() {
+    let limit = OutputLimit {
+        capacity: Some(1),
+        policy: OutputPolicy::Block,
+    };
+    let (mut sequencer, receiver) = Sequencer::with_output(Duration::from_secs(1), 16, limit);
+    let consumer = thread::spawn(move || {
+        receiver
+            .iter()
+            .map(|e: SequencedEvent<Block<Vec<u8>>>| {
+                thread::sleep(Duration::from_millis(1));
+                e.into_block().unwrap().header.seqnum
+            })
+            .collect::<Vec<_>>()
+    });
+    for seqnum in 0..20 {
+        sequencer.push(block(seqnum));
+    }
+    let stats = sequencer.stats();
+    assert_eq!(stats.lagged, 0);
+    assert!(stats.max_output_depth <= 1);
+    drop(sequencer);
+    assert_eq!(consumer.join().unwrap(), (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn dropping_the_sequencer_delivers_the_backlog() {
+    let limit = OutputLimit {
+        capacity: Some(2),
+        policy: OutputPolicy::DropOldest,
+    };
+    let (mut sequencer, receiver) = Sequencer::with_output(Duration::from_secs(1), 16, limit);
+    for seqnum in 0..4 {
+        sequencer.push(block(seqnum));
+    }
+    let consumer = thread::spawn(move || receiver.iter().count());
+    drop(sequencer);
+    assert_eq!(consumer.join().unwrap(), 4);
+}
+
+#[test]
+fn arbiter_stops_once_the_consumer_is_gone() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    sequencer.push(block(0));
+    drop(receiver);
+    // Discarded rather than taking the sequencer down
+    sequencer.push(block(1));
+    assert!(sequencer.consumer_gone());
+
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(1));
+    let feed = arbiter.add_feed();
+    feed.send(block(2)).unwrap();
+    let sequencer = arbiter.run();
+    assert_eq!(sequencer.seqnum(0), 2);
+    // Its queue went away with the arbiter
+    assert!(feed.send(block(3)).is_err());
+}
@@ -0,0 +1,99 @@
+use crossbeam_channel::Receiver;
+use sequencer::{
+    Block, BlockHeader, BufferLimit, GapReason, OverflowPolicy, SequencedEvent, Sequencer,
+};
+use std::time::Duration;
+
+type Packet = Block<Vec<u8>>;
+
+fn block(seqnum: u64) -> Packet {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![0; 10])
+}
+
+fn limited(policy: OverflowPolicy) -> (Sequencer<Packet>, Receiver<SequencedEvent<Packet>>) {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_buffer_limit(BufferLimit {
+        max_entries: Some(2),
+        max_bytes: None,
+        policy,
+    });
+    (sequencer, receiver)
+}
+
+#[test]
+fn flush_with_gap_skips_head() {
+    let (mut sequencer, receiver) = limited(OverflowPolicy::FlushWithGap);
+    for seqnum in [2, 3, 5] {
+        sequencer.push(block(seqnum));
+    }
+    let stats = sequencer.stats();
+    assert_eq!(sequencer.seqnum(0), 4);
+    assert_eq!(sequencer.pending(), 1);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Gap {
+                channel: 0,
+                from: 0,
+                to: 2,
+                reason: GapReason::Overflow
+            },
+            SequencedEvent::Block(block(2)),
+            SequencedEvent::Block(block(3)),
+        ]
+    );
+    assert_eq!(stats.overflows, 1);
+    assert_eq!(stats.dropped, 2);
+}
+
+#[test]
+fn drop_newest_discards_block() {
+    let (mut sequencer, receiver) = limited(OverflowPolicy::DropNewest);
+    for seqnum in [2, 3, 5, 0, 1] {
+        sequencer.push(block(seqnum));
+    }
+    let stats = sequencer.stats();
+    assert_eq!(sequencer.seqnum(0), 4);
+    assert_eq!(sequencer.pending(), 0);
+    drop(sequencer);
+
+    let blocks: Vec<_> = receiver.iter().filter_map(|e| e.into_block()).collect();
+    assert_eq!(blocks, (0..4).map(block).collect::<Vec<_>>());
+    assert_eq!(stats.overflows, 1);
+}
+
+#[test]
+fn block_policy_reports_full() {
+    let (mut sequencer, _receiver) = limited(OverflowPolicy::Block);
+    sequencer.push(block(2));
+    assert!(!sequencer.is_full());
+    sequencer.push(block(3));
+    assert!(sequencer.is_full());
+    sequencer.push(block(0));
+    sequencer.push(block(1));
+    assert!(!sequencer.is_full());
+    assert_eq!(sequencer.stats().overflows, 1);
+}
+
+#[test]
+fn byte_limit() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_buffer_limit(BufferLimit {
+        max_entries: None,
+        max_bytes: Some(25),
+        policy: OverflowPolicy::DropNewest,
+    });
+    for seqnum in [2, 3, 4] {
+        sequencer.push(block(seqnum));
+    }
+    assert_eq!(sequencer.pending(), 2);
+}
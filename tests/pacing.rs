@@ -0,0 +1,92 @@
+use sequencer::journal;
+use sequencer::pacing::{Pacer, Pacing};
+use sequencer::publisher::MulticastPublisher;
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const MS: Duration = Duration::from_millis(1);
+
+fn pacing(packets_per_ms: Option<u64>, bytes_per_ms: Option<u64>, burst: Duration) -> Pacing {
+    Pacing {
+        packets_per_ms,
+        bytes_per_ms,
+        burst,
+    }
+}
+
+#[test]
+fn spreads_a_backlog_at_the_packet_rate() {
+    let mut pacer = Pacer::new(pacing(Some(1), None, Duration::ZERO));
+    let now = Instant::now();
+    let delays: Vec<_> = (0..3).map(|_| pacer.delay(100, now)).collect();
+    assert_eq!(delays, [Duration::ZERO, MS, MS * 2]);
+    // A quiet spell doesn't bank credit past the burst
+    let later = now + MS * 10;
+    assert_eq!(pacer.delay(100, later), Duration::ZERO);
+    assert_eq!(pacer.delay(100, later), MS);
+    assert_eq!(pacer.held(), MS * 4);
+}
+
+#[test]
+fn lets_a_burst_through_back_to_back() {
+    let mut pacer = Pacer::new(pacing(Some(1), None, MS * 3));
+    let now = Instant::now();
+    let delays: Vec<_> = (0..6).map(|_| pacer.delay(100, now)).collect();
+    let zero = Duration::ZERO;
+    assert_eq!(delays, [zero, zero, zero, zero, MS, MS * 2]);
+}
+
+#[test]
+fn holds_to_the_slower_of_both_rates() {
+    let mut pacer = Pacer::new(pacing(Some(10), Some(1_000), Duration::ZERO));
+    let now = Instant::now();
+    assert_eq!(pacer.delay(500, now), Duration::ZERO);
+    // 500 bytes at 1000 a millisecond outlast a tenth of a millisecond
+    assert_eq!(pacer.delay(500, now), MS / 2);
+    assert_eq!(pacer.delay(10, now), MS);
+    // Then small packets are held back by the packet rate
+    let later = now + MS * 2;
+    assert_eq!(pacer.delay(10, later), Duration::ZERO);
+    assert_eq!(pacer.delay(10, later), MS / 10);
+
+    assert!(pacing(None, None, MS).is_off());
+    let mut unlimited = Pacer::new(pacing(None, None, Duration::ZERO));
+    assert!((0..100).all(|_| unlimited.delay(1_000, now).is_zero()));
+}
+
+#[test]
+fn publisher_sends_at_the_rate() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let dst = match receiver.local_addr().unwrap() {
+        SocketAddr::V4(addr) => addr,
+        _ => unreachable!(),
+    };
+    let mut publisher = MulticastPublisher::bind(dst, *dst.ip(), journal::session("OUT")).unwrap();
+    publisher.set_pacing(pacing(Some(1), None, Duration::ZERO));
+    let started = Instant::now();
+    for seqnum in 1..=5 {
+        let header = BlockHeader {
+            seqnum,
+            n_messages: 1,
+            ..Default::default()
+        };
+        publisher
+            .on_block(&Block::new(header, b"x"[..].into()))
+            .unwrap();
+    }
+    // Each held until a millisecond after the one before
+    assert!(started.elapsed() >= MS * 4);
+    assert!(publisher.paced() > Duration::ZERO && publisher.paced() <= MS * 4);
+    drop(publisher);
+
+    let mut buf = [0; 64];
+    // The blocks and the end of session
+    for _ in 0..6 {
+        receiver.recv(&mut buf).unwrap();
+    }
+}
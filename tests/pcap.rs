@@ -0,0 +1,42 @@
+use sequencer::pcap::{PcapSource, PcapngWriter};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn recorded_datagrams_replay_to_their_feeds() {
+    let src = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000);
+    let a = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 5000);
+    let b = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 2), 5001);
+    let other = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 3), 5002);
+
+    let mut capture = Vec::new();
+    let mut writer = PcapngWriter::new(&mut capture).unwrap();
+    for (i, dst) in [a, b, other, b].iter().enumerate() {
+        let mut datagram = (i as u64).to_be_bytes().to_vec();
+        datagram.extend_from_slice(&1_u16.to_be_bytes());
+        datagram.push(i as u8);
+        let ts = UNIX_EPOCH + Duration::from_nanos(1_000_000_007 * i as u64);
+        writer
+            .write_udp(i as u32 % 2, ts, src, *dst, &datagram)
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+
+    let mut source = PcapSource::new(capture.as_slice()).unwrap();
+    source.add_feed(a);
+    source.add_feed(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, b.port()));
+    let mut replayed = Vec::new();
+    while let Some((feed, ts, block)) = source.next_block().unwrap() {
+        replayed.push((feed, ts, block.header.seqnum, block.payload.to_vec()));
+    }
+    let ts = |i: u64| UNIX_EPOCH + Duration::from_nanos(1_000_000_007 * i);
+    assert_eq!(
+        replayed,
+        vec![
+            (0, ts(0), 0, vec![0]),
+            (1, ts(1), 1, vec![1]),
+            (1, ts(3), 3, vec![3]),
+        ]
+    );
+}
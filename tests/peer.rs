@@ -0,0 +1,131 @@
+use sequencer::gapfill::{GapFiller, TcpGapFiller};
+use sequencer::peer::{PeerCache, PeerGapFiller, PeerServer};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, ChannelId, Payload, NO_SESSION};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn block(channel: ChannelId, seqnum: u64, n_messages: u16) -> Block<Payload> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8; 2].into())
+}
+
+fn seqnums(blocks: &[Block<Payload>]) -> Vec<u64> {
+    blocks.iter().map(|b| b.header.seqnum).collect()
+}
+
+fn serve(cache: &PeerCache) -> SocketAddr {
+    let server = PeerServer::bind("127.0.0.1:0".parse().unwrap(), cache.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn().unwrap();
+    addr
+}
+
+// Remembers what it was asked for and fills it all
+#[derive(Default)]
+struct Exchange {
+    asked: Arc<Mutex<Vec<Range<u64>>>>,
+}
+
+impl GapFiller<Block<Payload>> for Exchange {
+    fn fill(&mut self, channel: ChannelId, range: Range<u64>) -> io::Result<Vec<Block<Payload>>> {
+        self.asked.lock().unwrap().push(range.clone());
+        Ok(range.map(|seqnum| block(channel, seqnum, 1)).collect())
+    }
+}
+
+#[test]
+fn caches_the_latest_blocks_of_each_channel() {
+    let mut cache = PeerCache::new(3);
+    for seqnum in [1, 2, 5, 8, 9] {
+        let n_messages = if seqnum == 5 { 3 } else { 1 };
+        cache.on_block(&block(0, seqnum, n_messages)).unwrap();
+        cache.on_block(&block(1, seqnum, 1)).unwrap();
+    }
+    assert_eq!(cache.len(), 6);
+    // Started inside the block at 5, the oldest are dropped
+    assert_eq!(seqnums(&cache.get(0, 6..9)), [5, 8]);
+    assert_eq!(seqnums(&cache.get(0, 1..20)), [5, 8, 9]);
+    assert_eq!(seqnums(&cache.get(1, 9..10)), [9]);
+    assert_eq!(seqnums(&cache.get(2, 1..10)), Vec::<u64>::new());
+
+    // A new session's seqnums start over
+    cache.on_reset(0, NO_SESSION, 1).unwrap();
+    assert_eq!(seqnums(&cache.get(0, 1..20)), Vec::<u64>::new());
+    assert_eq!(cache.len(), 3);
+
+    // Seqnums from the network could run past the last
+    cache.on_block(&block(0, u64::MAX - 1, 3)).unwrap();
+    assert_eq!(seqnums(&cache.get(0, 1..u64::MAX)), [u64::MAX - 1]);
+}
+
+#[test]
+fn serves_peers_from_the_cache() {
+    let mut cache = PeerCache::new(100);
+    for seqnum in 1..=10 {
+        cache.on_block(&block(0, seqnum, 1)).unwrap();
+    }
+    let addr = serve(&cache);
+    let mut filler = TcpGapFiller::new(addr, 0, Duration::from_secs(5));
+    let blocks = filler.fill(0, 4..7).unwrap();
+    assert_eq!(seqnums(&blocks), [4, 5, 6]);
+    assert_eq!(&*blocks[0].payload, &[4, 4]);
+    assert_eq!(seqnums(&filler.fill(1, 4..7).unwrap()), Vec::<u64>::new());
+
+    // Blocks sequenced since are served too
+    cache.on_block(&block(0, 11, 1)).unwrap();
+    assert_eq!(seqnums(&filler.fill(0, 10..20).unwrap()), [10, 11]);
+}
+
+#[test]
+fn asks_the_exchange_only_for_what_peers_missed() {
+    // Each peer missed different blocks
+    let mut a = PeerCache::new(100);
+    let mut b = PeerCache::new(100);
+    for seqnum in [1, 2, 5, 6] {
+        a.on_block(&block(0, seqnum, 1)).unwrap();
+    }
+    for seqnum in [2, 3, 6] {
+        b.on_block(&block(0, seqnum, 1)).unwrap();
+    }
+    let exchange = Exchange::default();
+    let asked = Arc::clone(&exchange.asked);
+    let mut filler = PeerGapFiller::new(&[serve(&a), serve(&b)], Duration::from_secs(5));
+    filler.set_fallback(Box::new(exchange));
+
+    let mut blocks = seqnums(&filler.fill(0, 1..8).unwrap());
+    blocks.sort();
+    blocks.dedup();
+    assert_eq!(blocks, [1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(*asked.lock().unwrap(), vec![4..5, 7..8]);
+
+    // Nothing is asked of the exchange once peers filled the gap
+    asked.lock().unwrap().clear();
+    assert_eq!(seqnums(&filler.fill(0, 5..7).unwrap()), [5, 6]);
+    assert!(asked.lock().unwrap().is_empty());
+}
+
+#[test]
+fn falls_back_when_peers_are_unreachable() {
+    // Nothing listens here once the listener is dropped
+    let down = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut filler = PeerGapFiller::new(&[down], Duration::from_millis(100));
+    assert!(filler.fill(0, 1..3).is_err());
+
+    let exchange = Exchange::default();
+    let asked = Arc::clone(&exchange.asked);
+    filler.set_fallback(Box::new(exchange));
+    assert_eq!(seqnums(&filler.fill(0, 1..3).unwrap()), [1, 2]);
+    assert_eq!(*asked.lock().unwrap(), vec![1..3]);
+}
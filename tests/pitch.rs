@@ -0,0 +1,284 @@
+use sequencer::gapfill::GapFiller;
+use sequencer::pitch::{PitchGapFiller, PitchLogin, PitchSpinSource};
+use sequencer::protocol::pitch::{self, Control, PacketKind, ACCEPTED};
+use sequencer::protocol::{ParseError, Protocol};
+use sequencer::recovery::SnapshotSource;
+use sequencer::BlockHeader;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+const ADD_ORDER: u8 = 0x21;
+
+fn packet(unit: u8, sequence: u32, bodies: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    pitch::write_header(&mut buf, unit, sequence);
+    for body in bodies {
+        pitch::write_message(&mut buf, ADD_ORDER, body);
+    }
+    buf
+}
+
+fn control(control: Control) -> Vec<u8> {
+    let mut buf = Vec::new();
+    pitch::write_header(&mut buf, 0, 0);
+    pitch::write_control(&mut buf, &control);
+    buf
+}
+
+// The session messages of the next packet the client sent
+fn read_controls(stream: &mut TcpStream) -> Vec<Control> {
+    let mut buf = vec![0_u8; pitch::HEADER_LEN];
+    stream.read_exact(&mut buf).unwrap();
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    buf.resize(len, 0);
+    stream.read_exact(&mut buf[pitch::HEADER_LEN..]).unwrap();
+    let packet = pitch::parse(&buf).unwrap();
+    packet
+        .messages()
+        .map(|msg| pitch::parse_control(&msg.unwrap()).unwrap().unwrap())
+        .collect()
+}
+
+fn accept_login(listener: &TcpListener) -> TcpStream {
+    let (mut stream, _) = listener.accept().unwrap();
+    match read_controls(&mut stream)[..] {
+        [Control::Login { username, .. }] => assert_eq!(&username, b"user"),
+        ref other => panic!("not a login: {:?}", other),
+    }
+    let response = control(Control::LoginResponse { status: ACCEPTED });
+    stream.write_all(&response).unwrap();
+    stream
+}
+
+fn login() -> PitchLogin {
+    PitchLogin {
+        session_sub_id: "0001".to_string(),
+        username: "user".to_string(),
+        password: "secret".to_string(),
+    }
+}
+
+#[test]
+fn parses_sequenced_units() {
+    let buf = packet(3, 100, &[b"abc", b"de"]);
+    let parsed = pitch::parse(&buf).unwrap();
+    assert_eq!(parsed.kind, PacketKind::Data);
+    assert_eq!(parsed.unit, 3);
+    assert_eq!((parsed.header.seqnum, parsed.header.n_messages), (100, 2));
+    assert!(!parsed.malformed());
+    let msgs: Vec<_> = parsed.messages().map(|m| m.unwrap()).collect();
+    assert_eq!((msgs[0].kind, msgs[0].body), (ADD_ORDER, &b"abc"[..]));
+    assert_eq!(msgs[1].body, b"de");
+    assert_eq!(pitch::packet_len(&buf), Some(buf.len()));
+    assert_eq!(pitch::packet_len(&buf[..buf.len() - 1]), None);
+
+    let heartbeat = packet(3, 103, &[]);
+    assert_eq!(
+        pitch::parse(&heartbeat).unwrap().kind,
+        PacketKind::Heartbeat
+    );
+    let unsequenced = control(Control::LoginResponse { status: ACCEPTED });
+    assert_eq!(
+        pitch::parse(&unsequenced).unwrap().kind,
+        PacketKind::Unsequenced
+    );
+    assert_eq!(pitch::parse(&buf[..4]).unwrap_err(), ParseError::Truncated);
+}
+
+#[test]
+fn counts_only_whole_messages() {
+    // The count says 2, the second is cut short
+    let buf = packet(1, 10, &[b"abc", b"de"]);
+    let parsed = pitch::parse(&buf[..buf.len() - 1]).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.missing), (1, 1));
+    assert!(parsed.malformed());
+
+    let mut long = buf.clone();
+    long.extend_from_slice(&[0; 3]);
+    let parsed = pitch::parse(&long).unwrap();
+    assert_eq!((parsed.header.n_messages, parsed.trailing), (2, 3));
+    assert_eq!(parsed.payload, &buf[pitch::HEADER_LEN..]);
+}
+
+#[test]
+fn session_messages_round_trip() {
+    let controls = [
+        Control::Login {
+            session_sub_id: pitch::padded("0001"),
+            username: pitch::padded("user"),
+            password: pitch::padded("secret"),
+        },
+        Control::LoginResponse { status: ACCEPTED },
+        Control::GapRequest {
+            unit: 2,
+            sequence: 1_000,
+            count: 50,
+        },
+        Control::GapResponse {
+            unit: 2,
+            sequence: 1_000,
+            count: 50,
+            status: b'O',
+        },
+        Control::SpinImageAvailable { sequence: 99 },
+        Control::SpinRequest { sequence: 99 },
+        Control::SpinResponse {
+            sequence: 99,
+            count: 7,
+            status: ACCEPTED,
+        },
+        Control::SpinFinished { sequence: 99 },
+    ];
+    for c in controls {
+        let buf = control(c);
+        let parsed = pitch::parse(&buf).unwrap();
+        let msg = parsed.messages().next().unwrap().unwrap();
+        assert_eq!(pitch::parse_control(&msg), Some(Ok(c)));
+    }
+    assert_eq!(&pitch::padded::<10>("secret"), b"secret    ");
+    let msg = pitch::Message {
+        kind: pitch::GAP_REQUEST,
+        body: &[2, 0],
+    };
+    assert_eq!(pitch::parse_control(&msg), Some(Err(ParseError::Truncated)));
+    let msg = pitch::Message {
+        kind: ADD_ORDER,
+        body: b"abc",
+    };
+    assert_eq!(pitch::parse_control(&msg), None);
+}
+
+#[test]
+fn sequences_each_unit_as_a_channel() {
+    assert_eq!("pitch".parse(), Ok(Protocol::Pitch));
+    assert_eq!(Protocol::Pitch.first_seqnum(), 1);
+    let buf = packet(5, 20, &[b"abc"]);
+    let (header, payload) = Protocol::Pitch.parse(&buf).unwrap();
+    assert_eq!((header.seqnum, header.n_messages), (20, 1));
+    assert_eq!(payload, &buf[pitch::HEADER_LEN..]);
+    assert_eq!(Protocol::Pitch.channel(2, &header), 2 << 8 | 5);
+    assert_eq!(Protocol::Raw.channel(2, &header), 2);
+
+    // Session messages have no seqnum to sequence by
+    let unsequenced = control(Control::LoginResponse { status: ACCEPTED });
+    assert_eq!(
+        Protocol::Pitch.parse_checked(&unsequenced).unwrap_err(),
+        ParseError::Invalid
+    );
+}
+
+#[test]
+fn fills_gaps_from_the_gap_response_line() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let replays = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let line = replays.local_addr().unwrap();
+    let grp = thread::spawn(move || {
+        let mut stream = accept_login(&listener);
+        let out = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..2 {
+            let (unit, sequence, count) = match read_controls(&mut stream)[..] {
+                [Control::GapRequest {
+                    unit,
+                    sequence,
+                    count,
+                }] => (unit, sequence, count),
+                ref other => panic!("not a gap request: {:?}", other),
+            };
+            let status = if sequence == 1 { ACCEPTED } else { b'O' };
+            let response = control(Control::GapResponse {
+                unit,
+                sequence,
+                count,
+                status,
+            });
+            stream.write_all(&response).unwrap();
+            if status != ACCEPTED {
+                continue;
+            }
+            // Another unit's replay, then the two asked for over two packets
+            out.send_to(&packet(unit + 1, sequence, &[b"x"]), line)
+                .unwrap();
+            out.send_to(&packet(unit, sequence, &[b"a"]), line).unwrap();
+            out.send_to(&packet(unit, sequence + 1, &[b"b"]), line)
+                .unwrap();
+        }
+    });
+
+    let mut filler = PitchGapFiller::new(addr, login(), replays, Duration::from_secs(5));
+    let channel = 1 << 8 | 4;
+    let blocks = filler.fill(channel, 1..3).unwrap();
+    let headers: Vec<_> = blocks.iter().map(|b| b.header.clone()).collect();
+    let header = |seqnum| BlockHeader {
+        channel,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    assert_eq!(headers, [header(1), header(2)]);
+    assert_eq!(&blocks[1].payload[..], &[3, ADD_ORDER, b'b']);
+
+    let e = filler.fill(channel, 10..12).unwrap_err();
+    assert!(e.to_string().contains("refused"), "{}", e);
+    grp.join().unwrap();
+}
+
+#[test]
+fn spins_a_units_book() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut stream = accept_login(&listener);
+        let available = control(Control::SpinImageAvailable { sequence: 41 });
+        stream.write_all(&available).unwrap();
+        assert_eq!(
+            read_controls(&mut stream),
+            [Control::SpinRequest { sequence: 41 }]
+        );
+        let response = control(Control::SpinResponse {
+            sequence: 41,
+            count: 2,
+            status: ACCEPTED,
+        });
+        stream.write_all(&response).unwrap();
+        stream.write_all(&packet(0, 0, &[b"a"])).unwrap();
+        // The last of the book shares a packet with spin finished
+        let mut last = packet(0, 0, &[b"b"]);
+        pitch::write_control(&mut last, &Control::SpinFinished { sequence: 41 });
+        stream.write_all(&last).unwrap();
+    });
+
+    let mut source = PitchSpinSource::new(addr, login(), Duration::from_secs(5));
+    let snapshot = source.snapshot(7).unwrap();
+    assert_eq!(snapshot.seqnum, 42);
+    let payloads: Vec<_> = snapshot.blocks.iter().map(|b| b.payload.to_vec()).collect();
+    assert_eq!(
+        payloads,
+        [vec![3, ADD_ORDER, b'a'], vec![3, ADD_ORDER, b'b']]
+    );
+    assert!(snapshot.blocks.iter().all(|b| b.header.channel == 7));
+    server.join().unwrap();
+}
+
+#[test]
+fn refused_spins_fail() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut stream = accept_login(&listener);
+        let available = control(Control::SpinImageAvailable { sequence: 5 });
+        stream.write_all(&available).unwrap();
+        read_controls(&mut stream);
+        let response = control(Control::SpinResponse {
+            sequence: 5,
+            count: 0,
+            status: b'O',
+        });
+        stream.write_all(&response).unwrap();
+    });
+    let mut source = PitchSpinSource::new(addr, login(), Duration::from_secs(5));
+    assert!(source.snapshot(0).is_err());
+    server.join().unwrap();
+}
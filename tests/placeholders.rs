@@ -0,0 +1,166 @@
+use sequencer::message_level::{Framing, MessageSink};
+use sequencer::placeholders::PlaceholderSink;
+use sequencer::protocol::{custom, moldudp64, pitch};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, Payload};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Event {
+    Block(u64, u16, Vec<u8>),
+    Gap(ChannelId, Range<u64>),
+}
+
+#[derive(Default)]
+struct Record(Arc<Mutex<Vec<Event>>>);
+
+impl Sink for Record {
+    fn on_block(&mut self, block: &Block<Payload>) -> std::io::Result<()> {
+        let (seqnum, n_messages) = (block.header.seqnum, block.header.n_messages);
+        let event = Event::Block(seqnum, n_messages, block.payload.to_vec());
+        self.0.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    fn on_gap(
+        &mut self,
+        channel: ChannelId,
+        range: Range<u64>,
+        _reason: GapReason,
+    ) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(Event::Gap(channel, range));
+        Ok(())
+    }
+}
+
+fn block(seqnum: u64, session: u8) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 1,
+        session: [session; 10],
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Payload::from(vec![0, 1, 7]))
+}
+
+#[test]
+fn fills_gaps_with_empty_messages() {
+    let record = Record::default();
+    let events = Arc::clone(&record.0);
+    let mut sink = PlaceholderSink::new(record, Framing::LengthPrefixed);
+    sink.on_block(&block(1, 1)).unwrap();
+    sink.on_gap(1, 2..5, GapReason::Timeout).unwrap();
+    sink.on_block(&block(5, 1)).unwrap();
+    // Nothing to fill
+    sink.on_gap(1, 6..6, GapReason::Timeout).unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        *events,
+        [
+            Event::Block(1, 1, vec![0, 1, 7]),
+            // Told of the gap first
+            Event::Gap(1, 2..5),
+            Event::Block(2, 3, vec![0; 6]),
+            Event::Block(5, 1, vec![0, 1, 7]),
+            Event::Gap(1, 6..6),
+        ]
+    );
+    // Messages that parse, each empty
+    let Event::Block(_, n, payload) = &events[2] else {
+        unreachable!()
+    };
+    let msgs = moldudp64::Messages::new(payload, *n);
+    assert!(msgs.map(Result::unwrap).all(|m| m.is_empty()));
+}
+
+#[test]
+fn placeholders_are_in_the_latest_session() {
+    struct Sessions(Arc<Mutex<Vec<(u64, u8)>>>);
+    impl Sink for Sessions {
+        fn on_block(&mut self, block: &Block<Payload>) -> std::io::Result<()> {
+            let session = (block.header.seqnum, block.header.session[0]);
+            self.0.lock().unwrap().push(session);
+            Ok(())
+        }
+    }
+    let sessions = Arc::default();
+    let mut sink = PlaceholderSink::new(Sessions(Arc::clone(&sessions)), Framing::Pitch);
+    sink.on_block(&block(1, 1)).unwrap();
+    sink.on_gap(1, 2..3, GapReason::Timeout).unwrap();
+    sink.on_reset(1, [2; 10], 1).unwrap();
+    sink.on_gap(1, 1..2, GapReason::Timeout).unwrap();
+    // Of a channel not seen yet
+    sink.on_gap(2, 1..2, GapReason::Timeout).unwrap();
+    assert_eq!(*sessions.lock().unwrap(), [(1, 1), (2, 1), (1, 2), (1, 0)]);
+}
+
+#[test]
+fn large_gaps_take_blocks_of_as_many_as_they_count() {
+    let record = Record::default();
+    let events = Arc::clone(&record.0);
+    let mut sink = PlaceholderSink::new(record, Framing::Pitch);
+    let end = 1 + u16::MAX as u64 + 10;
+    sink.on_gap(1, 1..end, GapReason::Overflow).unwrap();
+    let events = events.lock().unwrap();
+    let blocks: Vec<_> = events[1..]
+        .iter()
+        .map(|e| match e {
+            Event::Block(seqnum, n, payload) => (*seqnum, *n, payload.len()),
+            Event::Gap(..) => unreachable!(),
+        })
+        .collect();
+    let most = u16::MAX;
+    assert_eq!(
+        blocks,
+        [(1, most, 2 * most as usize), (1 + most as u64, 10, 20)]
+    );
+    let Event::Block(_, n, payload) = &events[2] else {
+        unreachable!()
+    };
+    let mut msgs = pitch::Messages::new(payload, *n);
+    assert!(msgs.all(|m| m.is_ok_and(|m| m.kind == 0 && m.body.is_empty())));
+}
+
+#[test]
+fn split_into_messages_for_message_level_sinks() {
+    let record = Record::default();
+    let events = Arc::clone(&record.0);
+    let messages = MessageSink::new(record, Framing::LengthPrefixed);
+    let mut sink = PlaceholderSink::new(messages, Framing::LengthPrefixed);
+    sink.on_gap(1, 4..6, GapReason::Timeout).unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            Event::Gap(1, 4..6),
+            Event::Block(4, 1, vec![0, 0]),
+            Event::Block(5, 1, vec![0, 0]),
+        ]
+    );
+}
+
+#[test]
+fn custom_framings_placeholders() {
+    let prefixed = |inclusive| {
+        Framing::Custom(custom::MessageFraming::LengthPrefixed {
+            width: 2,
+            endian: custom::Endian::Little,
+            inclusive,
+        })
+    };
+    let placeholder = |framing: Framing| {
+        let mut buf = Vec::new();
+        framing.write_placeholder(&mut buf);
+        buf
+    };
+    assert_eq!(placeholder(prefixed(false)), [0, 0]);
+    assert_eq!(placeholder(prefixed(true)), [2, 0]);
+    let fixed = Framing::Custom(custom::MessageFraming::Fixed(3));
+    assert_eq!(placeholder(fixed), [0, 0, 0]);
+    for framing in [prefixed(false), prefixed(true), fixed, Framing::Opra] {
+        let buf = [placeholder(framing), placeholder(framing)].concat();
+        assert_eq!(framing.messages(&buf, 2).unwrap().len(), 2, "{:?}", framing);
+    }
+}
@@ -0,0 +1,77 @@
+use sequencer::pool::BufferPool;
+use sequencer::protocol::Protocol;
+use sequencer::udp::{FeedConfig, UdpFeed};
+use sequencer::Payload;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
+
+#[test]
+fn buffers_return_when_last_payload_drops() {
+    let pool = BufferPool::new(2, 16);
+    let mut buf = pool.get();
+    assert_eq!((pool.free(), buf.len()), (1, 16));
+    buf[..5].copy_from_slice(b"hello");
+    let payload = buf.into_payload(1..4);
+    assert!(payload.is_pooled());
+    assert_eq!(payload, b"ell");
+    let copy = payload.clone();
+    drop(payload);
+    assert_eq!(pool.free(), 1);
+    assert_eq!(&copy[..], b"ell");
+    drop(copy);
+    assert_eq!((pool.free(), pool.allocated()), (2, 2));
+}
+
+#[test]
+fn grows_when_dry_and_keeps_capacity() {
+    let pool = BufferPool::new(2, 16);
+    let bufs: Vec<_> = (0..3).map(|_| pool.get()).collect();
+    assert_eq!((pool.free(), pool.allocated()), (0, 3));
+    drop(bufs);
+    assert_eq!(pool.free(), 2);
+    let _buf = pool.get();
+    assert_eq!(pool.allocated(), 3);
+}
+
+#[test]
+fn owned_payloads() {
+    let payload = Payload::from(vec![1, 2, 3]);
+    assert!(!payload.is_pooled());
+    assert_eq!(payload, [1, 2, 3]);
+    assert_eq!(payload.len(), 3);
+    assert_eq!(Payload::default(), Payload::from(&[][..]));
+}
+
+#[test]
+fn udp_payloads_stay_in_receive_buffer() {
+    let config = FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 4),
+        port: 0,
+        protocol: Protocol::Raw,
+        channel: 0,
+        source: None,
+        reuse_port: false,
+        recv_buffer: None,
+    };
+    let mut feed = UdpFeed::join(&config).unwrap();
+    let pool = BufferPool::new(4, 1024);
+    feed.set_pool(pool.clone());
+    feed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let port = feed.local_addr().unwrap().port();
+
+    let mut datagram = 7_u64.to_be_bytes().to_vec();
+    datagram.extend_from_slice(&1_u16.to_be_bytes());
+    datagram.extend_from_slice(b"payload");
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(&datagram, (config.group, port)).unwrap();
+
+    let block = feed.recv().unwrap().unwrap();
+    assert_eq!(block.header.seqnum, 7);
+    assert!(block.payload.is_pooled());
+    assert_eq!(block.payload, b"payload");
+    // One receiving the next datagram, one holding this one
+    assert_eq!(pool.free(), 2);
+    drop(block);
+    assert_eq!(pool.free(), 3);
+}
@@ -0,0 +1,113 @@
+use proptest::prelude::*;
+use sequencer::{Block, BlockHeader, ChannelId, SequencedEvent, Sequencer};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+type Packet = Block<Vec<u8>>;
+
+// The stream every feed carries: blocks of 1 to 3 messages spread over up to
+// 3 channels
+fn stream(sizes: &[(ChannelId, u16)]) -> Vec<Packet> {
+    let mut next = HashMap::new();
+    sizes
+        .iter()
+        .map(|(channel, n_messages)| {
+            let seqnum = next.entry(*channel).or_insert(0);
+            let header = BlockHeader {
+                channel: *channel,
+                seqnum: *seqnum,
+                n_messages: *n_messages,
+                ..Default::default()
+            };
+            *seqnum += *n_messages as u64;
+            Block::new(header, seqnum.to_be_bytes().to_vec())
+        })
+        .collect()
+}
+
+// What one feed receives: each block lost or late by up to a few positions
+fn feed(blocks: &[Packet], impairments: &[(bool, usize)]) -> Vec<Packet> {
+    let mut received: Vec<_> = blocks
+        .iter()
+        .zip(impairments)
+        .enumerate()
+        .filter(|(_, (_, (lost, _)))| !lost)
+        .map(|(i, (b, (_, late)))| (i + late, b.clone()))
+        .collect();
+    received.sort_by_key(|(pos, _)| *pos);
+    received.into_iter().map(|(_, b)| b).collect()
+}
+
+fn streams() -> impl Strategy<Value = (Vec<Packet>, Vec<Vec<Packet>>)> {
+    (
+        prop::collection::vec((0_u32..3, 1_u16..4), 1..200),
+        1_usize..4,
+    )
+        .prop_flat_map(|(sizes, n_feeds)| {
+            let blocks = stream(&sizes);
+            let impairments = (prop::bool::weighted(0.1), 0_usize..8);
+            let feeds =
+                prop::collection::vec(prop::collection::vec(impairments, blocks.len()), n_feeds);
+            (Just(blocks), feeds)
+        })
+        .prop_map(|(blocks, impairments)| {
+            let feeds = impairments.iter().map(|i| feed(&blocks, i)).collect();
+            (blocks, feeds)
+        })
+}
+
+proptest! {
+    #[test]
+    fn output_is_in_order_or_annotated((blocks, feeds) in streams()) {
+        let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(1));
+        for _ in 0..feeds.len() {
+            sequencer.add_feed();
+        }
+        let longest = feeds.iter().map(|f| f.len()).max().unwrap();
+        for i in 0..longest {
+            for (feed_id, f) in feeds.iter().enumerate() {
+                if let Some(b) = f.get(i) {
+                    sequencer.push_from(feed_id, b.clone());
+                }
+            }
+        }
+        // Everything still buffered times out
+        thread::sleep(Duration::from_millis(2));
+        sequencer.poll_timeouts();
+        drop(sequencer);
+
+        let sent: HashMap<_, _> = blocks
+            .iter()
+            .map(|b| ((b.header.channel, b.header.seqnum), b))
+            .collect();
+        let mut expected: HashMap<ChannelId, u64> = HashMap::new();
+        for event in receiver.iter() {
+            match event {
+                SequencedEvent::Block(b) => {
+                    let next = expected.entry(b.header.channel).or_insert(0);
+                    prop_assert_eq!(b.header.seqnum, *next, "channel {}", b.header.channel);
+                    prop_assert_eq!(Some(&&b), sent.get(&(b.header.channel, b.header.seqnum)));
+                    *next += b.header.n_messages as u64;
+                }
+                SequencedEvent::Gap { channel, from, to, .. } => {
+                    let next = expected.entry(channel).or_insert(0);
+                    prop_assert_eq!(from, *next, "channel {}", channel);
+                    prop_assert!(to > from);
+                    *next = to;
+                }
+                e => prop_assert!(false, "unexpected {:?}", e),
+            }
+        }
+
+        // Nothing a feed received is left behind
+        for b in feeds.iter().flatten() {
+            let end = b.header.seqnum + b.header.n_messages as u64;
+            prop_assert!(expected[&b.header.channel] >= end);
+        }
+        for (channel, next) in expected {
+            let last = blocks.iter().rev().find(|b| b.header.channel == channel).unwrap();
+            prop_assert!(next <= last.header.seqnum + last.header.n_messages as u64);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use sequencer::journal;
+use sequencer::protocol::moldudp64::{self, PacketKind};
+use sequencer::protocol::relay::{self, Packet};
+use sequencer::protocol::ParseError;
+use sequencer::publisher::{MulticastPublisher, PublishPayload, UnicastPublisher};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, GapReason, Payload};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+fn block(channel: u32, seqnum: u64, n_messages: u16, payload: &[u8]) -> Block<Payload> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    };
+    Block::new(header, payload.into())
+}
+
+#[test]
+fn publishes_with_own_seqnums() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let dst = match receiver.local_addr().unwrap() {
+        SocketAddr::V4(addr) => addr,
+        _ => unreachable!(),
+    };
+    let session = journal::session("OUT");
+    let mut publisher = MulticastPublisher::bind(dst, *dst.ip(), session).unwrap();
+
+    publisher.on_block(&block(0, 10, 1, b"a")).unwrap();
+    publisher.on_gap(0, 11..12, GapReason::Timeout).unwrap();
+    publisher.on_block(&block(1, 7, 1, b"b")).unwrap();
+    publisher.set_payload(PublishPayload::MoldMessages);
+    let mut messages = Vec::new();
+    for msg in [b"c", b"d", b"e"] {
+        moldudp64::write_message(&mut messages, msg);
+    }
+    publisher.on_block(&block(0, 12, 3, &messages)).unwrap();
+    assert_eq!(publisher.seqnum(), 6);
+    drop(publisher);
+
+    let mut buf = [0; 1500];
+    let mut packets = Vec::new();
+    loop {
+        let n = receiver.recv(&mut buf).unwrap();
+        let p = moldudp64::parse(&buf[..n]).unwrap();
+        assert_eq!(p.session, session);
+        if p.kind == PacketKind::EndOfSession {
+            assert_eq!(p.header.seqnum, 6);
+            break;
+        }
+        let msgs: Vec<Vec<u8>> = p.messages().map(|m| m.unwrap().to_vec()).collect();
+        packets.push((p.header.seqnum, msgs));
+    }
+    assert_eq!(
+        packets,
+        vec![
+            (1, vec![b"a".to_vec()]),
+            (2, vec![b"b".to_vec()]),
+            (3, vec![b"c".to_vec(), b"d".to_vec(), b"e".to_vec()]),
+        ]
+    );
+}
+
+#[test]
+fn relays_blocks_and_gaps_with_record_numbers() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut publisher = UnicastPublisher::bind(receiver.local_addr().unwrap()).unwrap();
+    let mut sessioned = block(2, 10, 2, b"ab");
+    sessioned.header.session = journal::session("S1");
+    publisher.on_block(&sessioned).unwrap();
+    publisher.on_gap(2, 12..15, GapReason::Timeout).unwrap();
+    publisher.on_block(&block(3, 0, 1, b"")).unwrap();
+    // Too big for a datagram
+    let big = vec![0; relay::MAX_PAYLOAD + 1];
+    publisher.on_block(&block(3, 1, 1, &big)).unwrap();
+    assert_eq!(publisher.record(), 5);
+
+    let mut buf = [0_u8; 65_536];
+    let mut packets = Vec::new();
+    for _ in 0..4 {
+        let n = receiver.recv(&mut buf).unwrap();
+        packets.push(buf[..n].to_vec());
+    }
+    let packets: Vec<_> = packets.iter().map(|p| relay::parse(p).unwrap()).collect();
+    assert_eq!(
+        packets,
+        vec![
+            Packet::Block {
+                record: 1,
+                header: sessioned.header.clone(),
+                payload: b"ab",
+            },
+            Packet::Gap {
+                record: 2,
+                channel: 2,
+                range: 12..15,
+            },
+            Packet::Block {
+                record: 3,
+                header: block(3, 0, 1, b"").header,
+                payload: b"",
+            },
+            Packet::Gap {
+                record: 4,
+                channel: 3,
+                range: 1..2,
+            },
+        ]
+    );
+}
+
+#[test]
+fn relay_rejects_short_and_unknown_packets() {
+    let mut buf = Vec::new();
+    relay::write_gap(&mut buf, 1, 0, 0..1);
+    assert_eq!(
+        relay::parse(&buf[..buf.len() - 1]),
+        Err(ParseError::Truncated)
+    );
+    buf[10] = 9;
+    assert_eq!(relay::parse(&buf), Err(ParseError::Invalid));
+}
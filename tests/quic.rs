@@ -0,0 +1,115 @@
+#![cfg(feature = "quic")]
+
+use crossbeam_channel::unbounded;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use quinn::{Endpoint, ServerConfig};
+use sequencer::protocol::{moldudp64, Protocol};
+use sequencer::quic::{self, QuicFeed, QuicFeedConfig};
+use sequencer::shutdown::Shutdown;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+
+const SESSION: [u8; 10] = *b"SESSION001";
+
+fn moldudp64(seqnum: u64, msg: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    moldudp64::write_header(&mut buf, &SESSION, seqnum, 1);
+    moldudp64::write_message(&mut buf, msg);
+    buf
+}
+
+// A publisher for "localhost" sending `datagrams` to the first client, which
+// it waits for to close the connection
+fn publisher(datagrams: Vec<Vec<u8>>) -> (SocketAddr, CertificateDer<'static>) {
+    let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = key.cert.der().clone();
+    let private = PrivatePkcs8KeyDer::from(key.signing_key.serialize_der());
+    let config =
+        ServerConfig::with_single_cert(vec![cert.clone()], PrivateKeyDer::Pkcs8(private)).unwrap();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let endpoint = Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+            addr_tx.send(endpoint.local_addr().unwrap()).unwrap();
+            // Fails if the client doesn't trust it
+            let Ok(connection) = endpoint.accept().await.unwrap().await else {
+                return;
+            };
+            for datagram in datagrams {
+                connection.send_datagram(datagram.into()).unwrap();
+            }
+            connection.closed().await;
+        });
+    });
+    (addr_rx.recv().unwrap(), cert)
+}
+
+fn config(server: SocketAddr, cert: CertificateDer<'static>) -> QuicFeedConfig {
+    QuicFeedConfig {
+        server,
+        server_name: "localhost".to_string(),
+        roots: vec![cert],
+        protocol: Protocol::MoldUdp64,
+        channel: 3,
+    }
+}
+
+#[test]
+fn receives_datagrams_as_blocks() {
+    let (addr, cert) = publisher(vec![moldudp64(1, b"abc"), vec![0; 4], moldudp64(2, b"de")]);
+    let mut feed = QuicFeed::connect(&config(addr, cert)).unwrap();
+    assert_eq!(feed.remote_addr(), addr);
+
+    let mut blocks = Vec::new();
+    while blocks.len() < 2 {
+        blocks.extend(feed.recv().unwrap());
+    }
+    let received: Vec<_> = blocks
+        .iter()
+        .map(|b| (b.header.channel, b.header.seqnum, b.header.n_messages))
+        .collect();
+    assert_eq!(received, [(3, 1, 1), (3, 2, 1)]);
+    assert!(blocks.iter().all(|b| b.received.is_some()));
+    assert_eq!(&blocks[1].payload[..], &moldudp64(2, b"de")[20..]);
+}
+
+#[test]
+fn runs_until_shutdown() {
+    let (addr, cert) = publisher((1..=4).map(|i| moldudp64(i, b"x")).collect());
+    let mut feed = QuicFeed::connect(&config(addr, cert)).unwrap();
+    let shutdown = Shutdown::default();
+    feed.set_shutdown(shutdown.clone());
+    let (sender, receiver) = unbounded();
+    let thread = thread::spawn(move || feed.run(sender));
+
+    let seqnums: Vec<_> = (0..4)
+        .map(|_| receiver.recv().unwrap().header.seqnum)
+        .collect();
+    assert_eq!(seqnums, [1, 2, 3, 4]);
+    shutdown.request();
+    thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn verifies_the_server() {
+    let (addr, _) = publisher(Vec::new());
+    // Signed by someone else
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let err = QuicFeed::connect(&config(addr, other.cert.der().clone()))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+    let path = std::env::temp_dir().join(format!("sequencer-quic-ca-{}.pem", std::process::id()));
+    std::fs::write(&path, other.cert.pem()).unwrap();
+    assert_eq!(quic::load_certs(&path).unwrap(), [other.cert.der().clone()]);
+    std::fs::write(&path, "not a certificate").unwrap();
+    assert!(quic::load_certs(&path).unwrap().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,107 @@
+use sequencer::recovery::TcpSnapshotSource;
+use sequencer::{Block, BlockHeader, Payload, SequencedEvent, Sequencer};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn block(seqnum: u64) -> Block<Payload> {
+    let header = BlockHeader {
+        channel: 3,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, b"x".to_vec().into())
+}
+
+fn frame(seqnum: u64, payload: &[u8]) -> Vec<u8> {
+    let mut f = ((10 + payload.len()) as u16).to_be_bytes().to_vec();
+    f.extend_from_slice(&seqnum.to_be_bytes());
+    f.extend_from_slice(&1_u16.to_be_bytes());
+    f.extend_from_slice(payload);
+    f
+}
+
+#[test]
+fn resyncs_from_snapshot_when_gap_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut channel = [0; 4];
+        stream.read_exact(&mut channel).unwrap();
+        assert_eq!(u32::from_be_bytes(channel), 3);
+        let mut response = 4_u64.to_be_bytes().to_vec();
+        response.extend(frame(0, b"book"));
+        response.extend(0_u16.to_be_bytes());
+        stream.write_all(&response).unwrap();
+    });
+
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_snapshot_source(Box::new(TcpSnapshotSource::new(
+        addr,
+        Duration::from_secs(5),
+    )));
+    let received = Instant::now() - Duration::from_millis(50);
+    sequencer.push(block(0));
+    for seqnum in [3, 4, 5] {
+        let mut b = block(seqnum);
+        b.received = Some(received);
+        sequencer.push(b);
+    }
+    sequencer.poll_timeouts();
+    server.join().unwrap();
+    assert_eq!(sequencer.seqnum(3), 6);
+    assert_eq!(sequencer.pending(), 0);
+    assert_eq!(sequencer.stats().resyncs, 1);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 4);
+    match &events[1] {
+        SequencedEvent::Resynced {
+            channel,
+            seqnum,
+            snapshot,
+        } => {
+            assert_eq!((*channel, *seqnum), (3, 4));
+            assert_eq!(snapshot.len(), 1);
+            assert_eq!(snapshot[0].payload, b"book");
+        }
+        e => panic!("expected a resync, got {:?}", e),
+    }
+    // 3 is covered by the snapshot
+    let seqnums: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            SequencedEvent::Block(b) => Some(b.header.seqnum),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(seqnums, vec![0, 4, 5]);
+}
+
+#[test]
+fn skips_gap_when_snapshot_server_is_down() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_snapshot_source(Box::new(TcpSnapshotSource::new(
+        addr,
+        Duration::from_secs(1),
+    )));
+    let mut b = block(2);
+    b.received = Some(Instant::now() - Duration::from_millis(50));
+    sequencer.push(b);
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(3), 3);
+    assert_eq!(sequencer.stats().resyncs, 0);
+    drop(sequencer);
+    assert!(matches!(
+        receiver.iter().next(),
+        Some(SequencedEvent::Gap { .. })
+    ));
+}
@@ -0,0 +1,85 @@
+use sequencer::arbiter::Arbiter;
+use sequencer::udp::RecvBatch;
+use sequencer::{Block, BlockHeader, SequencedEvent, Sequencer};
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn receives_queued_datagrams_in_batches() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for i in 0..10_u8 {
+        sender
+            .send_to(&[i; 3], receiver.local_addr().unwrap())
+            .unwrap();
+    }
+
+    let mut batch = RecvBatch::new(8);
+    assert_eq!(batch.capacity(), 8);
+    let mut received = Vec::new();
+    while received.len() < 10 {
+        let n = batch.recv(&receiver).unwrap();
+        assert!((1..=8).contains(&n));
+        for (data, src, _) in batch.iter() {
+            assert_eq!(src, sender.local_addr().unwrap());
+            received.push(data.to_vec());
+        }
+    }
+    let expected: Vec<_> = (0..10_u8).map(|i| vec![i; 3]).collect();
+    assert_eq!(received, expected);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn one_syscall_takes_whole_batch() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for i in 0..10_u8 {
+        sender
+            .send_to(&[i], receiver.local_addr().unwrap())
+            .unwrap();
+    }
+    let mut batch = RecvBatch::new(8);
+    assert_eq!(batch.recv(&receiver).unwrap(), 8);
+    assert_eq!(batch.recv(&receiver).unwrap(), 2);
+}
+
+#[test]
+fn arbiter_sequences_batches() {
+    let (sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(5));
+    let a = arbiter.add_batch_feed(4);
+    let b = arbiter.add_feed();
+    let feeds = thread::spawn(move || {
+        a.send(vec![block(0), block(1), block(3)]).unwrap();
+        b.send(block(2)).unwrap();
+        a.send(vec![block(4)]).unwrap();
+    });
+    let sequencer = arbiter.run();
+    feeds.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 5);
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .map(|e| match e {
+            SequencedEvent::Block(b) => b.header.seqnum,
+            e => panic!("unexpected {:?}", e),
+        })
+        .collect();
+    assert_eq!(seqnums, vec![0, 1, 2, 3, 4]);
+}
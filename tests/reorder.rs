@@ -0,0 +1,259 @@
+use proptest::prelude::*;
+use sequencer::reorder::{Action, Discarded, Header, Reorder};
+use std::collections::HashMap;
+
+const N: usize = 8;
+
+fn header(seqnum: u64, n_messages: u16) -> Header {
+    Header { seqnum, n_messages }
+}
+
+// A caller that keeps what it is told to hold, by slot, and checks every
+// action makes sense for what it has
+struct Caller {
+    reorder: Reorder<N>,
+    held: HashMap<usize, Header>,
+    // Delivered packets and gaps given up on, as the ranges they cover
+    stream: Vec<(u64, u64, bool)>,
+    missing: Vec<(u64, u64)>,
+    discarded: Vec<(u64, Discarded)>,
+}
+
+impl Caller {
+    fn new(next: u64, timeout: u64) -> Self {
+        Self {
+            reorder: Reorder::new(next, timeout),
+            held: HashMap::new(),
+            stream: Vec::new(),
+            missing: Vec::new(),
+            discarded: Vec::new(),
+        }
+    }
+
+    fn packet(&mut self, seqnum: u64, n_messages: u16, now: u64) {
+        let given = header(seqnum, n_messages);
+        let actions: Vec<_> = self.reorder.on_packet(given, now).collect();
+        self.take(actions, Some(given));
+    }
+
+    fn tick(&mut self, now: u64) {
+        let actions: Vec<_> = self.reorder.on_tick(now).collect();
+        self.take(actions, None);
+    }
+
+    fn take(&mut self, actions: Vec<Action>, mut given: Option<Header>) {
+        for action in actions {
+            match action {
+                Action::Deliver { seqnum, n_messages } => {
+                    let packet = match given.take_if(|g| g.seqnum == seqnum) {
+                        Some(g) => g,
+                        None => self.unhold(seqnum),
+                    };
+                    assert_eq!(packet.n_messages, n_messages);
+                    self.stream.push((seqnum, seqnum + n_messages as u64, true));
+                }
+                Action::Hold { seqnum } => {
+                    let packet = given.take().expect("held what wasn't given");
+                    assert_eq!(packet.seqnum, seqnum);
+                    let slot = Reorder::<N>::slot(seqnum);
+                    assert!(self.held.insert(slot, packet).is_none(), "slot in use");
+                }
+                Action::Missing { from, to } => self.missing.push((from, to)),
+                Action::Discard { seqnum, reason } => {
+                    if given.take_if(|g| g.seqnum == seqnum).is_none() {
+                        self.unhold(seqnum);
+                    }
+                    self.discarded.push((seqnum, reason));
+                }
+                Action::Gap { from, to } => self.stream.push((from, to, false)),
+            }
+        }
+        assert_eq!(given, None, "nothing done with the packet given");
+        assert_eq!(self.held.len(), self.reorder.held());
+    }
+
+    fn unhold(&mut self, seqnum: u64) -> Header {
+        let slot = Reorder::<N>::slot(seqnum);
+        let packet = self.held.remove(&slot).expect("not held");
+        assert_eq!(packet.seqnum, seqnum);
+        packet
+    }
+
+    fn delivered(&self) -> Vec<u64> {
+        let delivered = self.stream.iter().filter(|(_, _, d)| *d);
+        delivered.map(|(from, _, _)| *from).collect()
+    }
+
+    // Delivered packets and gaps follow on from each other
+    fn check_stream(&self, start: u64) {
+        let mut next = start;
+        for (from, to, _) in &self.stream {
+            assert_eq!(*from, next);
+            next = *to;
+        }
+        assert_eq!(next, self.reorder.next_seqnum());
+    }
+}
+
+fn permutations(items: &[u64]) -> Vec<Vec<u64>> {
+    if items.is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut all = Vec::new();
+    for (i, first) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut p in permutations(&rest) {
+            p.insert(0, *first);
+            all.push(p);
+        }
+    }
+    all
+}
+
+#[test]
+fn delivers_every_arrival_order_in_order() {
+    for order in permutations(&[1, 2, 3, 4, 5, 6]) {
+        let mut caller = Caller::new(1, 100);
+        for seqnum in &order {
+            caller.packet(*seqnum, 1, 0);
+            // A second feed's copy is a duplicate
+            caller.packet(*seqnum, 1, 0);
+        }
+        assert_eq!(caller.delivered(), [1, 2, 3, 4, 5, 6], "{:?}", order);
+        assert_eq!(caller.discarded.len(), 6);
+        assert_eq!(caller.reorder.held(), 0);
+        assert_eq!(caller.reorder.deadline(), None);
+        caller.check_stream(1);
+    }
+}
+
+#[test]
+fn every_order_of_multi_message_packets_is_delivered_whole() {
+    let sizes: HashMap<u64, u16> = [(0, 2), (2, 1), (3, 3), (6, 1)].into();
+    for order in permutations(&[0, 2, 3, 6]) {
+        let mut caller = Caller::new(0, 100);
+        for seqnum in &order {
+            caller.packet(*seqnum, sizes[seqnum], 0);
+        }
+        assert_eq!(caller.delivered(), [0, 2, 3, 6]);
+        assert_eq!(caller.reorder.next_seqnum(), 7);
+        caller.check_stream(0);
+    }
+}
+
+#[test]
+fn reports_each_hole_once_as_it_opens() {
+    let mut caller = Caller::new(0, 100);
+    for seqnum in [0, 3, 2, 6, 5] {
+        caller.packet(seqnum, 1, 0);
+    }
+    assert_eq!(caller.missing, [(1, 3), (4, 6)]);
+    assert_eq!(caller.reorder.next_seqnum(), 1);
+}
+
+#[test]
+fn gives_up_on_gaps_once_held_packets_time_out() {
+    let mut caller = Caller::new(0, 10);
+    caller.packet(0, 1, 0);
+    caller.packet(2, 1, 0);
+    caller.packet(5, 1, 4);
+    caller.packet(6, 1, 4);
+    assert_eq!(caller.reorder.deadline(), Some(10));
+    caller.tick(9);
+    assert_eq!(caller.delivered(), [0]);
+
+    caller.tick(10);
+    assert_eq!(caller.delivered(), [0, 2]);
+    assert_eq!(caller.reorder.deadline(), Some(14));
+    caller.tick(14);
+    assert_eq!(caller.delivered(), [0, 2, 5, 6]);
+    assert_eq!(
+        caller
+            .stream
+            .iter()
+            .filter(|(_, _, d)| !d)
+            .collect::<Vec<_>>(),
+        [&(1, 2, false), &(3, 5, false)]
+    );
+    caller.check_stream(0);
+    // Too late
+    caller.packet(1, 1, 15);
+    assert_eq!(caller.discarded, [(1, Discarded::Duplicate)]);
+}
+
+#[test]
+fn discards_what_it_has_no_room_or_use_for() {
+    let mut caller = Caller::new(0, 10);
+    // One past the last slot
+    caller.packet(N as u64, 1, 0);
+    caller.packet(3, 0, 0);
+    // Overlapped by the packet before it, which is delivered first
+    caller.packet(2, 2, 0);
+    caller.packet(0, 3, 0);
+    assert_eq!(
+        caller.discarded,
+        [
+            (N as u64, Discarded::Overflow),
+            (3, Discarded::Empty),
+            (2, Discarded::Duplicate)
+        ]
+    );
+    assert_eq!(caller.delivered(), [0]);
+    assert_eq!(caller.reorder.held(), 0);
+
+    caller.packet(u64::MAX - 1, 2, 0);
+    assert_eq!(
+        caller.discarded.last(),
+        Some(&(u64::MAX - 1, Discarded::Invalid))
+    );
+    assert_eq!(caller.reorder.next_seqnum(), 3);
+}
+
+#[test]
+fn actions_not_taken_are_left_for_later() {
+    let mut reorder = Reorder::<N>::new(0, 10);
+    let missing = Action::Missing { from: 0, to: 1 };
+    assert!(reorder
+        .on_packet(header(1, 1), 0)
+        .eq([missing, Action::Hold { seqnum: 1 }]));
+    let mut actions = reorder.on_packet(header(0, 1), 0);
+    assert_eq!(
+        actions.next(),
+        Some(Action::Deliver {
+            seqnum: 0,
+            n_messages: 1
+        })
+    );
+    drop(actions);
+    let next = reorder.on_tick(0).collect::<Vec<_>>();
+    assert_eq!(
+        next,
+        [Action::Deliver {
+            seqnum: 1,
+            n_messages: 1
+        }]
+    );
+}
+
+proptest! {
+    // Whatever arrives when, the stream has no holes or overlaps and the
+    // caller is never told to do what it can't
+    #[test]
+    fn stream_is_whole(
+        arrivals in prop::collection::vec((0_u64..24, 1_u16..3, 0_u64..4, any::<bool>()), 1..100)
+    ) {
+        let mut caller = Caller::new(0, 8);
+        let mut now = 0;
+        for (seqnum, n_messages, elapsed, tick) in arrivals {
+            now += elapsed;
+            caller.packet(seqnum, n_messages, now);
+            if tick {
+                caller.tick(now);
+            }
+        }
+        caller.tick(u64::MAX);
+        prop_assert_eq!(caller.reorder.held(), 0);
+        caller.check_stream(0);
+    }
+}
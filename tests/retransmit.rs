@@ -0,0 +1,70 @@
+use sequencer::gapfill::{GapFiller, TcpGapFiller};
+use sequencer::journal::{self, JournalWriter};
+use sequencer::retransmit::{RetransmitConfig, RetransmitServer};
+use sequencer::BlockHeader;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn serves_journaled_ranges() {
+    let path = std::env::temp_dir().join(format!("retransmit-{}.journal", std::process::id()));
+    let mut writer =
+        JournalWriter::create(&path, &journal::session("OUT"), Some(Duration::ZERO)).unwrap();
+    let mut append = |channel, seqnum, n_messages| {
+        let header = BlockHeader {
+            channel,
+            seqnum,
+            n_messages,
+            ..Default::default()
+        };
+        writer
+            .append(&header, SystemTime::now(), &[seqnum as u8; 2])
+            .unwrap();
+        writer.flush().unwrap();
+    };
+    // Seqnums 1..11 of channel 0 with a 3 message block at 5, channel 1
+    // interleaved
+    for seqnum in [1, 2, 3, 4, 5, 8, 9, 10] {
+        let n_messages = if seqnum == 5 { 3 } else { 1 };
+        append(0, seqnum, n_messages);
+        append(1, seqnum, 1);
+    }
+
+    let config = RetransmitConfig {
+        bytes_per_sec: Some(1_000_000),
+        ..Default::default()
+    };
+    let server = RetransmitServer::bind("127.0.0.1:0".parse().unwrap(), &path, config).unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn().unwrap();
+
+    let fill = move |channel, range| {
+        let mut filler = TcpGapFiller::new(addr, 0, Duration::from_secs(5));
+        let blocks = filler.fill(channel, range).unwrap();
+        blocks
+            .iter()
+            .map(|b| (b.header.seqnum, b.header.n_messages, b.payload.to_vec()))
+            .collect::<Vec<_>>()
+    };
+    // Start inside a multi-message block
+    assert_eq!(fill(0, 6..9), vec![(5, 3, vec![5, 5]), (8, 1, vec![8, 8])]);
+    assert_eq!(
+        fill(1, 9..20),
+        vec![(9, 1, vec![9, 9]), (10, 1, vec![10, 10])]
+    );
+    assert_eq!(fill(0, 20..30), vec![]);
+
+    // Clients are served at once and see records appended since the server
+    // started
+    append(0, 11, 1);
+    let clients: Vec<_> = (0..4)
+        .map(|_| thread::spawn(move || fill(0, 10..12)))
+        .collect();
+    for c in clients {
+        assert_eq!(
+            c.join().unwrap(),
+            vec![(10, 1, vec![10, 10]), (11, 1, vec![11, 11])]
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,93 @@
+#![cfg(unix)]
+use sequencer::sandbox::{self, User};
+use std::env;
+use std::io;
+use std::net::UdpSocket;
+use std::process::Command;
+
+// Set in the copy of this test binary the sandbox is applied to, so it
+// doesn't reach the other tests
+const CHILD: &str = "SANDBOX_TEST_CHILD";
+
+#[test]
+fn looks_up_users() {
+    let root = User::lookup("root").unwrap();
+    assert_eq!((root.uid, root.gid), (0, 0));
+    let err = User::lookup("no-such-user-here").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(User::lookup("nul\0").is_err());
+}
+
+#[test]
+fn switching_to_the_current_user_changes_nothing() {
+    // SAFETY: these only read the process's credentials
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let user = User {
+        name: "whoever".to_string(),
+        uid,
+        gid,
+    };
+    user.switch().unwrap();
+    assert_eq!(unsafe { libc::geteuid() }, uid);
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn seccomp_refuses_exec_but_not_sockets() {
+    if env::var_os(CHILD).is_some() {
+        sandbox::seccomp().unwrap();
+        // From a thread spawned before and one spawned after
+        let before = std::thread::spawn(|| Command::new("true").status());
+        let err = before.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        let after = std::thread::spawn(|| Command::new("true").status());
+        assert!(after.join().unwrap().is_err());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(b"x", socket.local_addr().unwrap()).unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(socket.recv(&mut buf).unwrap(), 1);
+        return;
+    }
+    let status = Command::new(env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "seccomp_refuses_exec_but_not_sockets",
+            "--test-threads=1",
+        ])
+        .env(CHILD, "1")
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn root_switches_for_good() {
+    // SAFETY: reads the process's credentials
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let nobody = match User::lookup("nobody") {
+        Ok(nobody) => nobody,
+        Err(_) => return,
+    };
+    if env::var_os(CHILD).is_some() {
+        nobody.switch().unwrap();
+        // SAFETY: as above
+        assert_eq!(
+            unsafe { (libc::getuid(), libc::geteuid()) },
+            (nobody.uid, nobody.uid)
+        );
+        assert_eq!(unsafe { libc::getgid() }, nobody.gid);
+        assert_ne!(unsafe { libc::setuid(0) }, 0);
+        return;
+    }
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["--exact", "root_switches_for_good", "--test-threads=1"])
+        .env(CHILD, "1")
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
@@ -0,0 +1,180 @@
+use sequencer::journal::{self, JournalReader, JournalWriter, Rotation, Segments};
+use sequencer::BlockHeader;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// 2023-11-14T22:13:20Z
+const TS: u64 = 1_700_000_000;
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("segments-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    dir
+}
+
+fn append(writer: &mut JournalWriter, seqnum: u64) {
+    let header = BlockHeader {
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    let ts = UNIX_EPOCH + Duration::from_secs(TS);
+    writer.append(&header, ts, b"abcd").unwrap();
+}
+
+fn files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+fn seqnums(path: &Path) -> Vec<u64> {
+    JournalReader::open(path)
+        .unwrap()
+        .map(|r| r.unwrap().seqnum)
+        .collect()
+}
+
+#[test]
+fn rolls_by_size() {
+    let dir = dir("size");
+    let path = dir.join("messages.journal");
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    // Two records each
+    let record = 4 + journal::RECORD_HEADER_LEN as u64 + 4;
+    writer.set_segments(Segments {
+        max_bytes: Some(journal::FILE_HEADER_LEN as u64 + 2 * record),
+        ..Default::default()
+    });
+    for seqnum in 1..=5 {
+        append(&mut writer, seqnum);
+    }
+    writer.sync().unwrap();
+    assert_eq!(
+        files(&dir),
+        [
+            "messages-DAY1-20231114-1.journal",
+            "messages-DAY1-20231114-3.journal",
+            "messages.journal"
+        ]
+    );
+    assert_eq!(
+        seqnums(&dir.join("messages-DAY1-20231114-3.journal")),
+        [3, 4]
+    );
+    assert_eq!(seqnums(&path), [5]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rolls_by_time() {
+    let dir = dir("time");
+    let path = dir.join("messages.journal");
+    let mut writer = JournalWriter::create(&path, &journal::session(""), None).unwrap();
+    writer.set_segments(Segments {
+        max_age: Some(Duration::ZERO),
+        ..Default::default()
+    });
+    for seqnum in 1..=3 {
+        append(&mut writer, seqnum);
+    }
+    writer.sync().unwrap();
+    // No session in the names of a journal without one
+    assert_eq!(
+        files(&dir),
+        [
+            "messages-20231114-1.journal",
+            "messages-20231114-2.journal",
+            "messages.journal"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rotations_are_named_like_segments() {
+    let dir = dir("rotate");
+    let path = dir.join("messages.journal");
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    writer.set_segments(Segments::default());
+    let rotation = Rotation::default();
+    writer.set_rotation(rotation.clone());
+    for _ in 0..2 {
+        append(&mut writer, 1);
+        rotation.request();
+    }
+    append(&mut writer, 2);
+    writer.sync().unwrap();
+    // The same first seqnum on the same day
+    assert_eq!(
+        files(&dir),
+        [
+            "messages-DAY1-20231114-1-1.journal",
+            "messages-DAY1-20231114-1.journal",
+            "messages.journal"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn retires_old_segments() {
+    let dir = dir("retire");
+    let path = dir.join("messages.journal");
+    for name in [
+        "messages-DAY1-20231114-1.journal",
+        "messages-DAY1-20231113-1.journal.zst",
+        "other-DAY1-20231114-1.journal",
+        "messages.journal",
+    ] {
+        fs::write(dir.join(name), b"").unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(10));
+    let keep = Segments {
+        retention: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    assert!(journal::retire_segments(&path, &keep).unwrap().is_empty());
+
+    let retire = Segments {
+        retention: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let retired = journal::retire_segments(&path, &retire).unwrap();
+    assert_eq!(retired, [dir.join("messages-DAY1-20231114-1.journal")]);
+    assert_eq!(
+        files(&dir),
+        [
+            "messages-DAY1-20231113-1.journal.zst",
+            "messages.journal",
+            "other-DAY1-20231114-1.journal"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compresses_retired_segments() {
+    let dir = dir("compress");
+    let path = dir.join("messages.journal");
+    let segment = dir.join("messages-DAY1-20231114-1.journal");
+    fs::write(&segment, b"").unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let mut segments = Segments {
+        retention: Some(Duration::ZERO),
+        compress: Some("false".to_string()),
+        ..Default::default()
+    };
+    assert!(journal::retire_segments(&path, &segments).is_err());
+    // Left to the command rather than deleted
+    segments.compress = Some("true".to_string());
+    let retired = journal::retire_segments(&path, &segments).unwrap();
+    assert_eq!(retired, [dir.join("messages-DAY1-20231114-1.journal")]);
+    assert!(segment.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
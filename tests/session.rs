@@ -0,0 +1,198 @@
+use sequencer::journal::{self, JournalReader, JournalWriter};
+use sequencer::protocol::{moldudp64, Protocol};
+use sequencer::session::{ExitAtSessionEnd, TimeOfDay};
+use sequencer::shutdown::Shutdown;
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, GapReason, Payload, SequencedEvent, Sequencer, Session};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn block(session: &str, seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        session: journal::session(session),
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+fn end_of_session(session: &str, seqnum: u64) -> Block<Vec<u8>> {
+    let mut b = block(session, seqnum);
+    b.header.n_messages = 0;
+    b.header.end_of_session = true;
+    b.payload.clear();
+    b
+}
+
+#[test]
+fn moldudp64_end_of_session_is_marked() {
+    let mut buf = Vec::new();
+    let session = journal::session("DAY1");
+    moldudp64::write_header(&mut buf, &session, 42, moldudp64::END_OF_SESSION);
+    let (header, payload) = Protocol::MoldUdp64.parse(&buf).unwrap();
+    assert!(header.end_of_session);
+    assert_eq!((header.seqnum, header.n_messages), (42, 0));
+    assert!(payload.is_empty());
+
+    buf.clear();
+    moldudp64::write_header(&mut buf, &session, 42, 0);
+    assert!(!Protocol::MoldUdp64.parse(&buf).unwrap().0.end_of_session);
+}
+
+#[test]
+fn end_of_session_marker_ends_the_channel() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(1);
+    sequencer.push_from(0, block("DAY1", 1));
+    sequencer.push_from(0, block("DAY1", 3));
+    sequencer.push_from(0, end_of_session("DAY1", 5));
+    // Feed 1 is behind, its copies of the old session are stale
+    sequencer.push_from(1, block("DAY1", 4));
+    sequencer.push_from(1, end_of_session("DAY1", 5));
+    sequencer.push_from(0, block("DAY2", 1));
+    assert_eq!(sequencer.seqnum(0), 2);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    let gap = |from, to| SequencedEvent::Gap {
+        channel: 0,
+        from,
+        to,
+        reason: GapReason::SessionReset,
+    };
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block("DAY1", 1)),
+            gap(2, 3),
+            SequencedEvent::Block(block("DAY1", 3)),
+            gap(4, 5),
+            SequencedEvent::EndOfSession {
+                channel: 0,
+                session: journal::session("DAY1"),
+                seqnum: 5,
+            },
+            SequencedEvent::Block(block("DAY2", 1)),
+        ]
+    );
+}
+
+#[test]
+fn end_sessions_starts_channels_over() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.push(block("", 0));
+    sequencer.push(block("", 1));
+    assert_eq!(sequencer.end_sessions(), 1);
+    assert_eq!(sequencer.end_sessions(), 0);
+    sequencer.push(block("", 0));
+    drop(sequencer);
+
+    let seqnums: Vec<_> = receiver
+        .iter()
+        .filter_map(|e| match e {
+            SequencedEvent::Block(b) => Some(b.header.seqnum),
+            SequencedEvent::EndOfSession { seqnum, .. } => Some(100 + seqnum),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(seqnums, vec![0, 1, 102, 0]);
+}
+
+#[test]
+fn parses_times_of_day() {
+    assert_eq!("16:30".parse(), Ok(TimeOfDay::new(16, 30, 0).unwrap()));
+    assert_eq!("00:00:59".parse(), Ok(TimeOfDay::new(0, 0, 59).unwrap()));
+    assert!("24:00".parse::<TimeOfDay>().is_err());
+    assert!("16".parse::<TimeOfDay>().is_err());
+    assert!("4pm".parse::<TimeOfDay>().is_err());
+}
+
+#[test]
+fn waits_until_the_next_time_of_day() {
+    let at = TimeOfDay::new(16, 30, 0).unwrap();
+    let day = UNIX_EPOCH + Duration::from_secs(20_000 * 24 * 3600);
+    let hours = |h: u64| Duration::from_secs(h * 3600);
+    assert_eq!(at.until_next(day + hours(16)), Duration::from_secs(1800));
+    assert_eq!(
+        at.until_next(day + hours(17)),
+        hours(23) + Duration::from_secs(1800)
+    );
+    assert_eq!(
+        at.until_next(day + hours(16) + Duration::from_secs(1800)),
+        hours(24)
+    );
+}
+
+#[test]
+fn exits_once_every_channel_ended() {
+    let shutdown = Shutdown::default();
+    let mut exit = ExitAtSessionEnd::new(shutdown.clone());
+    let session: Session = journal::session("DAY1");
+    for channel in [0, 1] {
+        let mut b: Block<Payload> = Block::new(BlockHeader::default(), vec![0].into());
+        b.header.channel = channel;
+        exit.on_block(&b).unwrap();
+    }
+    exit.on_end_of_session(0, session, 10).unwrap();
+    assert!(!shutdown.requested());
+    exit.on_end_of_session(0, session, 10).unwrap();
+    assert!(!shutdown.requested());
+    exit.on_end_of_session(1, session, 10).unwrap();
+    assert!(shutdown.requested());
+}
+
+#[test]
+fn journal_rotates_once_every_channel_ended() {
+    let path = std::env::temp_dir().join(format!("session-{}.journal", std::process::id()));
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    for channel in [0, 1] {
+        let header = BlockHeader {
+            channel,
+            seqnum: 1,
+            n_messages: 1,
+            ..Default::default()
+        };
+        writer.append(&header, SystemTime::now(), b"x").unwrap();
+    }
+    assert_eq!(writer.end_session(0).unwrap(), None);
+    let rotated = writer.end_session(1).unwrap().unwrap();
+    drop(writer);
+
+    let day = JournalReader::open(&rotated).unwrap().count();
+    let next = JournalReader::open(&path).unwrap().count();
+    std::fs::remove_file(&rotated).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((day, next), (2, 0));
+}
+
+#[test]
+fn seqnums_that_would_overflow_are_dropped() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_first_seqnum(1);
+    sequencer.push_from(0, block("DAY1", 1));
+    let mut last = block("DAY1", u64::MAX - 1);
+    last.header.n_messages = 2;
+    sequencer.push_from(0, last);
+    sequencer.push_from(0, block("DAY1", 2));
+    assert_eq!(sequencer.stats().feeds[0].malformed, 1);
+    assert_eq!(sequencer.seqnum(0), 3);
+    drop(sequencer);
+    let seqnums: Vec<_> = receiver
+        .try_iter()
+        .filter_map(SequencedEvent::into_block)
+        .map(|b| b.header.seqnum)
+        .collect();
+    assert_eq!(seqnums, [1, 2]);
+
+    // Nor does the journal overflow on a block ending at the last seqnum
+    let path = std::env::temp_dir().join(format!("session-last-{}", std::process::id()));
+    let mut writer = JournalWriter::create(&path, &journal::session("DAY1"), None).unwrap();
+    let header = BlockHeader {
+        seqnum: u64::MAX,
+        n_messages: 1,
+        ..Default::default()
+    };
+    writer.append(&header, UNIX_EPOCH, b"x").unwrap();
+    drop(writer);
+    std::fs::remove_file(&path).unwrap();
+}
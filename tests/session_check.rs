@@ -0,0 +1,132 @@
+use crossbeam_channel::Receiver;
+use sequencer::clock::MockClock;
+use sequencer::journal;
+use sequencer::{Block, BlockHeader, SequencedEvent, Sequencer};
+use std::time::Duration;
+
+const WINDOW: Duration = Duration::from_millis(100);
+
+fn block(session: &str, seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        session: journal::session(session),
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn sequencer() -> (Sequencer<Block<Vec<u8>>>, Receiver<Event>, MockClock) {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    let clock = MockClock::new();
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_first_seqnum(1);
+    sequencer.set_session_check(Some(WINDOW));
+    (sequencer, receiver, clock)
+}
+
+fn is_check(event: &Event) -> bool {
+    matches!(
+        event,
+        SequencedEvent::SessionsDiverged { .. } | SequencedEvent::SessionsAgreed { .. }
+    )
+}
+
+#[test]
+fn diverged_feeds_are_not_sequenced_until_they_agree() {
+    let (mut sequencer, receiver, clock) = sequencer();
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    for seqnum in 1..=2 {
+        sequencer.push_from(a, block("SESSION001", seqnum));
+        sequencer.push_from(b, block("SESSION002", seqnum));
+        clock.advance(WINDOW * 3 / 5);
+    }
+    // Both have stuck to their own sessions for the window
+    sequencer.push_from(a, block("SESSION001", 3));
+    sequencer.push_from(b, block("SESSION002", 3));
+    assert_eq!(sequencer.diverged().collect::<Vec<_>>(), [0]);
+    // B rejoins A's session
+    sequencer.push_from(b, block("SESSION001", 4));
+    sequencer.push_from(a, block("SESSION001", 5));
+    assert_eq!(sequencer.diverged().count(), 0);
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    let checks: Vec<_> = events.iter().filter(|e| is_check(e)).collect();
+    let diverged = SequencedEvent::SessionsDiverged {
+        channel: 0,
+        sessions: vec![
+            (a, journal::session("SESSION001")),
+            (b, journal::session("SESSION002")),
+        ],
+    };
+    let agreed = SequencedEvent::SessionsAgreed {
+        channel: 0,
+        session: journal::session("SESSION001"),
+    };
+    assert_eq!(checks, [&diverged, &agreed]);
+    let agreed_at = events.iter().position(|e| *e == agreed).unwrap();
+    let after: Vec<_> = events[agreed_at..]
+        .iter()
+        .filter_map(|e| e.clone().into_block())
+        .collect();
+    assert_eq!(
+        after,
+        [block("SESSION001", 4), block("SESSION001", 5)],
+        "carried on in the agreed session"
+    );
+    assert_eq!(stats.diverged, 2);
+}
+
+#[test]
+fn feeds_moving_to_a_new_session_together_agree() {
+    let (mut sequencer, receiver, clock) = sequencer();
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    sequencer.push_from(a, block("SESSION001", 1));
+    sequencer.push_from(b, block("SESSION001", 1));
+    clock.advance(WINDOW * 2);
+    // A rolls over first, B within the window
+    sequencer.push_from(a, block("SESSION002", 1));
+    clock.advance(WINDOW / 2);
+    sequencer.push_from(b, block("SESSION001", 2));
+    sequencer.push_from(b, block("SESSION002", 1));
+    clock.advance(WINDOW * 2);
+    sequencer.push_from(a, block("SESSION002", 2));
+    sequencer.push_from(b, block("SESSION002", 2));
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    assert!(!receiver.iter().any(|e| is_check(&e)));
+    assert_eq!((stats.diverged, stats.resets), (0, 1));
+}
+
+#[test]
+fn a_feed_left_in_the_old_session_diverges() {
+    let (mut sequencer, receiver, clock) = sequencer();
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    sequencer.push_from(a, block("SESSION001", 1));
+    sequencer.push_from(b, block("SESSION001", 1));
+    sequencer.push_from(a, block("SESSION002", 1));
+    for seqnum in 2..=4 {
+        clock.advance(WINDOW / 2);
+        sequencer.push_from(a, block("SESSION002", seqnum));
+        sequencer.push_from(b, block("SESSION001", seqnum));
+    }
+    drop(sequencer);
+
+    let checks: Vec<_> = receiver.iter().filter(is_check).collect();
+    assert!(matches!(
+        &checks[..],
+        [SequencedEvent::SessionsDiverged { channel: 0, sessions }] if sessions.len() == 2
+    ));
+
+    // Not compared at all without a window
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.push_from(a, block("SESSION001", 0));
+    sequencer.push_from(b, block("SESSION002", 0));
+    drop(sequencer);
+    assert!(!receiver.iter().any(|e| is_check(&e)));
+}
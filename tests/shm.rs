@@ -0,0 +1,135 @@
+use sequencer::shm::{ShmEvent, ShmReader, ShmSink};
+use sequencer::sink::Sink;
+use sequencer::{Block, BlockHeader, GapReason, Payload};
+use std::path::PathBuf;
+use std::thread;
+
+fn block(channel: u32, seqnum: u64, payload: &[u8]) -> Block<Payload> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, payload.to_vec().into())
+}
+
+fn ring_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sequencer-shm-{}-{}", name, std::process::id()))
+}
+
+fn seqnum(event: ShmEvent) -> u64 {
+    match event {
+        ShmEvent::Block(r) => r.seqnum,
+        e => panic!("unexpected event {:?}", e),
+    }
+}
+
+#[test]
+fn readers_see_blocks_and_gaps_in_order() {
+    let path = ring_path("order");
+    let mut sink = ShmSink::create(&path, 16, 64).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+    assert_eq!(reader.try_read(), None);
+
+    sink.on_block(&block(2, 1, b"abc")).unwrap();
+    sink.on_gap(2, 2..5, GapReason::Timeout).unwrap();
+    sink.on_block(&block(2, 5, b"")).unwrap();
+    // Too big for a slot
+    sink.on_block(&block(2, 6, &[0; 1_000])).unwrap();
+    assert_eq!(sink.written(), 4);
+
+    match reader.try_read() {
+        Some(ShmEvent::Block(r)) => {
+            assert_eq!((r.channel, r.seqnum, r.n_messages), (2, 1, 1));
+            assert_eq!(r.payload, b"abc");
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+    let gap = ShmEvent::Gap {
+        channel: 2,
+        range: 2..5,
+    };
+    assert_eq!(reader.try_read(), Some(gap));
+    assert_eq!(seqnum(reader.try_read().unwrap()), 5);
+    let skipped = ShmEvent::Gap {
+        channel: 2,
+        range: 6..7,
+    };
+    assert_eq!(reader.try_read(), Some(skipped));
+    assert_eq!(reader.try_read(), None);
+    assert!(!reader.is_closed());
+
+    // Readers have their own cursors and start at the writer
+    let mut late = ShmReader::open(&path).unwrap();
+    assert_eq!(late.try_read(), None);
+    late.rewind();
+    assert_eq!(seqnum(late.try_read().unwrap()), 1);
+
+    drop(sink);
+    assert!(reader.is_closed());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn lapped_readers_are_told_what_they_missed() {
+    let path = ring_path("lapped");
+    let mut sink = ShmSink::create(&path, 4, 16).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+    for s in 0..10 {
+        sink.on_block(&block(0, s, b"x")).unwrap();
+    }
+    // 7..10 are still in the ring, 6's slot is the next to be overwritten
+    assert_eq!(reader.try_read(), Some(ShmEvent::Lagged { records: 7 }));
+    let rest: Vec<_> = std::iter::from_fn(|| reader.try_read())
+        .map(seqnum)
+        .collect();
+    assert_eq!(rest, vec![7, 8, 9]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn concurrent_reader_never_sees_a_torn_record() {
+    let path = ring_path("concurrent");
+    let mut sink = ShmSink::create(&path, 64, 64).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+    let writer = thread::spawn(move || {
+        for s in 0..20_000_u64 {
+            sink.on_block(&block(0, s, &s.to_le_bytes().repeat(4)))
+                .unwrap();
+        }
+    });
+    let (mut next, mut missed) = (0, 0);
+    loop {
+        match reader.try_read() {
+            Some(ShmEvent::Block(r)) => {
+                assert_eq!(r.seqnum, next);
+                assert_eq!(r.payload, r.seqnum.to_le_bytes().repeat(4));
+                next += 1;
+            }
+            Some(ShmEvent::Lagged { records }) => {
+                next += records;
+                missed += records;
+            }
+            Some(e) => panic!("unexpected event {:?}", e),
+            None if reader.is_closed() => match reader.try_read() {
+                None => break,
+                Some(_) => panic!("read after close returned None"),
+            },
+            None => thread::yield_now(),
+        }
+    }
+    writer.join().unwrap();
+    assert_eq!(next, 20_000);
+    assert!(missed < 20_000);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_files_that_are_not_rings() {
+    let path = ring_path("invalid");
+    std::fs::write(&path, [0_u8; 256]).unwrap();
+    let e = ShmReader::open(&path).err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,61 @@
+use sequencer::arbiter::Arbiter;
+use sequencer::shutdown::Shutdown;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+#[test]
+fn shutdown_drains_reorder_buffer() {
+    let (sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(5));
+    let shutdown = Shutdown::default();
+    arbiter.set_shutdown(shutdown.clone());
+    let s = arbiter.add_feed();
+    let feed = thread::spawn(move || {
+        for seqnum in [0, 2, 4] {
+            s.send(block(seqnum)).unwrap();
+        }
+        // Never hangs up on its own
+        while s.send(block(0)).is_ok() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+    let stop = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        shutdown.request();
+    });
+    let sequencer = arbiter.run();
+    stop.join().unwrap();
+    feed.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 5);
+    assert_eq!(sequencer.pending(), 0);
+    drop(sequencer);
+
+    let gap = |from, to| SequencedEvent::Gap {
+        channel: 0,
+        from,
+        to,
+        reason: GapReason::Shutdown,
+    };
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            SequencedEvent::Block(block(0)),
+            gap(1, 2),
+            SequencedEvent::Block(block(2)),
+            gap(3, 4),
+            SequencedEvent::Block(block(4)),
+        ]
+    );
+}
@@ -0,0 +1,38 @@
+use sequencer::sim::SimConfig;
+use std::time::Duration;
+
+#[test]
+fn schedules_are_seeded() {
+    let sim = SimConfig {
+        seed: 42,
+        loss: 0.1,
+        reorder_window: 3,
+        duplication: 0.05,
+        jitter: Duration::from_micros(10),
+        ..Default::default()
+    };
+    let blocks: Vec<u64> = (0..10_000).collect();
+    let a = sim.schedule(&blocks, 0);
+    assert_eq!(a, sim.schedule(&blocks, 0));
+    assert_ne!(a, sim.schedule(&blocks, 1));
+
+    let seqnums: Vec<u64> = a.iter().map(|(_, s)| *s).collect();
+    let mut unique = seqnums.clone();
+    unique.sort();
+    unique.dedup();
+    let lost = blocks.len() - unique.len();
+    let duplicated = seqnums.len() - unique.len();
+    assert!((800..1200).contains(&lost), "lost {}", lost);
+    assert!(
+        (300..700).contains(&duplicated),
+        "duplicated {}",
+        duplicated
+    );
+    // Nothing arrives more than the window late
+    for (i, s) in seqnums.iter().enumerate() {
+        let next = &seqnums[i..(i + 8 * sim.reorder_window).min(seqnums.len())];
+        let ahead = next.iter().filter(|later| *later < s).count();
+        assert!(ahead <= 2 * sim.reorder_window, "{} overtaken {}", s, ahead);
+    }
+    assert!(a.iter().all(|(d, _)| *d <= sim.jitter));
+}
@@ -0,0 +1,58 @@
+use sequencer::protocol::Protocol;
+use sequencer::udp::{self, FeedConfig};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
+
+fn config(port: u16) -> FeedConfig {
+    FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 3),
+        port,
+        protocol: Protocol::Raw,
+        channel: 0,
+        source: None,
+        reuse_port: true,
+        recv_buffer: Some(64 * 1024),
+    }
+}
+
+#[test]
+fn shares_port_and_sizes_receive_buffer() {
+    let a = udp::bind_multicast(&config(0)).unwrap();
+    let port = a.local_addr().unwrap().port();
+    let b = udp::bind_multicast(&config(port)).unwrap();
+    assert_eq!(b.local_addr().unwrap().port(), port);
+
+    let info = udp::socket_info(&b).unwrap();
+    assert!(info.reuse_address);
+    assert!(info.reuse_port);
+    assert!(info.recv_buffer >= 64 * 1024);
+}
+
+#[test]
+fn port_is_exclusive_without_reuse() {
+    let mut c = config(0);
+    c.reuse_port = false;
+    let a = udp::bind_multicast(&c).unwrap();
+    c.port = a.local_addr().unwrap().port();
+    assert!(udp::bind_multicast(&c).is_err());
+    assert!(!udp::socket_info(&a).unwrap().reuse_port);
+}
+
+#[test]
+fn source_specific_join_receives_from_source() {
+    let mut c = config(0);
+    c.source = Some(Ipv4Addr::LOCALHOST);
+    let receiver = udp::bind_multicast(&c).unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let dst = (c.group, receiver.local_addr().unwrap().port());
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.set_multicast_loop_v4(true).unwrap();
+    sender.send_to(b"hello", dst).unwrap();
+
+    let mut buf = [0; 64];
+    let (n, _) = receiver.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
@@ -0,0 +1,81 @@
+use crossbeam_channel::unbounded;
+use sequencer::journal;
+use sequencer::protocol::soupbintcp::{self, ClientPacket};
+use sequencer::soupbintcp::{SoupBinTcpConfig, SoupBinTcpSource};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+#[test]
+fn logs_in_and_streams_sequenced_packets() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let session = journal::session("S1");
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut login = vec![0; 2 + 1 + 6 + 10 + 10 + 20];
+        stream.read_exact(&mut login).unwrap();
+        match soupbintcp::parse_client(&login).unwrap() {
+            ClientPacket::LoginRequest {
+                username,
+                password,
+                seqnum,
+                ..
+            } => {
+                assert_eq!(username, b"user");
+                assert_eq!(password, b"pass");
+                assert_eq!(seqnum, 5);
+            }
+            p => panic!("{:?}", p),
+        }
+        let mut out = Vec::new();
+        soupbintcp::write_login_accepted(&mut out, &session, 5);
+        soupbintcp::write_sequenced(&mut out, b"first");
+        soupbintcp::write_server_heartbeat(&mut out);
+        soupbintcp::write_sequenced(&mut out, b"second");
+        soupbintcp::write_end_of_session(&mut out);
+        // Split mid packet to exercise reassembly
+        stream.write_all(&out[..7]).unwrap();
+        stream.flush().unwrap();
+        stream.write_all(&out[7..]).unwrap();
+    });
+
+    let config = SoupBinTcpConfig {
+        addr,
+        username: "user".to_string(),
+        password: "pass".to_string(),
+        session: journal::session(""),
+        seqnum: 5,
+        channel: 3,
+    };
+    let source = SoupBinTcpSource::connect(&config).unwrap();
+    assert_eq!(source.session(), &session);
+    let (sender, receiver) = unbounded();
+    source.run(sender).unwrap();
+    server.join().unwrap();
+
+    let blocks: Vec<_> = receiver.iter().collect();
+    let ended: Vec<_> = blocks.iter().map(|b| b.header.end_of_session).collect();
+    assert_eq!(ended, vec![false, false, false, true]);
+    let blocks: Vec<_> = blocks
+        .into_iter()
+        .map(|b| {
+            (
+                b.header.channel,
+                b.header.seqnum,
+                b.header.n_messages,
+                b.payload.to_vec(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        blocks,
+        vec![
+            (3, 5, 1, b"first".to_vec()),
+            (3, 6, 0, Vec::new()),
+            (3, 6, 1, b"second".to_vec()),
+            // End of session
+            (3, 7, 0, Vec::new()),
+        ]
+    );
+}
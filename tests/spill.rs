@@ -0,0 +1,113 @@
+use crossbeam_channel::Receiver;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn block(seqnum: u64, received: Instant) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 3,
+        session: *b"session 01",
+        seqnum,
+        n_messages: 1,
+        end_of_session: false,
+    };
+    let mut b = Block::new(header, seqnum.to_le_bytes().to_vec());
+    b.received = Some(received);
+    b
+}
+
+fn spill_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sequencer-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    dir
+}
+
+fn drain(sequencer: &mut Sequencer<Block<Vec<u8>>>, receiver: &Receiver<Event>) -> Vec<Event> {
+    let mut events = Vec::new();
+    loop {
+        let n = events.len();
+        events.extend(receiver.try_iter());
+        sequencer.poll_timeouts();
+        if events.len() == n && receiver.is_empty() {
+            return events;
+        }
+    }
+}
+
+#[test]
+fn spills_beyond_high_water_and_replays_in_order() {
+    let dir = spill_dir("replay");
+    let (mut sequencer, receiver) = Sequencer::with_spill(Duration::ZERO, 16, 2, &dir).unwrap();
+    // Unlinked as soon as it is created
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    let received = Instant::now();
+    let mut expected = Vec::new();
+    for seqnum in (0..100).filter(|&s| s != 50) {
+        sequencer.push(block(seqnum, received));
+        if seqnum == 51 {
+            // The gap times out straight away and waits in memory
+            expected.push(SequencedEvent::Gap {
+                channel: 3,
+                from: 50,
+                to: 51,
+                reason: GapReason::Timeout,
+            });
+        }
+        expected.push(SequencedEvent::Block(block(seqnum, received)));
+    }
+    let stats = sequencer.stats();
+    assert_eq!((stats.spilled, stats.spill_depth), (97, 97));
+    assert_eq!((stats.output_depth, stats.lagged), (100, 0));
+
+    assert_eq!(drain(&mut sequencer, &receiver), expected);
+    let stats = sequencer.stats();
+    assert_eq!((stats.spill_depth, stats.output_depth), (0, 0));
+
+    // The drained segment is reused
+    for seqnum in 100..110 {
+        sequencer.push(block(seqnum, received));
+    }
+    let seqnums: Vec<_> = drain(&mut sequencer, &receiver)
+        .into_iter()
+        .map(|e| e.into_block().unwrap().header.seqnum)
+        .collect();
+    assert_eq!(seqnums, (100..110).collect::<Vec<_>>());
+    assert_eq!(sequencer.stats().spilled, 105);
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn dropping_the_sequencer_replays_the_spool() {
+    let dir = spill_dir("drop");
+    let (mut sequencer, receiver) =
+        Sequencer::with_spill(Duration::from_secs(1), 16, 4, &dir).unwrap();
+    let received = Instant::now();
+    for seqnum in 0..50 {
+        sequencer.push(block(seqnum, received));
+    }
+    assert_eq!(sequencer.stats().spill_depth, 46);
+    let consumer = thread::spawn(move || {
+        receiver
+            .iter()
+            .map(|e| e.into_block().unwrap().header.seqnum)
+            .collect::<Vec<_>>()
+    });
+    drop(sequencer);
+    assert_eq!(consumer.join().unwrap(), (0..50).collect::<Vec<_>>());
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn missing_spill_dir_is_an_error() {
+    let dir = std::env::temp_dir().join("sequencer-nonexistent-spill-dir");
+    let e = Sequencer::<Block<Vec<u8>>>::with_spill(Duration::from_secs(1), 16, 4, &dir)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+}
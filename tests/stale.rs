@@ -0,0 +1,69 @@
+use sequencer::journal::{self, JournalWriter};
+use sequencer::stale::{JournalCheck, StaleCheck, StalePolicy};
+use sequencer::{Block, BlockHeader, Payload, Sequencer};
+use std::time::{Duration, SystemTime};
+
+fn block(seqnum: u64, payload: &[u8]) -> Block<Payload> {
+    let header = BlockHeader {
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, payload.into())
+}
+
+#[test]
+fn logs_stale_copies_that_differ_from_the_journal() {
+    let path = std::env::temp_dir().join(format!("stale-{}.journal", std::process::id()));
+    let mut writer =
+        JournalWriter::create(&path, &journal::session("OUT"), Some(Duration::ZERO)).unwrap();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_stale_check(Box::new(JournalCheck::new(&path)));
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    for seqnum in 0..3 {
+        let delivered = block(seqnum, b"abc");
+        writer.append_block(&delivered, SystemTime::now()).unwrap();
+        writer.flush().unwrap();
+        sequencer.push_from(a, delivered);
+    }
+    // The same, corrupted, and one the journal hasn't got
+    sequencer.push_from(b, block(0, b"abc"));
+    sequencer.push_from(b, block(1, b"abd"));
+    sequencer.push_from(b, block(2, b"abc"));
+    sequencer.push_from(a, block(3, b"abc"));
+    sequencer.push_from(b, block(3, b"xyz"));
+    // Already buffered isn't stale
+    sequencer.push_from(b, block(5, b"abc"));
+    sequencer.push_from(a, block(5, b"abc"));
+    let stats = sequencer.stats();
+    drop(sequencer);
+
+    assert_eq!(receiver.iter().filter_map(|e| e.into_block()).count(), 4);
+    assert_eq!((stats.duplicates, stats.stale, stats.mismatched), (5, 4, 1));
+    assert_eq!(stats.feeds[a].mismatched, 0);
+    assert_eq!(stats.feeds[b].mismatched, 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn counts_stale_copies_without_a_check() {
+    let (mut sequencer, _receiver) = Sequencer::new(Duration::from_secs(60));
+    let (a, b) = (sequencer.add_feed(), sequencer.add_feed());
+    for seqnum in 0..3 {
+        sequencer.push_from(a, block(seqnum, b"abc"));
+        sequencer.push_from(b, block(seqnum, b"xyz"));
+    }
+    let stats = sequencer.stats();
+    assert_eq!((stats.stale, stats.mismatched), (3, 0));
+}
+
+#[test]
+fn journal_check_without_a_journal() {
+    let path = std::env::temp_dir().join(format!("stale-missing-{}.journal", std::process::id()));
+    let mut check = JournalCheck::new(&path);
+    assert!(check.matches(&block(0, b"abc")).is_err());
+
+    assert_eq!("count".parse(), Ok(StalePolicy::Count));
+    assert_eq!("verify".parse(), Ok(StalePolicy::Verify));
+    assert!("strict".parse::<StalePolicy>().is_err());
+}
@@ -0,0 +1,224 @@
+use sequencer::clock::MockClock;
+use sequencer::sink::Sink;
+use sequencer::standby::{Heartbeat, Position, Progress, Standby, StandbySink};
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, Payload, Session};
+use std::io;
+use std::net::UdpSocket;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DAY1: Session = *b"DAY1      ";
+const DAY2: Session = *b"DAY2      ";
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+fn block(session: Session, seqnum: u64) -> Block<Payload> {
+    let header = BlockHeader {
+        session,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8].into())
+}
+
+// What a sink was given, as (seqnum, published seqnum) or gap ranges
+#[derive(Clone, Default)]
+struct Recorder {
+    blocks: Arc<Mutex<Vec<(u64, u64)>>>,
+    gaps: Arc<Mutex<Vec<Range<u64>>>>,
+    published: u64,
+    flushes: Arc<Mutex<u64>>,
+}
+
+impl Sink for Recorder {
+    fn on_block(&mut self, block: &Block<Payload>) -> io::Result<()> {
+        let entry = (block.header.seqnum, self.published);
+        self.blocks.lock().unwrap().push(entry);
+        self.published += 1;
+        Ok(())
+    }
+
+    fn on_gap(&mut self, _: ChannelId, range: Range<u64>, _: GapReason) -> io::Result<()> {
+        self.gaps.lock().unwrap().push(range);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        *self.flushes.lock().unwrap() += 1;
+        Ok(())
+    }
+
+    fn published(&self) -> Option<u64> {
+        Some(self.published)
+    }
+
+    fn resume_published(&mut self, seqnum: u64) {
+        self.published = seqnum;
+    }
+}
+
+fn heartbeat(progress: &Progress) -> Vec<u8> {
+    let mut buf = Vec::new();
+    progress.encode("journal", &mut buf);
+    buf
+}
+
+fn progress(session: Session, next: u64, published: Option<u64>) -> Progress {
+    let mut progress = Progress {
+        published,
+        ..Default::default()
+    };
+    progress.channels.insert(0, Position { session, next });
+    progress
+}
+
+#[test]
+fn heartbeats_round_trip() {
+    let mut sent = progress(DAY1, 7, Some(100));
+    sent.channels.insert(
+        9,
+        Position {
+            session: DAY2,
+            next: u64::MAX - 1,
+        },
+    );
+    let buf = heartbeat(&sent);
+    assert_eq!(
+        Progress::decode(&buf),
+        Some(("journal".to_string(), sent.clone()))
+    );
+    sent.published = None;
+    assert_eq!(Progress::decode(&heartbeat(&sent)).unwrap().1, sent);
+    assert_eq!(Progress::decode(&buf[..buf.len() - 1]), None);
+    assert_eq!(Progress::decode(b"SQHX"), None);
+}
+
+#[test]
+fn progress_covers_older_sessions_and_seqnums() {
+    let day2 = progress(DAY2, 5, None);
+    assert!(day2.covers(0, DAY2, 5, Some(&DAY2)));
+    assert!(!day2.covers(0, DAY2, 6, Some(&DAY2)));
+    // The primary got to the session the standby is in
+    assert!(day2.covers(0, DAY1, 100, Some(&DAY2)));
+    // The standby is in a session the primary hasn't got to
+    assert!(!progress(DAY1, 100, None).covers(0, DAY2, 1, Some(&DAY2)));
+    assert!(!day2.covers(1, DAY2, 1, Some(&DAY2)));
+}
+
+#[test]
+fn takes_over_from_where_the_primary_left_off() {
+    let clock = MockClock::new();
+    let standby = Standby::with_clock(TIMEOUT, Box::new(clock.clone()));
+    let recorder = Recorder::default();
+    let mut sink = StandbySink::new(recorder.clone(), "journal", standby.clone());
+    for seqnum in 1..=5 {
+        sink.on_block(&block(DAY1, seqnum)).unwrap();
+    }
+    sink.on_gap(0, 6..8, GapReason::Timeout).unwrap();
+    assert!(standby.on_heartbeat(&heartbeat(&progress(DAY1, 4, Some(40)))));
+    sink.on_block(&block(DAY1, 8)).unwrap();
+    // What the primary got to is dropped
+    assert_eq!(sink.held(), 4);
+    assert!(recorder.blocks.lock().unwrap().is_empty());
+
+    clock.advance(TIMEOUT);
+    sink.on_idle().unwrap();
+    assert!(!standby.took_over());
+    clock.advance(Duration::from_millis(1));
+    sink.on_idle().unwrap();
+    assert!(standby.took_over());
+    assert_eq!(sink.held(), 0);
+    sink.on_block(&block(DAY1, 9)).unwrap();
+    assert_eq!(
+        *recorder.blocks.lock().unwrap(),
+        [(4, 40), (5, 41), (8, 42), (9, 43)]
+    );
+    assert_eq!(recorder.gaps.lock().unwrap()[..], vec![6..8]);
+    // No failing back
+    assert!(standby.on_heartbeat(&heartbeat(&progress(DAY1, 100, None))));
+    assert!(standby.took_over());
+}
+
+#[test]
+fn takes_over_a_primary_that_never_started() {
+    let clock = MockClock::new();
+    let standby = Standby::with_clock(TIMEOUT, Box::new(clock.clone()));
+    let recorder = Recorder::default();
+    let mut sink = StandbySink::new(recorder.clone(), "journal", standby);
+    sink.on_block(&block(DAY1, 1)).unwrap();
+    clock.advance(TIMEOUT * 2);
+    sink.on_block(&block(DAY1, 2)).unwrap();
+    assert_eq!(*recorder.blocks.lock().unwrap(), [(1, 0), (2, 1)]);
+}
+
+#[test]
+fn holds_back_at_most_max_held() {
+    let standby = Standby::new(Duration::from_secs(60));
+    let mut sink = StandbySink::new(Recorder::default(), "journal", standby);
+    sink.set_max_held(3);
+    for seqnum in 1..=5 {
+        sink.on_block(&block(DAY1, seqnum)).unwrap();
+    }
+    assert_eq!(sink.held(), 3);
+}
+
+#[test]
+fn holds_back_blocks_ending_at_the_last_seqnum() {
+    let standby = Standby::new(Duration::from_secs(60));
+    let mut sink = StandbySink::new(Recorder::default(), "journal", standby);
+    sink.on_block(&block(DAY1, u64::MAX)).unwrap();
+    assert_eq!(sink.held(), 1);
+}
+
+#[test]
+fn heartbeats_what_was_flushed() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut heartbeat =
+        Heartbeat::new(receiver.local_addr().unwrap(), Duration::from_secs(60)).unwrap();
+    let recorder = Recorder::default();
+    let mut sink = heartbeat.track("journal", recorder.clone());
+    let standby = Standby::new(Duration::from_secs(60));
+    let mut buf = [0_u8; 1500];
+    let mut received = || {
+        heartbeat.send().unwrap();
+        let n = receiver.recv(&mut buf).unwrap();
+        assert!(standby.on_heartbeat(&buf[..n]));
+        standby.progress("journal").unwrap()
+    };
+
+    sink.on_block(&block(DAY1, 1)).unwrap();
+    sink.on_block(&block(DAY1, 2)).unwrap();
+    // Not flushed yet
+    let unflushed = Progress {
+        published: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(received(), unflushed);
+    sink.on_idle().unwrap();
+    assert_eq!(*recorder.flushes.lock().unwrap(), 1);
+    assert_eq!(received(), progress(DAY1, 3, Some(2)));
+}
+
+#[test]
+fn a_standbys_heartbeat_waits_for_it_to_take_over() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let mut heartbeat =
+        Heartbeat::new(receiver.local_addr().unwrap(), Duration::from_secs(60)).unwrap();
+    let _sink = heartbeat.track("journal", Recorder::default());
+    let clock = MockClock::new();
+    let standby = Standby::with_clock(TIMEOUT, Box::new(clock.clone()));
+    heartbeat.set_standby(standby.clone());
+    heartbeat.send().unwrap();
+    let mut buf = [0_u8; 1500];
+    assert!(receiver.recv(&mut buf).is_err());
+    clock.advance(TIMEOUT * 2);
+    assert!(standby.took_over());
+    heartbeat.send().unwrap();
+    receiver.set_nonblocking(false).unwrap();
+    assert!(receiver.recv(&mut buf).is_ok());
+}
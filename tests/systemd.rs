@@ -0,0 +1,91 @@
+#![cfg(unix)]
+
+use sequencer::health::{Health, HealthConfig};
+use sequencer::metrics::Metrics;
+use sequencer::protocol::Protocol;
+use sequencer::shutdown::Shutdown;
+use sequencer::systemd::{self, Inherited, Watchdog};
+use sequencer::udp::{FeedConfig, UdpFeed};
+use std::env;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Every test of the environment, which tests running at once would share
+#[test]
+fn notifies_the_service_manager() {
+    let path = env::temp_dir().join(format!("systemd-notify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let manager = UnixDatagram::bind(&path).unwrap();
+    manager
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    env::remove_var("NOTIFY_SOCKET");
+    assert!(!systemd::notify("READY=1").unwrap());
+
+    env::set_var("NOTIFY_SOCKET", &path);
+    assert!(systemd::notify("READY=1").unwrap());
+    let mut buf = [0; 64];
+    let n = manager.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+
+    // Pinged while the arbiter is live, as it is until the stall passes
+    let health = Health::new(Arc::<Metrics>::default(), HealthConfig::default());
+    let shutdown = Shutdown::default();
+    let watchdog = Watchdog::new(health, Duration::from_millis(20)).spawn(shutdown.clone());
+    let n = manager.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"WATCHDOG=1");
+    shutdown.request();
+    watchdog.unwrap().join().unwrap().unwrap();
+    env::remove_var("NOTIFY_SOCKET");
+    std::fs::remove_file(&path).unwrap();
+
+    env::set_var("WATCHDOG_USEC", "2000000");
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(2)));
+    env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(2)));
+    env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(systemd::watchdog_interval(), None);
+    env::remove_var("WATCHDOG_PID");
+    env::set_var("WATCHDOG_USEC", "0");
+    assert_eq!(systemd::watchdog_interval(), None);
+    env::remove_var("WATCHDOG_USEC");
+
+    // Sockets passed to some other process
+    env::set_var("LISTEN_PID", "1");
+    env::set_var("LISTEN_FDS", "2");
+    assert!(Inherited::take().is_empty());
+    assert_eq!(env::var("LISTEN_FDS").as_deref(), Ok("2"));
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    assert!(Inherited::take().is_empty());
+}
+
+#[test]
+fn joins_the_group_on_a_bound_socket() {
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let config = FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 7),
+        port,
+        protocol: Protocol::Raw,
+        channel: 0,
+        source: None,
+        reuse_port: false,
+        recv_buffer: None,
+    };
+    let mut feed = UdpFeed::with_socket(socket, &config).unwrap();
+    feed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(feed.local_addr().unwrap().port(), port);
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.set_multicast_loop_v4(true).unwrap();
+    let mut datagram = 7_u64.to_be_bytes().to_vec();
+    datagram.extend_from_slice(&1_u16.to_be_bytes());
+    datagram.push(b'x');
+    sender.send_to(&datagram, (config.group, port)).unwrap();
+    let b = feed.recv().unwrap().unwrap();
+    assert_eq!((b.header.seqnum, &b.payload[..]), (7, &b"x"[..]));
+}
@@ -0,0 +1,180 @@
+use crossbeam_channel::unbounded;
+use sequencer::protocol::{moldudp64, Protocol};
+use sequencer::shutdown::Shutdown;
+use sequencer::tcp::{LengthPrefix, TcpFeed, TcpFeedConfig, MAX_PACKET};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+
+const SESSION: [u8; 10] = *b"SESSION001";
+
+fn moldudp64(seqnum: u64, msg: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    moldudp64::write_header(&mut buf, &SESSION, seqnum, 1);
+    moldudp64::write_message(&mut buf, msg);
+    buf
+}
+
+fn config(addr: SocketAddr, prefix: LengthPrefix) -> TcpFeedConfig {
+    TcpFeedConfig {
+        addr,
+        prefix,
+        protocol: Protocol::MoldUdp64,
+        channel: 2,
+        #[cfg(feature = "tls")]
+        tls: None,
+    }
+}
+
+// A server writing `stream` to the first client in pieces of `chunk` bytes,
+// then closing the connection
+fn server(stream: Vec<u8>, chunk: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut client, _) = listener.accept().unwrap();
+        for piece in stream.chunks(chunk) {
+            client.write_all(piece).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+    addr
+}
+
+fn recv_all(feed: &mut TcpFeed) -> (Vec<u64>, io::Error) {
+    let mut seqnums = Vec::new();
+    loop {
+        match feed.recv() {
+            Ok(b) => seqnums.extend(b.map(|b| b.header.seqnum)),
+            Err(e) => return (seqnums, e),
+        }
+    }
+}
+
+#[test]
+fn frames_packets_split_across_reads() {
+    for prefix in [LengthPrefix::U16, LengthPrefix::U32] {
+        let mut stream = Vec::new();
+        prefix.write(&mut stream, &moldudp64(1, b"abc"));
+        // A heartbeat, then a packet that isn't MoldUDP64
+        prefix.write(&mut stream, &[]);
+        prefix.write(&mut stream, &[0; 4]);
+        prefix.write(&mut stream, &moldudp64(2, b"de"));
+        let mut feed = TcpFeed::connect(&config(server(stream, 3), prefix)).unwrap();
+
+        let mut blocks = Vec::new();
+        while blocks.len() < 2 {
+            blocks.extend(feed.recv().unwrap());
+        }
+        assert_eq!((blocks[0].header.channel, blocks[0].header.seqnum), (2, 1));
+        assert_eq!(blocks[1].header.seqnum, 2);
+        assert!(blocks.iter().all(|b| b.received.is_some()));
+        assert_eq!(&blocks[1].payload[..], &moldudp64(2, b"de")[20..]);
+        // The server hung up
+        let (_, err) = recv_all(&mut feed);
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
+
+#[test]
+fn rejects_lengths_past_the_limit() {
+    let stream = ((MAX_PACKET + 1) as u32).to_be_bytes().to_vec();
+    let mut feed = TcpFeed::connect(&config(server(stream, 4), LengthPrefix::U32)).unwrap();
+    let (seqnums, err) = recv_all(&mut feed);
+    assert!(seqnums.is_empty());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!("u64".parse::<LengthPrefix>().is_err());
+}
+
+#[test]
+fn runs_until_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut feed = TcpFeed::connect(&config(addr, LengthPrefix::U16)).unwrap();
+    let (mut client, _) = listener.accept().unwrap();
+    let mut stream = Vec::new();
+    for i in 1..=3 {
+        LengthPrefix::U16.write(&mut stream, &moldudp64(i, b"x"));
+    }
+    client.write_all(&stream).unwrap();
+
+    let shutdown = Shutdown::default();
+    feed.set_shutdown(shutdown.clone());
+    let (sender, receiver) = unbounded();
+    let thread = thread::spawn(move || feed.run(sender));
+    let seqnums: Vec<_> = (0..3)
+        .map(|_| receiver.recv().unwrap().header.seqnum)
+        .collect();
+    assert_eq!(seqnums, [1, 2, 3]);
+    // With the connection still open
+    shutdown.request();
+    thread.join().unwrap().unwrap();
+    drop(client);
+}
+
+#[test]
+fn fails_to_connect_without_a_server() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    assert!(TcpFeed::connect(&config(addr, LengthPrefix::U16)).is_err());
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn streams_over_tls() {
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use sequencer::tcp::TlsConfig;
+    use std::sync::Arc;
+
+    let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = key.cert.der().clone();
+    let private = PrivatePkcs8KeyDer::from(key.signing_key.serialize_der());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server_config = Arc::new(
+        ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], PrivateKeyDer::Pkcs8(private))
+            .unwrap(),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        // The first client doesn't trust the server, the second does
+        for _ in 0..2 {
+            let (client, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            let mut tls = StreamOwned::new(conn, client);
+            let mut stream = Vec::new();
+            for i in 1..=2 {
+                LengthPrefix::U16.write(&mut stream, &moldudp64(i, b"x"));
+            }
+            if tls.write_all(&stream).and_then(|_| tls.flush()).is_ok() {
+                tls.conn.send_close_notify();
+                let _ = tls.flush();
+            }
+        }
+    });
+
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let mut untrusting = config(addr, LengthPrefix::U16);
+    untrusting.tls = Some(TlsConfig {
+        server_name: "localhost".to_string(),
+        roots: vec![other.cert.der().clone()],
+    });
+    assert!(TcpFeed::connect(&untrusting).is_err());
+
+    let mut trusting = config(addr, LengthPrefix::U16);
+    trusting.tls = Some(TlsConfig {
+        server_name: "localhost".to_string(),
+        roots: vec![cert],
+    });
+    let mut feed = TcpFeed::connect(&trusting).unwrap();
+    let (seqnums, _) = recv_all(&mut feed);
+    assert_eq!(seqnums, [1, 2]);
+}
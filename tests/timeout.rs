@@ -0,0 +1,94 @@
+use sequencer::arbiter::Arbiter;
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::thread;
+use std::time::Duration;
+
+fn block(seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, Vec::new())
+}
+
+fn gap(from: u64, to: u64) -> SequencedEvent<Block<Vec<u8>>> {
+    SequencedEvent::Gap {
+        channel: 0,
+        from,
+        to,
+        reason: GapReason::Timeout,
+    }
+}
+
+#[test]
+fn flushes_up_to_timed_out_block() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.push(block(2));
+    sequencer.push(block(5));
+    thread::sleep(Duration::from_millis(20));
+    sequencer.push(block(4));
+    sequencer.push(block(7));
+    // 5 timed out so 4 before it is delivered too, 7 waits
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(0), 6);
+    assert_eq!(sequencer.pending(), 1);
+
+    thread::sleep(Duration::from_millis(20));
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(0), 8);
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            gap(0, 2),
+            SequencedEvent::Block(block(2)),
+            gap(3, 4),
+            SequencedEvent::Block(block(4)),
+            SequencedEvent::Block(block(5)),
+            gap(6, 7),
+            SequencedEvent::Block(block(7)),
+        ]
+    );
+}
+
+#[test]
+fn ticked_pushes_leave_timeouts_to_poll() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_timeout_tick(Some(Duration::from_millis(1)));
+    sequencer.push(block(2));
+    thread::sleep(Duration::from_millis(20));
+    sequencer.push(block(3));
+    assert_eq!(sequencer.seqnum(0), 0);
+    assert_eq!(sequencer.pending(), 2);
+
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(0), 4);
+    drop(sequencer);
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events[0], gap(0, 2));
+}
+
+#[test]
+fn arbiter_ticks_timeouts_under_steady_traffic() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    sequencer.set_timeout_tick(Some(Duration::from_millis(1)));
+    // Never idle long enough to poll timeouts for lack of packets
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_secs(10));
+    let s = arbiter.add_feed();
+    let feed = thread::spawn(move || {
+        for seqnum in (0..100).filter(|s| *s != 1) {
+            s.send(block(seqnum)).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+    let sequencer = arbiter.run();
+    feed.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 100);
+    drop(sequencer);
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events[1], gap(1, 2));
+}
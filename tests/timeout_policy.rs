@@ -0,0 +1,190 @@
+use sequencer::clock::{Clock, MockClock};
+use sequencer::timeout::{
+    Adaptive, ChannelTimeout, GapTimeout, TimeoutPolicy, Tuned, MIN_ADAPTIVE,
+};
+use sequencer::{Block, BlockHeader, ChannelId, GapReason, SequencedEvent, Sequencer};
+use std::time::Duration;
+
+type Event = SequencedEvent<Block<Vec<u8>>>;
+
+fn block(channel: ChannelId, seqnum: u64) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    Block::new(header, vec![seqnum as u8])
+}
+
+fn gap(channel: ChannelId, from: u64, to: u64) -> Event {
+    SequencedEvent::Gap {
+        channel,
+        from,
+        to,
+        reason: GapReason::Timeout,
+    }
+}
+
+#[test]
+fn packet_count_times_out_after_newer_blocks() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_gap_timeout(GapTimeout::Packets(3));
+    for seqnum in [0, 2, 3, 4] {
+        sequencer.push(block(0, seqnum));
+    }
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![SequencedEvent::Block(block(0, 0))]
+    );
+
+    sequencer.push(block(0, 5));
+    let mut expected = vec![gap(0, 1, 2)];
+    expected.extend((2..=5).map(|s| SequencedEvent::Block(block(0, s))));
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn packet_count_times_out_a_quiet_channel() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(100));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_gap_timeout(GapTimeout::Packets(1000));
+    sequencer.push(block(0, 1));
+    clock.advance(Duration::from_millis(101));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![gap(0, 0, 1), SequencedEvent::Block(block(0, 1))]
+    );
+}
+
+#[test]
+fn adaptive_follows_the_arrival_rate() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_gap_timeout(GapTimeout::Adaptive(4.0));
+    for seqnum in 0..10 {
+        sequencer.push(block(0, seqnum));
+        clock.advance(Duration::from_millis(1));
+    }
+    sequencer.push(block(0, 11));
+    let deadline = sequencer.next_deadline().unwrap();
+    assert_eq!(deadline - clock.now(), Duration::from_millis(4));
+
+    clock.advance(Duration::from_millis(4));
+    sequencer.poll_timeouts();
+    assert_eq!(receiver.try_iter().count(), 10);
+    clock.advance(Duration::from_millis(1));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![gap(0, 10, 11), SequencedEvent::Block(block(0, 11))]
+    );
+}
+
+#[test]
+fn adaptive_waits_the_timeout_until_it_has_an_average() {
+    let mut policy = Adaptive::new(2.0);
+    let timeout = Duration::from_millis(50);
+    assert_eq!(policy.max_wait(timeout), timeout);
+    let clock = MockClock::new();
+    policy.on_arrival(clock.now());
+    assert_eq!(policy.max_wait(timeout), timeout);
+    clock.advance(Duration::from_millis(10));
+    policy.on_arrival(clock.now());
+    assert_eq!(policy.average(), Some(Duration::from_millis(10)));
+    assert_eq!(policy.max_wait(timeout), Duration::from_millis(20));
+    // Never longer than the timeout
+    clock.advance(Duration::from_secs(10));
+    policy.on_arrival(clock.now());
+    assert_eq!(policy.max_wait(timeout), timeout);
+}
+
+#[test]
+fn tuned_waits_for_the_slowest_gap_to_fill() {
+    let clock = MockClock::new();
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(1));
+    sequencer.set_clock(Box::new(clock.clone()));
+    sequencer.set_gap_timeout(GapTimeout::Tuned(2.0));
+    sequencer.push(block(0, 0));
+    // The first gap waits the timeout, and took 3ms to fill
+    sequencer.push(block(0, 2));
+    assert_eq!(
+        sequencer.next_deadline().unwrap() - clock.now(),
+        Duration::from_secs(1)
+    );
+    clock.advance(Duration::from_millis(3));
+    sequencer.push(block(0, 1));
+    assert_eq!(receiver.try_iter().count(), 3);
+
+    sequencer.push(block(0, 4));
+    assert_eq!(
+        sequencer.next_deadline().unwrap() - clock.now(),
+        Duration::from_millis(6)
+    );
+    clock.advance(Duration::from_millis(7));
+    sequencer.poll_timeouts();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![gap(0, 3, 4), SequencedEvent::Block(block(0, 4))]
+    );
+}
+
+#[test]
+fn tuned_stays_within_bounds() {
+    let mut policy = Tuned::new(2.0);
+    let timeout = Duration::from_millis(50);
+    assert_eq!(policy.max_wait(timeout), timeout);
+    policy.on_filled(Duration::from_micros(10));
+    assert_eq!(policy.max_wait(timeout), MIN_ADAPTIVE);
+    policy.on_filled(Duration::from_millis(5));
+    // Only ever the longest
+    policy.on_filled(Duration::from_millis(1));
+    assert_eq!(policy.longest(), Some(Duration::from_millis(5)));
+    assert_eq!(policy.max_wait(timeout), Duration::from_millis(10));
+    policy.on_filled(Duration::from_secs(1));
+    assert_eq!(policy.max_wait(timeout), timeout);
+}
+
+#[test]
+fn policies_are_per_channel() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    sequencer.set_channel_gap_timeout(1, GapTimeout::Packets(1));
+    assert_eq!(sequencer.gap_timeout(0), GapTimeout::Fixed);
+    assert_eq!(sequencer.gap_timeout(1), GapTimeout::Packets(1));
+    for channel in [0, 1] {
+        sequencer.push(block(channel, 1));
+        sequencer.push(block(channel, 2));
+    }
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![
+            gap(1, 0, 1),
+            SequencedEvent::Block(block(1, 1)),
+            SequencedEvent::Block(block(1, 2))
+        ]
+    );
+    assert_eq!(sequencer.pending(), 2);
+}
+
+#[test]
+fn parses_policies() {
+    assert_eq!("fixed".parse(), Ok(GapTimeout::Fixed));
+    assert_eq!("packets:8".parse(), Ok(GapTimeout::Packets(8)));
+    assert_eq!("adaptive:2.5".parse(), Ok(GapTimeout::Adaptive(2.5)));
+    assert!("packets:0".parse::<GapTimeout>().is_err());
+    assert!("adaptive:-1".parse::<GapTimeout>().is_err());
+    assert_eq!("tuned:3".parse(), Ok(GapTimeout::Tuned(3.0)));
+    assert!("tuned:0".parse::<GapTimeout>().is_err());
+    assert!("packets".parse::<GapTimeout>().is_err());
+    assert_eq!(
+        "3=packets:2".parse(),
+        Ok(ChannelTimeout {
+            channel: 3,
+            timeout: GapTimeout::Packets(2)
+        })
+    );
+    assert!("x=fixed".parse::<ChannelTimeout>().is_err());
+}
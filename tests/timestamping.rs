@@ -0,0 +1,67 @@
+use sequencer::udp::{self, Timestamping};
+use sequencer::{Block, BlockHeader, GapReason, SequencedEvent, Sequencer};
+use std::net::UdpSocket;
+use std::time::{Duration, Instant, SystemTime};
+
+fn block(seqnum: u64, received: Option<Instant>) -> Block<Vec<u8>> {
+    let header = BlockHeader {
+        channel: 0,
+        seqnum,
+        n_messages: 1,
+        ..Default::default()
+    };
+    let mut b = Block::new(header, Vec::new());
+    b.received = received;
+    b
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kernel_timestamps_datagrams() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    udp::enable_timestamping(&receiver, Timestamping::Software).unwrap();
+    // The kernel turns on receive timestamps from a work queue when the first
+    // socket asks for them, so give it a chance to run
+    std::thread::sleep(Duration::from_millis(50));
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let before = SystemTime::now();
+    sender
+        .send_to(b"hello", receiver.local_addr().unwrap())
+        .unwrap();
+
+    let mut buf = [0; 64];
+    let (n, src, ts) = udp::recv_timestamped(&receiver, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    assert_eq!(src, sender.local_addr().unwrap());
+    let ts = ts.unwrap();
+    // The kernel clock is coarser than ours
+    assert!(ts + Duration::from_millis(10) >= before);
+    assert!(ts <= SystemTime::now());
+}
+
+#[test]
+fn timeouts_count_from_receive_time() {
+    let (mut sequencer, receiver) = Sequencer::new(Duration::from_millis(10));
+    // Sat in a queue for longer than the timeout before being pushed
+    let received = Instant::now() - Duration::from_millis(50);
+    sequencer.push(block(0, Some(received)));
+    sequencer.push(block(2, Some(received)));
+    sequencer.poll_timeouts();
+    assert_eq!(sequencer.seqnum(0), 3);
+    assert!(sequencer.stats().max_rx_delay >= Duration::from_millis(50));
+    drop(sequencer);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        events[1],
+        SequencedEvent::Gap {
+            channel: 0,
+            from: 1,
+            to: 2,
+            reason: GapReason::Timeout,
+        }
+    );
+}
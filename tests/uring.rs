@@ -0,0 +1,98 @@
+#![cfg(all(feature = "io_uring", target_os = "linux"))]
+
+use sequencer::arbiter::Arbiter;
+use sequencer::pool::BufferPool;
+use sequencer::protocol::Protocol;
+use sequencer::shutdown::Shutdown;
+use sequencer::udp::{FeedConfig, UdpFeed};
+use sequencer::uring::{UringRecv, RECV_OVERHEAD};
+use sequencer::{SequencedEvent, Sequencer};
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+fn datagram(seqnum: u64, payload: &[u8]) -> Vec<u8> {
+    let mut d = seqnum.to_be_bytes().to_vec();
+    d.extend_from_slice(&1_u16.to_be_bytes());
+    d.extend_from_slice(payload);
+    d
+}
+
+#[test]
+fn receives_into_pooled_buffers() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let pool = BufferPool::new(8, 64 + RECV_OVERHEAD);
+    let mut ring = match UringRecv::new(&receiver, pool.clone(), 4) {
+        Ok(ring) => ring,
+        // Nothing to test on this kernel
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return,
+        Err(e) => panic!("{}", e),
+    };
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for i in 0..6_u8 {
+        sender
+            .send_to(&[i; 5], receiver.local_addr().unwrap())
+            .unwrap();
+    }
+
+    let mut received = Vec::new();
+    while received.len() < 6 {
+        ring.recv(&mut received, Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+    for (i, r) in received.iter().enumerate() {
+        assert_eq!(&r.buf[r.data.clone()], &[i as u8; 5]);
+        assert_eq!(r.src, sender.local_addr().unwrap());
+    }
+    // More datagrams than buffers, so some were provided again
+    assert!(pool.allocated() > 8);
+}
+
+#[test]
+fn feed_sequences_with_io_uring() {
+    let config = FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group: Ipv4Addr::new(239, 1, 2, 5),
+        port: 0,
+        protocol: Protocol::Raw,
+        channel: 0,
+        source: None,
+        reuse_port: false,
+        recv_buffer: None,
+    };
+    let mut feed = UdpFeed::join(&config).unwrap();
+    feed.set_pool(BufferPool::new(16, 64 + RECV_OVERHEAD));
+    let shutdown = Shutdown::default();
+    feed.set_shutdown(shutdown.clone()).unwrap();
+    let port = feed.local_addr().unwrap().port();
+
+    let (sequencer, receiver) = Sequencer::new(Duration::from_secs(60));
+    let mut arbiter = Arbiter::new(sequencer, Duration::from_millis(5));
+    let s = arbiter.add_batch_feed(64);
+    let feed = thread::spawn(move || feed.run_uring(s).unwrap());
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Queued on the socket until the receive is armed
+    for seqnum in [0, 2, 1] {
+        sender
+            .send_to(&datagram(seqnum, b"data"), (config.group, port))
+            .unwrap();
+    }
+    let stop = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        shutdown.request();
+    });
+    let sequencer = arbiter.run();
+    stop.join().unwrap();
+    feed.join().unwrap();
+    assert_eq!(sequencer.seqnum(0), 3);
+    drop(sequencer);
+
+    let blocks: Vec<_> = receiver
+        .iter()
+        .filter_map(SequencedEvent::into_block)
+        .collect();
+    assert_eq!(blocks.len(), 3);
+    assert!(blocks.iter().all(|b| b.payload == b"data"));
+    assert!(blocks.iter().all(|b| b.payload.is_pooled()));
+}
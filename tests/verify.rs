@@ -0,0 +1,113 @@
+use sequencer::journal::{self, JournalReader, JournalWriter, FILE_HEADER_LEN, RECORD_HEADER_LEN};
+use sequencer::verify::{self, Report};
+use sequencer::BlockHeader;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("verify-{}-{}", name, std::process::id()))
+}
+
+// (channel, seqnum, n_messages, secs) of each record
+fn write(path: &PathBuf, records: &[(u32, u64, u16, u64)]) {
+    let mut writer = JournalWriter::create(path, &journal::session("DAY1"), None).unwrap();
+    for (channel, seqnum, n_messages, secs) in records {
+        let header = BlockHeader {
+            channel: *channel,
+            seqnum: *seqnum,
+            n_messages: *n_messages,
+            ..Default::default()
+        };
+        let ts = UNIX_EPOCH + Duration::from_secs(*secs);
+        writer.append(&header, ts, b"abcd").unwrap();
+    }
+    writer.sync().unwrap();
+}
+
+fn verified(path: &PathBuf) -> Report {
+    let report = verify::verify(JournalReader::open(path).unwrap());
+    fs::remove_file(path).unwrap();
+    report
+}
+
+// Offset of the nth record of a journal of records with 4 byte payloads
+fn offset(n: u64) -> u64 {
+    FILE_HEADER_LEN as u64 + n * (4 + RECORD_HEADER_LEN as u64 + 4)
+}
+
+#[test]
+fn clean_journal_is_ok() {
+    let path = temp("clean");
+    write(
+        &path,
+        &[(0, 1, 2, 1), (1, 7, 1, 1), (0, 3, 1, 2), (1, 8, 1, 3)],
+    );
+    let report = verified(&path);
+    assert!(report.ok());
+    assert_eq!(report.records, 4);
+    assert_eq!(report.channels[&0].next, 4);
+    assert_eq!(report.channels[&1].first, 7);
+    assert!(report.to_string().ends_with("ok"));
+}
+
+#[test]
+fn reports_gaps_and_seqnums_going_backwards() {
+    let path = temp("gaps");
+    write(
+        &path,
+        &[(0, 1, 1, 1), (0, 4, 2, 1), (0, 2, 1, 1), (0, 9, 1, 1)],
+    );
+    let report = verified(&path);
+    let channel = &report.channels[&0];
+    assert_eq!(channel.missing, vec![2..4, 6..9]);
+    assert_eq!(channel.backwards, vec![(offset(2), 2)]);
+    assert!(!report.ok());
+    let text = report.to_string();
+    assert!(text.contains("channel 0 seqnums 1..10 records 4 missing 5"));
+    assert!(text.contains("  gap 6..9"));
+    assert!(text.ends_with("FAILED"));
+}
+
+#[test]
+fn reports_timestamp_regressions() {
+    let path = temp("ts");
+    write(&path, &[(0, 1, 1, 5), (0, 2, 1, 4), (0, 3, 1, 5)]);
+    let report = verified(&path);
+    assert_eq!(report.ts_regressions, vec![offset(1)]);
+    assert!(!report.ok());
+}
+
+#[test]
+fn skips_records_with_bad_crcs() {
+    let path = temp("crc");
+    write(&path, &[(0, 1, 1, 1), (0, 2, 1, 1), (0, 3, 1, 1)]);
+    let mut buf = fs::read(&path).unwrap();
+    // Last payload byte of the second record
+    buf[offset(2) as usize - 1] ^= 0xff;
+    fs::write(&path, buf).unwrap();
+    let report = verified(&path);
+    assert_eq!(report.crc_mismatches, vec![offset(1)]);
+    assert_eq!(report.records, 2);
+    assert_eq!(report.channels[&0].missing, vec![2..3]);
+}
+
+#[test]
+fn reports_a_torn_tail() {
+    let path = temp("torn");
+    write(&path, &[(0, 1, 1, 1), (0, 2, 1, 1)]);
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(offset(2) - 3).unwrap();
+    let report = verified(&path);
+    assert_eq!(report.records, 1);
+    assert_eq!(report.unreadable.as_ref().map(|u| u.0), Some(offset(1)));
+}
+
+#[test]
+fn records_ending_at_the_last_seqnum() {
+    let path = temp("last");
+    write(&path, &[(0, u64::MAX - 1, 1, 1), (0, u64::MAX, 1, 1)]);
+    let report = verified(&path);
+    assert!(report.ok());
+    assert_eq!(report.channels[&0].next, u64::MAX);
+}
@@ -0,0 +1,95 @@
+use sequencer::sink::Sink;
+use sequencer::websocket::WebSocketSink;
+use sequencer::{Block, BlockHeader, GapReason, Payload};
+use std::net::TcpStream;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+fn block(channel: u32, seqnum: u64, n_messages: u16, payload: &[u8]) -> Block<Payload> {
+    let header = BlockHeader {
+        channel,
+        seqnum,
+        n_messages,
+        ..Default::default()
+    };
+    Block::new(header, payload.into())
+}
+
+fn connect(sink: &WebSocketSink) -> WebSocket<MaybeTlsStream<TcpStream>> {
+    let url = format!("ws://{}", sink.local_addr());
+    let (client, _) = tungstenite::connect(url).unwrap();
+    if let MaybeTlsStream::Plain(stream) = client.get_ref() {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+    client
+}
+
+fn frame(client: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> String {
+    match client.read().unwrap() {
+        Message::Text(text) => text.to_string(),
+        msg => panic!("unexpected {:?}", msg),
+    }
+}
+
+#[test]
+fn sends_json_frames() {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut sink = WebSocketSink::bind(addr, Duration::ZERO).unwrap();
+    let mut client = connect(&sink);
+
+    sink.on_block(&block(0, 10, 2, b"abc")).unwrap();
+    sink.on_gap(0, 12..14, GapReason::Timeout).unwrap();
+    sink.on_reset(0, *b"SESSION001", 1).unwrap();
+    sink.on_feed_down(1, Duration::from_millis(500)).unwrap();
+    sink.on_feed_up(1).unwrap();
+
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"block","channel":0,"seqnum":10,"n_messages":2,"len":3}"#
+    );
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"gap","channel":0,"from":12,"to":14,"reason":"timeout"}"#
+    );
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"reset","channel":0,"seqnum":1}"#
+    );
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"feed_down","feed":1,"silent_ms":500}"#
+    );
+    assert_eq!(frame(&mut client), r#"{"type":"feed_up","feed":1}"#);
+    assert_eq!(sink.metrics().clients.load(Relaxed), 1);
+    assert_eq!(sink.metrics().frames.load(Relaxed), 5);
+}
+
+#[test]
+fn samples_blocks_per_channel() {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut sink = WebSocketSink::bind(addr, Duration::from_secs(60)).unwrap();
+    let mut client = connect(&sink);
+
+    sink.on_block(&block(0, 1, 1, b"a")).unwrap();
+    sink.on_block(&block(0, 2, 1, b"b")).unwrap();
+    sink.on_block(&block(1, 1, 1, b"c")).unwrap();
+    // Gaps are never sampled away
+    sink.on_gap(0, 3..4, GapReason::Overflow).unwrap();
+
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"block","channel":0,"seqnum":1,"n_messages":1,"len":1}"#
+    );
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"block","channel":1,"seqnum":1,"n_messages":1,"len":1}"#
+    );
+    assert_eq!(
+        frame(&mut client),
+        r#"{"type":"gap","channel":0,"from":3,"to":4,"reason":"overflow"}"#
+    );
+}
@@ -0,0 +1,108 @@
+#![cfg(all(feature = "af_xdp", target_os = "linux"))]
+
+use sequencer::protocol::Protocol;
+use sequencer::udp::FeedConfig;
+use sequencer::xdp::{AfXdpSource, XdpConfig, XdpMode};
+use socket2::SockRef;
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+
+fn datagram(seqnum: u64, payload: &[u8]) -> Vec<u8> {
+    let mut d = seqnum.to_be_bytes().to_vec();
+    d.extend_from_slice(&1_u16.to_be_bytes());
+    d.extend_from_slice(payload);
+    d
+}
+
+fn feed(group: Ipv4Addr, port: u16, channel: u32) -> FeedConfig {
+    FeedConfig {
+        interface: Ipv4Addr::LOCALHOST,
+        group,
+        port,
+        protocol: Protocol::Raw,
+        channel,
+        source: None,
+        reuse_port: true,
+        recv_buffer: None,
+    }
+}
+
+#[test]
+fn steers_configured_groups_into_umem() {
+    let (a, b) = (Ipv4Addr::new(239, 1, 3, 1), Ipv4Addr::new(239, 1, 3, 2));
+    let config = XdpConfig {
+        device: "lo".to_string(),
+        queue: 0,
+        mode: XdpMode::Skb,
+        frames: 64,
+        feeds: vec![feed(a, 31_001, 1), feed(b, 31_002, 2)],
+    };
+    let mut source = match AfXdpSource::bind(&config) {
+        Ok(source) => source,
+        // Needs root and a kernel with AF_XDP
+        Err(e) => {
+            eprintln!("skipping, af_xdp unavailable: {}", e);
+            return;
+        }
+    };
+    assert_eq!(source.mode(), XdpMode::Skb);
+    assert!(!source.zero_copy());
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    SockRef::from(&sender)
+        .set_multicast_if_v4(&Ipv4Addr::LOCALHOST)
+        .unwrap();
+    // More rounds than frames, so frames must be recycled
+    let mut received = Vec::new();
+    for round in 0..8_u64 {
+        for i in 0..16 {
+            let seqnum = round * 16 + i;
+            sender
+                .send_to(&datagram(seqnum, b"a"), (a, 31_001))
+                .unwrap();
+            // Not steered
+            sender
+                .send_to(&datagram(seqnum, b"c"), (b, 31_003))
+                .unwrap();
+        }
+        sender.send_to(&datagram(round, b"b"), (b, 31_002)).unwrap();
+
+        let mut blocks = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while blocks.len() < 17 && Instant::now() < deadline {
+            source
+                .recv(&mut blocks, Some(Duration::from_millis(100)))
+                .unwrap();
+        }
+        assert_eq!(blocks.len(), 17, "round {}", round);
+        for (feed, block) in &blocks {
+            assert!(block.payload.is_pooled());
+            match feed {
+                0 => assert_eq!((block.header.channel, &*block.payload), (1, &b"a"[..])),
+                1 => assert_eq!((block.header.channel, &*block.payload), (2, &b"b"[..])),
+                f => panic!("unexpected feed {}", f),
+            }
+        }
+        received.extend(
+            blocks
+                .into_iter()
+                .filter(|(feed, _)| *feed == 0)
+                .map(|(_, b)| b.header.seqnum),
+        );
+    }
+    assert_eq!(received, (0..128).collect::<Vec<_>>());
+}
+
+#[test]
+fn unknown_device_is_an_error() {
+    let config = XdpConfig {
+        device: "nonexistent0".to_string(),
+        queue: 0,
+        mode: XdpMode::Auto,
+        frames: 64,
+        feeds: Vec::new(),
+    };
+    let e = AfXdpSource::bind(&config).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}